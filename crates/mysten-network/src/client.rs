@@ -4,6 +4,7 @@
 use crate::{
     config::Config,
     multiaddr::{parse_dns, parse_ip4, parse_ip6},
+    retry::RetryChannel,
 };
 use eyre::{eyre, Context, Result};
 use multiaddr::{Multiaddr, Protocol};
@@ -21,7 +22,7 @@ pub fn connect_lazy(address: &Multiaddr) -> Result<Channel> {
 
 pub(crate) async fn connect_with_config(address: &Multiaddr, config: &Config) -> Result<Channel> {
     let channel = endpoint_from_multiaddr(address)?
-        .apply_config(config)
+        .apply_config(config)?
         .connect()
         .await?;
     Ok(channel)
@@ -29,15 +30,51 @@ pub(crate) async fn connect_with_config(address: &Multiaddr, config: &Config) ->
 
 pub(crate) fn connect_lazy_with_config(address: &Multiaddr, config: &Config) -> Result<Channel> {
     let channel = endpoint_from_multiaddr(address)?
-        .apply_config(config)
+        .apply_config(config)?
         .connect_lazy();
     Ok(channel)
 }
 
+pub(crate) async fn connect_with_retries(
+    address: &Multiaddr,
+    config: &Config,
+) -> Result<RetryChannel> {
+    let retry = config.retry.clone().unwrap_or_default();
+    let mut attempt = 1;
+    let channel = loop {
+        match connect_with_config(address, config).await {
+            Ok(channel) => break channel,
+            Err(e) if attempt < retry.max_attempts => {
+                let backoff = retry.jittered_backoff(attempt);
+                tracing::debug!("failed to connect to {address}, retrying in {backoff:?}: {e}");
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    Ok(RetryChannel::new(channel, retry))
+}
+
+pub(crate) fn connect_lazy_with_retries(
+    address: &Multiaddr,
+    config: &Config,
+) -> Result<RetryChannel> {
+    let retry = config.retry.clone().unwrap_or_default();
+    let channel = connect_lazy_with_config(address, config)?;
+    Ok(RetryChannel::new(channel, retry))
+}
+
 fn endpoint_from_multiaddr(addr: &Multiaddr) -> Result<MyEndpoint> {
     let mut iter = addr.iter();
 
     let channel = match iter.next().ok_or_else(|| eyre!("address is empty"))? {
+        #[cfg(feature = "quic")]
+        _ if crate::quic::is_quic(addr) => {
+            let connector = crate::quic::QuicConnector::new(addr)?;
+            let uri = "http://localhost".to_owned();
+            MyEndpoint::try_from_uri(uri)?.with_connector(Connector::Quic(connector))
+        }
         Protocol::Dns(_) => {
             let (dns_name, tcp_port, http_or_https) = parse_dns(addr)?;
             let uri = format!("{http_or_https}://{dns_name}:{tcp_port}");
@@ -53,12 +90,16 @@ fn endpoint_from_multiaddr(addr: &Multiaddr) -> Result<MyEndpoint> {
             let uri = format!("{http_or_https}://{socket_addr}");
             MyEndpoint::try_from_uri(uri)?
         }
-        // Protocol::Memory(_) => todo!(),
+        Protocol::Memory(_) => {
+            let (port, http_or_https) = crate::multiaddr::parse_memory(addr)?;
+            let uri = format!("{http_or_https}://localhost");
+            MyEndpoint::try_from_uri(uri)?.with_connector(Connector::Memory(port))
+        }
         #[cfg(unix)]
         Protocol::Unix(_) => {
             let (path, http_or_https) = crate::multiaddr::parse_unix(addr)?;
             let uri = format!("{http_or_https}://localhost");
-            MyEndpoint::try_from_uri(uri)?.with_uds_connector(path.as_ref().into())
+            MyEndpoint::try_from_uri(uri)?.with_connector(Connector::Unix(path.as_ref().into()))
         }
         unsupported => return Err(eyre!("unsupported protocol {unsupported}")),
     };
@@ -66,18 +107,26 @@ fn endpoint_from_multiaddr(addr: &Multiaddr) -> Result<MyEndpoint> {
     Ok(channel)
 }
 
+// How the connections of an endpoint are established, TCP being handled by tonic itself
+enum Connector {
+    Tcp,
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    Memory(u64),
+    #[cfg(feature = "quic")]
+    Quic(crate::quic::QuicConnector),
+}
+
 struct MyEndpoint {
     endpoint: Endpoint,
-    #[cfg(unix)]
-    uds_connector: Option<std::path::PathBuf>,
+    connector: Connector,
 }
 
 impl MyEndpoint {
     fn new(endpoint: Endpoint) -> Self {
         Self {
             endpoint,
-            #[cfg(unix)]
-            uds_connector: None,
+            connector: Connector::Tcp,
         }
     }
 
@@ -89,51 +138,91 @@ impl MyEndpoint {
         Ok(Self::new(endpoint))
     }
 
-    #[cfg(unix)]
-    fn with_uds_connector(self, path: std::path::PathBuf) -> Self {
+    fn with_connector(self, connector: Connector) -> Self {
         Self {
             endpoint: self.endpoint,
-            uds_connector: Some(path),
+            connector,
         }
     }
 
-    fn apply_config(mut self, config: &Config) -> Self {
+    fn apply_config(mut self, config: &Config) -> Result<Self> {
         self.endpoint = apply_config_to_endpoint(config, self.endpoint);
-        self
+        match &mut self.connector {
+            // QUIC does the TLS of its connections itself
+            #[cfg(feature = "quic")]
+            Connector::Quic(connector) => connector.apply_tls(config.tls.as_ref())?,
+            _ => {
+                if let Some(tls) = &config.tls {
+                    self.endpoint = self
+                        .endpoint
+                        .tls_config(tls.client_tls_config()?)
+                        .context("invalid client TLS config")?;
+                }
+            }
+        }
+        Ok(self)
     }
 
     fn connect_lazy(self) -> Channel {
-        #[cfg(unix)]
-        if let Some(path) = self.uds_connector {
-            return self
-                .endpoint
-                .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
-                    let path = path.clone();
-
-                    // Connect to a Uds socket
-                    tokio::net::UnixStream::connect(path)
-                }));
-        }
+        match self.connector {
+            Connector::Tcp => self.endpoint.connect_lazy(),
+            #[cfg(unix)]
+            Connector::Unix(path) => {
+                self.endpoint
+                    .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
+                        let path = path.clone();
 
-        self.endpoint.connect_lazy()
+                        // Connect to a Uds socket
+                        tokio::net::UnixStream::connect(path)
+                    }))
+            }
+            Connector::Memory(port) => {
+                self.endpoint
+                    .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
+                        crate::memory::connect(port)
+                    }))
+            }
+            #[cfg(feature = "quic")]
+            Connector::Quic(connector) => {
+                self.endpoint
+                    .connect_with_connector_lazy(tower::service_fn(move |_: Uri| {
+                        connector.clone().connect()
+                    }))
+            }
+        }
     }
 
     async fn connect(self) -> Result<Channel> {
-        #[cfg(unix)]
-        if let Some(path) = self.uds_connector {
-            return self
-                .endpoint
-                .connect_with_connector(tower::service_fn(move |_: Uri| {
-                    let path = path.clone();
-
-                    // Connect to a Uds socket
-                    tokio::net::UnixStream::connect(path)
-                }))
-                .await
-                .map_err(Into::into);
-        }
+        let channel = match self.connector {
+            Connector::Tcp => self.endpoint.connect().await?,
+            #[cfg(unix)]
+            Connector::Unix(path) => {
+                self.endpoint
+                    .connect_with_connector(tower::service_fn(move |_: Uri| {
+                        let path = path.clone();
 
-        self.endpoint.connect().await.map_err(Into::into)
+                        // Connect to a Uds socket
+                        tokio::net::UnixStream::connect(path)
+                    }))
+                    .await?
+            }
+            Connector::Memory(port) => {
+                self.endpoint
+                    .connect_with_connector(tower::service_fn(move |_: Uri| {
+                        crate::memory::connect(port)
+                    }))
+                    .await?
+            }
+            #[cfg(feature = "quic")]
+            Connector::Quic(connector) => {
+                self.endpoint
+                    .connect_with_connector(tower::service_fn(move |_: Uri| {
+                        connector.clone().connect()
+                    }))
+                    .await?
+            }
+        };
+        Ok(channel)
     }
 }
 
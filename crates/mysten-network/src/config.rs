@@ -1,15 +1,23 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use crate::metrics::{DefaultMetricsCallbackProvider, MetricsCallbackProvider};
+use crate::metrics::{
+    DefaultMetricsCallbackProvider, MetricsCallbackProvider, RequestMetrics, RequestMetricsService,
+};
 use crate::{
-    client::{connect_lazy_with_config, connect_with_config},
+    client::{
+        connect_lazy_with_config, connect_lazy_with_retries, connect_with_config,
+        connect_with_retries,
+    },
+    retry::{RetryChannel, RetryConfig},
     server::ServerBuilder,
+    tls::TlsConfig,
 };
-use eyre::Result;
+use eyre::{eyre, Result};
 use multiaddr::Multiaddr;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tonic::transport::Channel;
+use tower::Layer;
 
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Config {
@@ -72,6 +80,33 @@ pub struct Config {
 
     // Only affects servers
     pub global_concurrency_limit: Option<usize>,
+
+    /// Set the TLS of the connections, see `crate::tls`.
+    ///
+    /// Default is plaintext (None)
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Set the retries of the requests of the clients built with `connect_with_retries` and
+    /// `connect_lazy_with_retries`, see `crate::retry`.
+    ///
+    /// Default is `RetryConfig::default()` (None)
+    #[serde(default)]
+    pub retry: Option<RetryConfig>,
+
+    /// Set the metrics the requests of the servers, and of the clients built with
+    /// `connect_metered` and `connect_lazy_metered`, are recorded into.
+    ///
+    /// Default is no request metrics (None)
+    #[serde(skip)]
+    pub request_metrics: Option<RequestMetrics>,
+
+    /// Set the time the in-flight requests are given to complete once the server is shut down,
+    /// before being aborted.
+    ///
+    /// Only affects servers. Default is no deadline (None)
+    #[serde(default)]
+    pub shutdown_drain_deadline: Option<Duration>,
 }
 
 impl Config {
@@ -97,4 +132,39 @@ impl Config {
     pub fn connect_lazy(&self, addr: &Multiaddr) -> Result<Channel> {
         connect_lazy_with_config(addr, self)
     }
+
+    /// Connects to `addr`, recording the metrics of the requests into `request_metrics`
+    pub async fn connect_metered(
+        &self,
+        addr: &Multiaddr,
+    ) -> Result<RequestMetricsService<Channel>> {
+        let channel = self.connect(addr).await?;
+        self.metered(channel)
+    }
+
+    /// Creates a client of `addr` connecting on its first request, recording the metrics of the
+    /// requests into `request_metrics`
+    pub fn connect_lazy_metered(&self, addr: &Multiaddr) -> Result<RequestMetricsService<Channel>> {
+        let channel = self.connect_lazy(addr)?;
+        self.metered(channel)
+    }
+
+    fn metered(&self, channel: Channel) -> Result<RequestMetricsService<Channel>> {
+        let metrics = self
+            .request_metrics
+            .as_ref()
+            .ok_or_else(|| eyre!("no request metrics configured"))?;
+        Ok(metrics.client_layer().layer(channel))
+    }
+
+    /// Connects to `addr`, retrying the connection and then the requests according to `retry`
+    pub async fn connect_with_retries(&self, addr: &Multiaddr) -> Result<RetryChannel> {
+        connect_with_retries(addr, self).await
+    }
+
+    /// Creates a client of `addr` connecting on its first request, retrying the requests
+    /// according to `retry`
+    pub fn connect_lazy_with_retries(&self, addr: &Multiaddr) -> Result<RetryChannel> {
+        connect_lazy_with_retries(addr, self)
+    }
 }
@@ -3,6 +3,12 @@
 pub mod client;
 pub mod codec;
 pub mod config;
+pub mod memory;
 pub mod metrics;
 pub mod multiaddr;
+pub mod pool;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod retry;
 pub mod server;
+pub mod tls;
@@ -0,0 +1,146 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! An in-process transport, for `/memory/<port>/http` addresses.
+//!
+//! Servers bound to a memory address accept the connections of the clients of the same process,
+//! over in-memory pipes, so that local components and tests don't allocate TCP ports. Binding
+//! `/memory/0` allocates an unused port.
+
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use eyre::{eyre, Result};
+use futures::Stream;
+use once_cell::sync::Lazy;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+    sync::mpsc,
+};
+use tonic::transport::server::Connected;
+
+/// The size of the buffers of the in-memory pipes, in each direction
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+/// The number of connections waiting to be accepted by a memory listener
+const MEMORY_BACKLOG: usize = 128;
+
+static LISTENERS: Lazy<Mutex<MemoryListeners>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct MemoryListeners {
+    listeners: HashMap<u64, mpsc::Sender<MemoryStream>>,
+    next_port: u64,
+}
+
+fn listeners() -> std::sync::MutexGuard<'static, MemoryListeners> {
+    LISTENERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// One end of an in-memory connection
+#[derive(Debug)]
+pub struct MemoryStream(DuplexStream);
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl Connected for MemoryStream {
+    type ConnectInfo = ();
+
+    fn connect_info(&self) -> Self::ConnectInfo {}
+}
+
+/// The connections accepted by a server bound to a memory port, which is released on drop
+pub struct MemoryIncoming {
+    port: u64,
+    receiver: mpsc::Receiver<MemoryStream>,
+}
+
+impl MemoryIncoming {
+    pub fn port(&self) -> u64 {
+        self.port
+    }
+}
+
+impl Stream for MemoryIncoming {
+    type Item = io::Result<MemoryStream>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+impl Drop for MemoryIncoming {
+    fn drop(&mut self) {
+        listeners().listeners.remove(&self.port);
+    }
+}
+
+/// Binds the memory port `port`, or an unused one if 0
+pub fn bind(port: u64) -> Result<MemoryIncoming> {
+    let mut listeners = listeners();
+    let port = match port {
+        0 => loop {
+            listeners.next_port += 1;
+            if !listeners.listeners.contains_key(&listeners.next_port) {
+                break listeners.next_port;
+            }
+        },
+        port if listeners.listeners.contains_key(&port) => {
+            return Err(eyre!("memory port {port} is already bound"))
+        }
+        port => port,
+    };
+    let (sender, receiver) = mpsc::channel(MEMORY_BACKLOG);
+    listeners.listeners.insert(port, sender);
+    Ok(MemoryIncoming { port, receiver })
+}
+
+/// Connects to the server bound to the memory port `port`
+pub async fn connect(port: u64) -> io::Result<MemoryStream> {
+    let sender = listeners().listeners.get(&port).cloned().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("no server bound to memory port {port}"),
+        )
+    })?;
+    let (client, server) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+    sender.send(MemoryStream(server)).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("the server of memory port {port} stopped"),
+        )
+    })?;
+    Ok(MemoryStream(client))
+}
@@ -1,9 +1,20 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use std::time::Duration;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry, HistogramVec, IntCounterVec, IntGaugeVec, Registry,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tonic::codegen::http::header::HeaderName;
 use tonic::codegen::http::{HeaderValue, Request, Response};
 use tonic::{Code, Status};
+use tower::{Layer, Service};
 use tower_http::classify::GrpcFailureClass;
 use tower_http::trace::{OnFailure, OnRequest, OnResponse};
 use tracing::Span;
@@ -100,3 +111,218 @@ impl<M: MetricsCallbackProvider> OnFailure<GrpcFailureClass> for MetricsHandler<
         // just do nothing for now so we avoid printing unnecessary logs
     }
 }
+
+const LATENCY_SEC_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30., 60.,
+];
+
+/// The request metrics of the servers and clients built from a `Config` holding them, labelled
+/// by `side` (`server` or `client`) and `path` (the gRPC route).
+///
+/// Only the routes of the known services are labelled with their path, so that requests to
+/// arbitrary paths can't create an unbounded number of series: the others are labelled `unknown`.
+/// The services added to a server built from the `Config` are known, the services called by the
+/// clients are made known with `register_service`.
+///
+/// The status codes are read from the headers of the responses, where the servers put them when
+/// answering without messages, e.g. with an error. The other responses are counted as `Ok`.
+#[derive(Clone)]
+pub struct RequestMetrics {
+    requests: IntCounterVec,
+    inflight_requests: IntGaugeVec,
+    request_latency: HistogramVec,
+    responses: IntCounterVec,
+    /// The names of the services whose routes are labelled with their path
+    known_services: Arc<RwLock<HashSet<String>>>,
+}
+
+impl fmt::Debug for RequestMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestMetrics").finish_non_exhaustive()
+    }
+}
+
+impl RequestMetrics {
+    /// Registers the request metrics into `registry`, which should happen once per registry
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            requests: register_int_counter_vec_with_registry!(
+                "grpc_requests",
+                "The number of requests by route",
+                &["side", "path"],
+                registry
+            )
+            .unwrap(),
+            inflight_requests: register_int_gauge_vec_with_registry!(
+                "grpc_inflight_requests",
+                "The number of requests by route waiting for their response",
+                &["side", "path"],
+                registry
+            )
+            .unwrap(),
+            request_latency: register_histogram_vec_with_registry!(
+                "grpc_request_latency_seconds",
+                "The latency of the requests by route, until their response headers",
+                &["side", "path"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            responses: register_int_counter_vec_with_registry!(
+                "grpc_responses",
+                "The number of responses by route and status code",
+                &["side", "path", "code"],
+                registry
+            )
+            .unwrap(),
+            known_services: Arc::default(),
+        }
+    }
+
+    /// Labels the routes of the service `name`, e.g. `NAME` of its generated server, with their
+    /// path
+    pub fn register_service(&self, name: &str) {
+        self.known_services
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned());
+    }
+
+    /// The label of the route `path`, of the form `/<service>/<method>`
+    pub(crate) fn path_label(&self, path: &str) -> String {
+        let service = path
+            .strip_prefix('/')
+            .and_then(|route| route.split_once('/'))
+            .map(|(service, _)| service);
+        let known = service.map_or(false, |service| {
+            self.known_services
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .contains(service)
+        });
+        if known {
+            path.to_owned()
+        } else {
+            "unknown".to_owned()
+        }
+    }
+
+    pub fn server_layer(&self) -> RequestMetricsLayer {
+        RequestMetricsLayer {
+            metrics: self.clone(),
+            side: "server",
+        }
+    }
+
+    pub fn client_layer(&self) -> RequestMetricsLayer {
+        RequestMetricsLayer {
+            metrics: self.clone(),
+            side: "client",
+        }
+    }
+}
+
+/// Records the metrics of the requests of the wrapped service into `RequestMetrics`
+#[derive(Clone, Debug)]
+pub struct RequestMetricsLayer {
+    metrics: RequestMetrics,
+    side: &'static str,
+}
+
+impl<S> Layer<S> for RequestMetricsLayer {
+    type Service = RequestMetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestMetricsService {
+            inner,
+            metrics: self.metrics.clone(),
+            side: self.side,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestMetricsService<S> {
+    inner: S,
+    metrics: RequestMetrics,
+    side: &'static str,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestMetricsService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let path = self.metrics.path_label(request.uri().path());
+        self.metrics
+            .requests
+            .with_label_values(&[self.side, &path])
+            .inc();
+        let guard = InflightGuard::new(self.metrics.clone(), self.side, path);
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let result = response.await;
+            let code = match &result {
+                Ok(response) => format!(
+                    "{:?}",
+                    Status::from_header_map(response.headers()).map_or(Code::Ok, |s| s.code())
+                ),
+                Err(_) => "TransportError".to_owned(),
+            };
+            guard.finish(&code);
+            result
+        })
+    }
+}
+
+// Tracks a request in flight, until its response or its cancellation
+struct InflightGuard {
+    metrics: RequestMetrics,
+    side: &'static str,
+    path: String,
+    start: Instant,
+}
+
+impl InflightGuard {
+    fn new(metrics: RequestMetrics, side: &'static str, path: String) -> Self {
+        metrics
+            .inflight_requests
+            .with_label_values(&[side, &path])
+            .inc();
+        Self {
+            metrics,
+            side,
+            path,
+            start: Instant::now(),
+        }
+    }
+
+    fn finish(self, code: &str) {
+        self.metrics
+            .request_latency
+            .with_label_values(&[self.side, &self.path])
+            .observe(self.start.elapsed().as_secs_f64());
+        self.metrics
+            .responses
+            .with_label_values(&[self.side, &self.path, code])
+            .inc();
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .inflight_requests
+            .with_label_values(&[self.side, &self.path])
+            .dec();
+    }
+}
@@ -130,6 +130,56 @@ pub(crate) fn parse_unix(address: &Multiaddr) -> Result<(Cow<'_, str>, &'static
     Ok((path, http_or_https))
 }
 
+// Parse a full /{ip4,ip6,dns}/-/udp/-/quic address into its host and port
+#[cfg(feature = "quic")]
+pub(crate) fn parse_quic(address: &Multiaddr) -> Result<(Cow<'_, str>, u16)> {
+    let mut iter = address.iter();
+
+    let host = match iter
+        .next()
+        .ok_or_else(|| eyre!("unexpected end of multiaddr"))?
+    {
+        Protocol::Ip4(ip4_addr) => Cow::Owned(ip4_addr.to_string()),
+        Protocol::Ip6(ip6_addr) => Cow::Owned(ip6_addr.to_string()),
+        Protocol::Dns(dns_name) => dns_name,
+        other => return Err(eyre!("expected ip4, ip6 or dns found {other}")),
+    };
+    let udp_port = match iter
+        .next()
+        .ok_or_else(|| eyre!("unexpected end of multiaddr"))?
+    {
+        Protocol::Udp(port) => port,
+        other => return Err(eyre!("expected udp found {other}")),
+    };
+    match iter
+        .next()
+        .ok_or_else(|| eyre!("unexpected end of multiaddr"))?
+    {
+        Protocol::Quic => {}
+        other => return Err(eyre!("expected quic found {other}")),
+    }
+    parse_end(&mut iter)?;
+
+    Ok((host, udp_port))
+}
+
+// Parse a full /memory/-/{http,https} address
+pub(crate) fn parse_memory(address: &Multiaddr) -> Result<(u64, &'static str)> {
+    let mut iter = address.iter();
+
+    let port = match iter
+        .next()
+        .ok_or_else(|| eyre!("unexpected end of multiaddr"))?
+    {
+        Protocol::Memory(port) => port,
+        other => return Err(eyre!("expected memory found {other}")),
+    };
+    let http_or_https = parse_http_https(&mut iter)?;
+    parse_end(&mut iter)?;
+
+    Ok((port, http_or_https))
+}
+
 #[cfg(test)]
 mod test {
     use super::to_socket_addr;
@@ -0,0 +1,359 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A pool of channels keyed by the address of their peer, shared by the clients of a service.
+//!
+//! The channels are created lazily on their first use, and kept alive by HTTP/2 keepalive pings.
+//! The health checks of the pool periodically query the health service of each peer, and evict
+//! the channels of the peers failing `max_failures` checks in a row, so that the next use of a
+//! peer creates a new channel.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::Duration,
+};
+
+use eyre::Result;
+use multiaddr::Multiaddr;
+use prometheus::{
+    register_int_counter_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_with_registry, IntCounter, IntCounterVec, IntGauge, Registry,
+};
+use tokio::task::JoinHandle;
+use tonic::transport::Channel;
+use tonic_health::proto::{health_client::HealthClient, HealthCheckRequest};
+use tracing::{debug, warn};
+
+use crate::config::Config;
+
+#[derive(Clone, Debug)]
+pub struct ChannelPoolConfig {
+    /// The interval between two health checks of the channels.
+    ///
+    /// Default is 10s
+    pub health_check_interval: Duration,
+
+    /// The time a peer is given to answer a health check.
+    ///
+    /// Default is 5s
+    pub health_check_timeout: Duration,
+
+    /// The number of failed health checks in a row after which a channel is evicted.
+    ///
+    /// Default is 3
+    pub max_failures: usize,
+}
+
+impl Default for ChannelPoolConfig {
+    fn default() -> Self {
+        Self {
+            health_check_interval: Duration::from_secs(10),
+            health_check_timeout: Duration::from_secs(5),
+            max_failures: 3,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PoolMetrics {
+    channels: IntGauge,
+    lookups: IntCounterVec,
+    health_check_failures: IntCounter,
+    evictions: IntCounter,
+}
+
+impl PoolMetrics {
+    fn new(registry: &Registry) -> Self {
+        Self {
+            channels: register_int_gauge_with_registry!(
+                "channel_pool_channels",
+                "The number of channels in the pool",
+                registry
+            )
+            .unwrap(),
+            lookups: register_int_counter_vec_with_registry!(
+                "channel_pool_lookups",
+                "The number of channel lookups, by whether a channel was pooled",
+                &["result"],
+                registry
+            )
+            .unwrap(),
+            health_check_failures: register_int_counter_with_registry!(
+                "channel_pool_health_check_failures",
+                "The number of failed health checks of the pooled channels",
+                registry
+            )
+            .unwrap(),
+            evictions: register_int_counter_with_registry!(
+                "channel_pool_evictions",
+                "The number of channels evicted from the pool",
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+struct PooledChannel {
+    channel: Channel,
+    // The number of failed health checks in a row
+    failures: usize,
+}
+
+struct PoolInner {
+    config: Config,
+    pool_config: ChannelPoolConfig,
+    channels: Mutex<HashMap<Multiaddr, PooledChannel>>,
+    metrics: Option<PoolMetrics>,
+}
+
+/// A pool of channels keyed by the address of their peer, cheap to clone
+#[derive(Clone)]
+pub struct ChannelPool {
+    inner: Arc<PoolInner>,
+}
+
+impl fmt::Debug for ChannelPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelPool")
+            .field("pool_config", &self.inner.pool_config)
+            .field("channels", &self.len())
+            .finish()
+    }
+}
+
+impl ChannelPool {
+    /// Creates a pool connecting with `config`. The HTTP/2 keepalive interval of the channels
+    /// defaults to the health check interval.
+    pub fn new(config: Config, pool_config: ChannelPoolConfig) -> Self {
+        Self::build(config, pool_config, None)
+    }
+
+    /// Creates a pool recording its metrics into `registry`, which should happen once per registry
+    pub fn new_with_metrics(
+        config: Config,
+        pool_config: ChannelPoolConfig,
+        registry: &Registry,
+    ) -> Self {
+        Self::build(config, pool_config, Some(PoolMetrics::new(registry)))
+    }
+
+    fn build(
+        mut config: Config,
+        pool_config: ChannelPoolConfig,
+        metrics: Option<PoolMetrics>,
+    ) -> Self {
+        if config.http2_keepalive_interval.is_none() {
+            config.http2_keepalive_interval = Some(pool_config.health_check_interval);
+        }
+        Self {
+            inner: Arc::new(PoolInner {
+                config,
+                pool_config,
+                channels: Mutex::new(HashMap::new()),
+                metrics,
+            }),
+        }
+    }
+
+    fn channels(&self) -> MutexGuard<'_, HashMap<Multiaddr, PooledChannel>> {
+        self.inner
+            .channels
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the channel of `address`, creating it if it isn't pooled
+    pub fn get(&self, address: &Multiaddr) -> Result<Channel> {
+        let mut channels = self.channels();
+        if let Some(pooled) = channels.get(address) {
+            if let Some(metrics) = &self.inner.metrics {
+                metrics.lookups.with_label_values(&["hit"]).inc();
+            }
+            return Ok(pooled.channel.clone());
+        }
+
+        let channel = self.inner.config.connect_lazy(address)?;
+        channels.insert(
+            address.clone(),
+            PooledChannel {
+                channel: channel.clone(),
+                failures: 0,
+            },
+        );
+        if let Some(metrics) = &self.inner.metrics {
+            metrics.lookups.with_label_values(&["miss"]).inc();
+            metrics.channels.set(channels.len() as i64);
+        }
+        Ok(channel)
+    }
+
+    /// Removes the channel of `address` from the pool, returning whether it was pooled
+    pub fn evict(&self, address: &Multiaddr) -> bool {
+        let mut channels = self.channels();
+        let evicted = channels.remove(address).is_some();
+        if let Some(metrics) = &self.inner.metrics {
+            if evicted {
+                metrics.evictions.inc();
+            }
+            metrics.channels.set(channels.len() as i64);
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks the health of all the pooled channels once, evicting the channels failing
+    /// `max_failures` checks in a row
+    pub async fn check_health(&self) {
+        let channels: Vec<_> = self
+            .channels()
+            .iter()
+            .map(|(address, pooled)| (address.clone(), pooled.channel.clone()))
+            .collect();
+
+        let timeout = self.inner.pool_config.health_check_timeout;
+        let checks = channels.into_iter().map(|(address, channel)| async move {
+            let mut client = HealthClient::new(channel);
+            let check = client.check(HealthCheckRequest {
+                service: "".to_owned(),
+            });
+            let healthy = matches!(tokio::time::timeout(timeout, check).await, Ok(Ok(_)));
+            (address, healthy)
+        });
+        let results = futures::future::join_all(checks).await;
+
+        let mut channels = self.channels();
+        for (address, healthy) in results {
+            // The channel may have been evicted during the check
+            let pooled = match channels.get_mut(&address) {
+                Some(pooled) => pooled,
+                None => continue,
+            };
+            if healthy {
+                pooled.failures = 0;
+                continue;
+            }
+            pooled.failures += 1;
+            if let Some(metrics) = &self.inner.metrics {
+                metrics.health_check_failures.inc();
+            }
+            debug!(
+                "health check of {address} failed {} time(s) in a row",
+                pooled.failures
+            );
+            if pooled.failures >= self.inner.pool_config.max_failures {
+                warn!("evicting the channel of {address}, failing its health checks");
+                channels.remove(&address);
+                if let Some(metrics) = &self.inner.metrics {
+                    metrics.evictions.inc();
+                }
+            }
+        }
+        if let Some(metrics) = &self.inner.metrics {
+            metrics.channels.set(channels.len() as i64);
+        }
+    }
+
+    /// Spawns the task checking the health of the channels every `health_check_interval`,
+    /// which stops once the pool is dropped
+    pub fn spawn_health_checks(&self) -> JoinHandle<()> {
+        let pool: Weak<PoolInner> = Arc::downgrade(&self.inner);
+        let interval = self.inner.pool_config.health_check_interval;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match pool.upgrade() {
+                    Some(inner) => ChannelPool { inner }.check_health().await,
+                    None => return,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChannelPool, ChannelPoolConfig};
+    use crate::config::Config;
+    use multiaddr::Multiaddr;
+    use tonic_health::proto::health_client::HealthClient;
+    use tonic_health::proto::HealthCheckRequest;
+
+    #[tokio::test]
+    async fn pool() {
+        let registry = prometheus::Registry::new();
+        let pool = ChannelPool::new_with_metrics(
+            Config::new(),
+            ChannelPoolConfig {
+                max_failures: 2,
+                ..Default::default()
+            },
+            &registry,
+        );
+
+        let mut server = Config::new()
+            .server_builder()
+            .bind(&"/memory/0/http".parse().unwrap())
+            .await
+            .unwrap();
+        let address = server.local_addr().to_owned();
+        let cancel_handle = server.take_cancel_handle().unwrap();
+        let server_handle = tokio::spawn(server.serve());
+
+        let mut client = HealthClient::new(pool.get(&address).unwrap());
+        client
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+        pool.get(&address).unwrap();
+        assert_eq!(pool.len(), 1);
+
+        // Healthy channels are kept
+        pool.check_health().await;
+        assert_eq!(pool.len(), 1);
+
+        // The channels failing their health checks are evicted after `max_failures` checks
+        cancel_handle.send(()).unwrap();
+        server_handle.await.unwrap().unwrap();
+        pool.check_health().await;
+        assert_eq!(pool.len(), 1);
+        pool.check_health().await;
+        assert!(pool.is_empty());
+
+        let unknown: Multiaddr = "/memory/4840/http".parse().unwrap();
+        assert!(!pool.evict(&unknown));
+
+        let value = |name: &str| {
+            registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == name)
+                .unwrap()
+                .get_metric()
+                .iter()
+                .map(|metric| {
+                    if metric.has_gauge() {
+                        metric.get_gauge().get_value()
+                    } else {
+                        metric.get_counter().get_value()
+                    }
+                })
+                .sum::<f64>()
+        };
+        assert_eq!(value("channel_pool_channels"), 0.0);
+        assert_eq!(value("channel_pool_lookups"), 2.0);
+        assert_eq!(value("channel_pool_health_check_failures"), 2.0);
+        assert_eq!(value("channel_pool_evictions"), 1.0);
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A QUIC transport, for `/{ip4,ip6,dns}/-/udp/-/quic` addresses, behind the `quic` feature.
+//!
+//! Every request is sent in its own bidirectional stream of the QUIC connection, as an HTTP/2
+//! connection carrying this request only, so that the requests of a channel don't block each
+//! other, and the servers and clients keep the same API. A channel reaches its QUIC connection
+//! through an in-memory HTTP/2 connection, whose requests are forwarded to new streams. The channels
+//! created with the same connector share one client endpoint and one QUIC connection, which is
+//! established again on the next request once lost.
+//!
+//! QUIC encrypts the connections with the `tls` of the config, which is then required: the
+//! certificates are verified as for TLS over TCP, and no TLS is added on top of QUIC. The clients
+//! resolve the address of the server on each connection.
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use eyre::{eyre, Context as _, Result};
+use futures::{Stream, StreamExt};
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response};
+use multiaddr::{Multiaddr, Protocol};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+};
+use tonic::transport::server::Connected;
+use tracing::debug;
+
+use crate::{multiaddr::parse_quic, tls::TlsConfig};
+
+/// The number of connections waiting to be accepted by a QUIC server
+const QUIC_BACKLOG: usize = 128;
+
+/// The size of the buffers of the in-memory connection between a channel and its QUIC connection
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+pub(crate) fn is_quic(address: &Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::Quic))
+}
+
+/// A stream of a QUIC connection, carrying the HTTP/2 connection of a request
+pub struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    remote_addr: SocketAddr,
+    _connection: quinn::Connection,
+}
+
+impl QuicStream {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+impl Connected for QuicStream {
+    type ConnectInfo = SocketAddr;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.remote_addr
+    }
+}
+
+/// The streams accepted by a server bound to a QUIC address, which is closed on drop
+pub struct QuicIncoming {
+    receiver: mpsc::Receiver<QuicStream>,
+    endpoint: quinn::Endpoint,
+    accept: JoinHandle<()>,
+}
+
+impl Stream for QuicIncoming {
+    type Item = io::Result<QuicStream>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+impl Drop for QuicIncoming {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.endpoint.close(0u32.into(), b"server stopped");
+    }
+}
+
+async fn resolve(host: &str, port: u16) -> io::Result<SocketAddr> {
+    tokio::net::lookup_host((host, port))
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unable to resolve {host}")))
+}
+
+/// Binds the QUIC address `address`, returning it with its actual port
+pub(crate) async fn bind(
+    address: &Multiaddr,
+    tls: Option<&TlsConfig>,
+) -> Result<(Multiaddr, QuicIncoming)> {
+    let (host, port) = parse_quic(address)?;
+    let tls = tls.ok_or_else(|| eyre!("a QUIC server requires a TLS config"))?;
+    let crypto = tls.rustls_server_config()?;
+    let socket_addr = resolve(&host, port).await?;
+    let (endpoint, mut incoming) = quinn::Endpoint::server(
+        quinn::ServerConfig::with_crypto(Arc::new(crypto)),
+        socket_addr,
+    )
+    .with_context(|| format!("unable to bind {address}"))?;
+    let local_addr = update_udp_port_in_multiaddr(address, endpoint.local_addr()?.port());
+
+    let (sender, receiver) = mpsc::channel(QUIC_BACKLOG);
+    let accept = tokio::spawn(async move {
+        while let Some(connecting) = incoming.next().await {
+            let sender = sender.clone();
+            // The handshakes of the connections don't delay each other
+            tokio::spawn(async move {
+                if let Err(e) = accept_streams(connecting, sender).await {
+                    debug!("failed to accept the streams of a QUIC connection: {e}");
+                }
+            });
+        }
+    });
+    Ok((
+        local_addr,
+        QuicIncoming {
+            receiver,
+            endpoint,
+            accept,
+        },
+    ))
+}
+
+/// Passes every stream opened by the client of `connecting` to the server, until the connection
+/// is closed
+async fn accept_streams(
+    connecting: quinn::Connecting,
+    sender: mpsc::Sender<QuicStream>,
+) -> Result<()> {
+    let quinn::NewConnection {
+        connection,
+        mut bi_streams,
+        ..
+    } = connecting.await?;
+    while let Some(streams) = bi_streams.next().await {
+        let (send, recv) = match streams {
+            Ok(streams) => streams,
+            Err(quinn::ConnectionError::ApplicationClosed(_)) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let stream = QuicStream {
+            send,
+            recv,
+            remote_addr: connection.remote_address(),
+            _connection: connection.clone(),
+        };
+        if sender.send(stream).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn update_udp_port_in_multiaddr(address: &Multiaddr, port: u16) -> Multiaddr {
+    address
+        .replace(1, |protocol| {
+            if let Protocol::Udp(_) = protocol {
+                Some(Protocol::Udp(port))
+            } else {
+                panic!("expected udp protocol at index 1");
+            }
+        })
+        .expect("udp protocol at index 1")
+}
+
+/// Connects the channels to a QUIC address
+#[derive(Clone)]
+pub(crate) struct QuicConnector {
+    host: String,
+    port: u16,
+    server_name: String,
+    crypto: Option<Arc<rustls::ClientConfig>>,
+    // Shared by the clones of the connector, i.e. by its channels and their reconnections
+    client: Arc<Mutex<QuicClient>>,
+}
+
+/// The client endpoint of a connector and its connection to the server, both created on demand
+#[derive(Default)]
+struct QuicClient {
+    endpoint: Option<quinn::Endpoint>,
+    connection: Option<quinn::Connection>,
+}
+
+impl QuicConnector {
+    pub(crate) fn new(address: &Multiaddr) -> Result<Self> {
+        let (host, port) = parse_quic(address)?;
+        Ok(Self {
+            server_name: host.to_string(),
+            host: host.into_owned(),
+            port,
+            crypto: None,
+            client: Default::default(),
+        })
+    }
+
+    pub(crate) fn apply_tls(&mut self, tls: Option<&TlsConfig>) -> Result<()> {
+        if let Some(tls) = tls {
+            self.crypto = Some(Arc::new(tls.rustls_client_config()?));
+            if let Some(domain_name) = &tls.domain_name {
+                self.server_name = domain_name.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the in-memory connection of a channel, whose requests are each forwarded to a new
+    /// stream of the QUIC connection
+    pub(crate) async fn connect(self) -> io::Result<DuplexStream> {
+        // Reports the unreachable servers, and the missing TLS config, to the channel
+        self.connection().await?;
+
+        let (channel_io, bridge_io) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let service = service_fn(move |request| self.clone().forward(request));
+            if let Err(e) = Http::new()
+                .http2_only(true)
+                .serve_connection(bridge_io, service)
+                .await
+            {
+                debug!("the connection of a QUIC channel failed: {e}");
+            }
+        });
+        Ok(channel_io)
+    }
+
+    /// Sends `request` in a new stream of the QUIC connection
+    async fn forward(self, request: Request<Body>) -> Result<Response<Body>, tower::BoxError> {
+        let connection = self.connection().await?;
+        let (send, recv) = match connection.open_bi().await {
+            Ok(streams) => streams,
+            Err(e) => {
+                // The next request connects again
+                self.client.lock().await.connection = None;
+                return Err(e.into());
+            }
+        };
+        let stream = QuicStream {
+            send,
+            recv,
+            remote_addr: connection.remote_address(),
+            _connection: connection,
+        };
+        let (mut sender, http2) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake(stream)
+            .await?;
+        tokio::spawn(async move {
+            if let Err(e) = http2.await {
+                debug!("the stream of a QUIC request failed: {e}");
+            }
+        });
+        Ok(sender.send_request(request).await?)
+    }
+
+    /// Returns the QUIC connection of the connector, connecting it first if needed
+    async fn connection(&self) -> io::Result<quinn::Connection> {
+        let crypto = self.crypto.clone().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a QUIC client requires a TLS config",
+            )
+        })?;
+        let mut client = self.client.lock().await;
+        if let Some(connection) = &client.connection {
+            return Ok(connection.clone());
+        }
+
+        let remote_addr = resolve(&self.host, self.port).await?;
+        let endpoint = match &client.endpoint {
+            Some(endpoint) if endpoint.local_addr()?.is_ipv4() == remote_addr.is_ipv4() => {
+                endpoint.clone()
+            }
+            _ => {
+                let local_addr: SocketAddr = if remote_addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let mut endpoint = quinn::Endpoint::client(local_addr)?;
+                endpoint.set_default_client_config(quinn::ClientConfig::new(crypto));
+                client.endpoint = Some(endpoint.clone());
+                endpoint
+            }
+        };
+        let connection = endpoint
+            .connect(remote_addr, &self.server_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e))?
+            .connection;
+        client.connection = Some(connection.clone());
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Config;
+    use crate::tls::{PemSource, TlsConfig};
+    use multiaddr::Multiaddr;
+    use tonic_health::proto::health_client::HealthClient;
+    use tonic_health::proto::HealthCheckRequest;
+
+    #[tokio::test]
+    async fn quic() {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let private_key = PemSource::Pem(certificate.serialize_private_key_pem());
+        let certificate = PemSource::Pem(certificate.serialize_pem().unwrap());
+        let server_config = Config {
+            tls: Some(TlsConfig::new().with_identity(certificate.clone(), private_key)),
+            ..Config::new()
+        };
+        let client_config = Config {
+            tls: Some(TlsConfig::new().with_ca_certificate(certificate)),
+            ..Config::new()
+        };
+
+        let address: Multiaddr = "/dns/localhost/udp/0/quic".parse().unwrap();
+        let mut server = server_config.server_builder().bind(&address).await.unwrap();
+        let address = server.local_addr().to_owned();
+        let cancel_handle = server.take_cancel_handle().unwrap();
+        let server_handle = tokio::spawn(server.serve());
+
+        let channel = client_config.connect(&address).await.unwrap();
+        HealthClient::new(channel.clone())
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        // The concurrent requests of a channel are sent in streams of the same connection
+        let checks = (0..10).map(|_| {
+            let mut client = HealthClient::new(channel.clone());
+            async move {
+                client
+                    .check(HealthCheckRequest {
+                        service: "".to_owned(),
+                    })
+                    .await
+            }
+        });
+        for response in futures::future::join_all(checks).await {
+            response.unwrap();
+        }
+
+        // QUIC requires TLS
+        assert!(Config::new().connect(&address).await.is_err());
+        assert!(Config::new()
+            .server_builder()
+            .bind(&"/ip4/127.0.0.1/udp/0/quic".parse().unwrap())
+            .await
+            .is_err());
+
+        cancel_handle.send(()).unwrap();
+        server_handle.await.unwrap().unwrap();
+    }
+}
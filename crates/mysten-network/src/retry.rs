@@ -0,0 +1,519 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retries of the requests of the clients built with a `RetryConfig`.
+//!
+//! A request is retried, after a jittered exponential backoff, when it fails to reach the server
+//! (e.g. while the server restarts). The requests of the idempotent methods, see
+//! `RetryConfig::idempotent_methods`, are also retried when they fail in flight, or when the server
+//! answers with one of the retryable codes before sending any message. The channels reconnect
+//! lazily after a connection is lost, and re-resolve the DNS names of their address on each
+//! reconnection, so that retries reach a server that restarted elsewhere.
+//!
+//! The bodies of the requests are buffered so that they can be sent again. Only the bodies which
+//! are complete when the request is sent, and of at most `RetryConfig::max_body_bytes`, are: the
+//! other requests, e.g. of streaming methods, are sent as their body comes, and never retried.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::FutureExt;
+use hyper::body::HttpBody;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{Request, Response},
+        StdError,
+    },
+    transport::{Body, Channel},
+    Code, Status,
+};
+use tower::{retry::Policy, Service, ServiceExt};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RetryConfig {
+    /// The maximum number of attempts of a request, including the first one.
+    ///
+    /// Default is 5
+    pub max_attempts: usize,
+
+    /// The backoff before the first retry, doubled before each following one.
+    ///
+    /// Default is 100ms
+    pub initial_backoff: Duration,
+
+    /// The maximum backoff between two attempts.
+    ///
+    /// Default is 5s
+    pub max_backoff: Duration,
+
+    /// The fraction of the backoff randomly added or removed, to spread the retries of the
+    /// clients failing at the same time.
+    ///
+    /// Default is 0.2
+    pub jitter: f64,
+
+    /// The codes the server answers with which the requests of the idempotent methods are
+    /// retried. The requests failing to reach the server are always retried.
+    ///
+    /// Default is `Unavailable`
+    #[serde(with = "codes")]
+    pub retryable_codes: Vec<Code>,
+
+    /// The paths of the idempotent methods, e.g. `/package.Service/Method`, whose requests are
+    /// retried once they reached the server: when they fail in flight, or are answered with one
+    /// of the `retryable_codes`. The requests of the other methods may have been processed then.
+    ///
+    /// Default is none
+    #[serde(default)]
+    pub idempotent_methods: Vec<String>,
+
+    /// Whether the requests of all the methods are retried as the ones of `idempotent_methods`.
+    ///
+    /// Default is false
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
+
+    /// The maximum size of the body of the requests which are buffered to be retried, the larger
+    /// requests are sent once.
+    ///
+    /// Default is 1 MiB
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+}
+
+fn default_max_body_bytes() -> usize {
+    1 << 20
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.2,
+            retryable_codes: vec![Code::Unavailable],
+            idempotent_methods: vec![],
+            retry_non_idempotent: false,
+            max_body_bytes: default_max_body_bytes(),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The backoff before the retry following the `attempt`-th attempt, without jitter
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31) as u32;
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff)
+    }
+
+    pub(crate) fn jittered_backoff(&self, attempt: usize) -> Duration {
+        let backoff = self.backoff(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+    }
+
+    /// Whether the requests to `path` are retried once they reached the server
+    fn is_idempotent(&self, path: &str) -> bool {
+        self.retry_non_idempotent || self.idempotent_methods.iter().any(|method| method == path)
+    }
+}
+
+// The codes are (de)serialized as their numeric values
+mod codes {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tonic::Code;
+
+    pub fn serialize<S: Serializer>(codes: &[Code], serializer: S) -> Result<S::Ok, S::Error> {
+        codes
+            .iter()
+            .map(|code| *code as i32)
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Code>, D::Error> {
+        Ok(Vec::<i32>::deserialize(deserializer)?
+            .into_iter()
+            .map(Code::from_i32)
+            .collect())
+    }
+}
+
+/// The retry policy of a request, tracking its attempts
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    config: RetryConfig,
+    attempt: usize,
+}
+
+impl RetryPolicy {
+    fn new(config: RetryConfig) -> Self {
+        Self { config, attempt: 1 }
+    }
+}
+
+/// Whether the request failed before reaching the server, while connecting
+fn failed_to_connect(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<hyper::Error>() {
+            return error.is_connect();
+        }
+        // The errors of the connectors of the channel, e.g. a refused connection
+        if error.is::<std::io::Error>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+impl Policy<Request<Bytes>, Response<Body>, tonic::transport::Error> for RetryPolicy {
+    type Future = Pin<Box<dyn Future<Output = Self> + Send>>;
+
+    fn retry(
+        &self,
+        request: &Request<Bytes>,
+        result: Result<&Response<Body>, &tonic::transport::Error>,
+    ) -> Option<Self::Future> {
+        if self.attempt >= self.config.max_attempts {
+            return None;
+        }
+        let idempotent = self.config.is_idempotent(request.uri().path());
+        let retryable = match result {
+            // The channel reconnects before the next attempt
+            Err(e) => idempotent || failed_to_connect(e),
+            // The status of the answers without messages is in the headers
+            Ok(response) => {
+                idempotent
+                    && Status::from_header_map(response.headers()).map_or(false, |status| {
+                        self.config.retryable_codes.contains(&status.code())
+                    })
+            }
+        };
+        if !retryable {
+            return None;
+        }
+        let backoff = self.config.jittered_backoff(self.attempt);
+        let policy = Self {
+            config: self.config.clone(),
+            attempt: self.attempt + 1,
+        };
+        Some(Box::pin(async move {
+            tokio::time::sleep(backoff).await;
+            policy
+        }))
+    }
+
+    fn clone_request(&self, request: &Request<Bytes>) -> Option<Request<Bytes>> {
+        let mut clone = Request::new(request.body().clone());
+        *clone.method_mut() = request.method().clone();
+        *clone.uri_mut() = request.uri().clone();
+        *clone.version_mut() = request.version();
+        *clone.headers_mut() = request.headers().clone();
+        Some(clone)
+    }
+}
+
+/// Sends the buffered requests through the channel
+#[derive(Clone, Debug)]
+pub(crate) struct BufferedChannel(Channel);
+
+impl Service<Request<Bytes>> for BufferedChannel {
+    type Response = Response<Body>;
+    type Error = tonic::transport::Error;
+    type Future = tonic::transport::channel::ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request<BoxBody>>::poll_ready(&mut self.0, cx)
+    }
+
+    fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+        self.0
+            .call(request.map(|body| tonic::body::boxed(Body::from(body))))
+    }
+}
+
+/// The body of a request, buffered if it can be retried
+enum RequestBody {
+    Buffered(Bytes),
+    /// The chunks read so far, followed by the rest of the body
+    Streaming(BoxBody),
+}
+
+/// Buffers `body` if it is complete, and of at most `max_bytes`
+async fn buffer_body(mut body: BoxBody, max_bytes: usize) -> Result<RequestBody, Status> {
+    let mut buffered = BytesMut::new();
+    loop {
+        match body.data().now_or_never() {
+            Some(Some(chunk)) => {
+                buffered.extend_from_slice(&chunk?);
+                if buffered.len() > max_bytes {
+                    break;
+                }
+            }
+            Some(None) => return Ok(RequestBody::Buffered(buffered.freeze())),
+            // The next chunk isn't written yet, e.g. by a streaming request
+            None => break,
+        }
+    }
+    Ok(RequestBody::Streaming(tonic::body::boxed(PrefixedBody {
+        prefix: Some(buffered.freeze()),
+        body,
+    })))
+}
+
+/// A body whose first chunks were already read as `prefix`
+struct PrefixedBody {
+    prefix: Option<Bytes>,
+    body: BoxBody,
+}
+
+impl HttpBody for PrefixedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match self.prefix.take() {
+            Some(prefix) if !prefix.is_empty() => Poll::Ready(Some(Ok(prefix))),
+            _ => Pin::new(&mut self.body).poll_data(cx),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.body).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.prefix.as_ref().map_or(true, Bytes::is_empty) && self.body.is_end_stream()
+    }
+}
+
+/// A channel retrying its requests according to a `RetryConfig`
+#[derive(Clone, Debug)]
+pub struct RetryChannel {
+    inner: tower::retry::Retry<RetryPolicy, BufferedChannel>,
+    channel: Channel,
+    max_body_bytes: usize,
+}
+
+impl RetryChannel {
+    pub fn new(channel: Channel, config: RetryConfig) -> Self {
+        let max_body_bytes = config.max_body_bytes;
+        let inner = tower::ServiceBuilder::new()
+            .retry(RetryPolicy::new(config))
+            .service(BufferedChannel(channel.clone()));
+        Self {
+            inner,
+            channel,
+            max_body_bytes,
+        }
+    }
+}
+
+impl Service<Request<BoxBody>> for RetryChannel {
+    type Response = Response<Body>;
+    type Error = StdError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Each call waits for the readiness of its own clone of the inner service
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let inner = self.inner.clone();
+        let channel = self.channel.clone();
+        let max_body_bytes = self.max_body_bytes;
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let response = match buffer_body(body, max_body_bytes).await? {
+                RequestBody::Buffered(body) => {
+                    inner.oneshot(Request::from_parts(parts, body)).await?
+                }
+                RequestBody::Streaming(body) => {
+                    channel.oneshot(Request::from_parts(parts, body)).await?
+                }
+            };
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{buffer_body, RequestBody, RetryConfig, RetryPolicy};
+    use crate::config::Config;
+    use bytes::Bytes;
+    use multiaddr::Multiaddr;
+    use std::time::Duration;
+    use tonic::codegen::http::{Request, Response};
+    use tonic::transport::Body;
+    use tonic_health::proto::health_client::HealthClient;
+    use tonic_health::proto::HealthCheckRequest;
+    use tower::retry::Policy;
+
+    #[test]
+    fn backoff() {
+        let config = RetryConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(config.jittered_backoff(1), Duration::from_millis(100));
+        assert_eq!(config.jittered_backoff(2), Duration::from_millis(200));
+        assert_eq!(config.jittered_backoff(3), Duration::from_millis(400));
+        assert_eq!(config.jittered_backoff(4), Duration::from_millis(500));
+        assert_eq!(config.jittered_backoff(100), Duration::from_millis(500));
+
+        let config = RetryConfig {
+            jitter: 0.5,
+            ..config
+        };
+        for _ in 0..100 {
+            let backoff = config.jittered_backoff(1);
+            assert!(backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(150));
+        }
+    }
+
+    #[tokio::test]
+    async fn buffer_complete_bodies() {
+        let body = tonic::body::boxed(Body::from("request"));
+        assert!(matches!(
+            buffer_body(body, 1024).await.unwrap(),
+            RequestBody::Buffered(bytes) if bytes == "request"
+        ));
+
+        // Too large to be retried, the body is sent as read
+        let body = tonic::body::boxed(Body::from("request"));
+        match buffer_body(body, 4).await.unwrap() {
+            RequestBody::Streaming(body) => {
+                assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "request")
+            }
+            RequestBody::Buffered(_) => panic!("a body over the limit is buffered"),
+        }
+
+        // The body of a streaming request isn't complete when it is sent
+        let (sender, body) = Body::channel();
+        let body = buffer_body(tonic::body::boxed(body), 1024).await.unwrap();
+        assert!(matches!(body, RequestBody::Streaming(_)));
+        drop(sender);
+    }
+
+    #[test]
+    fn retry_idempotent_methods_only() {
+        let unavailable = Response::builder()
+            .header("grpc-status", "14")
+            .body(Body::empty())
+            .unwrap();
+        let request = |path: &str| {
+            Request::builder()
+                .uri(format!("http://localhost{path}"))
+                .body(Bytes::new())
+                .unwrap()
+        };
+        let policy = RetryPolicy::new(RetryConfig {
+            idempotent_methods: vec!["/test.Service/Get".to_owned()],
+            ..Default::default()
+        });
+        let retries = |path: &str| {
+            Policy::<_, _, tonic::transport::Error>::retry(
+                &policy,
+                &request(path),
+                Ok(&unavailable),
+            )
+            .is_some()
+        };
+        assert!(retries("/test.Service/Get"));
+        assert!(!retries("/test.Service/Set"));
+
+        let policy = RetryPolicy::new(RetryConfig {
+            retry_non_idempotent: true,
+            ..Default::default()
+        });
+        assert!(Policy::<_, _, tonic::transport::Error>::retry(
+            &policy,
+            &request("/test.Service/Set"),
+            Ok(&unavailable),
+        )
+        .is_some());
+    }
+
+    #[tokio::test]
+    async fn retry_until_server_is_up() {
+        let address: Multiaddr = "/memory/4740/http".parse().unwrap();
+        let mut config = Config::new();
+        config.retry = Some(RetryConfig {
+            initial_backoff: Duration::from_millis(50),
+            ..Default::default()
+        });
+
+        let channel = config.connect_lazy_with_retries(&address).unwrap();
+        let mut client = HealthClient::new(channel);
+
+        let server_address = address.clone();
+        let server_handle = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let server = Config::new()
+                .server_builder()
+                .bind(&server_address)
+                .await
+                .unwrap();
+            server.serve().await.unwrap();
+        });
+
+        // The first attempts fail to reach the server, which is not bound yet
+        client
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+        server_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_attempts() {
+        let address: Multiaddr = "/memory/4741/http".parse().unwrap();
+        let mut config = Config::new();
+        config.retry = Some(RetryConfig {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        });
+
+        let channel = config.connect_lazy_with_retries(&address).unwrap();
+        let mut client = HealthClient::new(channel);
+        client
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap_err();
+    }
+}
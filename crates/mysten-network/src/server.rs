@@ -1,19 +1,26 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use crate::metrics::{
-    DefaultMetricsCallbackProvider, MetricsCallbackProvider, MetricsHandler,
-    GRPC_ENDPOINT_PATH_HEADER,
+    DefaultMetricsCallbackProvider, MetricsCallbackProvider, MetricsHandler, RequestMetrics,
+    RequestMetricsLayer, GRPC_ENDPOINT_PATH_HEADER,
 };
 use crate::{
     config::Config,
     multiaddr::{parse_dns, parse_ip4, parse_ip6},
 };
-use eyre::{eyre, Result};
-use futures::FutureExt;
+use eyre::{eyre, Context as _, Result};
+use futures::{Stream, StreamExt};
 use multiaddr::{Multiaddr, Protocol};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use std::{convert::Infallible, net::SocketAddr};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio_rustls::TlsAcceptor;
 use tokio_stream::wrappers::TcpListenerStream;
 use tonic::codegen::http::HeaderValue;
 use tonic::{
@@ -22,7 +29,11 @@ use tonic::{
         http::{Request, Response},
         BoxFuture,
     },
-    transport::{server::Router, Body, NamedService},
+    transport::{
+        server::{Connected, Router},
+        Body, NamedService,
+    },
+    Status,
 };
 use tower::{
     layer::util::{Identity, Stack},
@@ -35,34 +46,63 @@ use tower_http::classify::{GrpcErrorsAsFailures, SharedClassifier};
 use tower_http::propagate_header::PropagateHeaderLayer;
 use tower_http::set_header::SetRequestHeaderLayer;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnBodyChunk, DefaultOnEos, TraceLayer};
+use tracing::{debug, warn};
+
+/// The time the connections are given to close after their requests are aborted on shutdown,
+/// e.g. to send the responses of the aborted requests.
+const ABORT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+/// The time a connection is given to complete its TLS handshake before it is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The number of TLS handshakes run concurrently, further connections waiting to be accepted
+const MAX_CONCURRENT_TLS_HANDSHAKES: usize = 64;
 
 pub struct ServerBuilder<M: MetricsCallbackProvider = DefaultMetricsCallbackProvider> {
     router: Router<WrapperService<M>>,
     health_reporter: tonic_health::server::HealthReporter,
+    // The TLS handshakes of the connections, done before tonic serves them
+    tls_acceptor: Option<TlsAcceptor>,
+    // The error of the TLS config, returned by `bind` so that the server never serves plaintext
+    tls_error: Option<eyre::Report>,
+    shutdown_handle: ShutdownHandle,
+    abort: watch::Sender<bool>,
+    shutdown_drain_deadline: Option<Duration>,
+    // The metrics the routes of the added services are registered into
+    request_metrics: Option<RequestMetrics>,
+    // The router of the QUIC addresses, without gRPC-level TLS
+    #[cfg(feature = "quic")]
+    quic_router: Router<WrapperService<M>>,
+    #[cfg(feature = "quic")]
+    tls: Option<crate::tls::TlsConfig>,
 }
 
 type AddPathToHeaderFunction = fn(&Request<Body>) -> Option<HeaderValue>;
 
 type WrapperService<M> = Stack<
     Stack<
-        PropagateHeaderLayer,
+        AbortLayer,
         Stack<
-            TraceLayer<
-                SharedClassifier<GrpcErrorsAsFailures>,
-                DefaultMakeSpan,
-                MetricsHandler<M>,
-                MetricsHandler<M>,
-                DefaultOnBodyChunk,
-                DefaultOnEos,
-                MetricsHandler<M>,
-            >,
+            PropagateHeaderLayer,
             Stack<
-                SetRequestHeaderLayer<AddPathToHeaderFunction>,
+                TraceLayer<
+                    SharedClassifier<GrpcErrorsAsFailures>,
+                    DefaultMakeSpan,
+                    MetricsHandler<M>,
+                    MetricsHandler<M>,
+                    DefaultOnBodyChunk,
+                    DefaultOnEos,
+                    MetricsHandler<M>,
+                >,
                 Stack<
-                    RequestLifetimeLayer<M>,
+                    SetRequestHeaderLayer<AddPathToHeaderFunction>,
                     Stack<
-                        Either<LoadShedLayer, Identity>,
-                        Stack<Either<GlobalConcurrencyLimitLayer, Identity>, Identity>,
+                        RequestLifetimeLayer<M>,
+                        Stack<
+                            Either<LoadShedLayer, Identity>,
+                            Stack<
+                                Either<GlobalConcurrencyLimitLayer, Identity>,
+                                Stack<Either<RequestMetricsLayer, Identity>, Identity>,
+                            >,
+                        >,
                     >,
                 >,
             >,
@@ -87,6 +127,21 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
             builder = builder.tcp_nodelay(tcp_nodelay);
         }
 
+        // QUIC does the TLS of its connections itself
+        #[cfg(feature = "quic")]
+        let quic_builder = builder.clone();
+
+        let (mut tls_acceptor, mut tls_error) = (None, None);
+        if let Some(tls) = &config.tls {
+            match tls
+                .rustls_server_config()
+                .context("invalid server TLS config")
+            {
+                Ok(tls) => tls_acceptor = Some(TlsAcceptor::from(Arc::new(tls))),
+                Err(e) => tls_error = Some(e),
+            }
+        }
+
         let load_shed = config
             .load_shed
             .unwrap_or_default()
@@ -108,7 +163,15 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
             Some(HeaderValue::from_str(path).unwrap())
         }
 
+        let request_metrics_layer = config
+            .request_metrics
+            .as_ref()
+            .map(|metrics| metrics.server_layer());
+
+        let (abort, abort_receiver) = watch::channel(false);
+
         let layer = ServiceBuilder::new()
+            .option_layer(request_metrics_layer)
             .option_layer(global_concurrency_limit)
             .option_layer(load_shed)
             .layer(RequestLifetimeLayer { metrics_provider })
@@ -118,22 +181,43 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
             ))
             .layer(request_metrics)
             .layer(PropagateHeaderLayer::new(GRPC_ENDPOINT_PATH_HEADER.clone()))
+            .layer(AbortLayer {
+                aborted: abort_receiver,
+            })
             .into_inner();
 
         let (health_reporter, health_service) = tonic_health::server::health_reporter();
-        let router = builder
-            .initial_stream_window_size(config.http2_initial_stream_window_size)
-            .initial_connection_window_size(config.http2_initial_connection_window_size)
-            .http2_keepalive_interval(config.http2_keepalive_interval)
-            .http2_keepalive_timeout(config.http2_keepalive_timeout)
-            .max_concurrent_streams(config.http2_max_concurrent_streams)
-            .tcp_keepalive(config.tcp_keepalive)
-            .layer(layer)
-            .add_service(health_service);
+        if let Some(request_metrics) = &config.request_metrics {
+            request_metrics.register_service(service_name(&health_service));
+        }
+        let into_router = |builder: tonic::transport::server::Server| {
+            builder
+                .initial_stream_window_size(config.http2_initial_stream_window_size)
+                .initial_connection_window_size(config.http2_initial_connection_window_size)
+                .http2_keepalive_interval(config.http2_keepalive_interval)
+                .http2_keepalive_timeout(config.http2_keepalive_timeout)
+                .max_concurrent_streams(config.http2_max_concurrent_streams)
+                .tcp_keepalive(config.tcp_keepalive)
+                .layer(layer.clone())
+                .add_service(health_service.clone())
+        };
+        let router = into_router(builder);
+        #[cfg(feature = "quic")]
+        let quic_router = into_router(quic_builder);
 
         Self {
             router,
             health_reporter,
+            tls_acceptor,
+            tls_error,
+            shutdown_handle: ShutdownHandle::new(),
+            abort,
+            shutdown_drain_deadline: config.shutdown_drain_deadline,
+            request_metrics: config.request_metrics.clone(),
+            #[cfg(feature = "quic")]
+            quic_router,
+            #[cfg(feature = "quic")]
+            tls: config.tls.clone(),
         }
     }
 
@@ -141,6 +225,11 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
         self.health_reporter.clone()
     }
 
+    /// The handle shutting down the server, which can be taken before it is bound
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
     /// Add a new service to this Server.
     pub fn add_service<S>(mut self, svc: S) -> Self
     where
@@ -151,59 +240,84 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
             + 'static,
         S::Future: Send + 'static,
     {
+        if let Some(request_metrics) = &self.request_metrics {
+            request_metrics.register_service(S::NAME);
+        }
+        #[cfg(feature = "quic")]
+        {
+            self.quic_router = self.quic_router.add_service(svc.clone());
+        }
         self.router = self.router.add_service(svc);
         self
     }
 
     pub async fn bind(self, addr: &Multiaddr) -> Result<Server> {
+        if let Some(e) = self.tls_error {
+            return Err(e);
+        }
         let mut iter = addr.iter();
 
         let (tx_cancellation, rx_cancellation) = tokio::sync::oneshot::channel();
-        let rx_cancellation = rx_cancellation.map(|_| ());
+        let shutdown_handle = self.shutdown_handle.clone();
+        // Either the cancel handle or the shutdown handle shut the server down
+        let rx_cancellation = async move {
+            tokio::select! {
+                _ = rx_cancellation => shutdown_handle.shutdown(),
+                _ = shutdown_handle.wait() => {}
+            }
+        };
         let (local_addr, server): (Multiaddr, BoxFuture<(), tonic::transport::Error>) =
             match iter.next().ok_or_else(|| eyre!("malformed addr"))? {
+                #[cfg(feature = "quic")]
+                _ if crate::quic::is_quic(addr) => {
+                    let (local_addr, incoming) = crate::quic::bind(addr, self.tls.as_ref()).await?;
+                    let server = Box::pin(
+                        self.quic_router
+                            .serve_with_incoming_shutdown(incoming, rx_cancellation),
+                    );
+                    (local_addr, server)
+                }
                 Protocol::Dns(_) => {
                     let (dns_name, tcp_port, _http_or_https) = parse_dns(addr)?;
                     let (local_addr, incoming) =
                         tcp_listener_and_update_multiaddr(addr, (dns_name.as_ref(), tcp_port))
                             .await?;
-                    let server = Box::pin(
-                        self.router
-                            .serve_with_incoming_shutdown(incoming, rx_cancellation),
-                    );
+                    let server =
+                        serve_incoming(self.router, incoming, self.tls_acceptor, rx_cancellation);
                     (local_addr, server)
                 }
                 Protocol::Ip4(_) => {
                     let (socket_addr, _http_or_https) = parse_ip4(addr)?;
                     let (local_addr, incoming) =
                         tcp_listener_and_update_multiaddr(addr, socket_addr).await?;
-                    let server = Box::pin(
-                        self.router
-                            .serve_with_incoming_shutdown(incoming, rx_cancellation),
-                    );
+                    let server =
+                        serve_incoming(self.router, incoming, self.tls_acceptor, rx_cancellation);
                     (local_addr, server)
                 }
                 Protocol::Ip6(_) => {
                     let (socket_addr, _http_or_https) = parse_ip6(addr)?;
                     let (local_addr, incoming) =
                         tcp_listener_and_update_multiaddr(addr, socket_addr).await?;
-                    let server = Box::pin(
-                        self.router
-                            .serve_with_incoming_shutdown(incoming, rx_cancellation),
-                    );
+                    let server =
+                        serve_incoming(self.router, incoming, self.tls_acceptor, rx_cancellation);
+                    (local_addr, server)
+                }
+                Protocol::Memory(_) => {
+                    let (port, _http_or_https) = crate::multiaddr::parse_memory(addr)?;
+                    let incoming = crate::memory::bind(port)?;
+                    let local_addr = update_memory_port_in_multiaddr(addr, incoming.port());
+                    let server =
+                        serve_incoming(self.router, incoming, self.tls_acceptor, rx_cancellation);
                     (local_addr, server)
                 }
-                // Protocol::Memory(_) => todo!(),
                 #[cfg(unix)]
                 Protocol::Unix(_) => {
                     let (path, _http_or_https) = crate::multiaddr::parse_unix(addr)?;
                     let uds = tokio::net::UnixListener::bind(path.as_ref())?;
                     let uds_stream = tokio_stream::wrappers::UnixListenerStream::new(uds);
                     let local_addr = addr.to_owned();
-                    let server = Box::pin(
-                        self.router
-                            .serve_with_incoming_shutdown(uds_stream, rx_cancellation),
-                    );
+                    let server =
+                        serve_incoming(self.router, uds_stream, self.tls_acceptor, rx_cancellation);
                     (local_addr, server)
                 }
                 unsupported => return Err(eyre!("unsupported protocol {unsupported}")),
@@ -214,10 +328,75 @@ impl<M: MetricsCallbackProvider> ServerBuilder<M> {
             cancel_handle: Some(tx_cancellation),
             local_addr,
             health_reporter: self.health_reporter,
+            shutdown_handle: self.shutdown_handle,
+            abort: self.abort,
+            shutdown_drain_deadline: self.shutdown_drain_deadline,
         })
     }
 }
 
+fn service_name<S: NamedService>(_service: &S) -> &'static str {
+    S::NAME
+}
+
+/// Serves the connections of `incoming` with `router`, after their TLS handshakes if `tls` is given
+fn serve_incoming<M, I, IO, IE>(
+    router: Router<WrapperService<M>>,
+    incoming: I,
+    tls: Option<TlsAcceptor>,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> BoxFuture<(), tonic::transport::Error>
+where
+    M: MetricsCallbackProvider,
+    I: Stream<Item = Result<IO, IE>> + Send + 'static,
+    IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    IO::ConnectInfo: Clone + Send + Sync + 'static,
+    IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    match tls {
+        Some(acceptor) => {
+            Box::pin(router.serve_with_incoming_shutdown(tls_incoming(incoming, acceptor), signal))
+        }
+        None => Box::pin(router.serve_with_incoming_shutdown(incoming, signal)),
+    }
+}
+
+/// Does the TLS handshakes of the connections of `incoming` concurrently. The connections whose
+/// handshake fails or times out, e.g. clients whose certificate is rejected, are dropped
+fn tls_incoming<I, IO, IE>(
+    incoming: I,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = Result<tokio_rustls::server::TlsStream<IO>, IE>> + Send + 'static
+where
+    I: Stream<Item = Result<IO, IE>> + Send + 'static,
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    IE: Send + 'static,
+{
+    incoming
+        .map(move |connection| {
+            let acceptor = acceptor.clone();
+            async move {
+                let io = match connection {
+                    Ok(io) => io,
+                    Err(e) => return Err(e),
+                };
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                    Ok(Ok(stream)) => Ok(Some(stream)),
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake failed: {e}");
+                        Ok(None)
+                    }
+                    Err(_) => {
+                        debug!("TLS handshake timed out after {TLS_HANDSHAKE_TIMEOUT:?}");
+                        Ok(None)
+                    }
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_TLS_HANDSHAKES)
+        .filter_map(|result| async move { result.transpose() })
+}
+
 async fn tcp_listener_and_update_multiaddr<T: ToSocketAddrs>(
     address: &Multiaddr,
     socket_addr: T,
@@ -239,11 +418,40 @@ pub struct Server {
     cancel_handle: Option<tokio::sync::oneshot::Sender<()>>,
     local_addr: Multiaddr,
     health_reporter: tonic_health::server::HealthReporter,
+    shutdown_handle: ShutdownHandle,
+    abort: watch::Sender<bool>,
+    shutdown_drain_deadline: Option<Duration>,
 }
 
 impl Server {
+    /// Serves until shut down, and until the in-flight requests complete or are aborted after
+    /// the `shutdown_drain_deadline` of the config
     pub async fn serve(self) -> Result<(), tonic::transport::Error> {
-        self.server.await
+        let deadline = match self.shutdown_drain_deadline {
+            Some(deadline) => deadline,
+            None => return self.server.await,
+        };
+        let mut server = self.server;
+        tokio::select! {
+            result = &mut server => return result,
+            _ = self.shutdown_handle.wait() => {}
+        }
+        if let Ok(result) = tokio::time::timeout(deadline, &mut server).await {
+            return result;
+        }
+
+        warn!("aborting the in-flight requests after the drain deadline of {deadline:?}");
+        let _ = self.abort.send(true);
+        // The aborted requests are answered, but the streaming responses already sent keep their
+        // connections open: the server stops waiting for them after a grace period
+        match tokio::time::timeout(ABORT_GRACE_PERIOD, server).await {
+            Ok(result) => result,
+            Err(_) => Ok(()),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
     }
 
     pub fn local_addr(&self) -> &Multiaddr {
@@ -270,10 +478,114 @@ fn update_tcp_port_in_multiaddr(addr: &Multiaddr, port: u16) -> Multiaddr {
     .expect("tcp protocol at index 1")
 }
 
+fn update_memory_port_in_multiaddr(addr: &Multiaddr, port: u64) -> Multiaddr {
+    addr.replace(0, |protocol| {
+        if let Protocol::Memory(_) = protocol {
+            Some(Protocol::Memory(port))
+        } else {
+            panic!("expected memory protocol at index 0");
+        }
+    })
+    .expect("memory protocol at index 0")
+}
+
+/// Shuts a server down: it stops accepting connections, and waits for its in-flight requests,
+/// which are aborted after the `shutdown_drain_deadline` of its config
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownHandle {
+    fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn shutdown(&self) {
+        // Never fails, the handle holding a receiver
+        let _ = self.sender.send(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    async fn wait(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Answers the requests in flight with `Unavailable` once the server aborts them
+#[derive(Clone)]
+struct AbortLayer {
+    aborted: watch::Receiver<bool>,
+}
+
+impl<S> Layer<S> for AbortLayer {
+    type Service = Abort<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Abort {
+            inner,
+            aborted: self.aborted.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Abort<S> {
+    inner: S,
+    aborted: watch::Receiver<bool>,
+}
+
+impl<S, RequestBody> Service<Request<RequestBody>> for Abort<S>
+where
+    S: Service<Request<RequestBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<RequestBody>) -> Self::Future {
+        let response = self.inner.call(request);
+        let mut aborted = self.aborted.clone();
+        Box::pin(async move {
+            let abort = async move {
+                while !*aborted.borrow_and_update() {
+                    if aborted.changed().await.is_err() {
+                        // The server is gone, never abort
+                        futures::future::pending::<()>().await;
+                    }
+                }
+            };
+            tokio::select! {
+                response = response => response,
+                _ = abort => Ok(Status::unavailable("the server is shutting down").to_http()),
+            }
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::config::Config;
-    use crate::metrics::MetricsCallbackProvider;
+    use crate::metrics::{MetricsCallbackProvider, RequestMetrics};
+    use crate::tls::{PemSource, TlsConfig};
     use multiaddr::multiaddr;
     use multiaddr::Multiaddr;
     use std::ops::Deref;
@@ -469,6 +781,326 @@ mod test {
         std::fs::remove_file(path).unwrap();
     }
 
+    #[tokio::test]
+    async fn memory() {
+        let address: Multiaddr = "/memory/0/http".parse().unwrap();
+        test_multiaddr(address).await;
+    }
+
+    #[tokio::test]
+    async fn memory_port_released_on_shutdown() {
+        let config = Config::new();
+        let mut server = config
+            .server_builder()
+            .bind(&"/memory/0/http".parse().unwrap())
+            .await
+            .unwrap();
+        let address = server.local_addr().to_owned();
+        assert_ne!(address, "/memory/0/http".parse().unwrap());
+
+        // The port can't be bound twice while the server is running
+        assert!(config.server_builder().bind(&address).await.is_err());
+
+        let cancel_handle = server.take_cancel_handle().unwrap();
+        let server_handle = tokio::spawn(server.serve());
+        cancel_handle.send(()).unwrap();
+        server_handle.await.unwrap().unwrap();
+
+        // Nothing is listening anymore, and the port can be bound again
+        assert!(config.connect(&address).await.is_err());
+        let server = config.server_builder().bind(&address).await.unwrap();
+        assert_eq!(server.local_addr(), &address);
+    }
+
+    #[tokio::test]
+    async fn request_metrics() {
+        let registry = prometheus::Registry::new();
+        let mut config = Config::new();
+        config.request_metrics = Some(RequestMetrics::new(&registry));
+
+        let mut server = config
+            .server_builder()
+            .bind(&"/memory/0/http".parse().unwrap())
+            .await
+            .unwrap();
+        let address = server.local_addr().to_owned();
+        let cancel_handle = server.take_cancel_handle().unwrap();
+        let server_handle = tokio::spawn(server.serve());
+        let channel = config.connect_metered(&address).await.unwrap();
+        let mut client = HealthClient::new(channel);
+
+        client
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+        let code = client
+            .check(HealthCheckRequest {
+                service: "unknown".to_owned(),
+            })
+            .await
+            .unwrap_err()
+            .code();
+        assert_eq!(code, Code::NotFound);
+
+        let path = "/grpc.health.v1.Health/Check";
+        let families = registry.gather();
+        let value = |name: &str, labels: &[(&str, &str)]| -> f64 {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            family
+                .get_metric()
+                .iter()
+                .find(|m| {
+                    labels.iter().all(|(label, value)| {
+                        m.get_label()
+                            .iter()
+                            .any(|l| l.get_name() == *label && l.get_value() == *value)
+                    })
+                })
+                .map_or(0.0, |m| {
+                    if m.has_counter() {
+                        m.get_counter().get_value()
+                    } else if m.has_gauge() {
+                        m.get_gauge().get_value()
+                    } else {
+                        m.get_histogram().get_sample_count() as f64
+                    }
+                })
+        };
+        for side in ["server", "client"] {
+            let labels = [("side", side), ("path", path)];
+            assert_eq!(value("grpc_requests", &labels), 2.0);
+            assert_eq!(value("grpc_inflight_requests", &labels), 0.0);
+            assert_eq!(value("grpc_request_latency_seconds", &labels), 2.0);
+            let ok = [("side", side), ("path", path), ("code", "Ok")];
+            assert_eq!(value("grpc_responses", &ok), 1.0);
+            let not_found = [("side", side), ("path", path), ("code", "NotFound")];
+            assert_eq!(value("grpc_responses", &not_found), 1.0);
+        }
+        // The routes of the services which were not added are not labelled with their path
+        let metrics = config.request_metrics.as_ref().unwrap();
+        assert_eq!(metrics.path_label(path), path);
+        assert_eq!(metrics.path_label("/random.Service/Method"), "unknown");
+        assert_eq!(metrics.path_label("/favicon.ico"), "unknown");
+
+        cancel_handle.send(()).unwrap();
+        server_handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let config = Config::new();
+        let builder = config.server_builder();
+        let shutdown_handle = builder.shutdown_handle();
+        let server = builder
+            .bind(&"/memory/0/http".parse().unwrap())
+            .await
+            .unwrap();
+        let address = server.local_addr().to_owned();
+        let server_handle = tokio::spawn(server.serve());
+        let mut client = HealthClient::new(config.connect(&address).await.unwrap());
+        client
+            .check(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        assert!(!shutdown_handle.is_shutdown());
+        shutdown_handle.shutdown();
+        assert!(shutdown_handle.is_shutdown());
+        server_handle.await.unwrap().unwrap();
+        assert!(config.connect(&address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_drain_deadline() {
+        let config = Config {
+            shutdown_drain_deadline: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let mut server = config
+            .server_builder()
+            .bind(&"/memory/0/http".parse().unwrap())
+            .await
+            .unwrap();
+        let address = server.local_addr().to_owned();
+        let shutdown_handle = server.shutdown_handle();
+        let server_handle = tokio::spawn(server.serve());
+
+        // The watch streams never complete, keeping the server from draining
+        let mut client = HealthClient::new(config.connect(&address).await.unwrap());
+        let _stream = client
+            .watch(HealthCheckRequest {
+                service: "".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        shutdown_handle.shutdown();
+        tokio::time::timeout(Duration::from_secs(5), server_handle)
+            .await
+            .expect("the server should stop after its drain deadline")
+            .unwrap()
+            .unwrap();
+    }
+
+    fn self_signed_identity() -> (PemSource, PemSource) {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        (
+            PemSource::Pem(certificate.serialize_pem().unwrap()),
+            PemSource::Pem(certificate.serialize_private_key_pem()),
+        )
+    }
+
+    async fn check_health(server_config: &Config, client_config: &Config) -> bool {
+        let address: Multiaddr = "/dns/localhost/tcp/0/https".parse().unwrap();
+        let mut server = server_config.server_builder().bind(&address).await.unwrap();
+        let address = server.local_addr().to_owned();
+        let cancel_handle = server.take_cancel_handle().unwrap();
+        let server_handle = tokio::spawn(server.serve());
+
+        let healthy = match client_config.connect(&address).await {
+            Ok(channel) => HealthClient::new(channel)
+                .check(HealthCheckRequest {
+                    service: "".to_owned(),
+                })
+                .await
+                .is_ok(),
+            Err(_) => false,
+        };
+
+        cancel_handle.send(()).unwrap();
+        server_handle.await.unwrap().unwrap();
+        healthy
+    }
+
+    #[tokio::test]
+    async fn tls() {
+        let (certificate, private_key) = self_signed_identity();
+        let server_config = Config {
+            tls: Some(TlsConfig::new().with_identity(certificate.clone(), private_key)),
+            ..Config::new()
+        };
+        let client_config = Config {
+            tls: Some(TlsConfig::new().with_ca_certificate(certificate)),
+            ..Config::new()
+        };
+        assert!(check_health(&server_config, &client_config).await);
+
+        // The certificate of the server must be trusted
+        let (other_certificate, _) = self_signed_identity();
+        let untrusting_config = Config {
+            tls: Some(TlsConfig::new().with_ca_certificate(other_certificate)),
+            ..Config::new()
+        };
+        assert!(!check_health(&server_config, &untrusting_config).await);
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let (server_certificate, server_key) = self_signed_identity();
+        let (client_certificate, client_key) = self_signed_identity();
+        let server_config = Config {
+            tls: Some(
+                TlsConfig::new()
+                    .with_identity(server_certificate.clone(), server_key)
+                    .with_ca_certificate(client_certificate.clone()),
+            ),
+            ..Config::new()
+        };
+        let client_config = Config {
+            tls: Some(
+                TlsConfig::new()
+                    .with_identity(client_certificate, client_key)
+                    .with_ca_certificate(server_certificate.clone()),
+            ),
+            ..Config::new()
+        };
+        assert!(check_health(&server_config, &client_config).await);
+
+        // Clients without a certificate are rejected
+        let anonymous_config = Config {
+            tls: Some(TlsConfig::new().with_ca_certificate(server_certificate)),
+            ..Config::new()
+        };
+        assert!(!check_health(&server_config, &anonymous_config).await);
+    }
+
+    /// Accepts the clients presenting one certificate
+    struct PinnedClientVerifier {
+        certificate: rustls::Certificate,
+    }
+
+    impl rustls::server::ClientCertVerifier for PinnedClientVerifier {
+        fn client_auth_root_subjects(&self) -> Option<rustls::DistinguishedNames> {
+            Some(vec![])
+        }
+
+        fn verify_client_cert(
+            &self,
+            end_entity: &rustls::Certificate,
+            _intermediates: &[rustls::Certificate],
+            _now: std::time::SystemTime,
+        ) -> Result<rustls::server::ClientCertVerified, rustls::Error> {
+            if *end_entity == self.certificate {
+                Ok(rustls::server::ClientCertVerified::assertion())
+            } else {
+                Err(rustls::Error::General("unknown client".to_owned()))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_client_verifier() {
+        let (server_certificate, server_key) = self_signed_identity();
+        let accepted = rcgen::generate_simple_self_signed(vec!["client".to_owned()]).unwrap();
+        // Every serialization signs the certificate again, the verifier pins the one presented
+        let accepted_pem = accepted.serialize_pem().unwrap();
+        let verifier = PinnedClientVerifier {
+            certificate: rustls::Certificate(
+                rustls_pemfile::certs(&mut accepted_pem.as_bytes())
+                    .unwrap()
+                    .remove(0),
+            ),
+        };
+        let server_config = Config {
+            tls: Some(
+                TlsConfig::new()
+                    .with_identity(server_certificate.clone(), server_key)
+                    .with_client_verifier(Arc::new(verifier)),
+            ),
+            ..Config::new()
+        };
+        let client_config = |(certificate, private_key)| Config {
+            tls: Some(
+                TlsConfig::new()
+                    .with_identity(certificate, private_key)
+                    .with_ca_certificate(server_certificate.clone()),
+            ),
+            ..Config::new()
+        };
+
+        let accepted_identity = (
+            PemSource::Pem(accepted_pem),
+            PemSource::Pem(accepted.serialize_private_key_pem()),
+        );
+        assert!(check_health(&server_config, &client_config(accepted_identity)).await);
+        let rejected_identity = self_signed_identity();
+        assert!(!check_health(&server_config, &client_config(rejected_identity)).await);
+    }
+
+    #[tokio::test]
+    async fn invalid_tls_config() {
+        let config = Config {
+            tls: Some(TlsConfig::new()),
+            ..Config::new()
+        };
+        let address: Multiaddr = "/dns/localhost/tcp/0/https".parse().unwrap();
+        assert!(config.server_builder().bind(&address).await.is_err());
+    }
+
     #[should_panic]
     #[tokio::test]
     async fn missing_http_protocol() {
@@ -0,0 +1,219 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TLS of the gRPC servers and clients built from a `Config`.
+//!
+//! A server given a `TlsConfig` only accepts TLS connections, presenting its `certificate`. When a
+//! `ca_certificate` is given, or a custom `client_verifier`, it also requires the clients to
+//! present a certificate (mTLS). A client given a `TlsConfig` verifies the certificate of the
+//! server against the `ca_certificate`, and presents its own `certificate` if any. The addresses
+//! of TLS servers must end with `/https`.
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use eyre::{eyre, Context, Result};
+use rustls::server::ClientCertVerifier;
+use serde::{Deserialize, Serialize};
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+/// The ALPN protocol of gRPC, set on the custom rustls configs
+const ALPN_H2: &[u8] = b"h2";
+
+/// A PEM encoded certificate or private key, read from a file or given in memory
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PemSource {
+    Path(PathBuf),
+    Pem(String),
+}
+
+impl PemSource {
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            PemSource::Path(path) => std::fs::read(path)
+                .with_context(|| format!("unable to read PEM file '{}'", path.display())),
+            PemSource::Pem(pem) => Ok(pem.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl fmt::Debug for PemSource {
+    // The private keys given in memory are not printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PemSource::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            PemSource::Pem(_) => f.write_str("Pem(..)"),
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// The certificate chain presented to the peers. Required by servers, and by clients for mTLS.
+    pub certificate: Option<PemSource>,
+
+    /// The private key of `certificate`.
+    pub private_key: Option<PemSource>,
+
+    /// The CA certificates the certificates of the peers are verified against.
+    ///
+    /// On servers, the clients are then required to present a certificate (mTLS).
+    /// On clients, the certificate of the server is verified against them.
+    pub ca_certificate: Option<PemSource>,
+
+    /// The name the certificate of the server is verified for, instead of the host of its address.
+    ///
+    /// Only affects clients
+    pub domain_name: Option<String>,
+
+    /// Verifies the certificates of the clients instead of `ca_certificate`, e.g. against the
+    /// keys of the current committee. The clients are then required to present a certificate.
+    ///
+    /// Only affects servers
+    #[serde(skip)]
+    pub client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("certificate", &self.certificate)
+            .field("private_key", &self.private_key)
+            .field("ca_certificate", &self.ca_certificate)
+            .field("domain_name", &self.domain_name)
+            .field("client_verifier", &self.client_verifier.is_some())
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_identity(mut self, certificate: PemSource, private_key: PemSource) -> Self {
+        self.certificate = Some(certificate);
+        self.private_key = Some(private_key);
+        self
+    }
+
+    pub fn with_ca_certificate(mut self, ca_certificate: PemSource) -> Self {
+        self.ca_certificate = Some(ca_certificate);
+        self
+    }
+
+    pub fn with_domain_name(mut self, domain_name: impl Into<String>) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    pub fn with_client_verifier(mut self, verifier: Arc<dyn ClientCertVerifier>) -> Self {
+        self.client_verifier = Some(verifier);
+        self
+    }
+
+    /// Reads the certificate and private key, if both are given
+    fn identity(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match (&self.certificate, &self.private_key) {
+            (Some(certificate), Some(private_key)) => {
+                Ok(Some((certificate.read()?, private_key.read()?)))
+            }
+            (None, None) => Ok(None),
+            _ => Err(eyre!(
+                "the certificate and the private key must be given together"
+            )),
+        }
+    }
+
+    /// The rustls config of servers, verifying the clients with `client_verifier`, or against
+    /// `ca_certificate` if any. The TCP, memory and unix servers do their TLS handshakes with it
+    /// through a `TlsAcceptor`, rather than through tonic, which can't take a custom verifier
+    pub(crate) fn rustls_server_config(&self) -> Result<rustls::ServerConfig> {
+        let (certificate, private_key) = self
+            .identity()?
+            .ok_or_else(|| eyre!("a TLS server requires a certificate and a private key"))?;
+        let verifier = match (&self.client_verifier, &self.ca_certificate) {
+            (Some(verifier), _) => verifier.clone(),
+            (None, Some(ca_certificate)) => {
+                rustls::server::AllowAnyAuthenticatedClient::new(root_store(ca_certificate)?)
+            }
+            (None, None) => rustls::server::NoClientAuth::new(),
+        };
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                parse_certificates(&certificate)?,
+                parse_private_key(&private_key)?,
+            )
+            .context("invalid server certificate or private key")?;
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(config)
+    }
+
+    /// The rustls config of clients, which requires a `ca_certificate`
+    #[cfg(feature = "quic")]
+    pub(crate) fn rustls_client_config(&self) -> Result<rustls::ClientConfig> {
+        let ca_certificate = self
+            .ca_certificate
+            .as_ref()
+            .ok_or_else(|| eyre!("a QUIC client requires a CA certificate"))?;
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store(ca_certificate)?);
+        let mut config = match self.identity()? {
+            Some((certificate, private_key)) => builder
+                .with_single_cert(
+                    parse_certificates(&certificate)?,
+                    parse_private_key(&private_key)?,
+                )
+                .context("invalid client certificate or private key")?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Ok(config)
+    }
+
+    pub(crate) fn client_tls_config(&self) -> Result<ClientTlsConfig> {
+        let mut tls = ClientTlsConfig::new();
+        if let Some(ca_certificate) = &self.ca_certificate {
+            tls = tls.ca_certificate(Certificate::from_pem(ca_certificate.read()?));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            tls = tls.domain_name(domain_name);
+        }
+        if let Some((certificate, private_key)) = self.identity()? {
+            tls = tls.identity(Identity::from_pem(certificate, private_key));
+        }
+        Ok(tls)
+    }
+}
+
+fn root_store(ca_certificate: &PemSource) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for certificate in parse_certificates(&ca_certificate.read()?)? {
+        roots.add(&certificate).context("invalid CA certificate")?;
+    }
+    Ok(roots)
+}
+
+fn parse_certificates(pem: &[u8]) -> Result<Vec<rustls::Certificate>> {
+    let certificates = rustls_pemfile::certs(&mut &pem[..]).context("invalid PEM certificate")?;
+    if certificates.is_empty() {
+        return Err(eyre!("no certificate found in PEM"));
+    }
+    Ok(certificates.into_iter().map(rustls::Certificate).collect())
+}
+
+fn parse_private_key(pem: &[u8]) -> Result<rustls::PrivateKey> {
+    let mut reader = pem;
+    loop {
+        match rustls_pemfile::read_one(&mut reader).context("invalid PEM private key")? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(rustls::PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(eyre!("no private key found in PEM")),
+        }
+    }
+}
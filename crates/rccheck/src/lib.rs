@@ -12,8 +12,9 @@
 //! In certgen, We also offer a trait `Certifiable` (and convenience implementation) that closes the loop: it can convert a key pair into a valid self-signed certificate,
 //! and a public key of the same format into some X509 SubjectPublicKeyInfo.
 
-use std::{fmt, time::SystemTime};
+use std::{fmt, sync::Arc, time::SystemTime};
 
+use arc_swap::ArcSwap;
 use ouroboros::self_referencing;
 use rustls::{
     client::{ServerCertVerified, ServerCertVerifier},
@@ -34,6 +35,10 @@ mod psk_tests;
 #[path = "tests/psk_set_tests.rs"]
 mod psk_set_tests;
 
+#[cfg(test)]
+#[path = "tests/swappable_psk_set_tests.rs"]
+mod swappable_psk_set_tests;
+
 #[cfg(test)]
 #[path = "tests/test_utils.rs"]
 pub(crate) mod test_utils;
@@ -162,6 +167,121 @@ impl PskSet {
     }
 }
 
+/// A `PskSet` which can be atomically swapped at runtime, to rotate the accepted keys.
+///
+/// Implements the traits ClientCertVerifier and ServerCertVerifier, accepting the certificates
+/// signed by any key of the current set. The clones of a `SwappablePskSet` share their set, so that
+/// a clone kept aside can rotate the keys of the verifiers installed in the rustls configs, without
+/// restarting the connections.
+///
+/// Example
+/// ```
+/// use rccheck::*;
+/// let mut rng = rand::thread_rng();
+/// let spki = |kp: &ed25519_dalek::Keypair| ed25519_certgen::Ed25519::public_key_to_spki(&kp.public);
+/// let (old, new) = (ed25519_dalek::Keypair::generate(&mut rng), ed25519_dalek::Keypair::generate(&mut rng));
+/// let verifier = SwappablePskSet::new(PskSet::from_der(&[&spki(&old)[..]]).unwrap());
+/// // accept both keys during the rotation
+/// verifier.swap(PskSet::from_der(&[&spki(&old)[..], &spki(&new)[..]]).unwrap());
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct SwappablePskSet {
+    psk_set: Arc<ArcSwap<PskSet>>,
+}
+
+impl SwappablePskSet {
+    pub fn new(psk_set: PskSet) -> Self {
+        SwappablePskSet {
+            psk_set: Arc::new(ArcSwap::from_pointee(psk_set)),
+        }
+    }
+
+    /// The current set of accepted keys
+    pub fn load(&self) -> Arc<PskSet> {
+        self.psk_set.load_full()
+    }
+
+    /// Atomically replaces the set of accepted keys, returning the previous one. The handshakes
+    /// in progress finish with the set they started with.
+    pub fn swap(&self, psk_set: PskSet) -> Arc<PskSet> {
+        self.psk_set.swap(Arc::new(psk_set))
+    }
+
+    /// Runs `verify` with each key of the current set, until one accepts the certificate
+    fn verify_with_any<T>(
+        &self,
+        mut verify: impl FnMut(&Psk) -> Result<T, rustls::Error>,
+    ) -> Result<T, rustls::Error> {
+        let psk_set = self.psk_set.load();
+        // The certificates can be signed by a key which is not theirs (e.g. a CA key), so the
+        // signing key can't be looked up from the certificate
+        let mut error = None;
+        for psk in &psk_set.spki_set {
+            match verify(psk) {
+                Ok(verified) => return Ok(verified),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.unwrap_or_else(|| {
+            rustls::Error::InvalidCertificateData(
+                "invalid peer certificate: the set of accepted public keys is empty".to_string(),
+            )
+        }))
+    }
+}
+
+impl ClientCertVerifier for SwappablePskSet {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<rustls::DistinguishedNames> {
+        // We can't guarantee subjects before having seen the cert. This should not be None for compatiblity reasons
+        Some(rustls::DistinguishedNames::new())
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.verify_with_any(|psk| {
+            ClientCertVerifier::verify_client_cert(psk, end_entity, intermediates, now)
+        })
+    }
+}
+
+impl ServerCertVerifier for SwappablePskSet {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let scts: Vec<&[u8]> = scts.collect();
+        self.verify_with_any(|psk| {
+            ServerCertVerifier::verify_server_cert(
+                psk,
+                end_entity,
+                intermediates,
+                server_name,
+                &mut scts.iter().copied(),
+                ocsp_response,
+                now,
+            )
+        })
+    }
+}
+
 /// X.509 `SubjectPublicKeyInfo` (SPKI) as defined in [RFC 5280 Section 4.1.2.7].
 ///
 /// ASN.1 structure containing an [`AlgorithmIdentifier`] and public key
@@ -0,0 +1,60 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{ed25519_certgen::Ed25519, *};
+
+fn verify_client(verifier: &SwappablePskSet, cert: &rustls::Certificate) -> bool {
+    ClientCertVerifier::verify_client_cert(verifier, cert, &[], SystemTime::now()).is_ok()
+}
+
+fn verify_server(verifier: &SwappablePskSet, cert: &rustls::Certificate) -> bool {
+    let mut empty = std::iter::empty();
+    ServerCertVerifier::verify_server_cert(
+        verifier,
+        cert,
+        &[],
+        &rustls::ServerName::try_from("localhost").unwrap(),
+        &mut empty,
+        &[],
+        SystemTime::now(),
+    )
+    .is_ok()
+}
+
+#[test]
+fn key_rotation() {
+    let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::from_seed([0; 32]);
+    let old_kp = ed25519_dalek::Keypair::generate(&mut rng);
+    let new_kp = ed25519_dalek::Keypair::generate(&mut rng);
+    let old_spki = Ed25519::public_key_to_spki(&old_kp.public);
+    let new_spki = Ed25519::public_key_to_spki(&new_kp.public);
+
+    let subject_alt_names = vec!["localhost".to_string()];
+    let old_cert = Ed25519::keypair_to_certificate(subject_alt_names.clone(), old_kp).unwrap();
+    let new_cert = Ed25519::keypair_to_certificate(subject_alt_names, new_kp).unwrap();
+
+    let verifier = SwappablePskSet::new(PskSet::from_der(&[&old_spki[..]]).unwrap());
+    // the clones share the set of the verifier
+    let handle = verifier.clone();
+    assert!(verify_client(&verifier, &old_cert));
+    assert!(verify_server(&verifier, &old_cert));
+    assert!(!verify_client(&verifier, &new_cert));
+    assert!(!verify_server(&verifier, &new_cert));
+
+    // both keys are accepted during the rotation
+    let previous = handle.swap(PskSet::from_der(&[&old_spki[..], &new_spki[..]]).unwrap());
+    assert_eq!(*previous, PskSet::from_der(&[&old_spki[..]]).unwrap());
+    assert!(verify_client(&verifier, &old_cert));
+    assert!(verify_client(&verifier, &new_cert));
+    assert!(verify_server(&verifier, &new_cert));
+
+    // then the old key is retired
+    handle.swap(PskSet::from_der(&[&new_spki[..]]).unwrap());
+    assert_eq!(verifier.load().spki_set.len(), 1);
+    assert!(!verify_client(&verifier, &old_cert));
+    assert!(!verify_server(&verifier, &old_cert));
+    assert!(verify_client(&verifier, &new_cert));
+
+    handle.swap(PskSet::from_der(&[]).unwrap());
+    assert!(!verify_client(&verifier, &new_cert));
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Crash reports, written by the panic hook before the process exits.
+//!
+//! A report holds the build info of the program, the thread and message of the panic, its
+//! backtrace, and the last log lines, so that the context of a crash is kept even when the logs
+//! are not collected. The reports are written to `<dir>/crash-<service>-<unix time>-<pid>.txt`.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write as _},
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(Clone, Debug)]
+pub struct CrashReportConfig {
+    /// The directory the crash reports are written to
+    pub dir: PathBuf,
+    /// The build info of the program, e.g. its version and git revision
+    pub build_info: String,
+    /// The number of the last log lines written to the crash reports
+    pub log_lines: usize,
+}
+
+impl CrashReportConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            build_info: String::new(),
+            log_lines: 100,
+        }
+    }
+
+    pub fn with_build_info(mut self, build_info: impl Into<String>) -> Self {
+        self.build_info = build_info.into();
+        self
+    }
+
+    pub fn with_log_lines(mut self, log_lines: usize) -> Self {
+        self.log_lines = log_lines;
+        self
+    }
+}
+
+#[derive(Default)]
+struct RecentLogsInner {
+    lines: VecDeque<String>,
+    // The end of the last write, not terminated by a newline yet
+    partial: String,
+}
+
+/// A log output keeping the last log lines in memory, for the crash reports
+#[derive(Clone)]
+pub struct RecentLogs {
+    inner: Arc<Mutex<RecentLogsInner>>,
+    capacity: usize,
+}
+
+impl RecentLogs {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Default::default(),
+            capacity,
+        }
+    }
+
+    /// The last log lines, oldest first.
+    ///
+    /// Doesn't wait for the lock, so that a panic while logging doesn't deadlock the panic hook.
+    pub fn lines(&self) -> Vec<String> {
+        match self.inner.try_lock() {
+            Ok(inner) => inner.lines.iter().cloned().collect(),
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => {
+                poisoned.into_inner().lines.iter().cloned().collect()
+            }
+            Err(std::sync::TryLockError::WouldBlock) => Vec::new(),
+        }
+    }
+}
+
+impl Write for RecentLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        inner.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(end) = inner.partial.find('\n') {
+            let line: String = inner.partial.drain(..=end).collect();
+            inner.lines.push_back(line.trim_end().to_owned());
+            if inner.lines.len() > self.capacity {
+                inner.lines.pop_front();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for RecentLogs {
+    type Writer = RecentLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Writes the crash report of `panic`, the panic info given to the panic hook, returning its path
+pub fn write_crash_report(
+    config: &CrashReportConfig,
+    service_name: &str,
+    panic: &dyn fmt::Display,
+    backtrace: &str,
+    recent_logs: Option<&RecentLogs>,
+) -> io::Result<PathBuf> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let thread = std::thread::current();
+
+    let mut report = String::new();
+    // Writing to a String never fails
+    let _ = writeln!(report, "service: {service_name}");
+    let _ = writeln!(report, "build: {}", config.build_info);
+    let _ = writeln!(report, "time: {now}");
+    let _ = writeln!(report, "thread: {}", thread.name().unwrap_or("<unnamed>"));
+    let _ = writeln!(report, "panic: {panic}");
+    let _ = writeln!(report, "\nbacktrace:\n{backtrace}");
+    if let Some(recent_logs) = recent_logs {
+        let _ = writeln!(report, "\nlast log lines:");
+        for line in recent_logs.lines() {
+            let _ = writeln!(report, "{line}");
+        }
+    }
+
+    fs::create_dir_all(&config.dir)?;
+    let path = config.dir.join(format!(
+        "crash-{service_name}-{now}-{}.txt",
+        std::process::id()
+    ));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_logs() {
+        let mut recent_logs = RecentLogs::new(2);
+        recent_logs.write_all(b"first\nsecond\nthi").unwrap();
+        assert_eq!(recent_logs.lines(), vec!["first", "second"]);
+        recent_logs.write_all(b"rd\n").unwrap();
+        assert_eq!(recent_logs.lines(), vec!["second", "third"]);
+    }
+
+    #[test]
+    fn test_write_crash_report() {
+        let dir = std::env::temp_dir().join(format!(
+            "telemetry-crash-report-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let config = CrashReportConfig::new(&dir).with_build_info("1.2.3-abcdef");
+        let mut recent_logs = RecentLogs::new(10);
+        recent_logs.write_all(b"the last log line\n").unwrap();
+
+        let path = std::thread::Builder::new()
+            .name("crashing".to_owned())
+            .spawn(move || {
+                write_crash_report(
+                    &config,
+                    "test",
+                    &"panicked at 'boom'",
+                    "<backtrace>",
+                    Some(&recent_logs),
+                )
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+        let report = fs::read_to_string(path).unwrap();
+        assert!(report.contains("service: test"));
+        assert!(report.contains("build: 1.2.3-abcdef"));
+        assert!(report.contains("thread: crashing"));
+        assert!(report.contains("boom"));
+        assert!(report.contains("<backtrace>"));
+        assert!(report.contains("the last log line"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
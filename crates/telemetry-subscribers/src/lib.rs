@@ -16,17 +16,28 @@
 //! - `jaeger` - this feature is enabled by default as it enables jaeger tracing
 //! - `json` - Bunyan formatter - JSON log output, optional
 //! - `tokio-console` - [Tokio-console](https://github.com/tokio-rs/console) subscriber, optional
+//!
+//! ## Log files
+//! Besides stderr, the logs can be written to a file rotated by size or time, see `log_file`.
 
+use crash_report::{CrashReportConfig, RecentLogs};
+use log_file::LogFileConfig;
 use span_latency_prom::PrometheusSpanLatencyLayer;
+use span_red_metrics::SpanRedMetricsLayer;
 use std::{
     env,
     io::{stderr, Write},
+    path::PathBuf,
 };
 use tracing::metadata::LevelFilter;
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_subscriber::{
-    fmt::{self, format::FmtSpan},
+    fmt::{
+        self,
+        format::FmtSpan,
+        writer::{BoxMakeWriter, MakeWriterExt},
+    },
     layer::SubscriberExt,
     reload,
     util::SubscriberInitExt,
@@ -35,7 +46,10 @@ use tracing_subscriber::{
 
 use crossterm::tty::IsTty;
 
+pub mod crash_report;
+pub mod log_file;
 pub mod span_latency_prom;
+pub mod span_red_metrics;
 
 /// Alias for a type-erased error type.
 pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -44,6 +58,7 @@ pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 /// ===
 /// - json_log_output: Output JSON logs to stdout only.
 /// - log_file: If defined, write output to a file starting with this name, ex app.log
+/// - file_output: If defined, also write output to a file rotated by size or time
 /// - log_level: error/warn/info/debug/trace, defaults to info
 /// - service_name:
 #[derive(Default, Clone, Debug)]
@@ -60,40 +75,97 @@ pub struct TelemetryConfig {
     pub chrome_trace_output: bool,
     /// If defined, write output to a file starting with this name, ex app.log
     pub log_file: Option<String>,
+    /// If defined, also write output to a rotated log file, in addition to stderr or `log_file`
+    pub file_output: Option<LogFileConfig>,
     /// Log level to set, defaults to info
     pub log_string: Option<String>,
     /// Set a panic hook
     pub panic_hook: bool,
     /// Crash on panic
     pub crash_on_panic: bool,
+    /// If defined, the panic hook writes a crash report before crashing, see `crash_report`
+    pub crash_report: Option<CrashReportConfig>,
     /// Optional Prometheus registry - if present, all enabled span latencies are measured
     pub prom_registry: Option<prometheus::Registry>,
+    /// The targets whose spans are recorded as RED metrics into `prom_registry`, see
+    /// `span_red_metrics`
+    pub red_metrics_targets: Vec<String>,
 }
 
 #[must_use]
 #[allow(dead_code)]
 pub struct TelemetryGuards {
     worker_guard: WorkerGuard,
+    file_output_guard: Option<WorkerGuard>,
 
     #[cfg(feature = "chrome")]
     chrome_guard: Option<tracing_chrome::FlushGuard>,
 }
 
+/// Changes the `EnvFilter` directives of a running program, e.g. to debug a production issue
+/// without restarting and losing its state.
 #[derive(Clone, Debug)]
-pub struct FilterHandle(reload::Handle<EnvFilter, Registry>);
+pub struct FilterHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    // The directives at init, restored by `reset`
+    initial_directives: String,
+}
 
 impl FilterHandle {
     pub fn update<S: AsRef<str>>(&self, directives: S) -> Result<(), BoxError> {
         let filter = EnvFilter::try_new(directives)?;
-        self.0.reload(filter)?;
+        self.handle.reload(filter)?;
         Ok(())
     }
 
     pub fn get(&self) -> Result<String, BoxError> {
-        self.0
+        self.handle
             .with_current(|filter| filter.to_string())
             .map_err(Into::into)
     }
+
+    /// Restores the directives the program started with
+    pub fn reset(&self) -> Result<(), BoxError> {
+        self.update(&self.initial_directives)
+    }
+
+    /// Spawns a task updating the directives on each SIGHUP with the content of the file at
+    /// `path`, or restoring the initial directives if the file is missing or empty.
+    ///
+    /// Must be called from a Tokio runtime.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(
+        &self,
+        path: impl Into<PathBuf>,
+    ) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let path = path.into();
+        let mut hangups = signal(SignalKind::hangup())?;
+        let handle = self.clone();
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                let directives = std::fs::read_to_string(&path).unwrap_or_default();
+                let directives = directives.trim();
+                let result = if directives.is_empty() {
+                    handle.reset()
+                } else {
+                    handle.update(directives)
+                };
+                match result {
+                    Ok(()) => tracing::info!(
+                        "reloaded the log filter from {}: {}",
+                        path.display(),
+                        handle.get().unwrap_or_default()
+                    ),
+                    Err(e) => tracing::error!(
+                        "failed to reload the log filter from {}: {e}",
+                        path.display()
+                    ),
+                }
+            }
+        }))
+    }
 }
 
 fn get_output(log_file: Option<String>) -> (NonBlocking, WorkerGuard) {
@@ -105,8 +177,15 @@ fn get_output(log_file: Option<String>) -> (NonBlocking, WorkerGuard) {
     }
 }
 
+// The state of the panic hook, beyond whether to crash
+struct PanicHookContext {
+    service_name: String,
+    panics: Option<prometheus::IntCounter>,
+    crash_report: Option<(CrashReportConfig, RecentLogs)>,
+}
+
 // NOTE: this function is copied from tracing's panic_hook example
-fn set_panic_hook(crash_on_panic: bool) {
+fn set_panic_hook(crash_on_panic: bool, context: PanicHookContext) {
     let default_panic_handler = std::panic::take_hook();
 
     // Set a panic hook that records the panic as a `tracing` event at the
@@ -116,6 +195,11 @@ fn set_panic_hook(crash_on_panic: bool) {
     // will include the current span, allowing the context in which the panic
     // occurred to be recorded.
     std::panic::set_hook(Box::new(move |panic| {
+        if let Some(panics) = &context.panics {
+            panics.inc();
+        }
+        let backtrace = format!("{:?}", backtrace::Backtrace::new());
+
         // If the panic has a source location, record it as structured fields.
         if let Some(location) = panic.location() {
             // On nightly Rust, where the `PanicInfo` type also exposes a
@@ -127,9 +211,23 @@ fn set_panic_hook(crash_on_panic: bool) {
                 panic.file = location.file(),
                 panic.line = location.line(),
                 panic.column = location.column(),
+                panic.backtrace = %backtrace,
             );
         } else {
-            tracing::error!(message = %panic);
+            tracing::error!(message = %panic, panic.backtrace = %backtrace);
+        }
+
+        if let Some((config, recent_logs)) = &context.crash_report {
+            match crash_report::write_crash_report(
+                config,
+                &context.service_name,
+                panic,
+                &backtrace,
+                Some(recent_logs),
+            ) {
+                Ok(path) => tracing::info!("crash report written to {}", path.display()),
+                Err(e) => tracing::error!("failed to write the crash report: {e}"),
+            }
         }
 
         default_panic_handler(panic);
@@ -154,10 +252,13 @@ impl TelemetryConfig {
             json_log_output: false,
             chrome_trace_output: false,
             log_file: None,
+            file_output: None,
             log_string: None,
             panic_hook: true,
             crash_on_panic: false,
+            crash_report: None,
             prom_registry: None,
+            red_metrics_targets: Vec::new(),
         }
     }
 
@@ -171,11 +272,26 @@ impl TelemetryConfig {
         self
     }
 
+    pub fn with_file_output(mut self, file_output: LogFileConfig) -> Self {
+        self.file_output = Some(file_output);
+        self
+    }
+
+    pub fn with_crash_report(mut self, crash_report: CrashReportConfig) -> Self {
+        self.crash_report = Some(crash_report);
+        self
+    }
+
     pub fn with_prom_registry(mut self, registry: &prometheus::Registry) -> Self {
         self.prom_registry = Some(registry.clone());
         self
     }
 
+    pub fn with_red_metrics_targets(mut self, targets: &[&str]) -> Self {
+        self.red_metrics_targets = targets.iter().map(|target| (*target).to_owned()).collect();
+        self
+    }
+
     pub fn with_env(mut self) -> Self {
         if env::var("CRASH_ON_PANIC").is_ok() {
             self.crash_on_panic = true
@@ -211,8 +327,12 @@ impl TelemetryConfig {
         let log_level = config.log_string.unwrap_or_else(|| "info".into());
         let env_filter =
             EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level));
+        let initial_directives = env_filter.to_string();
         let (filter, reload_handle) = reload::Layer::new(env_filter);
-        let filter_handle = FilterHandle(reload_handle);
+        let filter_handle = FilterHandle {
+            handle: reload_handle,
+            initial_directives,
+        };
 
         let mut layers = Vec::new();
 
@@ -231,10 +351,31 @@ impl TelemetryConfig {
             None
         };
 
+        // Only counted by the panic hook
+        let panics = config
+            .prom_registry
+            .as_ref()
+            .filter(|_| config.panic_hook)
+            .map(|registry| {
+                prometheus::register_int_counter_with_registry!(
+                    "panics_total",
+                    "Number of panics",
+                    registry
+                )
+                .expect("Could not register the panics metric")
+            });
+
         if let Some(registry) = config.prom_registry {
             let span_lat_layer = PrometheusSpanLatencyLayer::try_new(&registry, 15)
                 .expect("Could not initialize span latency layer");
             layers.push(span_lat_layer.boxed());
+
+            if !config.red_metrics_targets.is_empty() {
+                let red_metrics_layer =
+                    SpanRedMetricsLayer::try_new(&registry, config.red_metrics_targets)
+                        .expect("Could not initialize span RED metrics layer");
+                layers.push(red_metrics_layer.boxed());
+            }
         }
 
         #[cfg(feature = "jaeger")]
@@ -259,19 +400,42 @@ impl TelemetryConfig {
         }
 
         let (nb_output, worker_guard) = get_output(config.log_file.clone());
+        let (output, file_output_guard) = match &config.file_output {
+            Some(file_output) => {
+                let (file_output, guard) = file_output
+                    .open()
+                    .expect("Could not open the log file output");
+                (BoxMakeWriter::new(nb_output.and(file_output)), Some(guard))
+            }
+            None => (BoxMakeWriter::new(nb_output), None),
+        };
+        // The last log lines are kept for the crash reports
+        let recent_logs = config
+            .crash_report
+            .as_ref()
+            .map(|crash_report| RecentLogs::new(crash_report.log_lines));
+        let output = match &recent_logs {
+            Some(recent_logs) => BoxMakeWriter::new(output.and(recent_logs.clone())),
+            None => output,
+        };
+        // The escape codes of the colors would end up in the log files
+        let ansi = config.log_file.is_none() && config.file_output.is_none() && stderr().is_tty();
         if config.json_log_output {
             // See https://www.lpalmieri.com/posts/2020-09-27-zero-to-production-4-are-we-observable-yet/#5-7-tracing-bunyan-formatter
             // Also Bunyan layer addes JSON logging for tracing spans with duration information
             let json_layer = JsonStorageLayer
-                .and_then(BunyanFormattingLayer::new(config.service_name, nb_output))
+                .and_then(BunyanFormattingLayer::new(
+                    config.service_name.clone(),
+                    output,
+                ))
                 .boxed();
             layers.push(json_layer);
         } else {
             // Output to file or to stderr with ANSI colors
             let fmt_layer = fmt::layer()
-                .with_ansi(config.log_file.is_none() && stderr().is_tty())
+                .with_ansi(ansi)
                 .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
-                .with_writer(nb_output)
+                .with_writer(output)
                 .boxed();
             layers.push(fmt_layer);
         }
@@ -282,13 +446,19 @@ impl TelemetryConfig {
             .init();
 
         if config.panic_hook {
-            set_panic_hook(config.crash_on_panic);
+            let context = PanicHookContext {
+                service_name: config.service_name.clone(),
+                panics,
+                crash_report: config.crash_report.clone().zip(recent_logs),
+            };
+            set_panic_hook(config.crash_on_panic, context);
         }
 
         // The guard must be returned and kept in the main fn of the app, as when it's dropped then the output
         // gets flushed and closed. If this is dropped too early then no output will appear!
         let guards = TelemetryGuards {
             worker_guard,
+            file_output_guard,
             #[cfg(feature = "chrome")]
             chrome_guard,
         };
@@ -342,11 +512,12 @@ mod tests {
         });
 
         let metrics = registry.gather();
-        // There should be 1 metricFamily and 1 metric
-        assert_eq!(metrics.len(), 1);
-        assert_eq!(metrics[0].get_name(), "tracing_span_latencies");
-        assert_eq!(metrics[0].get_field_type(), MetricType::HISTOGRAM);
-        let inner = metrics[0].get_metric();
+        // There should be 2 metricFamilies: the span latencies with 1 metric, and the panics
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].get_name(), "panics_total");
+        assert_eq!(metrics[1].get_name(), "tracing_span_latencies");
+        assert_eq!(metrics[1].get_field_type(), MetricType::HISTOGRAM);
+        let inner = metrics[1].get_metric();
         assert_eq!(inner.len(), 1);
         let labels = inner[0].get_label();
         assert_eq!(labels.len(), 1);
@@ -0,0 +1,317 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A log file output with size- or time-based rotation, and retention of the rotated files.
+//!
+//! The logs are written to the file at `path`, which keeps its name so that it can be followed.
+//! On rotation, it is renamed by suffixing the UTC time of the rotation, e.g.
+//! `node.log.2022-10-15-13-45-00`, and a new file is started. Only the `max_files` most recent
+//! rotated files are kept. The writes go through a non-blocking writer, so that a slow disk
+//! doesn't stall the application.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use time::OffsetDateTime;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    /// The file is never rotated
+    Never,
+    /// The file is rotated before it grows past the given number of bytes
+    Size(u64),
+    /// The file is rotated at the start of each UTC hour
+    Hourly,
+    /// The file is rotated at the start of each UTC day
+    Daily,
+}
+
+impl LogRotation {
+    /// The length of the time periods, for time-based rotations
+    fn period(&self) -> Option<Duration> {
+        match self {
+            LogRotation::Hourly => Some(Duration::from_secs(60 * 60)),
+            LogRotation::Daily => Some(Duration::from_secs(24 * 60 * 60)),
+            LogRotation::Never | LogRotation::Size(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogFileConfig {
+    /// The path of the current log file, and the prefix of the rotated ones
+    pub path: PathBuf,
+    pub rotation: LogRotation,
+    /// The number of rotated files kept, all of them if None
+    pub max_files: Option<usize>,
+}
+
+impl LogFileConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            rotation: LogRotation::Daily,
+            max_files: None,
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: LogRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    /// Opens the log file, returning its non-blocking writer and the guard flushing it on drop
+    pub fn open(&self) -> io::Result<(NonBlocking, WorkerGuard)> {
+        let writer = RollingFileWriter::open(self.clone())?;
+        Ok(tracing_appender::non_blocking(writer))
+    }
+}
+
+/// Writes to the log file of a `LogFileConfig`, rotating it when needed
+pub struct RollingFileWriter {
+    config: LogFileConfig,
+    file: File,
+    size: u64,
+    period_index: u64,
+}
+
+impl RollingFileWriter {
+    pub fn open(config: LogFileConfig) -> io::Result<Self> {
+        if let Some(parent) = config.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        let period_index = period_index(config.rotation, SystemTime::now());
+        Ok(Self {
+            config,
+            file,
+            size,
+            period_index,
+        })
+    }
+
+    fn should_rotate(&self, len: usize, now: SystemTime) -> bool {
+        match self.config.rotation {
+            LogRotation::Never => false,
+            // A single write larger than the limit still goes to a file on its own
+            LogRotation::Size(max_size) => self.size > 0 && self.size + len as u64 > max_size,
+            LogRotation::Hourly | LogRotation::Daily => {
+                period_index(self.config.rotation, now) != self.period_index
+            }
+        }
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        self.file.flush()?;
+        fs::rename(&self.config.path, self.rotated_path(now))?;
+        self.file = open_append(&self.config.path)?;
+        self.size = 0;
+        self.period_index = period_index(self.config.rotation, now);
+        self.remove_old_files()
+    }
+
+    fn rotated_path(&self, now: SystemTime) -> PathBuf {
+        let date = OffsetDateTime::from(now);
+        let base = format!(
+            "{}.{:04}-{:02}-{:02}-{:02}-{:02}-{:02}",
+            self.config.path.display(),
+            date.year(),
+            date.month() as u8,
+            date.day(),
+            date.hour(),
+            date.minute(),
+            date.second(),
+        );
+        // Several rotations can happen within a second with size-based rotations
+        let mut path = PathBuf::from(&base);
+        let mut index = 1;
+        while path.exists() {
+            path = PathBuf::from(format!("{base}.{index}"));
+            index += 1;
+        }
+        path
+    }
+
+    /// The rotated files, oldest first. The other files named after the log file, e.g. backups or
+    /// the logs of another tool, are left alone
+    fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        let dir = match self.config.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let prefix = match self.config.path.file_name() {
+            Some(name) => format!("{}.", name.to_string_lossy()),
+            None => return Ok(Vec::new()),
+        };
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let rotated = name
+                .to_string_lossy()
+                .strip_prefix(&prefix)
+                .map_or(false, is_rotation_suffix);
+            if rotated {
+                let modified = entry.metadata()?.modified()?;
+                files.push((modified, entry.path()));
+            }
+        }
+        files.sort();
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn remove_old_files(&self) -> io::Result<()> {
+        let max_files = match self.config.max_files {
+            Some(max_files) => max_files,
+            None => return Ok(()),
+        };
+        let files = self.rotated_files()?;
+        let excess = files.len().saturating_sub(max_files);
+        for path in &files[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], now: SystemTime) -> io::Result<usize> {
+        if self.should_rotate(buf.len(), now) {
+            self.rotate(now)?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+}
+
+impl Write for RollingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(buf, SystemTime::now())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Whether `suffix` is the suffix of a rotated file: the time of its rotation as written by
+/// `rotated_path`, e.g. `2022-10-15-13-45-00`, optionally followed by an index, e.g. `.1`
+fn is_rotation_suffix(suffix: &str) -> bool {
+    let (time, index) = match suffix.split_once('.') {
+        Some((time, index)) => (time, Some(index)),
+        None => (suffix, None),
+    };
+    let time_matches = time.len() == 19
+        && time.char_indices().all(|(i, c)| match i {
+            4 | 7 | 10 | 13 | 16 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    let index_matches = index.map_or(true, |index| {
+        !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+    });
+    time_matches && index_matches
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn period_index(rotation: LogRotation, now: SystemTime) -> u64 {
+    match rotation.period() {
+        Some(period) => {
+            let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+            since_epoch.as_secs() / period.as_secs()
+        }
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "telemetry-log-file-{name}-{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_size_rotation_and_retention() {
+        let dir = temp_dir("size");
+        let path = dir.join("node.log");
+        let config = LogFileConfig::new(&path)
+            .with_rotation(LogRotation::Size(10))
+            .with_max_files(2);
+        let mut writer = RollingFileWriter::open(config).unwrap();
+        // Files which were not rotated are never removed
+        let unrelated = [
+            "node.log.bak",
+            "node.log.2022-10-15",
+            "node.log.2022-10-15-13-45-00.old",
+        ];
+        for name in unrelated {
+            fs::write(dir.join(name), "unrelated\n").unwrap();
+        }
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(fs::read_to_string(&rotated[0]).unwrap(), "second\n");
+        assert_eq!(fs::read_to_string(&rotated[1]).unwrap(), "third\n");
+        for name in unrelated {
+            assert!(dir.join(name).exists());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_time_rotation() {
+        let dir = temp_dir("time");
+        let path = dir.join("node.log");
+        let config = LogFileConfig::new(&path).with_rotation(LogRotation::Hourly);
+        let mut writer = RollingFileWriter::open(config).unwrap();
+
+        let now = SystemTime::now();
+        writer.write_at(b"before\n", now).unwrap();
+        writer.write_at(b"same hour\n", now).unwrap();
+        writer
+            .write_at(b"next hour\n", now + Duration::from_secs(60 * 60))
+            .unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "next hour\n");
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&rotated[0]).unwrap(),
+            "before\nsame hour\n"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! This is a module that derives RED (requests, errors, duration) metrics from the Tokio-tracing
+//! spans of configured targets, so that operations get basic metrics by being instrumented with
+//! spans, without counting and timing them by hand.
+//!
+//! A span matches a target if its target is the target, or a module under it. Each matching span
+//! counts as a request when it closes, and as an error if an `ERROR` event was recorded within it.
+//! The metrics are labelled by `target` and `span_name`:
+//! - `tracing_span_requests_total`
+//! - `tracing_span_errors_total`
+//! - `tracing_span_duration_seconds[_sum/count/bucket]`
+//!
+//! Like `span_latency_prom`, only the spans enabled by the env_filter are recorded.
+
+use std::time::Instant;
+
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry, HistogramVec,
+    IntCounterVec, Registry,
+};
+use tracing::{span, Event, Level, Subscriber};
+
+const DURATION_SEC_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1., 5., 10., 30., 60., 300.,
+];
+
+/// A tokio_tracing Layer that records the RED metrics of the spans of some targets
+pub struct SpanRedMetricsLayer {
+    targets: Vec<String>,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    durations: HistogramVec,
+}
+
+impl SpanRedMetricsLayer {
+    /// Create a new layer recording the spans of `targets`, e.g. `["narwhal_primary::core"]`,
+    /// into the given registry.
+    pub fn try_new(registry: &Registry, targets: Vec<String>) -> Result<Self, prometheus::Error> {
+        let requests = register_int_counter_vec_with_registry!(
+            "tracing_span_requests_total",
+            "Number of closed tokio-tracing spans",
+            &["target", "span_name"],
+            registry
+        )?;
+        let errors = register_int_counter_vec_with_registry!(
+            "tracing_span_errors_total",
+            "Number of closed tokio-tracing spans within which an error was logged",
+            &["target", "span_name"],
+            registry
+        )?;
+        let durations = register_histogram_vec_with_registry!(
+            "tracing_span_duration_seconds",
+            "Durations of tokio-tracing spans",
+            &["target", "span_name"],
+            DURATION_SEC_BUCKETS.to_vec(),
+            registry
+        )?;
+        Ok(Self {
+            targets,
+            requests,
+            errors,
+            durations,
+        })
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        self.targets.iter().any(|prefix| {
+            target
+                .strip_prefix(prefix.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        })
+    }
+}
+
+struct RedSpanState {
+    start: Instant,
+    error: bool,
+}
+
+impl<S> tracing_subscriber::Layer<S> for SpanRedMetricsLayer
+where
+    S: Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &span::Attributes,
+        id: &span::Id,
+        ctx: tracing_subscriber::layer::Context<S>,
+    ) {
+        if !self.matches(attrs.metadata().target()) {
+            return;
+        }
+        let span = ctx.span(id).unwrap();
+        span.extensions_mut().insert(RedSpanState {
+            start: Instant::now(),
+            error: false,
+        });
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        // The error counts for all the recorded spans it happened within
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope {
+                if let Some(state) = span.extensions_mut().get_mut::<RedSpanState>() {
+                    state.error = true;
+                }
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let state = match span.extensions_mut().remove::<RedSpanState>() {
+            Some(state) => state,
+            None => return,
+        };
+        let labels = [span.metadata().target(), span.name()];
+        self.requests.with_label_values(&labels).inc();
+        if state.error {
+            self.errors.with_label_values(&labels).inc();
+        }
+        self.durations
+            .with_label_values(&labels)
+            .observe(state.start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{error, info, info_span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_span_red_metrics() {
+        let registry = prometheus::Registry::new();
+        let layer = SpanRedMetricsLayer::try_new(
+            &registry,
+            vec!["telemetry_subscribers::span_red_metrics".to_owned()],
+        )
+        .unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("operation").in_scope(|| info!("ok"));
+            info_span!("operation").in_scope(|| error!("failed"));
+            // Spans of other targets are not recorded
+            info_span!(target: "other", "operation").in_scope(|| error!("failed"));
+        });
+
+        let target = "telemetry_subscribers::span_red_metrics::tests";
+        let value = |name: &str, target: &str| {
+            registry
+                .gather()
+                .into_iter()
+                .find(|family| family.get_name() == name)
+                .unwrap()
+                .get_metric()
+                .iter()
+                .find(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == "target" && label.get_value() == target)
+                })
+                .map(|metric| {
+                    assert_eq!(metric.get_label()[0].get_value(), "operation");
+                    if metric.has_histogram() {
+                        metric.get_histogram().get_sample_count()
+                    } else {
+                        metric.get_counter().get_value() as u64
+                    }
+                })
+        };
+        assert_eq!(value("tracing_span_requests_total", target), Some(2));
+        assert_eq!(value("tracing_span_errors_total", target), Some(1));
+        assert_eq!(value("tracing_span_duration_seconds", target), Some(2));
+        assert_eq!(value("tracing_span_requests_total", "other"), None);
+    }
+
+    #[test]
+    fn test_target_matching() {
+        let layer =
+            SpanRedMetricsLayer::try_new(&prometheus::Registry::new(), vec!["a::b".to_owned()])
+                .unwrap();
+        assert!(layer.matches("a::b"));
+        assert!(layer.matches("a::b::c"));
+        assert!(!layer.matches("a::bc"));
+        assert!(!layer.matches("a"));
+    }
+}
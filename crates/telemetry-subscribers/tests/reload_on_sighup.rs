@@ -0,0 +1,67 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+#![cfg(unix)]
+
+use camino::Utf8PathBuf;
+use std::env;
+use std::fs;
+use std::time::Duration;
+use telemetry_subscribers::TelemetryConfig;
+use tracing::{debug, info};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_on_sighup() {
+    let log_file_prefix = "sighup.log";
+    let filter_file = "sighup-filter";
+    fs::write(filter_file, "debug\n").unwrap();
+    let mut config = TelemetryConfig::new("test");
+    config.log_file = Some(log_file_prefix.to_owned());
+    config.panic_hook = false;
+
+    let (guard, reload_handle) = config.init();
+    let _task = reload_handle.reload_on_sighup(filter_file).unwrap();
+
+    info!("Should be able to see this");
+    debug!("This won't be captured");
+
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    for _ in 0..100 {
+        if reload_handle.get().unwrap() == "debug" {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(reload_handle.get().unwrap(), "debug");
+    debug!("Now you can see this!");
+
+    reload_handle.reset().unwrap();
+    debug!("This won't be captured either");
+
+    drop(guard);
+    fs::remove_file(filter_file).unwrap();
+
+    let current_dir = Utf8PathBuf::from_path_buf(env::current_dir().unwrap()).unwrap();
+
+    for entry in current_dir.read_dir_utf8().unwrap() {
+        let entry = entry.unwrap();
+
+        if entry.file_name().starts_with(log_file_prefix) {
+            let logs = fs::read_to_string(entry.path()).unwrap();
+
+            assert!(logs.contains("Should be able to see this"));
+            assert!(!logs.contains("This won't be captured"));
+            assert!(logs.contains("Now you can see this!"));
+            assert!(!logs.contains("This won't be captured either"));
+
+            fs::remove_file(entry.path()).unwrap();
+            return;
+        }
+    }
+
+    panic!("could not find log file");
+}
@@ -5,17 +5,47 @@ use std::collections::{BTreeMap, HashSet};
 
 use proc_macro::TokenStream;
 use proc_macro2::Ident;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::Type::{self};
 use syn::{
     parse_macro_input, AngleBracketedGenericArguments, Attribute, Generics, ItemStruct, Lit, Meta,
-    PathArguments,
+    NestedMeta, PathArguments,
 };
 
 // This is used as default when none is specified
 const DEFAULT_DB_OPTIONS_CUSTOM_FN: &str = "typed_store::rocks::default_rocksdb_options";
 // Custom function which returns the option and overrides the defaults for this table
 const DB_OPTIONS_CUSTOM_FUNCTION: &str = "default_options_override_fn";
+// Marks a table whose values are encoded by the value codec given at open, e.g. encrypted
+const ENCRYPTED_TABLE: &str = "encrypted";
+// The weight of a table when dividing a memory budget among the tables
+const MEMORY_WEIGHT: &str = "memory_weight";
+const DEFAULT_MEMORY_WEIGHT: u32 = 1;
+// A secondary index of a table on a field of its values, in format `#[index(name = "by_owner", key = "owner")]`
+const INDEX: &str = "index";
+// The filter of a table, in format `#[filter(bloom)]`, `#[filter(prefix_bloom, prefix_len = 8)]` or `#[filter(ribbon)]`
+const FILTER: &str = "filter";
+// Marks a table too large to hold its indexes and filters in memory, see `typed_store::rocks::large_table_options`
+const LARGE_TABLE: &str = "large_table";
+// The key and value types of a table whose type is an alias, in format `#[dbmap(key = "Digest", value = "Cert")]`
+const DBMAP_TYPES: &str = "dbmap";
+// The serialization format of the values of a table, in format `#[codec = "json"]`
+const CODEC: &str = "codec";
+const CODECS: [&str; 3] = ["bincode", "bcs", "json"];
+// The serialization format of the keys of a table, in format `#[key_codec = "bcs"]`, among the
+// formats keeping the order of the integers, see `typed_store::codec::KeyFormat`
+const KEY_CODEC: &str = "key_codec";
+const KEY_CODECS: [&str; 2] = ["bincode", "bcs"];
+// Marks a table of type `ReadOnlyMap<K, V>`, only written through the writer returned at open
+const READ_ONLY_AFTER_OPEN: &str = "read_only_after_open";
+const READ_ONLY_MAP: &str = "ReadOnlyMap";
+// The attributes of the struct, e.g. `#[dbmap_utils(subcommands)]`
+const DBMAP_UTILS: &str = "dbmap_utils";
+const SUBCOMMANDS: &str = "subcommands";
+const SUPPORT_BUNDLE: &str = "support_bundle";
+const IMPL_TRAIT: &str = "impl_trait";
+const DB_NAME: &str = "db_name";
+const MIGRATE_FROM: &str = "migrate_from";
 
 /// Options can either be simplified form or
 enum GeneralTableOptions {
@@ -28,14 +58,48 @@ impl Default for GeneralTableOptions {
     }
 }
 
-// Extracts the field names, field types, inner types (K,V in {map_type_name}<K, V>), and the options attrs
+/// The attributes of a table
+struct TableAttributes {
+    options: GeneralTableOptions,
+    encrypted: bool,
+    memory_weight: u32,
+    indexes: Vec<TableIndex>,
+    /// The `typed_store::rocks::FilterPolicy` of the table, if any
+    filter: Option<proc_macro2::TokenStream>,
+    /// Whether the table has partitioned indexes and filters
+    large_table: bool,
+    /// Whether the table is a `ReadOnlyMap<K, V>`, see `typed_store::rocks::ReadOnlyMap`
+    read_only_after_open: bool,
+    /// The serialization format of the values of the table, if not bincode
+    codec: Option<String>,
+    /// The serialization format of the keys of the table, if not bincode
+    key_codec: Option<String>,
+}
+
+/// The types of a table given by `#[dbmap(...)]`, for fields whose type is an alias of the map type
+struct TableTypes {
+    /// The name of the map type, `DBMap` unless given with `map = "Store"`
+    map: String,
+    /// The `<K, V>` arguments of the map type
+    inner_types: AngleBracketedGenericArguments,
+}
+
+/// A secondary index of a table, see `typed_store::rocks::SecondaryIndex`
+struct TableIndex {
+    /// The name of the index, e.g. `by_owner`, which names its lookup method
+    name: String,
+    /// The field of the values of the table which is indexed
+    key: String,
+}
+
+// Extracts the field names, field types, inner types (K,V in {map_type_name}<K, V>), and the table attrs
 fn extract_struct_info(
     input: ItemStruct,
     allowed_map_type_names: HashSet<String>,
 ) -> (
     Vec<Ident>,
     Vec<AngleBracketedGenericArguments>,
-    Vec<GeneralTableOptions>,
+    Vec<TableAttributes>,
     String,
 ) {
     // There must only be one map type used for all entries
@@ -58,26 +122,81 @@ fn extract_struct_info(
                 get_options_override_function(attrs.get(0).unwrap()).unwrap(),
             )
         };
+        let encrypted = f.attrs.iter().any(|a| a.path.is_ident(ENCRYPTED_TABLE));
+        let memory_weight = f
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident(MEMORY_WEIGHT))
+            .map_or(DEFAULT_MEMORY_WEIGHT, |a| get_memory_weight(a).unwrap());
+        let indexes = f
+            .attrs
+            .iter()
+            .filter(|a| a.path.is_ident(INDEX))
+            .map(|a| get_index(a).unwrap())
+            .collect();
+        let filter = f
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident(FILTER))
+            .map(|a| get_filter(a).unwrap());
+        let large_table = f.attrs.iter().any(|a| a.path.is_ident(LARGE_TABLE));
+        let read_only_after_open = f.attrs.iter().any(|a| a.path.is_ident(READ_ONLY_AFTER_OPEN));
+        let codec = f
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident(CODEC))
+            .map(|a| get_codec(a).unwrap())
+            .filter(|codec| codec != "bincode");
+        let key_codec = f
+            .attrs
+            .iter()
+            .find(|a| a.path.is_ident(KEY_CODEC))
+            .map(|a| get_key_codec(a).unwrap())
+            .filter(|codec| codec != "bincode");
+        let attributes = TableAttributes {
+            options,
+            encrypted,
+            memory_weight,
+            indexes,
+            filter,
+            large_table,
+            read_only_after_open,
+            codec,
+            key_codec,
+        };
+
+        let field_name = f.ident.as_ref().unwrap().clone();
+        if let Some(attr) = f.attrs.iter().find(|a| a.path.is_ident(DBMAP_TYPES)) {
+            let types = get_table_types(attr).unwrap();
+            if !allowed_map_type_names.contains(&types.map) {
+                panic!("All struct members must be of type {allowed_strs}");
+            }
+            return ((field_name, types.map), (types.inner_types, attributes));
+        }
 
         let ty = &f.ty;
         if let Type::Path(p) = ty {
-            let type_info = &p.path.segments.first().unwrap();
+            // The last segment, so that paths like `typed_store::rocks::DBMap<K, V>` are matched
+            let type_info = &p.path.segments.last().unwrap();
             let inner_type =
                 if let PathArguments::AngleBracketed(angle_bracket_type) = &type_info.arguments {
                     angle_bracket_type.clone()
                 } else {
-                    panic!("All struct members must be of type {allowed_strs}");
+                    panic!("All struct members must be of type {allowed_strs}, or have their types given with `#[{DBMAP_TYPES}(key = \"{{K}}\", value = \"{{V}}\")]`");
                 };
 
             let type_str = format!("{}", &type_info.ident);
+            // Read only tables are opened as DBMaps, and wrapped once open
+            match (type_str == READ_ONLY_MAP, read_only_after_open) {
+                (true, true) => return ((field_name, "DBMap".to_owned()), (inner_type, attributes)),
+                (false, false) => {}
+                _ => panic!("Tables of type {READ_ONLY_MAP}<K, V> must be marked with `#[{READ_ONLY_AFTER_OPEN}]`, and only them"),
+            }
             // Rough way to check that this is map_type_name
             if allowed_map_type_names.contains(&type_str) {
-                return (
-                    (f.ident.as_ref().unwrap().clone(), type_str),
-                    (inner_type, options),
-                );
+                return ((field_name, type_str), (inner_type, attributes));
             } else {
-                panic!("All struct members must be of type {allowed_strs}");
+                panic!("All struct members must be of type {allowed_strs}, or have their types given with `#[{DBMAP_TYPES}(key = \"{{K}}\", value = \"{{V}}\")]`");
             }
         }
         panic!("All struct members must be of type {allowed_strs}");
@@ -97,12 +216,12 @@ fn extract_struct_info(
         panic!("Cannot derive on empty struct");
     };
 
-    let (inner_types, options): (Vec<_>, Vec<_>) = inner_types_with_opts.into_iter().unzip();
+    let (inner_types, attributes): (Vec<_>, Vec<_>) = inner_types_with_opts.into_iter().unzip();
 
     (
         field_names,
         inner_types,
-        options,
+        attributes,
         simple_field_type_names.get(0).unwrap().clone(),
     )
 }
@@ -139,6 +258,256 @@ fn get_options_override_function(attr: &Attribute) -> syn::Result<String> {
     Ok(fn_name.value())
 }
 
+/// Extracts the serialization format of the values of a table, in format `#[codec = "json"]`.
+/// The keys have their own format, see `get_key_codec`
+fn get_codec(attr: &Attribute) -> syn::Result<String> {
+    let meta = attr.parse_meta()?;
+    let error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!(
+                "Expected a codec in format `#[{CODEC} = \"{{codec}}\"]`, one of {}",
+                CODECS.join(", ")
+            ),
+        )
+    };
+    match &meta {
+        Meta::NameValue(val) => match &val.lit {
+            Lit::Str(codec) if CODECS.contains(&codec.value().as_str()) => Ok(codec.value()),
+            _ => Err(error()),
+        },
+        _ => Err(error()),
+    }
+}
+
+/// Extracts the serialization format of the keys of a table, in format `#[key_codec = "bcs"]`.
+/// The range scans rely on the order of the encoded keys, so only the formats keeping the order
+/// of the integers are accepted
+fn get_key_codec(attr: &Attribute) -> syn::Result<String> {
+    let meta = attr.parse_meta()?;
+    let error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!(
+                "Expected a key codec in format `#[{KEY_CODEC} = \"{{codec}}\"]`, one of {}, which keep the order of the keys",
+                KEY_CODECS.join(", ")
+            ),
+        )
+    };
+    match &meta {
+        Meta::NameValue(val) => match &val.lit {
+            Lit::Str(codec) if KEY_CODECS.contains(&codec.value().as_str()) => Ok(codec.value()),
+            _ => Err(error()),
+        },
+        _ => Err(error()),
+    }
+}
+
+/// Extracts the memory weight of a table, in format `#[memory_weight = 3]`
+fn get_memory_weight(attr: &Attribute) -> syn::Result<u32> {
+    let meta = attr.parse_meta()?;
+    match &meta {
+        Meta::NameValue(val) => match &val.lit {
+            Lit::Int(weight) => weight.base10_parse(),
+            _ => Err(syn::Error::new_spanned(
+                &meta,
+                format!("Expected an integer in format `#[{MEMORY_WEIGHT} = {{weight}}]`"),
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            &meta,
+            format!("Expected an integer in format `#[{MEMORY_WEIGHT} = {{weight}}]`"),
+        )),
+    }
+}
+
+/// The name of the column family of an index, as returned by `typed_store::rocks::index_cf_name`
+fn index_cf_name(table: &impl std::fmt::Display, index: &str) -> String {
+    format!("__index/{table}/{index}")
+}
+
+/// Extracts a secondary index, in format `#[index(name = "by_owner", key = "owner")]`
+fn get_index(attr: &Attribute) -> syn::Result<TableIndex> {
+    let meta = attr.parse_meta()?;
+    let error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!("Expected an index in format `#[{INDEX}(name = \"{{name}}\", key = \"{{field}}\")]`"),
+        )
+    };
+    let list = match &meta {
+        Meta::List(list) => list,
+        _ => return Err(error()),
+    };
+    let (mut name, mut key) = (None, None);
+    for nested in &list.nested {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(val)) => match &val.lit {
+                Lit::Str(s) if val.path.is_ident("name") => name = Some(s.value()),
+                Lit::Str(s) if val.path.is_ident("key") => key = Some(s.value()),
+                _ => return Err(error()),
+            },
+            _ => return Err(error()),
+        }
+    }
+    match (name, key) {
+        (Some(name), Some(key)) => Ok(TableIndex { name, key }),
+        _ => Err(error()),
+    }
+}
+
+/// Extracts the types of a table whose type is an alias, in format
+/// `#[dbmap(key = "Digest", value = "Cert")]`, with an optional `map = "Store"`
+fn get_table_types(attr: &Attribute) -> syn::Result<TableTypes> {
+    let meta = attr.parse_meta()?;
+    let error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!("Expected the table types in format `#[{DBMAP_TYPES}(key = \"{{K}}\", value = \"{{V}}\")]`"),
+        )
+    };
+    let list = match &meta {
+        Meta::List(list) => list,
+        _ => return Err(error()),
+    };
+    let (mut map, mut key, mut value) = (None, None, None);
+    for nested in &list.nested {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(val)) => match &val.lit {
+                Lit::Str(s) if val.path.is_ident("map") => map = Some(s.value()),
+                Lit::Str(s) if val.path.is_ident("key") => key = Some(s.parse::<Type>()?),
+                Lit::Str(s) if val.path.is_ident("value") => value = Some(s.parse::<Type>()?),
+                _ => return Err(error()),
+            },
+            _ => return Err(error()),
+        }
+    }
+    match (key, value) {
+        (Some(key), Some(value)) => Ok(TableTypes {
+            map: map.unwrap_or_else(|| "DBMap".to_owned()),
+            inner_types: syn::parse_quote! { <#key, #value> },
+        }),
+        _ => Err(error()),
+    }
+}
+
+/// Extracts the filter policy of a table, in format `#[filter(bloom)]`, `#[filter(ribbon)]` or
+/// `#[filter(prefix_bloom, prefix_len = 8)]`, with an optional `bits_per_key = 12`
+fn get_filter(attr: &Attribute) -> syn::Result<proc_macro2::TokenStream> {
+    let meta = attr.parse_meta()?;
+    let error = || {
+        syn::Error::new_spanned(
+            &meta,
+            format!("Expected a filter in format `#[{FILTER}(bloom | ribbon | prefix_bloom, prefix_len = {{bytes}}, bits_per_key = {{bits}})]`"),
+        )
+    };
+    let list = match &meta {
+        Meta::List(list) => list,
+        _ => return Err(error()),
+    };
+    let (mut kind, mut prefix_len, mut bits_per_key) = (None, None, None);
+    for nested in &list.nested {
+        match nested {
+            NestedMeta::Meta(Meta::Path(path)) => match path.get_ident() {
+                Some(ident) if kind.is_none() => kind = Some(ident.to_string()),
+                _ => return Err(error()),
+            },
+            NestedMeta::Meta(Meta::NameValue(val)) => match &val.lit {
+                Lit::Int(len) if val.path.is_ident("prefix_len") => {
+                    prefix_len = Some(len.base10_parse::<usize>()?)
+                }
+                Lit::Int(bits) if val.path.is_ident("bits_per_key") => {
+                    bits_per_key = Some(bits.base10_parse::<f64>()?)
+                }
+                Lit::Float(bits) if val.path.is_ident("bits_per_key") => {
+                    bits_per_key = Some(bits.base10_parse::<f64>()?)
+                }
+                _ => return Err(error()),
+            },
+            _ => return Err(error()),
+        }
+    }
+    let bits_per_key = match bits_per_key {
+        Some(bits) => quote! { #bits },
+        None => quote! { typed_store::rocks::DEFAULT_FILTER_BITS_PER_KEY },
+    };
+    match (kind.as_deref(), prefix_len) {
+        (Some("bloom"), None) => Ok(quote! {
+            typed_store::rocks::FilterPolicy::WholeKeyBloom { bits_per_key: #bits_per_key }
+        }),
+        (Some("prefix_bloom"), Some(prefix_len)) => Ok(quote! {
+            typed_store::rocks::FilterPolicy::PrefixBloom { prefix_len: #prefix_len, bits_per_key: #bits_per_key }
+        }),
+        (Some("ribbon"), None) => Ok(quote! {
+            typed_store::rocks::FilterPolicy::Ribbon { bloom_equivalent_bits_per_key: #bits_per_key }
+        }),
+        _ => Err(error()),
+    }
+}
+
+/// The attributes of the struct of tables
+#[derive(Default)]
+struct StructAttributes {
+    /// Whether to generate a `clap` enum of admin subcommands
+    subcommands: bool,
+    /// Whether to generate the `create_support_bundle` method of the read only handle
+    support_bundle: bool,
+    /// The traits of table accessors to implement
+    impl_traits: Vec<syn::Path>,
+    /// The name of the database in the metrics and secondary paths, the name of the struct by default
+    db_name: Option<String>,
+    /// The struct of the previous layout of the tables, to generate the `migrate_from` method of
+    migrate_from: Option<Type>,
+}
+
+/// Extracts the struct attributes, in format `#[dbmap_utils(subcommands, impl_trait = "MyStoreTrait", db_name = "consensus", migrate_from = "TablesV1")]`
+fn get_struct_attributes(attrs: &[Attribute]) -> syn::Result<StructAttributes> {
+    let mut attributes = StructAttributes::default();
+    for attr in attrs.iter().filter(|a| a.path.is_ident(DBMAP_UTILS)) {
+        let meta = attr.parse_meta()?;
+        let error = |spanned: &dyn quote::ToTokens| {
+            syn::Error::new_spanned(
+                spanned,
+                format!("Expected attributes in format `#[{DBMAP_UTILS}({SUBCOMMANDS}, {SUPPORT_BUNDLE}, {IMPL_TRAIT} = \"{{trait_name}}\", {DB_NAME} = \"{{db_name}}\", {MIGRATE_FROM} = \"{{struct_name}}\")]`"),
+            )
+        };
+        let list = match &meta {
+            Meta::List(list) => list,
+            _ => return Err(error(&meta)),
+        };
+        for nested in &list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident(SUBCOMMANDS) => {
+                    attributes.subcommands = true
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident(SUPPORT_BUNDLE) => {
+                    attributes.support_bundle = true
+                }
+                NestedMeta::Meta(Meta::NameValue(val)) if val.path.is_ident(IMPL_TRAIT) => {
+                    match &val.lit {
+                        Lit::Str(trait_name) => attributes.impl_traits.push(trait_name.parse()?),
+                        _ => return Err(error(nested)),
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(val)) if val.path.is_ident(DB_NAME) => {
+                    match &val.lit {
+                        Lit::Str(db_name) => attributes.db_name = Some(db_name.value()),
+                        _ => return Err(error(nested)),
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(val)) if val.path.is_ident(MIGRATE_FROM) => {
+                    match &val.lit {
+                        Lit::Str(old) => attributes.migrate_from = Some(old.parse()?),
+                        _ => return Err(error(nested)),
+                    }
+                }
+                _ => return Err(error(nested)),
+            }
+        }
+    }
+    Ok(attributes)
+}
+
 fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
     generics
         .params
@@ -159,6 +528,19 @@ fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
 /// 3. Auto-generated `read_only_mode` handle
 /// 4. Auto-generated memory stats method
 /// 5. Other convenience features
+/// 6. Auto-generated typed batch
+/// 7. Admin subcommands
+/// 8. Accessor traits
+/// 9. Read only tables
+/// 10. Migrations
+///
+/// The metrics and the secondary paths of the database are labelled with the name of the struct, or with
+/// `#[dbmap_utils(db_name = "consensus")]`. Structs of `DBMap<K, V>` can also be relabelled once open with
+/// `self.with_db_name("consensus-shard-1")`, to tell apart several instances of the struct
+///
+/// The key and value types of a table are read from its `DBMap<K, V>` type, where `K` and `V` can be aliases.
+/// A table whose type is itself an alias, e.g. `type Certificates = DBMap<Digest, Certificate>`, must give them
+/// with `#[dbmap(key = "Digest", value = "Certificate")]`, and `map = "Store"` for an alias of `Store<K, V>`
 ///
 /// 1. Flexible confguration:
 /// a. Static options specified at struct definition
@@ -253,12 +635,137 @@ fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
 /// // Use this handle for dumping
 /// let ret = read_only_handle.dump("table2", 100, 0).unwrap();
 /// let key_count = read_only_handle.count_keys("table1").unwrap();
+/// let summary = read_only_handle.summary("table1").unwrap();
 /// ```
 /// 4. Auto-generated memory stats method
 /// `self.get_memory_usage` is derived to provide memory and cache usage
 ///
 /// 5. Other convenience features
 /// `Tables::describe_tables` is used to get a list of the table names and key-value types as string in a BTreeMap
+/// `self.pause_background_work` and `self.continue_background_work` suspend and resume automatic compactions,
+/// `self.pause_background_work_guard` returns a guard which resumes them when dropped
+/// `self.apply_runtime_config` changes the options of the tables which can be changed without reopening them
+/// `self.options_summary` reports the default options function of each table and a digest of its effective options
+/// `Tables::memory_budget_configurator` divides a `typed_store::rocks::MemoryBudget` among the tables according
+/// to their `#[memory_weight = N]` attribute (1 by default), and `self.memory_usage` reports their usage
+/// A table annotated with `#[filter(bloom)]`, `#[filter(ribbon)]` or `#[filter(prefix_bloom, prefix_len = N)]`
+/// gets the corresponding `typed_store::rocks::FilterPolicy`, on top of its default options and of the
+/// memory budget configuration. The bits per key can be set with e.g. `#[filter(bloom, bits_per_key = 12)]`
+/// A table annotated with `#[large_table]` gets partitioned indexes and filters, see
+/// `typed_store::rocks::default_options_for_large_table`, with a whole key bloom filter unless it has a `#[filter(...)]`
+/// `self.orphan_tables` lists the column families left on disk by tables which were removed from the struct,
+/// and `self.drop_orphan_tables` drops them. Orphans are also logged when the tables are opened
+/// `self.schema_check` returns the tables whose key or value types changed since the last `self.accept_schema`,
+/// which are also logged when the tables are opened
+/// Opening the tables fails if the database was written by a newer version of typed-store with a newer format,
+/// unknown table features or SST files of a newer RocksDB format, see `typed_store::rocks::check_compatibility`.
+/// A database with entries but without a compatibility record is opened with a warning, its writer being unknown
+///
+/// Tables annotated with `#[encrypted]` have their values encoded by a `typed_store::rocks::ValueCodec`,
+/// and must be opened with `Tables::open_tables_with_value_codecs`, e.g. with the master keys of the
+/// `encryption` feature of typed_store. Opening them with another read-write routine fails
+///
+/// A table annotated with `#[codec = "json"]` or `#[codec = "bcs"]` stores its values in that format instead of
+/// bincode, see `typed_store::codec::ValueFormat`, which requires the `json` or `bcs` feature of typed_store.
+/// A table annotated with `#[key_codec = "bcs"]` stores its keys in BCS with big endian integers, see
+/// `typed_store::codec::KeyFormat`. Only the formats keeping the order of the keys the range scans rely on are
+/// accepted, `#[key_codec = "json"]` fails to compile. The formats are reported along the key and value types
+/// by `Tables::describe_tables`, e.g. `Digest as bcs` and `Certificate as json`
+///
+/// 6. Auto-generated typed batch
+/// For structs of `DBMap<K, V>`, a `{StructName}Batch` type is generated along with a `batch()` method.
+/// It exposes typed `insert_{table}` and `delete_{table}` methods for every table, and a single `commit()`
+/// which writes all the operations atomically across the column families. `into_batch()` returns the
+/// underlying `DBBatch`, to add writes to the tables of another struct opened on the same database
+///
+/// A table field annotated with `#[index(name = "by_owner", key = "owner")]` gets a secondary index on the
+/// `owner` field of its values, stored in the `__index/{table}/by_owner` column family. The typed batch keeps the
+/// index consistent with the table in the same atomic write, replacing the entries of the stored values under
+/// the locks of their keys, and `self.get_by_owner(&owner)` returns the matching entries. Writes made directly
+/// through the `DBMap` bypass the index
+/// ```
+/// use typed_store::rocks::DBMap;
+/// use typed_store::Map;
+/// use typed_store_derive::DBMapUtils;
+/// use typed_store::traits::TypedStoreDebug;
+///
+/// #[derive(DBMapUtils)]
+/// struct Tables {
+///     table1: DBMap<String, String>,
+///     table2: DBMap<i32, String>,
+/// }
+///
+/// let primary_path = tempfile::tempdir().expect("Failed to open temporary directory").into_path();
+/// let tables = Tables::open_tables_read_write(primary_path, None, None);
+///
+/// tables
+///     .batch()
+///     .insert_table1(&"key".to_owned(), &"value".to_owned())
+///     .unwrap()
+///     .delete_table2(&1)
+///     .unwrap()
+///     .commit()
+///     .unwrap();
+/// assert!(tables.table1.contains_key(&"key".to_owned()).unwrap());
+/// ```
+///
+/// 7. Admin subcommands
+/// With `#[dbmap_utils(subcommands)]` on the struct, a `{StructName}Command` enum deriving `clap::Subcommand`
+/// is generated, with `dump`, `count`, `summary` and `get` subcommands run against the read only handle.
+/// A node binary can then nest it in its own command line, e.g. `my-node db-tool count table1`.
+/// The crate must depend on `clap` with the `derive` feature, and on typed_store with the `cli` feature
+///
+/// With `#[dbmap_utils(support_bundle)]` on the struct, the read only handle gets a `create_support_bundle`
+/// method, writing the schema, summary, sampled entries, LSM report and effective options of the tables to a
+/// single `.tar.zst` file, see `typed_store::rocks::create_support_bundle`. The crate must depend on typed_store
+/// with the `bundle` feature
+///
+/// 8. Accessor traits
+/// With `#[dbmap_utils(impl_trait = "MyStoreTrait")]` on the struct, the struct implements `MyStoreTrait`,
+/// which must declare exactly one accessor per table, named after it, e.g. `fn table1(&self) -> &DBMap<String, String>`.
+/// Business logic can then depend on the trait, and be tested against other implementations of it
+///
+/// 9. Read only tables
+/// A table of type `ReadOnlyMap<K, V>` marked with `#[read_only_after_open]` can't be written through the struct,
+/// even in read-write mode, nor through its typed batch. Its writable `DBMap` is only returned by
+/// `open_tables_read_write_with_writer`, in the generated `{Struct}Writer`, so that only the components given the
+/// writer can write it, which the compiler checks
+///
+/// 10. Migrations
+/// With `#[dbmap_utils(migrate_from = "TablesV1")]` on a struct of `DBMap<K, V>`, e.g. `TablesV2`, a `TablesV2Migration`
+/// struct is generated with a `typed_store::rocks::TableMigration` per table, and `self.migrate_from(&old, |old| ...)`
+/// fills each table of `self` from the tables of `old` per the migration returned by the closure: kept as is by default,
+/// or copied from a renamed table, converted from a table of another type, or filled with arbitrary entries
+/// ```
+/// use typed_store::rocks::{DBMap, TableMigration};
+/// use typed_store::Map;
+/// use typed_store_derive::DBMapUtils;
+///
+/// #[derive(DBMapUtils)]
+/// struct TablesV1 {
+///     accounts: DBMap<u64, u64>,
+/// }
+///
+/// #[derive(DBMapUtils)]
+/// #[dbmap_utils(migrate_from = "TablesV1")]
+/// struct TablesV2 {
+///     balances: DBMap<u64, u128>,
+///     owners: DBMap<u64, String>,
+/// }
+///
+/// let old = TablesV1::open_tables_read_write(tempfile::tempdir().unwrap().into_path(), None, None);
+/// old.accounts.insert(&1, &100).unwrap();
+///
+/// let new = TablesV2::open_tables_read_write(tempfile::tempdir().unwrap().into_path(), None, None);
+/// let migrated = new
+///     .migrate_from(&old, |old| TablesV2Migration {
+///         balances: TableMigration::convert(&old.accounts, |(id, balance)| Some((id, balance as u128))),
+///         ..Default::default()
+///     })
+///     .unwrap();
+/// assert_eq!(migrated["balances"].entries_committed, 1);
+/// assert_eq!(new.balances.get(&1).unwrap(), Some(100));
+/// ```
 ///
 ///
 /// // Bad usage example
@@ -271,12 +778,28 @@ fn extract_generics_names(generics: &Generics) -> Vec<Ident> {
 /// // #}
 /// ```
 
-#[proc_macro_derive(DBMapUtils, attributes(default_options_override_fn))]
+#[proc_macro_derive(
+    DBMapUtils,
+    attributes(
+        default_options_override_fn,
+        encrypted,
+        memory_weight,
+        index,
+        filter,
+        large_table,
+        dbmap,
+        dbmap_utils,
+        read_only_after_open,
+        codec,
+        key_codec
+    )
+)]
 pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as ItemStruct);
     let name = &input.ident;
     let generics = &input.generics;
     let generics_names = extract_generics_names(generics);
+    let struct_attributes = get_struct_attributes(&input.attrs).unwrap();
 
     let allowed_types_with_post_process_fn: BTreeMap<_, _> =
         [("DBMap", ""), ("Store", "typed_store::Store::new")]
@@ -288,28 +811,176 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
         .collect();
 
     // TODO: use `parse_quote` over `parse()`
-    let (field_names, inner_types, derived_table_options, simple_field_type_name_str) =
+    let (field_names, inner_types, table_attributes, simple_field_type_name_str) =
         extract_struct_info(input.clone(), allowed_strs);
+    let encrypted: Vec<_> = table_attributes.iter().map(|a| a.encrypted).collect();
+    let memory_weights: Vec<_> = table_attributes.iter().map(|a| a.memory_weight).collect();
+    let read_only: Vec<_> = table_attributes
+        .iter()
+        .map(|a| a.read_only_after_open)
+        .collect();
+    if read_only.contains(&true) && simple_field_type_name_str != "DBMap" {
+        panic!("Read only tables are only supported in structs of DBMap<K, V>");
+    }
+    if table_attributes
+        .iter()
+        .any(|a| a.read_only_after_open && !a.indexes.is_empty())
+    {
+        panic!("Read only tables can't have indexes, which are updated by the typed batch");
+    }
 
     let (key_names, value_names): (Vec<_>, Vec<_>) = inner_types
         .iter()
         .map(|q| (q.args.first().unwrap(), q.args.last().unwrap()))
         .unzip();
+    // The key and value types of the tables as described, with their format unless bincode
+    let key_descriptions: Vec<_> = key_names
+        .iter()
+        .zip(&table_attributes)
+        .map(|(k, a)| match &a.key_codec {
+            Some(codec) => {
+                let format = format!(" as {codec}");
+                quote! { concat!(stringify!(#k), #format) }
+            }
+            None => quote! { stringify!(#k) },
+        })
+        .collect();
+    let value_descriptions: Vec<_> = value_names
+        .iter()
+        .zip(&table_attributes)
+        .map(|(v, a)| match &a.codec {
+            Some(codec) => {
+                let format = format!(" as {codec}");
+                quote! { concat!(stringify!(#v), #format) }
+            }
+            None => quote! { stringify!(#v) },
+        })
+        .collect();
+
+    // The secondary indexes are stored in column families named by `typed_store::rocks::index_cf_name`
+    let index_cf_names: Vec<String> = field_names
+        .iter()
+        .zip(&table_attributes)
+        .flat_map(|(f, a)| a.indexes.iter().map(move |i| index_cf_name(f, &i.name)))
+        .collect();
+    if !index_cf_names.is_empty() && simple_field_type_name_str != "DBMap" {
+        panic!("Indexes are only supported on tables of type DBMap<K, V>");
+    }
+    // The features of the tables recorded for the compatibility checks of older binaries
+    let mut compatibility_features = vec![];
+    if !index_cf_names.is_empty() {
+        compatibility_features.push(quote! { typed_store::rocks::SECONDARY_INDEXES_FEATURE });
+    }
+    if table_attributes
+        .iter()
+        .any(|a| a.encrypted || a.codec.is_some())
+    {
+        compatibility_features.push(quote! { typed_store::rocks::VALUE_CODECS_FEATURE });
+    }
+    if table_attributes.iter().any(|a| a.key_codec.is_some()) {
+        compatibility_features.push(quote! { typed_store::rocks::KEY_FORMATS_FEATURE });
+    }
+    // The key and value formats of the tables, wired into their maps at open and reported with their types
+    let value_formats: Vec<_> = table_attributes
+        .iter()
+        .map(|a| {
+            let key_format = a.key_codec.as_deref().map(|codec| {
+                let format = format_ident!("{}", codec[..1].to_uppercase() + &codec[1..]);
+                quote! { let map = map.with_key_format(typed_store::codec::KeyFormat::#format); }
+            });
+            let value_format = a.codec.as_deref().map(|codec| {
+                let format = format_ident!("{}", codec[..1].to_uppercase() + &codec[1..]);
+                quote! { let map = map.with_value_format(typed_store::codec::ValueFormat::#format); }
+            });
+            quote! { #key_format #value_format }
+        })
+        .collect();
+    // All the column families of the struct, tables and indexes
+    let cf_names = quote! { #(stringify!(#field_names),)* #(#index_cf_names,)* };
 
     // This is the actual name of the type which was found
     let post_process_fn_str = allowed_types_with_post_process_fn
         .get(&simple_field_type_name_str.as_str())
         .unwrap();
     let post_process_fn: proc_macro2::TokenStream = post_process_fn_str.parse().unwrap();
+    // The tables of the struct, from those of the intermediate struct `inner` opened as DBMaps
+    let field_values: Vec<_> = field_names
+        .iter()
+        .zip(&read_only)
+        .map(|(f, read_only)| match read_only {
+            true => quote! { typed_store::rocks::ReadOnlyMap::new(inner.#f) },
+            false => quote! { #post_process_fn(inner.#f) },
+        })
+        .collect();
+    // The full path of the map type, so that the generated code doesn't depend on the imports of the crate
+    let map_type_path = match simple_field_type_name_str.as_str() {
+        "Store" => quote! { typed_store::Store },
+        _ => quote! { typed_store::rocks::DBMap },
+    };
+    let field_types: Vec<_> = inner_types
+        .iter()
+        .zip(&read_only)
+        .map(|(inner_type, read_only)| match read_only {
+            true => quote! { typed_store::rocks::ReadOnlyMap #inner_type },
+            false => quote! { #map_type_path #inner_type },
+        })
+        .collect();
 
-    let default_options_override_fn_names: Vec<proc_macro2::TokenStream> = derived_table_options
+    let default_options_override_fn_names: Vec<proc_macro2::TokenStream> = table_attributes
         .iter()
         .map(|q| {
-            let GeneralTableOptions::OverrideFunction(fn_name) = q;
+            let GeneralTableOptions::OverrideFunction(fn_name) = &q.options;
             fn_name.parse().unwrap()
         })
         .collect();
 
+    let default_options_override_fn_strs: Vec<String> = table_attributes
+        .iter()
+        .map(|q| {
+            let GeneralTableOptions::OverrideFunction(fn_name) = &q.options;
+            fn_name.clone()
+        })
+        .collect();
+
+    // The options of the tables when no options are given at open, with their filter if any.
+    // Large tables always have a filter, partitioned like their index
+    let large_table_filter = |a: &TableAttributes| match &a.filter {
+        Some(filter) => filter.clone(),
+        None => quote! { typed_store::rocks::FilterPolicy::whole_key_bloom() },
+    };
+    let table_options: Vec<_> = default_options_override_fn_names
+        .iter()
+        .zip(&table_attributes)
+        .map(|(options_fn, a)| {
+            if a.large_table {
+                let filter = large_table_filter(a);
+                return quote! { typed_store::rocks::large_table_options(#options_fn(), #filter) };
+            }
+            match &a.filter {
+                Some(filter) => quote! { #filter.table_options(#options_fn()) },
+                None => quote! { #options_fn() },
+            }
+        })
+        .collect();
+    // The same options, sharing the block cache of a memory budget
+    let budget_table_options: Vec<_> = default_options_override_fn_names
+        .iter()
+        .zip(&table_attributes)
+        .zip(&field_names)
+        .map(|((options_fn, a), field_name)| {
+            let table = field_name.to_string();
+            if a.large_table {
+                let filter = large_table_filter(a);
+                return quote! { budget.large_table_options(#options_fn(), &weights, #table, #filter) };
+            }
+            let filter = match &a.filter {
+                Some(filter) => quote! { Some(#filter) },
+                None => quote! { None },
+            };
+            quote! { budget.table_options_with_filter(#options_fn(), &weights, #table, #filter) }
+        })
+        .collect();
+
     let generics_bounds =
         "std::fmt::Debug + serde::Serialize + for<'de> serde::de::Deserialize<'de>";
     let generics_bounds_token: proc_macro2::TokenStream = generics_bounds.parse().unwrap();
@@ -330,6 +1001,438 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
         .expect("Expected at least one field")
         .clone();
 
+    // The name of the database at open, and of an open database, which DBMap based structs can relabel
+    let db_name = struct_attributes
+        .db_name
+        .clone()
+        .unwrap_or_else(|| name.to_string());
+    let self_db_name = if simple_field_type_name_str == "DBMap" {
+        quote! { self.#first_field_name.db_name() }
+    } else {
+        quote! { #db_name }
+    };
+
+    let batch_struct_name_str = format!("{}Batch", name);
+    let batch_struct_name: proc_macro2::TokenStream = batch_struct_name_str.parse().unwrap();
+
+    let batch_insert_fn_names: Vec<_> = field_names
+        .iter()
+        .map(|f| format_ident!("insert_{}", f))
+        .collect();
+    let batch_delete_fn_names: Vec<_> = field_names
+        .iter()
+        .map(|f| format_ident!("delete_{}", f))
+        .collect();
+
+    // The typed batch keeps the indexes of a table up to date with a helper, called on every write
+    let mut index_update_fns = vec![];
+    let mut index_get_fns = vec![];
+    let (batch_insert_index_updates, batch_delete_index_updates): (Vec<_>, Vec<_>) = field_names
+        .iter()
+        .zip(&table_attributes)
+        .zip(key_names.iter().zip(&value_names))
+        .map(|((f, a), (k, v))| {
+            if a.indexes.is_empty() {
+                return (quote! {}, quote! {});
+            }
+            let update_fn_name = format_ident!("update_{}_indexes", f);
+            let updates = a.indexes.iter().map(|i| {
+                let cf = index_cf_name(f, &i.name);
+                let field = format_ident!("{}", i.key);
+                quote! {
+                    let index = typed_store::rocks::SecondaryIndex::<#k>::reopen(&table.rocksdb, #cf)?;
+                    self.batch = self.batch.update_index_batch(table, &index, key, value, |v: &#v| &v.#field)?;
+                }
+            });
+            index_update_fns.push(quote! {
+                fn #update_fn_name(mut self, key: &#k, value: Option<&#v>) -> Result<Self, typed_store::rocks::TypedStoreError> {
+                    let table = self.#f;
+                    #(#updates)*
+                    Ok(self)
+                }
+            });
+            index_get_fns.extend(a.indexes.iter().map(|i| {
+                let cf = index_cf_name(f, &i.name);
+                let field = format_ident!("{}", i.key);
+                let get_fn_name = format_ident!("get_{}", i.name);
+                let doc = format!(
+                    "Returns the entries of `{f}` whose `{}` is `index_key`, from the `{cf}` index. \
+                    Fails if `index_key` is not encoded as the `{}` field, see `typed_store::rocks::SecondaryIndex::get_by_field`",
+                    i.key, i.key
+                );
+                quote! {
+                    #[doc = #doc]
+                    pub fn #get_fn_name<I: serde::Serialize + ?Sized>(&self, index_key: &I) -> Result<Vec<(#k, #v)>, typed_store::rocks::TypedStoreError> {
+                        let index = typed_store::rocks::SecondaryIndex::<#k>::reopen(&self.#f.rocksdb, #cf)?;
+                        let keys = index.get_by_field(|v: &#v| &v.#field, index_key)?;
+                        let values = typed_store::traits::Map::multi_get(&self.#f, &keys)?;
+                        Ok(keys.into_iter().zip(values).filter_map(|(k, v)| Some((k, v?))).collect())
+                    }
+                }
+            }));
+            (
+                quote! { self = self.#update_fn_name(key, Some(value))?; },
+                quote! { self = self.#update_fn_name(key, None)?; },
+            )
+        })
+        .unzip();
+
+    // The read only tables are left out of the typed batch, they are written through the writer
+    let writable: Vec<_> = (0..field_names.len()).filter(|i| !read_only[*i]).collect();
+    let batch_field_names: Vec<_> = writable.iter().map(|i| &field_names[*i]).collect();
+    let batch_inner_types: Vec<_> = writable.iter().map(|i| &inner_types[*i]).collect();
+    let batch_key_names: Vec<_> = writable.iter().map(|i| key_names[*i]).collect();
+    let batch_value_names: Vec<_> = writable.iter().map(|i| value_names[*i]).collect();
+    let batch_insert_fn_names: Vec<_> = writable
+        .iter()
+        .map(|i| &batch_insert_fn_names[*i])
+        .collect();
+    let batch_delete_fn_names: Vec<_> = writable
+        .iter()
+        .map(|i| &batch_delete_fn_names[*i])
+        .collect();
+    let batch_insert_index_updates: Vec<_> = writable
+        .iter()
+        .map(|i| &batch_insert_index_updates[*i])
+        .collect();
+    let batch_delete_index_updates: Vec<_> = writable
+        .iter()
+        .map(|i| &batch_delete_index_updates[*i])
+        .collect();
+
+    // Only DBMap based structs keep the maps around after opening, so the typed batch is
+    // only generated for these
+    let typed_batch = if simple_field_type_name_str == "DBMap" {
+        quote! {
+            // <----------- This section generates the typed batch -------------->
+
+            /// A typed batch of write operations across all the tables
+            /// The operations are written atomically by `commit()`
+            pub struct #batch_struct_name<'a, #(#generics_names),*> {
+                batch: typed_store::rocks::DBBatch,
+                #(
+                    #batch_field_names : &'a typed_store::rocks::DBMap #batch_inner_types,
+                )*
+            }
+
+            impl <
+                    'a,
+                    #(
+                        #generics_names: #generics_bounds_token,
+                    )*
+                > #batch_struct_name<'a, #(#generics_names),*> {
+                #(
+                    /// Insert a key-value pair in this table
+                    pub fn #batch_insert_fn_names(mut self, key: &#batch_key_names, value: &#batch_value_names) -> Result<Self, typed_store::rocks::TypedStoreError> {
+                        #batch_insert_index_updates
+                        self.batch = self.batch.insert_batch(self.#batch_field_names, std::iter::once((key, value)))?;
+                        Ok(self)
+                    }
+
+                    /// Delete a key from this table
+                    pub fn #batch_delete_fn_names(mut self, key: &#batch_key_names) -> Result<Self, typed_store::rocks::TypedStoreError> {
+                        #batch_delete_index_updates
+                        self.batch = self.batch.delete_batch(self.#batch_field_names, std::iter::once(key))?;
+                        Ok(self)
+                    }
+                )*
+
+                #(#index_update_fns)*
+
+                /// Atomically write all the operations of the batch
+                pub fn commit(self) -> Result<typed_store::rocks::BatchStats, typed_store::rocks::TypedStoreError> {
+                    self.batch.write()
+                }
+
+                /// Returns the underlying batch, e.g. to add the writes to the tables of another struct
+                /// opened on the same database before writing them all atomically
+                pub fn into_batch(self) -> typed_store::rocks::DBBatch {
+                    self.batch
+                }
+            }
+
+            impl <
+                    #(
+                        #generics_names: #generics_bounds_token,
+                    )*
+                > #name #generics {
+                /// Returns a typed batch spanning all the tables, committed atomically with `commit()`
+                pub fn batch(&self) -> #batch_struct_name<'_, #(#generics_names),*> {
+                    #batch_struct_name {
+                        batch: typed_store::rocks::DBBatch::new(&self.#first_field_name.rocksdb).with_db_name(#self_db_name),
+                        #(
+                            #batch_field_names: &self.#batch_field_names,
+                        )*
+                    }
+                }
+
+                #(#index_get_fns)*
+
+                /// Returns the tables labelled with `db_name` in the metrics, e.g. to tell apart the databases
+                /// of several instances of the struct, instead of the name given at open. The operations on
+                /// the whole database are relabelled too, see `typed_store::rocks::set_db_name`
+                pub fn with_db_name(self, db_name: &str) -> Self {
+                    typed_store::rocks::set_db_name(self.#first_field_name.rocksdb.path(), db_name);
+                    Self {
+                        #(
+                            #field_names: self.#field_names.with_db_name(db_name),
+                        )*
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // The writer of the read only tables, returned along the struct at open
+    let writer_struct_name = format_ident!("{}Writer", name);
+    let read_only_field_names: Vec<_> = (0..field_names.len())
+        .filter(|i| read_only[*i])
+        .map(|i| &field_names[i])
+        .collect();
+    let read_only_inner_types: Vec<_> = (0..field_names.len())
+        .filter(|i| read_only[*i])
+        .map(|i| &inner_types[i])
+        .collect();
+    let writer = if read_only_field_names.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            // <----------- This section generates the writer of the read only tables -------------->
+
+            /// The writable maps of the `#[read_only_after_open]` tables, only returned at open by
+            /// `open_tables_read_write_with_writer`
+            pub struct #writer_struct_name<#(#generics_names),*> {
+                #(
+                    pub #read_only_field_names: typed_store::rocks::DBMap #read_only_inner_types,
+                )*
+                _phantom: std::marker::PhantomData<fn() -> (#(#generics_names,)*)>,
+            }
+
+            impl <
+                    #(
+                        #generics_names: #generics_bounds_token,
+                    )*
+                > #name #generics {
+                /// Opens a set of tables in read-write mode, like `open_tables_read_write`, and returns the writer
+                /// of the `#[read_only_after_open]` tables, which can't be written through the struct
+                #[allow(unused_parens)]
+                pub fn open_tables_read_write_with_writer(
+                    path: std::path::PathBuf,
+                    global_db_options_override: Option<rocksdb::Options>,
+                    tables_db_options_override: Option<typed_store::rocks::DBMapTableConfigMap>
+                ) -> (Self, #writer_struct_name<#(#generics_names),*>) {
+                    let inner = #intermediate_db_map_struct_name::open_tables_impl(path, None, global_db_options_override, tables_db_options_override);
+                    let writer = #writer_struct_name {
+                        #(
+                            #read_only_field_names: inner.#read_only_field_names.clone(),
+                        )*
+                        _phantom: std::marker::PhantomData,
+                    };
+                    let tables = Self {
+                        #(
+                            #field_names: #field_values,
+                        )*
+                    };
+                    (tables, writer)
+                }
+            }
+        }
+    };
+
+    // The accessors of the tables, implementing the traits given with `impl_trait`
+    let accessor_traits: Vec<_> = struct_attributes
+        .impl_traits
+        .iter()
+        .map(|impl_trait| {
+            quote! {
+                impl <
+                        #(
+                            #generics_names: #generics_bounds_token,
+                        )*
+                    > #impl_trait for #name #generics {
+                    #(
+                        fn #field_names(&self) -> &#field_types {
+                            &self.#field_names
+                        }
+                    )*
+                }
+            }
+        })
+        .collect();
+
+    let command_enum_name = format_ident!("{}Command", name);
+    let subcommands = if struct_attributes.subcommands {
+        quote! {
+            // <----------- This section generates the admin subcommands -------------->
+
+            /// The admin subcommands of the tables, run against their read only handle
+            #[derive(clap::Subcommand, Clone, Debug)]
+            pub enum #command_enum_name {
+                /// Dump a page of the entries of a table
+                Dump {
+                    table_name: String,
+                    #[clap(long, default_value = "100")]
+                    page_size: u16,
+                    #[clap(long, default_value = "0")]
+                    page_number: usize,
+                },
+                /// Count the keys of a table
+                Count { table_name: String },
+                /// Show the number of entries and the encoded sizes of the keys and values of a table
+                Summary { table_name: String },
+                /// Get the value of a key, given in JSON. Keys which are not valid JSON are read as strings
+                Get { table_name: String, key: String },
+            }
+
+            impl #command_enum_name {
+                /// Runs the subcommand against `handle`, returning its output
+                pub fn run<#(#generics_names: #generics_bounds_token),*>(self, handle: &#secondary_db_map_struct_name #generics) -> eyre::Result<String> {
+                    Ok(match self {
+                        Self::Dump { table_name, page_size, page_number } => handle
+                            .dump(&table_name, page_size, page_number)?
+                            .into_iter()
+                            .map(|(k, v)| format!("{k}: {v}"))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        Self::Count { table_name } => handle.count_keys(&table_name)?.to_string(),
+                        Self::Summary { table_name } => format!("{:#?}", handle.summary(&table_name)?),
+                        Self::Get { table_name, key } => match table_name.as_str() {
+                            #(
+                                stringify!(#field_names) => {
+                                    typed_store::traits::Map::try_catch_up_with_primary(&handle.#field_names)?;
+                                    let key: #key_names = typed_store::cli::parse_key(&key)?;
+                                    match typed_store::traits::Map::get(&handle.#field_names, &key)? {
+                                        Some(value) => format!("{:?}", value),
+                                        None => eyre::bail!("No such key in table {}: {:?}", table_name, key),
+                                    }
+                                }
+                            )*
+
+                            _ => eyre::bail!("No such table name: {}", table_name),
+                        },
+                    })
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let support_bundle = if struct_attributes.support_bundle {
+        quote! {
+            // <----------- This section generates the support bundle of the read only handle -------------->
+
+            impl <
+                    #(
+                        #generics_names: #generics_bounds_token,
+                    )*
+                > #secondary_db_map_struct_name #generics {
+                /// Writes a support bundle of `tables`, or of all the tables if empty, to `output`, with a sample
+                /// of their first entries. See `typed_store::rocks::create_support_bundle`
+                pub fn create_support_bundle(&self, tables: &[&str], output: &std::path::Path) -> eyre::Result<typed_store::rocks::SupportBundleManifest> {
+                    const SAMPLE_SIZE: u16 = 100;
+                    let limits = typed_store::traits::DumpLimits {
+                        max_page_bytes: Some(1 << 20),
+                        max_value_bytes: Some(1 << 10),
+                    };
+                    let all_tables = [#(stringify!(#field_names)),*];
+                    let tables = if tables.is_empty() { &all_tables[..] } else { tables };
+                    let descriptions = Self::describe_tables();
+                    let mut bundle_tables = Vec::with_capacity(tables.len());
+                    for table_name in tables {
+                        let options_fn = match *table_name {
+                            #(
+                                stringify!(#field_names) => #default_options_override_fn_strs,
+                            )*
+                            _ => eyre::bail!("No such table name: {}", table_name),
+                        };
+                        let (key_type, value_type) = descriptions[*table_name].clone();
+                        bundle_tables.push(typed_store::rocks::BundleTable {
+                            name: table_name.to_string(),
+                            key_type,
+                            value_type,
+                            options_fn: options_fn.to_owned(),
+                            summary: self.summary(table_name)?,
+                            sample: self.dump_with_limits(table_name, SAMPLE_SIZE, 0, &limits)?,
+                        });
+                    }
+                    Ok(typed_store::rocks::create_support_bundle(
+                        self.#first_field_name.db_name(),
+                        &self.#first_field_name.rocksdb,
+                        &bundle_tables,
+                        output,
+                    )?)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let migration_struct_name = format_ident!("{}Migration", name);
+    let migration = match &struct_attributes.migrate_from {
+        Some(old) => {
+            if simple_field_type_name_str != "DBMap" {
+                panic!("Migrations are only supported in structs of DBMap<K, V>");
+            }
+            let migration_doc = format!(
+                "How each table of `{name}` is filled from the tables of a `{}` by `{name}::migrate_from`, \
+                 `TableMigration::Keep` by default",
+                quote! { #old }
+            );
+            quote! {
+                // <----------- This section generates the migration from the previous layout -------------->
+
+                #[doc = #migration_doc]
+                pub struct #migration_struct_name<'a, #(#generics_names),*> {
+                    #(
+                        pub #batch_field_names: typed_store::rocks::TableMigration<'a, #batch_key_names, #batch_value_names>,
+                    )*
+                    #[doc(hidden)]
+                    pub _phantom: std::marker::PhantomData<fn(&'a ()) -> (#(#generics_names,)*)>,
+                }
+
+                impl<'a, #(#generics_names),*> Default for #migration_struct_name<'a, #(#generics_names),*> {
+                    fn default() -> Self {
+                        Self {
+                            #(
+                                #batch_field_names: typed_store::rocks::TableMigration::Keep,
+                            )*
+                            _phantom: std::marker::PhantomData,
+                        }
+                    }
+                }
+
+                impl <
+                        #(
+                            #generics_names: #generics_bounds_token,
+                        )*
+                    > #name #generics {
+                    /// Fills the tables from `old`, the tables with their previous layout, per the `TableMigration` of each
+                    /// table returned by `migration`, e.g. copying a renamed table or converting the values of another.
+                    /// Returns the progress of the writes of the tables which were not kept, by table name
+                    pub fn migrate_from<'a>(
+                        &self,
+                        old: &'a #old,
+                        migration: impl FnOnce(&'a #old) -> #migration_struct_name<'a, #(#generics_names),*>,
+                    ) -> Result<std::collections::BTreeMap<String, typed_store::rocks::ChunkProgress>, typed_store::rocks::TypedStoreError> {
+                        let migration = migration(old);
+                        let mut migrated = std::collections::BTreeMap::new();
+                        #(
+                            if let Some(progress) = migration.#batch_field_names.apply(&self.#batch_field_names)? {
+                                migrated.insert(stringify!(#batch_field_names).to_owned(), progress);
+                            }
+                        )*
+                        Ok(migrated)
+                    }
+                }
+            }
+        }
+        None => quote! {},
+    };
+
     TokenStream::from(quote! {
 
         // <----------- This section generates the configurator struct -------------->
@@ -370,6 +1473,18 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 pub fn configurator() -> #config_struct_name {
                     #config_struct_name::init()
                 }
+
+                /// Returns a configurator dividing the write buffers of `budget` among the tables according to
+                /// their `memory_weight`, and sharing its block cache. The global options should be passed to
+                /// `budget.apply_to_db_options`
+                pub fn memory_budget_configurator(budget: &typed_store::rocks::MemoryBudget) -> #config_struct_name {
+                    let weights = [#((stringify!(#field_names), #memory_weights)),*];
+                    #config_struct_name {
+                        #(
+                            #field_names: #budget_table_options,
+                        )*
+                    }
+                }
         }
 
         // <----------- This section generates the core open logic for opening DBMaps -------------->
@@ -378,7 +1493,7 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
         /// This is only used internally
         struct #intermediate_db_map_struct_name #generics {
                 #(
-                    pub #field_names : DBMap #inner_types,
+                    pub #field_names : typed_store::rocks::DBMap #inner_types,
                 )*
         }
 
@@ -396,18 +1511,48 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 global_db_options_override: Option<rocksdb::Options>,
                 tables_db_options_override: Option<typed_store::rocks::DBMapTableConfigMap>
             ) -> Self {
+                Self::try_open_tables_impl(
+                    path,
+                    as_secondary_with_path,
+                    global_db_options_override,
+                    tables_db_options_override,
+                    typed_store::rocks::RepairPolicy::Fail,
+                    None,
+                ).unwrap_or_else(|e| panic!("Cannot open DB: {e}"))
+            }
+
+            /// Opens a set of tables, returning a diagnostics report on failure
+            /// `repair_policy` is only applied when opening in read-write mode
+            /// `value_codecs` is required in read-write mode if a table is `#[encrypted]`
+            pub fn try_open_tables_impl(
+                path: std::path::PathBuf,
+                as_secondary_with_path: Option<std::path::PathBuf>,
+                global_db_options_override: Option<rocksdb::Options>,
+                tables_db_options_override: Option<typed_store::rocks::DBMapTableConfigMap>,
+                repair_policy: typed_store::rocks::RepairPolicy,
+                value_codecs: Option<&dyn typed_store::rocks::ValueCodecProvider>,
+            ) -> Result<Self, typed_store::rocks::TypedStoreError> {
                 let path = &path;
+                let is_primary = as_secondary_with_path.is_none();
+                // Labels the events of the whole database, e.g. its opening, like its tables
+                typed_store::rocks::set_db_name(path, #db_name);
                 let db = {
                     let opt_cfs = match tables_db_options_override {
                         None => [
                             #(
-                                (stringify!(#field_names).to_owned(), #default_options_override_fn_names()),
+                                (stringify!(#field_names).to_owned(), #table_options),
+                            )*
+                            #(
+                                (#index_cf_names.to_owned(), typed_store::rocks::default_rocksdb_options()),
                             )*
                         ],
                         Some(o) => [
                             #(
                                 (stringify!(#field_names).to_owned(), o.to_map().get(stringify!(#field_names)).unwrap().clone()),
                             )*
+                            #(
+                                (#index_cf_names.to_owned(), typed_store::rocks::default_rocksdb_options()),
+                            )*
                         ]
                     };
 
@@ -415,24 +1560,52 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
 
                     let res = match as_secondary_with_path {
                         Some(p) => typed_store::rocks::open_cf_opts_secondary(path, Some(&p), global_db_options_override, &opt_cfs),
-                        None    => typed_store::rocks::open_cf_opts(path, global_db_options_override, &opt_cfs)
+                        None    => typed_store::rocks::open_cf_opts_with_repair_policy(path, global_db_options_override, &opt_cfs, repair_policy)
                     };
                     res
-                }.expect("Cannot open DB.");
+                }?;
+                // Fails before reading anything written by a newer version
+                typed_store::rocks::check_compatibility(&db)?;
+                if is_primary {
+                    typed_store::rocks::record_compatibility(&db, &[#(#compatibility_features),*])?;
+                    typed_store::rocks::warn_orphan_cfs(&db, &[#cf_names]);
+                }
+                // Secondary instances only report the drifts, the schema is recorded by the primary
+                let schema = vec![#(
+                    (stringify!(#field_names).to_owned(), (#key_descriptions.to_owned(), #value_descriptions.to_owned())),
+                )*].into_iter().collect();
+                typed_store::rocks::check_schema_on_open(&db, &schema, is_primary);
 
                 let (
                         #(
                             #field_names
                         ),*
                 ) = (#(
-                        DBMap::#inner_types::reopen(&db, Some(stringify!(#field_names))).expect(&format!("Cannot open {} CF.", stringify!(#field_names))[..])
+                        {
+                            let started = std::time::Instant::now();
+                            let map = typed_store::rocks::DBMap::#inner_types::reopen(&db, Some(stringify!(#field_names)))?.with_db_name(#db_name);
+                            let map = match (#encrypted, value_codecs) {
+                                (false, _) => map,
+                                (true, Some(p)) => map.with_value_codec(p.value_codec(&db, stringify!(#field_names))?),
+                                // Read only handles can still inspect the keys of encrypted tables
+                                (true, None) if !is_primary => map,
+                                (true, None) => return Err(typed_store::rocks::TypedStoreError::MissingValueCodec(stringify!(#field_names).to_owned())),
+                            };
+                            #value_formats
+                            typed_store::rocks::report_open_progress(typed_store::rocks::OpenProgress::TableOpened {
+                                db_name: #db_name.to_owned(),
+                                cf_name: stringify!(#field_names).to_owned(),
+                                elapsed: started.elapsed(),
+                            });
+                            map
+                        }
                     ),*);
 
-                Self {
+                Ok(Self {
                     #(
                         #field_names,
                     )*
-                }
+                })
             }
         }
 
@@ -457,11 +1630,47 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 let inner = #intermediate_db_map_struct_name::open_tables_impl(path, None, global_db_options_override, tables_db_options_override);
                 Self {
                     #(
-                        #field_names: #post_process_fn(inner.#field_names),
+                        #field_names: #field_values,
                     )*
                 }
             }
 
+            /// Opens a set of tables in read-write mode, like `open_tables_read_write`
+            /// If the DB is corrupted, `repair_policy` decides whether to fail or try to repair it
+            /// On failure, the error carries a `typed_store::rocks::OpenFailureReport`
+            #[allow(unused_parens)]
+            pub fn open_tables_with_repair_policy(
+                path: std::path::PathBuf,
+                global_db_options_override: Option<rocksdb::Options>,
+                tables_db_options_override: Option<typed_store::rocks::DBMapTableConfigMap>,
+                repair_policy: typed_store::rocks::RepairPolicy,
+            ) -> Result<Self, typed_store::rocks::TypedStoreError> {
+                let inner = #intermediate_db_map_struct_name::try_open_tables_impl(path, None, global_db_options_override, tables_db_options_override, repair_policy, None)?;
+                Ok(Self {
+                    #(
+                        #field_names: #field_values,
+                    )*
+                })
+            }
+
+            /// Opens a set of tables in read-write mode, like `open_tables_read_write`
+            /// The values of the `#[encrypted]` tables are encoded by the codecs built by `value_codecs`,
+            /// e.g. `typed_store::rocks::encryption::MasterKeys`
+            #[allow(unused_parens)]
+            pub fn open_tables_with_value_codecs(
+                path: std::path::PathBuf,
+                global_db_options_override: Option<rocksdb::Options>,
+                tables_db_options_override: Option<typed_store::rocks::DBMapTableConfigMap>,
+                value_codecs: &dyn typed_store::rocks::ValueCodecProvider,
+            ) -> Result<Self, typed_store::rocks::TypedStoreError> {
+                let inner = #intermediate_db_map_struct_name::try_open_tables_impl(path, None, global_db_options_override, tables_db_options_override, typed_store::rocks::RepairPolicy::Fail, Some(value_codecs))?;
+                Ok(Self {
+                    #(
+                        #field_names: #field_values,
+                    )*
+                })
+            }
+
             /// This gives info about memory usage and returns a tuple of total table memory usage and cache memory usage
             pub fn get_memory_usage(&self) -> Result<(u64, u64), typed_store::rocks::TypedStoreError> {
                 let stats = rocksdb::perf::get_memory_usage_stats(Some(&[&self.#first_field_name.rocksdb]), None)
@@ -469,13 +1678,101 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 Ok((stats.mem_table_total, stats.cache_total))
             }
 
+            /// Returns the memory used by the tables, opened with `budget`, see `typed_store::rocks::MemoryBudget`
+            pub fn memory_usage(&self, budget: &typed_store::rocks::MemoryBudget) -> Result<typed_store::rocks::MemoryUsage, typed_store::rocks::TypedStoreError> {
+                budget.usage(&self.#first_field_name.rocksdb, &[#cf_names])
+            }
+
             /// Returns a list of the tables name and type pairs
             pub fn describe_tables() -> std::collections::BTreeMap<String, (String, String)> {
                 vec![#(
-                    (stringify!(#field_names).to_owned(), (stringify!(#key_names).to_owned(), stringify!(#value_names).to_owned())),
+                    (stringify!(#field_names).to_owned(), (#key_descriptions.to_owned(), #value_descriptions.to_owned())),
                 )*].into_iter().collect()
             }
 
+            /// Returns, for each table, the function which provided its default options and a digest of its
+            /// effective options. See `typed_store::rocks::options_summary`
+            pub fn options_summary(&self) -> Result<Vec<typed_store::rocks::TableOptionsSummary>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::options_summary(&self.#first_field_name.rocksdb, &[#(
+                    (stringify!(#field_names), #default_options_override_fn_strs),
+                )*])
+            }
+
+            /// Stops scheduling automatic compactions on all the tables, e.g. during latency critical windows
+            /// See `typed_store::rocks::pause_background_work`
+            pub fn pause_background_work(&self) -> Result<(), typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::pause_background_work(&self.#first_field_name.rocksdb, &[#cf_names])
+            }
+
+            /// Resumes automatic compactions on all the tables
+            pub fn continue_background_work(&self) -> Result<(), typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::continue_background_work(&self.#first_field_name.rocksdb, &[#cf_names])
+            }
+
+            /// Changes the runtime mutable options of the tables, e.g. to re-tune a running node
+            /// See `typed_store::rocks::apply_runtime_config`
+            pub fn apply_runtime_config(&self, config: &typed_store::rocks::RuntimeConfig) -> Result<(), typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::apply_runtime_config(&self.#first_field_name.rocksdb, &[#cf_names], config)
+            }
+
+            /// Pauses automatic compactions on all the tables until the returned guard is dropped
+            pub fn pause_background_work_guard(&self) -> Result<typed_store::rocks::BackgroundWorkGuard, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::BackgroundWorkGuard::new(&self.#first_field_name.rocksdb, &[#cf_names])
+            }
+
+            /// Returns the column families found on disk which are not tables of this struct, with their sizes
+            pub fn orphan_tables(&self) -> Result<Vec<typed_store::rocks::OrphanColumnFamily>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::find_orphan_cfs(&self.#first_field_name.rocksdb, &[#cf_names])
+            }
+
+            /// Drops the orphan column families listed in `confirm_list`, see `typed_store::rocks::drop_orphan_cfs`
+            pub fn drop_orphan_tables(&self, confirm_list: &[&str]) -> Result<Vec<String>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::drop_orphan_cfs(&self.#first_field_name.rocksdb, &[#cf_names], confirm_list)
+            }
+
+            /// Checks the disk usage of the database of the tables against `quota`, and returns the limits
+            /// which are exceeded. See `typed_store::rocks::spawn_disk_quota_check` to check it periodically
+            pub fn check_disk_quota(&self, quota: &typed_store::rocks::DiskQuota) -> Result<Vec<typed_store::rocks::QuotaExceeded>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::check_disk_quota(#self_db_name, &self.#first_field_name.rocksdb, quota)
+            }
+
+            /// Exports `tables` to files in `dir` from a checkpoint of the database, for offline analytics
+            /// See `typed_store::rocks::export_snapshot`
+            pub fn export_snapshot(&self, tables: &[&str], dir: &std::path::Path, format: typed_store::rocks::ExportFormat) -> Result<Vec<typed_store::rocks::ExportedTable>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::export_snapshot(&self.#first_field_name.rocksdb, tables, dir, format)
+            }
+
+            /// Returns the background errors and write stall state of the database of the tables
+            pub fn health(&self) -> Result<typed_store::rocks::events::DBHealth, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::events::db_health(&self.#first_field_name.rocksdb)
+            }
+
+            /// Spawns a task polling the database of the tables every `interval`, and returns a channel
+            /// of the background errors it detects. Must be called from within a tokio runtime
+            pub fn watch_background_errors(&self, interval: std::time::Duration) -> typed_store::rocks::events::BackgroundErrorReceiver {
+                let broadcaster = std::sync::Arc::new(typed_store::rocks::events::BackgroundErrorBroadcaster::default());
+                let receiver = broadcaster.subscribe();
+                typed_store::rocks::events::spawn_event_listener(
+                    #self_db_name.to_owned(),
+                    &self.#first_field_name.rocksdb,
+                    None,
+                    broadcaster,
+                    interval,
+                );
+                receiver
+            }
+
+            /// Returns the tables whose key or value types changed since their schema was recorded
+            /// See `typed_store::rocks::schema_check`
+            pub fn schema_check(&self) -> Result<Vec<typed_store::rocks::SchemaDrift>, typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::schema_check(&self.#first_field_name.rocksdb, &Self::describe_tables())
+            }
+
+            /// Records the current types of the tables, accepting the drifts returned by `schema_check`
+            pub fn accept_schema(&self) -> Result<(), typed_store::rocks::TypedStoreError> {
+                typed_store::rocks::record_schema(&self.#first_field_name.rocksdb, &Self::describe_tables())
+            }
+
             /// This opens the DB in read only mode and returns a struct which exposes debug features
             pub fn get_read_only_handle (
                 primary_path: std::path::PathBuf,
@@ -492,8 +1789,10 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
         /// This is only used internally
         pub struct #secondary_db_map_struct_name #generics {
             #(
-                pub #field_names : DBMap #inner_types,
+                pub #field_names : typed_store::rocks::DBMap #inner_types,
             )*
+            // Removed once the tables are dropped, so the maps shouldn't outlive this struct
+            managed_secondary_path: Option<typed_store::rocks::ManagedSecondaryPath>,
         }
 
         impl <
@@ -502,40 +1801,56 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 )*
             > #secondary_db_map_struct_name #generics {
             /// Open in read only mode. No limitation on number of processes to do this
+            /// Without `with_secondary_path`, the handle gets a `typed_store::rocks::ManagedSecondaryPath`,
+            /// removed when it is dropped
             pub fn open_tables_read_only(
                 primary_path: std::path::PathBuf,
                 with_secondary_path: Option<std::path::PathBuf>,
                 global_db_options_override: Option<rocksdb::Options>,
             ) -> Self {
-                let inner = match with_secondary_path {
-                    Some(q) => #intermediate_db_map_struct_name::open_tables_impl(primary_path, Some(q), global_db_options_override, None),
+                let (secondary_path, managed_secondary_path) = match with_secondary_path {
+                    Some(q) => (q, None),
                     None => {
-                        let p: std::path::PathBuf = tempfile::tempdir()
-                        .expect("Failed to open temporary directory")
-                        .into_path();
-                        #intermediate_db_map_struct_name::open_tables_impl(primary_path, Some(p), global_db_options_override, None)
+                        let managed = typed_store::rocks::ManagedSecondaryPath::allocate(&primary_path, #db_name)
+                            .unwrap_or_else(|e| panic!("Cannot allocate a secondary path: {e}"));
+                        (managed.path().to_path_buf(), Some(managed))
                     }
                 };
+                let inner = #intermediate_db_map_struct_name::open_tables_impl(primary_path, Some(secondary_path), global_db_options_override, None);
                 Self {
                     #(
                         #field_names: inner.#field_names,
                     )*
+                    managed_secondary_path,
                 }
             }
 
+            /// The secondary path of the handle, if it was allocated by `open_tables_read_only`
+            pub fn managed_secondary_path(&self) -> Option<&std::path::Path> {
+                self.managed_secondary_path.as_ref().map(|managed| managed.path())
+            }
+
             /// Dump all key-value pairs in the page at the given table name
             /// Tables must be opened in read only mode using `open_tables_read_only`
             pub fn dump(&self, table_name: &str, page_size: u16,
                 page_number: usize) -> eyre::Result<std::collections::BTreeMap<String, String>> {
+                Ok(self.dump_with_limits(table_name, page_size, page_number, &Default::default())?.entries)
+            }
+
+            /// Dump the key-value pairs in the page at the given table name, like `dump`
+            /// The page stops once its output exceeds the byte budget of `limits`, and its long values are truncated
+            pub fn dump_with_limits(&self, table_name: &str, page_size: u16,
+                page_number: usize, limits: &typed_store::traits::DumpLimits) -> eyre::Result<typed_store::traits::DumpPage> {
                 Ok(match table_name {
                     #(
                         stringify!(#field_names) => {
                             typed_store::traits::Map::try_catch_up_with_primary(&self.#field_names)?;
-                            typed_store::traits::Map::iter(&self.#field_names)
-                                .skip((page_number * (page_size) as usize))
-                                .take(page_size as usize)
-                                .map(|(k, v)| (format!("{:?}", k), format!("{:?}", v)))
-                                .collect::<std::collections::BTreeMap<_, _>>()
+                            limits.page(
+                                typed_store::traits::Map::iter(&self.#field_names)
+                                    .skip((page_number * (page_size) as usize))
+                                    .take(page_size as usize)
+                                    .map(|(k, v)| (format!("{:?}", k), format!("{:?}", v)))
+                            )
                         }
                     )*
 
@@ -558,13 +1873,47 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                 })
             }
 
+            /// Get the number of entries and the encoded sizes of the keys and values in this table
+            /// Tables must be opened in read only mode using `open_tables_read_only`
+            pub fn summary(&self, table_name: &str) -> eyre::Result<typed_store::traits::TableSummary> {
+                Ok(match table_name {
+                    #(
+                        stringify!(#field_names) => {
+                            typed_store::traits::Map::try_catch_up_with_primary(&self.#field_names)?;
+                            self.#field_names.table_summary()?
+                        }
+                    )*
+
+                    _ => eyre::bail!("No such table name: {}", table_name),
+                })
+            }
+
             pub fn describe_tables() -> std::collections::BTreeMap<String, (String, String)> {
                 vec![#(
-                    (stringify!(#field_names).to_owned(), (stringify!(#key_names).to_owned(), stringify!(#value_names).to_owned())),
+                    (stringify!(#field_names).to_owned(), (#key_descriptions.to_owned(), #value_descriptions.to_owned())),
                 )*].into_iter().collect()
             }
+
+            /// Returns how far the tables, opened as a secondary instance of the tables of `primary`,
+            /// are behind them, so that replicas can refuse to serve overly stale data. See
+            /// `typed_store::rocks::replica_lag`
+            pub fn lag(&self, primary: &Self) -> typed_store::rocks::ReplicaLag {
+                typed_store::rocks::replica_lag(&primary.#first_field_name.rocksdb, &self.#first_field_name.rocksdb)
+            }
         }
 
+        #typed_batch
+
+        #writer
+
+        #subcommands
+
+        #support_bundle
+
+        #migration
+
+        #(#accessor_traits)*
+
         impl <
                 #(
                     #generics_names: #generics_bounds_token,
@@ -579,8 +1928,18 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                     self.dump(table_name.as_str(), page_size, page_number)
                 }
 
+                fn dump_table_with_limits(
+                    &self,
+                    table_name: String,
+                    page_size: u16,
+                    page_number: usize,
+                    limits: &typed_store::traits::DumpLimits,
+                ) -> eyre::Result<typed_store::traits::DumpPage> {
+                    self.dump_with_limits(table_name.as_str(), page_size, page_number, limits)
+                }
+
                 fn primary_db_name(&self) -> String {
-                    stringify!(#name).to_owned()
+                    self.#first_field_name.db_name().to_owned()
                 }
 
                 fn describe_all_tables(&self) -> std::collections::BTreeMap<String, (String, String)> {
@@ -591,6 +1950,10 @@ pub fn derive_dbmap_utils_general(input: TokenStream) -> TokenStream {
                     self.count_keys(table_name.as_str())
                 }
 
+                fn table_summary(&self, table_name: String) -> eyre::Result<typed_store::traits::TableSummary> {
+                    self.summary(table_name.as_str())
+                }
+
         }
 
     })
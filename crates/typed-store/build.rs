@@ -0,0 +1,58 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+fn main() {
+    #[cfg(feature = "admin")]
+    build_admin_service();
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Generates the `TypedStoreAdmin` gRPC service, see `typed_store::admin`.
+/// Messages are serde types encoded with bincode, so no protobuf compiler is needed.
+#[cfg(feature = "admin")]
+fn build_admin_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let codec_path = "mysten_network::codec::BincodeCodec";
+    let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(input_type)
+            .output_type(output_type)
+            .codec_path(codec_path)
+            .build()
+    };
+
+    let admin_service = Service::builder()
+        .name("TypedStoreAdmin")
+        .package("typed_store")
+        .comment("Debug access to the tables of read-only database handles")
+        .method(method(
+            "describe_tables",
+            "DescribeTables",
+            "crate::admin::DescribeTablesRequest",
+            "crate::admin::DescribeTablesResponse",
+        ))
+        .method(method(
+            "dump_table",
+            "DumpTable",
+            "crate::admin::DumpTableRequest",
+            "crate::admin::DumpTableResponse",
+        ))
+        .method(method(
+            "count_keys",
+            "CountKeys",
+            "crate::admin::CountKeysRequest",
+            "crate::admin::CountKeysResponse",
+        ))
+        .method(method(
+            "table_summary",
+            "TableSummary",
+            "crate::admin::TableSummaryRequest",
+            "crate::traits::TableSummary",
+        ))
+        .build();
+
+    Builder::new().compile(&[admin_service]);
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A gRPC service exposing the `TypedStoreDebug` features of read-only handles,
+//! so that the tables of a running node can be inspected remotely.
+//!
+//! The service is defined in `build.rs` and uses the bincode codec of `mysten_network`.
+//! Several databases can be served at once, requests select one by its `primary_db_name`.
+//!
+//! ```ignore
+//! let service = TypedStoreAdminService::new()
+//!     .with_db(Tables::get_read_only_handle(path, None, None));
+//! mysten_network::config::Config::new()
+//!     .server_builder()
+//!     .add_service(service.into_server())
+//!     .bind(&address)
+//!     .await?;
+//! ```
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tonic::{Request, Response, Status};
+
+use crate::traits::{TableSummary, TypedStoreDebug};
+
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/typed_store.TypedStoreAdmin.rs"));
+}
+
+pub use generated::{
+    typed_store_admin_client::TypedStoreAdminClient,
+    typed_store_admin_server::{TypedStoreAdmin, TypedStoreAdminServer},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DescribeTablesRequest {
+    pub db_name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DescribeTablesResponse {
+    /// Table names mapped to their key and value types
+    pub tables: BTreeMap<String, (String, String)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DumpTableRequest {
+    pub db_name: String,
+    pub table_name: String,
+    pub page_size: u16,
+    pub page_number: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DumpTableResponse {
+    /// Debug representations of the keys and values in the page
+    pub entries: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountKeysRequest {
+    pub db_name: String,
+    pub table_name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountKeysResponse {
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TableSummaryRequest {
+    pub db_name: String,
+    pub table_name: String,
+}
+
+type DebugHandle = Arc<dyn TypedStoreDebug + Send + Sync>;
+
+/// Serves the `TypedStoreAdmin` gRPC service over a set of read-only handles
+#[derive(Clone, Default)]
+pub struct TypedStoreAdminService {
+    dbs: BTreeMap<String, DebugHandle>,
+}
+
+impl TypedStoreAdminService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the tables of `handle` under its `primary_db_name`
+    pub fn with_db<T: TypedStoreDebug + Send + Sync + 'static>(mut self, handle: T) -> Self {
+        self.dbs.insert(handle.primary_db_name(), Arc::new(handle));
+        self
+    }
+
+    pub fn into_server(self) -> TypedStoreAdminServer<Self> {
+        TypedStoreAdminServer::new(self)
+    }
+
+    fn table(&self, db_name: &str, table_name: &str) -> Result<DebugHandle, Status> {
+        let db = self.db(db_name)?;
+        if !db.describe_all_tables().contains_key(table_name) {
+            return Err(Status::not_found(format!(
+                "No such table name: {table_name} in {db_name}"
+            )));
+        }
+        Ok(db)
+    }
+
+    fn db(&self, db_name: &str) -> Result<DebugHandle, Status> {
+        self.dbs
+            .get(db_name)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("No such database: {db_name}")))
+    }
+}
+
+/// Runs a table scan on the blocking thread pool
+async fn scan<R, F>(db: DebugHandle, f: F) -> Result<Response<R>, Status>
+where
+    R: Send + 'static,
+    F: FnOnce(&dyn TypedStoreDebug) -> eyre::Result<R> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(db.as_ref()))
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map(Response::new)
+        .map_err(|e| Status::internal(e.to_string()))
+}
+
+#[tonic::async_trait]
+impl TypedStoreAdmin for TypedStoreAdminService {
+    async fn describe_tables(
+        &self,
+        request: Request<DescribeTablesRequest>,
+    ) -> Result<Response<DescribeTablesResponse>, Status> {
+        let db = self.db(&request.get_ref().db_name)?;
+        Ok(Response::new(DescribeTablesResponse {
+            tables: db.describe_all_tables(),
+        }))
+    }
+
+    async fn dump_table(
+        &self,
+        request: Request<DumpTableRequest>,
+    ) -> Result<Response<DumpTableResponse>, Status> {
+        let request = request.into_inner();
+        let db = self.table(&request.db_name, &request.table_name)?;
+        scan(db, move |db| {
+            let entries = db.dump_table(
+                request.table_name,
+                request.page_size,
+                request.page_number as usize,
+            )?;
+            Ok(DumpTableResponse { entries })
+        })
+        .await
+    }
+
+    async fn count_keys(
+        &self,
+        request: Request<CountKeysRequest>,
+    ) -> Result<Response<CountKeysResponse>, Status> {
+        let request = request.into_inner();
+        let db = self.table(&request.db_name, &request.table_name)?;
+        scan(db, move |db| {
+            let count = db.count_table_keys(request.table_name)? as u64;
+            Ok(CountKeysResponse { count })
+        })
+        .await
+    }
+
+    async fn table_summary(
+        &self,
+        request: Request<TableSummaryRequest>,
+    ) -> Result<Response<TableSummary>, Status> {
+        let request = request.into_inner();
+        let db = self.table(&request.db_name, &request.table_name)?;
+        scan(db, move |db| db.table_summary(request.table_name)).await
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The reader of the table archives written by `typed_store::rocks::archive_table`, see its
+//! documentation for their format.
+//!
+//! It doesn't depend on RocksDB, with the `archive-reader` feature only, so that the archives of a
+//! support bundle can be inspected by tools built without the `rocks` feature.
+
+use std::io::{self, Read};
+
+use crate::errors::TypedStoreError;
+
+/// The first bytes of an archive, changed with its format
+pub const ARCHIVE_MAGIC: &[u8; 8] = b"TSARCHV1";
+
+pub(crate) fn archive_error(e: io::Error) -> TypedStoreError {
+    TypedStoreError::IoError(format!("failed to access the archive: {e}"))
+}
+
+/// Reads the entries of an archive written by `archive_table`, as encoded in the table
+pub struct TableArchiveReader<R: Read> {
+    decoder: zstd::stream::read::Decoder<'static, io::BufReader<R>>,
+    table: String,
+}
+
+impl<R: Read> TableArchiveReader<R> {
+    /// Reads the header of the archive from `reader`, failing if it is not an archive of this format
+    pub fn new(reader: R) -> Result<Self, TypedStoreError> {
+        let mut decoder = zstd::stream::read::Decoder::new(reader).map_err(archive_error)?;
+        let mut magic = [0; ARCHIVE_MAGIC.len()];
+        decoder.read_exact(&mut magic).map_err(archive_error)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(TypedStoreError::SerializationError(format!(
+                "not a table archive, or of an unsupported version: {magic:?}"
+            )));
+        }
+        let mut reader = Self {
+            decoder,
+            table: String::new(),
+        };
+        let table = reader.read_bytes(false)?.expect("The table name is read");
+        reader.table = String::from_utf8(table).map_err(|e| {
+            TypedStoreError::SerializationError(format!("invalid table name in the archive: {e}"))
+        })?;
+        Ok(reader)
+    }
+
+    /// The name of the archived table
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Reads length prefixed bytes, or `None` at the end of the archive if `may_end`
+    fn read_bytes(&mut self, may_end: bool) -> Result<Option<Vec<u8>>, TypedStoreError> {
+        let mut len = [0u8; 4];
+        match self.decoder.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if may_end && e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(archive_error(e)),
+        }
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        self.decoder.read_exact(&mut bytes).map_err(archive_error)?;
+        Ok(Some(bytes))
+    }
+}
+
+impl<R: Read> Iterator for TableArchiveReader<R> {
+    type Item = Result<(Vec<u8>, Vec<u8>), TypedStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_bytes(true) {
+            Ok(Some(key)) => Some(
+                self.read_bytes(false)
+                    .map(|value| (key, value.expect("Values are always read"))),
+            ),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
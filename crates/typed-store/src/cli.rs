@@ -0,0 +1,16 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers of the admin subcommands generated by `#[dbmap_utils(subcommands)]`, enabled by the `cli` feature.
+
+use serde::de::DeserializeOwned;
+
+/// Parses a key given on the command line in JSON, e.g. `42` or `[1, "a"]`.
+/// Keys which are not valid JSON are read as JSON strings, so that string keys don't need quotes.
+pub fn parse_key<K: DeserializeOwned>(key: &str) -> eyre::Result<K> {
+    match serde_json::from_str(key) {
+        Ok(key) => Ok(key),
+        Err(e) => serde_json::from_value(serde_json::Value::String(key.to_owned()))
+            .map_err(|_| eyre::eyre!("Invalid key {key}: {e}")),
+    }
+}
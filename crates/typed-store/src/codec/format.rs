@@ -0,0 +1,112 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The serialization formats of the values of the tables.
+//!
+//! Values are serialized with bincode, unless their table stores them in another `ValueFormat`,
+//! e.g. to be read by tools in other languages. The values are serialized in their format directly,
+//! before being transformed by the `ValueCodec` of their table if any, e.g. to encrypt them. Keys
+//! have their own `KeyFormat`, limited to the formats preserving the order of integers that range
+//! scans and prefix seeks rely on.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use typed_store::codec::ValueFormat;
+//! use typed_store::rocks::*;
+//! use typed_store::Map;
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Account { balance: u64 }
+//!
+//! let path = tempfile::tempdir().unwrap();
+//! let db = DBMap::<u32, Account>::open(&path, None, None).unwrap()
+//!     .with_value_format(ValueFormat::default());
+//! db.insert(&1, &Account { balance: 10 }).unwrap();
+//! assert_eq!(db.get(&1).unwrap(), Some(Account { balance: 10 }));
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::TypedStoreError;
+
+/// The serialization format of the values of a table
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ValueFormat {
+    #[default]
+    Bincode,
+    /// BCS, the canonical serialization of the Move values, with the `bcs` feature
+    #[cfg(feature = "bcs")]
+    Bcs,
+    /// JSON, with the `json` feature
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl ValueFormat {
+    /// The name of the format, as given to `#[codec = "..."]`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueFormat::Bincode => "bincode",
+            #[cfg(feature = "bcs")]
+            ValueFormat::Bcs => "bcs",
+            #[cfg(feature = "json")]
+            ValueFormat::Json => "json",
+        }
+    }
+
+    pub(crate) fn serialize<V: Serialize + ?Sized>(
+        &self,
+        value: &V,
+    ) -> Result<Vec<u8>, TypedStoreError> {
+        match self {
+            ValueFormat::Bincode => Ok(bincode::serialize(value)?),
+            #[cfg(feature = "bcs")]
+            ValueFormat::Bcs => {
+                bcs::to_bytes(value).map_err(|e| TypedStoreError::SerializationError(e.to_string()))
+            }
+            #[cfg(feature = "json")]
+            ValueFormat::Json => serde_json::to_vec(value)
+                .map_err(|e| TypedStoreError::SerializationError(e.to_string())),
+        }
+    }
+
+    pub(crate) fn deserialize<V: DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<V, TypedStoreError> {
+        match self {
+            ValueFormat::Bincode => Ok(bincode::deserialize(bytes)?),
+            #[cfg(feature = "bcs")]
+            ValueFormat::Bcs => bcs::from_bytes(bytes)
+                .map_err(|e| TypedStoreError::SerializationError(e.to_string())),
+            #[cfg(feature = "json")]
+            ValueFormat::Json => serde_json::from_slice(bytes)
+                .map_err(|e| TypedStoreError::SerializationError(e.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ValueFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ValueFormat {
+    type Err = TypedStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(ValueFormat::Bincode),
+            #[cfg(feature = "bcs")]
+            "bcs" => Ok(ValueFormat::Bcs),
+            #[cfg(feature = "json")]
+            "json" => Ok(ValueFormat::Json),
+            _ => Err(TypedStoreError::SerializationError(format!(
+                "unknown or disabled value format {s}"
+            ))),
+        }
+    }
+}
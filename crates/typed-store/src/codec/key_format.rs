@@ -0,0 +1,102 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The serialization formats of the keys of the tables.
+//!
+//! Range scans, prefix seeks and the iteration order of the tables rely on the order of the
+//! encoded keys, so only the formats whose integers keep their order once encoded are offered:
+//! big endian bincode by default, and BCS with big endian integers. Their encodings are
+//! prefix-free, so that the first fields of a key can be used as a prefix.
+//!
+//! ```
+//! use typed_store::codec::KeyFormat;
+//! use typed_store::rocks::*;
+//! use typed_store::Map;
+//!
+//! let path = tempfile::tempdir().unwrap();
+//! let db = DBMap::<(u64, u64), String>::open(&path, None, None).unwrap()
+//!     .with_key_format(KeyFormat::default());
+//! db.insert(&(2, 1), &"b".to_owned()).unwrap();
+//! db.insert(&(1, 300), &"a".to_owned()).unwrap();
+//! assert_eq!(db.keys().collect::<Vec<_>>(), vec![(1, 300), (2, 1)]);
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "bcs")]
+use super::ordered_bcs;
+use crate::errors::TypedStoreError;
+
+/// The serialization format of the keys of a table
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum KeyFormat {
+    /// Bincode with big endian fixed size integers
+    #[default]
+    Bincode,
+    /// BCS with big endian integers instead of little endian ones, with the `bcs` feature.
+    /// Floats and chars are not supported
+    #[cfg(feature = "bcs")]
+    Bcs,
+}
+
+impl KeyFormat {
+    /// The name of the format, as given to `#[key_codec = "..."]`
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyFormat::Bincode => "bincode",
+            #[cfg(feature = "bcs")]
+            KeyFormat::Bcs => "bcs",
+        }
+    }
+
+    pub(crate) fn serialize<K: Serialize + ?Sized>(
+        &self,
+        key: &K,
+    ) -> Result<Vec<u8>, TypedStoreError> {
+        match self {
+            KeyFormat::Bincode => super::be_fix_int_ser(key),
+            #[cfg(feature = "bcs")]
+            KeyFormat::Bcs => ordered_bcs::to_bytes(key)
+                .map_err(|e| TypedStoreError::SerializationError(e.to_string())),
+        }
+    }
+
+    pub(crate) fn deserialize<K: DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<K, TypedStoreError> {
+        match self {
+            KeyFormat::Bincode => Ok(bincode::DefaultOptions::new()
+                .with_big_endian()
+                .with_fixint_encoding()
+                .deserialize(bytes)?),
+            #[cfg(feature = "bcs")]
+            KeyFormat::Bcs => ordered_bcs::from_bytes(bytes)
+                .map_err(|e| TypedStoreError::SerializationError(e.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KeyFormat {
+    type Err = TypedStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bincode" => Ok(KeyFormat::Bincode),
+            #[cfg(feature = "bcs")]
+            "bcs" => Ok(KeyFormat::Bcs),
+            _ => Err(TypedStoreError::SerializationError(format!(
+                "unknown, disabled or not order preserving key format {s}"
+            ))),
+        }
+    }
+}
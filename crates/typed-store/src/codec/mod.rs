@@ -0,0 +1,82 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The encoding of the keys and values of the tables, shared by all the storage backends.
+
+use std::fmt;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::TypedStoreError;
+
+mod format;
+mod key_format;
+#[cfg(feature = "bcs")]
+mod ordered_bcs;
+mod versioned;
+
+pub use format::ValueFormat;
+pub use key_format::KeyFormat;
+pub use versioned::{ValueVersion, VersionedCodec};
+
+/// A transformation of the serialized values of a table, e.g. encryption, applied after
+/// serialization on writes and reverted before deserialization on reads.
+/// Keys are never transformed, so that their ordering is preserved, but are passed serialized
+/// along the values, e.g. to authenticate a value with the key it is stored under.
+pub trait ValueCodec: Send + Sync + fmt::Debug {
+    fn encode(&self, key: &[u8], value: Vec<u8>) -> Result<Vec<u8>, TypedStoreError>;
+
+    fn decode(&self, key: &[u8], bytes: &[u8]) -> Result<Vec<u8>, TypedStoreError>;
+
+    /// Whether a value stored as `bytes` should be written back re-encoded by
+    /// `DBMap::rewrite_stale_values`, e.g. after a lazy upgrade
+    fn rewrite_on_read(&self, _bytes: &[u8]) -> bool {
+        false
+    }
+}
+
+/// How the values of a table are stored: serialized in `format`, then transformed by `codec` if any
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ValueEncoding<'a> {
+    pub(crate) format: ValueFormat,
+    pub(crate) codec: Option<&'a dyn ValueCodec>,
+}
+
+/// Encodes `value`, stored under the serialized `key`
+pub(crate) fn encode_value<V: Serialize + ?Sized>(
+    encoding: ValueEncoding<'_>,
+    key: &[u8],
+    value: &V,
+) -> Result<Vec<u8>, TypedStoreError> {
+    let bytes = encoding.format.serialize(value)?;
+    match encoding.codec {
+        Some(codec) => codec.encode(key, bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Decodes the value stored as `bytes` under the serialized `key`
+pub(crate) fn decode_value<V: DeserializeOwned>(
+    encoding: ValueEncoding<'_>,
+    key: &[u8],
+    bytes: &[u8],
+) -> Result<V, TypedStoreError> {
+    match encoding.codec {
+        Some(codec) => encoding.format.deserialize(&codec.decode(key, bytes)?),
+        None => encoding.format.deserialize(bytes),
+    }
+}
+
+/// TODO: Good description of why we're doing this : RocksDB stores keys in BE and has a seek operator on iterators, see https://github.com/facebook/rocksdb/wiki/Iterator#introduction
+#[inline]
+pub(crate) fn be_fix_int_ser<S>(t: &S) -> Result<Vec<u8>, TypedStoreError>
+where
+    S: ?Sized + serde::Serialize,
+{
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .serialize(t)
+        .map_err(|e| e.into())
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Versioned values, upgraded lazily on read.
+//!
+//! A `VersionedCodec` prefixes every value with the version of its schema. When the schema of a
+//! table changes, the new version is registered along with a function upgrading the values of
+//! the previous version: values are upgraded when they are read, so that the table can be served
+//! while it still contains values of several versions. Upgraded values can optionally be written
+//! back, so that the old versions eventually disappear from the table.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::ValueCodec;
+use crate::errors::TypedStoreError;
+
+/// The version of the schema of a value
+pub type ValueVersion = u32;
+
+const VERSION_LEN: usize = std::mem::size_of::<ValueVersion>();
+
+type Upgrade = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, TypedStoreError> + Send + Sync>;
+
+/// A value codec prefixing the values with their schema version, and upgrading the values of
+/// previous versions on read, see the module documentation.
+///
+/// The codec must be set on a table from its creation, as values without a version prefix
+/// can't be told apart from versioned ones.
+///
+/// ```
+/// use std::sync::Arc;
+/// use serde::{Deserialize, Serialize};
+/// use typed_store::rocks::*;
+/// use typed_store::Map;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct AccountV0 { balance: u32 }
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct AccountV1 { balance: u64, frozen: bool }
+///
+/// let path = tempfile::tempdir().unwrap();
+/// let db = DBMap::<u32, AccountV0>::open(&path, None, None).unwrap()
+///     .with_value_codec(Arc::new(VersionedCodec::new(0)));
+/// db.insert(&1, &AccountV0 { balance: 10 }).unwrap();
+///
+/// let codec = VersionedCodec::new(1).with_upgrade(0, |old: AccountV0| AccountV1 {
+///     balance: old.balance.into(),
+///     frozen: false,
+/// });
+/// let db = DBMap::<u32, AccountV1>::reopen(&db.rocksdb, None).unwrap()
+///     .with_value_codec(Arc::new(codec));
+/// assert_eq!(db.get(&1).unwrap(), Some(AccountV1 { balance: 10, frozen: false }));
+/// ```
+pub struct VersionedCodec {
+    version: ValueVersion,
+    upgrades: BTreeMap<ValueVersion, Upgrade>,
+    write_back: bool,
+}
+
+impl VersionedCodec {
+    /// Returns a codec writing values of version `version`, which can't read previous versions
+    /// until their upgrades are registered
+    pub fn new(version: ValueVersion) -> Self {
+        Self {
+            version,
+            upgrades: BTreeMap::new(),
+            write_back: false,
+        }
+    }
+
+    /// Registers the upgrade of the values of version `from` to version `from + 1`.
+    /// Values of older versions are upgraded through all the intermediate versions.
+    pub fn with_upgrade<Old, New>(
+        mut self,
+        from: ValueVersion,
+        upgrade: impl Fn(Old) -> New + Send + Sync + 'static,
+    ) -> Self
+    where
+        Old: DeserializeOwned,
+        New: Serialize,
+    {
+        assert!(
+            from < self.version,
+            "Can't register an upgrade from version {from}, which is not older than {}",
+            self.version
+        );
+        let upgrade = move |bytes: &[u8]| -> Result<Vec<u8>, TypedStoreError> {
+            Ok(bincode::serialize(&upgrade(bincode::deserialize(bytes)?))?)
+        };
+        self.upgrades.insert(from, Box::new(upgrade));
+        self
+    }
+
+    /// Lets `DBMap::rewrite_stale_values` write back the values of older versions, upgraded and
+    /// re-encoded with the current version. Reads upgrade the values without writing them back.
+    pub fn with_write_back(mut self) -> Self {
+        self.write_back = true;
+        self
+    }
+
+    /// The version of the values written by the codec
+    pub fn version(&self) -> ValueVersion {
+        self.version
+    }
+
+    fn split(bytes: &[u8]) -> Result<(ValueVersion, &[u8]), TypedStoreError> {
+        if bytes.len() < VERSION_LEN {
+            return Err(TypedStoreError::ValueVersionError(
+                "value is missing its version".to_owned(),
+            ));
+        }
+        let (version, value) = bytes.split_at(VERSION_LEN);
+        Ok((
+            ValueVersion::from_be_bytes(version.try_into().unwrap()),
+            value,
+        ))
+    }
+}
+
+impl fmt::Debug for VersionedCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionedCodec")
+            .field("version", &self.version)
+            .field("upgrades", &self.upgrades.keys().collect::<Vec<_>>())
+            .field("write_back", &self.write_back)
+            .finish()
+    }
+}
+
+impl ValueCodec for VersionedCodec {
+    fn encode(&self, _key: &[u8], value: Vec<u8>) -> Result<Vec<u8>, TypedStoreError> {
+        let mut bytes = Vec::with_capacity(VERSION_LEN + value.len());
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&value);
+        Ok(bytes)
+    }
+
+    fn decode(&self, _key: &[u8], bytes: &[u8]) -> Result<Vec<u8>, TypedStoreError> {
+        let (version, value) = Self::split(bytes)?;
+        if version > self.version {
+            return Err(TypedStoreError::ValueVersionError(format!(
+                "value of version {version} is newer than the current version {}",
+                self.version
+            )));
+        }
+        let mut value = value.to_vec();
+        for from in version..self.version {
+            let upgrade = self.upgrades.get(&from).ok_or_else(|| {
+                TypedStoreError::ValueVersionError(format!(
+                    "no upgrade registered from version {from}"
+                ))
+            })?;
+            value = upgrade(&value)?;
+        }
+        Ok(value)
+    }
+
+    fn rewrite_on_read(&self, bytes: &[u8]) -> bool {
+        self.write_back && matches!(Self::split(bytes), Ok((version, _)) if version < self.version)
+    }
+}
@@ -3,7 +3,11 @@
 
 use bincode::ErrorKind as BincodeErrorKind;
 
+#[cfg(feature = "rocks")]
 use rocksdb::Error as RocksError;
+
+#[cfg(feature = "rocks")]
+use crate::rocks::OpenFailureReport;
 use serde::{Deserialize, Serialize};
 use std::{fmt, fmt::Display};
 use thiserror::Error;
@@ -15,17 +19,74 @@ pub enum TypedStoreError {
     RocksDBError(String),
     #[error("(de)serialization error: {0}")]
     SerializationError(String),
+    #[error("I/O error: {0}")]
+    IoError(String),
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfiguration(String),
     #[error("the column family {0} was not registered with the database")]
     UnregisteredColumn(String),
     #[error("a batch operation can't operate across databases")]
     CrossDBBatch,
+    #[cfg(feature = "rocks")]
+    #[error("{0}")]
+    OpenFailure(Box<OpenFailureReport>),
+    #[error("the column family {0} is not an orphan and can't be dropped")]
+    NotAnOrphanColumn(String),
+    #[error("encryption error: {0}")]
+    EncryptionError(String),
+    #[error("the table {0} requires a value codec, which was not provided")]
+    MissingValueCodec(String),
+    #[error("the table {0} can't have a value codec")]
+    UnsupportedValueCodec(String),
+    #[error("value version error: {0}")]
+    ValueVersionError(String),
+    #[error("the column families of {path} don't match: unknown {unknown:?}, missing {missing:?}")]
+    ColumnFamilyMismatch {
+        path: String,
+        /// The column families of the database which were not given
+        unknown: Vec<String>,
+        /// The given column families which the database doesn't have yet
+        missing: Vec<String>,
+    },
+    #[error("the writes to {0} exceed its write limit")]
+    WriteThrottled(String),
+    #[error("the database was written by a newer typed-store {written_by}, {reason}: upgrade the binary, or restore a backup of the database taken before the upgrade")]
+    IncompatibleDatabase { written_by: String, reason: String },
+    #[error("the {option} of the table {table} can't be changed from {existing} to {requested}: {reason}")]
+    IncompatibleOptions {
+        table: String,
+        option: String,
+        existing: String,
+        requested: String,
+        reason: String,
+    },
+    #[error("the table {0} is frozen, and rejects writes")]
+    TableFrozen(String),
+    #[error("the keys loaded into {table} are not sorted: the key at position {position} is not greater than the previous one")]
+    UnsortedKeys { table: String, position: usize },
+    #[error("the index {index} is keyed by {expected}, which a {found} is not encoded as")]
+    IndexKeyMismatch {
+        index: String,
+        expected: String,
+        found: String,
+    },
+    #[error("the {entries} entries read by an iterator over {table} exceed its memory cap of {cap} bytes")]
+    IteratorMemoryCapExceeded {
+        table: String,
+        cap: usize,
+        entries: usize,
+    },
 }
 
+#[cfg(feature = "rocks")]
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash, Debug, Error)]
 pub(crate) struct RocksErrorDef {
     message: String,
 }
 
+#[cfg(feature = "rocks")]
 impl From<RocksError> for RocksErrorDef {
     fn from(err: RocksError) -> Self {
         RocksErrorDef {
@@ -34,12 +95,14 @@ impl From<RocksError> for RocksErrorDef {
     }
 }
 
+#[cfg(feature = "rocks")]
 impl From<RocksError> for TypedStoreError {
     fn from(err: RocksError) -> Self {
         TypedStoreError::RocksDBError(format!("{err}"))
     }
 }
 
+#[cfg(feature = "rocks")]
 impl Display for RocksErrorDef {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         self.message.fmt(formatter)
@@ -0,0 +1,43 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The readers of the tables exported by `typed_store::rocks::export_snapshot`.
+//!
+//! They don't depend on RocksDB, so that the exports can be loaded by tools built without the
+//! `rocks` feature, e.g. into an `InMemoryMap`.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::errors::TypedStoreError;
+
+pub(crate) fn io_error(path: &Path) -> impl Fn(std::io::Error) -> TypedStoreError + '_ {
+    move |e| TypedStoreError::IoError(format!("failed to access {path:?}: {e}"))
+}
+
+/// Reads the encoded entries of a table exported in the `ExportFormat::Raw` format: for every
+/// entry, the length of the key as a big endian u32, the key, and the same for the value
+pub fn read_raw_export(path: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>, TypedStoreError> {
+    let mut file = BufReader::new(File::open(path).map_err(io_error(path))?);
+    let mut read_bytes = |first: bool| -> Result<Option<Vec<u8>>, TypedStoreError> {
+        let mut len = [0u8; 4];
+        match file.read_exact(&mut len) {
+            Ok(()) => {}
+            // The file may only end before a key
+            Err(e) if first && e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(io_error(path)(e)),
+        }
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        file.read_exact(&mut bytes).map_err(io_error(path))?;
+        Ok(Some(bytes))
+    };
+    let mut entries = vec![];
+    while let Some(key) = read_bytes(true)? {
+        let value = read_bytes(false)?.expect("Values are always read");
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! HTTP routes exposing the `TypedStoreDebug` features of a read-only handle,
+//! to be nested in the admin server of a node:
+//!
+//! ```ignore
+//! let app = axum::Router::new().nest(
+//!     "/storage",
+//!     typed_store::http::debug_router(Tables::get_read_only_handle(path, None, None)),
+//! );
+//! ```
+//!
+//! - `GET /tables` lists the tables with their key and value types
+//! - `GET /tables/:name/dump?cursor=<page>&page_size=<size>` dumps a page of the table, of at most
+//!   `max_page_bytes` (`DEFAULT_DUMP_MAX_PAGE_BYTES` by default) with its values truncated to
+//!   `max_value_bytes`, if given
+//! - `GET /tables/:name/count` counts the keys of the table
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::traits::{DumpLimits, TypedStoreDebug};
+
+/// The page size used when the dump request does not specify one
+pub const DEFAULT_DUMP_PAGE_SIZE: u16 = 100;
+/// The size of the output of a page when the dump request does not specify one
+pub const DEFAULT_DUMP_MAX_PAGE_BYTES: usize = 16 << 20;
+
+type DebugHandle = Arc<dyn TypedStoreDebug + Send + Sync>;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DumpQuery {
+    /// The page to dump, starting at 0
+    #[serde(default)]
+    pub cursor: usize,
+    pub page_size: Option<u16>,
+    pub max_page_bytes: Option<usize>,
+    pub max_value_bytes: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DumpResponse {
+    pub entries: BTreeMap<String, String>,
+    /// The cursor of the next page, if this page was full
+    pub next_cursor: Option<usize>,
+    /// Whether the page stopped before its end at `max_page_bytes`, see `DumpPage`
+    pub exceeded_page_bytes: bool,
+    /// The number of values truncated to `max_value_bytes`
+    pub truncated_values: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountResponse {
+    pub count: usize,
+}
+
+/// Returns a router serving the debug endpoints of `handle`
+pub fn debug_router<T: TypedStoreDebug + Send + Sync + 'static>(handle: T) -> Router {
+    let handle: DebugHandle = Arc::new(handle);
+    Router::new()
+        .route("/tables", get(describe_tables))
+        .route("/tables/:name/dump", get(dump_table))
+        .route("/tables/:name/count", get(count_keys))
+        .layer(Extension(handle))
+}
+
+type HandlerError = (StatusCode, String);
+
+async fn describe_tables(
+    Extension(handle): Extension<DebugHandle>,
+) -> Json<BTreeMap<String, (String, String)>> {
+    Json(handle.describe_all_tables())
+}
+
+async fn dump_table(
+    Extension(handle): Extension<DebugHandle>,
+    Path(name): Path<String>,
+    Query(query): Query<DumpQuery>,
+) -> Result<Json<DumpResponse>, HandlerError> {
+    let page_size = query.page_size.unwrap_or(DEFAULT_DUMP_PAGE_SIZE);
+    let limits = DumpLimits {
+        max_page_bytes: Some(query.max_page_bytes.unwrap_or(DEFAULT_DUMP_MAX_PAGE_BYTES)),
+        max_value_bytes: query.max_value_bytes,
+    };
+    let page = scan(handle, name, move |handle, name| {
+        handle.dump_table_with_limits(name, page_size, query.cursor, &limits)
+    })
+    .await?;
+    let next_cursor = (page.entries.len() == page_size as usize).then(|| query.cursor + 1);
+    Ok(Json(DumpResponse {
+        entries: page.entries,
+        next_cursor,
+        exceeded_page_bytes: page.exceeded_page_bytes,
+        truncated_values: page.truncated_values,
+    }))
+}
+
+async fn count_keys(
+    Extension(handle): Extension<DebugHandle>,
+    Path(name): Path<String>,
+) -> Result<Json<CountResponse>, HandlerError> {
+    let count = scan(handle, name, |handle, name| handle.count_table_keys(name)).await?;
+    Ok(Json(CountResponse { count }))
+}
+
+/// Checks that the table exists and runs a scan of it on the blocking thread pool
+async fn scan<R, F>(handle: DebugHandle, name: String, f: F) -> Result<R, HandlerError>
+where
+    R: Send + 'static,
+    F: FnOnce(&dyn TypedStoreDebug, String) -> eyre::Result<R> + Send + 'static,
+{
+    if !handle.describe_all_tables().contains_key(&name) {
+        return Err((StatusCode::NOT_FOUND, format!("No such table name: {name}")));
+    }
+    tokio::task::spawn_blocking(move || f(handle.as_ref(), name))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
@@ -8,32 +8,68 @@
     rust_2021_compatibility
 )]
 
-use eyre::Result;
+//! Typed tables on top of RocksDB.
+//!
+//! Without the default `rocks` feature, only the parts which don't depend on RocksDB are
+//! built: the `Map` traits, the codecs of the keys and values, the `InMemoryMap` and the readers
+//! of the exported tables, so that lightweight tools (e.g. compiled to wasm) can decode the content
+//! of the tables.
+
+#[cfg(feature = "rocks")]
 use rocksdb::MultiThreaded;
+#[cfg(feature = "rocks")]
 use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "rocks")]
 use std::{
     cmp::Eq,
     collections::{HashMap, VecDeque},
     hash::Hash,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Instant,
 };
+#[cfg(feature = "rocks")]
 use tokio::sync::{
-    mpsc::{channel, Sender},
+    mpsc::{channel, error::SendError, Sender},
     oneshot,
 };
 
 pub mod traits;
 pub use traits::Map;
+#[cfg(feature = "archive-reader")]
+pub mod archive;
+pub mod codec;
+mod errors;
+pub use errors::TypedStoreError;
+pub mod export;
+mod memory;
+pub use memory::{InMemoryIter, InMemoryKeys, InMemoryMap, InMemoryValues};
+#[cfg(feature = "rocks")]
+pub mod metrics;
+#[cfg(feature = "rocks")]
 pub mod rocks;
 
-#[cfg(test)]
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(any(feature = "testing", all(test, feature = "rocks")))]
+pub mod testing;
+#[cfg(feature = "cli")]
+pub mod cli;
+
+#[cfg(all(test, feature = "rocks"))]
 #[path = "tests/store_tests.rs"]
 pub mod store_tests;
 
-pub type StoreError = rocks::TypedStoreError;
+pub type StoreError = errors::TypedStoreError;
 
+#[cfg(feature = "rocks")]
 type StoreResult<T> = Result<T, StoreError>;
 
+#[cfg(feature = "rocks")]
 pub enum StoreCommand<Key, Value> {
     Write(Key, Value),
     WriteAll(Vec<(Key, Value)>, oneshot::Sender<StoreResult<()>>),
@@ -49,12 +85,75 @@ pub enum StoreCommand<Key, Value> {
     ),
 }
 
+/// A command sent to the worker of a `Store`, with the time it was sent at
+#[cfg(feature = "rocks")]
+struct QueuedCommand<Key, Value> {
+    sent_at: Instant,
+    command: StoreCommand<Key, Value>,
+}
+
+/// The commands sent to the worker of a `Store` and not processed by it yet
+#[cfg(feature = "rocks")]
+#[derive(Default)]
+struct StoreQueue {
+    /// The number of commands sent and not processed yet, including the one being processed
+    pending: AtomicUsize,
+    /// The time the command being processed was sent at. The commands are processed in the order
+    /// they are sent in, so it is the oldest pending one
+    processing: Mutex<Option<Instant>>,
+}
+
+#[cfg(feature = "rocks")]
+impl StoreQueue {
+    fn processing(&self) -> MutexGuard<'_, Option<Instant>> {
+        self.processing
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    fn oldest_age_seconds(&self) -> f64 {
+        self.processing()
+            .map_or(0.0, |sent_at| sent_at.elapsed().as_secs_f64())
+    }
+
+    /// Records that the worker starts processing the command sent at `sent_at`
+    fn start(&self, sent_at: Instant) {
+        *self.processing() = Some(sent_at);
+    }
+
+    /// Records that the worker processed the command it started
+    fn processed(&self) {
+        *self.processing() = None;
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Unrecords a command whose send was cancelled or failed
+#[cfg(feature = "rocks")]
+struct PendingSend<'a> {
+    queue: &'a StoreQueue,
+}
+
+#[cfg(feature = "rocks")]
+impl Drop for PendingSend<'_> {
+    fn drop(&mut self) {
+        self.queue.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "rocks")]
 #[derive(Clone)]
 pub struct Store<K, V> {
-    channel: Sender<StoreCommand<K, V>>,
+    channel: Sender<QueuedCommand<K, V>>,
+    queue: Arc<StoreQueue>,
     pub rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
 }
 
+#[cfg(feature = "rocks")]
 impl<Key, Value> Store<Key, Value>
 where
     Key: Hash + Eq + Serialize + DeserializeOwned + Send + 'static,
@@ -63,9 +162,12 @@ where
     pub fn new(keyed_db: rocks::DBMap<Key, Value>) -> Self {
         let mut obligations = HashMap::<Key, VecDeque<oneshot::Sender<_>>>::new();
         let clone_db = keyed_db.rocksdb.clone();
+        let queue = Arc::new(StoreQueue::default());
+        let worker_queue = queue.clone();
         let (tx, mut rx) = channel(100);
         tokio::spawn(async move {
-            while let Some(command) = rx.recv().await {
+            while let Some(QueuedCommand { sent_at, command }) = rx.recv().await {
+                worker_queue.start(sent_at);
                 match command {
                     StoreCommand::Write(key, value) => {
                         let _ = keyed_db.insert(&key, &value);
@@ -146,22 +248,79 @@ where
                         let _ = sender.send(response);
                     }
                 }
+                worker_queue.processed();
             }
         });
         Self {
             channel: tx,
+            queue,
             rocksdb: clone_db,
         }
     }
+
+    /// Creates a store exporting the state of its command queue to `registry`, labelled by
+    /// `store_name`:
+    /// - `store_pending_commands`, the number of commands sent to the store and not processed yet
+    /// - `store_oldest_pending_command_age_seconds`, how long ago the oldest of them, the one being
+    ///   processed, was sent, 0 if there is none
+    pub fn new_with_metrics(
+        keyed_db: rocks::DBMap<Key, Value>,
+        store_name: &str,
+        registry: &prometheus::Registry,
+    ) -> Self {
+        let store = Self::new(keyed_db);
+        let labels = [("store", store_name)];
+        let queue = store.queue.clone();
+        let pending = metrics::ClosureGauge::register(
+            "store_pending_commands",
+            "The number of commands sent to a store and not processed yet",
+            &labels,
+            move || queue.pending() as f64,
+            registry,
+        );
+        let queue = store.queue.clone();
+        let oldest = metrics::ClosureGauge::register(
+            "store_oldest_pending_command_age_seconds",
+            "How long ago the oldest pending command of a store was sent, 0 if there is none",
+            &labels,
+            move || queue.oldest_age_seconds(),
+            registry,
+        );
+        if let Err(e) = pending.and(oldest) {
+            tracing::warn!("unable to register the metrics of store {store_name}: {e}");
+        }
+        store
+    }
 }
 
+#[cfg(feature = "rocks")]
 impl<Key, Value> Store<Key, Value>
 where
     Key: Serialize + DeserializeOwned + Send,
     Value: Serialize + DeserializeOwned + Send,
 {
+    async fn send(
+        &self,
+        command: StoreCommand<Key, Value>,
+    ) -> Result<(), SendError<StoreCommand<Key, Value>>> {
+        // Recorded before waiting for room in the channel, so that a full channel shows up too
+        let sent_at = Instant::now();
+        self.queue.pending.fetch_add(1, Ordering::Relaxed);
+        let guard = PendingSend { queue: &self.queue };
+        let result = self
+            .channel
+            .send(QueuedCommand { sent_at, command })
+            .await
+            .map_err(|SendError(queued)| SendError(queued.command));
+        if result.is_ok() {
+            // Unrecorded by the worker once processed
+            std::mem::forget(guard);
+        }
+        result
+    }
+
     pub async fn write(&self, key: Key, value: Value) {
-        if let Err(e) = self.channel.send(StoreCommand::Write(key, value)).await {
+        if let Err(e) = self.send(StoreCommand::Write(key, value)).await {
             panic!("Failed to send Write command to store: {e}");
         }
     }
@@ -175,7 +334,6 @@ where
     ) -> StoreResult<()> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self
-            .channel
             .send(StoreCommand::WriteAll(
                 key_value_pairs.into_iter().collect(),
                 sender,
@@ -190,7 +348,7 @@ where
     }
 
     pub async fn remove(&self, key: Key) {
-        if let Err(e) = self.channel.send(StoreCommand::Delete(key)).await {
+        if let Err(e) = self.send(StoreCommand::Delete(key)).await {
             panic!("Failed to send Delete command to store: {e}");
         }
     }
@@ -201,7 +359,6 @@ where
     pub async fn remove_all(&self, keys: impl IntoIterator<Item = Key>) -> StoreResult<()> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self
-            .channel
             .send(StoreCommand::DeleteAll(keys.into_iter().collect(), sender))
             .await
         {
@@ -215,11 +372,7 @@ where
     /// Returns the read value in raw bincode bytes
     pub async fn read_raw_bytes(&self, key: Key) -> StoreResult<Option<Vec<u8>>> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self
-            .channel
-            .send(StoreCommand::ReadRawBytes(key, sender))
-            .await
-        {
+        if let Err(e) = self.send(StoreCommand::ReadRawBytes(key, sender)).await {
             panic!("Failed to send ReadRawBytes command to store: {e}");
         }
         receiver
@@ -229,7 +382,7 @@ where
 
     pub async fn read(&self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
+        if let Err(e) = self.send(StoreCommand::Read(key, sender)).await {
             panic!("Failed to send Read command to store: {e}");
         }
         receiver
@@ -244,7 +397,6 @@ where
     ) -> StoreResult<Vec<Option<Value>>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self
-            .channel
             .send(StoreCommand::ReadAll(keys.into_iter().collect(), sender))
             .await
         {
@@ -257,11 +409,7 @@ where
 
     pub async fn notify_read(&self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self
-            .channel
-            .send(StoreCommand::NotifyRead(key, sender))
-            .await
-        {
+        if let Err(e) = self.send(StoreCommand::NotifyRead(key, sender)).await {
             panic!("Failed to send NotifyRead command to store: {e}");
         }
         receiver
@@ -274,11 +422,7 @@ where
         predicate: Option<Box<dyn Fn(&(Key, Value)) -> bool + Send>>,
     ) -> HashMap<Key, Value> {
         let (sender, receiver) = oneshot::channel();
-        if let Err(e) = self
-            .channel
-            .send(StoreCommand::Iter(predicate, sender))
-            .await
-        {
+        if let Err(e) = self.send(StoreCommand::Iter(predicate, sender)).await {
             panic!("Failed to send Iter command to store: {e}");
         }
         receiver
@@ -0,0 +1,272 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    vec,
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    codec::{be_fix_int_ser, decode_value, encode_value, ValueCodec, ValueEncoding},
+    errors::TypedStoreError,
+    traits::Map,
+};
+
+type Entries = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A `Map` kept in memory, which doesn't require RocksDB.
+///
+/// Keys and values are encoded like in a `DBMap`, so that the two iterate in the same order
+/// and the encoded entries of a table can be loaded as is, e.g. by analysis tools built
+/// without the `rocks` feature. Clones of the map share its content.
+///
+/// ```
+/// use typed_store::{InMemoryMap, Map};
+/// let map = InMemoryMap::<u32, String>::new();
+/// map.multi_insert([(2, "b".to_owned()), (1, "a".to_owned())]).unwrap();
+/// assert_eq!(map.keys().collect::<Vec<_>>(), vec![1, 2]);
+/// ```
+pub struct InMemoryMap<K, V> {
+    entries: Arc<RwLock<Entries>>,
+    value_codec: Option<Arc<dyn ValueCodec>>,
+    _phantom: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> Clone for InMemoryMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            value_codec: self.value_codec.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Default for InMemoryMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+            value_codec: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<K, V> InMemoryMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a map holding already encoded entries, e.g. the raw entries of an exported table
+    pub fn from_encoded(entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(entries.into_iter().collect())),
+            ..Self::default()
+        }
+    }
+
+    /// Returns a map whose values are transformed by `codec` after serialization, like
+    /// `DBMap::with_value_codec`
+    pub fn with_value_codec(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        self.value_codec = Some(codec);
+        self
+    }
+
+    fn codec(&self) -> Option<&dyn ValueCodec> {
+        self.value_codec.as_deref()
+    }
+
+    /// The values are always serialized with bincode
+    fn encoding(&self) -> ValueEncoding<'_> {
+        ValueEncoding {
+            codec: self.codec(),
+            ..ValueEncoding::default()
+        }
+    }
+
+    /// Returns a copy of the encoded entries, in order
+    fn snapshot(&self) -> vec::IntoIter<(Vec<u8>, Vec<u8>)> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+/// An iterator over the entries of an `InMemoryMap`, as of its creation
+pub struct InMemoryIter<K, V> {
+    entries: vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    codec: Option<Arc<dyn ValueCodec>>,
+    _phantom: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Iterator for InMemoryIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.entries.next()?;
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        let encoding = ValueEncoding {
+            codec: self.codec.as_deref(),
+            ..ValueEncoding::default()
+        };
+        let value = decode_value(encoding, &key, &value).ok()?;
+        let key = config.deserialize(&key).ok()?;
+        Some((key, value))
+    }
+}
+
+/// An iterator over the keys of an `InMemoryMap`
+pub struct InMemoryKeys<K, V>(InMemoryIter<K, V>);
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Iterator for InMemoryKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+}
+
+/// An iterator over the values of an `InMemoryMap`
+pub struct InMemoryValues<K, V>(InMemoryIter<K, V>);
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Iterator for InMemoryValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+}
+
+impl<'a, K, V> Map<'a, K, V> for InMemoryMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    type Error = TypedStoreError;
+    type Iterator = InMemoryIter<K, V>;
+    type Keys = InMemoryKeys<K, V>;
+    type Values = InMemoryValues<K, V>;
+
+    fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        Ok(self.entries.read().unwrap().contains_key(&key_buf))
+    }
+
+    fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        match self.entries.read().unwrap().get(&key_buf) {
+            Some(data) => Ok(Some(decode_value(self.encoding(), &key_buf, data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_raw_bytes(&self, key: &K) -> Result<Option<Vec<u8>>, TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        match self.entries.read().unwrap().get(&key_buf) {
+            Some(data) => match self.codec() {
+                Some(codec) => Ok(Some(codec.decode(&key_buf, data)?)),
+                None => Ok(Some(data.clone())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, key: &K, value: &V) -> Result<(), TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        let value_buf = encode_value(self.encoding(), &key_buf, value)?;
+        self.entries.write().unwrap().insert(key_buf, value_buf);
+        Ok(())
+    }
+
+    fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        self.entries.write().unwrap().remove(&key_buf);
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<(), TypedStoreError> {
+        self.entries.write().unwrap().clear();
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.read().unwrap().is_empty()
+    }
+
+    fn iter(&'a self) -> Self::Iterator {
+        InMemoryIter {
+            entries: self.snapshot(),
+            codec: self.value_codec.clone(),
+            _phantom: PhantomData,
+        }
+    }
+
+    fn keys(&'a self) -> Self::Keys {
+        InMemoryKeys(self.iter())
+    }
+
+    fn values(&'a self) -> Self::Values {
+        InMemoryValues(self.iter())
+    }
+
+    fn multi_get<J>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<Option<V>>, TypedStoreError>
+    where
+        J: Borrow<K>,
+    {
+        keys.into_iter().map(|key| self.get(key.borrow())).collect()
+    }
+
+    /// Inserts the key-value pairs atomically
+    fn multi_insert<J, U>(
+        &self,
+        key_val_pairs: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<(), TypedStoreError>
+    where
+        J: Borrow<K>,
+        U: Borrow<V>,
+    {
+        let encoded = key_val_pairs
+            .into_iter()
+            .map(|(k, v)| {
+                let key_buf = be_fix_int_ser(k.borrow())?;
+                let value_buf = encode_value(self.encoding(), &key_buf, v.borrow())?;
+                Ok((key_buf, value_buf))
+            })
+            .collect::<Result<Vec<_>, TypedStoreError>>()?;
+        self.entries.write().unwrap().extend(encoded);
+        Ok(())
+    }
+
+    /// Removes the keys atomically
+    fn multi_remove<J>(&self, keys: impl IntoIterator<Item = J>) -> Result<(), TypedStoreError>
+    where
+        J: Borrow<K>,
+    {
+        let encoded = keys
+            .into_iter()
+            .map(|k| be_fix_int_ser(k.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut entries = self.entries.write().unwrap();
+        for key in encoded {
+            entries.remove(&key);
+        }
+        Ok(())
+    }
+
+    fn try_catch_up_with_primary(&self) -> Result<(), TypedStoreError> {
+        Ok(())
+    }
+}
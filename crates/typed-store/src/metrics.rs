@@ -0,0 +1,441 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Process-wide storage metrics.
+//!
+//! All the `DBMap`s of a process report into a single `DBMetrics` instance, labelled by
+//! `db_name` (the name of the table struct, or the directory name of the database) and
+//! `cf_name` (the table name), so that several table structs share one metrics namespace.
+//!
+//! `DBMetrics::init` must be called once at startup, before opening any database. Otherwise
+//! metrics are recorded in a private registry which is never exported, and `init` fails.
+
+use std::sync::Arc;
+
+use once_cell::sync::OnceCell;
+use prometheus::{
+    core::{Collector, Desc},
+    exponential_buckets, register_gauge_vec_with_registry, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, Gauge, GaugeVec,
+    HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+};
+
+static DB_METRICS: OnceCell<Arc<DBMetrics>> = OnceCell::new();
+
+const LATENCY_SEC_BUCKETS: &[f64] = &[
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1., 5., 10.,
+];
+const READ_AMP_BUCKETS: &[f64] = &[0., 1., 2., 3., 4., 6., 8., 12., 16., 24., 32., 64.];
+const PINNED_SEC_BUCKETS: &[f64] = &[
+    0.001, 0.01, 0.1, 1., 10., 30., 60., 300., 600., 1800., 3600., 10800.,
+];
+
+/// Metrics of the reads and writes performed through `DBMap`s
+pub struct OperationMetrics {
+    pub rocksdb_get_latency_seconds: HistogramVec,
+    pub rocksdb_get_bytes: HistogramVec,
+    pub rocksdb_multiget_latency_seconds: HistogramVec,
+    pub rocksdb_multiget_bytes: HistogramVec,
+    pub rocksdb_put_latency_seconds: HistogramVec,
+    pub rocksdb_put_bytes: HistogramVec,
+    pub rocksdb_delete_latency_seconds: HistogramVec,
+    pub rocksdb_batch_commit_latency_seconds: HistogramVec,
+    pub rocksdb_batch_commit_bytes: HistogramVec,
+    pub rocksdb_iter_pinned_seconds: HistogramVec,
+    pub rocksdb_iter_memory_cap_exceeded: IntCounterVec,
+    pub rocksdb_throttled_writes: IntCounterVec,
+    pub rocksdb_batch_label_bytes: IntCounterVec,
+    pub rocksdb_read_amp_sst_files: HistogramVec,
+    pub rocksdb_read_amp_blocks_read: HistogramVec,
+}
+
+impl OperationMetrics {
+    fn new(registry: &Registry) -> Self {
+        let bytes_buckets = exponential_buckets(1.0, 4.0, 15).unwrap();
+        OperationMetrics {
+            rocksdb_get_latency_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_get_latency_seconds",
+                "The get latency of a table",
+                &["db_name", "cf_name"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_get_bytes: register_histogram_vec_with_registry!(
+                "rocksdb_get_bytes",
+                "The size of the values read by get in a table",
+                &["db_name", "cf_name"],
+                bytes_buckets.clone(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_multiget_latency_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_multiget_latency_seconds",
+                "The multi_get latency of a table",
+                &["db_name", "cf_name"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_multiget_bytes: register_histogram_vec_with_registry!(
+                "rocksdb_multiget_bytes",
+                "The total size of the values read by multi_get in a table",
+                &["db_name", "cf_name"],
+                bytes_buckets.clone(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_put_latency_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_put_latency_seconds",
+                "The put latency of a table",
+                &["db_name", "cf_name"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_put_bytes: register_histogram_vec_with_registry!(
+                "rocksdb_put_bytes",
+                "The size of the key and value written by put in a table",
+                &["db_name", "cf_name"],
+                bytes_buckets.clone(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_delete_latency_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_delete_latency_seconds",
+                "The delete latency of a table",
+                &["db_name", "cf_name"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_batch_commit_latency_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_batch_commit_latency_seconds",
+                "The latency of committing a write batch to a database",
+                &["db_name"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_batch_commit_bytes: register_histogram_vec_with_registry!(
+                "rocksdb_batch_commit_bytes",
+                "The encoded size of the write batches committed to a database",
+                &["db_name"],
+                bytes_buckets,
+                registry
+            )
+            .unwrap(),
+            rocksdb_iter_pinned_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_iter_pinned_seconds",
+                "How long iterators over a table pinned its state, preventing the deletion of compacted files",
+                &["db_name", "cf_name"],
+                PINNED_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_iter_memory_cap_exceeded: register_int_counter_vec_with_registry!(
+                "rocksdb_iter_memory_cap_exceeded",
+                "The number of iterators over a table stopped for reading more entries than their memory cap",
+                &["db_name", "cf_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_throttled_writes: register_int_counter_vec_with_registry!(
+                "rocksdb_throttled_writes",
+                "The number of writes to a table delayed or rejected by its write limit",
+                &["db_name", "cf_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_batch_label_bytes: register_int_counter_vec_with_registry!(
+                "rocksdb_batch_label_bytes",
+                "The encoded size of the batches committed to a database, by label of the operation writing them",
+                &["db_name", "label"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_read_amp_sst_files: register_histogram_vec_with_registry!(
+                "rocksdb_read_amp_sst_files",
+                "The number of SST files consulted by the sampled gets of a table",
+                &["db_name", "cf_name"],
+                READ_AMP_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_read_amp_blocks_read: register_histogram_vec_with_registry!(
+                "rocksdb_read_amp_blocks_read",
+                "The number of blocks read from the SST files by the sampled gets of a table",
+                &["db_name", "cf_name"],
+                READ_AMP_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Metrics exported from the RocksDB statistics and properties, see `typed_store::rocks::statistics`
+pub struct RocksDBStatsMetrics {
+    pub rocksdb_ticker: IntGaugeVec,
+    pub rocksdb_histogram: GaugeVec,
+    pub rocksdb_cf_property: IntGaugeVec,
+    pub rocksdb_block_cache_hit_rate: GaugeVec,
+    pub rocksdb_oldest_snapshot_age_seconds: IntGaugeVec,
+}
+
+impl RocksDBStatsMetrics {
+    fn new(registry: &Registry) -> Self {
+        RocksDBStatsMetrics {
+            rocksdb_ticker: register_int_gauge_vec_with_registry!(
+                "rocksdb_ticker",
+                "The value of a RocksDB statistics ticker since the database was opened",
+                &["db_name", "ticker"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_histogram: register_gauge_vec_with_registry!(
+                "rocksdb_histogram",
+                "A quantile of a RocksDB statistics histogram since the database was opened",
+                &["db_name", "histogram", "quantile"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_cf_property: register_int_gauge_vec_with_registry!(
+                "rocksdb_cf_property",
+                "The value of a RocksDB integer property of a table",
+                &["db_name", "cf_name", "property"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_block_cache_hit_rate: register_gauge_vec_with_registry!(
+                "rocksdb_block_cache_hit_rate",
+                "The ratio of block cache hits over block cache accesses since the database was opened",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_oldest_snapshot_age_seconds: register_int_gauge_vec_with_registry!(
+                "rocksdb_oldest_snapshot_age_seconds",
+                "The age of the oldest snapshot of a database, 0 if there is none",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Metrics of the background events detected on the databases, see `typed_store::rocks::events`
+pub struct RocksDBEventMetrics {
+    pub rocksdb_flushes_completed: IntCounterVec,
+    pub rocksdb_compactions_completed: IntCounterVec,
+    pub rocksdb_background_errors: IntGaugeVec,
+    pub rocksdb_write_stall: IntGaugeVec,
+}
+
+impl RocksDBEventMetrics {
+    fn new(registry: &Registry) -> Self {
+        RocksDBEventMetrics {
+            rocksdb_flushes_completed: register_int_counter_vec_with_registry!(
+                "rocksdb_flushes_completed",
+                "The number of memtable flushes completed, approximate unless the database has statistics enabled",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_compactions_completed: register_int_counter_vec_with_registry!(
+                "rocksdb_compactions_completed",
+                "The number of compactions completed, approximate unless the database has statistics enabled",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_background_errors: register_int_gauge_vec_with_registry!(
+                "rocksdb_background_errors",
+                "The number of background errors since the database was opened",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_write_stall: register_int_gauge_vec_with_registry!(
+                "rocksdb_write_stall",
+                "The write stall condition of a database: 0 when normal, 1 when delayed, 2 when stopped",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Metrics of the opens, flushes, compactions and drops of the tables, see `typed_store::rocks::TableLifecycleEvent`
+pub struct RocksDBLifecycleMetrics {
+    pub rocksdb_table_lifecycle_events: IntCounterVec,
+    pub rocksdb_table_lifecycle_seconds: HistogramVec,
+    pub rocksdb_table_lifecycle_size_bytes: IntGaugeVec,
+}
+
+impl RocksDBLifecycleMetrics {
+    fn new(registry: &Registry) -> Self {
+        RocksDBLifecycleMetrics {
+            rocksdb_table_lifecycle_events: register_int_counter_vec_with_registry!(
+                "rocksdb_table_lifecycle_events",
+                "The number of times a table was opened, flushed, compacted or dropped",
+                &["db_name", "cf_name", "event"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_table_lifecycle_seconds: register_histogram_vec_with_registry!(
+                "rocksdb_table_lifecycle_seconds",
+                "The duration of the opens, flushes, compactions and drops of a table",
+                &["db_name", "cf_name", "event"],
+                LATENCY_SEC_BUCKETS.to_vec(),
+                registry
+            )
+            .unwrap(),
+            rocksdb_table_lifecycle_size_bytes: register_int_gauge_vec_with_registry!(
+                "rocksdb_table_lifecycle_size_bytes",
+                "The size of a table after its last open, flush or compaction, or before its drop",
+                &["db_name", "cf_name", "event"],
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Metrics of the disk usage of the databases, see `typed_store::rocks::check_disk_quota`
+pub struct RocksDBQuotaMetrics {
+    pub rocksdb_disk_usage_bytes: IntGaugeVec,
+    pub rocksdb_disk_quota_exceeded: IntCounterVec,
+}
+
+impl RocksDBQuotaMetrics {
+    fn new(registry: &Registry) -> Self {
+        RocksDBQuotaMetrics {
+            rocksdb_disk_usage_bytes: register_int_gauge_vec_with_registry!(
+                "rocksdb_disk_usage_bytes",
+                "The size of all the files of a database",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+            rocksdb_disk_quota_exceeded: register_int_counter_vec_with_registry!(
+                "rocksdb_disk_quota_exceeded",
+                "The number of checks finding a table (or the whole database if empty) over its disk quota",
+                &["db_name", "cf_name"],
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Metrics of the RocksDB instances opened by the process
+pub struct RocksDBInstanceMetrics {
+    pub rocksdb_secondary_instances: IntGaugeVec,
+}
+
+impl RocksDBInstanceMetrics {
+    fn new(registry: &Registry) -> Self {
+        RocksDBInstanceMetrics {
+            rocksdb_secondary_instances: register_int_gauge_vec_with_registry!(
+                "rocksdb_secondary_instances",
+                "The number of read only handles of a database open on a managed secondary path",
+                &["db_name"],
+                registry
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// A gauge whose value is computed by a closure when the metrics are gathered, for the values
+/// which are cheaper to read on demand than to keep up to date, e.g. the state of a queue
+pub struct ClosureGauge<F> {
+    gauge: Gauge,
+    value: F,
+}
+
+impl<F: Fn() -> f64 + Send + Sync> ClosureGauge<F> {
+    pub fn new(opts: Opts, value: F) -> prometheus::Result<Self> {
+        Ok(ClosureGauge {
+            gauge: Gauge::with_opts(opts)?,
+            value,
+        })
+    }
+
+    /// Registers a gauge named `name`, labelled by the constant `labels`, in `registry`
+    pub fn register(
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+        value: F,
+        registry: &Registry,
+    ) -> prometheus::Result<()>
+    where
+        F: 'static,
+    {
+        let opts = labels
+            .iter()
+            .fold(Opts::new(name, help), |opts, (label, value)| {
+                opts.const_label(*label, *value)
+            });
+        registry.register(Box::new(ClosureGauge::new(opts, value)?))
+    }
+}
+
+impl<F: Fn() -> f64 + Send + Sync> Collector for ClosureGauge<F> {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        self.gauge.set((self.value)());
+        self.gauge.collect()
+    }
+}
+
+/// The storage metrics shared by all the databases of the process
+pub struct DBMetrics {
+    pub op_metrics: OperationMetrics,
+    pub stats_metrics: RocksDBStatsMetrics,
+    pub event_metrics: RocksDBEventMetrics,
+    pub lifecycle_metrics: RocksDBLifecycleMetrics,
+    pub quota_metrics: RocksDBQuotaMetrics,
+    pub instance_metrics: RocksDBInstanceMetrics,
+}
+
+impl DBMetrics {
+    fn new(registry: &Registry) -> Self {
+        DBMetrics {
+            op_metrics: OperationMetrics::new(registry),
+            stats_metrics: RocksDBStatsMetrics::new(registry),
+            event_metrics: RocksDBEventMetrics::new(registry),
+            lifecycle_metrics: RocksDBLifecycleMetrics::new(registry),
+            quota_metrics: RocksDBQuotaMetrics::new(registry),
+            instance_metrics: RocksDBInstanceMetrics::new(registry),
+        }
+    }
+
+    /// Initializes the global metrics, registering them in `registry`. Fails with
+    /// `prometheus::Error::AlreadyReg` if the metrics are already initialized, by an earlier call
+    /// or by `get`, e.g. when opening a table: they are then registered in another registry
+    pub fn init(registry: &Registry) -> prometheus::Result<&'static Arc<DBMetrics>> {
+        let mut initialized = false;
+        let metrics = DB_METRICS.get_or_init(|| {
+            initialized = true;
+            Arc::new(DBMetrics::new(registry))
+        });
+        if initialized {
+            Ok(metrics)
+        } else {
+            Err(prometheus::Error::AlreadyReg)
+        }
+    }
+
+    /// Returns the global metrics, initializing them in a private registry if `init` was not called
+    pub fn get() -> &'static Arc<DBMetrics> {
+        DB_METRICS.get_or_init(|| Arc::new(DBMetrics::new(&Registry::new())))
+    }
+}
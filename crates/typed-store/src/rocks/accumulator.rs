@@ -0,0 +1,241 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+
+use super::{codec::ValueCodec, filters::total_order_read_options, ContentDigest, TypedStoreError};
+
+/// A multiset hash of the entries of a table, which can be updated entry by entry.
+///
+/// Each entry is hashed with blake2b256 over its encoded key and serialized value, and the
+/// accumulator is the sum of these hashes modulo 2^256. Adding and removing entries commute,
+/// so two tables with the same entries have the same accumulator regardless of the order of
+/// the writes which led to them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Accumulator([u64; 4]);
+
+impl Accumulator {
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        let hash = Self::hash_entry(key, value);
+        let mut carry = false;
+        for (limb, h) in self.0.iter_mut().zip(hash) {
+            let (sum, c1) = limb.overflowing_add(h);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8], value: &[u8]) {
+        let hash = Self::hash_entry(key, value);
+        let mut borrow = false;
+        for (limb, h) in self.0.iter_mut().zip(hash) {
+            let (diff, b1) = limb.overflowing_sub(h);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+    }
+
+    pub fn digest(&self) -> ContentDigest {
+        let mut digest = [0u8; 32];
+        for (chunk, limb) in digest.chunks_exact_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        digest
+    }
+
+    fn hash_entry(key: &[u8], value: &[u8]) -> [u64; 4] {
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update(value);
+        let hash = hasher.finalize();
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(hash.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        limbs
+    }
+}
+
+/// Computes the accumulator of all the entries of the table `cf_name`
+pub(crate) fn accumulate_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    codec: Option<&dyn ValueCodec>,
+) -> Result<Accumulator, TypedStoreError> {
+    let cf = rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+    let mut accumulator = Accumulator::default();
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, total_order_read_options());
+    db_iter.seek_to_first();
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        match codec {
+            Some(codec) => accumulator.insert(key, &codec.decode(key, value)?),
+            None => accumulator.insert(key, value),
+        }
+        db_iter.next();
+    }
+    db_iter.status()?;
+    Ok(accumulator)
+}
+
+/// The accumulator of a table, with what is needed to read the previous values of its entries
+#[derive(Clone, Debug)]
+pub(crate) struct AccumulatedTable {
+    pub(crate) accumulator: Arc<Mutex<Accumulator>>,
+    pub(crate) cf_name: String,
+    pub(crate) codec: Option<Arc<dyn ValueCodec>>,
+}
+
+#[derive(Debug)]
+enum AccumulatedOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+    DeleteRange { from: Vec<u8>, to: Vec<u8> },
+}
+
+/// The writes of a batch to accumulated tables, applied to their accumulators when the batch is written
+#[derive(Debug, Default)]
+pub(crate) struct AccumulatorUpdates {
+    ops: Vec<(AccumulatedTable, AccumulatedOp)>,
+}
+
+impl AccumulatorUpdates {
+    /// Records the put of `value`, serialized but not encoded by the codec of the table
+    pub(crate) fn put(&mut self, table: &AccumulatedTable, key: Vec<u8>, value: Vec<u8>) {
+        self.ops
+            .push((table.clone(), AccumulatedOp::Put { key, value }));
+    }
+
+    pub(crate) fn delete(&mut self, table: &AccumulatedTable, key: Vec<u8>) {
+        self.ops
+            .push((table.clone(), AccumulatedOp::Delete { key }));
+    }
+
+    pub(crate) fn delete_range(&mut self, table: &AccumulatedTable, from: Vec<u8>, to: Vec<u8>) {
+        self.ops
+            .push((table.clone(), AccumulatedOp::DeleteRange { from, to }));
+    }
+
+    /// Performs `write` while holding the locks of the accumulators, and updates them if it succeeds.
+    /// The previous values of the written entries are read from the database before `write`.
+    pub(crate) fn write<F: FnOnce() -> Result<(), TypedStoreError>>(
+        self,
+        rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+        write: F,
+    ) -> Result<(), TypedStoreError> {
+        if self.ops.is_empty() {
+            return write();
+        }
+
+        // Locks are always taken in the same order so that concurrent batches can't deadlock
+        let mut accumulators: Vec<_> = self
+            .ops
+            .iter()
+            .map(|(table, _)| table.accumulator.clone())
+            .collect();
+        accumulators.sort_by_key(Arc::as_ptr);
+        accumulators.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        let mut guards: Vec<_> = accumulators
+            .iter()
+            .map(|a| a.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+            .collect();
+        let mut updated: Vec<Accumulator> = guards.iter().map(|guard| **guard).collect();
+
+        // The values written by the previous operations of the batch
+        let mut overlay: HashMap<(String, Vec<u8>), Option<Vec<u8>>> = HashMap::new();
+        for (table, op) in &self.ops {
+            let index = accumulators
+                .iter()
+                .position(|a| Arc::ptr_eq(a, &table.accumulator))
+                .expect("All the accumulators are locked");
+            let accumulator = &mut updated[index];
+            let keys = match op {
+                AccumulatedOp::Put { key, .. } | AccumulatedOp::Delete { key } => vec![key.clone()],
+                AccumulatedOp::DeleteRange { from, to } => {
+                    range_keys(rocksdb, table, &overlay, from, to)?
+                }
+            };
+            for key in keys {
+                if let Some(previous) = previous_value(rocksdb, table, &overlay, &key)? {
+                    accumulator.remove(&key, &previous);
+                }
+                let value = match op {
+                    AccumulatedOp::Put { value, .. } => {
+                        accumulator.insert(&key, value);
+                        Some(value.clone())
+                    }
+                    _ => None,
+                };
+                overlay.insert((table.cf_name.clone(), key), value);
+            }
+        }
+
+        write()?;
+        for (guard, accumulator) in guards.iter_mut().zip(updated) {
+            **guard = accumulator;
+        }
+        Ok(())
+    }
+}
+
+fn previous_value(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &AccumulatedTable,
+    overlay: &HashMap<(String, Vec<u8>), Option<Vec<u8>>>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, TypedStoreError> {
+    if let Some(value) = overlay.get(&(table.cf_name.clone(), key.to_vec())) {
+        return Ok(value.clone());
+    }
+    let cf = rocksdb
+        .cf_handle(&table.cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.cf_name.clone()))?;
+    match rocksdb.get_cf(&cf, key)? {
+        Some(value) => match &table.codec {
+            Some(codec) => Ok(Some(codec.decode(key, &value)?)),
+            None => Ok(Some(value)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The keys in `[from, to)`, either in the database or written by the previous operations of the batch
+fn range_keys(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &AccumulatedTable,
+    overlay: &HashMap<(String, Vec<u8>), Option<Vec<u8>>>,
+    from: &[u8],
+    to: &[u8],
+) -> Result<Vec<Vec<u8>>, TypedStoreError> {
+    let mut keys: BTreeSet<Vec<u8>> = overlay
+        .keys()
+        .filter(|(cf_name, key)| {
+            *cf_name == table.cf_name && key.as_slice() >= from && key.as_slice() < to
+        })
+        .map(|(_, key)| key.clone())
+        .collect();
+    let cf = rocksdb
+        .cf_handle(&table.cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.cf_name.clone()))?;
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, total_order_read_options());
+    db_iter.seek(from);
+    while let Some(key) = db_iter.key() {
+        if key >= to {
+            break;
+        }
+        keys.insert(key.to_vec());
+        db_iter.next();
+    }
+    db_iter.status()?;
+    Ok(keys.into_iter().collect())
+}
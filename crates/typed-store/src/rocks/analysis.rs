@@ -0,0 +1,284 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::BTreeMap;
+
+use bincode::Options;
+use rocksdb::MultiThreaded;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{filters::total_order_read_options, TypedStoreError};
+
+/// How to scan a table in `analyze_table_sizes`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeAnalysisOptions {
+    /// Only one entry out of `sample_every` is analyzed, 1 analyzes every entry
+    pub sample_every: usize,
+    /// The number of leading bytes of the encoded keys used to group entries by prefix, 0 disables grouping
+    pub prefix_len: usize,
+    /// Stop the scan after this many entries, sampled or not
+    pub max_entries: Option<usize>,
+}
+
+impl Default for SizeAnalysisOptions {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl SizeAnalysisOptions {
+    /// Analyzes every entry of the table
+    pub fn full() -> Self {
+        Self {
+            sample_every: 1,
+            prefix_len: 0,
+            max_entries: None,
+        }
+    }
+
+    /// Analyzes one entry out of `sample_every`
+    pub fn sampled(sample_every: usize) -> Self {
+        Self {
+            sample_every: sample_every.max(1),
+            ..Self::full()
+        }
+    }
+
+    pub fn with_prefix_len(mut self, prefix_len: usize) -> Self {
+        self.prefix_len = prefix_len;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+}
+
+/// A histogram of sizes in bytes, with power of two buckets
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeHistogram {
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+    /// `buckets[i]` counts the sizes in `[2^(i-1), 2^i)`, `buckets[0]` the empty ones
+    pub buckets: Vec<u64>,
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, size: usize) {
+        let size = size as u64;
+        let bucket = (u64::BITS - size.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += size;
+        self.max = self.max.max(size);
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Returns (exclusive upper bound of the bucket, count) pairs for the non empty buckets
+    pub fn non_empty_buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| (1u64 << i, *count))
+    }
+}
+
+/// The entries sharing a key prefix
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixUsage {
+    pub entries: u64,
+    pub total_bytes: u64,
+}
+
+impl PrefixUsage {
+    fn record(&mut self, key: &[u8], value: &[u8]) {
+        self.entries += 1;
+        self.total_bytes += (key.len() + value.len()) as u64;
+    }
+}
+
+/// The `n` prefixes using the most bytes, largest first
+fn top_prefixes<P: Ord>(prefixes: &BTreeMap<P, PrefixUsage>, n: usize) -> Vec<(&P, &PrefixUsage)> {
+    let mut prefixes: Vec<_> = prefixes.iter().collect();
+    prefixes.sort_by(|(_, a), (_, b)| b.total_bytes.cmp(&a.total_bytes));
+    prefixes.truncate(n);
+    prefixes
+}
+
+/// Scans the table `cf_name`, and calls `analyze` with the entries sampled per `options`. Returns
+/// the number of entries scanned, sampled or not
+fn scan_sampled(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    options: SizeAnalysisOptions,
+    mut analyze: impl FnMut(&[u8], &[u8]),
+) -> Result<u64, TypedStoreError> {
+    let cf = rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+    let sample_every = options.sample_every.max(1) as u64;
+    let mut entries_scanned = 0;
+
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, total_order_read_options());
+    db_iter.seek_to_first();
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        if options
+            .max_entries
+            .map_or(false, |max| entries_scanned as usize >= max)
+        {
+            break;
+        }
+        if entries_scanned % sample_every == 0 {
+            analyze(key, value);
+        }
+        entries_scanned += 1;
+        db_iter.next();
+    }
+    db_iter.status()?;
+    Ok(entries_scanned)
+}
+
+/// The result of `analyze_table_sizes`. Counts only include the analyzed entries,
+/// the `estimated_*` fields extrapolate them to the whole table.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeReport {
+    pub table: String,
+    pub entries_scanned: u64,
+    pub entries_analyzed: u64,
+    pub key_sizes: SizeHistogram,
+    pub value_sizes: SizeHistogram,
+    /// Key and value bytes per encoded key prefix
+    pub prefixes: BTreeMap<Vec<u8>, PrefixUsage>,
+    pub estimated_entries: u64,
+    pub estimated_total_bytes: u64,
+}
+
+impl SizeReport {
+    /// The `n` prefixes using the most bytes, largest first
+    pub fn top_prefixes(&self, n: usize) -> Vec<(&[u8], &PrefixUsage)> {
+        top_prefixes(&self.prefixes, n)
+            .into_iter()
+            .map(|(prefix, usage)| (prefix.as_slice(), usage))
+            .collect()
+    }
+}
+
+/// Scans the table `cf_name` and computes histograms of its key and value sizes, and the bytes used per key prefix,
+/// to find out which tables and prefixes dominate the disk usage. Sizes are those of the encoded, uncompressed entries.
+pub fn analyze_table_sizes(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    options: SizeAnalysisOptions,
+) -> Result<SizeReport, TypedStoreError> {
+    let sample_every = options.sample_every.max(1);
+    let mut report = SizeReport {
+        table: cf_name.to_owned(),
+        ..Default::default()
+    };
+    let mut project = (options.prefix_len > 0).then(|| byte_prefix(options.prefix_len));
+
+    report.entries_scanned = scan_sampled(rocksdb, cf_name, options, |key, value| {
+        report.entries_analyzed += 1;
+        report.key_sizes.record(key.len());
+        report.value_sizes.record(value.len());
+        if let Some(prefix) = project.as_mut().and_then(|project| project(key)) {
+            report
+                .prefixes
+                .entry(prefix)
+                .or_default()
+                .record(key, value);
+        }
+    })?;
+
+    report.estimated_entries = report.entries_analyzed * sample_every as u64;
+    report.estimated_total_bytes =
+        (report.key_sizes.sum + report.value_sizes.sum) * sample_every as u64;
+    Ok(report)
+}
+
+/// The result of `collect_prefix_stats`: the number of entries and bytes per prefix of the keys,
+/// e.g. per epoch or per owner. Counts only include the analyzed entries
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixStats<P: Ord> {
+    pub table: String,
+    pub entries_scanned: u64,
+    pub entries_analyzed: u64,
+    /// The sampling rate of the scan, by which the counts are multiplied in the estimations
+    pub sample_every: u64,
+    pub prefixes: BTreeMap<P, PrefixUsage>,
+}
+
+impl<P: Ord> PrefixStats<P> {
+    /// The `n` prefixes using the most bytes, largest first
+    pub fn top_prefixes(&self, n: usize) -> Vec<(&P, &PrefixUsage)> {
+        top_prefixes(&self.prefixes, n)
+    }
+
+    /// The usage of `prefix` in the whole table, extrapolated from the analyzed entries
+    pub fn estimated_usage(&self, prefix: &P) -> PrefixUsage {
+        self.prefixes
+            .get(prefix)
+            .map(|usage| PrefixUsage {
+                entries: usage.entries * self.sample_every,
+                total_bytes: usage.total_bytes * self.sample_every,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Scans the table `cf_name` and aggregates the number of entries and bytes of key and value per
+/// prefix, as returned by `project` from the encoded keys, to find out which part of the key space
+/// (an epoch, an owner...) accounts for most of the table. The entries for which `project` returns
+/// `None` are skipped. The sampling and limit of `options` apply, its `prefix_len` is ignored
+pub fn collect_prefix_stats<P: Ord>(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    options: SizeAnalysisOptions,
+    mut project: impl FnMut(&[u8]) -> Option<P>,
+) -> Result<PrefixStats<P>, TypedStoreError> {
+    let mut stats = PrefixStats {
+        table: cf_name.to_owned(),
+        entries_scanned: 0,
+        entries_analyzed: 0,
+        sample_every: options.sample_every.max(1) as u64,
+        prefixes: BTreeMap::new(),
+    };
+
+    stats.entries_scanned = scan_sampled(rocksdb, cf_name, options, |key, value| {
+        if let Some(prefix) = project(key) {
+            stats.entries_analyzed += 1;
+            stats.prefixes.entry(prefix).or_default().record(key, value);
+        }
+    })?;
+    Ok(stats)
+}
+
+/// Projects the encoded keys of a table to their first `prefix_len` bytes, for `collect_prefix_stats`
+pub fn byte_prefix(prefix_len: usize) -> impl FnMut(&[u8]) -> Option<Vec<u8>> {
+    move |key| Some(key[..prefix_len.min(key.len())].to_vec())
+}
+
+/// Projects the encoded keys of a table to `project` of the decoded keys, for `collect_prefix_stats`.
+/// The keys, in the default `KeyFormat`, which can't be decoded are skipped
+pub fn key_projection<K: DeserializeOwned, P>(
+    project: impl Fn(&K) -> P,
+) -> impl FnMut(&[u8]) -> Option<P> {
+    move |key| {
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        config.deserialize(key).ok().map(|key| project(&key))
+    }
+}
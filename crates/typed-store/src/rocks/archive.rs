@@ -0,0 +1,70 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compressed archives of tables, for support bundles and bug reports.
+//!
+//! An archive is a zstd stream of:
+//! - the magic bytes `ARCHIVE_MAGIC`, which also version the format,
+//! - the name of the table, as a big endian u32 length and its UTF-8 bytes,
+//! - for every entry, in key order, the length of the key as a big endian u32, the key, and the
+//!   same for the value.
+//!
+//! Keys and values are archived as encoded in the table, like `ExportFormat::Raw` exports. The
+//! archive is read from a snapshot of the table, so it is consistent while the table is written.
+//! Archives are read back with `typed_store::archive::TableArchiveReader`, which doesn't require
+//! the `rocks` feature.
+
+use std::io::{self, BufWriter, Write};
+
+use rocksdb::MultiThreaded;
+use tracing::info;
+
+use super::{filters::total_order_read_options, TypedStoreError};
+use crate::archive::{archive_error, ARCHIVE_MAGIC};
+
+/// The zstd compression level of the archives
+const ARCHIVE_COMPRESSION_LEVEL: i32 = 3;
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Writes an archive of the table `table` to `writer`, see the module documentation, and returns
+/// the number of entries archived
+pub fn archive_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+    writer: impl Write,
+) -> Result<u64, TypedStoreError> {
+    let cf = rocksdb
+        .cf_handle(table)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.to_owned()))?;
+    let snapshot = rocksdb.snapshot();
+    let mut readopts = total_order_read_options();
+    readopts.set_snapshot(&snapshot);
+    readopts.fill_cache(false);
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, readopts);
+    db_iter.seek_to_first();
+
+    let mut encoder =
+        zstd::stream::write::Encoder::new(BufWriter::new(writer), ARCHIVE_COMPRESSION_LEVEL)
+            .map_err(archive_error)?;
+    encoder.write_all(ARCHIVE_MAGIC).map_err(archive_error)?;
+    write_bytes(&mut encoder, table.as_bytes()).map_err(archive_error)?;
+    let mut entries = 0;
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        write_bytes(&mut encoder, key)
+            .and_then(|_| write_bytes(&mut encoder, value))
+            .map_err(archive_error)?;
+        entries += 1;
+        db_iter.next();
+    }
+    db_iter.status()?;
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .map_err(archive_error)?;
+    info!("Archived {entries} entries of {table}");
+    Ok(entries)
+}
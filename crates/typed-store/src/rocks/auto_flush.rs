@@ -0,0 +1,129 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Periodic flushing of idle tables.
+//!
+//! The memtables of a table which stopped being written to are only flushed once they are full,
+//! or when an unrelated event (e.g. an epoch change) forces a flush of the whole database. Until
+//! then, they pin the WAL files holding their writes, which can't be deleted. Flushing idle tables
+//! periodically keeps both small, and spreads the flushes over time.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use rocksdb::MultiThreaded;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::{default_db_name, lifecycle::flush_table, Clock, SystemClock, TypedStoreError};
+
+/// The default interval between two checks of the tables
+pub const DEFAULT_AUTO_FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Flushes the tables which were not written to since the previous call to `flush_idle_tables`
+pub struct AutoFlusher {
+    cfs: Vec<String>,
+    /// The number of entries in the active memtable of each table at the previous check
+    last_entries: HashMap<String, u64>,
+    /// The source of time of the durations of the flushes
+    clock: Arc<dyn Clock>,
+}
+
+impl AutoFlusher {
+    /// Watches the given column families, or all the ones of the database if `cfs` is empty
+    pub fn new(cfs: &[&str]) -> Self {
+        Self {
+            cfs: cfs.iter().map(|cf| cf.to_string()).collect(),
+            last_entries: HashMap::new(),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// Measures the durations of the flushes with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Flushes the memtables of the tables holding writes which were idle since the previous call,
+    /// and returns their names. RocksDB then deletes the WAL files which are no longer needed.
+    pub fn flush_idle_tables(
+        &mut self,
+        rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    ) -> Result<Vec<String>, TypedStoreError> {
+        let cfs = if self.cfs.is_empty() {
+            rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(
+                &rocksdb::Options::default(),
+                rocksdb.path(),
+            )?
+        } else {
+            self.cfs.clone()
+        };
+
+        let mut flushed = vec![];
+        for cf_name in cfs {
+            let cf = match rocksdb.cf_handle(&cf_name) {
+                Some(cf) => cf,
+                None => continue,
+            };
+            let entries = rocksdb
+                .property_int_value_cf(&cf, "rocksdb.num-entries-active-mem-table")?
+                .unwrap_or_default();
+            let previous = self.last_entries.insert(cf_name.clone(), entries);
+            if entries > 0 && previous == Some(entries) {
+                flush_table(
+                    rocksdb,
+                    &default_db_name(rocksdb),
+                    &cf_name,
+                    self.clock.as_ref(),
+                )?;
+                self.last_entries.insert(cf_name.clone(), 0);
+                flushed.push(cf_name);
+            }
+        }
+        if !flushed.is_empty() {
+            info!("Flushed the idle tables {:?}", flushed);
+        }
+        Ok(flushed)
+    }
+}
+
+/// Spawns a task flushing the idle tables among `cfs` every `interval`, see `AutoFlusher`.
+/// All the tables are watched if `cfs` is empty. The task stops once the database is closed.
+pub fn spawn_auto_flush(
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: &[&str],
+    interval: Duration,
+) -> JoinHandle<()> {
+    spawn_auto_flush_with_clock(rocksdb, cfs, interval, SystemClock::shared())
+}
+
+/// Like `spawn_auto_flush`, waiting for the intervals with `clock`
+pub fn spawn_auto_flush_with_clock(
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: &[&str],
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+) -> JoinHandle<()> {
+    let rocksdb: Weak<_> = Arc::downgrade(rocksdb);
+    let mut flusher = AutoFlusher::new(cfs).with_clock(clock.clone());
+    tokio::spawn(async move {
+        loop {
+            match rocksdb.upgrade() {
+                Some(db) => {
+                    if let Err(e) = flusher.flush_idle_tables(&db) {
+                        warn!("Failed to flush idle tables: {e}");
+                    }
+                }
+                None => {
+                    debug!("Database is closed, stopping the auto flush task");
+                    break;
+                }
+            }
+            clock.sleep_async(interval).await;
+        }
+    })
+}
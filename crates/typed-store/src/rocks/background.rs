@@ -0,0 +1,120 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+use rocksdb::MultiThreaded;
+use tracing::{debug, error};
+
+use super::TypedStoreError;
+
+fn set_auto_compactions(
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: &[&str],
+    disabled: bool,
+) -> Result<(), TypedStoreError> {
+    let value = if disabled { "true" } else { "false" };
+    for cf in cfs {
+        let handle = rocksdb
+            .cf_handle(cf)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf.to_string()))?;
+        rocksdb.set_options_cf(&handle, &[("disable_auto_compactions", value)])?;
+    }
+    Ok(())
+}
+
+/// Stops scheduling automatic compactions on the given column families.
+///
+/// Compactions which are already running complete, and flushes still happen, so writes
+/// keep being accepted. Pausing for too long increases read amplification and eventually
+/// triggers write stalls once too many L0 files accumulate.
+pub fn pause_background_work(
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: &[&str],
+) -> Result<(), TypedStoreError> {
+    debug!("Pausing automatic compactions on {:?}", cfs);
+    set_auto_compactions(rocksdb, cfs, true)
+}
+
+/// Resumes automatic compactions on the given column families.
+pub fn continue_background_work(
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: &[&str],
+) -> Result<(), TypedStoreError> {
+    debug!("Resuming automatic compactions on {:?}", cfs);
+    set_auto_compactions(rocksdb, cfs, false)
+}
+
+/// The number of live `BackgroundWorkGuard`s of every column family, keyed by the path of its
+/// database and its name
+static PAUSED_CFS: Lazy<Mutex<HashMap<(PathBuf, String), usize>>> = Lazy::new(Default::default);
+
+/// Pauses automatic compactions on a set of column families until dropped.
+///
+/// The guards of a column family nest: its compactions resume when the last of its guards is
+/// dropped.
+pub struct BackgroundWorkGuard {
+    rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cfs: Vec<String>,
+}
+
+impl BackgroundWorkGuard {
+    pub fn new(
+        rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        cfs: &[&str],
+    ) -> Result<Self, TypedStoreError> {
+        for cf in cfs {
+            if rocksdb.cf_handle(cf).is_none() {
+                return Err(TypedStoreError::UnregisteredColumn(cf.to_string()));
+            }
+        }
+        let path = rocksdb.path().to_path_buf();
+        let mut paused = PAUSED_CFS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        // Only the column families without a live guard are paused, the others already are
+        let newly_paused: Vec<_> = cfs
+            .iter()
+            .copied()
+            .filter(|cf| !paused.contains_key(&(path.clone(), cf.to_string())))
+            .collect();
+        pause_background_work(rocksdb, &newly_paused)?;
+        for cf in cfs {
+            *paused.entry((path.clone(), cf.to_string())).or_default() += 1;
+        }
+        Ok(Self {
+            rocksdb: rocksdb.clone(),
+            cfs: cfs.iter().map(|cf| cf.to_string()).collect(),
+        })
+    }
+}
+
+impl Drop for BackgroundWorkGuard {
+    fn drop(&mut self) {
+        let path = self.rocksdb.path().to_path_buf();
+        let mut paused = PAUSED_CFS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut released = vec![];
+        for cf in &self.cfs {
+            let key = (path.clone(), cf.clone());
+            if let Some(count) = paused.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    paused.remove(&key);
+                    released.push(cf.as_str());
+                }
+            }
+        }
+        if let Err(e) = continue_background_work(&self.rocksdb, &released) {
+            error!(
+                "Failed to resume automatic compactions on {:?}: {e}",
+                released
+            );
+        }
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sets of ids stored as compressed roaring bitmaps.
+//!
+//! A `DBMap<K, Bitmap>` stores a set of `u64` ids per key, e.g. the objects owned by an address.
+//! Ids are added with a RocksDB merge operator, so adding ids to a set of several megabytes
+//! writes the added ids only, instead of reading and rewriting the whole set. The merge operands
+//! are unioned by RocksDB when the set is read and when it is compacted.
+//!
+//! The table must be opened with the merge operator, e.g. with `bitmap_options` as the
+//! `#[default_options_override_fn]` of the field, and without a value codec.
+//!
+//! ```
+//! use typed_store::rocks::{bitmap::{bitmap_options, Bitmap}, open_cf_opts, DBMap};
+//! let rocks = open_cf_opts(tempfile::tempdir().unwrap(), None, &[("owned", &bitmap_options())]).unwrap();
+//! let owned = DBMap::<String, Bitmap>::reopen(&rocks, Some("owned")).unwrap();
+//! owned.add(&"alice".to_owned(), [1, 2, 3]).unwrap();
+//! owned.add(&"alice".to_owned(), [42]).unwrap();
+//! assert!(owned.contains(&"alice".to_owned(), 42).unwrap());
+//! ```
+
+use std::fmt;
+
+use roaring::RoaringTreemap;
+use rocksdb::MergeOperands;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::error;
+
+use super::{default_rocksdb_options, DBMap, TypedStoreError, ValueFormat};
+use crate::traits::Map;
+
+/// The name of the merge operator registered by `set_bitmap_merge_operator`
+pub const BITMAP_MERGE_OPERATOR: &str = "typed_store_bitmap_union";
+
+/// A set of `u64` ids, serialized in the portable roaring format
+#[derive(Clone, Default, PartialEq)]
+pub struct Bitmap(pub RoaringTreemap);
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, id: u64) -> bool {
+        self.0.insert(id)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.0.contains(id)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the ids in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0.iter()
+    }
+}
+
+impl fmt::Debug for Bitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.iter()).finish()
+    }
+}
+
+impl FromIterator<u64> for Bitmap {
+    fn from_iter<I: IntoIterator<Item = u64>>(ids: I) -> Self {
+        Self(ids.into_iter().collect())
+    }
+}
+
+impl Serialize for Bitmap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bytes = Vec::with_capacity(self.0.serialized_size());
+        self.0
+            .serialize_into(&mut bytes)
+            .map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bitmap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = deserialize_bytes(deserializer)?;
+        RoaringTreemap::deserialize_from(bytes.as_slice())
+            .map(Self)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Deserializes the bytes written by `serialize_bytes`
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a serialized roaring bitmap")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+/// Unions the existing bitmap with the operands, all encoded like the values of a `DBMap<K, Bitmap>`
+fn bitmap_union(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let mut union = match existing.map(bincode::deserialize::<Bitmap>).transpose() {
+        Ok(existing) => existing.unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to decode a stored bitmap: {e}");
+            return None;
+        }
+    };
+    for operand in operands {
+        match bincode::deserialize::<Bitmap>(operand) {
+            Ok(bitmap) => union.0 |= bitmap.0,
+            Err(e) => {
+                error!("Failed to decode a bitmap merge operand: {e}");
+                return None;
+            }
+        }
+    }
+    bincode::serialize(&union).ok()
+}
+
+/// Registers the merge operator of the bitmap tables in `opts`
+pub fn set_bitmap_merge_operator(opts: &mut rocksdb::Options) {
+    opts.set_merge_operator_associative(BITMAP_MERGE_OPERATOR, bitmap_union);
+}
+
+/// The default options, with the merge operator of the bitmap tables
+pub fn bitmap_options() -> rocksdb::Options {
+    let mut opts = default_rocksdb_options();
+    set_bitmap_merge_operator(&mut opts);
+    opts
+}
+
+impl<K: Serialize + DeserializeOwned> DBMap<K, Bitmap> {
+    /// Adds `ids` to the set of `key`, without reading it
+    pub fn add(&self, key: &K, ids: impl IntoIterator<Item = u64>) -> Result<(), TypedStoreError> {
+        self.union_into(key, &ids.into_iter().collect())
+    }
+
+    /// Adds the ids of `other` to the set of `key`, without reading it.
+    /// Merges are not reported to the watchers and the accumulator of the map.
+    /// Fails if the map has a value codec or format, since the merge operator reads the plain
+    /// bincode values
+    pub fn union_into(&self, key: &K, other: &Bitmap) -> Result<(), TypedStoreError> {
+        if self.value_codec.is_some() || self.value_format != ValueFormat::Bincode {
+            return Err(TypedStoreError::UnsupportedValueCodec(self.cf.clone()));
+        }
+        let key_buf = self.encode_key(key)?;
+        let operand = bincode::serialize(other)?;
+        let _permit = self.permit_writes()?;
+        self.rocksdb
+            .merge_cf_opt(&self.cf(), key_buf, operand, &self.write_options())?;
+        Ok(())
+    }
+
+    /// Whether `id` is in the set of `key`
+    pub fn contains(&self, key: &K, id: u64) -> Result<bool, TypedStoreError> {
+        Ok(self.get(key)?.map_or(false, |bitmap| bitmap.contains(id)))
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support bundles, collecting the state of a database needed to investigate an incident in a
+//! single file.
+//!
+//! A bundle is a zstd compressed tar archive of JSON files:
+//! - `manifest.json`, the `SupportBundleManifest` of the bundle,
+//! - for every table, in `tables/{table}/`: `schema.json` with its key and value types,
+//!   `summary.json` with its `TableSummary`, `sample.json` with a `DumpPage` of its first entries,
+//!   `lsm.json` with its `LsmReport` and `options.json` with its `TableOptionsSummary`.
+//!
+//! The reports which can't be computed, e.g. the options of a secondary instance which has no
+//! options file, are listed in the errors of the manifest instead of failing the bundle.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{lsm_report, options_summary, TypedStoreError};
+use crate::traits::{DumpPage, TableSummary};
+
+/// The zstd compression level of the bundles
+const BUNDLE_COMPRESSION_LEVEL: i32 = 3;
+
+fn bundle_error(e: io::Error) -> TypedStoreError {
+    TypedStoreError::IoError(format!("failed to write the support bundle: {e}"))
+}
+
+/// A table to include in a support bundle, with the reports which need its types
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleTable {
+    pub name: String,
+    pub key_type: String,
+    pub value_type: String,
+    /// The function which returned the default options of the table, see `options_summary`
+    pub options_fn: String,
+    pub summary: TableSummary,
+    /// The first entries of the table, formatted
+    pub sample: DumpPage,
+}
+
+/// The description of a support bundle, written in its `manifest.json`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupportBundleManifest {
+    pub db_name: String,
+    pub db_path: String,
+    pub crate_version: String,
+    /// The creation time of the bundle, in milliseconds since the Unix epoch
+    pub created_at_ms: u64,
+    pub tables: Vec<String>,
+    /// The reports which could not be computed, with the reason
+    pub errors: Vec<String>,
+}
+
+/// Writes a support bundle of `tables` of the database to `output`, see the module documentation,
+/// and returns its manifest. The output is conventionally named `{something}.tar.zst`
+pub fn create_support_bundle(
+    db_name: &str,
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[BundleTable],
+    output: &Path,
+) -> Result<SupportBundleManifest, TypedStoreError> {
+    let mut manifest = SupportBundleManifest {
+        db_name: db_name.to_owned(),
+        db_path: rocksdb.path().display().to_string(),
+        crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+        created_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        tables: tables.iter().map(|table| table.name.clone()).collect(),
+        errors: Vec::new(),
+    };
+
+    let file = File::create(output).map_err(bundle_error)?;
+    let encoder = zstd::stream::write::Encoder::new(BufWriter::new(file), BUNDLE_COMPRESSION_LEVEL)
+        .map_err(bundle_error)?;
+    let mut bundle = BundleWriter {
+        builder: tar::Builder::new(encoder),
+        mtime: manifest.created_at_ms / 1000,
+    };
+
+    for table in tables {
+        let dir = format!("tables/{}", table.name);
+        bundle.add_json(
+            &format!("{dir}/schema.json"),
+            &serde_json::json!({ "key_type": table.key_type, "value_type": table.value_type }),
+        )?;
+        bundle.add_json(&format!("{dir}/summary.json"), &table.summary)?;
+        bundle.add_json(&format!("{dir}/sample.json"), &table.sample)?;
+        match lsm_report(rocksdb, &table.name) {
+            Ok(report) => bundle.add_json(&format!("{dir}/lsm.json"), &report)?,
+            Err(e) => manifest
+                .errors
+                .push(format!("LSM report of {}: {e}", table.name)),
+        }
+        match options_summary(rocksdb, &[(table.name.as_str(), table.options_fn.as_str())]) {
+            Ok(options) => bundle.add_json(&format!("{dir}/options.json"), &options[0])?,
+            Err(e) => manifest
+                .errors
+                .push(format!("options of {}: {e}", table.name)),
+        }
+    }
+    bundle.add_json("manifest.json", &manifest)?;
+
+    bundle
+        .builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .map_err(bundle_error)?;
+    info!(
+        "Wrote a support bundle of {} tables of {db_name} to {output:?}",
+        tables.len()
+    );
+    Ok(manifest)
+}
+
+struct BundleWriter<W: Write> {
+    builder: tar::Builder<W>,
+    /// The modification time of the files of the bundle, in seconds since the Unix epoch
+    mtime: u64,
+}
+
+impl<W: Write> BundleWriter<W> {
+    fn add_json(&mut self, path: &str, value: &impl Serialize) -> Result<(), TypedStoreError> {
+        let bytes = serde_json::to_vec_pretty(value)
+            .map_err(|e| TypedStoreError::SerializationError(e.to_string()))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        self.builder
+            .append_data(&mut header, path, bytes.as_slice())
+            .map_err(bundle_error)
+    }
+}
@@ -0,0 +1,51 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The options the column families were opened with by this process.
+//!
+//! RocksDB doesn't return the options of a column family once it is open, while some operations
+//! need them, e.g. writing SST files for ingestion with the compression, filters and prefix
+//! extractor of the table, or creating a new column family for a table with its options. They are
+//! recorded by database path and column family name by `open_cf_opts` and its variants.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{PoisonError, RwLock},
+};
+
+use once_cell::sync::Lazy;
+use rocksdb::MultiThreaded;
+
+/// The options of the column families, by database path and column family name
+static CF_OPTIONS: Lazy<RwLock<HashMap<(PathBuf, String), rocksdb::Options>>> =
+    Lazy::new(Default::default);
+
+/// Records that the column family `cf_name` of `rocksdb` was opened or created with `options`
+pub(crate) fn record_cf_options(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    options: &rocksdb::Options,
+) {
+    CF_OPTIONS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(
+            (rocksdb.path().to_path_buf(), cf_name.to_owned()),
+            options.clone(),
+        );
+}
+
+/// The options the column family `cf_name` of `rocksdb` was opened with, or the default options
+/// if it was not opened by `open_cf_opts`, e.g. by a database opened with the RocksDB API
+pub(crate) fn cf_options(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> rocksdb::Options {
+    CF_OPTIONS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&(rocksdb.path().to_path_buf(), cf_name.to_owned()))
+        .cloned()
+        .unwrap_or_else(super::default_rocksdb_options)
+}
@@ -0,0 +1,228 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{borrow::Borrow, collections::BTreeSet, sync::Arc};
+
+use rocksdb::{MultiThreaded, WriteBatch, WriteOptions};
+use serde::Serialize;
+
+use super::{
+    accumulator::AccumulatorUpdates,
+    codec::encode_value,
+    freeze::permit_writes,
+    watch::{PrefixWatchers, RawChange},
+    DBMap, TypedStoreError,
+};
+
+/// Default maximum number of operations buffered before a chunk is committed.
+pub const DEFAULT_CHUNK_MAX_ENTRIES: usize = 100_000;
+/// Default maximum number of encoded key and value bytes buffered before a chunk is committed.
+pub const DEFAULT_CHUNK_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Progress of a `ChunkedBatch`, reported every time a chunk is committed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkProgress {
+    /// Number of chunks written to the database so far
+    pub chunks_committed: usize,
+    /// Number of operations (puts and deletes) written to the database so far
+    pub entries_committed: usize,
+    /// Number of encoded key and value bytes written to the database so far
+    pub bytes_committed: usize,
+}
+
+/// A write batch which splits itself into several RocksDB write batches once a
+/// configurable number of operations or encoded bytes is buffered.
+///
+/// # Atomicity
+///
+/// Unlike `DBBatch`, a `ChunkedBatch` is **not** atomic as a whole: each chunk is
+/// committed atomically, but a failure (or a crash) midway leaves the chunks
+/// committed so far in the database. Callers should only use it for idempotent
+/// bulk loads (e.g. backfills that can be restarted from scratch), and rely on
+/// `DBBatch` when several writes must become visible together.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::Map;
+/// let db = DBMap::<u32, u32>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+///
+/// let progress = db
+///     .chunked_batch(100, DEFAULT_CHUNK_MAX_BYTES)
+///     .insert_batch(&db, (0..1000).map(|i| (i, i)))
+///     .expect("Failed to batch insert")
+///     .write()
+///     .expect("Failed to execute batch");
+/// assert_eq!(progress.chunks_committed, 10);
+/// assert_eq!(db.iter().count(), 1000);
+/// ```
+pub struct ChunkedBatch {
+    rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    batch: WriteBatch,
+    max_entries: usize,
+    max_bytes: usize,
+    pending_entries: usize,
+    pending_bytes: usize,
+    progress: ChunkProgress,
+    on_progress: Option<Box<dyn FnMut(&ChunkProgress) + Send>>,
+    low_priority: bool,
+    accumulated: AccumulatorUpdates,
+    notifications: Vec<(Arc<PrefixWatchers>, RawChange)>,
+    /// The tables written by the batch, which must not be frozen when a chunk is committed
+    tables: BTreeSet<String>,
+}
+
+impl ChunkedBatch {
+    /// Create a new chunked batch associated with a DB reference, committing a chunk
+    /// every `max_entries` operations or `max_bytes` encoded bytes, whichever comes first.
+    pub fn new(
+        dbref: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Self {
+        ChunkedBatch {
+            rocksdb: dbref.clone(),
+            batch: WriteBatch::default(),
+            max_entries: max_entries.max(1),
+            max_bytes: max_bytes.max(1),
+            pending_entries: 0,
+            pending_bytes: 0,
+            progress: ChunkProgress::default(),
+            on_progress: None,
+            low_priority: false,
+            accumulated: AccumulatorUpdates::default(),
+            notifications: Vec::new(),
+            tables: BTreeSet::new(),
+        }
+    }
+
+    /// Flag the chunks as low priority writes, see `DBMap::with_low_priority_writes`
+    pub fn low_priority(mut self) -> Self {
+        self.low_priority = true;
+        self
+    }
+
+    /// Register a callback invoked after every committed chunk
+    pub fn with_progress(
+        mut self,
+        on_progress: impl FnMut(&ChunkProgress) + Send + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Deletes a set of keys given as an iterator
+    pub fn delete_batch<J: Borrow<K>, K: Serialize, V>(
+        mut self,
+        db: &DBMap<K, V>,
+        purged_vals: impl IntoIterator<Item = J>,
+    ) -> Result<Self, TypedStoreError> {
+        if !Arc::ptr_eq(&db.rocksdb, &self.rocksdb) {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+
+        self.write_to(db);
+        purged_vals
+            .into_iter()
+            .try_for_each::<_, Result<_, TypedStoreError>>(|k| {
+                let k_buf = db.encode_key(k.borrow())?;
+                if let Some(table) = db.accumulated_table() {
+                    self.accumulated.delete(&table, k_buf.clone());
+                }
+                if db.watchers.is_watching(&k_buf) {
+                    let change = RawChange::Delete {
+                        key: k_buf.as_slice().into(),
+                    };
+                    self.notifications.push((db.watchers.clone(), change));
+                }
+                self.pending_bytes += k_buf.len();
+                self.batch.delete_cf(&db.cf(), k_buf);
+                self.pending_entries += 1;
+                self.maybe_commit()
+            })?;
+        Ok(self)
+    }
+
+    /// inserts a range of (key, value) pairs given as an iterator
+    pub fn insert_batch<J: Borrow<K>, K: Serialize, U: Borrow<V>, V: Serialize>(
+        mut self,
+        db: &DBMap<K, V>,
+        new_vals: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<Self, TypedStoreError> {
+        if !Arc::ptr_eq(&db.rocksdb, &self.rocksdb) {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+
+        self.write_to(db);
+        new_vals
+            .into_iter()
+            .try_for_each::<_, Result<_, TypedStoreError>>(|(k, v)| {
+                let k_buf = db.encode_key(k.borrow())?;
+                let v_buf = encode_value(db.encoding(), &k_buf, v.borrow())?;
+                if let Some(table) = db.accumulated_table() {
+                    self.accumulated.put(
+                        &table,
+                        k_buf.clone(),
+                        db.value_format.serialize(v.borrow())?,
+                    );
+                }
+                if db.watchers.is_watching(&k_buf) {
+                    let change = RawChange::Put {
+                        key: k_buf.as_slice().into(),
+                        value: bincode::serialize(v.borrow())?.into(),
+                    };
+                    self.notifications.push((db.watchers.clone(), change));
+                }
+                self.pending_bytes += k_buf.len() + v_buf.len();
+                self.batch.put_cf(&db.cf(), k_buf, v_buf);
+                self.pending_entries += 1;
+                self.maybe_commit()
+            })?;
+        Ok(self)
+    }
+
+    /// Consume the batch, write the last (possibly partial) chunk and return the overall progress
+    pub fn write(mut self) -> Result<ChunkProgress, TypedStoreError> {
+        self.commit_chunk()?;
+        Ok(self.progress)
+    }
+
+    /// Records that the batch writes to the table of `db`
+    fn write_to<K, V>(&mut self, db: &DBMap<K, V>) {
+        if !self.tables.contains(&db.cf) {
+            self.tables.insert(db.cf.clone());
+        }
+    }
+
+    fn maybe_commit(&mut self) -> Result<(), TypedStoreError> {
+        if self.pending_entries >= self.max_entries || self.pending_bytes >= self.max_bytes {
+            self.commit_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn commit_chunk(&mut self) -> Result<(), TypedStoreError> {
+        if self.pending_entries == 0 {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let mut opts = WriteOptions::default();
+        opts.set_low_pri(self.low_priority);
+        let rocksdb = &self.rocksdb;
+        let permit = permit_writes(rocksdb, self.tables.iter().map(String::as_str))?;
+        std::mem::take(&mut self.accumulated)
+            .write(rocksdb, || Ok(rocksdb.write_opt(batch, &opts)?))?;
+        drop(permit);
+        for (watchers, change) in self.notifications.drain(..) {
+            watchers.notify(change);
+        }
+
+        self.progress.chunks_committed += 1;
+        self.progress.entries_committed += self.pending_entries;
+        self.progress.bytes_committed += self.pending_bytes;
+        self.pending_entries = 0;
+        self.pending_bytes = 0;
+        if let Some(on_progress) = self.on_progress.as_mut() {
+            on_progress(&self.progress);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The source of time of the time dependent features: the expiry of the points of a
+//! `DBTimeSeries`, the write and read rate limits, the commit latencies of the batches, the ages
+//! of the idempotency keys and the periodic flushes.
+//!
+//! The wall clock time of `Clock::now` dates what is persisted, while the durations are measured
+//! with the monotonic `Clock::instant`, which doesn't jump when the system time is set.
+//!
+//! They read the `SystemClock` by default, and can be given another `Clock`, e.g. the `MockClock`
+//! of the `testing` feature, to be driven deterministically in tests and simulations.
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use once_cell::sync::Lazy;
+
+/// The origin of the monotonic time of the `SystemClock`
+static SYSTEM_CLOCK_ORIGIN: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// A source of time, shared by the components it drives
+pub trait Clock: Send + Sync + Debug {
+    /// The current time, as the duration since the Unix epoch
+    fn now(&self) -> Duration;
+
+    /// Blocks the calling thread for `duration`
+    fn sleep(&self, duration: Duration);
+
+    /// Waits for `duration` from an async task
+    fn sleep_async(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+    /// The current time in milliseconds since the Unix epoch
+    fn now_millis(&self) -> u64 {
+        self.now().as_millis() as u64
+    }
+
+    /// The current monotonic time, as the duration since an arbitrary origin, to measure durations.
+    /// `now` by default, for the clocks which only move forward
+    fn instant(&self) -> Duration {
+        self.now()
+    }
+}
+
+/// The clock of the system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl SystemClock {
+    /// The system clock, as a shared `Clock`
+    pub fn shared() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+
+    fn instant(&self) -> Duration {
+        SYSTEM_CLOCK_ORIGIN.elapsed()
+    }
+
+    fn sleep_async(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
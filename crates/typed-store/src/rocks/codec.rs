@@ -0,0 +1,20 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::sync::Arc;
+
+use rocksdb::MultiThreaded;
+
+pub(crate) use crate::codec::{
+    decode_value, encode_value, KeyFormat, ValueCodec, ValueEncoding, ValueFormat,
+};
+
+use super::TypedStoreError;
+
+/// Builds the value codecs of the tables which require one when a derived struct of tables is opened
+pub trait ValueCodecProvider {
+    fn value_codec(
+        &self,
+        rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        table: &str,
+    ) -> Result<Arc<dyn ValueCodec>, TypedStoreError>;
+}
@@ -0,0 +1,371 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    path::Path,
+    sync::Arc,
+};
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    be_fix_int_ser, filters::total_order_read_options, open_cf_read_only, TypedStoreError,
+};
+
+/// The number of entries read from each database at a time
+const COMPARE_CHUNK_SIZE: usize = 1024;
+
+/// A range of encoded keys, the start being inclusive and the end exclusive
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRange {
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    /// The range of all keys
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// The range of keys in `[start, end)`, encoded like the keys of a `DBMap` with the default
+    /// `KeyFormat`
+    pub fn new<K: Serialize>(start: Option<&K>, end: Option<&K>) -> Result<Self, TypedStoreError> {
+        Ok(Self {
+            start: start.map(be_fix_int_ser).transpose()?,
+            end: end.map(be_fix_int_ser).transpose()?,
+        })
+    }
+
+    /// The range of keys starting with `prefix` once encoded, e.g. the first fields of a tuple key,
+    /// with the default `KeyFormat`
+    pub fn prefix<P: Serialize + ?Sized>(prefix: &P) -> Result<Self, TypedStoreError> {
+        Ok(Self::encoded_prefix(be_fix_int_ser(prefix)?))
+    }
+
+    /// The range of keys starting with the encoded `start`
+    pub(crate) fn encoded_prefix(start: Vec<u8>) -> Self {
+        // The smallest key greater than all the keys starting with the prefix, if any
+        let mut end = start.clone();
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                break;
+            }
+        }
+        Self {
+            end: (!end.is_empty()).then_some(end),
+            start: Some(start),
+        }
+    }
+
+    pub(crate) fn contains(&self, key: &[u8]) -> bool {
+        self.start.as_deref().map_or(true, |start| key >= start)
+            && self.end.as_deref().map_or(true, |end| key < end)
+    }
+}
+
+/// A difference between two databases, with the encoded keys and values
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffEntry {
+    OnlyInA {
+        table: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    OnlyInB {
+        table: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    ValueMismatch {
+        table: String,
+        key: Vec<u8>,
+        value_a: Vec<u8>,
+        value_b: Vec<u8>,
+    },
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffEntry::OnlyInA { table, key, value } => {
+                write!(f, "{table}: {} only in A: {}", hex(key), hex(value))
+            }
+            DiffEntry::OnlyInB { table, key, value } => {
+                write!(f, "{table}: {} only in B: {}", hex(key), hex(value))
+            }
+            DiffEntry::ValueMismatch {
+                table,
+                key,
+                value_a,
+                value_b,
+            } => write!(
+                f,
+                "{table}: {} differs, A: {} B: {}",
+                hex(key),
+                hex(value_a),
+                hex(value_b)
+            ),
+        }
+    }
+}
+
+/// Counters of the entries compared so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub keys_compared: u64,
+    pub only_in_a: u64,
+    pub only_in_b: u64,
+    pub value_mismatches: u64,
+}
+
+type RawEntries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+/// A streaming comparison of two databases, see `compare_databases`
+pub struct DatabaseDiff {
+    db_a: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    db_b: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    tables: VecDeque<String>,
+    key_range: KeyRange,
+    /// The key to resume the comparison of the current table from
+    cursor: Option<Vec<u8>>,
+    pending: VecDeque<DiffEntry>,
+    summary: DiffSummary,
+}
+
+/// Compares the given tables of the databases at `path_a` and `path_b` within `key_range`.
+/// All the tables of the first database are compared if `tables` is empty.
+///
+/// Both databases are opened read only, and entries are compared in their encoded form
+/// without knowledge of their types. The returned iterator reads the databases chunk by
+/// chunk as it is consumed, so that databases of any size can be compared in constant memory.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::Map;
+///
+/// let (path_a, path_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+/// {
+///     let db_a = DBMap::<u32, String>::open(&path_a, None, Some("table")).unwrap();
+///     let db_b = DBMap::<u32, String>::open(&path_b, None, Some("table")).unwrap();
+///     db_a.multi_insert([(1, "one"), (2, "two")].map(|(k, v)| (k, v.to_string()))).unwrap();
+///     db_b.multi_insert([(1, "one"), (2, "deux")].map(|(k, v)| (k, v.to_string()))).unwrap();
+/// }
+///
+/// let mut diff = compare_databases(&path_a, &path_b, &["table"], KeyRange::all()).unwrap();
+/// assert!(matches!(diff.next(), Some(Ok(DiffEntry::ValueMismatch { .. }))));
+/// assert!(diff.next().is_none());
+/// assert_eq!(diff.summary().keys_compared, 2);
+/// ```
+pub fn compare_databases<P: AsRef<Path>>(
+    path_a: P,
+    path_b: P,
+    tables: &[&str],
+    key_range: KeyRange,
+) -> Result<DatabaseDiff, TypedStoreError> {
+    let tables: Vec<String> = if tables.is_empty() {
+        rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(&rocksdb::Options::default(), &path_a)?
+    } else {
+        tables.iter().map(|t| t.to_string()).collect()
+    };
+    let db_a = open_cf_read_only(&path_a, &tables)?;
+    let db_b = open_cf_read_only(&path_b, &tables)?;
+    Ok(DatabaseDiff {
+        db_a,
+        db_b,
+        tables: tables.into(),
+        cursor: key_range.start.clone(),
+        key_range,
+        pending: VecDeque::new(),
+        summary: DiffSummary::default(),
+    })
+}
+
+/// The encoded keys of a table which changed between two checkpoints, in key order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableChanges {
+    pub added: Vec<Vec<u8>>,
+    pub removed: Vec<Vec<u8>>,
+    pub modified: Vec<Vec<u8>>,
+}
+
+impl TableChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Returns the keys added, removed and modified in each of the given tables between the checkpoints
+/// of a database at `before` and `after`, e.g. taken with `rocksdb::checkpoint::Checkpoint`.
+/// All the tables of `before` are compared if `tables` is empty, and every compared table has an entry.
+///
+/// Both checkpoints are walked in parallel in key order, see `compare_databases`. Only the changed keys
+/// are kept in memory.
+pub fn diff_checkpoints<P: AsRef<Path>>(
+    before: P,
+    after: P,
+    tables: &[&str],
+) -> Result<BTreeMap<String, TableChanges>, TypedStoreError> {
+    let diff = compare_databases(before, after, tables, KeyRange::all())?;
+    let mut changes: BTreeMap<_, _> = diff
+        .tables
+        .iter()
+        .map(|table| (table.clone(), TableChanges::default()))
+        .collect();
+    for entry in diff {
+        match entry? {
+            DiffEntry::OnlyInA { table, key, .. } => {
+                changes.entry(table).or_default().removed.push(key)
+            }
+            DiffEntry::OnlyInB { table, key, .. } => {
+                changes.entry(table).or_default().added.push(key)
+            }
+            DiffEntry::ValueMismatch { table, key, .. } => {
+                changes.entry(table).or_default().modified.push(key)
+            }
+        }
+    }
+    Ok(changes)
+}
+
+impl DatabaseDiff {
+    /// Counters of the entries compared so far
+    pub fn summary(&self) -> DiffSummary {
+        self.summary
+    }
+
+    fn read_chunk(
+        &self,
+        db: &rocksdb::DBWithThreadMode<MultiThreaded>,
+        table: &str,
+    ) -> Result<RawEntries, TypedStoreError> {
+        let cf = db
+            .cf_handle(table)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.to_owned()))?;
+        let mut db_iter = db.raw_iterator_cf_opt(&cf, total_order_read_options());
+        match &self.cursor {
+            Some(cursor) => db_iter.seek(cursor),
+            None => db_iter.seek_to_first(),
+        }
+        let mut entries = Vec::new();
+        while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+            if entries.len() == COMPARE_CHUNK_SIZE || !self.key_range.contains(key) {
+                break;
+            }
+            entries.push((key.into(), value.into()));
+            db_iter.next();
+        }
+        db_iter.status()?;
+        Ok(entries)
+    }
+
+    /// Compares the next chunk of the current table, returns false once all tables are compared
+    fn compare_next_chunk(&mut self) -> Result<bool, TypedStoreError> {
+        let table = match self.tables.front() {
+            Some(table) => table.clone(),
+            None => return Ok(false),
+        };
+        let chunk_a = self.read_chunk(&self.db_a, &table)?;
+        let chunk_b = self.read_chunk(&self.db_b, &table)?;
+
+        // Only keys up to the last key of a full chunk can be compared, since the other
+        // database may have more keys before it
+        let last_full = |chunk: &RawEntries| {
+            (chunk.len() == COMPARE_CHUNK_SIZE).then(|| chunk.last().unwrap().0.clone())
+        };
+        let limit = match (last_full(&chunk_a), last_full(&chunk_b)) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let in_chunk = |key: &[u8]| limit.as_deref().map_or(true, |limit| key <= limit);
+
+        {
+            let mut iter_a = chunk_a.into_iter().filter(|(k, _)| in_chunk(k)).peekable();
+            let mut iter_b = chunk_b.into_iter().filter(|(k, _)| in_chunk(k)).peekable();
+            loop {
+                let order = match (iter_a.peek(), iter_b.peek()) {
+                    (None, None) => break,
+                    (Some(_), None) => std::cmp::Ordering::Less,
+                    (None, Some(_)) => std::cmp::Ordering::Greater,
+                    (Some((key_a, _)), Some((key_b, _))) => key_a.cmp(key_b),
+                };
+                self.summary.keys_compared += 1;
+                match order {
+                    std::cmp::Ordering::Less => {
+                        let (key, value) = iter_a.next().unwrap();
+                        self.summary.only_in_a += 1;
+                        self.pending.push_back(DiffEntry::OnlyInA {
+                            table: table.clone(),
+                            key: key.into(),
+                            value: value.into(),
+                        });
+                    }
+                    std::cmp::Ordering::Greater => {
+                        let (key, value) = iter_b.next().unwrap();
+                        self.summary.only_in_b += 1;
+                        self.pending.push_back(DiffEntry::OnlyInB {
+                            table: table.clone(),
+                            key: key.into(),
+                            value: value.into(),
+                        });
+                    }
+                    std::cmp::Ordering::Equal => {
+                        let (key, value_a) = iter_a.next().unwrap();
+                        let (_, value_b) = iter_b.next().unwrap();
+                        if value_a != value_b {
+                            self.summary.value_mismatches += 1;
+                            self.pending.push_back(DiffEntry::ValueMismatch {
+                                table: table.clone(),
+                                key: key.into(),
+                                value_a: value_a.into(),
+                                value_b: value_b.into(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        match limit {
+            Some(limit) => {
+                // Resume from the smallest key greater than the limit
+                let mut cursor = limit.into_vec();
+                cursor.push(0);
+                self.cursor = Some(cursor);
+            }
+            None => {
+                self.tables.pop_front();
+                self.cursor = self.key_range.start.clone();
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Iterator for DatabaseDiff {
+    type Item = Result<DiffEntry, TypedStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(Ok(entry));
+            }
+            match self.compare_next_chunk() {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => {
+                    // Stop after reporting the error
+                    self.tables.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
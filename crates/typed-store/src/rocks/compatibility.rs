@@ -0,0 +1,227 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of databases written by newer versions of typed-store.
+//!
+//! A binary rolled back after an upgrade may open a database whose tables use features it doesn't
+//! know of, e.g. values encoded by a codec, and misread them at their first access. The version of
+//! typed-store, of its on-disk format, of the format of the SST files written by RocksDB and the
+//! features used by the tables are recorded in the `METADATA_CF` column family by
+//! `record_compatibility`.
+//! `check_compatibility` then fails when the database was written with a newer format or with
+//! unknown features, before anything is read, and tells apart the databases with entries but
+//! without a record, whose writer is unknown. The tables opened by `DBMapUtils` are checked and
+//! recorded at open.
+
+use std::collections::BTreeSet;
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    filters::total_order_read_options,
+    metadata::{read_metadata, write_metadata, METADATA_CF},
+    TypedStoreError,
+};
+
+const COMPATIBILITY_KEY: &[u8] = b"typed_store_compatibility";
+
+/// The version of the on-disk format of typed-store, increased when the tables written by this
+/// version can't be read by the previous ones
+pub const TYPED_STORE_FORMAT_VERSION: u32 = 1;
+
+/// The `format_version` of the block based tables written by the RocksDB linked to typed-store,
+/// whose default all the table options keep. The SST files of a newer format can't be read by an
+/// older RocksDB
+pub const ROCKSDB_FORMAT_VERSION: u32 = 5;
+
+/// The features of the tables which are recorded, and known to this version
+pub const SECONDARY_INDEXES_FEATURE: &str = "secondary_indexes";
+pub const VALUE_CODECS_FEATURE: &str = "value_codecs";
+pub const SWAPPABLE_TABLES_FEATURE: &str = "swappable_tables";
+pub const KEY_FORMATS_FEATURE: &str = "key_formats";
+pub const KNOWN_FEATURES: &[&str] = &[
+    SECONDARY_INDEXES_FEATURE,
+    VALUE_CODECS_FEATURE,
+    SWAPPABLE_TABLES_FEATURE,
+    KEY_FORMATS_FEATURE,
+];
+
+/// The versions and features recorded in a database, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompatibilityRecord {
+    /// The version of typed-store which first wrote the format of the database
+    pub crate_version: String,
+    /// The newest on-disk format the database was written with
+    pub format_version: u32,
+    /// The features used by the tables of the database
+    pub features: BTreeSet<String>,
+    /// The newest format of the SST files written to the database, see `ROCKSDB_FORMAT_VERSION`.
+    /// Last, so that the previous versions still read the record
+    pub rocksdb_format_version: u32,
+}
+
+impl Default for CompatibilityRecord {
+    /// The record of a database written by this version, without any feature
+    fn default() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            format_version: TYPED_STORE_FORMAT_VERSION,
+            features: BTreeSet::new(),
+            rocksdb_format_version: ROCKSDB_FORMAT_VERSION,
+        }
+    }
+}
+
+/// The record written by the versions which didn't record the format of the SST files, all
+/// linking a RocksDB writing them in `ROCKSDB_FORMAT_VERSION`
+#[derive(Deserialize)]
+struct LegacyCompatibilityRecord {
+    crate_version: String,
+    format_version: u32,
+    features: BTreeSet<String>,
+}
+
+fn read_record(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<Option<CompatibilityRecord>, TypedStoreError> {
+    let bytes = match read_metadata(rocksdb, COMPATIBILITY_KEY)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    match bincode::deserialize(&bytes) {
+        Ok(record) => Ok(Some(record)),
+        Err(_) => {
+            let legacy: LegacyCompatibilityRecord = bincode::deserialize(&bytes)?;
+            Ok(Some(CompatibilityRecord {
+                crate_version: legacy.crate_version,
+                format_version: legacy.format_version,
+                features: legacy.features,
+                rocksdb_format_version: ROCKSDB_FORMAT_VERSION,
+            }))
+        }
+    }
+}
+
+/// Whether the tables of the database have no entry. The column families which are not open
+/// can't be checked, and count as not empty
+fn is_empty(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>) -> Result<bool, TypedStoreError> {
+    let cfs = rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(
+        &rocksdb::Options::default(),
+        rocksdb.path(),
+    )?;
+    for name in cfs.iter().filter(|name| name.as_str() != METADATA_CF) {
+        let cf = match rocksdb.cf_handle(name) {
+            Some(cf) => cf,
+            None => return Ok(false),
+        };
+        let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, total_order_read_options());
+        db_iter.seek_to_first();
+        if db_iter.valid() {
+            return Ok(false);
+        }
+        db_iter.status()?;
+    }
+    Ok(true)
+}
+
+/// What the compatibility record of a database tells of its writer, see `check_compatibility`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DatabaseWriter {
+    /// The versions and features recorded by the writers of the database, readable by this version
+    Recorded(CompatibilityRecord),
+    /// A database without any entry nor record, e.g. just created, written from now on by this version
+    New,
+    /// A database with entries but without a record, e.g. written before the records were
+    /// introduced or by another tool: the format of its entries and the features of its tables are
+    /// unknown, and may not be readable by this version
+    Unknown,
+}
+
+impl DatabaseWriter {
+    /// The record of the database, the one of this version for a new database, and `None` when
+    /// the writer is unknown
+    pub fn into_record(self) -> Option<CompatibilityRecord> {
+        match self {
+            DatabaseWriter::Recorded(record) => Some(record),
+            DatabaseWriter::New => Some(CompatibilityRecord::default()),
+            DatabaseWriter::Unknown => None,
+        }
+    }
+}
+
+/// Returns what the record of the database tells of its writer, or fails if this version can't
+/// read it safely. A database without a record is only assumed to be written by this version
+/// when it has no entry, and is otherwise reported as `DatabaseWriter::Unknown`, with a warning
+pub fn check_compatibility(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<DatabaseWriter, TypedStoreError> {
+    let record = match read_record(rocksdb)? {
+        Some(record) => record,
+        None if is_empty(rocksdb)? => return Ok(DatabaseWriter::New),
+        None => {
+            warn!(
+                path = ?rocksdb.path(),
+                "The database has entries but no compatibility record, the format of its entries is unknown"
+            );
+            return Ok(DatabaseWriter::Unknown);
+        }
+    };
+    let incompatible = |reason: String| TypedStoreError::IncompatibleDatabase {
+        written_by: record.crate_version.clone(),
+        reason,
+    };
+    if record.format_version > TYPED_STORE_FORMAT_VERSION {
+        return Err(incompatible(format!(
+            "its format version {} is newer than {TYPED_STORE_FORMAT_VERSION}",
+            record.format_version
+        )));
+    }
+    let unknown: Vec<_> = record
+        .features
+        .iter()
+        .filter(|feature| !KNOWN_FEATURES.contains(&feature.as_str()))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(incompatible(format!(
+            "its tables use the unknown features {unknown:?}"
+        )));
+    }
+    if record.rocksdb_format_version > ROCKSDB_FORMAT_VERSION {
+        return Err(incompatible(format!(
+            "its SST files use the RocksDB table format version {}, newer than the version \
+             {ROCKSDB_FORMAT_VERSION} read by the RocksDB linked to this binary",
+            record.rocksdb_format_version
+        )));
+    }
+    Ok(DatabaseWriter::Recorded(record))
+}
+
+/// Records that this version wrote to the database, with the tables using `features`, which are
+/// added to the recorded ones. The recorded format version is never lowered
+pub fn record_compatibility(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    features: &[&str],
+) -> Result<(), TypedStoreError> {
+    let stored = read_record(rocksdb)?;
+    let mut record = stored.clone().unwrap_or_default();
+    if record.format_version < TYPED_STORE_FORMAT_VERSION {
+        info!(
+            "Upgrading the format of the database from version {} to {TYPED_STORE_FORMAT_VERSION}",
+            record.format_version
+        );
+        record = CompatibilityRecord {
+            features: record.features,
+            ..Default::default()
+        };
+    }
+    record.rocksdb_format_version = record.rocksdb_format_version.max(ROCKSDB_FORMAT_VERSION);
+    record
+        .features
+        .extend(features.iter().map(|feature| feature.to_string()));
+    if stored.as_ref() != Some(&record) {
+        write_metadata(rocksdb, COMPATIBILITY_KEY, &bincode::serialize(&record)?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Durability profiles, bundling the settings of when written data is synced to disk.
+//!
+//! RocksDB acknowledges writes once they are in the WAL file, which the OS writes to disk later:
+//! a process crash loses nothing, but a power failure or kernel crash loses the writes not synced
+//! yet. The `DurabilityProfile`s trade write throughput against the amount of writes lost then:
+//! - `Throughput` leaves syncing to the OS, which may write many megabytes at once, stalling the
+//!   writes meanwhile,
+//! - `Balanced` syncs the WAL and SST files in the background every megabyte written,
+//! - `Durable` also syncs the WAL before acknowledging every write.
+//!
+//! A profile can be set for all the databases opened with `default_rocksdb_options` with
+//! `set_global_durability_profile`. The syncing of the WAL and SST files in the background applies
+//! to a whole database, while the sync of the writes can be chosen per table with
+//! `DBMap::with_durability`.
+
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use rocksdb::WriteOptions;
+use serde::{Deserialize, Serialize};
+
+/// The size written between two background syncs of the files of the `Balanced` and `Durable`
+/// profiles
+const BACKGROUND_SYNC_BYTES: u64 = 1024 * 1024;
+
+static GLOBAL_DURABILITY_PROFILE: Lazy<RwLock<Option<DurabilityProfile>>> =
+    Lazy::new(Default::default);
+
+/// When written data is synced to disk, see the module documentation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DurabilityProfile {
+    Throughput,
+    #[default]
+    Balanced,
+    Durable,
+}
+
+impl DurabilityProfile {
+    /// Whether every write syncs the WAL before being acknowledged
+    pub fn sync_writes(&self) -> bool {
+        *self == DurabilityProfile::Durable
+    }
+
+    /// The size written between two background syncs of the SST files, 0 if left to the OS
+    pub fn bytes_per_sync(&self) -> u64 {
+        match self {
+            DurabilityProfile::Throughput => 0,
+            DurabilityProfile::Balanced | DurabilityProfile::Durable => BACKGROUND_SYNC_BYTES,
+        }
+    }
+
+    /// The size written between two background syncs of the WAL, 0 if left to the OS
+    pub fn wal_bytes_per_sync(&self) -> u64 {
+        self.bytes_per_sync()
+    }
+
+    /// Sets the background syncs of the profile in the options of a database
+    pub fn apply_to_options(&self, options: &mut rocksdb::Options) {
+        options.set_bytes_per_sync(self.bytes_per_sync());
+        options.set_wal_bytes_per_sync(self.wal_bytes_per_sync());
+    }
+
+    /// Sets the sync of the writes of the profile in `write_options`
+    pub fn apply_to_write_options(&self, write_options: &mut WriteOptions) {
+        write_options.set_sync(self.sync_writes());
+    }
+}
+
+/// Sets the profile applied by `default_rocksdb_options`, to the databases opened from now on.
+/// Without a global profile, the background syncs are left to the RocksDB defaults
+pub fn set_global_durability_profile(profile: Option<DurabilityProfile>) {
+    *GLOBAL_DURABILITY_PROFILE
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = profile;
+}
+
+/// The profile applied by `default_rocksdb_options`, if any
+pub fn global_durability_profile() -> Option<DurabilityProfile> {
+    *GLOBAL_DURABILITY_PROFILE
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,399 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encryption at rest of the values of a table, with AES-256-GCM and envelope encryption.
+//!
+//! Values are encrypted with a per table data key. Data keys are stored in the metadata column
+//! family of the database, encrypted ("wrapped") with a master key which is provided when the
+//! tables are opened and never persisted. This allows two kinds of key rotation:
+//! - `rotate_master_key` re-wraps the data keys with a new master key, without rewriting any value,
+//! - `rotate_data_key` adds a data key to a table, used to encrypt the values written from the next
+//!   open on. Values encrypted with the previous data keys remain readable.
+//!
+//! Keys are not encrypted, so that the ordering of the tables is preserved, but each value is
+//! authenticated along the name of its table and its key, so that it can't be moved undetected.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{
+    codec::{ValueCodec, ValueCodecProvider},
+    metadata::{read_metadata, write_metadata},
+    TypedStoreError,
+};
+
+const DATA_KEYS_PREFIX: &[u8] = b"typed_store_data_keys/";
+const FORMAT_VERSION: u8 = 2;
+/// The format of the values encrypted without associated data, still read until rewritten
+const UNAUTHENTICATED_FORMAT_VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+/// The version byte, the data key id and the nonce
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+/// A 256 bits key encrypting the data keys of the tables, e.g. fetched from a KMS at startup
+#[derive(Clone)]
+pub struct MasterKey {
+    id: u32,
+    key: [u8; 32],
+}
+
+impl MasterKey {
+    /// `id` identifies the key among the ones used over time, and is stored along the data keys it wraps
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        Self { id, key }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.key.into())
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The master keys used to open encrypted tables: the current one, which wraps the new data keys,
+/// and the previous ones, needed until `rotate_master_key` re-wrapped the data keys with the current one
+#[derive(Clone, Debug)]
+pub struct MasterKeys {
+    current: MasterKey,
+    previous: Vec<MasterKey>,
+}
+
+impl MasterKeys {
+    pub fn new(current: MasterKey) -> Self {
+        Self {
+            current,
+            previous: vec![],
+        }
+    }
+
+    pub fn with_previous(mut self, key: MasterKey) -> Self {
+        self.previous.push(key);
+        self
+    }
+
+    fn get(&self, id: u32) -> Result<&MasterKey, TypedStoreError> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|key| key.id == id)
+            .ok_or_else(|| {
+                TypedStoreError::EncryptionError(format!("the master key {id} was not provided"))
+            })
+    }
+}
+
+impl ValueCodecProvider for MasterKeys {
+    fn value_codec(
+        &self,
+        rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        table: &str,
+    ) -> Result<Arc<dyn ValueCodec>, TypedStoreError> {
+        Ok(Arc::new(EnvelopeCodec::open(rocksdb, table, self)?))
+    }
+}
+
+/// A data key, encrypted with a master key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct WrappedDataKey {
+    id: u32,
+    master_key_id: u32,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+fn encryption_error(e: aes_gcm::Error) -> TypedStoreError {
+    TypedStoreError::EncryptionError(e.to_string())
+}
+
+fn encrypt(
+    cipher: &Aes256Gcm,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<([u8; NONCE_LEN], Vec<u8>), TypedStoreError> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let payload = Payload {
+        msg: plaintext,
+        aad,
+    };
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(encryption_error)?;
+    Ok((nonce, ciphertext))
+}
+
+fn decrypt(
+    cipher: &Aes256Gcm,
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, TypedStoreError> {
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(encryption_error)
+}
+
+/// The data authenticated along a value: the name of its table, prefixed with its length, and
+/// its serialized key
+fn associated_data(table: &str, key: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + table.len() + key.len());
+    aad.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    aad.extend_from_slice(table.as_bytes());
+    aad.extend_from_slice(key);
+    aad
+}
+
+/// The locks serializing the creation and rotation of the data keys, by database path and table
+static DATA_KEYS_LOCKS: Lazy<Mutex<HashMap<(PathBuf, String), Arc<Mutex<()>>>>> =
+    Lazy::new(Default::default);
+
+fn data_keys_lock(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+) -> Arc<Mutex<()>> {
+    DATA_KEYS_LOCKS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry((rocksdb.path().to_path_buf(), table.to_owned()))
+        .or_default()
+        .clone()
+}
+
+fn data_keys_key(table: &str) -> Vec<u8> {
+    [DATA_KEYS_PREFIX, table.as_bytes()].concat()
+}
+
+/// Returns the wrapped data keys of the table, the last one being the current one
+fn read_data_keys(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+) -> Result<Vec<WrappedDataKey>, TypedStoreError> {
+    match read_metadata(rocksdb, &data_keys_key(table))? {
+        Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+        None => Ok(vec![]),
+    }
+}
+
+fn write_data_keys(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+    data_keys: &[WrappedDataKey],
+) -> Result<(), TypedStoreError> {
+    write_metadata(
+        rocksdb,
+        &data_keys_key(table),
+        &bincode::serialize(data_keys)?,
+    )
+}
+
+fn unwrap_data_key(
+    data_key: &WrappedDataKey,
+    keys: &MasterKeys,
+) -> Result<[u8; 32], TypedStoreError> {
+    let master_key = keys.get(data_key.master_key_id)?;
+    let key = decrypt(
+        &master_key.cipher(),
+        &data_key.nonce,
+        &data_key.ciphertext,
+        &[],
+    )?;
+    key.try_into()
+        .map_err(|_| TypedStoreError::EncryptionError(format!("invalid data key {}", data_key.id)))
+}
+
+fn wrap_data_key(
+    id: u32,
+    key: &[u8; 32],
+    master_key: &MasterKey,
+) -> Result<WrappedDataKey, TypedStoreError> {
+    let (nonce, ciphertext) = encrypt(&master_key.cipher(), key, &[])?;
+    Ok(WrappedDataKey {
+        id,
+        master_key_id: master_key.id,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Adds a new data key to `table`, wrapped with the current master key, and returns its id.
+/// The new key encrypts the values written after the table is reopened.
+pub fn rotate_data_key(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+    keys: &MasterKeys,
+) -> Result<u32, TypedStoreError> {
+    let lock = data_keys_lock(rocksdb, table);
+    let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    add_data_key(rocksdb, table, keys)
+}
+
+/// Adds a new data key to `table`, the caller holding its `data_keys_lock`
+fn add_data_key(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+    keys: &MasterKeys,
+) -> Result<u32, TypedStoreError> {
+    let mut data_keys = read_data_keys(rocksdb, table)?;
+    let id = data_keys.last().map_or(0, |key| key.id + 1);
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    data_keys.push(wrap_data_key(id, &key, &keys.current)?);
+    write_data_keys(rocksdb, table, &data_keys)?;
+    info!("Added data key {id} to table {table}");
+    Ok(id)
+}
+
+/// Re-wraps all the data keys of `table` with the current master key of `keys`.
+/// The previous master keys are not needed by the table afterwards.
+pub fn rotate_master_key(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+    keys: &MasterKeys,
+) -> Result<(), TypedStoreError> {
+    let lock = data_keys_lock(rocksdb, table);
+    let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let data_keys = read_data_keys(rocksdb, table)?
+        .iter()
+        .map(|data_key| {
+            let key = unwrap_data_key(data_key, keys)?;
+            wrap_data_key(data_key.id, &key, &keys.current)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    write_data_keys(rocksdb, table, &data_keys)?;
+    info!(
+        "Re-wrapped the data keys of table {table} with master key {}",
+        keys.current.id
+    );
+    Ok(())
+}
+
+/// A `ValueCodec` encrypting the values of a table with its data keys, see the module documentation.
+///
+/// Encrypted values are formatted as the format version, the id of the data key,
+/// the nonce and the AES-256-GCM ciphertext, authenticated along the table name and the key.
+/// The values of the first format version, encrypted without associated data, are still read,
+/// and rewritten in the current format by `DBMap::rewrite_stale_values`.
+pub struct EnvelopeCodec {
+    table: String,
+    current: u32,
+    data_keys: BTreeMap<u32, Aes256Gcm>,
+}
+
+impl fmt::Debug for EnvelopeCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvelopeCodec")
+            .field("table", &self.table)
+            .field("current", &self.current)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EnvelopeCodec {
+    /// Unwraps the data keys of `table`, creating the first one if the table has none
+    pub fn open(
+        rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+        table: &str,
+        keys: &MasterKeys,
+    ) -> Result<Self, TypedStoreError> {
+        let mut wrapped = read_data_keys(rocksdb, table)?;
+        if wrapped.is_empty() {
+            let lock = data_keys_lock(rocksdb, table);
+            let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            // Another opening may have created the first key while the lock was awaited
+            wrapped = read_data_keys(rocksdb, table)?;
+            if wrapped.is_empty() {
+                add_data_key(rocksdb, table, keys)?;
+                wrapped = read_data_keys(rocksdb, table)?;
+            }
+        }
+        let current = wrapped.last().map(|key| key.id).unwrap_or_default();
+        let data_keys = wrapped
+            .iter()
+            .map(|data_key| {
+                let key = unwrap_data_key(data_key, keys)?;
+                Ok((data_key.id, Aes256Gcm::new(&key.into())))
+            })
+            .collect::<Result<_, TypedStoreError>>()?;
+        Ok(Self {
+            table: table.to_owned(),
+            current,
+            data_keys,
+        })
+    }
+
+    fn cipher(&self, id: u32) -> Result<&Aes256Gcm, TypedStoreError> {
+        self.data_keys.get(&id).ok_or_else(|| {
+            TypedStoreError::EncryptionError(format!(
+                "unknown data key {id} for table {}",
+                self.table
+            ))
+        })
+    }
+}
+
+impl ValueCodec for EnvelopeCodec {
+    fn encode(&self, key: &[u8], value: Vec<u8>) -> Result<Vec<u8>, TypedStoreError> {
+        let (nonce, ciphertext) = encrypt(
+            self.cipher(self.current)?,
+            &value,
+            &associated_data(&self.table, key),
+        )?;
+        let mut bytes = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        bytes.push(FORMAT_VERSION);
+        bytes.extend_from_slice(&self.current.to_be_bytes());
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        Ok(bytes)
+    }
+
+    fn decode(&self, key: &[u8], bytes: &[u8]) -> Result<Vec<u8>, TypedStoreError> {
+        let aad = match bytes.first() {
+            Some(&FORMAT_VERSION) if bytes.len() >= HEADER_LEN => associated_data(&self.table, key),
+            Some(&UNAUTHENTICATED_FORMAT_VERSION) if bytes.len() >= HEADER_LEN => vec![],
+            _ => {
+                return Err(TypedStoreError::EncryptionError(format!(
+                    "value of table {} is not encrypted",
+                    self.table
+                )))
+            }
+        };
+        let id = u32::from_be_bytes(bytes[1..5].try_into().unwrap());
+        decrypt(
+            self.cipher(id)?,
+            &bytes[5..HEADER_LEN],
+            &bytes[HEADER_LEN..],
+            &aad,
+        )
+    }
+
+    fn rewrite_on_read(&self, bytes: &[u8]) -> bool {
+        bytes.first() == Some(&UNAUTHENTICATED_FORMAT_VERSION)
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Notifications of RocksDB background events: completed flushes and compactions,
+//! background errors and write stalls.
+//!
+//! rust-rocksdb does not expose the RocksDB `EventListener` API, so events are detected by
+//! polling the database properties and statistics. A `DBEventPoller` compares them with their
+//! values at the previous poll and notifies a `DBEventListener` of the changes, which means
+//! events happening between two polls are aggregated. Flushes and compactions are counted
+//! exactly when the database is opened with statistics enabled, and estimated from the number
+//! of running background jobs otherwise: the jobs which start and complete between two polls
+//! are then missed, so the counts are lower bounds.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use rocksdb::MultiThreaded;
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::{debug, error, warn};
+
+use super::{
+    default_db_name, default_rocksdb_options, labels::take_labelled_writes, open_cf_opts,
+    statistics::parse_statistics, TypedStoreError,
+};
+use crate::metrics::DBMetrics;
+
+/// The interval at which `open_cf_opts_with_event_listener` polls for events
+pub const DEFAULT_EVENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// The number of background errors a lagging `BackgroundErrorBroadcaster` subscriber can miss
+pub const DEFAULT_ERROR_CHANNEL_CAPACITY: usize = 16;
+
+/// The write stall state of a database
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    /// Writes are slowed down to let compactions catch up
+    Delayed,
+    /// Writes are blocked until compactions catch up
+    Stopped,
+}
+
+/// Flushes or compactions completed since the previous notification.
+///
+/// The counts are exact only if the poller was given the options of a database opened with
+/// statistics enabled, and approximate otherwise, see the module documentation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JobsCompletedInfo {
+    pub completed: u64,
+    /// The number of jobs completed since the database was opened, or since the first poll
+    /// without statistics
+    pub total_completed: u64,
+}
+
+/// Background errors which occurred since the previous notification
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackgroundErrorInfo {
+    pub new_errors: u64,
+    /// The number of background errors since the database was opened
+    pub total_errors: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteStallInfo {
+    pub previous: WriteStallCondition,
+    pub current: WriteStallCondition,
+}
+
+/// Callbacks invoked when background events are detected on a database.
+/// Every callback defaults to doing nothing, events are recorded in the `DBMetrics` regardless.
+pub trait DBEventListener: Send + Sync {
+    fn on_flush_completed(&self, _db_name: &str, _info: JobsCompletedInfo) {}
+
+    /// Called after `on_flush_completed` with the bytes written by the labelled batches since the
+    /// previous flushes, by label, if any. See `typed_store::rocks::take_labelled_writes`
+    fn on_labelled_writes_flushed(&self, _db_name: &str, _writes: &BTreeMap<String, u64>) {}
+
+    fn on_compaction_completed(&self, _db_name: &str, _info: JobsCompletedInfo) {}
+
+    /// RocksDB stops accepting writes after a background error, this allows reacting
+    /// to it before the next write fails
+    fn on_background_error(&self, _db_name: &str, _info: BackgroundErrorInfo) {}
+
+    fn on_stall_conditions_changed(&self, _db_name: &str, _info: WriteStallInfo) {}
+}
+
+/// A listener which only records the events in the `DBMetrics`
+pub struct NoopEventListener;
+
+impl DBEventListener for NoopEventListener {}
+
+/// A background error detected on a database, as sent by a `BackgroundErrorBroadcaster`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackgroundErrorEvent {
+    pub db_name: String,
+    pub info: BackgroundErrorInfo,
+}
+
+pub type BackgroundErrorReceiver = broadcast::Receiver<BackgroundErrorEvent>;
+
+/// A listener broadcasting the background errors of a database, so that node supervisors can
+/// watch them, e.g. to restart the node before writes start failing
+pub struct BackgroundErrorBroadcaster {
+    sender: broadcast::Sender<BackgroundErrorEvent>,
+}
+
+impl BackgroundErrorBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Returns a receiver of the background errors detected from now on
+    pub fn subscribe(&self) -> BackgroundErrorReceiver {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for BackgroundErrorBroadcaster {
+    fn default() -> Self {
+        Self::new(DEFAULT_ERROR_CHANNEL_CAPACITY)
+    }
+}
+
+impl DBEventListener for BackgroundErrorBroadcaster {
+    fn on_background_error(&self, db_name: &str, info: BackgroundErrorInfo) {
+        // Nobody may be subscribed, the error is logged and recorded in the metrics regardless
+        let _ = self.sender.send(BackgroundErrorEvent {
+            db_name: db_name.to_owned(),
+            info,
+        });
+    }
+}
+
+/// The health of a database, see `db_health`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DBHealth {
+    /// The number of background errors since the database was opened
+    pub background_errors: u64,
+    pub write_stall: WriteStallCondition,
+}
+
+impl DBHealth {
+    /// Whether the database had no background error and accepts writes
+    pub fn is_healthy(&self) -> bool {
+        self.background_errors == 0 && self.write_stall != WriteStallCondition::Stopped
+    }
+}
+
+/// Returns the background errors and the write stall state of a database
+pub fn db_health(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<DBHealth, TypedStoreError> {
+    let property = |name: &str| -> Result<u64, TypedStoreError> {
+        Ok(rocksdb.property_int_value(name)?.unwrap_or(0))
+    };
+    Ok(DBHealth {
+        background_errors: property("rocksdb.background-errors")?,
+        write_stall: write_stall_condition(rocksdb)?,
+    })
+}
+
+fn write_stall_condition(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<WriteStallCondition, TypedStoreError> {
+    let property = |name: &str| -> Result<u64, TypedStoreError> {
+        Ok(rocksdb.property_int_value(name)?.unwrap_or(0))
+    };
+    Ok(if property("rocksdb.is-write-stopped")? > 0 {
+        WriteStallCondition::Stopped
+    } else if property("rocksdb.actual-delayed-write-rate")? > 0 {
+        WriteStallCondition::Delayed
+    } else {
+        WriteStallCondition::Normal
+    })
+}
+
+#[derive(Default)]
+struct EventCounters {
+    flushes: u64,
+    compactions: u64,
+    running_flushes: u64,
+    running_compactions: u64,
+    background_errors: u64,
+    stall: WriteStallCondition,
+}
+
+/// Detects background events on a database and notifies a listener, see the module documentation
+pub struct DBEventPoller {
+    db_name: String,
+    options: Option<rocksdb::Options>,
+    listener: Arc<dyn DBEventListener>,
+    counters: EventCounters,
+}
+
+impl DBEventPoller {
+    /// `options` must be the options the database was opened with, or a clone of them,
+    /// to count jobs using its statistics
+    pub fn new(
+        db_name: String,
+        options: Option<rocksdb::Options>,
+        listener: Arc<dyn DBEventListener>,
+    ) -> Self {
+        Self {
+            db_name,
+            options,
+            listener,
+            counters: EventCounters::default(),
+        }
+    }
+
+    /// Reads the state of the database and notifies the listener of the changes since the previous poll
+    pub fn poll(
+        &mut self,
+        rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    ) -> Result<(), TypedStoreError> {
+        let property = |name: &str| -> Result<u64, TypedStoreError> {
+            Ok(rocksdb.property_int_value(name)?.unwrap_or(0))
+        };
+        let running_flushes = property("rocksdb.num-running-flushes")?;
+        let running_compactions = property("rocksdb.num-running-compactions")?;
+        let background_errors = property("rocksdb.background-errors")?;
+        let stall = write_stall_condition(rocksdb)?;
+
+        let previous = &self.counters;
+        let (flushes, compactions) = match self.options.as_ref().and_then(|o| o.get_statistics()) {
+            Some(stats) => {
+                let (_, histograms) = parse_statistics(&stats);
+                let count = |name: &str| {
+                    histograms
+                        .iter()
+                        .filter(|h| h.name == name)
+                        .flat_map(|h| h.values.iter())
+                        .find(|(key, _)| key == "COUNT")
+                        .map(|(_, value)| *value as u64)
+                        .unwrap_or(0)
+                };
+                (
+                    count("rocksdb.db.flush.micros"),
+                    count("rocksdb.compaction.times.micros"),
+                )
+            }
+            // Without statistics, a decrease of the number of running jobs means some completed
+            None => (
+                previous.flushes + previous.running_flushes.saturating_sub(running_flushes),
+                previous.compactions
+                    + previous
+                        .running_compactions
+                        .saturating_sub(running_compactions),
+            ),
+        };
+
+        let metrics = &DBMetrics::get().event_metrics;
+        let db_name = self.db_name.as_str();
+        let completed = flushes.saturating_sub(previous.flushes);
+        if completed > 0 {
+            metrics
+                .rocksdb_flushes_completed
+                .with_label_values(&[db_name])
+                .inc_by(completed);
+            self.listener.on_flush_completed(
+                db_name,
+                JobsCompletedInfo {
+                    completed,
+                    total_completed: flushes,
+                },
+            );
+            let writes = take_labelled_writes(db_name);
+            if !writes.is_empty() {
+                debug!(
+                    "Flushed {completed} memtables of database {db_name}, written by {writes:?}"
+                );
+                self.listener.on_labelled_writes_flushed(db_name, &writes);
+            }
+        }
+        let completed = compactions.saturating_sub(previous.compactions);
+        if completed > 0 {
+            metrics
+                .rocksdb_compactions_completed
+                .with_label_values(&[db_name])
+                .inc_by(completed);
+            self.listener.on_compaction_completed(
+                db_name,
+                JobsCompletedInfo {
+                    completed,
+                    total_completed: compactions,
+                },
+            );
+        }
+        let new_errors = background_errors.saturating_sub(previous.background_errors);
+        if new_errors > 0 {
+            error!("{new_errors} new background errors on database {db_name}");
+            metrics
+                .rocksdb_background_errors
+                .with_label_values(&[db_name])
+                .set(background_errors as i64);
+            self.listener.on_background_error(
+                db_name,
+                BackgroundErrorInfo {
+                    new_errors,
+                    total_errors: background_errors,
+                },
+            );
+        }
+        if stall != previous.stall {
+            warn!(
+                "Write stall condition of database {db_name} changed from {:?} to {stall:?}",
+                previous.stall
+            );
+            metrics
+                .rocksdb_write_stall
+                .with_label_values(&[db_name])
+                .set(stall as i64);
+            self.listener.on_stall_conditions_changed(
+                db_name,
+                WriteStallInfo {
+                    previous: previous.stall,
+                    current: stall,
+                },
+            );
+        }
+
+        self.counters = EventCounters {
+            flushes,
+            compactions,
+            running_flushes,
+            running_compactions,
+            background_errors,
+            stall,
+        };
+        Ok(())
+    }
+}
+
+/// Spawns a task polling the database for events every `interval`, see `DBEventPoller`.
+/// The task stops once the database is closed.
+pub fn spawn_event_listener(
+    db_name: String,
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    options: Option<rocksdb::Options>,
+    listener: Arc<dyn DBEventListener>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    let rocksdb: Weak<_> = Arc::downgrade(rocksdb);
+    let mut poller = DBEventPoller::new(db_name, options, listener);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let db = match rocksdb.upgrade() {
+                Some(db) => db,
+                None => {
+                    debug!(
+                        "Database {} is closed, stopping the event listener",
+                        poller.db_name
+                    );
+                    break;
+                }
+            };
+            if let Err(e) = poller.poll(&db) {
+                warn!("Failed to poll events of {}: {e}", poller.db_name);
+            }
+        }
+    })
+}
+
+/// Opens a database like `open_cf_opts` and registers `listener` on it, polling for events
+/// every `DEFAULT_EVENT_POLL_INTERVAL`. Must be called from within a tokio runtime.
+pub fn open_cf_opts_with_event_listener<P: AsRef<Path>>(
+    path: P,
+    db_options: Option<rocksdb::Options>,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+    listener: Arc<dyn DBEventListener>,
+) -> Result<Arc<rocksdb::DBWithThreadMode<MultiThreaded>>, TypedStoreError> {
+    let options = db_options.unwrap_or_else(default_rocksdb_options);
+    let rocksdb = open_cf_opts(path, Some(options.clone()), opt_cfs)?;
+    spawn_event_listener(
+        default_db_name(&rocksdb),
+        &rocksdb,
+        Some(options),
+        listener,
+        DEFAULT_EVENT_POLL_INTERVAL,
+    );
+    Ok(rocksdb)
+}
@@ -0,0 +1,193 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exports of tables to files, for offline analytics.
+//!
+//! `export_snapshot` takes a checkpoint of the database, which is cheap since it hard links the
+//! SST files, and converts the selected tables of the checkpoint to one file per table. The
+//! conversion reads the checkpoint rather than the live database, so it neither competes with
+//! the node for its block cache nor pins the state of the database while it runs. The checkpoint
+//! is deleted once converted.
+//!
+//! Keys and values are exported as encoded in the tables: keys with big endian fixed size
+//! integers, values with bincode. Two formats are supported:
+//! - `ExportFormat::Raw`, a sequence of length prefixed key and value pairs, which can be read back
+//!   with `typed_store::export::read_raw_export`, also without the `rocks` feature,
+//! - `ExportFormat::Parquet`, with the `parquet` feature, a Parquet file with a binary `key` and
+//!   a binary `value` column.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use rocksdb::MultiThreaded;
+use tracing::info;
+
+use super::{filters::total_order_read_options, open_cf_read_only, TypedStoreError};
+use crate::export::io_error;
+
+/// The name of the directory of the checkpoint, in the export directory
+const CHECKPOINT_DIR: &str = "checkpoint";
+/// The number of entries of a Parquet row group
+#[cfg(feature = "parquet")]
+const PARQUET_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// The format of the exported tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// For every entry, the length of the key as a big endian u32, the key, and the same for the value
+    Raw,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Raw => "bin",
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// A table exported by `export_snapshot`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedTable {
+    pub table: String,
+    pub path: PathBuf,
+    pub entries: u64,
+}
+
+/// Exports `tables` of the database to `dir`, in files named after the tables, from a checkpoint
+/// of the database. See the module documentation.
+pub fn export_snapshot(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[&str],
+    dir: &Path,
+    format: ExportFormat,
+) -> Result<Vec<ExportedTable>, TypedStoreError> {
+    for table in tables {
+        rocksdb
+            .cf_handle(table)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.to_string()))?;
+    }
+    fs::create_dir_all(dir).map_err(io_error(dir))?;
+    let checkpoint_path = dir.join(CHECKPOINT_DIR);
+    rocksdb::checkpoint::Checkpoint::new(rocksdb)?.create_checkpoint(&checkpoint_path)?;
+
+    let exported = export_checkpoint(&checkpoint_path, tables, dir, format);
+    fs::remove_dir_all(&checkpoint_path).map_err(io_error(&checkpoint_path))?;
+    exported
+}
+
+fn export_checkpoint(
+    checkpoint_path: &Path,
+    tables: &[&str],
+    dir: &Path,
+    format: ExportFormat,
+) -> Result<Vec<ExportedTable>, TypedStoreError> {
+    let checkpoint = open_cf_read_only(checkpoint_path, tables)?;
+    let mut exported = vec![];
+    for table in tables {
+        let path = dir.join(format!("{table}.{}", format.extension()));
+        let cf = checkpoint
+            .cf_handle(table)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.to_string()))?;
+        let mut db_iter = checkpoint.raw_iterator_cf_opt(&cf, total_order_read_options());
+        db_iter.seek_to_first();
+        let entries = std::iter::from_fn(|| {
+            let entry = db_iter
+                .key()
+                .zip(db_iter.value())
+                .map(|(k, v)| (k.to_vec(), v.to_vec()));
+            db_iter.next();
+            entry
+        });
+        let entries = match format {
+            ExportFormat::Raw => write_raw(&path, entries)?,
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => write_parquet(&path, entries)?,
+        };
+        db_iter.status()?;
+        info!("Exported {entries} entries of {table} to {path:?}");
+        exported.push(ExportedTable {
+            table: table.to_string(),
+            path,
+            entries,
+        });
+    }
+    Ok(exported)
+}
+
+fn write_raw(
+    path: &Path,
+    entries: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+) -> Result<u64, TypedStoreError> {
+    let mut file = BufWriter::new(File::create(path).map_err(io_error(path))?);
+    let mut count = 0;
+    for (key, value) in entries {
+        for bytes in [key, value] {
+            file.write_all(&(bytes.len() as u32).to_be_bytes())
+                .and_then(|_| file.write_all(&bytes))
+                .map_err(io_error(path))?;
+        }
+        count += 1;
+    }
+    file.flush().map_err(io_error(path))?;
+    Ok(count)
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(
+    path: &Path,
+    entries: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+) -> Result<u64, TypedStoreError> {
+    use parquet::{
+        data_type::{ByteArray, ByteArrayType},
+        file::{properties::WriterProperties, writer::SerializedFileWriter},
+        schema::parser::parse_message_type,
+    };
+    use std::sync::Arc;
+
+    let parquet_error =
+        |e: parquet::errors::ParquetError| TypedStoreError::SerializationError(e.to_string());
+    let schema =
+        parse_message_type("message table { REQUIRED BYTE_ARRAY key; REQUIRED BYTE_ARRAY value; }")
+            .map_err(parquet_error)?;
+    let file = File::create(path).map_err(io_error(path))?;
+    let mut writer = SerializedFileWriter::new(
+        file,
+        Arc::new(schema),
+        Arc::new(WriterProperties::builder().build()),
+    )
+    .map_err(parquet_error)?;
+
+    let mut count = 0;
+    let mut entries = entries.peekable();
+    while entries.peek().is_some() {
+        let (keys, values): (Vec<ByteArray>, Vec<ByteArray>) = entries
+            .by_ref()
+            .take(PARQUET_ROW_GROUP_SIZE)
+            .map(|(k, v)| (ByteArray::from(k), ByteArray::from(v)))
+            .unzip();
+        count += keys.len() as u64;
+        let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+        for column in [keys, values] {
+            let mut column_writer = row_group
+                .next_column()
+                .map_err(parquet_error)?
+                .expect("The schema has a key and a value column");
+            column_writer
+                .typed::<ByteArrayType>()
+                .write_batch(&column, None, None)
+                .map_err(parquet_error)?;
+            column_writer.close().map_err(parquet_error)?;
+        }
+        row_group.close().map_err(parquet_error)?;
+    }
+    writer.close().map_err(parquet_error)?;
+    Ok(count)
+}
@@ -0,0 +1,100 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use rocksdb::{BlockBasedOptions, ReadOptions, SliceTransform};
+
+use super::default_rocksdb_options;
+
+/// The bits per key of the filters built by the `FilterPolicy` constructors, for a false positive
+/// rate of about 1%
+pub const DEFAULT_FILTER_BITS_PER_KEY: f64 = 10.0;
+
+/// The filter stored in the SST files of a table, checked before reading a data block.
+///
+/// Without filters, a lookup of a key reads a data block of every level which may hold it,
+/// including when the key doesn't exist. Filters skip most of these reads, for a memory cost
+/// of a few bits per key. Tables can select one with the `#[filter(...)]` derive attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterPolicy {
+    /// A bloom filter on the whole keys, used by point lookups (`get`, `contains_key`, `multi_get`)
+    WholeKeyBloom { bits_per_key: f64 },
+    /// A bloom filter on the first `prefix_len` bytes of the encoded keys (e.g. the first field of a
+    /// tuple key), used by seeks within a prefix, in addition to the whole key bloom filter.
+    /// Keys shorter than `prefix_len` are only in the whole key filter. Only the scans bounded to a
+    /// prefix (`DBMultiMap` values, `SecondaryIndex::get`, `remove_prefix`) check the filter, which is
+    /// then only effective for prefixes of at least `prefix_len` bytes. The other iterators and
+    /// seeks read the table in total order, and cross the prefixes
+    PrefixBloom {
+        prefix_len: usize,
+        bits_per_key: f64,
+    },
+    /// A ribbon filter on the whole keys, about 30% smaller than a bloom filter with the same
+    /// false positive rate, but slower to build. Suited to large tables which are rarely written
+    Ribbon { bloom_equivalent_bits_per_key: f64 },
+}
+
+impl FilterPolicy {
+    pub fn whole_key_bloom() -> Self {
+        Self::WholeKeyBloom {
+            bits_per_key: DEFAULT_FILTER_BITS_PER_KEY,
+        }
+    }
+
+    pub fn prefix_bloom(prefix_len: usize) -> Self {
+        Self::PrefixBloom {
+            prefix_len,
+            bits_per_key: DEFAULT_FILTER_BITS_PER_KEY,
+        }
+    }
+
+    pub fn ribbon() -> Self {
+        Self::Ribbon {
+            bloom_equivalent_bits_per_key: DEFAULT_FILTER_BITS_PER_KEY,
+        }
+    }
+
+    /// Sets the filter in `block_options`, which must then be set in the options of the table
+    pub fn apply_to_block_options(&self, block_options: &mut BlockBasedOptions) {
+        match *self {
+            FilterPolicy::WholeKeyBloom { bits_per_key }
+            | FilterPolicy::PrefixBloom { bits_per_key, .. } => {
+                block_options.set_bloom_filter(bits_per_key, false);
+            }
+            FilterPolicy::Ribbon {
+                bloom_equivalent_bits_per_key,
+            } => block_options.set_ribbon_filter(bloom_equivalent_bits_per_key),
+        }
+        block_options.set_whole_key_filtering(true);
+    }
+
+    /// Sets the prefix extractor of the prefix filters in `options`, see `apply_to_block_options`
+    /// for the filter itself
+    pub fn apply_to_options(&self, options: &mut rocksdb::Options) {
+        if let FilterPolicy::PrefixBloom { prefix_len, .. } = *self {
+            options.set_prefix_extractor(SliceTransform::create_fixed_prefix(prefix_len));
+        }
+    }
+
+    /// Returns `options` with the filter. The block based table options of `options` are replaced,
+    /// see `MemoryBudget::table_options_with_filter` to also share a block cache
+    pub fn table_options(&self, mut options: rocksdb::Options) -> rocksdb::Options {
+        let mut block_options = BlockBasedOptions::default();
+        self.apply_to_block_options(&mut block_options);
+        options.set_block_based_table_factory(&block_options);
+        self.apply_to_options(&mut options);
+        options
+    }
+}
+
+/// The default options, with the filter `policy`
+pub fn options_with_filter(policy: FilterPolicy) -> rocksdb::Options {
+    policy.table_options(default_rocksdb_options())
+}
+
+/// The read options of the iterators which may cross the prefixes of the prefix filter of the
+/// table, if any. Without `total_order_seek`, the seeks of a table with a prefix extractor check
+/// the prefix filter, and skip the keys of the following prefixes when the sought one is absent
+pub(crate) fn total_order_read_options() -> ReadOptions {
+    let mut readopts = ReadOptions::default();
+    readopts.set_total_order_seek(true);
+    readopts
+}
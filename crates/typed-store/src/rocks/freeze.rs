@@ -0,0 +1,108 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runtime freezes of tables, rejecting their writes while their reads continue.
+//!
+//! A table is frozen for the process, by database path, until unfrozen: the writes to it through
+//! any `DBMap` opened on it, and the batches writing to it, fail with
+//! `TypedStoreError::TableFrozen`, e.g. while a migration copies it, or once it is suspected to be
+//! corrupted. Freezing is atomic with the writes: `freeze_table` waits for the writes in progress
+//! to the database, and no write to the table is applied once it returns.
+
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{RwLock, RwLockReadGuard},
+};
+
+use once_cell::sync::Lazy;
+use rocksdb::MultiThreaded;
+use tracing::{info, warn};
+
+use super::TypedStoreError;
+
+/// The frozen tables, by database path and column family name
+static FROZEN_TABLES: Lazy<RwLock<HashSet<(PathBuf, String)>>> = Lazy::new(Default::default);
+
+fn table_id(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>, table: &str) -> (PathBuf, String) {
+    (rocksdb.path().to_path_buf(), table.to_owned())
+}
+
+fn read_frozen_tables() -> RwLockReadGuard<'static, HashSet<(PathBuf, String)>> {
+    FROZEN_TABLES
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Rejects the writes to `table` from now on, see the module documentation. Returns whether the
+/// table was not frozen already
+pub fn freeze_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    table: &str,
+) -> Result<bool, TypedStoreError> {
+    if rocksdb.cf_handle(table).is_none() {
+        return Err(TypedStoreError::UnregisteredColumn(table.to_owned()));
+    }
+    let frozen = FROZEN_TABLES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(table_id(rocksdb, table));
+    if frozen {
+        warn!("Froze the table {table} of {:?}", rocksdb.path());
+    }
+    Ok(frozen)
+}
+
+/// Accepts the writes to `table` again. Returns whether the table was frozen
+pub fn unfreeze_table(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>, table: &str) -> bool {
+    let unfrozen = FROZEN_TABLES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&table_id(rocksdb, table));
+    if unfrozen {
+        info!("Unfroze the table {table} of {:?}", rocksdb.path());
+    }
+    unfrozen
+}
+
+/// Whether the writes to `table` are rejected
+pub fn is_table_frozen(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>, table: &str) -> bool {
+    read_frozen_tables().contains(&table_id(rocksdb, table))
+}
+
+/// The frozen tables of the database, sorted by name
+pub fn frozen_tables(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>) -> Vec<String> {
+    let path = rocksdb.path();
+    let mut tables: Vec<_> = read_frozen_tables()
+        .iter()
+        .filter(|(db_path, _)| db_path == path)
+        .map(|(_, table)| table.clone())
+        .collect();
+    tables.sort();
+    tables
+}
+
+/// The permission to write to some tables, preventing them from being frozen until dropped. A
+/// write must not take a permit while holding another one
+pub(crate) struct WritePermit {
+    _frozen_tables: RwLockReadGuard<'static, HashSet<(PathBuf, String)>>,
+}
+
+/// Returns the permission to write to `tables`, or fails if one of them is frozen
+pub(crate) fn permit_writes<'a>(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: impl IntoIterator<Item = &'a str>,
+) -> Result<WritePermit, TypedStoreError> {
+    let frozen_tables = read_frozen_tables();
+    if !frozen_tables.is_empty() {
+        let path = rocksdb.path();
+        for table in tables {
+            if frozen_tables.contains(&(path.to_path_buf(), table.to_owned())) {
+                return Err(TypedStoreError::TableFrozen(table.to_owned()));
+            }
+        }
+    }
+    Ok(WritePermit {
+        _frozen_tables: frozen_tables,
+    })
+}
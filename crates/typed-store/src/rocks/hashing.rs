@@ -0,0 +1,113 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+
+use super::{codec::ValueCodec, filters::total_order_read_options, KeyRange, TypedStoreError};
+
+type Blake2b256 = Blake2b<U32>;
+
+/// The digest of the contents of a table
+pub type ContentDigest = [u8; 32];
+
+/// The state of a content hash, from which the hashing of a table can be resumed, e.g. after a restart.
+///
+/// The digest is chained over the entries in key order: starting from zeroes, each entry updates it
+/// to `blake2b256(digest || key length || key || value length || value)`, with the lengths as
+/// big-endian u64 and the keys and values in their encoded form. Two tables with the same entries
+/// in the same range have the same digest.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentHashCheckpoint {
+    range: KeyRange,
+    /// The last key hashed so far
+    last_key: Option<Vec<u8>>,
+    digest: ContentDigest,
+    entries: u64,
+    complete: bool,
+}
+
+impl ContentHashCheckpoint {
+    /// The checkpoint to start hashing the entries in `range` from
+    pub fn new(range: KeyRange) -> Self {
+        Self {
+            range,
+            ..Default::default()
+        }
+    }
+
+    /// The digest of the entries hashed so far
+    pub fn digest(&self) -> ContentDigest {
+        self.digest
+    }
+
+    /// The number of entries hashed so far
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Whether all the entries of the range were hashed
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    fn update(&mut self, key: &[u8], value: &[u8]) {
+        let mut hasher = Blake2b256::new();
+        hasher.update(self.digest);
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update((value.len() as u64).to_be_bytes());
+        hasher.update(value);
+        self.digest = hasher.finalize().into();
+        self.last_key = Some(key.to_vec());
+        self.entries += 1;
+    }
+}
+
+/// Hashes at most `max_entries` entries of the table `cf_name` after `checkpoint`, and returns the updated checkpoint.
+/// Values are hashed after being decoded by `codec`, so that tables encrypted with different keys can be compared.
+///
+/// The entries are read without a snapshot: if the table is written to while it is hashed,
+/// the digest may not correspond to any state of the table.
+pub fn content_hash(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    codec: Option<&dyn ValueCodec>,
+    mut checkpoint: ContentHashCheckpoint,
+    max_entries: usize,
+) -> Result<ContentHashCheckpoint, TypedStoreError> {
+    if checkpoint.complete {
+        return Ok(checkpoint);
+    }
+    let cf = rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf, total_order_read_options());
+    match (&checkpoint.last_key, &checkpoint.range.start) {
+        (Some(last_key), _) => {
+            db_iter.seek(last_key);
+            if db_iter.key() == Some(last_key.as_slice()) {
+                db_iter.next();
+            }
+        }
+        (None, Some(start)) => db_iter.seek(start),
+        (None, None) => db_iter.seek_to_first(),
+    }
+
+    for _ in 0..max_entries {
+        let (key, value) = match (db_iter.key(), db_iter.value()) {
+            (Some(key), Some(value)) if checkpoint.range.contains(key) => (key, value),
+            _ => {
+                db_iter.status()?;
+                checkpoint.complete = true;
+                break;
+            }
+        };
+        match codec {
+            Some(codec) => checkpoint.update(key, &codec.decode(key, value)?),
+            None => checkpoint.update(key, value),
+        }
+        db_iter.next();
+    }
+    Ok(checkpoint)
+}
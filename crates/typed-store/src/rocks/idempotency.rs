@@ -0,0 +1,106 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Batches applied at most once, by idempotency key.
+//!
+//! A process retrying a journaled operation after a crash can't always tell whether the batch of
+//! the operation was written before the crash. A batch given an idempotency key with
+//! `DBBatch::with_idempotency_key` records the key in the `APPLIED_BATCHES_CF` column family, in
+//! the same atomic write as its operations, and is skipped if the key was already recorded: its
+//! `BatchStats` are then `already_applied`. The keys are kept until pruned by
+//! `prune_applied_batches`, which must only forget the keys of operations which can't be retried.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use rocksdb::{MultiThreaded, WriteBatch};
+
+use super::{freeze::permit_writes, Clock, SystemClock, TypedStoreError};
+
+/// The column family of the idempotency keys of the applied batches, with the time they were
+/// applied at in milliseconds since the UNIX epoch, as a big endian u64
+pub const APPLIED_BATCHES_CF: &str = "applied_batches";
+
+/// Serializes the checks and writes of the batches with an idempotency key, so that two batches
+/// with the same key can't both be applied
+static IDEMPOTENT_WRITES: Lazy<Mutex<()>> = Lazy::new(Default::default);
+
+pub(crate) fn lock_idempotent_writes() -> MutexGuard<'static, ()> {
+    IDEMPOTENT_WRITES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn applied_batches_cf(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<Arc<rocksdb::BoundColumnFamily<'_>>, TypedStoreError> {
+    rocksdb
+        .cf_handle(APPLIED_BATCHES_CF)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(APPLIED_BATCHES_CF.to_owned()))
+}
+
+/// Whether a batch with the idempotency key `key` was applied, and not pruned since
+pub fn is_batch_applied(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    key: &[u8],
+) -> Result<bool, TypedStoreError> {
+    Ok(rocksdb
+        .get_pinned_cf(&applied_batches_cf(rocksdb)?, key)?
+        .is_some())
+}
+
+/// Adds the record of the idempotency key `key` to `batch`, applied at the current time of `clock`
+pub(crate) fn record_applied(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    batch: &mut WriteBatch,
+    key: &[u8],
+    clock: &dyn Clock,
+) -> Result<(), TypedStoreError> {
+    batch.put_cf(
+        &applied_batches_cf(rocksdb)?,
+        key,
+        clock.now_millis().to_be_bytes(),
+    );
+    Ok(())
+}
+
+/// Forgets the idempotency keys of the batches applied more than `max_age` ago, and returns the
+/// number of keys forgotten. Batches with these keys are applied again if retried
+pub fn prune_applied_batches(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    max_age: Duration,
+) -> Result<u64, TypedStoreError> {
+    prune_applied_batches_with_clock(rocksdb, max_age, &SystemClock)
+}
+
+/// Like `prune_applied_batches`, with the ages of the keys read from `clock`, which must be the
+/// clock of the batches which recorded them, see `DBBatch::with_clock`
+pub fn prune_applied_batches_with_clock(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    max_age: Duration,
+    clock: &dyn Clock,
+) -> Result<u64, TypedStoreError> {
+    let cf = applied_batches_cf(rocksdb)?;
+    let oldest = clock
+        .now_millis()
+        .saturating_sub(max_age.as_millis() as u64);
+    let mut batch = WriteBatch::default();
+    let mut pruned = 0;
+    let mut db_iter = rocksdb.raw_iterator_cf(&cf);
+    db_iter.seek_to_first();
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        let applied_at = value.try_into().map(u64::from_be_bytes).unwrap_or_default();
+        if applied_at < oldest {
+            batch.delete_cf(&cf, key);
+            pruned += 1;
+        }
+        db_iter.next();
+    }
+    db_iter.status()?;
+    let _permit = permit_writes(rocksdb, [APPLIED_BATCHES_CF])?;
+    rocksdb.write(batch)?;
+    Ok(pruned)
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    sync::{Arc, MutexGuard},
+};
+
+use bincode::Options;
+use rocksdb::{MultiThreaded, WriteBatch};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    be_fix_int_ser,
+    codec::{decode_value, ValueEncoding},
+    key_locks::KeyLocks,
+    BatchStats, DBBatch, DBMap, TypedStoreError,
+};
+
+const EMPTY: &[u8] = &[];
+/// The prefix of the names of the column families of the indexes, which the names of the tables
+/// must not start with
+const INDEX_CF_PREFIX: &str = "__index/";
+
+/// The name of the column family of the index `index_name` of the table `table_name`.
+///
+/// The name starts with a reserved prefix, and separates the names of the table and the index with
+/// a `/`, which the identifiers of the tables and indexes generated by `DBMapUtils` can't contain,
+/// so that it can't collide with the name of a table or another index
+pub fn index_cf_name(table_name: &str, index_name: &str) -> String {
+    format!("{INDEX_CF_PREFIX}{table_name}/{index_name}")
+}
+
+/// A secondary index of a table, stored in its own column family.
+///
+/// The column family is named by `index_cf_name`. Every entry is the encoded
+/// `(index key, primary key)` pair with an empty value, so that the primary keys sharing an index
+/// key are stored next to each other, in order.
+/// Indexes are maintained by the typed batch generated by `DBMapUtils` for the fields
+/// annotated with `#[index(name = "...", key = "...")]`, see `DBBatch::update_index_batch`.
+pub struct SecondaryIndex<K> {
+    rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cf: String,
+    _phantom: PhantomData<fn(K)>,
+}
+
+impl<K> SecondaryIndex<K> {
+    /// Reopens the index stored in the column family `cf_name` of an open database
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        cf_name: &str,
+    ) -> Result<Self, TypedStoreError> {
+        db.cf_handle(cf_name)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+        Ok(Self {
+            rocksdb: db.clone(),
+            cf: cf_name.to_owned(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The name of the column family of the index
+    pub fn cf_name(&self) -> &str {
+        &self.cf
+    }
+
+    fn cf(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.rocksdb
+            .cf_handle(&self.cf)
+            .expect("Index column family should have been checked at DB creation")
+    }
+}
+
+impl<K: Serialize> SecondaryIndex<K> {
+    /// Returns the index entry of `key`, whose indexed field is `index_key`
+    pub fn entry<I: Serialize + ?Sized>(
+        &self,
+        index_key: &I,
+        key: &K,
+    ) -> Result<Vec<u8>, TypedStoreError> {
+        be_fix_int_ser(&(index_key, key))
+    }
+}
+
+impl<K: DeserializeOwned> SecondaryIndex<K> {
+    /// Returns the primary keys whose indexed field is `index_key`, in order
+    pub fn get<I: Serialize + ?Sized>(&self, index_key: &I) -> Result<Vec<K>, TypedStoreError> {
+        // The encoding is prefix-free, so the entries of `index_key` are exactly the ones
+        // starting with its encoding, and the primary key is the rest of the entry
+        let prefix = be_fix_int_ser(index_key)?;
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        let mut keys = Vec::new();
+        let mut db_iter = self.rocksdb.raw_iterator_cf(&self.cf());
+        db_iter.seek(&prefix);
+        while let Some(entry) = db_iter.key() {
+            if !entry.starts_with(&prefix) {
+                break;
+            }
+            keys.push(config.deserialize(&entry[prefix.len()..])?);
+            db_iter.next();
+        }
+        db_iter.status()?;
+        Ok(keys)
+    }
+
+    /// Like `get`, for the index on the field of the values of type `V` returned by `field`.
+    ///
+    /// Fails with `TypedStoreError::IndexKeyMismatch` if `index_key` is not encoded as a value of
+    /// the field, e.g. a `u64` given for a `u32` field, instead of silently matching no entry.
+    /// Types encoded alike, e.g. `str` for a `String` field, are accepted
+    pub fn get_by_field<V, F, I>(
+        &self,
+        _field: fn(&V) -> &F,
+        index_key: &I,
+    ) -> Result<Vec<K>, TypedStoreError>
+    where
+        F: Serialize + DeserializeOwned,
+        I: Serialize + ?Sized,
+    {
+        let encoded = be_fix_int_ser(index_key)?;
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        let matches_field = config
+            .deserialize::<F>(&encoded)
+            .ok()
+            .and_then(|decoded| be_fix_int_ser(&decoded).ok())
+            .map_or(false, |reencoded| reencoded == encoded);
+        if !matches_field {
+            return Err(TypedStoreError::IndexKeyMismatch {
+                index: self.cf.clone(),
+                expected: std::any::type_name::<F>().to_owned(),
+                found: std::any::type_name::<I>().to_owned(),
+            });
+        }
+        self.get(index_key)
+    }
+}
+
+/// The entry of a key in an index, to be replaced when the batch is written
+pub(crate) struct PendingIndexUpdate {
+    index_cf: String,
+    table_cf: String,
+    key_locks: Arc<KeyLocks>,
+    key: Vec<u8>,
+    entry: Option<Vec<u8>>,
+    /// The entry of the key from its value stored in the table
+    stored_entry: Box<dyn Fn(&[u8]) -> Result<Vec<u8>, TypedStoreError> + Send>,
+}
+
+impl DBBatch {
+    /// Replaces the entry of `key` in `index` by the one of `value`, whose indexed field is returned
+    /// by `field`, or removes it if `value` is `None`.
+    ///
+    /// The entry to replace is the one written earlier in this batch if any, and otherwise the one
+    /// of the value stored in `table`, which is read by `write` under the locks of the keys of the
+    /// table, see `DBMap::update_batch_locked`. Several writes to the same key in a batch hence leave
+    /// a single, up to date, index entry, and concurrent batches updating the same key leave the
+    /// entry of the last one written.
+    pub fn update_index_batch<K, V, F>(
+        mut self,
+        table: &DBMap<K, V>,
+        index: &SecondaryIndex<K>,
+        key: &K,
+        value: Option<&V>,
+        field: fn(&V) -> &F,
+    ) -> Result<Self, TypedStoreError>
+    where
+        K: Serialize,
+        V: DeserializeOwned + 'static,
+        F: Serialize + ?Sized + 'static,
+    {
+        if !Arc::ptr_eq(&index.rocksdb, &self.rocksdb)
+            || !Arc::ptr_eq(&table.rocksdb, &self.rocksdb)
+        {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+
+        // The key of the table, in its key format, locating the stored value
+        let key_buf = table.encode_key(key)?;
+        let entry = value.map(|v| index.entry(field(v), key)).transpose()?;
+        let format = table.value_format;
+        let codec = table.value_codec.clone();
+        let primary_key = be_fix_int_ser(key)?;
+        let stored_key = key_buf.clone();
+        // The entry is the encoded field followed by the primary key, both encoded with bincode
+        let stored_entry = move |bytes: &[u8]| -> Result<Vec<u8>, TypedStoreError> {
+            let value: V = decode_value(
+                ValueEncoding {
+                    format,
+                    codec: codec.as_deref(),
+                },
+                &stored_key,
+                bytes,
+            )?;
+            let mut entry = be_fix_int_ser(field(&value))?;
+            entry.extend_from_slice(&primary_key);
+            Ok(entry)
+        };
+        self.pending_indexes.push(PendingIndexUpdate {
+            index_cf: index.cf.clone(),
+            table_cf: table.cf.clone(),
+            key_locks: table.key_locks.clone(),
+            key: key_buf,
+            entry,
+            stored_entry: Box::new(stored_entry),
+        });
+        Ok(self)
+    }
+}
+
+/// Locks the keys of the tables whose index entries are updated by `updates`, the tables in the
+/// order of their names, so that concurrent batches can't deadlock
+pub(crate) fn lock_index_keys(updates: &[PendingIndexUpdate]) -> Vec<MutexGuard<'_, ()>> {
+    let mut tables: BTreeMap<&str, (&KeyLocks, Vec<&[u8]>)> = BTreeMap::new();
+    for update in updates {
+        tables
+            .entry(update.table_cf.as_str())
+            .or_insert_with(|| (&*update.key_locks, Vec::new()))
+            .1
+            .push(update.key.as_slice());
+    }
+    tables
+        .into_values()
+        .flat_map(|(key_locks, keys)| key_locks.lock(keys))
+        .collect()
+}
+
+/// Adds the index entries of `updates` to `batch`, replacing the ones of the values stored in the
+/// tables, which must not change until the batch is written, see `lock_index_keys`
+pub(crate) fn write_index_updates(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    batch: &mut WriteBatch,
+    stats: &mut BatchStats,
+    updates: &[PendingIndexUpdate],
+) -> Result<(), TypedStoreError> {
+    let mut entries: HashMap<(&str, &[u8]), Option<Vec<u8>>> = HashMap::new();
+    for update in updates {
+        let previous = match entries.remove(&(update.index_cf.as_str(), update.key.as_slice())) {
+            Some(previous) => previous,
+            None => {
+                let table_cf = rocksdb
+                    .cf_handle(&update.table_cf)
+                    .ok_or_else(|| TypedStoreError::UnregisteredColumn(update.table_cf.clone()))?;
+                rocksdb
+                    .get_pinned_cf(&table_cf, &update.key)?
+                    .map(|bytes| (update.stored_entry)(&*bytes))
+                    .transpose()?
+            }
+        };
+        if previous != update.entry {
+            let index_cf = rocksdb
+                .cf_handle(&update.index_cf)
+                .ok_or_else(|| TypedStoreError::UnregisteredColumn(update.index_cf.clone()))?;
+            if let Some(previous) = previous {
+                stats.entries += 1;
+                stats.key_bytes += previous.len();
+                batch.delete_cf(&index_cf, previous);
+            }
+            if let Some(entry) = &update.entry {
+                stats.entries += 1;
+                stats.key_bytes += entry.len();
+                batch.put_cf(&index_cf, entry, EMPTY);
+            }
+        }
+        entries.insert(
+            (update.index_cf.as_str(), update.key.as_slice()),
+            update.entry.clone(),
+        );
+    }
+    Ok(())
+}
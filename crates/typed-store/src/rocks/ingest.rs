@@ -0,0 +1,150 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bulk loads of key-sorted entries, e.g. at genesis or when restoring a snapshot.
+//!
+//! The entries are written to an SST file outside of the database, which is then ingested into the
+//! table: they skip the WAL, the memtables and the flushes, and land in the LSM tree at once. The
+//! file can only be written with the keys in strictly increasing order, which is verified as they
+//! are written: unsorted entries fail the load before anything is ingested. The file is written
+//! with the options of the table, so that it is compressed and filtered like the files of its
+//! flushes, in the directory of the database, from which it is moved into the table without a
+//! copy.
+
+use std::{
+    borrow::Borrow,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::{IngestExternalFileOptions, SstFileWriter};
+use serde::Serialize;
+use tracing::{debug, info};
+
+use super::{
+    accumulator::AccumulatorUpdates, cf_options::cf_options, codec::encode_value,
+    throttle::ThrottledWrite, watch::RawChange, BatchStats, DBMap, TypedStoreError,
+};
+
+/// An SST file written for an ingestion, removed when dropped unless it was moved into the table
+struct IngestedFile(PathBuf);
+
+impl Drop for IngestedFile {
+    fn drop(&mut self) {
+        if self.0.exists() {
+            if let Err(e) = fs::remove_file(&self.0) {
+                debug!("Failed to remove {:?}: {e}", self.0);
+            }
+        }
+    }
+}
+
+/// Writes `entries`, sorted by encoded key, to `table` through an SST file, see the module
+/// documentation
+pub(crate) fn ingest_sorted<J, K, U, V>(
+    table: &DBMap<K, V>,
+    entries: impl IntoIterator<Item = (J, U)>,
+) -> Result<BatchStats, TypedStoreError>
+where
+    J: Borrow<K>,
+    K: Serialize,
+    U: Borrow<V>,
+    V: Serialize,
+{
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    // RocksDB ignores the files of its directory whose names it doesn't generate
+    let file = IngestedFile(table.rocksdb.path().join(format!(
+        "typed-store-ingest-{}-{}-{nanos}.sst",
+        std::process::id(),
+        table.cf
+    )));
+    let mut throttled = Vec::new();
+    let ingested = ingest_file(table, entries, &file.0, &mut throttled);
+    // Nothing was ingested, so the entries don't count towards the write limit of the table
+    if ingested.is_err() {
+        throttled.into_iter().for_each(ThrottledWrite::refund);
+    }
+    ingested
+}
+
+fn ingest_file<J, K, U, V>(
+    table: &DBMap<K, V>,
+    entries: impl IntoIterator<Item = (J, U)>,
+    path: &Path,
+    throttled: &mut Vec<ThrottledWrite>,
+) -> Result<BatchStats, TypedStoreError>
+where
+    J: Borrow<K>,
+    K: Serialize,
+    U: Borrow<V>,
+    V: Serialize,
+{
+    let options = cf_options(&table.rocksdb, &table.cf);
+    let mut writer = SstFileWriter::create(&options);
+    writer.open(path)?;
+
+    let mut stats = BatchStats::default();
+    let mut accumulated = AccumulatorUpdates::default();
+    let mut notifications = Vec::new();
+    let mut previous: Option<Vec<u8>> = None;
+    for (position, (k, v)) in entries.into_iter().enumerate() {
+        let k_buf = table.encode_key(k.borrow())?;
+        if previous
+            .as_ref()
+            .map_or(false, |previous| *previous >= k_buf)
+        {
+            return Err(TypedStoreError::UnsortedKeys {
+                table: table.cf.clone(),
+                position,
+            });
+        }
+        let v_buf = encode_value(table.encoding(), &k_buf, v.borrow())?;
+        throttled.extend(table.throttle_writes(1, k_buf.len() + v_buf.len())?);
+        if let Some(accumulated_table) = table.accumulated_table() {
+            accumulated.put(
+                &accumulated_table,
+                k_buf.clone(),
+                table.value_format.serialize(v.borrow())?,
+            );
+        }
+        if table.watchers.is_watching(&k_buf) {
+            notifications.push(RawChange::Put {
+                key: k_buf.as_slice().into(),
+                value: bincode::serialize(v.borrow())?.into(),
+            });
+        }
+        writer.put(&k_buf, &v_buf)?;
+        stats.entries += 1;
+        stats.key_bytes += k_buf.len();
+        stats.value_bytes += v_buf.len();
+        previous = Some(k_buf);
+    }
+    if stats.entries == 0 {
+        return Ok(stats);
+    }
+    writer.finish()?;
+
+    let mut ingest_options = IngestExternalFileOptions::default();
+    ingest_options.set_move_files(true);
+    let start = table.clock.instant();
+    let permit = table.permit_writes()?;
+    accumulated.write(&table.rocksdb, || {
+        Ok(table
+            .rocksdb
+            .ingest_external_file_cf_opts(&table.cf(), &ingest_options, vec![path])?)
+    })?;
+    drop(permit);
+    stats.commit_latency = table.clock.instant().saturating_sub(start);
+    for change in notifications {
+        table.watchers.notify(change);
+    }
+    info!(
+        "Ingested {} sorted entries into {} in {:?}",
+        stats.entries, table.cf, stats.commit_latency
+    );
+    Ok(stats)
+}
@@ -1,45 +1,179 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use std::marker::PhantomData;
+use std::{
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use bincode::Options;
-use rocksdb::Direction;
+use rocksdb::{Direction, MultiThreaded};
 
-use super::{be_fix_int_ser, errors::TypedStoreError};
+use super::{
+    codec::{decode_value, ValueEncoding},
+    Clock, KeyFormat, TypedStoreError,
+};
+use crate::metrics::DBMetrics;
 use serde::{de::DeserializeOwned, Serialize};
 
 use super::DBRawIteratorMultiThreaded;
 
+type Snapshot<'a> = rocksdb::SnapshotWithThreadMode<'a, rocksdb::DBWithThreadMode<MultiThreaded>>;
+
+/// How an iterator pins the state of the table it reads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotPinning {
+    /// The iterator reads the table as of its creation, and keeps the memtables and SST files
+    /// of that state alive until it is dropped, even once they are flushed or compacted
+    Implicit,
+    /// Like `Implicit`, reading from an explicit snapshot released when the iterator is dropped
+    Explicit,
+    /// The iterator sees the writes made after its creation and releases the memtables and
+    /// files it is done with, so two entries it returns may not be from the same state
+    None,
+}
+
+/// Limits the rate at which an iterator reads entries, see `DBMap::iter_rate_limited`
+struct RateLimit {
+    bytes_per_sec: u64,
+    clock: Arc<dyn Clock>,
+    /// The monotonic time of the first read, and the bytes read since
+    started: Option<Duration>,
+    bytes: u64,
+}
+
+impl RateLimit {
+    /// Records `bytes` read, and sleeps until reading them is within the rate
+    fn throttle(&mut self, bytes: usize) {
+        let clock = &self.clock;
+        let started = *self.started.get_or_insert_with(|| clock.instant());
+        self.bytes += bytes as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.bytes_per_sec.max(1) as f64);
+        let elapsed = self.clock.instant().saturating_sub(started);
+        if let Some(ahead) = due.checked_sub(elapsed) {
+            self.clock.sleep(ahead);
+        }
+    }
+}
+
 /// An iterator over all key-value pairs in a data map.
 pub struct Iter<'a, K, V> {
+    // Dropped before the snapshot it may read from
     db_iter: DBRawIteratorMultiThreaded<'a>,
+    snapshot: Option<Snapshot<'a>>,
+    key_format: KeyFormat,
+    encoding: ValueEncoding<'a>,
     _phantom: PhantomData<(K, V)>,
     direction: Direction,
+    pinning: SnapshotPinning,
+    /// The database and table names, labelling the metrics
+    table: (&'a str, &'a str),
+    /// The creation time reported in the metrics when the iterator is dropped
+    pinned_since: Option<Instant>,
+    rate_limit: Option<RateLimit>,
 }
 
 impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iter<'a, K, V> {
-    pub(super) fn new(db_iter: DBRawIteratorMultiThreaded<'a>) -> Self {
+    pub(super) fn new(
+        db_iter: DBRawIteratorMultiThreaded<'a>,
+        key_format: KeyFormat,
+        encoding: ValueEncoding<'a>,
+        db_name: &'a str,
+        cf_name: &'a str,
+    ) -> Self {
         Self {
             db_iter,
+            snapshot: None,
+            key_format,
+            encoding,
             _phantom: PhantomData,
             direction: Direction::Forward,
+            pinning: SnapshotPinning::Implicit,
+            table: (db_name, cf_name),
+            pinned_since: None,
+            rate_limit: None,
+        }
+    }
+
+    /// Reports how long the iterator pins the state of the table in the metrics,
+    /// and keeps `snapshot` alive while the iterator reads from it
+    pub(super) fn pinned(mut self, snapshot: Option<Snapshot<'a>>) -> Self {
+        if snapshot.is_some() {
+            self.pinning = SnapshotPinning::Explicit;
         }
+        self.snapshot = snapshot;
+        self.pinned_since = Some(Instant::now());
+        self
+    }
+
+    /// Marks the iterator as a tailing iterator
+    pub(super) fn tailing(mut self) -> Self {
+        self.pinning = SnapshotPinning::None;
+        self
+    }
+
+    /// Limits the size of the keys and values read by the iterator to `bytes_per_sec` on average,
+    /// by sleeping in `next`
+    pub(super) fn rate_limited(mut self, bytes_per_sec: u64, clock: Arc<dyn Clock>) -> Self {
+        self.rate_limit = Some(RateLimit {
+            bytes_per_sec,
+            clock,
+            started: None,
+            bytes: 0,
+        });
+        self
     }
 }
 
-impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for Iter<'a, K, V> {
-    type Item = (K, V);
+impl<'a, K, V> Iter<'a, K, V> {
+    /// How the iterator pins the state of the table, see `DBMap::iter_latest` to avoid it
+    pub fn pinning(&self) -> SnapshotPinning {
+        self.pinning
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Whether the iterator keeps the state of the table as of its creation alive until dropped,
+    /// preventing the deletion of the files compacted in the meantime
+    pub fn pins_snapshot(&self) -> bool {
+        self.pinning != SnapshotPinning::None
+    }
+}
+
+impl<'a, K, V> Drop for Iter<'a, K, V> {
+    fn drop(&mut self) {
+        if let Some(since) = self.pinned_since {
+            let (db_name, cf_name) = self.table;
+            DBMetrics::get()
+                .op_metrics
+                .rocksdb_iter_pinned_seconds
+                .with_label_values(&[db_name, cf_name])
+                .observe(since.elapsed().as_secs_f64());
+        }
+    }
+}
+
+impl<'a, K: DeserializeOwned, V> Iter<'a, K, V> {
+    /// Returns the current key, and its value mapped by `read_value` along the key bytes, and
+    /// moves to the next entry
+    fn next_entry<T>(
+        &mut self,
+        read_value: impl FnOnce(&[u8], &[u8], ValueEncoding<'a>) -> Option<T>,
+    ) -> Option<(K, T)> {
         if self.db_iter.valid() {
-            let config = bincode::DefaultOptions::new()
-                .with_big_endian()
-                .with_fixint_encoding();
-            let key = self.db_iter.key().and_then(|k| config.deserialize(k).ok());
+            let key_format = self.key_format;
+            let key = self
+                .db_iter
+                .key()
+                .and_then(|k| key_format.deserialize(k).ok());
+            let encoding = self.encoding;
             let value = self
                 .db_iter
-                .value()
-                .and_then(|v| bincode::deserialize(v).ok());
+                .key()
+                .zip(self.db_iter.value())
+                .and_then(|(k, v)| read_value(k, v, encoding));
+            if let Some(rate_limit) = &mut self.rate_limit {
+                let len = self.db_iter.key().map_or(0, |k| k.len())
+                    + self.db_iter.value().map_or(0, |v| v.len());
+                rate_limit.throttle(len);
+            }
 
             match self.direction {
                 Direction::Forward => self.db_iter.next(),
@@ -51,6 +185,82 @@ impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for Iter<'a, K, V> {
             None
         }
     }
+
+    /// Returns an iterator over the same entries, with the values decoded on demand by
+    /// `LazyValue::decode`. Filters on the keys then don't decode the values of the entries
+    /// they reject, only copy their bytes
+    pub fn values_lazy(self) -> LazyValuesIter<'a, K, V> {
+        LazyValuesIter { iter: self }
+    }
+
+    /// Returns an iterator over the same entries, which fails with
+    /// `TypedStoreError::IteratorMemoryCapExceeded` once the entries it returned take more than
+    /// `cap` bytes, see `MemoryCappedIter`
+    pub fn memory_capped(self, cap: usize) -> MemoryCappedIter<'a, K, V> {
+        MemoryCappedIter {
+            iter: self,
+            cap,
+            held: 0,
+            entries: 0,
+            exceeded: false,
+        }
+    }
+}
+
+impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for Iter<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry(|k, v, encoding| decode_value(encoding, k, v).ok())
+    }
+}
+
+/// A value read by `Iter::values_lazy`, decoded on demand
+pub struct LazyValue<'a, V> {
+    key: Box<[u8]>,
+    bytes: Box<[u8]>,
+    encoding: ValueEncoding<'a>,
+    _phantom: PhantomData<fn() -> V>,
+}
+
+impl<'a, V: DeserializeOwned> LazyValue<'a, V> {
+    /// Decodes the value, which is decoded again at every call
+    pub fn decode(&self) -> Result<V, TypedStoreError> {
+        decode_value(self.encoding, &self.key, &self.bytes)
+    }
+
+    /// The value as stored in the table, encoded by the value codec of the table if any
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<'a, V> std::fmt::Debug for LazyValue<'a, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyValue")
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
+
+/// An iterator over the key-value pairs of a table, with lazily decoded values, see `Iter::values_lazy`
+pub struct LazyValuesIter<'a, K, V> {
+    iter: Iter<'a, K, V>,
+}
+
+impl<'a, K: DeserializeOwned, V> Iterator for LazyValuesIter<'a, K, V> {
+    type Item = (K, LazyValue<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next_entry(|k, v, encoding| {
+            Some(LazyValue {
+                key: k.into(),
+                bytes: v.into(),
+                encoding,
+                _phantom: PhantomData,
+            })
+        })
+    }
 }
 
 impl<'a, K: Serialize, V> Iter<'a, K, V> {
@@ -58,7 +268,7 @@ impl<'a, K: Serialize, V> Iter<'a, K, V> {
     /// and either lands on the key or the first one greater than
     /// the key.
     pub fn skip_to(mut self, key: &K) -> Result<Self, TypedStoreError> {
-        self.db_iter.seek(be_fix_int_ser(key)?);
+        self.db_iter.seek(self.key_format.serialize(key)?);
         Ok(self)
     }
 
@@ -66,7 +276,7 @@ impl<'a, K: Serialize, V> Iter<'a, K, V> {
     /// the one prior to it if it does not exist. If there is
     /// no element prior to it, it returns an empty iterator.
     pub fn skip_prior_to(mut self, key: &K) -> Result<Self, TypedStoreError> {
-        self.db_iter.seek_for_prev(be_fix_int_ser(key)?);
+        self.db_iter.seek_for_prev(self.key_format.serialize(key)?);
         Ok(self)
     }
 
@@ -99,6 +309,13 @@ impl<'a, K, V> RevIter<'a, K, V> {
     }
 }
 
+impl<'a, K: DeserializeOwned, V> RevIter<'a, K, V> {
+    /// Returns an iterator over the same entries, backwards, capped like `Iter::memory_capped`
+    pub fn memory_capped(self, cap: usize) -> MemoryCappedIter<'a, K, V> {
+        self.iter.memory_capped(cap)
+    }
+}
+
 impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for RevIter<'a, K, V> {
     type Item = (K, V);
 
@@ -107,3 +324,59 @@ impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for RevIter<'a, K, V
         self.iter.next()
     }
 }
+
+/// An iterator bounding the memory held by the entries it returns, e.g. when an RPC endpoint
+/// collects a scan requested by a client: `.collect::<Result<Vec<_>, _>>()` then fails with
+/// `TypedStoreError::IteratorMemoryCapExceeded` instead of growing until the node runs out of
+/// memory. The memory of an entry is approximated by the size of its encoded key and of its
+/// decoded value once serialized, plus the size of `(K, V)`: the values stored compressed by the
+/// value codec of the table count for their decoded size, but the decoded values holding more heap
+/// memory than their serialization, e.g. sparse maps, exceed the estimate. Ends after the error.
+pub struct MemoryCappedIter<'a, K, V> {
+    iter: Iter<'a, K, V>,
+    cap: usize,
+    /// The approximate memory of the entries returned so far
+    held: usize,
+    entries: usize,
+    exceeded: bool,
+}
+
+impl<'a, K, V> MemoryCappedIter<'a, K, V> {
+    /// The approximate memory of the entries returned so far
+    pub fn held_bytes(&self) -> usize {
+        self.held
+    }
+}
+
+impl<'a, K: DeserializeOwned, V: Serialize + DeserializeOwned> Iterator
+    for MemoryCappedIter<'a, K, V>
+{
+    type Item = Result<(K, V), TypedStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exceeded {
+            return None;
+        }
+        let key_len = self.iter.db_iter.key().map_or(0, <[u8]>::len);
+        let (key, value) = self.iter.next()?;
+        let value_len = bincode::serialized_size(&value).map_or(0, |len| len as usize);
+        let held = self.held + key_len + value_len + std::mem::size_of::<(K, V)>();
+        if held > self.cap {
+            self.exceeded = true;
+            let (db_name, cf_name) = self.iter.table;
+            DBMetrics::get()
+                .op_metrics
+                .rocksdb_iter_memory_cap_exceeded
+                .with_label_values(&[db_name, cf_name])
+                .inc();
+            return Some(Err(TypedStoreError::IteratorMemoryCapExceeded {
+                table: cf_name.to_owned(),
+                cap: self.cap,
+                entries: self.entries + 1,
+            }));
+        }
+        self.held = held;
+        self.entries += 1;
+        Some(Ok((key, value)))
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    borrow::Borrow,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use rocksdb::{MultiThreaded, WriteBatch};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use super::{codec::encode_value, freeze::permit_writes, DBBatch, DBMap, TypedStoreError};
+use crate::traits::Map;
+
+/// A single raw write operation recorded in a `JournalIntent`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+enum JournalOp {
+    Put {
+        cf: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        cf: String,
+        key: Vec<u8>,
+    },
+}
+
+/// The set of writes to perform on the second database of a `CrossDBJournal`.
+///
+/// Operations are recorded in their encoded form, which makes replaying them
+/// idempotent: applying the same intent twice leaves the database in the same state.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct JournalIntent {
+    ops: Vec<JournalOp>,
+}
+
+impl JournalIntent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the insertion of the given key-value pairs in the table `db`
+    pub fn insert_batch<J: Borrow<K>, K: Serialize, U: Borrow<V>, V: Serialize>(
+        mut self,
+        db: &DBMap<K, V>,
+        new_vals: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<Self, TypedStoreError> {
+        for (k, v) in new_vals {
+            let key = db.encode_key(k.borrow())?;
+            let value = encode_value(db.encoding(), &key, v.borrow())?;
+            self.ops.push(JournalOp::Put {
+                cf: db.cf.clone(),
+                key,
+                value,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Records the deletion of the given keys from the table `db`
+    pub fn delete_batch<J: Borrow<K>, K: Serialize, V>(
+        mut self,
+        db: &DBMap<K, V>,
+        purged_vals: impl IntoIterator<Item = J>,
+    ) -> Result<Self, TypedStoreError> {
+        for k in purged_vals {
+            self.ops.push(JournalOp::Delete {
+                cf: db.cf.clone(),
+                key: db.encode_key(k.borrow())?,
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// A small two-phase commit helper for writes spanning two physically separate databases.
+///
+/// The intent to write to the target database is recorded in a journal table of the
+/// source database, atomically with the source writes. The target writes are then
+/// applied and the journal entry is removed. If the process crashes in between,
+/// `recover` replays the pending intents on the target database. Since intents are
+/// idempotent, replaying an intent which was already (partially) applied is safe.
+///
+/// `recover` must be called once at startup, before any new `commit`.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::Map;
+///
+/// let source = open_cf(tempfile::tempdir().unwrap(), None, &["data", "journal"]).unwrap();
+/// let target = open_cf(tempfile::tempdir().unwrap(), None, &["data"]).unwrap();
+/// let source_data = DBMap::<u32, String>::reopen(&source, Some("data")).unwrap();
+/// let target_data = DBMap::<u32, String>::reopen(&target, Some("data")).unwrap();
+///
+/// let journal = CrossDBJournal::new(
+///     DBMap::reopen(&source, Some("journal")).unwrap(),
+///     &target,
+/// )
+/// .unwrap();
+/// journal.recover().unwrap();
+///
+/// let batch = source_data
+///     .batch()
+///     .insert_batch(&source_data, [(1, "one".to_string())])
+///     .unwrap();
+/// let intent = JournalIntent::new()
+///     .insert_batch(&target_data, [(1, "uno".to_string())])
+///     .unwrap();
+/// journal.commit(batch, intent).unwrap();
+///
+/// assert_eq!(target_data.get(&1).unwrap(), Some("uno".to_string()));
+/// ```
+pub struct CrossDBJournal {
+    journal: DBMap<u64, JournalIntent>,
+    target: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    next_sequence: AtomicU64,
+}
+
+impl CrossDBJournal {
+    /// Creates a journal stored in the table `journal`, applying intents on `target`
+    pub fn new(
+        journal: DBMap<u64, JournalIntent>,
+        target: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    ) -> Result<Self, TypedStoreError> {
+        if Arc::ptr_eq(&journal.rocksdb, target) {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+        let next_sequence = journal
+            .keys()
+            .skip_to_last()
+            .next()
+            .map(|s| s + 1)
+            .unwrap_or(0);
+        Ok(Self {
+            journal,
+            target: target.clone(),
+            next_sequence: AtomicU64::new(next_sequence),
+        })
+    }
+
+    /// Atomically writes `batch` to the source database along with the journaled `intent`,
+    /// then applies the intent on the target database.
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn commit(&self, batch: DBBatch, intent: JournalIntent) -> Result<(), TypedStoreError> {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::SeqCst);
+        batch
+            .insert_batch(&self.journal, [(sequence, &intent)])?
+            .write()?;
+        self.apply(sequence, &intent)
+    }
+
+    /// Replays all the intents left pending by a crash. Returns the number of intents replayed.
+    #[instrument(level = "debug", skip_all, err)]
+    pub fn recover(&self) -> Result<usize, TypedStoreError> {
+        let pending: Vec<_> = self.journal.iter().collect();
+        for (sequence, intent) in &pending {
+            debug!("Replaying journaled intent {sequence}");
+            self.apply(*sequence, intent)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Returns the number of intents recorded but not yet applied on the target database
+    pub fn pending(&self) -> usize {
+        self.journal.keys().count()
+    }
+
+    fn apply(&self, sequence: u64, intent: &JournalIntent) -> Result<(), TypedStoreError> {
+        let mut batch = WriteBatch::default();
+        let mut tables = Vec::new();
+        for op in &intent.ops {
+            match op {
+                JournalOp::Put { cf, key, value } => {
+                    let handle = self
+                        .target
+                        .cf_handle(cf)
+                        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf.clone()))?;
+                    batch.put_cf(&handle, key, value);
+                    tables.push(cf.as_str());
+                }
+                JournalOp::Delete { cf, key } => {
+                    let handle = self
+                        .target
+                        .cf_handle(cf)
+                        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf.clone()))?;
+                    batch.delete_cf(&handle, key);
+                    tables.push(cf.as_str());
+                }
+            }
+        }
+        // A frozen target table leaves the intent pending, to be replayed once unfrozen
+        let permit = permit_writes(&self.target, tables)?;
+        self.target.write(batch)?;
+        drop(permit);
+        self.journal.remove(&sequence)
+    }
+}
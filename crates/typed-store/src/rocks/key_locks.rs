@@ -0,0 +1,83 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+};
+
+use once_cell::sync::Lazy;
+use rocksdb::MultiThreaded;
+
+/// The number of locks the keys of a table are spread over
+const KEY_LOCK_STRIPES: usize = 64;
+
+/// The insert locks of the tables, by database path and column family name. The entries of the
+/// tables without a live map are pruned when another lock is taken
+static INSERT_LOCKS: Lazy<Mutex<HashMap<(PathBuf, String), Weak<Mutex<()>>>>> =
+    Lazy::new(Default::default);
+
+/// The lock serializing the read-then-write operations of the column family `cf_name` of
+/// `rocksdb`, shared by all the maps of the table, and not only by the clones of a map
+pub(crate) fn insert_lock(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> Arc<Mutex<()>> {
+    let mut locks = INSERT_LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+    let key = (rocksdb.path().to_path_buf(), cf_name.to_owned());
+    if let Some(lock) = locks.get(&key).and_then(Weak::upgrade) {
+        return lock;
+    }
+    locks.retain(|_, lock| lock.strong_count() > 0);
+    let lock = Arc::new(Mutex::new(()));
+    locks.insert(key, Arc::downgrade(&lock));
+    lock
+}
+
+/// Locks over the encoded keys of a table, shared by the clones of a map.
+///
+/// The keys are spread over a fixed number of locks, so that two keys may share one: holding
+/// the locks of some keys only excludes the other holders of the locks of the same keys, and
+/// of a few unrelated ones.
+#[derive(Debug)]
+pub(crate) struct KeyLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl Default for KeyLocks {
+    fn default() -> Self {
+        Self {
+            stripes: (0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl KeyLocks {
+    /// Locks `keys` until the returned guards are dropped. The locks are always taken in the
+    /// same order, so that concurrent callers can't deadlock
+    pub(crate) fn lock<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<_> = keys
+            .into_iter()
+            .map(|key| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                hasher.finish() as usize % self.stripes.len()
+            })
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            // The locks guard no data, a panic while holding one can't leave it inconsistent
+            .map(|stripe| {
+                self.stripes[stripe]
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+            })
+            .collect()
+    }
+}
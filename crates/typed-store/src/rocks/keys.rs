@@ -1,22 +1,22 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use bincode::Options;
-
 use serde::{de::DeserializeOwned, Serialize};
 use std::marker::PhantomData;
 
-use super::{be_fix_int_ser, DBRawIteratorMultiThreaded, TypedStoreError};
+use super::{DBRawIteratorMultiThreaded, KeyFormat, TypedStoreError};
 
 /// An iterator over the keys of a prefix.
 pub struct Keys<'a, K> {
     db_iter: DBRawIteratorMultiThreaded<'a>,
+    key_format: KeyFormat,
     _phantom: PhantomData<K>,
 }
 
 impl<'a, K: DeserializeOwned> Keys<'a, K> {
-    pub(crate) fn new(db_iter: DBRawIteratorMultiThreaded<'a>) -> Self {
+    pub(crate) fn new(db_iter: DBRawIteratorMultiThreaded<'a>, key_format: KeyFormat) -> Self {
         Self {
             db_iter,
+            key_format,
             _phantom: PhantomData,
         }
     }
@@ -27,10 +27,11 @@ impl<'a, K: DeserializeOwned> Iterator for Keys<'a, K> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.db_iter.valid() {
-            let config = bincode::DefaultOptions::new()
-                .with_big_endian()
-                .with_fixint_encoding();
-            let key = self.db_iter.key().and_then(|k| config.deserialize(k).ok());
+            let key_format = self.key_format;
+            let key = self
+                .db_iter
+                .key()
+                .and_then(|k| key_format.deserialize(k).ok());
 
             self.db_iter.next();
             key
@@ -45,7 +46,7 @@ impl<'a, K: Serialize> Keys<'a, K> {
     /// and either lands on the key or the first one greater than
     /// the key.
     pub fn skip_to(mut self, key: &K) -> Result<Self, TypedStoreError> {
-        self.db_iter.seek(be_fix_int_ser(key)?);
+        self.db_iter.seek(self.key_format.serialize(key)?);
         Ok(self)
     }
 
@@ -53,7 +54,7 @@ impl<'a, K: Serialize> Keys<'a, K> {
     /// the one prior to it if it does not exist. If there is
     /// no element prior to it, it returns an empty iterator.
     pub fn skip_prior_to(mut self, key: &K) -> Result<Self, TypedStoreError> {
-        self.db_iter.seek_for_prev(be_fix_int_ser(key)?);
+        self.db_iter.seek_for_prev(self.key_format.serialize(key)?);
         Ok(self)
     }
 
@@ -0,0 +1,56 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Labels of the batches written to a database, naming the logical operation which wrote them.
+//!
+//! A batch labelled with `DBBatch::with_label`, e.g. "checkpoint commit", records the bytes it
+//! writes under its label, in the `rocksdb_batch_label_bytes` metric and in the writes reported
+//! with the next flushes of the database to `DBEventListener::on_labelled_writes_flushed`, so that
+//! the memtables flushed and compacted can be attributed to the operations which filled them.
+//! Commits slower than `SLOW_BATCH_COMMIT_THRESHOLD` are logged with their label.
+//!
+//! Labels are used in the metrics, and must be few: they should name operations, not their inputs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::metrics::DBMetrics;
+
+/// The commit latency above which the commits of labelled batches are logged
+pub const SLOW_BATCH_COMMIT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// The bytes written by label since the last flush notification, by database
+static LABELLED_WRITES: Lazy<Mutex<HashMap<String, BTreeMap<String, u64>>>> =
+    Lazy::new(Default::default);
+
+fn lock_labelled_writes() -> std::sync::MutexGuard<'static, HashMap<String, BTreeMap<String, u64>>>
+{
+    LABELLED_WRITES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn record_labelled_write(db_name: &str, label: &str, bytes: u64) {
+    DBMetrics::get()
+        .op_metrics
+        .rocksdb_batch_label_bytes
+        .with_label_values(&[db_name, label])
+        .inc_by(bytes);
+    *lock_labelled_writes()
+        .entry(db_name.to_owned())
+        .or_default()
+        .entry(label.to_owned())
+        .or_default() += bytes;
+}
+
+/// Returns the bytes written by the labelled batches of the database `db_name` since the previous
+/// call, by label. The `DBEventPoller` calls it when flushes complete: the writes reported are
+/// mostly those of the flushed memtables, and may include some still in the active memtable
+pub fn take_labelled_writes(db_name: &str) -> BTreeMap<String, u64> {
+    lock_labelled_writes().remove(db_name).unwrap_or_default()
+}
@@ -0,0 +1,194 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lifecycle events of the tables: opens, flushes, compactions and drops.
+//!
+//! Every event is logged as a structured tracing event with the `typed_store::lifecycle` target,
+//! with the database and table names, the duration of the operation and the size of the table, and
+//! is counted in the `DBMetrics`. The timeline of the storage activity of a node can then be
+//! reconstructed from its logs, e.g. to tell whether a latency spike followed a manual compaction.
+//! The flushes and compactions run by RocksDB in the background are not reported here, see
+//! `typed_store::rocks::events`.
+//!
+//! The durations are measured with the `Clock` of the map operating on the table, if any, and the
+//! events are labelled with its `db_name`, or with the name set with `set_db_name` otherwise.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use rocksdb::MultiThreaded;
+use tracing::info;
+
+use super::{Clock, TypedStoreError};
+use crate::metrics::DBMetrics;
+
+/// An operation changing the state of a whole table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TableLifecycleEvent {
+    /// The table was opened, or created, along with its database
+    Opened,
+    /// The memtable of the table was flushed to an SST file
+    Flushed,
+    /// The whole key range of the table was compacted
+    Compacted,
+    /// The column family of the table was dropped
+    Dropped,
+}
+
+impl TableLifecycleEvent {
+    /// The name of the event, as labelled in the metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            TableLifecycleEvent::Opened => "opened",
+            TableLifecycleEvent::Flushed => "flushed",
+            TableLifecycleEvent::Compacted => "compacted",
+            TableLifecycleEvent::Dropped => "dropped",
+        }
+    }
+}
+
+impl fmt::Display for TableLifecycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The size of the SST files and memtables of a table, 0 if it can't be read
+pub(crate) fn table_size_bytes(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> u64 {
+    let cf = match rocksdb.cf_handle(cf_name) {
+        Some(cf) => cf,
+        None => return 0,
+    };
+    [
+        "rocksdb.total-sst-files-size",
+        "rocksdb.cur-size-all-mem-tables",
+    ]
+    .into_iter()
+    .filter_map(|property| rocksdb.property_int_value_cf(&cf, property).ok().flatten())
+    .sum()
+}
+
+/// Logs and counts `event` on the table `cf_name`, which took `elapsed` and left it with
+/// `size_bytes`, or had them before a drop
+pub(crate) fn record_table_event(
+    db_name: &str,
+    cf_name: &str,
+    event: TableLifecycleEvent,
+    elapsed: Duration,
+    size_bytes: u64,
+) {
+    info!(
+        target: "typed_store::lifecycle",
+        db_name,
+        cf_name,
+        event = event.name(),
+        duration_ms = elapsed.as_millis() as u64,
+        size_bytes,
+        "Table {cf_name} of {db_name} {event}"
+    );
+    let metrics = &DBMetrics::get().lifecycle_metrics;
+    let labels = [db_name, cf_name, event.name()];
+    metrics
+        .rocksdb_table_lifecycle_events
+        .with_label_values(&labels)
+        .inc();
+    metrics
+        .rocksdb_table_lifecycle_seconds
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+    metrics
+        .rocksdb_table_lifecycle_size_bytes
+        .with_label_values(&labels)
+        .set(size_bytes as i64);
+}
+
+/// Records the opening of the tables `cf_names` along with their database, which took `elapsed`
+pub(crate) fn record_tables_opened<'a>(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    db_name: &str,
+    cf_names: impl IntoIterator<Item = &'a str>,
+    elapsed: Duration,
+) {
+    for cf_name in cf_names {
+        record_table_event(
+            db_name,
+            cf_name,
+            TableLifecycleEvent::Opened,
+            elapsed,
+            table_size_bytes(rocksdb, cf_name),
+        );
+    }
+}
+
+fn cf_handle<'a>(
+    rocksdb: &'a rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> Result<Arc<rocksdb::BoundColumnFamily<'a>>, TypedStoreError> {
+    rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))
+}
+
+/// Flushes the memtable of the table `cf_name`, and records it
+pub(crate) fn flush_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    db_name: &str,
+    cf_name: &str,
+    clock: &dyn Clock,
+) -> Result<(), TypedStoreError> {
+    let cf = cf_handle(rocksdb, cf_name)?;
+    let start = clock.instant();
+    rocksdb.flush_cf(&cf)?;
+    let elapsed = clock.instant().saturating_sub(start);
+    record_table_event(
+        db_name,
+        cf_name,
+        TableLifecycleEvent::Flushed,
+        elapsed,
+        table_size_bytes(rocksdb, cf_name),
+    );
+    Ok(())
+}
+
+/// Compacts the whole key range of the table `cf_name`, and records it
+pub(crate) fn compact_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    db_name: &str,
+    cf_name: &str,
+    clock: &dyn Clock,
+) -> Result<(), TypedStoreError> {
+    let cf = cf_handle(rocksdb, cf_name)?;
+    let start = clock.instant();
+    rocksdb.compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>);
+    let elapsed = clock.instant().saturating_sub(start);
+    record_table_event(
+        db_name,
+        cf_name,
+        TableLifecycleEvent::Compacted,
+        elapsed,
+        table_size_bytes(rocksdb, cf_name),
+    );
+    Ok(())
+}
+
+/// Drops the column family of the table `cf_name`, and records it with its size before the drop
+pub(crate) fn drop_table(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    db_name: &str,
+    cf_name: &str,
+    clock: &dyn Clock,
+) -> Result<(), TypedStoreError> {
+    let size_bytes = table_size_bytes(rocksdb, cf_name);
+    let start = clock.instant();
+    rocksdb.drop_cf(cf_name)?;
+    record_table_event(
+        db_name,
+        cf_name,
+        TableLifecycleEvent::Dropped,
+        clock.instant().saturating_sub(start),
+        size_bytes,
+    );
+    Ok(())
+}
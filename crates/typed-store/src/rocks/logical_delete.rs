@@ -0,0 +1,328 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Logical deletes, for tables with heavy churn.
+//!
+//! Each RocksDB delete leaves a tombstone which is only dropped once it is compacted down to the
+//! last level. Until then, the scans of the table step over all the tombstones of the deleted
+//! keys, which makes them slower and slower on tables whose keys are continuously inserted and
+//! removed. A `DBLogicalDeleteMap` instead removes a key by overwriting its value with a small
+//! tombstone value, stamped with the current generation, which the reads skip. A background job
+//! then starts a new generation, and compacts the table with a compaction filter dropping the
+//! tombstones of the previous generations.
+//!
+//! The generations start at the time of the opening of the table in milliseconds, so that the
+//! tombstones written before a restart are always from a previous generation.
+
+use std::{
+    borrow::Borrow,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::{compaction_filter::Decision, MultiThreaded};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::{
+    be_fix_int_ser, default_rocksdb_options, filters::total_order_read_options,
+    lifecycle::compact_table, open_cf_opts, Clock, DBMap, TypedStoreError,
+};
+use crate::traits::Map;
+
+/// The bincode encoding of the variant index of `Entry::Deleted`
+const TOMBSTONE_TAG: [u8; 4] = 1u32.to_le_bytes();
+
+/// The name of the compaction filter of the tables with logical deletes
+const COMPACTION_FILTER_NAME: &str = "logical_deletes";
+
+/// The value stored for each key of a `DBLogicalDeleteMap`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum Entry<V> {
+    Live(V),
+    Deleted { generation: u64 },
+}
+
+/// Returns the generation of the tombstone `value`, or None if it holds a live value
+fn tombstone_generation(value: &[u8]) -> Option<u64> {
+    if value.len() != TOMBSTONE_TAG.len() + 8 || value[..TOMBSTONE_TAG.len()] != TOMBSTONE_TAG {
+        return None;
+    }
+    let generation = value[TOMBSTONE_TAG.len()..].try_into().ok()?;
+    Some(u64::from_le_bytes(generation))
+}
+
+/// The generations of the tombstones of a table, shared with its compaction filter
+#[derive(Clone, Debug)]
+pub struct LogicalDeletes {
+    inner: Arc<Generations>,
+}
+
+#[derive(Debug)]
+struct Generations {
+    /// The generation stamped on the tombstones written now
+    current: AtomicU64,
+    /// The tombstones of this generation and the previous ones are dropped by the compactions
+    purgeable: AtomicU64,
+}
+
+impl Default for LogicalDeletes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogicalDeletes {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            inner: Arc::new(Generations {
+                current: AtomicU64::new(now),
+                purgeable: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Sets the compaction filter dropping the purgeable tombstones in `options`, which must then
+    /// be the options of the table. The table can't have another compaction filter
+    pub fn apply_to_options(&self, options: &mut rocksdb::Options) {
+        let generations = self.inner.clone();
+        options.set_compaction_filter(COMPACTION_FILTER_NAME, move |_level, _key, value| {
+            match tombstone_generation(value) {
+                Some(generation) if generation <= generations.purgeable.load(Ordering::Acquire) => {
+                    Decision::Remove
+                }
+                _ => Decision::Keep,
+            }
+        });
+    }
+
+    /// The generation stamped on the tombstones written now
+    pub fn generation(&self) -> u64 {
+        self.inner.current.load(Ordering::Acquire)
+    }
+
+    /// Starts a new generation, making the tombstones of the previous ones purgeable, and returns
+    /// the last purgeable generation
+    fn next_generation(&self) -> u64 {
+        let previous = self.inner.current.fetch_add(1, Ordering::AcqRel);
+        self.inner.purgeable.fetch_max(previous, Ordering::AcqRel);
+        previous
+    }
+}
+
+/// A table whose removals write tombstone values, dropped later by a compaction filter, instead
+/// of RocksDB tombstones. See the module documentation.
+///
+/// All the accesses to the table must go through a `DBLogicalDeleteMap`, or they would see the
+/// tombstones as values. The table can't have a value codec.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// let map = DBLogicalDeleteMap::<u64, String>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// map.insert(&1, &"a".to_owned()).unwrap();
+/// map.insert(&2, &"b".to_owned()).unwrap();
+/// map.remove(&1).unwrap();
+/// assert_eq!(map.get(&1).unwrap(), None);
+/// assert_eq!(map.iter().collect::<Vec<_>>(), vec![(2, "b".to_owned())]);
+/// map.compact_tombstones().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct DBLogicalDeleteMap<K, V> {
+    map: DBMap<K, Entry<V>>,
+    deletes: LogicalDeletes,
+}
+
+impl<K, V> DBLogicalDeleteMap<K, V> {
+    /// Opens a database from a path, with specific options and an optional column family, setting
+    /// the compaction filter of the table
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        db_options: Option<rocksdb::Options>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        let deletes = LogicalDeletes::new();
+        let mut cf_options = db_options.clone().unwrap_or_else(default_rocksdb_options);
+        deletes.apply_to_options(&mut cf_options);
+        let cf_key = opt_cf.unwrap_or(rocksdb::DEFAULT_COLUMN_FAMILY_NAME);
+        let rocksdb = open_cf_opts(path, db_options, &[(cf_key, &cf_options)])?;
+        Self::reopen(&rocksdb, opt_cf, deletes)
+    }
+
+    /// Reopens an open database under a specific column family, see `DBMap::reopen`. The options
+    /// of the table must have been set by `deletes.apply_to_options`
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        opt_cf: Option<&str>,
+        deletes: LogicalDeletes,
+    ) -> Result<Self, TypedStoreError> {
+        Ok(Self {
+            map: DBMap::reopen(db, opt_cf)?,
+            deletes,
+        })
+    }
+
+    /// The generations of the tombstones of the table
+    pub fn logical_deletes(&self) -> &LogicalDeletes {
+        &self.deletes
+    }
+
+    /// Starts a new generation, and compacts the whole table to drop the tombstones of the
+    /// previous ones. Returns the last generation purged.
+    ///
+    /// The compaction rewrites the whole table, it is meant to be run periodically in the
+    /// background, see `spawn_tombstone_compaction`
+    pub fn compact_tombstones(&self) -> Result<u64, TypedStoreError> {
+        compact_tombstones(
+            &self.map.rocksdb,
+            &self.map.db_name,
+            &self.map.cf,
+            self.map.clock.as_ref(),
+            &self.deletes,
+        )
+    }
+
+    /// Spawns a task compacting the tombstones of the table every `interval`, see
+    /// `compact_tombstones`. The task stops once the database is closed.
+    pub fn spawn_tombstone_compaction(&self, interval: Duration) -> JoinHandle<()> {
+        let rocksdb: Weak<_> = Arc::downgrade(&self.map.rocksdb);
+        let db_name = self.map.db_name.clone();
+        let cf = self.map.cf.clone();
+        let clock = self.map.clock.clone();
+        let deletes = self.deletes.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            // The first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let db = match rocksdb.upgrade() {
+                    Some(db) => db,
+                    None => {
+                        debug!("Database is closed, stopping the tombstone compaction of {cf}");
+                        break;
+                    }
+                };
+                if let Err(e) = compact_tombstones(&db, &db_name, &cf, clock.as_ref(), &deletes) {
+                    warn!("Failed to compact the tombstones of {cf}: {e}");
+                }
+            }
+        })
+    }
+}
+
+fn compact_tombstones(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    db_name: &str,
+    cf_name: &str,
+    clock: &dyn Clock,
+    deletes: &LogicalDeletes,
+) -> Result<u64, TypedStoreError> {
+    let purged = deletes.next_generation();
+    compact_table(rocksdb, db_name, cf_name, clock)?;
+    debug!("Compacted the tombstones of {cf_name} up to generation {purged}");
+    Ok(purged)
+}
+
+impl<K, V> DBLogicalDeleteMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        Ok(match self.map.get(key)? {
+            Some(Entry::Live(value)) => Some(value),
+            Some(Entry::Deleted { .. }) | None => None,
+        })
+    }
+
+    pub fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    pub fn multi_get<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<Option<V>>, TypedStoreError> {
+        Ok(self
+            .map
+            .multi_get(keys)?
+            .into_iter()
+            .map(|entry| match entry {
+                Some(Entry::Live(value)) => Some(value),
+                Some(Entry::Deleted { .. }) | None => None,
+            })
+            .collect())
+    }
+
+    pub fn insert(&self, key: &K, value: &V) -> Result<(), TypedStoreError> {
+        // An `Entry<&V>` serializes as the `Entry<V>` of the table, without cloning the value
+        let value_buf = bincode::serialize(&Entry::Live(value))?;
+        let _permit = self.map.permit_writes()?;
+        self.map.rocksdb.put_cf_opt(
+            &self.map.cf(),
+            be_fix_int_ser(key)?,
+            value_buf,
+            &self.map.write_options(),
+        )?;
+        Ok(())
+    }
+
+    /// Removes `key` by writing a tombstone value stamped with the current generation
+    pub fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
+        self.multi_remove([key])
+    }
+
+    /// Atomically removes `keys`, see `remove`
+    pub fn multi_remove<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<(), TypedStoreError> {
+        let tombstone = Entry::Deleted {
+            generation: self.deletes.generation(),
+        };
+        self.map
+            .batch()
+            .insert_batch(&self.map, keys.into_iter().map(|key| (key, &tombstone)))?
+            .write()?;
+        Ok(())
+    }
+
+    /// Iterates over the live entries of the table in key order
+    pub fn iter(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.map.iter().filter_map(|(key, entry)| match entry {
+            Entry::Live(value) => Some((key, value)),
+            Entry::Deleted { .. } => None,
+        })
+    }
+
+    /// Iterates over the keys of the live entries of the table in key order
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.iter().map(|(key, _)| key)
+    }
+
+    /// The number of tombstones in the table, which are not compacted yet. Scans the table
+    pub fn tombstones(&self) -> usize {
+        let mut db_iter = self
+            .map
+            .rocksdb
+            .raw_iterator_cf_opt(&self.map.cf(), total_order_read_options());
+        db_iter.seek_to_first();
+        let mut tombstones = 0;
+        while let Some(value) = db_iter.value() {
+            if tombstone_generation(value).is_some() {
+                tombstones += 1;
+            }
+            db_iter.next();
+        }
+        tombstones
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+
+use super::TypedStoreError;
+
+/// The SST files of a table in one level of the LSM tree
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelStats {
+    pub level: usize,
+    pub num_files: u64,
+    pub size_bytes: u64,
+    pub num_entries: u64,
+    pub num_deletions: u64,
+}
+
+/// The shape of the LSM tree of a table, see `lsm_report`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LsmReport {
+    pub table: String,
+    /// One entry per level, from level 0 to the last non empty level
+    pub levels: Vec<LevelStats>,
+    pub total_size_bytes: u64,
+    /// The number of files a point lookup may have to read in the worst case:
+    /// every level 0 file, plus one file per non empty level below
+    pub estimated_read_amplification: u64,
+    pub num_immutable_memtables: u64,
+    pub memtables_size_bytes: u64,
+    pub pending_compaction_bytes: u64,
+    pub compaction_pending: bool,
+    /// The `rocksdb.cfstats` report, with the compaction statistics of each level
+    pub cfstats: Option<String>,
+}
+
+/// Returns the per level file counts and sizes of the table `cf_name`, along with read
+/// amplification and compaction estimates, to base tuning decisions (level sizes,
+/// compaction style...) on the actual shape of the data.
+pub fn lsm_report(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> Result<LsmReport, TypedStoreError> {
+    let cf = rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+
+    let mut levels: Vec<LevelStats> = Vec::new();
+    for file in rocksdb.live_files()? {
+        if file.column_family_name != cf_name {
+            continue;
+        }
+        let level = file.level.max(0) as usize;
+        while levels.len() <= level {
+            levels.push(LevelStats {
+                level: levels.len(),
+                ..Default::default()
+            });
+        }
+        let stats = &mut levels[level];
+        stats.num_files += 1;
+        stats.size_bytes += file.size as u64;
+        stats.num_entries += file.num_entries;
+        stats.num_deletions += file.num_deletions;
+    }
+
+    let property = |name: &str| -> Result<u64, TypedStoreError> {
+        Ok(rocksdb.property_int_value_cf(&cf, name)?.unwrap_or(0))
+    };
+    let estimated_read_amplification = levels
+        .iter()
+        .map(|stats| match stats.level {
+            0 => stats.num_files,
+            _ => (stats.num_files > 0) as u64,
+        })
+        .sum();
+
+    Ok(LsmReport {
+        table: cf_name.to_owned(),
+        total_size_bytes: levels.iter().map(|stats| stats.size_bytes).sum(),
+        levels,
+        estimated_read_amplification,
+        num_immutable_memtables: property("rocksdb.num-immutable-mem-table")?,
+        memtables_size_bytes: property("rocksdb.cur-size-all-mem-tables")?,
+        pending_compaction_bytes: property("rocksdb.estimate-pending-compaction-bytes")?,
+        compaction_pending: property("rocksdb.compaction-pending")? > 0,
+        cfstats: rocksdb.property_value_cf(&cf, "rocksdb.cfstats")?,
+    })
+}
@@ -0,0 +1,214 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use rocksdb::{BlockBasedOptions, Cache, MultiThreaded};
+
+use super::{set_large_table_block_options, FilterPolicy, TypedStoreError};
+
+/// The fractions of a `MemoryBudget` given to each kind of memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryShares {
+    /// The data, index and filter blocks cached for reads
+    pub block_cache: f64,
+    /// The memtables of all the tables
+    pub write_buffers: f64,
+    /// The blocks pinned in the block cache by open iterators, on top of the cached blocks
+    pub pinned_iterators: f64,
+}
+
+impl Default for MemoryShares {
+    fn default() -> Self {
+        Self {
+            block_cache: 0.5,
+            write_buffers: 0.4,
+            pinned_iterators: 0.1,
+        }
+    }
+}
+
+/// A single byte budget for the memory of a database, divided among its block cache, write buffers and
+/// pinned iterators according to `MemoryShares`, and among its tables according to their weights.
+///
+/// The budget is enforced by RocksDB: the block cache is shared by all the tables and sized to the block
+/// cache and pinned iterators shares, and the total size of the memtables is limited to the write buffers share.
+///
+/// ```
+/// use typed_store::rocks::*;
+///
+/// let budget = MemoryBudget::new(256 << 20).unwrap();
+/// let mut db_options = default_rocksdb_options();
+/// budget.apply_to_db_options(&mut db_options);
+/// let table_options = budget.table_options(default_rocksdb_options(), &[("hot", 3), ("cold", 1)], "hot");
+/// let rocksdb = open_cf_opts(
+///     tempfile::tempdir().unwrap(),
+///     Some(db_options),
+///     &[("hot", &table_options)],
+/// )
+/// .unwrap();
+/// assert!(budget.usage(&rocksdb, &["hot"]).unwrap().total() <= budget.total_bytes());
+/// ```
+#[derive(Clone)]
+pub struct MemoryBudget {
+    total_bytes: usize,
+    shares: MemoryShares,
+    cache: Cache,
+}
+
+/// The memory used by a database, see `MemoryBudget::usage`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub block_cache_bytes: usize,
+    pub pinned_bytes: usize,
+    pub memtable_bytes: usize,
+    pub table_readers_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.block_cache_bytes + self.memtable_bytes + self.table_readers_bytes
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(total_bytes: usize) -> Result<Self, TypedStoreError> {
+        Self::with_shares(total_bytes, MemoryShares::default())
+    }
+
+    /// Divides `total_bytes` according to `shares`, which are normalized to sum to 1.
+    /// Fails if a share is negative or not finite, or if the shares sum to zero
+    pub fn with_shares(total_bytes: usize, shares: MemoryShares) -> Result<Self, TypedStoreError> {
+        let parts = [
+            shares.block_cache,
+            shares.write_buffers,
+            shares.pinned_iterators,
+        ];
+        let sum: f64 = parts.iter().sum();
+        if parts.iter().any(|share| !share.is_finite() || *share < 0.0) || sum <= 0.0 {
+            return Err(TypedStoreError::InvalidConfiguration(format!(
+                "the memory shares must be finite, non negative and not all zero: {shares:?}"
+            )));
+        }
+        let shares = MemoryShares {
+            block_cache: shares.block_cache / sum,
+            write_buffers: shares.write_buffers / sum,
+            pinned_iterators: shares.pinned_iterators / sum,
+        };
+        let cache_bytes = total_bytes as f64 * (shares.block_cache + shares.pinned_iterators);
+        Ok(Self {
+            total_bytes,
+            shares,
+            cache: Cache::new_lru_cache(cache_bytes as usize)?,
+        })
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn block_cache_bytes(&self) -> usize {
+        (self.total_bytes as f64 * self.shares.block_cache) as usize
+    }
+
+    pub fn write_buffer_bytes(&self) -> usize {
+        (self.total_bytes as f64 * self.shares.write_buffers) as usize
+    }
+
+    pub fn pinned_iterator_bytes(&self) -> usize {
+        (self.total_bytes as f64 * self.shares.pinned_iterators) as usize
+    }
+
+    /// Limits the total size of the memtables of the database to the write buffers share
+    pub fn apply_to_db_options(&self, options: &mut rocksdb::Options) {
+        options.set_db_write_buffer_size(self.write_buffer_bytes());
+    }
+
+    /// Returns `options` for `table`, with a share of the write buffers proportional to its weight among
+    /// `weights`, and the block cache of the budget. The block based table options of `options` are replaced.
+    pub fn table_options(
+        &self,
+        options: rocksdb::Options,
+        weights: &[(&str, u32)],
+        table: &str,
+    ) -> rocksdb::Options {
+        self.table_options_with_filter(options, weights, table, None)
+    }
+
+    /// Returns the options of `table` like `table_options`, with the `filter` if any
+    pub fn table_options_with_filter(
+        &self,
+        options: rocksdb::Options,
+        weights: &[(&str, u32)],
+        table: &str,
+        filter: Option<FilterPolicy>,
+    ) -> rocksdb::Options {
+        self.table_options_impl(options, weights, table, filter, false)
+    }
+
+    /// Returns the options of `table` like `table_options_with_filter`, with partitioned indexes and
+    /// filters cached in the block cache of the budget, see `set_large_table_block_options`
+    pub fn large_table_options(
+        &self,
+        options: rocksdb::Options,
+        weights: &[(&str, u32)],
+        table: &str,
+        filter: FilterPolicy,
+    ) -> rocksdb::Options {
+        self.table_options_impl(options, weights, table, Some(filter), true)
+    }
+
+    fn table_options_impl(
+        &self,
+        mut options: rocksdb::Options,
+        weights: &[(&str, u32)],
+        table: &str,
+        filter: Option<FilterPolicy>,
+        large_table: bool,
+    ) -> rocksdb::Options {
+        let total_weight: u64 = weights.iter().map(|(_, w)| *w as u64).sum();
+        let weight = weights
+            .iter()
+            .find(|(name, _)| *name == table)
+            .map_or(1, |(_, w)| *w as u64);
+        let table_bytes = (self.write_buffer_bytes() as u128 * weight as u128
+            / total_weight.max(1) as u128) as usize;
+        // RocksDB keeps up to 2 write buffers per table by default, one being flushed
+        options.set_write_buffer_size((table_bytes / 2).max(1 << 20));
+        options.set_max_write_buffer_number(2);
+
+        let mut block_options = BlockBasedOptions::default();
+        block_options.set_block_cache(&self.cache);
+        if large_table {
+            set_large_table_block_options(&mut block_options);
+        }
+        if let Some(filter) = filter {
+            filter.apply_to_block_options(&mut block_options);
+            filter.apply_to_options(&mut options);
+        }
+        options.set_block_based_table_factory(&block_options);
+        options
+    }
+
+    /// Returns the memory currently used by the given tables of `rocksdb`, opened with this budget
+    pub fn usage(
+        &self,
+        rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+        cfs: &[&str],
+    ) -> Result<MemoryUsage, TypedStoreError> {
+        let mut usage = MemoryUsage {
+            block_cache_bytes: self.cache.get_usage(),
+            pinned_bytes: self.cache.get_pinned_usage(),
+            ..Default::default()
+        };
+        for cf_name in cfs {
+            let cf = rocksdb
+                .cf_handle(cf_name)
+                .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_string()))?;
+            usage.memtable_bytes += rocksdb
+                .property_int_value_cf(&cf, "rocksdb.cur-size-all-mem-tables")?
+                .unwrap_or_default() as usize;
+            usage.table_readers_bytes += rocksdb
+                .property_int_value_cf(&cf, "rocksdb.estimate-table-readers-mem")?
+                .unwrap_or_default() as usize;
+        }
+        Ok(usage)
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use serde::Serialize;
+
+use super::KeyFormat;
+
+/// Merges iterators over tables with the same key type, e.g. the per-epoch shards of a logical
+/// table, into a single iterator in key order.
+///
+/// Each iterator is given with a tag, returned with its entries to tell which source they come
+/// from. The iterators must be in ascending key order, as returned by `DBMap::iter`, and the
+/// entries are merged in the order of the tables, i.e. by serialized key. The entries of equal
+/// keys are returned in the order of their sources. The tables must have the default `KeyFormat`,
+/// see `merge_iter_with_key_format` otherwise.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::traits::Map;
+/// let epoch_1 = DBMap::<u64, String>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// let epoch_2 = DBMap::<u64, String>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// epoch_1.insert(&1, &"a".to_owned()).unwrap();
+/// epoch_1.insert(&3, &"c".to_owned()).unwrap();
+/// epoch_2.insert(&2, &"b".to_owned()).unwrap();
+/// let merged: Vec<_> = merge_iter([(1, epoch_1.iter()), (2, epoch_2.iter())]).collect();
+/// assert_eq!(
+///     merged,
+///     vec![(1, 1, "a".to_owned()), (2, 2, "b".to_owned()), (1, 3, "c".to_owned())]
+/// );
+/// ```
+pub fn merge_iter<S, I, K, V>(sources: impl IntoIterator<Item = (S, I)>) -> MergeIter<S, I, K, V>
+where
+    S: Clone,
+    I: Iterator<Item = (K, V)>,
+    K: Serialize,
+{
+    merge_iter_with_key_format(KeyFormat::default(), sources)
+}
+
+/// Like `merge_iter`, for tables whose keys are serialized in `key_format`
+pub fn merge_iter_with_key_format<S, I, K, V>(
+    key_format: KeyFormat,
+    sources: impl IntoIterator<Item = (S, I)>,
+) -> MergeIter<S, I, K, V>
+where
+    S: Clone,
+    I: Iterator<Item = (K, V)>,
+    K: Serialize,
+{
+    let mut merged = MergeIter {
+        sources: Vec::new(),
+        heads: BinaryHeap::new(),
+        key_format,
+    };
+    for (tag, iter) in sources {
+        merged.sources.push((tag, iter));
+        merged.advance(merged.sources.len() - 1);
+    }
+    merged
+}
+
+/// The next entry of a source, ordered so that the heap returns the smallest key first
+struct Head<K, V> {
+    key_bytes: Vec<u8>,
+    source: usize,
+    key: K,
+    value: V,
+}
+
+impl<K, V> PartialEq for Head<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K, V> Eq for Head<K, V> {}
+
+impl<K, V> PartialOrd for Head<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K, V> Ord for Head<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&other.key_bytes, other.source).cmp(&(&self.key_bytes, self.source))
+    }
+}
+
+/// An iterator merging several table iterators in key order, see `merge_iter`
+pub struct MergeIter<S, I, K, V> {
+    sources: Vec<(S, I)>,
+    heads: BinaryHeap<Head<K, V>>,
+    key_format: KeyFormat,
+}
+
+impl<S, I, K, V> MergeIter<S, I, K, V>
+where
+    I: Iterator<Item = (K, V)>,
+    K: Serialize,
+{
+    /// Pushes the next entry of the source at index `source` to the heads
+    fn advance(&mut self, source: usize) {
+        if let Some((key, value)) = self.sources[source].1.next() {
+            // The keys were deserialized from a table, they serialize back
+            let key_bytes = self
+                .key_format
+                .serialize(&key)
+                .expect("keys read from a table serialize");
+            self.heads.push(Head {
+                key_bytes,
+                source,
+                key,
+                value,
+            });
+        }
+    }
+}
+
+impl<S, I, K, V> Iterator for MergeIter<S, I, K, V>
+where
+    S: Clone,
+    I: Iterator<Item = (K, V)>,
+    K: Serialize,
+{
+    type Item = (S, K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let head = self.heads.pop()?;
+        self.advance(head.source);
+        Some((self.sources[head.source].0.clone(), head.key, head.value))
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The column family holding the metadata of typed-store, e.g. the compatibility record of the
+//! database.
+//!
+//! The metadata is kept out of the default column family, which is a table like the others when a
+//! `DBMap` is opened without a column family name, so that it is never iterated, counted or
+//! cleared along with the entries of a table. The column family is created at the first write of
+//! metadata, and is neither reported as an unknown column family at open nor as an orphan.
+
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rocksdb::MultiThreaded;
+
+use super::{default_rocksdb_options, TypedStoreError};
+
+/// The name of the column family of the metadata
+pub const METADATA_CF: &str = "typed_store_metadata";
+
+/// Serializes the creations of the metadata column family, which fail if it already exists
+static CREATE_METADATA_CF: Lazy<Mutex<()>> = Lazy::new(Default::default);
+
+/// Reads the metadata `key`, `None` if the database has no metadata column family yet
+pub(crate) fn read_metadata(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, TypedStoreError> {
+    match rocksdb.cf_handle(METADATA_CF) {
+        Some(cf) => Ok(rocksdb.get_cf(&cf, key)?),
+        None => Ok(None),
+    }
+}
+
+/// Writes the metadata `key`, creating the metadata column family if needed
+pub(crate) fn write_metadata(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), TypedStoreError> {
+    if rocksdb.cf_handle(METADATA_CF).is_none() {
+        let _guard = CREATE_METADATA_CF
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if rocksdb.cf_handle(METADATA_CF).is_none() {
+            rocksdb.create_cf(METADATA_CF, &default_rocksdb_options())?;
+        }
+    }
+    let cf = rocksdb
+        .cf_handle(METADATA_CF)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(METADATA_CF.to_owned()))?;
+    rocksdb.put_cf(&cf, key, value)?;
+    Ok(())
+}
+
+/// Whether `cf` is the default or the metadata column family, which are never reported as unknown
+/// column families or orphans
+pub(crate) fn is_internal_cf(cf: &str) -> bool {
+    cf == rocksdb::DEFAULT_COLUMN_FAMILY_NAME || cf == METADATA_CF
+}
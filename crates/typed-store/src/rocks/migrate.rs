@@ -0,0 +1,78 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+use super::{ChunkProgress, DBMap, TypedStoreError, DEFAULT_CHUNK_MAX_BYTES};
+
+/// The number of entries written per chunk by `TableMigration::apply`
+pub const MIGRATION_CHUNK_SIZE: usize = 10_000;
+
+/// How a table of a new layout of a struct of tables is filled from the tables of its previous
+/// layout, see `#[dbmap_utils(migrate_from = "...")]`.
+///
+/// The entries are written into the column family of the table of the new layout, which must be
+/// another column family than those of the tables they are read from: both layouts are open at once,
+/// so a table can't be converted in place. The table is not cleared first.
+pub enum TableMigration<'a, K, V> {
+    /// The table is left as is, e.g. a new table, or a table whose layout didn't change
+    Keep,
+    /// The table is filled with the entries of an iterator
+    Fill(Box<dyn Iterator<Item = (K, V)> + 'a>),
+}
+
+impl<'a, K, V> Default for TableMigration<'a, K, V> {
+    fn default() -> Self {
+        TableMigration::Keep
+    }
+}
+
+impl<'a, K: DeserializeOwned + 'a, V: DeserializeOwned + 'a> TableMigration<'a, K, V> {
+    /// Fills the table with the entries of `old`, e.g. a table which was renamed
+    pub fn copy(old: &'a DBMap<K, V>) -> Self {
+        TableMigration::Fill(Box::new(old.iter()))
+    }
+}
+
+impl<'a, K: 'a, V: 'a> TableMigration<'a, K, V> {
+    /// Fills the table with the entries of `old` converted by `convert`, leaving out those it
+    /// returns `None` for
+    pub fn convert<OK: DeserializeOwned + 'a, OV: DeserializeOwned + 'a>(
+        old: &'a DBMap<OK, OV>,
+        convert: impl FnMut((OK, OV)) -> Option<(K, V)> + 'a,
+    ) -> Self {
+        TableMigration::Fill(Box::new(old.iter().filter_map(convert)))
+    }
+
+    /// Fills the table with `entries`
+    pub fn fill(entries: impl IntoIterator<Item = (K, V)> + 'a) -> Self {
+        TableMigration::Fill(Box::new(entries.into_iter()))
+    }
+}
+
+impl<'a, K: Serialize, V: Serialize> TableMigration<'a, K, V> {
+    /// Writes the entries of the migration to `table` in chunks of `MIGRATION_CHUNK_SIZE`
+    /// entries. Returns the progress of the writes, `None` if the table is kept
+    pub fn apply(self, table: &DBMap<K, V>) -> Result<Option<ChunkProgress>, TypedStoreError> {
+        let entries = match self {
+            TableMigration::Keep => return Ok(None),
+            TableMigration::Fill(entries) => entries,
+        };
+        let cf = table.cf.clone();
+        let progress = table
+            .chunked_batch(MIGRATION_CHUNK_SIZE, DEFAULT_CHUNK_MAX_BYTES)
+            .with_progress(move |progress| {
+                info!(
+                    "Migrated {} entries to {cf} so far",
+                    progress.entries_committed
+                )
+            })
+            .insert_batch(table, entries)?
+            .write()?;
+        info!(
+            "Migrated {} entries to {}",
+            progress.entries_committed, table.cf
+        );
+        Ok(Some(progress))
+    }
+}
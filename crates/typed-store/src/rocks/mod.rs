@@ -1,21 +1,199 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-mod errors;
+mod accumulator;
+mod analysis;
+#[cfg(feature = "archive")]
+mod archive;
+mod auto_flush;
+mod background;
+#[cfg(feature = "bitmap")]
+pub mod bitmap;
+#[cfg(feature = "bundle")]
+mod bundle;
+mod cf_options;
+mod chunked;
+mod clock;
+mod codec;
+mod compare;
+mod compatibility;
+mod durability;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod events;
+mod export;
+mod filters;
+mod freeze;
+mod hashing;
+mod idempotency;
+mod index;
+mod ingest;
 mod iter;
+mod journal;
+mod key_locks;
 mod keys;
+mod labels;
+mod lifecycle;
+mod logical_delete;
+mod lsm;
+mod memory_budget;
+mod merge;
+mod metadata;
+mod migrate;
+mod multimap;
+mod open_progress;
+mod options_summary;
+mod orphans;
+mod prefetch;
+mod presets;
+mod quota;
+mod read_amp;
+mod read_only;
+mod recovery;
+mod replica;
+mod retain;
+mod runtime_options;
+mod scan_checkpoint;
+mod schema;
+mod secondary;
+mod session;
+mod set;
+mod split;
+pub mod statistics;
+mod swap;
+mod throttle;
+mod timeseries;
+mod validation;
 mod values;
+mod watch;
 
-use crate::traits::Map;
-use bincode::Options;
+use crate::{
+    metrics::DBMetrics,
+    traits::{Map, TableSummary},
+};
 use collectable::TryExtend;
-use rocksdb::{ColumnFamilyDescriptor, DBWithThreadMode, MultiThreaded, WriteBatch};
-use serde::{de::DeserializeOwned, Serialize};
-use std::{borrow::Borrow, collections::BTreeMap, env, marker::PhantomData, path::Path, sync::Arc};
+use rocksdb::{
+    ColumnFamilyDescriptor, DBWithThreadMode, MultiThreaded, ReadOptions, WriteBatch, WriteOptions,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    env,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tap::TapFallible;
-use tracing::{debug, info, instrument};
-
-use self::{iter::Iter, keys::Keys, values::Values};
-pub use errors::TypedStoreError;
+use tracing::{debug, info, instrument, warn};
+
+use self::{
+    accumulator::{accumulate_table, AccumulatedTable, AccumulatorUpdates},
+    codec::{decode_value, encode_value, ValueEncoding},
+    filters::total_order_read_options,
+    freeze::{permit_writes, WritePermit},
+    index::PendingIndexUpdate,
+    ingest::ingest_sorted,
+    iter::Iter,
+    key_locks::{insert_lock, KeyLocks},
+    keys::Keys,
+    read_amp::ReadAmpSampler,
+    retain::retain_entries,
+    throttle::{ThrottledWrite, WriteThrottle},
+    values::Values,
+    watch::{PrefixWatchers, RawChange},
+};
+#[cfg(feature = "archive")]
+pub use crate::archive::{TableArchiveReader, ARCHIVE_MAGIC};
+pub(crate) use crate::codec::be_fix_int_ser;
+pub use crate::codec::{KeyFormat, ValueCodec, ValueFormat, ValueVersion, VersionedCodec};
+pub use crate::errors::TypedStoreError;
+pub use crate::export::read_raw_export;
+pub use accumulator::Accumulator;
+pub use analysis::{
+    analyze_table_sizes, byte_prefix, collect_prefix_stats, key_projection, PrefixStats,
+    PrefixUsage, SizeAnalysisOptions, SizeHistogram, SizeReport,
+};
+#[cfg(feature = "archive")]
+pub use archive::archive_table;
+pub use auto_flush::{
+    spawn_auto_flush, spawn_auto_flush_with_clock, AutoFlusher, DEFAULT_AUTO_FLUSH_INTERVAL,
+};
+pub use background::{continue_background_work, pause_background_work, BackgroundWorkGuard};
+#[cfg(feature = "bundle")]
+pub use bundle::{create_support_bundle, BundleTable, SupportBundleManifest};
+pub use chunked::{
+    ChunkProgress, ChunkedBatch, DEFAULT_CHUNK_MAX_BYTES, DEFAULT_CHUNK_MAX_ENTRIES,
+};
+pub use clock::{Clock, SystemClock};
+pub use codec::ValueCodecProvider;
+pub use compare::{
+    compare_databases, diff_checkpoints, DatabaseDiff, DiffEntry, DiffSummary, KeyRange,
+    TableChanges,
+};
+pub use compatibility::{
+    check_compatibility, record_compatibility, CompatibilityRecord, DatabaseWriter,
+    KEY_FORMATS_FEATURE, KNOWN_FEATURES, ROCKSDB_FORMAT_VERSION, SECONDARY_INDEXES_FEATURE,
+    SWAPPABLE_TABLES_FEATURE, TYPED_STORE_FORMAT_VERSION, VALUE_CODECS_FEATURE,
+};
+pub use durability::{global_durability_profile, set_global_durability_profile, DurabilityProfile};
+pub use export::{export_snapshot, ExportFormat, ExportedTable};
+pub use filters::{options_with_filter, FilterPolicy, DEFAULT_FILTER_BITS_PER_KEY};
+pub use freeze::{freeze_table, frozen_tables, is_table_frozen, unfreeze_table};
+pub use hashing::{content_hash, ContentDigest, ContentHashCheckpoint};
+pub use idempotency::{
+    is_batch_applied, prune_applied_batches, prune_applied_batches_with_clock, APPLIED_BATCHES_CF,
+};
+pub use index::{index_cf_name, SecondaryIndex};
+pub use iter::{LazyValue, LazyValuesIter, MemoryCappedIter, SnapshotPinning};
+pub use journal::{CrossDBJournal, JournalIntent};
+pub use labels::{take_labelled_writes, SLOW_BATCH_COMMIT_THRESHOLD};
+pub use lifecycle::TableLifecycleEvent;
+pub use logical_delete::{DBLogicalDeleteMap, LogicalDeletes};
+pub use lsm::{lsm_report, LevelStats, LsmReport};
+pub use memory_budget::{MemoryBudget, MemoryShares, MemoryUsage};
+pub use merge::{merge_iter, merge_iter_with_key_format, MergeIter};
+pub use metadata::METADATA_CF;
+pub use migrate::{TableMigration, MIGRATION_CHUNK_SIZE};
+pub use multimap::{DBMultiMap, MultiMapIter};
+pub use open_progress::{
+    report_open_progress, set_open_progress_callback, OpenProgress, OpenProgressCallback,
+};
+pub use options_summary::{options_summary, TableOptionsSummary};
+pub use orphans::{
+    drop_orphan_cfs, find_orphan_cfs, list_orphan_tables, warn_orphan_cfs, OrphanColumnFamily,
+};
+pub use prefetch::{PrefetchIter, DEFAULT_PREFETCH_BATCH_SIZE};
+pub use presets::{
+    default_options_for_large_table, large_table_options, point_lookup_options,
+    set_large_table_block_options, universal_compaction_options, write_heavy_options,
+};
+pub use quota::{
+    check_disk_quota, db_disk_usage, spawn_disk_quota_check, table_disk_usage, DiskQuota,
+    QuotaExceeded, DEFAULT_QUOTA_CHECK_INTERVAL,
+};
+pub use read_amp::set_perf_level;
+pub use read_only::ReadOnlyMap;
+pub use recovery::{open_cf_opts_with_repair_policy, OpenFailureReport, RepairPolicy};
+pub use replica::{replica_lag, ReplicaLag};
+pub use retain::{
+    RetainOptions, RetainProgress, DEFAULT_MIN_RANGE_DELETE, DEFAULT_RETAIN_CHUNK_SIZE,
+};
+pub use runtime_options::{apply_runtime_config, set_options, RuntimeConfig, RuntimeOptions};
+pub use scan_checkpoint::ScanCheckpoint;
+pub use schema::{check_schema_on_open, record_schema, schema_check, SchemaDrift, TableSchema};
+pub use secondary::ManagedSecondaryPath;
+pub use session::Session;
+pub use set::{Combined, DBSet};
+pub use split::{copy_key_range, split_key_ranges, table_split_points, SPLIT_COPY_BATCH_SIZE};
+pub use swap::{SwappableMap, SWAP_POINTERS_CF};
+pub use throttle::{ThrottlePolicy, WriteLimit};
+pub use timeseries::{DBTimeSeries, TimeSeriesIter};
+pub use validation::validate_options;
+pub use watch::{
+    ChangeEvent, Invalidation, InvalidationWatch, PrefixWatch, WatchError, DEFAULT_WATCH_CAPACITY,
+};
 
 // Write buffer size per RocksDB instance can be set via the env var below.
 // If the env var is not set, use the default value in MiB.
@@ -81,6 +259,32 @@ pub struct DBMap<K, V> {
     _phantom: PhantomData<fn(K) -> V>,
     // the rocksDB ColumnFamily under which the map is stored
     cf: String,
+    // whether writes through this map are flagged as low priority
+    low_priority_writes: bool,
+    // the name of the database, used to label metrics
+    db_name: String,
+    // serializes the read-then-write operations of the maps of the table
+    insert_lock: Arc<Mutex<()>>,
+    // the per key locks of the read-then-write operations, shared by the clones of the map
+    key_locks: Arc<KeyLocks>,
+    // the serialization format of the keys
+    key_format: KeyFormat,
+    // the serialization format of the values
+    value_format: ValueFormat,
+    // transforms the serialized values, e.g. to encrypt them
+    value_codec: Option<Arc<dyn ValueCodec>>,
+    // the running multiset hash of the table, shared by the clones of the map
+    accumulator: Option<Arc<Mutex<Accumulator>>>,
+    // the subscribers to the changes of the table, shared by the clones of the map
+    watchers: Arc<PrefixWatchers>,
+    // the rate limit of the writes to the table, shared by the clones of the map
+    write_throttle: Option<Arc<WriteThrottle>>,
+    // whether the writes through this map sync the WAL, instead of per the global profile
+    durability: Option<DurabilityProfile>,
+    // the sampling of the read amplification of the gets, shared by the clones of the map
+    read_amp_sampler: Option<Arc<ReadAmpSampler>>,
+    // the source of time of the write limits and of the batches of the map
+    clock: Arc<dyn Clock>,
 }
 
 unsafe impl<K: Send, V: Send> Send for DBMap<K, V> {}
@@ -101,9 +305,22 @@ impl<K, V> DBMap<K, V> {
         let rocksdb = open_cf(path, db_options, &cfs)?;
 
         Ok(DBMap {
+            db_name: default_db_name(&rocksdb),
+            insert_lock: insert_lock(&rocksdb, cf_key),
             rocksdb,
             _phantom: PhantomData,
             cf: cf_key.to_string(),
+            low_priority_writes: false,
+            key_locks: Arc::default(),
+            key_format: KeyFormat::default(),
+            value_format: ValueFormat::default(),
+            value_codec: None,
+            accumulator: None,
+            watchers: Arc::default(),
+            write_throttle: None,
+            durability: None,
+            read_amp_sampler: None,
+            clock: SystemClock::shared(),
         })
     }
 
@@ -135,11 +352,341 @@ impl<K, V> DBMap<K, V> {
             rocksdb: db.clone(),
             _phantom: PhantomData,
             cf: cf_key,
+            low_priority_writes: false,
+            db_name: default_db_name(db),
+            insert_lock: insert_lock(db, &cf_key),
+            key_locks: Arc::default(),
+            key_format: KeyFormat::default(),
+            value_format: ValueFormat::default(),
+            value_codec: None,
+            accumulator: None,
+            watchers: Arc::default(),
+            write_throttle: None,
+            durability: None,
+            read_amp_sampler: None,
+            clock: SystemClock::shared(),
         })
     }
 
     pub fn batch(&self) -> DBBatch {
-        DBBatch::new(&self.rocksdb)
+        let mut batch = DBBatch::new(&self.rocksdb)
+            .with_db_name(&self.db_name)
+            .with_clock(self.clock.clone());
+        if let Some(durability) = self.durability {
+            batch = batch.with_durability(durability);
+        }
+        if self.low_priority_writes {
+            batch.low_priority()
+        } else {
+            batch
+        }
+    }
+
+    /// Returns a session buffering writes to the tables of the database, which reads its own writes,
+    /// see `Session`
+    pub fn session(&self) -> Session {
+        Session::from_batch(self.batch())
+    }
+
+    /// Returns a map whose writes (including batches created from it) are flagged as low priority.
+    ///
+    /// RocksDB throttles low priority writes when foreground writes would otherwise stall,
+    /// which is useful for backfill or indexing jobs writing to the same database.
+    /// Wrapping the returned map in a `Store` makes all the writes of that store low priority.
+    pub fn with_low_priority_writes(mut self) -> Self {
+        self.low_priority_writes = true;
+        self
+    }
+
+    /// Returns a map whose writes through `insert` and `DBBatch::insert_batch` are limited to
+    /// `limit`, waiting or failing beyond it per `policy`, so that a background job writing through
+    /// it can't starve the other writers of the database. The limit is shared by the clones of the
+    /// returned map, and not by other maps on the table. With `ThrottlePolicy::Wait`, the writes
+    /// block the calling thread, see `typed_store::rocks::ThrottlePolicy` and `WriteLimit`
+    pub fn with_write_limit(mut self, limit: WriteLimit, policy: ThrottlePolicy) -> Self {
+        self.write_throttle = Some(Arc::new(WriteThrottle::new(limit, policy)));
+        self
+    }
+
+    /// Returns a map reading the time from `clock` instead of the system clock, in its write limits
+    /// and the batches created from it, e.g. to drive them deterministically in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Rejects the writes to the table from now on, through this map and any other, with
+    /// `TypedStoreError::TableFrozen`, while the reads continue. See `typed_store::rocks::freeze_table`
+    pub fn freeze(&self) -> Result<bool, TypedStoreError> {
+        freeze_table(&self.rocksdb, &self.cf)
+    }
+
+    /// Accepts the writes to the table again, see `freeze`. Returns whether the table was frozen
+    pub fn unfreeze(&self) -> bool {
+        unfreeze_table(&self.rocksdb, &self.cf)
+    }
+
+    /// Whether the writes to the table are rejected, see `freeze`
+    pub fn is_frozen(&self) -> bool {
+        is_table_frozen(&self.rocksdb, &self.cf)
+    }
+
+    /// Flushes the memtable of the table to disk, logging and counting it as a
+    /// `TableLifecycleEvent::Flushed`
+    pub fn flush(&self) -> Result<(), TypedStoreError> {
+        lifecycle::flush_table(&self.rocksdb, &self.db_name, &self.cf, self.clock.as_ref())
+    }
+
+    /// Compacts the whole key range of the table, e.g. to reclaim the space of a bulk delete,
+    /// logging and counting it as a `TableLifecycleEvent::Compacted`
+    pub fn compact(&self) -> Result<(), TypedStoreError> {
+        lifecycle::compact_table(&self.rocksdb, &self.db_name, &self.cf, self.clock.as_ref())
+    }
+
+    fn permit_writes(&self) -> Result<WritePermit, TypedStoreError> {
+        permit_writes(&self.rocksdb, [self.cf.as_str()])
+    }
+
+    fn throttle_writes(
+        &self,
+        ops: usize,
+        bytes: usize,
+    ) -> Result<Option<ThrottledWrite>, TypedStoreError> {
+        self.write_throttle
+            .as_ref()
+            .map(|throttle| {
+                throttle.acquire(self.clock.as_ref(), &self.db_name, &self.cf, ops, bytes)
+            })
+            .transpose()
+    }
+
+    /// Returns a map measuring the read amplification of about `fraction` of its gets (and of its
+    /// clones) with the RocksDB perf context, in the `rocksdb_read_amp_sst_files` and
+    /// `rocksdb_read_amp_blocks_read` histograms. Sampled gets are slightly slower
+    pub fn with_read_amp_sampling(mut self, fraction: f64) -> Self {
+        self.read_amp_sampler = Some(Arc::new(ReadAmpSampler::new(fraction)));
+        self
+    }
+
+    /// Returns a map whose writes (including batches created from it) sync the WAL per `profile`,
+    /// instead of per the global profile, see `DurabilityProfile`
+    pub fn with_durability(mut self, profile: DurabilityProfile) -> Self {
+        self.durability = Some(profile);
+        self
+    }
+
+    /// Returns a map labelled with `db_name` in the metrics, instead of the database directory name
+    pub fn with_db_name(mut self, db_name: &str) -> Self {
+        self.db_name = db_name.to_owned();
+        self
+    }
+
+    /// The name of the database, used to label metrics
+    pub fn db_name(&self) -> &str {
+        &self.db_name
+    }
+
+    /// Returns a map whose values are transformed by `codec` after serialization, e.g. to encrypt them.
+    /// All the maps and batches accessing the table must use the same codec.
+    pub fn with_value_codec(mut self, codec: Arc<dyn ValueCodec>) -> Self {
+        self.value_codec = Some(codec);
+        self
+    }
+
+    /// Returns a map storing its values in `format` instead of bincode, before encoding them with its
+    /// value codec if any, e.g. to encrypt them, see `typed_store::codec::ValueFormat`. All the maps
+    /// accessing the table must use the same format.
+    pub fn with_value_format(mut self, format: ValueFormat) -> Self {
+        self.value_format = format;
+        self
+    }
+
+    /// Returns a map storing its keys in `format` instead of big endian bincode, see
+    /// `typed_store::codec::KeyFormat`. All the maps accessing the table must use the same format,
+    /// and the prefixes and bounds given to the map are encoded in it too.
+    pub fn with_key_format(mut self, format: KeyFormat) -> Self {
+        self.key_format = format;
+        self
+    }
+
+    fn encode_key<Q: Serialize + ?Sized>(&self, key: &Q) -> Result<Vec<u8>, TypedStoreError> {
+        self.key_format.serialize(key)
+    }
+
+    fn codec(&self) -> Option<&dyn ValueCodec> {
+        self.value_codec.as_deref()
+    }
+
+    fn encoding(&self) -> ValueEncoding<'_> {
+        ValueEncoding {
+            format: self.value_format,
+            codec: self.codec(),
+        }
+    }
+
+    /// Returns a map maintaining an `Accumulator` of the entries of the table, updated by every write
+    /// through the map, its clones and the batches it is used in. The accumulator is initialized by
+    /// scanning the table, and every write then reads the previous value of the written keys.
+    ///
+    /// Writes through other maps on the same table, `CrossDBJournal`s or range deletes of the database
+    /// are not accumulated. If the map has a value codec, it must be set before the accumulator.
+    pub fn with_accumulator(mut self) -> Result<Self, TypedStoreError> {
+        let accumulator = accumulate_table(&self.rocksdb, &self.cf, self.codec())?;
+        self.accumulator = Some(Arc::new(Mutex::new(accumulator)));
+        Ok(self)
+    }
+
+    /// Returns the digest of the accumulator of the table, if the map maintains one.
+    /// Tables with the same entries have the same digest, see `Accumulator`
+    pub fn accumulated_digest(&self) -> Option<ContentDigest> {
+        self.accumulator.as_ref().map(|accumulator| {
+            accumulator
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .digest()
+        })
+    }
+
+    fn accumulated_table(&self) -> Option<AccumulatedTable> {
+        self.accumulator
+            .as_ref()
+            .map(|accumulator| AccumulatedTable {
+                accumulator: accumulator.clone(),
+                cf_name: self.cf.clone(),
+                codec: self.value_codec.clone(),
+            })
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        opts.set_low_pri(self.low_priority_writes);
+        if let Some(durability) = self.durability.or_else(global_durability_profile) {
+            durability.apply_to_write_options(&mut opts);
+        }
+        opts
+    }
+
+    /// Returns a batch which commits itself in chunks of at most `max_entries` operations
+    /// or `max_bytes` encoded bytes. See `ChunkedBatch` for its atomicity guarantees.
+    pub fn chunked_batch(&self, max_entries: usize, max_bytes: usize) -> ChunkedBatch {
+        let batch = ChunkedBatch::new(&self.rocksdb, max_entries, max_bytes);
+        if self.low_priority_writes {
+            batch.low_priority()
+        } else {
+            batch
+        }
+    }
+
+    /// Scans the table and returns its number of entries and the encoded sizes of its keys and values
+    pub fn table_summary(&self) -> Result<TableSummary, TypedStoreError> {
+        let mut summary = TableSummary::default();
+        let mut db_iter = self
+            .rocksdb
+            .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
+        db_iter.seek_to_first();
+        while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+            summary.num_keys += 1;
+            summary.key_bytes_total += key.len() as u64;
+            summary.value_bytes_total += value.len() as u64;
+            summary.max_key_bytes = summary.max_key_bytes.max(key.len() as u64);
+            summary.max_value_bytes = summary.max_value_bytes.max(value.len() as u64);
+            db_iter.next();
+        }
+        db_iter.status()?;
+        Ok(summary)
+    }
+
+    /// Computes histograms of the key and value sizes of the table, see `analyze_table_sizes`
+    pub fn size_report(&self, options: SizeAnalysisOptions) -> Result<SizeReport, TypedStoreError> {
+        analyze_table_sizes(&self.rocksdb, &self.cf, options)
+    }
+
+    /// Aggregates the entries and bytes of the table per prefix of `prefix_len` bytes of the
+    /// encoded keys, see `collect_prefix_stats`
+    pub fn byte_prefix_stats(
+        &self,
+        prefix_len: usize,
+        options: SizeAnalysisOptions,
+    ) -> Result<PrefixStats<Vec<u8>>, TypedStoreError> {
+        collect_prefix_stats(&self.rocksdb, &self.cf, options, byte_prefix(prefix_len))
+    }
+
+    /// Writes a compressed archive of the table to `writer`, see `archive_table`
+    #[cfg(feature = "archive")]
+    pub fn archive(&self, writer: impl std::io::Write) -> Result<u64, TypedStoreError> {
+        archive_table(&self.rocksdb, &self.cf, writer)
+    }
+
+    /// Returns the shape of the LSM tree of the table, see `lsm_report`
+    pub fn lsm_report(&self) -> Result<LsmReport, TypedStoreError> {
+        lsm_report(&self.rocksdb, &self.cf)
+    }
+
+    /// Returns the ranges of keys splitting the table into up to `parts` ranges of about the same
+    /// size on disk, see `table_split_points`
+    pub fn split_ranges(&self, parts: usize) -> Result<Vec<KeyRange>, TypedStoreError> {
+        Ok(split_key_ranges(&table_split_points(
+            &self.rocksdb,
+            &self.cf,
+            parts,
+        )?))
+    }
+
+    /// Copies the entries of the table within `range` to `target`, as encoded, and returns the number
+    /// of entries copied, see `copy_key_range`. `target` must have the same value format and codec, e.g. the
+    /// same table of another database for an `EnvelopeCodec`, which authenticates the values along the table
+    /// name, and its accumulator and watchers, if any, are not updated
+    pub fn copy_range_to(
+        &self,
+        range: &KeyRange,
+        target: &DBMap<K, V>,
+    ) -> Result<u64, TypedStoreError> {
+        copy_key_range(&self.rocksdb, &self.cf, range, &target.rocksdb, &target.cf)
+    }
+
+    /// Returns the digest of the entries of the table within `range`, see `ContentHashCheckpoint`.
+    /// Two nodes can compare their tables by exchanging digests instead of the data
+    pub fn content_hash(&self, range: KeyRange) -> Result<ContentDigest, TypedStoreError> {
+        let checkpoint = self.content_hash_resume(ContentHashCheckpoint::new(range), usize::MAX)?;
+        Ok(checkpoint.digest())
+    }
+
+    /// Hashes at most `max_entries` more entries of the table from `checkpoint`, so that large
+    /// tables can be hashed in several steps. The hash is done once the returned checkpoint `is_complete()`
+    pub fn content_hash_resume(
+        &self,
+        checkpoint: ContentHashCheckpoint,
+        max_entries: usize,
+    ) -> Result<ContentHashCheckpoint, TypedStoreError> {
+        content_hash(
+            &self.rocksdb,
+            &self.cf,
+            self.codec(),
+            checkpoint,
+            max_entries,
+        )
+    }
+
+    /// Returns the size of the SST files and memtables of the table, see `check_disk_quota`
+    pub fn disk_usage(&self) -> Result<u64, TypedStoreError> {
+        table_disk_usage(&self.rocksdb, &self.cf)
+    }
+
+    /// Returns how far the database is behind the database of `primary`, if it is opened as a
+    /// secondary instance of it, see `replica_lag`
+    pub fn lag<K2, V2>(&self, primary: &DBMap<K2, V2>) -> ReplicaLag {
+        replica_lag(&primary.rocksdb, &self.rocksdb)
+    }
+
+    /// Changes options of the table without reopening it, see `set_options`
+    pub fn set_options(&self, opts: &[(&str, &str)]) -> Result<(), TypedStoreError> {
+        set_options(&self.rocksdb, &self.cf, opts)
+    }
+
+    /// Changes the runtime mutable options of the table which are set in `options`
+    pub fn set_runtime_options(&self, options: &RuntimeOptions) -> Result<(), TypedStoreError> {
+        let config = [(self.cf.clone(), options.clone())].into_iter().collect();
+        apply_runtime_config(&self.rocksdb, &[&self.cf], &config)
     }
 
     fn cf(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
@@ -149,6 +696,466 @@ impl<K, V> DBMap<K, V> {
     }
 }
 
+impl<K, V> DBMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Returns the value for the given key, inserting the value returned by `init` if there is none.
+    ///
+    /// The read and the conditional write are performed under a lock of the table, shared by all its maps,
+    /// so that concurrent calls insert at most one value for a key. Other writes do not take this lock,
+    /// and can still overwrite the key concurrently.
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn get_or_insert_with<F: FnOnce() -> V>(
+        &self,
+        key: &K,
+        init: F,
+    ) -> Result<V, TypedStoreError> {
+        // The lock guards no data, a panic in `init` can't leave it inconsistent
+        let _guard = self
+            .insert_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = init();
+        self.insert(key, &value)?;
+        Ok(value)
+    }
+
+    /// Reads the values of `keys`, and atomically writes the values returned by `f` from the key
+    /// and its current value, removing the keys for which `f` returns `None`.
+    ///
+    /// The values may be changed concurrently between the read and the write, see
+    /// `update_batch_locked` to serialize the updates of the same keys. If a key is given several
+    /// times, `f` is called with the same current value, and its last result is written
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn update_batch<J, F>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+        f: F,
+    ) -> Result<(), TypedStoreError>
+    where
+        J: Borrow<K>,
+        F: Fn(&K, Option<V>) -> Option<V>,
+    {
+        let keys: Vec<J> = keys.into_iter().collect();
+        let values = self.multi_get(keys.iter().map(|k| k.borrow()))?;
+        let mut batch = self.batch();
+        for (key, value) in keys.iter().zip(values) {
+            let key = key.borrow();
+            batch = match f(key, value) {
+                Some(value) => batch.insert_batch(self, [(key, value)])?,
+                None => batch.delete_batch(self, [key])?,
+            };
+        }
+        batch.write()?;
+        Ok(())
+    }
+
+    /// Like `update_batch`, holding the locks of `keys` from the read to the write, so that the
+    /// concurrent updates of the same keys through `update_batch_locked`, by this map and its
+    /// clones, are isolated from each other. Other writes don't take the locks
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn update_batch_locked<J, F>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+        f: F,
+    ) -> Result<(), TypedStoreError>
+    where
+        J: Borrow<K>,
+        F: Fn(&K, Option<V>) -> Option<V>,
+    {
+        let keys: Vec<J> = keys.into_iter().collect();
+        let keys_bytes = keys
+            .iter()
+            .map(|k| self.encode_key(k.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let _guards = self
+            .key_locks
+            .lock(keys_bytes.iter().map(|key| key.as_slice()));
+        self.update_batch(keys, f)
+    }
+
+    /// Writes back re-encoded the values the value codec of the table asks to rewrite, e.g. the
+    /// values of older versions upgraded by a `VersionedCodec` with write back, and returns their
+    /// number. Reads never write, this maintenance pass is run on a writable handle instead.
+    ///
+    /// The table is scanned in chunks of `DEFAULT_RETAIN_CHUNK_SIZE` entries, and the stale values
+    /// of each chunk are rewritten with `update_batch_locked`, so that the rewrites go through the
+    /// watchers, the metrics, the throttle and the freeze of the table like any other batch. A
+    /// value written concurrently without `update_batch_locked` can still be overwritten by the
+    /// rewrite of its previous value
+    #[instrument(level = "debug", skip_all, fields(cf = %self.cf), err)]
+    pub fn rewrite_stale_values(&self) -> Result<usize, TypedStoreError> {
+        let codec = match self.codec() {
+            Some(codec) => codec,
+            None => return Ok(0),
+        };
+        let mut rewritten = 0;
+        let mut resume_from: Option<Vec<u8>> = None;
+        loop {
+            // A fresh iterator per chunk, so that the scan doesn't pin the table throughout
+            let mut db_iter = self
+                .rocksdb
+                .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
+            match &resume_from {
+                Some(key) => db_iter.seek(key),
+                None => db_iter.seek_to_first(),
+            }
+            let mut stale = Vec::new();
+            let mut scanned = 0;
+            while scanned < DEFAULT_RETAIN_CHUNK_SIZE {
+                let (key, value) = match (db_iter.key(), db_iter.value()) {
+                    (Some(key), Some(value)) => (key, value),
+                    _ => break,
+                };
+                scanned += 1;
+                if codec.rewrite_on_read(value) {
+                    stale.push(self.key_format.deserialize::<K>(key)?);
+                }
+                db_iter.next();
+            }
+            db_iter.status()?;
+            resume_from = db_iter.key().map(<[u8]>::to_vec);
+            drop(db_iter);
+
+            if !stale.is_empty() {
+                rewritten += stale.len();
+                // Decoding upgrades the values, encoding stores them with the current version
+                self.update_batch_locked(stale, |_, value| value)?;
+            }
+            if resume_from.is_none() {
+                break;
+            }
+        }
+        info!("Rewrote {rewritten} stale values of {}", self.cf);
+        Ok(rewritten)
+    }
+
+    /// Writes `entries`, given in the order of their encoded keys, to the table in a single SST file
+    /// ingested at once, bypassing the WAL and the memtables. Much faster than batches for bulk
+    /// loads, e.g. at genesis or when restoring a snapshot.
+    ///
+    /// Fails with `TypedStoreError::UnsortedKeys` without writing anything if a key is not greater
+    /// than the previous one, e.g. the input should then be loaded with `multi_insert` instead
+    #[instrument(level = "debug", skip_all, fields(cf = %self.cf), err)]
+    pub fn insert_sorted_batch<J: Borrow<K>, U: Borrow<V>>(
+        &self,
+        entries: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<BatchStats, TypedStoreError> {
+        ingest_sorted(self, entries)
+    }
+
+    /// Deletes the entries of the table for which `pred` returns false, with the default
+    /// `RetainOptions`, see `retain_with`
+    pub fn retain(&self, pred: impl Fn(&K, &V) -> bool) -> Result<RetainProgress, TypedStoreError> {
+        self.retain_with(RetainOptions::default(), pred)
+    }
+
+    /// Deletes the entries of the table for which `pred` returns false, scanning the table in chunks
+    /// of `options.chunk_size` entries and committing the deletes of each chunk in a batch, as point
+    /// deletes unless range deletes are enabled. Returns the overall progress.
+    ///
+    /// Like `ChunkedBatch`, the deletes are not atomic as a whole: a failure midway leaves the
+    /// chunks committed so far deleted. With `RetainOptions::with_range_deletes`, the entries
+    /// written concurrently between the first and the last key of a range delete are deleted
+    /// without being checked by `pred`, so the table must not be written meanwhile
+    #[instrument(level = "debug", skip_all, fields(cf = %self.cf), err)]
+    pub fn retain_with(
+        &self,
+        options: RetainOptions,
+        pred: impl Fn(&K, &V) -> bool,
+    ) -> Result<RetainProgress, TypedStoreError> {
+        retain_entries(self, options, pred)
+    }
+
+    /// Returns whether each of `keys` is in the table, in the order of `keys`.
+    ///
+    /// The keys excluded by the bloom filters of the table, if any, are answered without reading it,
+    /// and the others are read together with a single `multi_get`, pinning the values in the block
+    /// cache instead of copying them. Cheaper than `multi_get` when only the existence matters
+    #[instrument(level = "trace", skip_all, err)]
+    pub fn multi_contains<J>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<bool>, TypedStoreError>
+    where
+        J: Borrow<K>,
+    {
+        let _timer = DBMetrics::get()
+            .op_metrics
+            .rocksdb_multiget_latency_seconds
+            .with_label_values(&[&self.db_name, &self.cf])
+            .start_timer();
+        let cf = self.cf();
+        let keys_bytes = keys
+            .into_iter()
+            .map(|k| self.encode_key(k.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut contained = vec![false; keys_bytes.len()];
+        // `key_may_exist_cf` can have false positives, but no false negatives
+        let (positions, candidates): (Vec<_>, Vec<_>) = keys_bytes
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| self.rocksdb.key_may_exist_cf(&cf, key))
+            .unzip();
+        let results = self.rocksdb.batched_multi_get_cf(&cf, candidates, false);
+        for (position, result) in positions.into_iter().zip(results) {
+            contained[position] = result?.is_some();
+        }
+        Ok(contained)
+    }
+
+    /// Returns the values of `keys`, in the order of `keys`, waiting for the missing ones to be written.
+    ///
+    /// Like `watch_prefix`, only the writes made by this map, its clones and the batches they are used in
+    /// resolve the missing keys, not those of other maps opened on the same table
+    pub async fn notify_read<J>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<V>, TypedStoreError>
+    where
+        J: Borrow<K>,
+    {
+        let keys_bytes = keys
+            .into_iter()
+            .map(|k| self.encode_key(k.borrow()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // The waiters are registered before reading, so that the writes made meanwhile aren't missed
+        let waiters: Vec<_> = keys_bytes
+            .iter()
+            .map(|key| self.watchers.wait_for(key.clone()))
+            .collect();
+        let stored = {
+            let cf = self.cf();
+            self.rocksdb
+                .multi_get_cf(keys_bytes.iter().map(|key| (&cf, key)))
+        };
+
+        let mut values = Vec::with_capacity(keys_bytes.len());
+        for ((key, stored), waiter) in keys_bytes.iter().zip(stored).zip(waiters) {
+            match stored {
+                Ok(Some(data)) => {
+                    drop(waiter);
+                    self.watchers.forget_dropped_waiters(key);
+                    values.push(decode_value(self.encoding(), key, &data)?);
+                }
+                Ok(None) => {
+                    let value = waiter
+                        .await
+                        .expect("Waiters are only dropped once they received a value");
+                    values.push(bincode::deserialize(&value)?);
+                }
+                Err(e) => {
+                    drop(waiter);
+                    self.watchers.forget_dropped_waiters(key);
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Subscribes to the changes of the keys starting with `prefix` once encoded, e.g. the first
+    /// fields of a tuple key, or `()` for all the keys. Changes are sent after being written by this map,
+    /// its clones and the batches they are used in, but not by other maps opened on the same table.
+    ///
+    /// Each watcher buffers up to `DEFAULT_WATCH_CAPACITY` changes, a slow watcher misses the older ones
+    pub fn watch_prefix<P: Serialize + ?Sized>(
+        &self,
+        prefix: &P,
+    ) -> Result<PrefixWatch<K, V>, TypedStoreError> {
+        let prefix = self.encode_key(prefix)?;
+        Ok(PrefixWatch::new(
+            self.watchers.subscribe(prefix),
+            self.key_format,
+        ))
+    }
+
+    /// Subscribes to the keys written to the table, for an external cache of its entries to evict
+    /// them. Unlike `watch_prefix`, the values aren't decoded, and missed changes invalidate all the
+    /// entries instead of failing the watch. The same writes are seen as by `watch_prefix`
+    pub fn watch_invalidations(&self) -> InvalidationWatch<K> {
+        InvalidationWatch::new(self.watchers.subscribe(Vec::new()), self.key_format)
+    }
+
+    /// Removes the keys starting with `prefix` once encoded, e.g. `&epoch` for keys of type
+    /// `(u64, ...)`, or `&(epoch, object)` for keys of type `(u64, ObjectID, ...)`
+    pub fn remove_prefix<P: Serialize + ?Sized>(&self, prefix: &P) -> Result<(), TypedStoreError> {
+        self.batch().delete_prefix(self, prefix)?.write()?;
+        Ok(())
+    }
+
+    /// Returns an iterator over all the key-value pairs of the table which doesn't pin its state:
+    /// it sees the writes made while it runs, and lets RocksDB delete the files compacted meanwhile.
+    /// Long scans tolerating entries from different states should prefer it to `iter`
+    pub fn iter_latest(&self) -> Iter<'_, K, V> {
+        let mut readopts = total_order_read_options();
+        readopts.set_tailing(true);
+        let mut db_iter = self.rocksdb.raw_iterator_cf_opt(&self.cf(), readopts);
+        db_iter.seek_to_first();
+
+        Iter::new(
+            db_iter,
+            self.key_format,
+            self.encoding(),
+            &self.db_name,
+            &self.cf,
+        )
+        .tailing()
+    }
+
+    /// Returns an iterator over all the key-value pairs of the table as of an explicit snapshot,
+    /// released when the iterator is dropped. Like `iter`, it prevents the deletion of the files
+    /// compacted while it runs, and how long it does so is reported in the metrics
+    pub fn iter_snapshot(&self) -> Iter<'_, K, V> {
+        let snapshot = self.rocksdb.snapshot();
+        let mut readopts = total_order_read_options();
+        readopts.set_snapshot(&snapshot);
+        let mut db_iter = self.rocksdb.raw_iterator_cf_opt(&self.cf(), readopts);
+        db_iter.seek_to_first();
+
+        Iter::new(
+            db_iter,
+            self.key_format,
+            self.encoding(),
+            &self.db_name,
+            &self.cf,
+        )
+        .pinned(Some(snapshot))
+    }
+
+    /// Returns an iterator over all the key-value pairs of the table, which reads at most `bytes_per_sec`
+    /// of keys and values on average, sleeping in `next` when ahead. Meant for maintenance scans
+    /// (integrity checks, backfills) which shouldn't take the disk bandwidth of the node, and which
+    /// don't fill the block cache with the blocks they read. The rate is measured, and waited for,
+    /// per the clock of the map, see `with_clock`
+    pub fn iter_rate_limited(&self, bytes_per_sec: u64) -> Iter<'_, K, V> {
+        let mut readopts = total_order_read_options();
+        readopts.fill_cache(false);
+        let mut db_iter = self.rocksdb.raw_iterator_cf_opt(&self.cf(), readopts);
+        db_iter.seek_to_first();
+
+        Iter::new(
+            db_iter,
+            self.key_format,
+            self.encoding(),
+            &self.db_name,
+            &self.cf,
+        )
+        .pinned(None)
+        .rate_limited(bytes_per_sec, self.clock.clone())
+    }
+
+    /// Returns an iterator over all the key-value pairs of the table, which reads batches of `batch_size` entries
+    /// on a background thread and deserializes them on the rayon thread pool. See `PrefetchIter`.
+    pub fn prefetching_iter(&self, batch_size: usize) -> PrefetchIter<K, V>
+    where
+        K: Send + 'static,
+        V: Send + 'static,
+    {
+        PrefetchIter::new(
+            self.rocksdb.clone(),
+            self.cf.clone(),
+            self.key_format,
+            self.value_format,
+            self.value_codec.clone(),
+            batch_size,
+        )
+    }
+
+    /// Aggregates the entries and bytes of the table per `project` of their keys, e.g. the epoch
+    /// of `(epoch, digest)` keys, see `collect_prefix_stats`
+    pub fn prefix_stats<P: Ord>(
+        &self,
+        options: SizeAnalysisOptions,
+        project: impl Fn(&K) -> P,
+    ) -> Result<PrefixStats<P>, TypedStoreError> {
+        let key_format = self.key_format;
+        collect_prefix_stats(&self.rocksdb, &self.cf, options, move |key: &[u8]| {
+            key_format.deserialize(key).ok().map(|key: K| project(&key))
+        })
+    }
+
+    /// Returns an iterator over the key-value pairs of the table with a key in `range` which are
+    /// accepted by `pred`, in key order.
+    ///
+    /// `pred` is given the key and the value as stored in the table, encoded by the value codec of
+    /// the table if any, and only the values of the accepted entries are deserialized. Scans which
+    /// select a small fraction of the entries, e.g. on a flag or a prefix of the value, save most
+    /// of the deserialization work.
+    ///
+    /// The entries whose key or accepted value fail to decode are returned as errors, and the scan
+    /// goes on past them. A failure of the underlying iterator is returned last
+    pub fn scan_filtered<'a, F>(
+        &'a self,
+        range: impl RangeBounds<K>,
+        pred: F,
+    ) -> Result<impl Iterator<Item = Result<(K, V), TypedStoreError>> + 'a, TypedStoreError>
+    where
+        F: Fn(&K, &[u8]) -> bool + 'a,
+    {
+        let mut readopts = total_order_read_options();
+        let end = match range.end_bound() {
+            Bound::Included(end) => Bound::Included(self.encode_key(end)?),
+            Bound::Excluded(end) => {
+                // RocksDB stops at the bound, without stepping over the tombstones past it
+                readopts.set_iterate_upper_bound(self.encode_key(end)?);
+                Bound::Unbounded
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let mut db_iter = self.rocksdb.raw_iterator_cf_opt(&self.cf(), readopts);
+        match range.start_bound() {
+            Bound::Included(start) => db_iter.seek(self.encode_key(start)?),
+            Bound::Excluded(start) => {
+                let start = self.encode_key(start)?;
+                db_iter.seek(&start);
+                if db_iter.key() == Some(start.as_slice()) {
+                    db_iter.next();
+                }
+            }
+            Bound::Unbounded => db_iter.seek_to_first(),
+        }
+
+        let key_format = self.key_format;
+        let encoding = self.encoding();
+        let mut done = false;
+        Ok(std::iter::from_fn(move || {
+            while !done {
+                let (key_bytes, value_bytes) = match (db_iter.key(), db_iter.value()) {
+                    (Some(key_bytes), Some(value_bytes)) => (key_bytes, value_bytes),
+                    _ => {
+                        done = true;
+                        return db_iter.status().err().map(|e| Err(e.into()));
+                    }
+                };
+                if let Bound::Included(end) = &end {
+                    if key_bytes > end.as_slice() {
+                        done = true;
+                        return None;
+                    }
+                }
+                let entry = key_format.deserialize(key_bytes).and_then(|key: K| {
+                    let value = pred(&key, value_bytes)
+                        .then(|| decode_value(encoding, key_bytes, value_bytes))
+                        .transpose()?;
+                    Ok(value.map(|value| (key, value)))
+                });
+                db_iter.next();
+                if let Some(entry) = entry.transpose() {
+                    return Some(entry);
+                }
+            }
+            None
+        }))
+    }
+}
+
 /// Provides a mutable struct to form a collection of database write operations, and execute them.
 ///
 /// Batching write and delete operations is faster than performing them one by one and ensures their atomicity,
@@ -194,6 +1201,40 @@ impl<K, V> DBMap<K, V> {
 pub struct DBBatch {
     rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
     batch: WriteBatch,
+    stats: BatchStats,
+    low_priority: bool,
+    db_name: String,
+    accumulated: AccumulatorUpdates,
+    notifications: Vec<(Arc<PrefixWatchers>, RawChange)>,
+    /// The secondary index entries written by the batch, in order, see `update_index_batch`
+    pending_indexes: Vec<PendingIndexUpdate>,
+    /// The logical operation writing the batch, see `with_label`
+    label: Option<String>,
+    /// The key of the batch, if it must be applied at most once, see `with_idempotency_key`
+    idempotency_key: Option<Vec<u8>>,
+    /// Whether the write of the batch syncs the WAL
+    sync_writes: bool,
+    /// The source of time of the commit latency, logged if slow
+    clock: Arc<dyn Clock>,
+    /// The tables written by the batch, which must not be frozen when it is written
+    tables: BTreeSet<String>,
+    /// The tokens taken from the write limits of the tables, given back if the write fails
+    throttled: Vec<ThrottledWrite>,
+}
+
+/// Statistics about a committed `DBBatch`, as returned by `DBBatch::write`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// Number of operations (puts, deletes and range deletes) in the batch
+    pub entries: usize,
+    /// Total size of the encoded keys in the batch
+    pub key_bytes: usize,
+    /// Total size of the encoded values in the batch
+    pub value_bytes: usize,
+    /// Time spent committing the batch to the database
+    pub commit_latency: Duration,
+    /// Whether the batch was skipped, since a batch with the same idempotency key was already applied
+    pub already_applied: bool,
 }
 
 impl DBBatch {
@@ -204,14 +1245,137 @@ impl DBBatch {
         DBBatch {
             rocksdb: dbref.clone(),
             batch: WriteBatch::default(),
+            stats: BatchStats::default(),
+            low_priority: false,
+            db_name: default_db_name(dbref),
+            accumulated: AccumulatorUpdates::default(),
+            notifications: Vec::new(),
+            pending_indexes: Vec::new(),
+            label: None,
+            idempotency_key: None,
+            sync_writes: global_durability_profile().map_or(false, |p| p.sync_writes()),
+            clock: SystemClock::shared(),
+            tables: BTreeSet::new(),
+            throttled: Vec::new(),
         }
     }
 
+    /// Label the batch with `db_name` in the metrics, instead of the database directory name
+    pub fn with_db_name(mut self, db_name: &str) -> Self {
+        self.db_name = db_name.to_owned();
+        self
+    }
+
+    /// Label the batch with the logical operation writing it, e.g. "checkpoint commit", so that its
+    /// commit, and the flushes and compactions of what it wrote, can be attributed to the operation.
+    /// See `typed_store::rocks::take_labelled_writes`
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    /// Applies the batch at most once: the batch is skipped if a batch with the same `key` was already
+    /// applied, see `typed_store::rocks::is_batch_applied`. The database must have the
+    /// `APPLIED_BATCHES_CF` column family
+    pub fn with_idempotency_key(mut self, key: &[u8]) -> Self {
+        self.idempotency_key = Some(key.to_vec());
+        self
+    }
+
+    /// Sync the WAL when writing the batch per `profile`, instead of per the global profile
+    pub fn with_durability(mut self, profile: DurabilityProfile) -> Self {
+        self.sync_writes = profile.sync_writes();
+        self
+    }
+
+    /// Measure the commit latency of the batch, and date its idempotency key, with `clock`, see
+    /// `DBMap::with_clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Flag the batch as a low priority write, see `DBMap::with_low_priority_writes`
+    pub fn low_priority(mut self) -> Self {
+        self.low_priority = true;
+        self
+    }
+
     /// Consume the batch and write its operations to the database
-    #[instrument(level = "trace", skip_all, err)]
-    pub fn write(self) -> Result<(), TypedStoreError> {
-        self.rocksdb.write(self.batch)?;
-        Ok(())
+    /// Returns the statistics of the committed batch
+    #[instrument(level = "trace", skip_all, fields(label = self.label.as_deref()), err)]
+    pub fn write(mut self) -> Result<BatchStats, TypedStoreError> {
+        let throttled = std::mem::take(&mut self.throttled);
+        let stats = self.commit();
+        // Nothing was written, so the writes of the batch don't count towards the write limits
+        if stats.as_ref().map_or(true, |stats| stats.already_applied) {
+            throttled.into_iter().for_each(ThrottledWrite::refund);
+        }
+        stats
+    }
+
+    fn commit(self) -> Result<BatchStats, TypedStoreError> {
+        let mut stats = self.stats;
+        let mut opts = WriteOptions::default();
+        opts.set_low_pri(self.low_priority);
+        opts.set_sync(self.sync_writes);
+        let (rocksdb, mut batch) = (&self.rocksdb, self.batch);
+        // The stored values the index entries are replaced from can't change until the write
+        let _index_locks = index::lock_index_keys(&self.pending_indexes);
+        index::write_index_updates(rocksdb, &mut batch, &mut stats, &self.pending_indexes)?;
+        // The idempotency key is recorded in its own table, which can be frozen too
+        let recorded = self.idempotency_key.as_ref().map(|_| APPLIED_BATCHES_CF);
+        let permit = permit_writes(
+            rocksdb,
+            self.tables.iter().map(String::as_str).chain(recorded),
+        )?;
+        let _applying = match &self.idempotency_key {
+            Some(key) => {
+                let applying = idempotency::lock_idempotent_writes();
+                if is_batch_applied(rocksdb, key)? {
+                    debug!("Skipping a batch of {} already applied", self.db_name);
+                    return Ok(BatchStats {
+                        already_applied: true,
+                        ..Default::default()
+                    });
+                }
+                idempotency::record_applied(rocksdb, &mut batch, key, self.clock.as_ref())?;
+                Some(applying)
+            }
+            None => None,
+        };
+        let start = self.clock.instant();
+        self.accumulated
+            .write(rocksdb, || Ok(rocksdb.write_opt(batch, &opts)?))?;
+        stats.commit_latency = self.clock.instant().saturating_sub(start);
+        drop(permit);
+        for (watchers, change) in self.notifications {
+            watchers.notify(change);
+        }
+
+        let op_metrics = &DBMetrics::get().op_metrics;
+        op_metrics
+            .rocksdb_batch_commit_latency_seconds
+            .with_label_values(&[&self.db_name])
+            .observe(stats.commit_latency.as_secs_f64());
+        op_metrics
+            .rocksdb_batch_commit_bytes
+            .with_label_values(&[&self.db_name])
+            .observe((stats.key_bytes + stats.value_bytes) as f64);
+        if let Some(label) = &self.label {
+            labels::record_labelled_write(
+                &self.db_name,
+                label,
+                (stats.key_bytes + stats.value_bytes) as u64,
+            );
+            if stats.commit_latency > SLOW_BATCH_COMMIT_THRESHOLD {
+                warn!(
+                    "Slow commit of a {label} batch to {}: {} entries committed in {:?}",
+                    self.db_name, stats.entries, stats.commit_latency
+                );
+            }
+        }
+        Ok(stats)
     }
 }
 
@@ -229,14 +1393,36 @@ impl DBBatch {
         purged_vals
             .into_iter()
             .try_for_each::<_, Result<_, TypedStoreError>>(|k| {
-                let k_buf = be_fix_int_ser(k.borrow())?;
-                self.batch.delete_cf(&db.cf(), k_buf);
-
+                let k_buf = db.encode_key(k.borrow())?;
+                self.delete_raw_key(db, k_buf);
                 Ok(())
             })?;
         Ok(self)
     }
 
+    /// Records that the batch writes to the table of `db`
+    fn write_to<K, V>(&mut self, db: &DBMap<K, V>) {
+        if !self.tables.contains(&db.cf) {
+            self.tables.insert(db.cf.clone());
+        }
+    }
+
+    fn delete_raw_key<K, V>(&mut self, db: &DBMap<K, V>, k_buf: Vec<u8>) {
+        self.write_to(db);
+        if let Some(table) = db.accumulated_table() {
+            self.accumulated.delete(&table, k_buf.clone());
+        }
+        if db.watchers.is_watching(&k_buf) {
+            let change = RawChange::Delete {
+                key: k_buf.as_slice().into(),
+            };
+            self.notifications.push((db.watchers.clone(), change));
+        }
+        self.stats.entries += 1;
+        self.stats.key_bytes += k_buf.len();
+        self.batch.delete_cf(&db.cf(), k_buf);
+    }
+
     /// Deletes a range of keys between `from` (inclusive) and `to` (non-inclusive)
     pub fn delete_range<'a, K: Serialize, V>(
         mut self,
@@ -248,11 +1434,72 @@ impl DBBatch {
             return Err(TypedStoreError::CrossDBBatch);
         }
 
-        let from_buf = be_fix_int_ser(from)?;
-        let to_buf = be_fix_int_ser(to)?;
+        let from_buf = db.encode_key(from)?;
+        let to_buf = db.encode_key(to)?;
+        self.delete_raw_range(db, from_buf, to_buf);
+        Ok(self)
+    }
+
+    /// Deletes the keys starting with `prefix` once encoded, e.g. the first fields of a tuple key.
+    ///
+    /// The keys are deleted with a range delete, after iterating over the table to skip prefixes
+    /// without keys, which would otherwise leave a range tombstone behind for nothing.
+    pub fn delete_prefix<P: Serialize + ?Sized, K, V>(
+        mut self,
+        db: &DBMap<K, V>,
+        prefix: &P,
+    ) -> Result<Self, TypedStoreError> {
+        if !Arc::ptr_eq(&db.rocksdb, &self.rocksdb) {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+
+        let range = KeyRange::encoded_prefix(db.encode_key(prefix)?);
+        let start = range.start.unwrap_or_default();
+        let mut readopts = ReadOptions::default();
+        if let Some(end) = &range.end {
+            readopts.set_iterate_upper_bound(end.clone());
+        }
+        let mut db_iter = db.rocksdb.raw_iterator_cf_opt(&db.cf(), readopts);
+        db_iter.seek(&start);
+        let has_keys = db_iter.valid();
+        db_iter.status()?;
+        if !has_keys {
+            return Ok(self);
+        }
+
+        match range.end {
+            Some(end) => self.delete_raw_range(db, start, end),
+            None => {
+                // All the bytes of the prefix are 0xFF, so the range ends after the last key of the table
+                db_iter.seek_to_last();
+                db_iter.status()?;
+                let last = db_iter
+                    .key()
+                    .expect("The table has keys with the prefix")
+                    .to_vec();
+                self.delete_raw_range(db, start, last.clone());
+                self.delete_raw_key(db, last);
+            }
+        }
+        Ok(self)
+    }
 
+    fn delete_raw_range<K, V>(&mut self, db: &DBMap<K, V>, from_buf: Vec<u8>, to_buf: Vec<u8>) {
+        self.write_to(db);
+        self.stats.entries += 1;
+        self.stats.key_bytes += from_buf.len() + to_buf.len();
+        if let Some(table) = db.accumulated_table() {
+            self.accumulated
+                .delete_range(&table, from_buf.clone(), to_buf.clone());
+        }
+        if !db.watchers.is_empty() {
+            let change = RawChange::DeleteRange {
+                from: from_buf.as_slice().into(),
+                to: to_buf.as_slice().into(),
+            };
+            self.notifications.push((db.watchers.clone(), change));
+        }
         self.batch.delete_range_cf(&db.cf(), from_buf, to_buf);
-        Ok(self)
     }
 
     /// inserts a range of (key, value) pairs given as an iterator
@@ -265,15 +1512,57 @@ impl DBBatch {
             return Err(TypedStoreError::CrossDBBatch);
         }
 
+        match self.add_inserts(db, new_vals) {
+            Ok(()) => Ok(self),
+            Err(e) => {
+                // The batch is dropped with the error, so nothing it took tokens for is written
+                self.throttled.drain(..).for_each(ThrottledWrite::refund);
+                Err(e)
+            }
+        }
+    }
+
+    fn add_inserts<J: Borrow<K>, K: Serialize, U: Borrow<V>, V: Serialize>(
+        &mut self,
+        db: &DBMap<K, V>,
+        new_vals: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<(), TypedStoreError> {
+        self.write_to(db);
+        let new_vals = new_vals
+            .into_iter()
+            .map(|(k, v)| {
+                let k_buf = db.encode_key(k.borrow())?;
+                let v_buf = encode_value(db.encoding(), &k_buf, v.borrow())?;
+                Ok((k_buf, v_buf, v))
+            })
+            .collect::<Result<Vec<_>, TypedStoreError>>()?;
+        // The entries are throttled at once, so that a throttled batch takes no tokens
+        let bytes = new_vals.iter().map(|(k, v, _)| k.len() + v.len()).sum();
+        let throttled = db.throttle_writes(new_vals.len(), bytes)?;
+        self.throttled.extend(throttled);
         new_vals
             .into_iter()
-            .try_for_each::<_, Result<_, TypedStoreError>>(|(k, v)| {
-                let k_buf = be_fix_int_ser(k.borrow())?;
-                let v_buf = bincode::serialize(v.borrow())?;
+            .try_for_each::<_, Result<_, TypedStoreError>>(|(k_buf, v_buf, v)| {
+                if let Some(table) = db.accumulated_table() {
+                    self.accumulated.put(
+                        &table,
+                        k_buf.clone(),
+                        db.value_format.serialize(v.borrow())?,
+                    );
+                }
+                if db.watchers.is_watching(&k_buf) {
+                    let change = RawChange::Put {
+                        key: k_buf.as_slice().into(),
+                        value: bincode::serialize(v.borrow())?.into(),
+                    };
+                    self.notifications.push((db.watchers.clone(), change));
+                }
+                self.stats.entries += 1;
+                self.stats.key_bytes += k_buf.len();
+                self.stats.value_bytes += v_buf.len();
                 self.batch.put_cf(&db.cf(), k_buf, v_buf);
                 Ok(())
-            })?;
-        Ok(self)
+            })
     }
 }
 
@@ -289,7 +1578,7 @@ where
 
     #[instrument(level = "trace", skip_all, err)]
     fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
-        let key_buf = be_fix_int_ser(key)?;
+        let key_buf = self.encode_key(key)?;
         // [`rocksdb::DBWithThreadMode::key_may_exist_cf`] can have false positives,
         // but no false negatives. We use it to short-circuit the absent case
         Ok(self.rocksdb.key_may_exist_cf(&self.cf(), &key_buf)
@@ -298,46 +1587,135 @@ where
 
     #[instrument(level = "trace", skip_all, err)]
     fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
-        let key_buf = be_fix_int_ser(key)?;
-        let res = self.rocksdb.get_pinned_cf(&self.cf(), &key_buf)?;
+        let op_metrics = &DBMetrics::get().op_metrics;
+        let _timer = op_metrics
+            .rocksdb_get_latency_seconds
+            .with_label_values(&[&self.db_name, &self.cf])
+            .start_timer();
+        let key_buf = self.encode_key(key)?;
+        let res = match &self.read_amp_sampler {
+            Some(sampler) => sampler.sample(&self.db_name, &self.cf, || {
+                self.rocksdb.get_pinned_cf(&self.cf(), &key_buf)
+            })?,
+            None => self.rocksdb.get_pinned_cf(&self.cf(), &key_buf)?,
+        };
         match res {
-            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            Some(data) => {
+                op_metrics
+                    .rocksdb_get_bytes
+                    .with_label_values(&[&self.db_name, &self.cf])
+                    .observe(data.len() as f64);
+                Ok(Some(decode_value(self.encoding(), &key_buf, &data)?))
+            }
             None => Ok(None),
         }
     }
 
     #[instrument(level = "trace", skip_all, err)]
     fn get_raw_bytes(&self, key: &K) -> Result<Option<Vec<u8>>, TypedStoreError> {
-        let key_buf = be_fix_int_ser(key)?;
+        let key_buf = self.encode_key(key)?;
         let res = self.rocksdb.get_pinned_cf(&self.cf(), &key_buf)?;
         match res {
-            Some(data) => Ok(Some(data.to_vec())),
+            Some(data) => match self.codec() {
+                Some(codec) => Ok(Some(codec.decode(&key_buf, &data)?)),
+                None => Ok(Some(data.to_vec())),
+            },
             None => Ok(None),
         }
     }
 
     #[instrument(level = "trace", skip_all, err)]
     fn insert(&self, key: &K, value: &V) -> Result<(), TypedStoreError> {
-        let key_buf = be_fix_int_ser(key)?;
-        let value_buf = bincode::serialize(value)?;
-
-        self.rocksdb.put_cf(&self.cf(), &key_buf, &value_buf)?;
+        let op_metrics = &DBMetrics::get().op_metrics;
+        let _timer = op_metrics
+            .rocksdb_put_latency_seconds
+            .with_label_values(&[&self.db_name, &self.cf])
+            .start_timer();
+        let key_buf = self.encode_key(key)?;
+        let value_buf = encode_value(self.encoding(), &key_buf, value)?;
+        let throttled = self.throttle_writes(1, key_buf.len() + value_buf.len())?;
+        op_metrics
+            .rocksdb_put_bytes
+            .with_label_values(&[&self.db_name, &self.cf])
+            .observe((key_buf.len() + value_buf.len()) as f64);
+
+        let put = || -> Result<(), TypedStoreError> {
+            self.rocksdb
+                .put_cf_opt(&self.cf(), &key_buf, &value_buf, &self.write_options())?;
+            Ok(())
+        };
+        let write = || -> Result<(), TypedStoreError> {
+            let _permit = self.permit_writes()?;
+            match self.accumulated_table() {
+                Some(table) => {
+                    let mut updates = AccumulatorUpdates::default();
+                    updates.put(&table, key_buf.clone(), self.value_format.serialize(value)?);
+                    updates.write(&self.rocksdb, put)
+                }
+                None => put(),
+            }
+        };
+        if let Err(e) = write() {
+            // Nothing was written, so the write doesn't count towards the write limit
+            throttled.into_iter().for_each(ThrottledWrite::refund);
+            return Err(e);
+        }
+        if self.watchers.is_watching(&key_buf) {
+            self.watchers.notify(RawChange::Put {
+                key: key_buf.into(),
+                value: bincode::serialize(value)?.into(),
+            });
+        }
         Ok(())
     }
 
     #[instrument(level = "trace", skip_all, err)]
     fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
-        let key_buf = be_fix_int_ser(key)?;
-
-        self.rocksdb.delete_cf(&self.cf(), &key_buf)?;
+        let _timer = DBMetrics::get()
+            .op_metrics
+            .rocksdb_delete_latency_seconds
+            .with_label_values(&[&self.db_name, &self.cf])
+            .start_timer();
+        let key_buf = self.encode_key(key)?;
+
+        let delete = || -> Result<(), TypedStoreError> {
+            self.rocksdb
+                .delete_cf_opt(&self.cf(), &key_buf, &self.write_options())?;
+            Ok(())
+        };
+        let permit = self.permit_writes()?;
+        match self.accumulated_table() {
+            Some(table) => {
+                let mut updates = AccumulatorUpdates::default();
+                updates.delete(&table, key_buf.clone());
+                updates.write(&self.rocksdb, delete)
+            }
+            None => delete(),
+        }?;
+        drop(permit);
+        if self.watchers.is_watching(&key_buf) {
+            self.watchers.notify(RawChange::Delete {
+                key: key_buf.into(),
+            });
+        }
         Ok(())
     }
 
     #[instrument(level = "trace", skip_all, err)]
     fn clear(&self) -> Result<(), TypedStoreError> {
-        let _ = self.rocksdb.drop_cf(&self.cf);
+        let _permit = self.permit_writes()?;
+        let mut accumulator = self.accumulator.as_ref().map(|accumulator| {
+            accumulator
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        });
+        let _ = lifecycle::drop_table(&self.rocksdb, &self.db_name, &self.cf, self.clock.as_ref());
         self.rocksdb
             .create_cf(self.cf.clone(), &default_rocksdb_options())?;
+        if let Some(accumulator) = accumulator.as_mut() {
+            **accumulator = Accumulator::default();
+        }
+        self.watchers.notify(RawChange::Clear);
         Ok(())
     }
 
@@ -346,24 +1724,37 @@ where
     }
 
     fn iter(&'a self) -> Self::Iterator {
-        let mut db_iter = self.rocksdb.raw_iterator_cf(&self.cf());
+        let mut db_iter = self
+            .rocksdb
+            .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
         db_iter.seek_to_first();
 
-        Iter::new(db_iter)
+        Iter::new(
+            db_iter,
+            self.key_format,
+            self.encoding(),
+            &self.db_name,
+            &self.cf,
+        )
+        .pinned(None)
     }
 
     fn keys(&'a self) -> Self::Keys {
-        let mut db_iter = self.rocksdb.raw_iterator_cf(&self.cf());
+        let mut db_iter = self
+            .rocksdb
+            .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
         db_iter.seek_to_first();
 
-        Keys::new(db_iter)
+        Keys::new(db_iter, self.key_format)
     }
 
     fn values(&'a self) -> Self::Values {
-        let mut db_iter = self.rocksdb.raw_iterator_cf(&self.cf());
+        let mut db_iter = self
+            .rocksdb
+            .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
         db_iter.seek_to_first();
 
-        Values::new(db_iter)
+        Values::new(db_iter, self.encoding())
     }
 
     /// Returns a vector of values corresponding to the keys provided.
@@ -375,19 +1766,38 @@ where
     where
         J: Borrow<K>,
     {
+        let op_metrics = &DBMetrics::get().op_metrics;
+        let _timer = op_metrics
+            .rocksdb_multiget_latency_seconds
+            .with_label_values(&[&self.db_name, &self.cf])
+            .start_timer();
         let cf = self.cf();
 
-        let keys_bytes: Result<Vec<_>, TypedStoreError> = keys
+        let keys_bytes = keys
             .into_iter()
-            .map(|k| Ok((&cf, be_fix_int_ser(k.borrow())?)))
-            .collect();
-
-        let results = self.rocksdb.multi_get_cf(keys_bytes?);
+            .map(|k| self.encode_key(k.borrow()))
+            .collect::<Result<Vec<_>, TypedStoreError>>()?;
+
+        let results = self
+            .rocksdb
+            .multi_get_cf(keys_bytes.iter().map(|key| (&cf, key)));
+        let total_bytes: usize = results
+            .iter()
+            .map(|r| match r {
+                Ok(Some(data)) => data.len(),
+                _ => 0,
+            })
+            .sum();
+        op_metrics
+            .rocksdb_multiget_bytes
+            .with_label_values(&[&self.db_name, &self.cf])
+            .observe(total_bytes as f64);
 
         let values_parsed: Result<Vec<_>, TypedStoreError> = results
             .into_iter()
-            .map(|value_byte| match value_byte? {
-                Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            .zip(&keys_bytes)
+            .map(|(value_byte, key)| match value_byte? {
+                Some(data) => Ok(Some(decode_value(self.encoding(), key, &data)?)),
                 None => Ok(None),
             })
             .collect();
@@ -405,7 +1815,8 @@ where
         J: Borrow<K>,
         U: Borrow<V>,
     {
-        self.batch().insert_batch(self, key_val_pairs)?.write()
+        self.batch().insert_batch(self, key_val_pairs)?.write()?;
+        Ok(())
     }
 
     /// Convenience method for batch removal
@@ -414,7 +1825,8 @@ where
     where
         J: Borrow<K>,
     {
-        self.batch().delete_batch(self, keys)?.write()
+        self.batch().delete_batch(self, keys)?.write()?;
+        Ok(())
     }
 
     /// Try to catch up with primary when running as secondary
@@ -438,13 +1850,15 @@ where
         T: Iterator<Item = (J, U)>,
     {
         let batch = self.batch().insert_batch(self, iter)?;
-        batch.write()
+        batch.write()?;
+        Ok(())
     }
 
     fn try_extend_from_slice(&mut self, slice: &[(J, U)]) -> Result<(), Self::Error> {
         let slice_of_refs = slice.iter().map(|(k, v)| (k.borrow(), v.borrow()));
         let batch = self.batch().insert_batch(self, slice_of_refs)?;
-        batch.write()
+        batch.write()?;
+        Ok(())
     }
 }
 
@@ -462,6 +1876,38 @@ fn read_size_from_env(var_name: &str) -> Option<usize> {
         .ok()
 }
 
+/// The names of the databases set with `set_db_name`, by path
+static DB_NAMES: once_cell::sync::Lazy<std::sync::RwLock<HashMap<PathBuf, String>>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Labels the metrics and lifecycle events of the database at `path` with `db_name`, instead of its
+/// directory name. This includes those of the maps opened afterwards, and of the operations on the
+/// whole database, e.g. its opening, the auto flushes or the drops of its orphan column families.
+/// `DBMap::with_db_name` only relabels a map
+pub fn set_db_name<P: AsRef<Path>>(path: P, db_name: &str) {
+    DB_NAMES
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .insert(path.as_ref().to_path_buf(), db_name.to_owned());
+}
+
+/// The name used to label the metrics of a database when none is given to a map: the one set with
+/// `set_db_name`, or its directory name
+fn default_db_name(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>) -> String {
+    if let Some(db_name) = DB_NAMES
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .get(rocksdb.path())
+    {
+        return db_name.clone();
+    }
+    rocksdb
+        .path()
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
 /// Creates a default RocksDB option, to be used when RocksDB option is not specified..
 pub fn default_rocksdb_options() -> rocksdb::Options {
     let mut opt = rocksdb::Options::default();
@@ -483,6 +1929,9 @@ pub fn default_rocksdb_options() -> rocksdb::Options {
     opt.set_max_total_wal_size(
         read_size_from_env(ENV_VAR_DB_WAL_SIZE).unwrap_or(DEFAULT_DB_WAL_SIZE) as u64 * 1024 * 1024,
     );
+    if let Some(durability) = global_durability_profile() {
+        durability.apply_to_options(&mut opt);
+    }
     opt
 }
 
@@ -498,12 +1947,40 @@ pub fn open_cf<P: AsRef<Path>>(
     open_cf_opts(path, Some(options.clone()), &column_descriptors[..])
 }
 
+/// What to do when opening a database whose column families don't match the given ones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CfMismatchPolicy {
+    /// Fail with `TypedStoreError::ColumnFamilyMismatch` if the database has column families
+    /// which were not given, e.g. the tables of a newer version of the node
+    ErrorOnUnknownCf,
+    /// Open the unknown column families with the default options, and log them along with the
+    /// given column families which are created
+    OpenWithDefaults,
+    /// Open the unknown column families with the default options silently, as `open_cf_opts` does
+    #[default]
+    Ignore,
+}
+
 /// Opens a database with options, and a number of column families with individual options that are created if they do not exist.
+/// The column families of the database which are not given are opened with the default options, see
+/// `open_cf_opts_with_cf_policy` to reject them instead
 #[instrument(level="debug", skip_all, fields(path = ?path.as_ref()), err)]
 pub fn open_cf_opts<P: AsRef<Path>>(
     path: P,
     db_options: Option<rocksdb::Options>,
     opt_cfs: &[(&str, &rocksdb::Options)],
+) -> Result<Arc<rocksdb::DBWithThreadMode<MultiThreaded>>, TypedStoreError> {
+    open_cf_opts_with_cf_policy(path, db_options, opt_cfs, CfMismatchPolicy::Ignore)
+}
+
+/// Opens a database like `open_cf_opts`, applying `policy` to the column families of the database which are
+/// not in `opt_cfs`. The column families of `opt_cfs` missing from the database are always created.
+#[instrument(level="debug", skip_all, fields(path = ?path.as_ref(), policy = ?policy), err)]
+pub fn open_cf_opts_with_cf_policy<P: AsRef<Path>>(
+    path: P,
+    db_options: Option<rocksdb::Options>,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+    policy: CfMismatchPolicy,
 ) -> Result<Arc<rocksdb::DBWithThreadMode<MultiThreaded>>, TypedStoreError> {
     // Customize database options
     let mut options = db_options.unwrap_or_else(default_rocksdb_options);
@@ -513,6 +1990,40 @@ pub fn open_cf_opts<P: AsRef<Path>>(
         .ok()
         .unwrap_or_default();
 
+    // The default and metadata column families are never given
+    let mut unknown: Vec<_> = cfs
+        .iter()
+        .filter(|cf| !metadata::is_internal_cf(cf))
+        .filter(|cf| !opt_cfs.contains_key(cf.as_str()))
+        .cloned()
+        .collect();
+    unknown.sort();
+    // A new database has no column family, and they are all created as expected
+    let mut missing: Vec<_> = opt_cfs
+        .keys()
+        .filter(|cf| !cfs.is_empty() && !cfs.iter().any(|existing| existing == *cf))
+        .map(|cf| cf.to_string())
+        .collect();
+    missing.sort();
+    match policy {
+        CfMismatchPolicy::ErrorOnUnknownCf if !unknown.is_empty() => {
+            return Err(TypedStoreError::ColumnFamilyMismatch {
+                path: path.as_ref().display().to_string(),
+                unknown,
+                missing,
+            });
+        }
+        CfMismatchPolicy::ErrorOnUnknownCf | CfMismatchPolicy::OpenWithDefaults => {
+            if !unknown.is_empty() {
+                warn!("Opening the unknown column families {unknown:?} with the default options");
+            }
+            if !missing.is_empty() {
+                info!("Creating the column families {missing:?}");
+            }
+        }
+        CfMismatchPolicy::Ignore => {}
+    }
+
     let default_rocksdb_options = default_rocksdb_options();
     // Add CFs not explicitly listed
     for cf_key in cfs.iter() {
@@ -523,6 +2034,7 @@ pub fn open_cf_opts<P: AsRef<Path>>(
 
     let primary = path.as_ref().to_path_buf();
 
+    let started = report_opening(&primary, opt_cfs.keys());
     let rocksdb = {
         options.create_if_missing(true);
         options.create_missing_column_families(true);
@@ -536,6 +2048,16 @@ pub fn open_cf_opts<P: AsRef<Path>>(
             )?,
         )
     };
+    for (name, opts) in &opt_cfs {
+        cf_options::record_cf_options(&rocksdb, name, opts);
+    }
+    lifecycle::record_tables_opened(
+        &rocksdb,
+        &default_db_name(&rocksdb),
+        opt_cfs.keys().copied(),
+        SystemClock.instant().saturating_sub(started),
+    );
+    report_opened(primary, opt_cfs.len(), started);
     Ok(rocksdb)
 }
 
@@ -576,6 +2098,7 @@ pub fn open_cf_opts_secondary<P: AsRef<Path>>(
             s.as_path().to_path_buf()
         });
 
+    let started = report_opening(&secondary_path, opt_cfs.keys());
     let rocksdb = {
         options.create_if_missing(true);
         options.create_missing_column_families(true);
@@ -590,20 +2113,57 @@ pub fn open_cf_opts_secondary<P: AsRef<Path>>(
             )?,
         )
     };
+    report_opened(secondary_path, opt_cfs.len(), started);
     Ok(rocksdb)
 }
 
-pub fn list_tables(path: std::path::PathBuf) -> eyre::Result<Vec<String>> {
-    const DB_DEFAULT_CF_NAME: &str = "default";
+/// Reports the opening of a database, and returns the instant of the `SystemClock` it started at
+fn report_opening<'a>(path: &Path, column_families: impl Iterator<Item = &'a &'a str>) -> Duration {
+    let mut column_families: Vec<_> = column_families.map(|cf| cf.to_string()).collect();
+    column_families.sort();
+    report_open_progress(OpenProgress::OpeningDatabase {
+        path: path.to_path_buf(),
+        column_families,
+    });
+    SystemClock.instant()
+}
+
+fn report_opened(path: PathBuf, column_families: usize, started: Duration) {
+    report_open_progress(OpenProgress::DatabaseOpened {
+        path,
+        column_families,
+        elapsed: SystemClock.instant().saturating_sub(started),
+    });
+}
+
+/// Opens an existing database in read only mode with the given column families.
+/// Any number of processes can open a database in read only mode, including while it is open as primary,
+/// but the data written after it is opened is not visible.
+pub fn open_cf_read_only<P: AsRef<Path>, N: AsRef<str>>(
+    path: P,
+    cfs: &[N],
+) -> Result<Arc<rocksdb::DBWithThreadMode<MultiThreaded>>, TypedStoreError> {
+    fdlimit::raise_fd_limit();
+    let options = default_rocksdb_options();
+    Ok(Arc::new(
+        rocksdb::DBWithThreadMode::<MultiThreaded>::open_cf_for_read_only(
+            &options,
+            path,
+            cfs.iter().map(|cf| cf.as_ref()),
+            false,
+        )?,
+    ))
+}
 
+pub fn list_tables(path: std::path::PathBuf) -> eyre::Result<Vec<String>> {
     let opts = rocksdb::Options::default();
     rocksdb::DBWithThreadMode::<rocksdb::MultiThreaded>::list_cf(&opts, &path)
         .map_err(|e| e.into())
         .map(|q| {
             q.iter()
                 .filter_map(|s| {
-                    // The `default` table is not used
-                    if s != DB_DEFAULT_CF_NAME {
+                    // The `default` table is not used, and the metadata is not a table
+                    if !metadata::is_internal_cf(s) {
                         Some(s.clone())
                     } else {
                         None
@@ -613,19 +2173,6 @@ pub fn list_tables(path: std::path::PathBuf) -> eyre::Result<Vec<String>> {
         })
 }
 
-/// TODO: Good description of why we're doing this : RocksDB stores keys in BE and has a seek operator on iterators, see https://github.com/facebook/rocksdb/wiki/Iterator#introduction
-#[inline]
-pub(crate) fn be_fix_int_ser<S>(t: &S) -> Result<Vec<u8>, TypedStoreError>
-where
-    S: ?Sized + serde::Serialize,
-{
-    bincode::DefaultOptions::new()
-        .with_big_endian()
-        .with_fixint_encoding()
-        .serialize(t)
-        .map_err(|e| e.into())
-}
-
 #[derive(Clone)]
 pub struct DBMapTableConfigMap(BTreeMap<String, rocksdb::Options>);
 impl DBMapTableConfigMap {
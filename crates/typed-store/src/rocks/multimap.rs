@@ -0,0 +1,260 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    borrow::Borrow,
+    marker::PhantomData,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use bincode::Options;
+use rocksdb::{MultiThreaded, WriteBatch};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{be_fix_int_ser, DBMap, DBRawIteratorMultiThreaded, TypedStoreError};
+
+/// A persistent map storing several values per key.
+///
+/// Every value is stored under its own composite key `(key, sequence number)`, so that
+/// appending a value to a key doesn't rewrite the values already stored for it. The values
+/// of a key are kept in insertion order.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// let votes = DBMultiMap::<u64, String>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// votes.insert(&1, &"alice".to_owned()).unwrap();
+/// votes.insert(&1, &"bob".to_owned()).unwrap();
+/// votes.insert(&2, &"carol".to_owned()).unwrap();
+/// assert_eq!(votes.get_all(&1).unwrap(), vec!["alice".to_owned(), "bob".to_owned()]);
+/// votes.remove_value(&1, &"alice".to_owned()).unwrap();
+/// assert_eq!(votes.iter().count(), 2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DBMultiMap<K, V> {
+    map: DBMap<(K, u64), V>,
+    // serializes the allocation of sequence numbers
+    insert_lock: Arc<Mutex<()>>,
+}
+
+impl<K, V> DBMultiMap<K, V> {
+    /// Opens a database from a path, with specific options and an optional column family
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        db_options: Option<rocksdb::Options>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        Ok(Self::from_map(DBMap::open(path, db_options, opt_cf)?))
+    }
+
+    /// Reopens an open database as a multimap operating under a specific column family, see `DBMap::reopen`
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        Ok(Self::from_map(DBMap::reopen(db, opt_cf)?))
+    }
+
+    fn from_map(map: DBMap<(K, u64), V>) -> Self {
+        Self {
+            map,
+            insert_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn rocksdb(&self) -> &Arc<rocksdb::DBWithThreadMode<MultiThreaded>> {
+        &self.map.rocksdb
+    }
+
+    /// The iterator over the entries whose encoded composite key starts with `prefix`
+    fn prefix_iter(&self, prefix: Vec<u8>) -> MultiMapIter<'_, K, V> {
+        let mut db_iter = self.map.rocksdb.raw_iterator_cf(&self.map.cf());
+        db_iter.seek(&prefix);
+        MultiMapIter {
+            db_iter,
+            prefix,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<K, V> DBMultiMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Appends `value` to the values of `key`, and returns its sequence number
+    pub fn insert(&self, key: &K, value: &V) -> Result<u64, TypedStoreError> {
+        let _guard = self
+            .insert_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let seq = self.next_seq(key)?;
+        let composite_key = be_fix_int_ser(&(key, seq))?;
+        let _permit = self.map.permit_writes()?;
+        self.map.rocksdb.put_cf_opt(
+            &self.map.cf(),
+            composite_key,
+            bincode::serialize(value)?,
+            &self.map.write_options(),
+        )?;
+        Ok(seq)
+    }
+
+    /// Atomically appends the given values to their keys
+    pub fn multi_insert<J: Borrow<K>, U: Borrow<V>>(
+        &self,
+        entries: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<(), TypedStoreError> {
+        let _guard = self
+            .insert_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut next_seqs: Vec<(Vec<u8>, u64)> = vec![];
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            let key_buf = be_fix_int_ser(key.borrow())?;
+            let index = match next_seqs.iter().position(|(k, _)| *k == key_buf) {
+                Some(index) => index,
+                None => {
+                    next_seqs.push((key_buf, self.next_seq(key.borrow())?));
+                    next_seqs.len() - 1
+                }
+            };
+            let seq = &mut next_seqs[index].1;
+            batch.put_cf(
+                &self.map.cf(),
+                be_fix_int_ser(&(key.borrow(), *seq))?,
+                bincode::serialize(value.borrow())?,
+            );
+            *seq += 1;
+        }
+        self.write(batch)
+    }
+
+    /// Returns the values of `key`, in insertion order
+    pub fn get_all(&self, key: &K) -> Result<Vec<V>, TypedStoreError> {
+        Ok(self
+            .prefix_iter(be_fix_int_ser(key)?)
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    /// Removes the values of `key` equal to `value`, and returns how many were removed
+    pub fn remove_value(&self, key: &K, value: &V) -> Result<usize, TypedStoreError>
+    where
+        V: PartialEq,
+    {
+        let _guard = self
+            .insert_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let seqs: Vec<_> = self
+            .entries_of(key)?
+            .into_iter()
+            .filter(|(_, v)| v == value)
+            .map(|(seq, _)| seq)
+            .collect();
+        let mut batch = WriteBatch::default();
+        for seq in &seqs {
+            batch.delete_cf(&self.map.cf(), be_fix_int_ser(&(key, *seq))?);
+        }
+        self.write(batch)?;
+        Ok(seqs.len())
+    }
+
+    /// Removes all the values of `key`
+    pub fn remove_all(&self, key: &K) -> Result<(), TypedStoreError> {
+        let _guard = self
+            .insert_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let last = be_fix_int_ser(&(key, u64::MAX))?;
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(&self.map.cf(), be_fix_int_ser(&(key, 0u64))?, &last);
+        batch.delete_cf(&self.map.cf(), last);
+        self.write(batch)
+    }
+
+    /// Iterates over all the entries, ordered by key then insertion order
+    pub fn iter(&self) -> MultiMapIter<'_, K, V> {
+        self.prefix_iter(vec![])
+    }
+
+    /// Iterates over the entries whose key starts with `prefix` once encoded, e.g. the first fields of a
+    /// tuple key, ordered by key then insertion order
+    pub fn iter_prefix<P: Serialize + ?Sized>(
+        &self,
+        prefix: &P,
+    ) -> Result<MultiMapIter<'_, K, V>, TypedStoreError> {
+        Ok(self.prefix_iter(be_fix_int_ser(prefix)?))
+    }
+
+    /// The sequence numbers and values of `key`
+    fn entries_of(&self, key: &K) -> Result<Vec<(u64, V)>, TypedStoreError> {
+        let prefix = be_fix_int_ser(key)?;
+        let mut entries = vec![];
+        let mut db_iter = self.map.rocksdb.raw_iterator_cf(&self.map.cf());
+        db_iter.seek(&prefix);
+        while let (Some(k), Some(v)) = (db_iter.key(), db_iter.value()) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let seq = u64::from_be_bytes(k[prefix.len()..].try_into().map_err(|_| {
+                TypedStoreError::SerializationError("invalid multimap key".to_owned())
+            })?);
+            entries.push((seq, bincode::deserialize(v)?));
+            db_iter.next();
+        }
+        db_iter.status()?;
+        Ok(entries)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), TypedStoreError> {
+        let _permit = self.map.permit_writes()?;
+        self.map
+            .rocksdb
+            .write_opt(batch, &self.map.write_options())?;
+        Ok(())
+    }
+
+    fn next_seq(&self, key: &K) -> Result<u64, TypedStoreError> {
+        let prefix = be_fix_int_ser(key)?;
+        let mut db_iter = self.map.rocksdb.raw_iterator_cf(&self.map.cf());
+        db_iter.seek_for_prev(be_fix_int_ser(&(key, u64::MAX))?);
+        match db_iter.key() {
+            Some(k) if k.starts_with(&prefix) && k.len() == prefix.len() + 8 => {
+                let seq = u64::from_be_bytes(k[prefix.len()..].try_into().unwrap());
+                Ok(seq + 1)
+            }
+            _ => {
+                db_iter.status()?;
+                Ok(0)
+            }
+        }
+    }
+}
+
+/// An iterator over the entries of a `DBMultiMap`, see `DBMultiMap::iter_prefix`
+pub struct MultiMapIter<'a, K, V> {
+    db_iter: DBRawIteratorMultiThreaded<'a>,
+    prefix: Vec<u8>,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<'a, K: DeserializeOwned, V: DeserializeOwned> Iterator for MultiMapIter<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match (self.db_iter.key(), self.db_iter.value()) {
+            (Some(key), Some(value)) if key.starts_with(&self.prefix) => (key, value),
+            _ => return None,
+        };
+        let config = bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding();
+        let key: Option<(K, u64)> = config.deserialize(key).ok();
+        let value = bincode::deserialize(value).ok();
+        self.db_iter.next();
+        key.and_then(|(k, _)| value.map(|v| (k, v)))
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Progress of the opening of the databases.
+//!
+//! Opening a large database recovers its WAL and reads the manifest of each of its column
+//! families, which can take minutes without any output. `open_cf_opts` and its variants report
+//! when they start opening a database and how long it took, and the tables opened by
+//! `DBMapUtils` report how long each of them took to set up. The events are logged, and passed
+//! to the callback set by `set_open_progress_callback`, if any, e.g. to report the progress of
+//! the startup of a node.
+//!
+//! RocksDB opens the column families of a database all at once, so the time spent on each of
+//! them while opening the database isn't known.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tracing::{debug, info};
+
+/// A callback receiving the progress of the opening of the databases of the process
+pub type OpenProgressCallback = Arc<dyn Fn(&OpenProgress) + Send + Sync>;
+
+static OPEN_PROGRESS_CALLBACK: Lazy<RwLock<Option<OpenProgressCallback>>> =
+    Lazy::new(Default::default);
+
+/// A step of the opening of a database, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OpenProgress {
+    /// RocksDB starts opening the database at `path`, with `column_families`
+    OpeningDatabase {
+        path: PathBuf,
+        column_families: Vec<String>,
+    },
+    /// RocksDB opened the database at `path` and its `column_families` column families
+    DatabaseOpened {
+        path: PathBuf,
+        column_families: usize,
+        elapsed: Duration,
+    },
+    /// The table `cf_name` of the database `db_name` was set up by `DBMapUtils`, once the
+    /// database was opened
+    TableOpened {
+        db_name: String,
+        cf_name: String,
+        elapsed: Duration,
+    },
+}
+
+/// Sets the callback receiving the progress of the databases opened from now on, or removes it
+pub fn set_open_progress_callback(callback: Option<OpenProgressCallback>) {
+    *OPEN_PROGRESS_CALLBACK
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = callback;
+}
+
+/// Logs `progress`, and passes it to the callback set by `set_open_progress_callback`, if any
+pub fn report_open_progress(progress: OpenProgress) {
+    match &progress {
+        OpenProgress::OpeningDatabase {
+            path,
+            column_families,
+        } => info!(
+            "Opening the database at {} with {} column families",
+            path.display(),
+            column_families.len()
+        ),
+        OpenProgress::DatabaseOpened {
+            path,
+            column_families,
+            elapsed,
+        } => info!(
+            "Opened the database at {} with {column_families} column families in {elapsed:?}",
+            path.display()
+        ),
+        OpenProgress::TableOpened {
+            db_name,
+            cf_name,
+            elapsed,
+        } => debug!("Opened the table {cf_name} of {db_name} in {elapsed:?}"),
+    }
+    let callback = OPEN_PROGRESS_CALLBACK
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    if let Some(callback) = callback {
+        callback(&progress);
+    }
+}
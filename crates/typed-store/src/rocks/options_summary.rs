@@ -0,0 +1,110 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{collections::BTreeMap, fs, path::Path};
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+
+use super::TypedStoreError;
+
+/// The effective options of a table, as persisted by RocksDB, see `options_summary`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableOptionsSummary {
+    pub table: String,
+    /// The function which returned the default options of the table, e.g. the
+    /// `#[default_options_override_fn]` of a table of a `DBMapUtils` struct
+    pub options_fn: String,
+    pub write_buffer_size: Option<u64>,
+    pub max_write_buffer_number: Option<u64>,
+    pub compaction_style: Option<String>,
+    pub compression: Option<String>,
+    pub bottommost_compression: Option<String>,
+    /// The TTL of the table in seconds, 0 if disabled
+    pub ttl: Option<u64>,
+}
+
+/// Returns a digest of the effective options of the `tables`, given as `(table, options_fn)`
+/// pairs, to show the configuration of a running database without reading its source.
+///
+/// The options are read back from the latest `OPTIONS-*` file which RocksDB writes in the
+/// directory of the database on open and on each `set_options`.
+pub fn options_summary(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[(&str, &str)],
+) -> Result<Vec<TableOptionsSummary>, TypedStoreError> {
+    let path = rocksdb.path();
+    let cf_options = read_latest_cf_options(path)?
+        .ok_or_else(|| TypedStoreError::IoError(format!("no options file found in {path:?}")))?;
+
+    tables
+        .iter()
+        .map(|(table, options_fn)| {
+            let options = cf_options
+                .get(*table)
+                .ok_or_else(|| TypedStoreError::UnregisteredColumn((*table).to_owned()))?;
+            let int_option = |name: &str| options.get(name).and_then(|value| value.parse().ok());
+            let option = |name: &str| options.get(name).cloned();
+            Ok(TableOptionsSummary {
+                table: (*table).to_owned(),
+                options_fn: (*options_fn).to_owned(),
+                write_buffer_size: int_option("write_buffer_size"),
+                max_write_buffer_number: int_option("max_write_buffer_number"),
+                compaction_style: option("compaction_style"),
+                compression: option("compression"),
+                bottommost_compression: option("bottommost_compression"),
+                ttl: int_option("ttl"),
+            })
+        })
+        .collect()
+}
+
+/// Reads the options of the column families from the latest options file of the database in
+/// `path`, by column family name, or returns `None` if there is none
+pub(crate) fn read_latest_cf_options(
+    path: &Path,
+) -> Result<Option<BTreeMap<String, BTreeMap<String, String>>>, TypedStoreError> {
+    let io_error = |e: std::io::Error| {
+        TypedStoreError::IoError(format!("failed to read the options of {path:?}: {e}"))
+    };
+    // The options files are numbered like the manifests, the latest one has the largest number
+    let mut latest: Option<(u64, std::path::PathBuf)> = None;
+    for entry in fs::read_dir(path).map_err(io_error)? {
+        let entry = entry.map_err(io_error)?;
+        let number = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("OPTIONS-"))
+            .and_then(|number| number.parse::<u64>().ok());
+        if let Some(number) = number {
+            if latest.as_ref().map_or(true, |(latest, _)| number > *latest) {
+                latest = Some((number, entry.path()));
+            }
+        }
+    }
+    match latest {
+        Some((_, options_file)) => Ok(Some(parse_cf_options(
+            &fs::read_to_string(options_file).map_err(io_error)?,
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// Parses the `[CFOptions "name"]` sections of an options file, by column family name
+fn parse_cf_options(contents: &str) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut cf_options: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut section: Option<String> = None;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = line
+                .strip_prefix("[CFOptions \"")
+                .and_then(|rest| rest.strip_suffix("\"]"))
+                .map(str::to_owned);
+        } else if let (Some(cf), Some((name, value))) = (&section, line.split_once('=')) {
+            cf_options
+                .entry(cf.clone())
+                .or_default()
+                .insert(name.trim().to_owned(), value.trim().to_owned());
+        }
+    }
+    cf_options
+}
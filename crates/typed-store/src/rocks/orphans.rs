@@ -0,0 +1,115 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::path::Path;
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    default_db_name, lifecycle::drop_table, metadata::is_internal_cf, open_cf_read_only,
+    SystemClock, TypedStoreError,
+};
+
+/// A column family present on disk but not used by the tables of the database,
+/// typically left behind when a table is removed or renamed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrphanColumnFamily {
+    pub name: String,
+    /// The size of the SST files and memtables of the column family
+    pub size_bytes: u64,
+    pub estimated_num_keys: u64,
+}
+
+/// Returns the column families of the database which are not in `tables`.
+/// The default and metadata column families are never reported.
+pub fn find_orphan_cfs(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[&str],
+) -> Result<Vec<OrphanColumnFamily>, TypedStoreError> {
+    let cfs = rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(
+        &rocksdb::Options::default(),
+        rocksdb.path(),
+    )?;
+    let mut orphans = vec![];
+    for name in cfs {
+        if is_internal_cf(&name) || tables.contains(&name.as_str()) {
+            continue;
+        }
+        let cf = rocksdb
+            .cf_handle(&name)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(name.clone()))?;
+        let property = |property: &str| -> Result<u64, TypedStoreError> {
+            Ok(rocksdb.property_int_value_cf(&cf, property)?.unwrap_or(0))
+        };
+        orphans.push(OrphanColumnFamily {
+            size_bytes: property("rocksdb.total-sst-files-size")?
+                + property("rocksdb.cur-size-all-mem-tables")?,
+            estimated_num_keys: property("rocksdb.estimate-num-keys")?,
+            name,
+        });
+    }
+    Ok(orphans)
+}
+
+/// Opens the database at `path` in read only mode and returns its column families which are not in `tables`
+pub fn list_orphan_tables<P: AsRef<Path>>(
+    path: P,
+    tables: &[&str],
+) -> Result<Vec<OrphanColumnFamily>, TypedStoreError> {
+    let cfs =
+        rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(&rocksdb::Options::default(), &path)?;
+    let rocksdb = open_cf_read_only(&path, &cfs)?;
+    find_orphan_cfs(&rocksdb, tables)
+}
+
+/// Logs a warning for every orphan column family of the database
+pub fn warn_orphan_cfs(rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>, tables: &[&str]) {
+    match find_orphan_cfs(rocksdb, tables) {
+        Ok(orphans) => {
+            for orphan in orphans {
+                warn!(
+                    path = ?rocksdb.path(),
+                    table = %orphan.name,
+                    size_bytes = orphan.size_bytes,
+                    estimated_num_keys = orphan.estimated_num_keys,
+                    "Found an orphan column family, it can be dropped with drop_orphan_cfs"
+                );
+            }
+        }
+        Err(e) => warn!("Failed to look for orphan column families: {e}"),
+    }
+}
+
+/// Drops the column families of `confirm_list`, which must all be orphans with regard to `tables`.
+/// Nothing is dropped if any of them is not an orphan. Returns the names of the dropped column families.
+pub fn drop_orphan_cfs(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[&str],
+    confirm_list: &[&str],
+) -> Result<Vec<String>, TypedStoreError> {
+    let orphans = find_orphan_cfs(rocksdb, tables)?;
+    if let Some(name) = confirm_list
+        .iter()
+        .find(|name| !orphans.iter().any(|orphan| orphan.name == **name))
+    {
+        return Err(TypedStoreError::NotAnOrphanColumn(name.to_string()));
+    }
+    let mut dropped = vec![];
+    for orphan in orphans {
+        if confirm_list.contains(&orphan.name.as_str()) {
+            info!(
+                "Dropping orphan column family {} of {} bytes",
+                orphan.name, orphan.size_bytes
+            );
+            drop_table(
+                rocksdb,
+                &default_db_name(rocksdb),
+                &orphan.name,
+                &SystemClock,
+            )?;
+            dropped.push(orphan.name);
+        }
+    }
+    Ok(dropped)
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::sync::{
+    mpsc::{sync_channel, Receiver, SyncSender},
+    Arc,
+};
+
+use rayon::prelude::*;
+use rocksdb::MultiThreaded;
+use serde::de::DeserializeOwned;
+
+use super::{
+    codec::{decode_value, KeyFormat, ValueCodec, ValueEncoding, ValueFormat},
+    filters::total_order_read_options,
+    TypedStoreError,
+};
+
+/// The number of entries read at a time by `DBMap::prefetching_iter` when unspecified
+pub const DEFAULT_PREFETCH_BATCH_SIZE: usize = 1024;
+
+/// The number of batches read or decoded ahead of the consumer
+const PREFETCH_QUEUE_DEPTH: usize = 2;
+
+type RawBatch = Vec<(Box<[u8]>, Box<[u8]>)>;
+type DecodedBatch<K, V> = Vec<Result<(K, V), TypedStoreError>>;
+
+/// An iterator over all the key-value pairs of a table, which overlaps IO and deserialization.
+///
+/// Raw entries are read in batches on a dedicated thread, then each batch is deserialized in parallel
+/// on the rayon thread pool while the next one is read. This speeds up full table scans when
+/// deserialization dominates, e.g. on tables with large values. The iterator reads from an implicit
+/// snapshot of the table taken when it is created. Dropping it stops the background work.
+pub struct PrefetchIter<K, V> {
+    receiver: Receiver<DecodedBatch<K, V>>,
+    current: std::vec::IntoIter<Result<(K, V), TypedStoreError>>,
+}
+
+impl<K, V> PrefetchIter<K, V>
+where
+    K: DeserializeOwned + Send + 'static,
+    V: DeserializeOwned + Send + 'static,
+{
+    pub(super) fn new(
+        rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        cf: String,
+        key_format: KeyFormat,
+        format: ValueFormat,
+        codec: Option<Arc<dyn ValueCodec>>,
+        batch_size: usize,
+    ) -> Self {
+        let (raw_sender, raw_receiver) = sync_channel(PREFETCH_QUEUE_DEPTH);
+        let (decoded_sender, receiver) = sync_channel(PREFETCH_QUEUE_DEPTH);
+        std::thread::spawn(move || read_batches(rocksdb, cf, batch_size.max(1), raw_sender));
+        std::thread::spawn(move || {
+            decode_batches(raw_receiver, key_format, format, codec, decoded_sender)
+        });
+        Self {
+            receiver,
+            current: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<K, V> Iterator for PrefetchIter<K, V> {
+    type Item = Result<(K, V), TypedStoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(item);
+            }
+            // The decoding thread hangs up once the table is exhausted
+            self.current = self.receiver.recv().ok()?.into_iter();
+        }
+    }
+}
+
+fn read_batches(
+    rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    cf: String,
+    batch_size: usize,
+    sender: SyncSender<Result<RawBatch, TypedStoreError>>,
+) {
+    let cf_handle = match rocksdb.cf_handle(&cf) {
+        Some(cf_handle) => cf_handle,
+        None => {
+            let _ = sender.send(Err(TypedStoreError::UnregisteredColumn(cf)));
+            return;
+        }
+    };
+    let mut db_iter = rocksdb.raw_iterator_cf_opt(&cf_handle, total_order_read_options());
+    db_iter.seek_to_first();
+    loop {
+        let mut batch = Vec::with_capacity(batch_size);
+        while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+            batch.push((key.into(), value.into()));
+            db_iter.next();
+            if batch.len() == batch_size {
+                break;
+            }
+        }
+        if let Err(e) = db_iter.status() {
+            let _ = sender.send(Err(e.into()));
+            return;
+        }
+        // Stop at the end of the table, or when the iterator was dropped
+        if batch.is_empty() || sender.send(Ok(batch)).is_err() {
+            return;
+        }
+    }
+}
+
+fn decode_batches<K, V>(
+    receiver: Receiver<Result<RawBatch, TypedStoreError>>,
+    key_format: KeyFormat,
+    format: ValueFormat,
+    codec: Option<Arc<dyn ValueCodec>>,
+    sender: SyncSender<DecodedBatch<K, V>>,
+) where
+    K: DeserializeOwned + Send,
+    V: DeserializeOwned + Send,
+{
+    let encoding = ValueEncoding {
+        format,
+        codec: codec.as_deref(),
+    };
+    for batch in receiver {
+        let decoded: DecodedBatch<K, V> = match batch {
+            Ok(batch) => batch
+                .par_iter()
+                .map(|(key, value)| -> Result<(K, V), TypedStoreError> {
+                    Ok((
+                        key_format.deserialize(key)?,
+                        decode_value(encoding, key, value)?,
+                    ))
+                })
+                .collect(),
+            Err(e) => vec![Err(e)],
+        };
+        if sender.send(decoded).is_err() {
+            return;
+        }
+    }
+}
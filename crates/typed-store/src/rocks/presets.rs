@@ -0,0 +1,124 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Preset options of tables, for common workloads.
+//!
+//! The presets start from `default_rocksdb_options`, and can be used as the
+//! `#[default_options_override_fn]` of a table, or further tuned by the override functions:
+//! - `universal_compaction_options`, for tables mostly appended to and read recently written,
+//! - `write_heavy_options`, for tables with sustained high write rates,
+//! - `point_lookup_options`, for tables read with `get` and never iterated,
+//! - `default_options_for_large_table`, for tables too large to hold their indexes in memory.
+//!
+//! The presets setting block based table options replace those of a memory budget or of a filter
+//! policy, and should not be combined with them.
+
+use rocksdb::{
+    BlockBasedIndexType, BlockBasedOptions, DBCompactionStyle, DataBlockIndexType,
+    UniversalCompactOptions,
+};
+
+use super::{default_rocksdb_options, FilterPolicy};
+
+/// The maximum size of a table with universal compaction, in percents of the size of its last
+/// sorted run, before all its files are compacted together
+const UNIVERSAL_MAX_SIZE_AMPLIFICATION_PERCENT: i32 = 200;
+/// The size of a memtable of the write heavy tables
+const WRITE_HEAVY_WRITE_BUFFER_SIZE: usize = 256 << 20;
+/// The number of memtables of the write heavy tables, merged by 2 when flushed
+const WRITE_HEAVY_MAX_WRITE_BUFFER_NUMBER: i32 = 6;
+/// The number of L0 files of the write heavy tables triggering a compaction, slowing down and
+/// stopping the writes
+const WRITE_HEAVY_L0_TRIGGERS: (i32, i32, i32) = (8, 32, 64);
+/// The fraction of the memtables of the point lookup tables used by their bloom filter
+const POINT_LOOKUP_MEMTABLE_BLOOM_RATIO: f64 = 0.02;
+
+/// Returns the default options with universal compaction.
+///
+/// Universal compaction merges the files of similar sizes together instead of compacting level by
+/// level, which writes about half as much as level compaction, for up to twice the disk usage
+/// (`UNIVERSAL_MAX_SIZE_AMPLIFICATION_PERCENT`) and slower reads of old keys. Suited to tables
+/// which are mostly appended to, like logs of transactions or certificates.
+pub fn universal_compaction_options() -> rocksdb::Options {
+    let mut options = default_rocksdb_options();
+    options.set_compaction_style(DBCompactionStyle::Universal);
+    let mut universal_options = UniversalCompactOptions::default();
+    universal_options.set_max_size_amplification_percent(UNIVERSAL_MAX_SIZE_AMPLIFICATION_PERCENT);
+    options.set_universal_compaction_options(&universal_options);
+    options
+}
+
+/// Returns the default options with larger and more memtables, and more L0 files before slowing
+/// down the writes, so that bursts of writes are absorbed without stalls. The memtables take up
+/// to 1.5GiB per table, within the write buffer size of the database.
+pub fn write_heavy_options() -> rocksdb::Options {
+    let mut options = default_rocksdb_options();
+    options.set_write_buffer_size(WRITE_HEAVY_WRITE_BUFFER_SIZE);
+    options.set_max_write_buffer_number(WRITE_HEAVY_MAX_WRITE_BUFFER_NUMBER);
+    options.set_min_write_buffer_number_to_merge(2);
+    let (compaction, slowdown, stop) = WRITE_HEAVY_L0_TRIGGERS;
+    options.set_level_zero_file_num_compaction_trigger(compaction);
+    options.set_level_zero_slowdown_writes_trigger(slowdown);
+    options.set_level_zero_stop_writes_trigger(stop);
+    // L1 holds about as much as the L0 files compacted into it at once
+    options.set_max_bytes_for_level_base(
+        (WRITE_HEAVY_WRITE_BUFFER_SIZE * 2 * compaction as usize) as u64,
+    );
+    options.set_target_file_size_base(WRITE_HEAVY_WRITE_BUFFER_SIZE as u64 / 2);
+    options
+}
+
+/// Returns the default options with whole key bloom filters in the SST files and in the
+/// memtables, and a hash index in the data blocks, so that `get` reads at most one data block
+/// of a single SST file in most cases. Iterations still work, without benefiting from these.
+pub fn point_lookup_options() -> rocksdb::Options {
+    let mut options = default_rocksdb_options();
+    let mut block_options = BlockBasedOptions::default();
+    FilterPolicy::whole_key_bloom().apply_to_block_options(&mut block_options);
+    block_options.set_data_block_index_type(DataBlockIndexType::BinaryAndHash);
+    options.set_block_based_table_factory(&block_options);
+    options.set_memtable_prefix_bloom_ratio(POINT_LOOKUP_MEMTABLE_BLOOM_RATIO);
+    options.set_memtable_whole_key_filtering(true);
+    options
+}
+
+/// The size of the partitions of the indexes and filters of the large tables
+const LARGE_TABLE_METADATA_BLOCK_SIZE: usize = 4096;
+
+/// Partitions the index and filter blocks in `block_options`, and caches them in the block cache.
+///
+/// The indexes and filters of a table are otherwise held in memory for every open SST file, which
+/// for tables of hundreds of gigabytes takes gigabytes of memory. With partitions, only a top level
+/// index of the partitions is held in memory, and the partitions are loaded in the block cache when
+/// used, competing with the data blocks. The top level index and the partitions of the L0 files,
+/// which are read by every lookup, are pinned in the block cache.
+pub fn set_large_table_block_options(block_options: &mut BlockBasedOptions) {
+    block_options.set_index_type(BlockBasedIndexType::TwoLevelIndexSearch);
+    block_options.set_partition_filters(true);
+    block_options.set_metadata_block_size(LARGE_TABLE_METADATA_BLOCK_SIZE);
+    block_options.set_cache_index_and_filter_blocks(true);
+    block_options.set_pin_top_level_index_and_filter(true);
+    block_options.set_pin_l0_filter_and_index_blocks_in_cache(true);
+}
+
+/// Returns `options` with partitioned indexes and filters, see `set_large_table_block_options`.
+/// The block based table options of `options` are replaced, see `MemoryBudget::large_table_options`
+/// to also share a block cache
+pub fn large_table_options(
+    mut options: rocksdb::Options,
+    filter: FilterPolicy,
+) -> rocksdb::Options {
+    let mut block_options = BlockBasedOptions::default();
+    set_large_table_block_options(&mut block_options);
+    filter.apply_to_block_options(&mut block_options);
+    filter.apply_to_options(&mut options);
+    options.set_block_based_table_factory(&block_options);
+    options
+}
+
+/// The default options of the tables too large to hold their indexes and filters in memory,
+/// with partitioned indexes and whole key bloom filters. Tables can also be annotated with the
+/// `#[large_table]` derive attribute, which also applies their `#[filter(...)]` if any
+pub fn default_options_for_large_table() -> rocksdb::Options {
+    large_table_options(default_rocksdb_options(), FilterPolicy::whole_key_bloom())
+}
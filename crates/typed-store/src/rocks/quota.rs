@@ -0,0 +1,175 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Disk usage quotas of databases and tables.
+//!
+//! A full disk makes RocksDB fail its writes, and can leave a truncated WAL behind. Checking the
+//! disk usage against a `DiskQuota` periodically lets nodes prune data or alert an operator while
+//! there is still room. The usage of the database is the size of all the files of its directory
+//! (SST files, WAL, manifests...), and the usage of a table the size of its SST files and memtables.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use rocksdb::MultiThreaded;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use super::TypedStoreError;
+use crate::metrics::DBMetrics;
+
+/// The default interval between two checks of the disk usage
+pub const DEFAULT_QUOTA_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The maximum disk usage of a database, and of some of its tables
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskQuota {
+    db_limit_bytes: Option<u64>,
+    table_limits_bytes: BTreeMap<String, u64>,
+}
+
+impl DiskQuota {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the size of all the files of the database
+    pub fn with_db_limit(mut self, bytes: u64) -> Self {
+        self.db_limit_bytes = Some(bytes);
+        self
+    }
+
+    /// Limits the size of the SST files and memtables of `table`
+    pub fn with_table_limit(mut self, table: &str, bytes: u64) -> Self {
+        self.table_limits_bytes.insert(table.to_owned(), bytes);
+        self
+    }
+}
+
+/// A limit of a `DiskQuota` which is exceeded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    /// The table over its limit, or `None` if it is the whole database
+    pub table: Option<String>,
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+}
+
+/// Returns the size of all the files of the database
+pub fn db_disk_usage(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<u64, TypedStoreError> {
+    let path = rocksdb.path();
+    let io_error = |e: std::io::Error| {
+        TypedStoreError::IoError(format!("failed to read the size of {path:?}: {e}"))
+    };
+    let mut size = 0;
+    for entry in fs::read_dir(path).map_err(io_error)? {
+        match entry.and_then(|entry| entry.metadata()) {
+            Ok(metadata) if metadata.is_file() => size += metadata.len(),
+            Ok(_) => {}
+            // Files are deleted concurrently, e.g. once compacted
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error(e)),
+        }
+    }
+    Ok(size)
+}
+
+/// Returns the size of the SST files and memtables of a table
+pub fn table_disk_usage(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+) -> Result<u64, TypedStoreError> {
+    let cf = rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+    let property = |property: &str| -> Result<u64, TypedStoreError> {
+        Ok(rocksdb.property_int_value_cf(&cf, property)?.unwrap_or(0))
+    };
+    Ok(property("rocksdb.total-sst-files-size")? + property("rocksdb.cur-size-all-mem-tables")?)
+}
+
+/// Checks the disk usage of the database against `quota`, and returns the limits which are exceeded.
+/// The usages are recorded in the `DBMetrics`, labelled with `db_name`
+pub fn check_disk_quota(
+    db_name: &str,
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    quota: &DiskQuota,
+) -> Result<Vec<QuotaExceeded>, TypedStoreError> {
+    let metrics = &DBMetrics::get().quota_metrics;
+    let mut exceeded = vec![];
+
+    let used_bytes = db_disk_usage(rocksdb)?;
+    metrics
+        .rocksdb_disk_usage_bytes
+        .with_label_values(&[db_name])
+        .set(used_bytes as i64);
+    if let Some(limit_bytes) = quota.db_limit_bytes {
+        if used_bytes > limit_bytes {
+            exceeded.push(QuotaExceeded {
+                table: None,
+                used_bytes,
+                limit_bytes,
+            });
+        }
+    }
+    for (table, &limit_bytes) in &quota.table_limits_bytes {
+        let used_bytes = table_disk_usage(rocksdb, table)?;
+        if used_bytes > limit_bytes {
+            exceeded.push(QuotaExceeded {
+                table: Some(table.clone()),
+                used_bytes,
+                limit_bytes,
+            });
+        }
+    }
+
+    for quota in &exceeded {
+        let table = quota.table.as_deref().unwrap_or("");
+        error!(
+            "Disk usage of {db_name} {table} is {} bytes, over its quota of {} bytes",
+            quota.used_bytes, quota.limit_bytes
+        );
+        metrics
+            .rocksdb_disk_quota_exceeded
+            .with_label_values(&[db_name, table])
+            .inc();
+    }
+    Ok(exceeded)
+}
+
+/// Spawns a task checking the disk usage of the database against `quota` every `interval`,
+/// and calling `on_exceeded` with the exceeded limits at every check where some are.
+/// The task stops once the database is closed.
+pub fn spawn_disk_quota_check(
+    db_name: String,
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    quota: DiskQuota,
+    interval: Duration,
+    on_exceeded: impl Fn(&[QuotaExceeded]) + Send + 'static,
+) -> JoinHandle<()> {
+    let rocksdb: Weak<_> = Arc::downgrade(rocksdb);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let db = match rocksdb.upgrade() {
+                Some(db) => db,
+                None => {
+                    debug!("Database {db_name} is closed, stopping the disk quota check");
+                    break;
+                }
+            };
+            match check_disk_quota(&db_name, &db, &quota) {
+                Ok(exceeded) if !exceeded.is_empty() => on_exceeded(&exceeded),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to check the disk usage of {db_name}: {e}"),
+            }
+        }
+    })
+}
@@ -0,0 +1,102 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sampling of the read amplification of the tables.
+//!
+//! A table whose compactions fall behind, or whose filters are missing or ineffective, consults
+//! more and more SST files per `get`. A `DBMap` with `with_read_amp_sampling` measures a fraction
+//! of its gets with the RocksDB `PerfContext` of the reading thread, and reports the SST files and
+//! blocks they read in the `rocksdb_read_amp_sst_files` and `rocksdb_read_amp_blocks_read`
+//! histograms, whose sum over count is the average per read.
+//!
+//! The SST files are counted by the data blocks the gets look up in them, from the block cache or
+//! from the files, so that the files excluded by their filters don't count. The index and filter
+//! blocks of the tables caching them in the block cache, e.g. the large tables, count as well.
+//!
+//! The sampling changes neither the perf level nor the perf context of the reading thread, as long
+//! as its perf level is set with `set_perf_level`: RocksDB doesn't expose the perf level of a
+//! thread, which is restored to its default after a sampled get otherwise.
+
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use rocksdb::perf::{set_perf_stats, PerfContext, PerfMetric, PerfStatsLevel};
+
+use crate::metrics::DBMetrics;
+
+thread_local! {
+    /// The perf level of the thread, as set by `set_perf_level`, RocksDB's default otherwise
+    static PERF_LEVEL: Cell<PerfStatsLevel> = Cell::new(PerfStatsLevel::EnableCount);
+}
+
+/// Sets the RocksDB perf level of the calling thread, like `rocksdb::perf::set_perf_stats`, and
+/// records it so that the sampled gets of `DBMap::with_read_amp_sampling` restore it
+pub fn set_perf_level(level: PerfStatsLevel) {
+    PERF_LEVEL.with(|perf_level| perf_level.set(level));
+    set_perf_stats(level);
+}
+
+/// The number of data blocks looked up so far by the thread of `context`, see the module docs
+fn blocks_looked_up(context: &PerfContext) -> u64 {
+    context.metric(PerfMetric::BlockCacheHitCount) + context.metric(PerfMetric::BlockReadCount)
+}
+
+/// Samples one of every `interval` gets of a map and its clones
+#[derive(Debug)]
+pub(crate) struct ReadAmpSampler {
+    interval: u64,
+    reads: AtomicU64,
+}
+
+impl ReadAmpSampler {
+    /// Samples about `fraction` of the gets, at least one of every `u64::MAX`
+    pub(crate) fn new(fraction: f64) -> Self {
+        let interval = if fraction > 0.0 {
+            (1.0 / fraction).round().max(1.0) as u64
+        } else {
+            u64::MAX
+        };
+        Self {
+            interval,
+            reads: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `read`, and reports its read amplification if it is sampled
+    pub(crate) fn sample<R>(&self, db_name: &str, cf_name: &str, read: impl FnOnce() -> R) -> R {
+        if self.reads.fetch_add(1, Ordering::Relaxed) % self.interval != 0 {
+            return read();
+        }
+        // The counters of the context are read before and after the get rather than reset, so
+        // that the context keeps counting for the thread
+        let level = PERF_LEVEL.with(Cell::get);
+        let counting = level as i32 >= PerfStatsLevel::EnableCount as i32;
+        if !counting {
+            set_perf_stats(PerfStatsLevel::EnableCount);
+        }
+        let context = PerfContext::default();
+        let (blocks_before, reads_before) = (
+            blocks_looked_up(&context),
+            context.metric(PerfMetric::BlockReadCount),
+        );
+        let result = read();
+        let sst_files = blocks_looked_up(&context) - blocks_before;
+        let blocks_read = context.metric(PerfMetric::BlockReadCount) - reads_before;
+        if !counting {
+            set_perf_stats(level);
+        }
+
+        let op_metrics = &DBMetrics::get().op_metrics;
+        op_metrics
+            .rocksdb_read_amp_sst_files
+            .with_label_values(&[db_name, cf_name])
+            .observe(sst_files as f64);
+        op_metrics
+            .rocksdb_read_amp_blocks_read
+            .with_label_values(&[db_name, cf_name])
+            .observe(blocks_read as f64);
+        result
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tables which only a designated writer may write once open.
+//!
+//! A field of a `DBMapUtils` struct marked `#[read_only_after_open]` has the type
+//! `ReadOnlyMap<K, V>`, which only exposes the reads of the table, on the primary handle too. The
+//! writable `DBMap` of the table is only returned along the struct by the generated
+//! `open_tables_read_write_with_writer`, in the generated `{Struct}Writer`, so that the compiler
+//! checks that only the components given the writer write the table.
+
+use std::{borrow::Borrow, sync::Arc};
+
+use rocksdb::MultiThreaded;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{iter::Iter, keys::Keys, values::Values, DBMap, TypedStoreError};
+use crate::traits::Map;
+
+/// The reads of a table, see the module documentation
+#[derive(Clone, Debug)]
+pub struct ReadOnlyMap<K, V> {
+    /// The database of the table, for the operations on the whole database
+    pub rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    map: DBMap<K, V>,
+}
+
+impl<K, V> ReadOnlyMap<K, V> {
+    /// Restricts `map` to its reads. The map can't be written through the returned one
+    pub fn new(map: DBMap<K, V>) -> Self {
+        Self {
+            rocksdb: map.rocksdb.clone(),
+            map,
+        }
+    }
+
+    /// The name of the database, labelling the metrics of the table
+    pub fn db_name(&self) -> &str {
+        self.map.db_name()
+    }
+
+    /// Returns the map labelled with `db_name` in the metrics, see `DBMap::with_db_name`
+    pub fn with_db_name(self, db_name: &str) -> Self {
+        Self::new(self.map.with_db_name(db_name))
+    }
+}
+
+impl<K, V> ReadOnlyMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
+        self.map.contains_key(key)
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        self.map.get(key)
+    }
+
+    pub fn multi_get<J>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<Option<V>>, TypedStoreError>
+    where
+        J: Borrow<K>,
+    {
+        self.map.multi_get(keys)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    pub fn keys(&self) -> Keys<'_, K> {
+        self.map.keys()
+    }
+
+    pub fn values(&self) -> Values<'_, V> {
+        self.map.values()
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{fmt, path::Path, sync::Arc};
+
+use rocksdb::{DBRecoveryMode, MultiThreaded};
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument, warn};
+
+use super::{default_rocksdb_options, open_cf_opts, TypedStoreError};
+
+/// What to do when a database fails to open because of corruption
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RepairPolicy {
+    /// Fail with an `OpenFailureReport`
+    #[default]
+    Fail,
+    /// Reopen without paranoid checks, skipping any corrupted WAL record.
+    /// Writes which were not yet flushed may be lost.
+    SkipCorruptedRecords,
+    /// Run the RocksDB repairer on the database before reopening it.
+    /// Data in corrupted files may be lost, and the repair can take a long time on large databases.
+    Repair,
+}
+
+/// Diagnostics gathered when a database fails to open
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OpenFailureReport {
+    /// Path of the database
+    pub path: String,
+    /// The error returned by RocksDB
+    pub error: String,
+    /// Whether RocksDB reported the failure as a corruption
+    pub is_corruption: bool,
+    /// The column families found on disk, if the manifest could be read
+    pub column_families: Vec<String>,
+    /// The files (SST, WAL, MANIFEST...) mentioned by the error
+    pub suspect_files: Vec<String>,
+    /// The least destructive policy which may allow opening the database
+    pub suggested_policy: Option<RepairPolicy>,
+}
+
+impl OpenFailureReport {
+    fn new<P: AsRef<Path>>(path: P, options: &rocksdb::Options, error: &TypedStoreError) -> Self {
+        let error = match error {
+            TypedStoreError::RocksDBError(e) => e.clone(),
+            e => e.to_string(),
+        };
+        let is_corruption = error.starts_with("Corruption:");
+        let column_families =
+            rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(options, &path).unwrap_or_default();
+        let suspect_files = error
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+            .filter(|token| {
+                token.ends_with(".sst")
+                    || token.ends_with(".log")
+                    || token.contains("MANIFEST-")
+                    || token.contains("OPTIONS-")
+            })
+            .map(|token| token.to_owned())
+            .collect();
+        let suggested_policy = if !is_corruption {
+            None
+        } else if error.contains(".log") {
+            // Corrupted WAL tail, typically caused by a crash in the middle of a write
+            Some(RepairPolicy::SkipCorruptedRecords)
+        } else {
+            Some(RepairPolicy::Repair)
+        };
+
+        OpenFailureReport {
+            path: path.as_ref().display().to_string(),
+            error,
+            is_corruption,
+            column_families,
+            suspect_files,
+            suggested_policy,
+        }
+    }
+}
+
+impl fmt::Display for OpenFailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to open {}: {}", self.path, self.error)?;
+        if !self.column_families.is_empty() {
+            write!(f, "; column families: {:?}", self.column_families)?;
+        }
+        if !self.suspect_files.is_empty() {
+            write!(f, "; suspect files: {:?}", self.suspect_files)?;
+        }
+        if let Some(policy) = self.suggested_policy {
+            write!(f, "; suggested repair policy: {:?}", policy)?;
+        }
+        Ok(())
+    }
+}
+
+/// Opens a database like `open_cf_opts`, applying `policy` if the database is corrupted.
+///
+/// Failures which can't be handled by the policy are returned as an
+/// `TypedStoreError::OpenFailure` carrying an `OpenFailureReport`.
+#[instrument(level="debug", skip_all, fields(path = ?path.as_ref(), policy = ?policy), err)]
+pub fn open_cf_opts_with_repair_policy<P: AsRef<Path>>(
+    path: P,
+    db_options: Option<rocksdb::Options>,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+    policy: RepairPolicy,
+) -> Result<Arc<rocksdb::DBWithThreadMode<MultiThreaded>>, TypedStoreError> {
+    let options = db_options.unwrap_or_else(default_rocksdb_options);
+    let error = match open_cf_opts(&path, Some(options.clone()), opt_cfs) {
+        Ok(db) => return Ok(db),
+        Err(e) => e,
+    };
+
+    let report = OpenFailureReport::new(&path, &options, &error);
+    error!("{report}");
+    if !report.is_corruption || policy == RepairPolicy::Fail {
+        return Err(TypedStoreError::OpenFailure(Box::new(report)));
+    }
+
+    let mut options = options;
+    match policy {
+        RepairPolicy::Fail => unreachable!("handled above"),
+        RepairPolicy::SkipCorruptedRecords => {
+            warn!("Reopening {} skipping corrupted records", report.path);
+            options.set_paranoid_checks(false);
+            options.set_wal_recovery_mode(DBRecoveryMode::SkipAnyCorruptedRecord);
+        }
+        RepairPolicy::Repair => {
+            warn!("Repairing {}", report.path);
+            rocksdb::DBWithThreadMode::<MultiThreaded>::repair(&options, &path)?;
+        }
+    }
+    open_cf_opts(&path, Some(options.clone()), opt_cfs).map_err(|e| {
+        TypedStoreError::OpenFailure(Box::new(OpenFailureReport::new(&path, &options, &e)))
+    })
+}
@@ -0,0 +1,42 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use rocksdb::MultiThreaded;
+
+/// How far a secondary instance is behind its primary, see `replica_lag`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplicaLag {
+    /// The last sequence number written by the primary
+    pub primary_sequence: u64,
+    /// The last sequence number the secondary caught up with
+    pub secondary_sequence: u64,
+}
+
+impl ReplicaLag {
+    /// The lag of `secondary` behind a primary whose last sequence number is `primary_sequence`,
+    /// e.g. published by a primary running in another process from its `latest_sequence_number`
+    pub fn from_primary_sequence(
+        primary_sequence: u64,
+        secondary: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    ) -> Self {
+        Self {
+            primary_sequence,
+            secondary_sequence: secondary.latest_sequence_number(),
+        }
+    }
+
+    /// Number of sequence numbers (roughly, of written keys) the secondary is behind the primary
+    pub fn sequences_behind(&self) -> u64 {
+        self.primary_sequence
+            .saturating_sub(self.secondary_sequence)
+    }
+}
+
+/// Returns how far `secondary`, opened as a secondary instance of `primary`, is behind it.
+///
+/// The gap only shrinks when the secondary catches up, e.g. with `try_catch_up_with_primary`.
+pub fn replica_lag(
+    primary: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    secondary: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> ReplicaLag {
+    ReplicaLag::from_primary_sequence(primary.latest_sequence_number(), secondary)
+}
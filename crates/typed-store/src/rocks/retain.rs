@@ -0,0 +1,192 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use tracing::{debug, info};
+
+use super::{
+    codec::decode_value, filters::total_order_read_options, DBBatch, DBMap, TypedStoreError,
+};
+
+/// Default number of entries `DBMap::retain` scans per chunk.
+pub const DEFAULT_RETAIN_CHUNK_SIZE: usize = 10_000;
+/// Suggested minimum number of consecutive entries deleted with a range delete, once enabled with
+/// `RetainOptions::with_range_deletes`.
+pub const DEFAULT_MIN_RANGE_DELETE: usize = 64;
+
+/// Progress of `DBMap::retain`, reported every time a chunk is committed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetainProgress {
+    /// Number of chunks scanned so far
+    pub chunks: usize,
+    /// Number of entries scanned so far
+    pub scanned: usize,
+    /// Number of entries deleted so far
+    pub deleted: usize,
+    /// Number of range deletes the deleted entries were written as so far
+    pub range_deletes: usize,
+}
+
+/// How `DBMap::retain_with` scans and deletes the entries of a table.
+pub struct RetainOptions {
+    /// Number of entries scanned, and whose deletes are committed, per chunk
+    pub chunk_size: usize,
+    /// Minimum number of consecutive deleted entries written as one range delete instead of point
+    /// deletes, if any, see `with_range_deletes`. Point deletes only by default
+    pub min_range_delete: Option<usize>,
+    /// Maximum number of entries deleted per second on average, by sleeping between chunks
+    pub deletes_per_sec: Option<u64>,
+    on_progress: Option<Box<dyn FnMut(&RetainProgress) + Send>>,
+}
+
+impl Default for RetainOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_RETAIN_CHUNK_SIZE,
+            min_range_delete: None,
+            deletes_per_sec: None,
+            on_progress: None,
+        }
+    }
+}
+
+impl RetainOptions {
+    /// Write the runs of at least `min_range_delete` consecutive deleted entries as range deletes,
+    /// which are cheaper to write, but slow down the reads until compacted.
+    ///
+    /// A range delete also deletes the entries written into its range since the scan, without
+    /// checking them with the predicate: range deletes must only be enabled while `retain` has
+    /// exclusive access to the table, e.g. when its writers are stopped
+    pub fn with_range_deletes(mut self, min_range_delete: usize) -> Self {
+        self.min_range_delete = Some(min_range_delete);
+        self
+    }
+
+    /// Register a callback invoked after every committed chunk
+    pub fn with_progress(
+        mut self,
+        on_progress: impl FnMut(&RetainProgress) + Send + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+}
+
+/// Deletes the entries of `map` for which `pred` returns false, see `DBMap::retain_with`
+pub(crate) fn retain_entries<K: DeserializeOwned, V: DeserializeOwned>(
+    map: &DBMap<K, V>,
+    mut options: RetainOptions,
+    pred: impl Fn(&K, &V) -> bool,
+) -> Result<RetainProgress, TypedStoreError> {
+    let chunk_size = options.chunk_size.max(1);
+    let started = map.clock.instant();
+    let mut progress = RetainProgress::default();
+    let mut resume_from: Option<Vec<u8>> = None;
+    loop {
+        // A fresh iterator per chunk, so that the scan doesn't pin the table for its whole duration
+        let mut db_iter = map
+            .rocksdb
+            .raw_iterator_cf_opt(&map.cf(), total_order_read_options());
+        match &resume_from {
+            Some(key) => db_iter.seek(key),
+            None => db_iter.seek_to_first(),
+        }
+        let mut deletes = Deletes::new(map.batch(), options.min_range_delete);
+        let mut scanned = 0;
+        while scanned < chunk_size {
+            let (key, value) = match (db_iter.key(), db_iter.value()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => break,
+            };
+            scanned += 1;
+            if pred(
+                &map.key_format.deserialize(key)?,
+                &decode_value(map.encoding(), key, value)?,
+            ) {
+                deletes.end_run(map);
+            } else {
+                deletes.push(key.to_vec());
+            }
+            db_iter.next();
+        }
+        db_iter.status()?;
+        resume_from = db_iter.key().map(<[u8]>::to_vec);
+        drop(db_iter);
+
+        deletes.end_run(map);
+        if deletes.deleted > 0 {
+            deletes.batch.write()?;
+        }
+        progress.chunks += 1;
+        progress.scanned += scanned;
+        progress.deleted += deletes.deleted;
+        progress.range_deletes += deletes.range_deletes;
+        debug!("Retain in {}: {progress:?}", map.cf);
+        if let Some(on_progress) = options.on_progress.as_mut() {
+            on_progress(&progress);
+        }
+        if resume_from.is_none() {
+            break;
+        }
+        if let Some(deletes_per_sec) = options.deletes_per_sec {
+            let due =
+                Duration::from_secs_f64(progress.deleted as f64 / deletes_per_sec.max(1) as f64);
+            let elapsed = map.clock.instant().saturating_sub(started);
+            if let Some(ahead) = due.checked_sub(elapsed) {
+                map.clock.sleep(ahead);
+            }
+        }
+    }
+    info!(
+        "Retained {} of {} entries of {}",
+        progress.scanned - progress.deleted,
+        progress.scanned,
+        map.cf
+    );
+    Ok(progress)
+}
+
+/// The deletes of a chunk, with the current run of consecutive deleted keys
+struct Deletes {
+    batch: DBBatch,
+    min_range_delete: Option<usize>,
+    run: Vec<Vec<u8>>,
+    deleted: usize,
+    range_deletes: usize,
+}
+
+impl Deletes {
+    fn new(batch: DBBatch, min_range_delete: Option<usize>) -> Self {
+        Self {
+            batch,
+            min_range_delete: min_range_delete.map(|min| min.max(1)),
+            run: Vec::new(),
+            deleted: 0,
+            range_deletes: 0,
+        }
+    }
+
+    fn push(&mut self, key: Vec<u8>) {
+        self.run.push(key);
+    }
+
+    /// Adds the deletes of the current run to the batch
+    fn end_run<K, V>(&mut self, map: &DBMap<K, V>) {
+        let run = std::mem::take(&mut self.run);
+        self.deleted += run.len();
+        if self.min_range_delete.map_or(true, |min| run.len() < min) {
+            for key in run {
+                self.batch.delete_raw_key(map, key);
+            }
+            return;
+        }
+        // The range ends right after the last key of the run, so that it can't cover the next key
+        // kept, nor the keys after it written since the scan
+        let mut end = run[run.len() - 1].clone();
+        end.push(0);
+        let start = run.into_iter().next().expect("The run is not empty");
+        self.batch.delete_raw_range(map, start, end);
+        self.range_deletes += 1;
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::BTreeMap;
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::TypedStoreError;
+
+/// The options of a table which RocksDB allows to change while the database is open.
+/// Unset options are left unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeOptions {
+    pub write_buffer_size: Option<usize>,
+    pub max_write_buffer_number: Option<i32>,
+    pub level0_file_num_compaction_trigger: Option<i32>,
+    pub level0_slowdown_writes_trigger: Option<i32>,
+    pub level0_stop_writes_trigger: Option<i32>,
+    pub target_file_size_base: Option<u64>,
+    pub max_bytes_for_level_base: Option<u64>,
+    pub disable_auto_compactions: Option<bool>,
+}
+
+impl RuntimeOptions {
+    /// The options as the name-value pairs expected by `set_options`
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![];
+        let mut push = |name, value: Option<String>| {
+            if let Some(value) = value {
+                pairs.push((name, value));
+            }
+        };
+        push(
+            "write_buffer_size",
+            self.write_buffer_size.map(|v| v.to_string()),
+        );
+        push(
+            "max_write_buffer_number",
+            self.max_write_buffer_number.map(|v| v.to_string()),
+        );
+        push(
+            "level0_file_num_compaction_trigger",
+            self.level0_file_num_compaction_trigger
+                .map(|v| v.to_string()),
+        );
+        push(
+            "level0_slowdown_writes_trigger",
+            self.level0_slowdown_writes_trigger.map(|v| v.to_string()),
+        );
+        push(
+            "level0_stop_writes_trigger",
+            self.level0_stop_writes_trigger.map(|v| v.to_string()),
+        );
+        push(
+            "target_file_size_base",
+            self.target_file_size_base.map(|v| v.to_string()),
+        );
+        push(
+            "max_bytes_for_level_base",
+            self.max_bytes_for_level_base.map(|v| v.to_string()),
+        );
+        push(
+            "disable_auto_compactions",
+            self.disable_auto_compactions.map(|v| v.to_string()),
+        );
+        pairs
+    }
+}
+
+/// The runtime options to apply to the tables of a database, by table name,
+/// e.g. deserialized from a configuration file
+pub type RuntimeConfig = BTreeMap<String, RuntimeOptions>;
+
+/// Changes options of the column family `cf` of an open database, e.g. `[("write_buffer_size", "67108864")]`.
+/// RocksDB rejects the options which can't be changed at runtime.
+pub fn set_options(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf: &str,
+    opts: &[(&str, &str)],
+) -> Result<(), TypedStoreError> {
+    let handle = rocksdb
+        .cf_handle(cf)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf.to_owned()))?;
+    rocksdb.set_options_cf(&handle, opts)?;
+    info!("Changed the options of {cf}: {:?}", opts);
+    Ok(())
+}
+
+/// Applies `config` to the tables of an open database.
+/// Fails without changing any option if `config` names a table which is not in `tables`.
+pub fn apply_runtime_config(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    tables: &[&str],
+    config: &RuntimeConfig,
+) -> Result<(), TypedStoreError> {
+    if let Some(unknown) = config
+        .keys()
+        .find(|table| !tables.contains(&table.as_str()))
+    {
+        return Err(TypedStoreError::UnregisteredColumn(unknown.clone()));
+    }
+    for (table, options) in config {
+        let pairs = options.to_pairs();
+        if pairs.is_empty() {
+            continue;
+        }
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        set_options(rocksdb, table, &pairs)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{be_fix_int_ser, DBBatch, DBMap, TypedStoreError};
+use crate::traits::Map;
+
+/// The position of a long running scan of a table, e.g. a backfill, persisted in a table so that
+/// the scan resumes after the last entry it processed once restarted.
+///
+/// A checkpoint is a value like any other: it can be stored in a `DBMap<String, ScanCheckpoint<K>>`
+/// keyed by the name of the job, and written in the same batch as the writes of the job, so that
+/// they are committed with the position they were made at.
+///
+/// The scans of the iterators of a table read it as of their creation. The sequence number of the
+/// database when the scan started is recorded for reference, but a resumed scan reads the table as
+/// of its resumption: it sees the entries written in the meantime after its last key.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::traits::Map;
+/// let rocks = open_cf(tempfile::tempdir().unwrap(), None, &["objects", "checkpoints"]).unwrap();
+/// let objects = DBMap::<u64, String>::reopen(&rocks, Some("objects")).unwrap();
+/// let checkpoints = DBMap::<String, ScanCheckpoint<u64>>::reopen(&rocks, Some("checkpoints")).unwrap();
+/// objects.multi_insert((0..10).map(|i| (i, i.to_string()))).unwrap();
+///
+/// let job = "backfill".to_owned();
+/// let mut checkpoint = ScanCheckpoint::start(&objects);
+/// for (key, _) in checkpoint.resume(&objects).unwrap().take(4) {
+///     checkpoint.advance(key);
+/// }
+/// checkpoint.save_in_batch(objects.batch(), &checkpoints, &job).unwrap().write().unwrap();
+///
+/// // After a restart
+/// let checkpoint = checkpoints.get(&job).unwrap().unwrap();
+/// let remaining: Vec<_> = checkpoint.resume(&objects).unwrap().map(|(key, _)| key).collect();
+/// assert_eq!(remaining, vec![4, 5, 6, 7, 8, 9]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanCheckpoint<K> {
+    /// The table scanned
+    pub table: String,
+    /// The last key processed, if any
+    pub last_key: Option<K>,
+    /// The sequence number of the database when the scan started
+    pub snapshot_seq: u64,
+    /// The number of entries processed
+    pub entries: u64,
+}
+
+impl<K> ScanCheckpoint<K> {
+    /// The checkpoint of a scan of `map` starting now, from its first entry
+    pub fn start<V>(map: &DBMap<K, V>) -> Self {
+        Self {
+            table: map.cf.clone(),
+            last_key: None,
+            snapshot_seq: map.rocksdb.latest_sequence_number(),
+            entries: 0,
+        }
+    }
+
+    /// Records that the entry of `key` was processed
+    pub fn advance(&mut self, key: K) {
+        self.last_key = Some(key);
+        self.entries += 1;
+    }
+}
+
+impl<K: Serialize + DeserializeOwned> ScanCheckpoint<K> {
+    /// Returns an iterator over the entries of `map` after the last key of the checkpoint, in key
+    /// order. Fails if the checkpoint is not the one of a scan of `map`
+    pub fn resume<'a, V: DeserializeOwned>(
+        &self,
+        map: &'a DBMap<K, V>,
+    ) -> Result<impl Iterator<Item = (K, V)> + 'a, TypedStoreError>
+    where
+        K: 'a,
+    {
+        if self.table != map.cf {
+            return Err(TypedStoreError::InvalidState(format!(
+                "the checkpoint of a scan of {} can't resume a scan of {}",
+                self.table, map.cf
+            )));
+        }
+        let mut iter = map.iter();
+        let mut last_key = None;
+        if let Some(key) = &self.last_key {
+            iter = iter.skip_to(key)?;
+            last_key = Some(be_fix_int_ser(key)?);
+        }
+        // The iterator lands on the last key, unless it was removed since
+        Ok(iter.skip_while(move |(key, _)| {
+            last_key.as_ref().map_or(false, |last_key| {
+                be_fix_int_ser(key).ok().as_ref() == Some(last_key)
+            })
+        }))
+    }
+
+    /// Adds the write of the checkpoint under `job` in `checkpoints` to `batch`, to commit it with
+    /// the writes of the entries it was advanced over
+    pub fn save_in_batch(
+        &self,
+        batch: DBBatch,
+        checkpoints: &DBMap<String, ScanCheckpoint<K>>,
+        job: &str,
+    ) -> Result<DBBatch, TypedStoreError> {
+        batch.insert_batch(checkpoints, [(job.to_owned(), self)])
+    }
+}
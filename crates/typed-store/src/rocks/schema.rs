@@ -0,0 +1,105 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of changes of the key and value types of tables between two runs.
+//!
+//! The type names of the tables are persisted in the default column family, which is not used
+//! by the tables of a derived struct. A table whose types changed still opens fine, but its
+//! entries will likely fail to deserialize: such drifts are logged at open and returned by
+//! `schema_check` until the new schema is recorded with `record_schema`.
+
+use std::collections::BTreeMap;
+
+use rocksdb::MultiThreaded;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use super::TypedStoreError;
+
+const SCHEMA_KEY: &[u8] = b"typed_store_schema";
+
+/// Table names mapped to their key and value type names, as returned by `describe_tables`
+pub type TableSchema = BTreeMap<String, (String, String)>;
+
+/// A table whose key or value type changed since its schema was recorded
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SchemaDrift {
+    pub table: String,
+    pub recorded: (String, String),
+    pub current: (String, String),
+}
+
+fn read_schema(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+) -> Result<TableSchema, TypedStoreError> {
+    match rocksdb.get(SCHEMA_KEY)? {
+        Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+        None => Ok(TableSchema::new()),
+    }
+}
+
+/// Returns the tables of `schema` whose types differ from the recorded ones.
+/// Tables which were never recorded are not reported.
+pub fn schema_check(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    schema: &TableSchema,
+) -> Result<Vec<SchemaDrift>, TypedStoreError> {
+    let recorded = read_schema(rocksdb)?;
+    Ok(schema
+        .iter()
+        .filter_map(|(table, current)| match recorded.get(table) {
+            Some(recorded) if recorded != current => Some(SchemaDrift {
+                table: table.clone(),
+                recorded: recorded.clone(),
+                current: current.clone(),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Records `schema` as the schema of the database, accepting any drift
+pub fn record_schema(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    schema: &TableSchema,
+) -> Result<(), TypedStoreError> {
+    let mut recorded = read_schema(rocksdb)?;
+    recorded.extend(schema.clone());
+    rocksdb.put(SCHEMA_KEY, bincode::serialize(&recorded)?)?;
+    Ok(())
+}
+
+/// Logs a warning for every drift of `schema`, and records the schema of the tables
+/// which were never recorded if the database is `writable`, i.e. opened as primary and not in
+/// read only or secondary mode. Drifts are left to be accepted with `record_schema`.
+pub fn check_schema_on_open(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    schema: &TableSchema,
+    writable: bool,
+) {
+    let result = schema_check(rocksdb, schema).and_then(|drifts| {
+        for drift in &drifts {
+            warn!(
+                path = ?rocksdb.path(),
+                table = %drift.table,
+                recorded_key_type = %drift.recorded.0,
+                recorded_value_type = %drift.recorded.1,
+                current_key_type = %drift.current.0,
+                current_value_type = %drift.current.1,
+                "The types of the table changed since its schema was recorded, its entries may fail to deserialize"
+            );
+        }
+        if !writable {
+            return Ok(());
+        }
+        let new_tables: TableSchema = schema
+            .iter()
+            .filter(|(table, _)| !drifts.iter().any(|drift| &drift.table == *table))
+            .map(|(table, types)| (table.clone(), types.clone()))
+            .collect();
+        record_schema(rocksdb, &new_tables)
+    });
+    if let Err(e) = result {
+        error!("Failed to check the schema of {:?}: {e}", rocksdb.path());
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Secondary paths allocated for the read only handles of a database.
+//!
+//! A RocksDB secondary instance needs a directory of its own for its info log and options files.
+//! Handles opened without an explicit secondary path get a `ManagedSecondaryPath`, under
+//! `{temp_dir}/typed-store-secondary/{db_name}-{hash of the primary path}/{slot}`: the slots of the
+//! live handles of a primary are distinct across all the processes, since secondary instances can't
+//! share a directory, and the lowest free slot is reused by the next handle. A slot is taken by
+//! holding an exclusive lock on its `{slot}.lock` file, which the OS releases when its process
+//! exits: a directory is only ever removed by the process holding the lock of its slot. The
+//! directory of a slot is removed when its handle is dropped, so that processes opening handles
+//! repeatedly don't fill the temporary directory, and the slots left behind by a crashed process
+//! are reused once their lock is released. The lock files are kept, removing them would let two
+//! processes lock different files for the same slot.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use tracing::{debug, warn};
+
+use super::TypedStoreError;
+use crate::metrics::DBMetrics;
+
+/// The directory of the managed secondary paths, in the temporary directory
+const SECONDARY_ROOT_DIR: &str = "typed-store-secondary";
+
+/// A secondary path for a read only handle, removed when dropped. See the module documentation
+#[derive(Debug)]
+pub struct ManagedSecondaryPath {
+    db_name: String,
+    path: PathBuf,
+    /// The locked lock file of the slot, unlocked when closed on drop
+    _lock: File,
+}
+
+impl ManagedSecondaryPath {
+    /// Allocates a secondary path for a new handle of the database at `primary_path`, labelled
+    /// `db_name` in the metrics of the live secondary instances
+    pub fn allocate(primary_path: &Path, db_name: &str) -> Result<Self, TypedStoreError> {
+        let mut hasher = DefaultHasher::new();
+        fs::canonicalize(primary_path)
+            .unwrap_or_else(|_| primary_path.to_path_buf())
+            .hash(&mut hasher);
+        let primary_dir = std::env::temp_dir()
+            .join(SECONDARY_ROOT_DIR)
+            .join(format!("{db_name}-{:016x}", hasher.finish()));
+
+        fs::create_dir_all(&primary_dir).map_err(|e| {
+            TypedStoreError::IoError(format!(
+                "failed to create the secondary paths directory {primary_dir:?}: {e}"
+            ))
+        })?;
+        let (slot, lock) = lock_free_slot(&primary_dir)?;
+        let managed = Self {
+            db_name: db_name.to_owned(),
+            path: primary_dir.join(slot.to_string()),
+            _lock: lock,
+        };
+        // Decremented when `managed` is dropped, including on failure
+        DBMetrics::get()
+            .instance_metrics
+            .rocksdb_secondary_instances
+            .with_label_values(&[db_name])
+            .inc();
+        // The directory of a slot may be left behind by a crashed process, whose lock was released
+        let _ = fs::remove_dir_all(&managed.path);
+        fs::create_dir_all(&managed.path).map_err(|e| {
+            TypedStoreError::IoError(format!(
+                "failed to create the secondary path {:?}: {e}",
+                managed.path
+            ))
+        })?;
+        debug!("Allocated the secondary path {:?}", managed.path);
+        Ok(managed)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ManagedSecondaryPath {
+    fn drop(&mut self) {
+        // Removed before the lock is released by closing its file, after the drop
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("Failed to remove the secondary path {:?}: {e}", self.path);
+        }
+        DBMetrics::get()
+            .instance_metrics
+            .rocksdb_secondary_instances
+            .with_label_values(&[&self.db_name])
+            .dec();
+    }
+}
+
+/// Locks the lock file of the lowest slot of `primary_dir` which no handle holds, in any process
+fn lock_free_slot(primary_dir: &Path) -> Result<(usize, File), TypedStoreError> {
+    for slot in 0.. {
+        let lock_path = primary_dir.join(format!("{slot}.lock"));
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| {
+                TypedStoreError::IoError(format!("failed to open the lock file {lock_path:?}: {e}"))
+            })?;
+        if lock.try_lock_exclusive().is_ok() {
+            return Ok((slot, lock));
+        }
+    }
+    unreachable!("a slot is always free")
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{collections::HashMap, sync::Arc};
+
+use rocksdb::MultiThreaded;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{be_fix_int_ser, BatchStats, DBBatch, DBMap, TypedStoreError};
+use crate::traits::Map;
+
+/// A batch of writes to the tables of a database which are visible to the reads made through it.
+///
+/// The writes are buffered in a `DBBatch` and committed atomically by `commit`, or discarded if
+/// the session is dropped. Reads return the buffered value of a key if it was written through the
+/// session, and the value in the table otherwise, so that code executing a transaction reads its own
+/// writes without a RocksDB transaction. There is no isolation from the other writers: the values
+/// read from the tables may change before the session commits.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// use typed_store::traits::Map;
+///
+/// let db = DBMap::<u32, String>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// let mut session = db.session();
+/// session.insert(&db, &1, &"one".to_owned()).unwrap();
+/// assert_eq!(session.get(&db, &1).unwrap(), Some("one".to_owned()));
+/// assert_eq!(db.get(&1).unwrap(), None);
+/// session.commit().unwrap();
+/// assert_eq!(db.get(&1).unwrap(), Some("one".to_owned()));
+/// ```
+pub struct Session {
+    /// Only missing after a write to the batch failed
+    batch: Option<DBBatch>,
+    /// The values written by the session, serialized without the value codec of their table,
+    /// or `None` if removed, by table and encoded key
+    writes: HashMap<(String, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl Session {
+    pub fn new(rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>) -> Self {
+        Self::from_batch(DBBatch::new(rocksdb))
+    }
+
+    pub(super) fn from_batch(batch: DBBatch) -> Self {
+        Self {
+            batch: Some(batch),
+            writes: HashMap::new(),
+        }
+    }
+
+    fn write_batch(
+        &mut self,
+        write: impl FnOnce(DBBatch) -> Result<DBBatch, TypedStoreError>,
+    ) -> Result<(), TypedStoreError> {
+        let batch = self.batch.take().ok_or_else(|| {
+            TypedStoreError::InvalidState("a previous write of the session failed".to_owned())
+        })?;
+        self.batch = Some(write(batch)?);
+        Ok(())
+    }
+
+    fn check_db<K, V>(&self, db: &DBMap<K, V>) -> Result<(), TypedStoreError> {
+        match &self.batch {
+            Some(batch) if !Arc::ptr_eq(&batch.rocksdb, &db.rocksdb) => {
+                Err(TypedStoreError::CrossDBBatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Buffers the insertion of `value` at `key` in `db`
+    pub fn insert<K: Serialize, V: Serialize>(
+        &mut self,
+        db: &DBMap<K, V>,
+        key: &K,
+        value: &V,
+    ) -> Result<(), TypedStoreError> {
+        self.check_db(db)?;
+        let key_buf = be_fix_int_ser(key)?;
+        let value_buf = bincode::serialize(value)?;
+        self.write_batch(|batch| batch.insert_batch(db, [(key, value)]))?;
+        self.writes
+            .insert((db.cf.clone(), key_buf), Some(value_buf));
+        Ok(())
+    }
+
+    /// Buffers the removal of `key` from `db`
+    pub fn remove<K: Serialize, V>(
+        &mut self,
+        db: &DBMap<K, V>,
+        key: &K,
+    ) -> Result<(), TypedStoreError> {
+        self.check_db(db)?;
+        let key_buf = be_fix_int_ser(key)?;
+        self.write_batch(|batch| batch.delete_batch(db, [key]))?;
+        self.writes.insert((db.cf.clone(), key_buf), None);
+        Ok(())
+    }
+
+    /// Returns the value at `key` in `db`, as last written by the session if it was
+    pub fn get<K, V>(&self, db: &DBMap<K, V>, key: &K) -> Result<Option<V>, TypedStoreError>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+    {
+        match self.writes.get(&(db.cf.clone(), be_fix_int_ser(key)?)) {
+            Some(Some(value)) => Ok(Some(bincode::deserialize(value)?)),
+            Some(None) => Ok(None),
+            None => db.get(key),
+        }
+    }
+
+    /// Whether `key` is in `db`, as last written by the session if it was
+    pub fn contains_key<K, V>(&self, db: &DBMap<K, V>, key: &K) -> Result<bool, TypedStoreError>
+    where
+        K: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned,
+    {
+        match self.writes.get(&(db.cf.clone(), be_fix_int_ser(key)?)) {
+            Some(value) => Ok(value.is_some()),
+            None => db.contains_key(key),
+        }
+    }
+
+    /// The number of keys written by the session
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Writes the buffered writes to the database atomically
+    pub fn commit(self) -> Result<BatchStats, TypedStoreError> {
+        self.batch
+            .ok_or_else(|| {
+                TypedStoreError::InvalidState("a previous write of the session failed".to_owned())
+            })?
+            .write()
+    }
+}
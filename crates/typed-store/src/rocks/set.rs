@@ -0,0 +1,285 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{borrow::Borrow, cmp::Ordering, marker::PhantomData, path::Path, sync::Arc};
+
+use bincode::Options;
+use rocksdb::{MultiThreaded, WriteBatch};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    be_fix_int_ser, filters::total_order_read_options, freeze::permit_writes, keys::Keys, open_cf,
+    DBBatch, DBRawIteratorMultiThreaded, TypedStoreError,
+};
+
+const EMPTY: &[u8] = &[];
+
+/// A persistent set of keys, stored in a column family with empty values.
+///
+/// Unlike a `DBMap<K, ()>`, no value is serialized or deserialized, and membership
+/// checks don't copy the stored value.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// let digests = DBSet::<u64>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// digests.multi_insert([1, 2, 3]).unwrap();
+/// digests.remove(&2).unwrap();
+/// assert!(digests.contains(&1).unwrap());
+/// assert_eq!(digests.iter().collect::<Vec<_>>(), vec![1, 3]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DBSet<K> {
+    pub rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    _phantom: PhantomData<fn(K)>,
+    cf: String,
+}
+
+impl<K> DBSet<K> {
+    /// Opens a database from a path, with specific options and an optional column family
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        db_options: Option<rocksdb::Options>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        let cf_key = opt_cf.unwrap_or(rocksdb::DEFAULT_COLUMN_FAMILY_NAME);
+        let rocksdb = open_cf(path, db_options, &[cf_key])?;
+        Self::reopen(&rocksdb, opt_cf)
+    }
+
+    /// Reopens an open database as a set operating under a specific column family, see `DBMap::reopen`
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        let cf_key = opt_cf
+            .unwrap_or(rocksdb::DEFAULT_COLUMN_FAMILY_NAME)
+            .to_owned();
+        db.cf_handle(&cf_key)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_key.clone()))?;
+        Ok(Self {
+            rocksdb: db.clone(),
+            _phantom: PhantomData,
+            cf: cf_key,
+        })
+    }
+
+    fn cf(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.rocksdb
+            .cf_handle(&self.cf)
+            .expect("Set-keying column family should have been checked at DB creation")
+    }
+
+    fn raw_iter(&self) -> DBRawIteratorMultiThreaded<'_> {
+        let mut db_iter = self
+            .rocksdb
+            .raw_iterator_cf_opt(&self.cf(), total_order_read_options());
+        db_iter.seek_to_first();
+        db_iter
+    }
+}
+
+impl<K: Serialize> DBSet<K> {
+    pub fn insert(&self, key: &K) -> Result<(), TypedStoreError> {
+        let _permit = permit_writes(&self.rocksdb, [self.cf.as_str()])?;
+        self.rocksdb
+            .put_cf(&self.cf(), be_fix_int_ser(key)?, EMPTY)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, key: &K) -> Result<bool, TypedStoreError> {
+        let key_buf = be_fix_int_ser(key)?;
+        // The bloom filters may tell the key is not there without reading it
+        if !self.rocksdb.key_may_exist_cf(&self.cf(), &key_buf) {
+            return Ok(false);
+        }
+        Ok(self.rocksdb.get_pinned_cf(&self.cf(), &key_buf)?.is_some())
+    }
+
+    pub fn remove(&self, key: &K) -> Result<(), TypedStoreError> {
+        let _permit = permit_writes(&self.rocksdb, [self.cf.as_str()])?;
+        self.rocksdb.delete_cf(&self.cf(), be_fix_int_ser(key)?)?;
+        Ok(())
+    }
+
+    /// Atomically inserts a set of keys
+    pub fn multi_insert<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<(), TypedStoreError> {
+        DBBatch::new(&self.rocksdb)
+            .insert_set_batch(self, keys)?
+            .write()?;
+        Ok(())
+    }
+
+    /// Atomically removes a set of keys
+    pub fn multi_remove<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<(), TypedStoreError> {
+        DBBatch::new(&self.rocksdb)
+            .delete_set_batch(self, keys)?
+            .write()?;
+        Ok(())
+    }
+
+    /// Returns whether each of the keys is in the set
+    pub fn multi_contains<J: Borrow<K>>(
+        &self,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Vec<bool>, TypedStoreError> {
+        let cf = self.cf();
+        let keys_bytes = keys
+            .into_iter()
+            .map(|k| Ok((&cf, be_fix_int_ser(k.borrow())?)))
+            .collect::<Result<Vec<_>, TypedStoreError>>()?;
+        self.rocksdb
+            .multi_get_cf(keys_bytes)
+            .into_iter()
+            .map(|value| Ok(value?.is_some()))
+            .collect()
+    }
+}
+
+impl<K: DeserializeOwned> DBSet<K> {
+    /// Iterates over the keys of the set in order
+    pub fn iter(&self) -> Keys<'_, K> {
+        Keys::new(self.raw_iter())
+    }
+
+    /// Iterates in order over the keys in either this set or `other`
+    pub fn union<'a>(&'a self, other: &'a DBSet<K>) -> Combined<'a, K> {
+        Combined::new(self.raw_iter(), other.raw_iter(), SetOperation::Union)
+    }
+
+    /// Iterates in order over the keys in both this set and `other`
+    pub fn intersection<'a>(&'a self, other: &'a DBSet<K>) -> Combined<'a, K> {
+        Combined::new(
+            self.raw_iter(),
+            other.raw_iter(),
+            SetOperation::Intersection,
+        )
+    }
+
+    /// Iterates in order over the keys in this set but not in `other`
+    pub fn difference<'a>(&'a self, other: &'a DBSet<K>) -> Combined<'a, K> {
+        Combined::new(self.raw_iter(), other.raw_iter(), SetOperation::Difference)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+/// An iterator combining two sets, walking both in key order, see `DBSet::union`
+pub struct Combined<'a, K> {
+    a: DBRawIteratorMultiThreaded<'a>,
+    b: DBRawIteratorMultiThreaded<'a>,
+    operation: SetOperation,
+    _phantom: PhantomData<K>,
+}
+
+impl<'a, K: DeserializeOwned> Combined<'a, K> {
+    fn new(
+        a: DBRawIteratorMultiThreaded<'a>,
+        b: DBRawIteratorMultiThreaded<'a>,
+        operation: SetOperation,
+    ) -> Self {
+        Self {
+            a,
+            b,
+            operation,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn decode(key: &[u8]) -> Option<K> {
+        bincode::DefaultOptions::new()
+            .with_big_endian()
+            .with_fixint_encoding()
+            .deserialize(key)
+            .ok()
+    }
+}
+
+impl<'a, K: DeserializeOwned> Iterator for Combined<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.a.key(), self.b.key()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => a.cmp(b),
+            };
+            let emit = match self.operation {
+                SetOperation::Union => true,
+                SetOperation::Intersection => order == Ordering::Equal,
+                SetOperation::Difference => order == Ordering::Less,
+            };
+            let key = match (emit, order) {
+                (false, _) => None,
+                (true, Ordering::Greater) => self.b.key().and_then(Self::decode),
+                (true, _) => self.a.key().and_then(Self::decode),
+            };
+            match order {
+                Ordering::Less => self.a.next(),
+                Ordering::Greater => self.b.next(),
+                Ordering::Equal => {
+                    self.a.next();
+                    self.b.next();
+                }
+            }
+            if emit {
+                return key;
+            }
+            // Stop walking the other set once it can't produce keys anymore
+            if self.operation != SetOperation::Union && !self.a.valid() {
+                return None;
+            }
+        }
+    }
+}
+
+impl DBBatch {
+    /// Inserts a set of keys in a `DBSet`
+    pub fn insert_set_batch<J: Borrow<K>, K: Serialize>(
+        self,
+        set: &DBSet<K>,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Self, TypedStoreError> {
+        self.set_batch(set, keys, |batch, cf, key| batch.put_cf(cf, key, EMPTY))
+    }
+
+    /// Removes a set of keys from a `DBSet`
+    pub fn delete_set_batch<J: Borrow<K>, K: Serialize>(
+        self,
+        set: &DBSet<K>,
+        keys: impl IntoIterator<Item = J>,
+    ) -> Result<Self, TypedStoreError> {
+        self.set_batch(set, keys, |batch, cf, key| batch.delete_cf(cf, key))
+    }
+
+    fn set_batch<J: Borrow<K>, K: Serialize>(
+        mut self,
+        set: &DBSet<K>,
+        keys: impl IntoIterator<Item = J>,
+        op: impl Fn(&mut WriteBatch, &Arc<rocksdb::BoundColumnFamily<'_>>, Vec<u8>),
+    ) -> Result<Self, TypedStoreError> {
+        if !Arc::ptr_eq(&set.rocksdb, &self.rocksdb) {
+            return Err(TypedStoreError::CrossDBBatch);
+        }
+        self.tables.insert(set.cf.clone());
+        let cf = set.cf();
+        for key in keys {
+            let k_buf = be_fix_int_ser(key.borrow())?;
+            self.stats.entries += 1;
+            self.stats.key_bytes += k_buf.len();
+            op(&mut self.batch, &cf, k_buf);
+        }
+        Ok(self)
+    }
+}
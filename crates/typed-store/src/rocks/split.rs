@@ -0,0 +1,127 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Splits of tables by ranges of keys, towards sharding the tables grown too large for a database.
+//!
+//! `table_split_points` picks the keys cutting a table into ranges of about the same size on disk,
+//! and `copy_key_range` copies the entries of a range to another table, of the same database or
+//! of another one. The sizes are estimated from the SST files of the table, by the first key of
+//! each file: the entries still in the memtables are not accounted for, and a file is never split,
+//! so tables should be flushed before, and have many more files than ranges.
+
+use std::sync::Arc;
+
+use rocksdb::{MultiThreaded, WriteBatch};
+use tracing::info;
+
+use super::{filters::total_order_read_options, freeze::permit_writes, KeyRange, TypedStoreError};
+
+/// The number of entries written to the target table at a time by `copy_key_range`
+pub const SPLIT_COPY_BATCH_SIZE: usize = 1024;
+
+/// Returns up to `parts - 1` increasing keys splitting the table `cf_name` into `parts` ranges
+/// of about the same size on disk, see the module documentation. Fewer keys are returned if the
+/// table doesn't have enough SST files
+pub fn table_split_points(
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    cf_name: &str,
+    parts: usize,
+) -> Result<Vec<Vec<u8>>, TypedStoreError> {
+    rocksdb
+        .cf_handle(cf_name)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(cf_name.to_owned()))?;
+
+    let mut files: Vec<(Vec<u8>, u64)> = rocksdb
+        .live_files()?
+        .into_iter()
+        .filter(|file| file.column_family_name == cf_name)
+        .filter_map(|file| Some((file.start_key?, file.size as u64)))
+        .collect();
+    files.sort();
+    let total_size: u64 = files.iter().map(|(_, size)| size).sum();
+
+    let mut points: Vec<Vec<u8>> = Vec::new();
+    let mut size_before = 0;
+    for (start_key, size) in files {
+        // The next point is the first key of the first file past its share of the table
+        let next_share = total_size * (points.len() as u64 + 1);
+        if points.len() + 1 < parts
+            && size_before > 0
+            && size_before * parts as u64 >= next_share
+            && points.last() != Some(&start_key)
+        {
+            points.push(start_key);
+        }
+        size_before += size;
+    }
+    Ok(points)
+}
+
+/// Returns the ranges between consecutive `points`, from the first key to the last, e.g. those
+/// returned by `table_split_points`
+pub fn split_key_ranges(points: &[Vec<u8>]) -> Vec<KeyRange> {
+    let bounds: Vec<_> = std::iter::once(None)
+        .chain(points.iter().cloned().map(Some))
+        .chain(std::iter::once(None))
+        .collect();
+    bounds
+        .windows(2)
+        .map(|bounds| KeyRange {
+            start: bounds[0].clone(),
+            end: bounds[1].clone(),
+        })
+        .collect()
+}
+
+/// Copies the entries of `range` in the table `source_cf` of `source` to the table `target_cf` of
+/// `target`, which can be the same database, and returns the number of entries copied. The entries
+/// are read from a snapshot, and written in batches of `SPLIT_COPY_BATCH_SIZE`: the target table
+/// is only complete once the copy returns. Fails with `TypedStoreError::TableFrozen` at the first
+/// batch written after the target table is frozen.
+pub fn copy_key_range(
+    source: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    source_cf: &str,
+    range: &KeyRange,
+    target: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    target_cf: &str,
+) -> Result<u64, TypedStoreError> {
+    let source_handle = source
+        .cf_handle(source_cf)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(source_cf.to_owned()))?;
+    let target_handle = target
+        .cf_handle(target_cf)
+        .ok_or_else(|| TypedStoreError::UnregisteredColumn(target_cf.to_owned()))?;
+
+    let snapshot = source.snapshot();
+    let mut readopts = total_order_read_options();
+    readopts.set_snapshot(&snapshot);
+    readopts.fill_cache(false);
+    if let Some(end) = &range.end {
+        readopts.set_iterate_upper_bound(end.clone());
+    }
+    let mut db_iter = source.raw_iterator_cf_opt(&source_handle, readopts);
+    match &range.start {
+        Some(start) => db_iter.seek(start),
+        None => db_iter.seek_to_first(),
+    }
+
+    let mut copied = 0;
+    let mut batch = WriteBatch::default();
+    let write = |batch: WriteBatch| -> Result<(), TypedStoreError> {
+        let _permit = permit_writes(target, [target_cf])?;
+        target.write(batch)?;
+        Ok(())
+    };
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        batch.put_cf(&target_handle, key, value);
+        if batch.len() == SPLIT_COPY_BATCH_SIZE {
+            write(std::mem::take(&mut batch))?;
+        }
+        copied += 1;
+        db_iter.next();
+    }
+    db_iter.status()?;
+    write(batch)?;
+    info!("Copied {copied} entries of {source_cf} in {range:?} to {target_cf}");
+    Ok(copied)
+}
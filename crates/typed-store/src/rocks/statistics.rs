@@ -0,0 +1,184 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Exports RocksDB statistics and properties to Prometheus.
+//!
+//! Tickers and histograms are only available when the database is opened with
+//! statistics enabled (`rocksdb::Options::enable_statistics`), and are read from the
+//! options used to open it. Per table properties are always available.
+
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::MultiThreaded;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::TypedStoreError;
+use crate::metrics::DBMetrics;
+
+/// The integer properties exported for every table
+pub const EXPORTED_CF_PROPERTIES: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.live-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.num-immutable-mem-table",
+    "rocksdb.estimate-pending-compaction-bytes",
+    "rocksdb.num-running-compactions",
+    "rocksdb.num-running-flushes",
+    "rocksdb.estimate-table-readers-mem",
+    "rocksdb.block-cache-usage",
+    "rocksdb.actual-delayed-write-rate",
+    "rocksdb.is-write-stopped",
+    "rocksdb.background-errors",
+];
+
+/// A histogram parsed from the RocksDB statistics dump
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatsHistogram {
+    pub name: String,
+    /// The (quantile or aggregate name, value) pairs, e.g. ("P99", 12.0) or ("COUNT", 3.0)
+    pub values: Vec<(String, f64)>,
+}
+
+/// Parses the statistics dump returned by `rocksdb::Options::get_statistics` into tickers and histograms.
+///
+/// Tickers are formatted as `rocksdb.block.cache.miss COUNT : 42`, histograms as
+/// `rocksdb.db.get.micros P50 : 1.0 P95 : 2.0 P99 : 3.0 P100 : 4.0 COUNT : 5 SUM : 6`.
+pub fn parse_statistics(stats: &str) -> (Vec<(String, u64)>, Vec<StatsHistogram>) {
+    let mut tickers = vec![];
+    let mut histograms = vec![];
+    for line in stats.lines() {
+        let mut tokens = line.split_whitespace();
+        let name = match tokens.next() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let tokens: Vec<_> = tokens.collect();
+        let values: Vec<_> = tokens
+            .chunks(3)
+            .filter_map(|chunk| match chunk {
+                [key, ":", value] => value.parse::<f64>().ok().map(|v| (key.to_string(), v)),
+                _ => None,
+            })
+            .collect();
+        match values.as_slice() {
+            [] => continue,
+            [(key, value)] if key == "COUNT" => tickers.push((name, *value as u64)),
+            _ => histograms.push(StatsHistogram { name, values }),
+        }
+    }
+    (tickers, histograms)
+}
+
+/// Reports the statistics and table properties of a database in the global `DBMetrics`.
+///
+/// `options` must be the options the database was opened with, or a clone of them, since
+/// RocksDB statistics are attached to the options rather than to the database handle.
+pub fn report_rocksdb_statistics(
+    db_name: &str,
+    rocksdb: &rocksdb::DBWithThreadMode<MultiThreaded>,
+    options: Option<&rocksdb::Options>,
+) -> Result<(), TypedStoreError> {
+    let stats_metrics = &DBMetrics::get().stats_metrics;
+
+    if let Some(stats) = options.and_then(|o| o.get_statistics()) {
+        let (tickers, histograms) = parse_statistics(&stats);
+        let mut cache_hits = 0;
+        let mut cache_misses = 0;
+        for (ticker, value) in tickers {
+            match ticker.as_str() {
+                "rocksdb.block.cache.hit" => cache_hits = value,
+                "rocksdb.block.cache.miss" => cache_misses = value,
+                _ => (),
+            }
+            stats_metrics
+                .rocksdb_ticker
+                .with_label_values(&[db_name, &ticker])
+                .set(value as i64);
+        }
+        if cache_hits + cache_misses > 0 {
+            stats_metrics
+                .rocksdb_block_cache_hit_rate
+                .with_label_values(&[db_name])
+                .set(cache_hits as f64 / (cache_hits + cache_misses) as f64);
+        }
+        for histogram in histograms {
+            for (quantile, value) in histogram.values {
+                stats_metrics
+                    .rocksdb_histogram
+                    .with_label_values(&[db_name, &histogram.name, &quantile])
+                    .set(value);
+            }
+        }
+    }
+
+    // Only explicit snapshots count, iterators pinning the state of a table implicitly are
+    // reported in `rocksdb_iter_pinned_seconds` once dropped
+    let oldest_snapshot_time = rocksdb
+        .property_int_value("rocksdb.oldest-snapshot-time")?
+        .unwrap_or(0);
+    let oldest_snapshot_age = if oldest_snapshot_time > 0 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        now.saturating_sub(oldest_snapshot_time)
+    } else {
+        0
+    };
+    stats_metrics
+        .rocksdb_oldest_snapshot_age_seconds
+        .with_label_values(&[db_name])
+        .set(oldest_snapshot_age as i64);
+
+    let cfs = rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(
+        &rocksdb::Options::default(),
+        rocksdb.path(),
+    )?;
+    for cf_name in cfs {
+        let cf = match rocksdb.cf_handle(&cf_name) {
+            Some(cf) => cf,
+            None => continue,
+        };
+        for property in EXPORTED_CF_PROPERTIES {
+            if let Some(value) = rocksdb.property_int_value_cf(&cf, property)? {
+                stats_metrics
+                    .rocksdb_cf_property
+                    .with_label_values(&[db_name, &cf_name, property])
+                    .set(value as i64);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Spawns a task reporting the statistics of the database every `interval`, see `report_rocksdb_statistics`.
+/// The task stops once the database is closed.
+pub fn spawn_statistics_collector(
+    db_name: String,
+    rocksdb: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    options: Option<rocksdb::Options>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    let rocksdb: Weak<_> = Arc::downgrade(rocksdb);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let db = match rocksdb.upgrade() {
+                Some(db) => db,
+                None => {
+                    debug!("Database {db_name} is closed, stopping the statistics collector");
+                    break;
+                }
+            };
+            if let Err(e) = report_rocksdb_statistics(&db_name, &db, options.as_ref()) {
+                warn!("Failed to report statistics of {db_name}: {e}");
+            }
+        }
+    })
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tables whose contents are replaced as a whole, atomically.
+//!
+//! Clearing a table and writing its new contents lets readers see the table empty or partially
+//! written, and a crash in between leaves it so. A `SwappableMap` stores each version of its
+//! contents in a column family of its own, `{table}` then `{table}__swap_{generation}`, and a
+//! pointer to the current one in the `SWAP_POINTERS_CF` column family. `replace_table_contents`
+//! writes the new contents to the column family of the next generation while the current one is
+//! still read, then switches the pointer with a single write, and drops the previous column family.
+//!
+//! The new contents are written to the WAL before the pointer, so after a crash the pointer is
+//! either the previous generation, whose column family is intact, or the new one, whose contents
+//! were completely recovered. The column families of interrupted replacements are dropped when the
+//! table is reopened.
+
+use std::{
+    borrow::Borrow,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use rocksdb::MultiThreaded;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+use super::{
+    cf_options::{cf_options, record_cf_options},
+    check_compatibility, default_db_name,
+    freeze::permit_writes,
+    lifecycle::drop_table,
+    record_compatibility, DBMap, SystemClock, TypedStoreError, SWAPPABLE_TABLES_FEATURE,
+};
+use crate::traits::Map;
+
+/// The column family of the generations of the swappable tables of a database, by table name
+pub const SWAP_POINTERS_CF: &str = "swap_pointers";
+/// The number of entries written at a time by `replace_table_contents`
+const REPLACE_BATCH_SIZE: usize = 1024;
+
+/// A table whose contents can be replaced atomically, see the module documentation
+pub struct SwappableMap<K, V> {
+    rocksdb: Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    table: String,
+    /// The options the column families of the generations are created with
+    options: rocksdb::Options,
+    /// The map of the current generation, and the generation
+    current: RwLock<(DBMap<K, V>, u64)>,
+    /// Serializes the replacements
+    replace_lock: Mutex<()>,
+}
+
+fn generation_cf(table: &str, generation: u64) -> String {
+    match generation {
+        0 => table.to_owned(),
+        generation => format!("{table}__swap_{generation}"),
+    }
+}
+
+impl<K, V> SwappableMap<K, V> {
+    /// Reopens the swappable table `table` of an open database, which must have the
+    /// `SWAP_POINTERS_CF` column family. The column family of its current generation is created if
+    /// missing, and those left behind by interrupted replacements are dropped.
+    ///
+    /// The column families of the generations are created with the options `table` was opened with
+    /// by `open_cf_opts`, if any, see `reopen_with_options`
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        table: &str,
+    ) -> Result<Self, TypedStoreError> {
+        Self::reopen_with_options(db, table, cf_options(db, table))
+    }
+
+    /// Same as `reopen`, creating the column families of the generations with `options`
+    pub fn reopen_with_options(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        table: &str,
+        options: rocksdb::Options,
+    ) -> Result<Self, TypedStoreError> {
+        check_compatibility(db)?;
+        record_compatibility(db, &[SWAPPABLE_TABLES_FEATURE])?;
+        let pointers = db
+            .cf_handle(SWAP_POINTERS_CF)
+            .ok_or_else(|| TypedStoreError::UnregisteredColumn(SWAP_POINTERS_CF.to_owned()))?;
+        let generation = match db.get_cf(&pointers, table)? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => 0,
+        };
+        let current_cf = generation_cf(table, generation);
+        if db.cf_handle(&current_cf).is_none() {
+            db.create_cf(&current_cf, &options)?;
+            record_cf_options(db, &current_cf, &options);
+        }
+
+        let swap_prefix = format!("{table}__swap_");
+        let stale: Vec<_> = rocksdb::DBWithThreadMode::<MultiThreaded>::list_cf(
+            &rocksdb::Options::default(),
+            db.path(),
+        )?
+        .into_iter()
+        .filter(|cf| (cf.starts_with(&swap_prefix) || cf == table) && *cf != current_cf)
+        .collect();
+        for cf in stale {
+            info!("Dropping the column family {cf} of an interrupted replacement of {table}");
+            drop_table(db, &default_db_name(db), &cf, &SystemClock)?;
+        }
+
+        Ok(Self {
+            rocksdb: db.clone(),
+            table: table.to_owned(),
+            options,
+            current: RwLock::new((DBMap::reopen(db, Some(&current_cf))?, generation)),
+            replace_lock: Mutex::new(()),
+        })
+    }
+
+    /// Runs `read` on the map of the current contents of the table, which are not replaced while
+    /// it runs. The map must not be kept past `read`, its column family is dropped by the next
+    /// replacement
+    pub fn read<R>(&self, read: impl FnOnce(&DBMap<K, V>) -> R) -> R {
+        let current = self
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        read(&current.0)
+    }
+
+    /// The name of the column family of the current contents of the table
+    pub fn current_cf(&self) -> String {
+        self.read(|map| map.cf.clone())
+    }
+}
+
+impl<K, V> SwappableMap<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn get(&self, key: &K) -> Result<Option<V>, TypedStoreError> {
+        self.read(|map| map.get(key))
+    }
+
+    pub fn contains_key(&self, key: &K) -> Result<bool, TypedStoreError> {
+        self.read(|map| map.contains_key(key))
+    }
+
+    /// Replaces all the entries of the table with `new_entries`, atomically for the readers and
+    /// across crashes, and returns the number of entries written. See the module documentation
+    pub fn replace_table_contents<J, U>(
+        &self,
+        new_entries: impl IntoIterator<Item = (J, U)>,
+    ) -> Result<u64, TypedStoreError>
+    where
+        J: Borrow<K>,
+        U: Borrow<V>,
+    {
+        let _replacing = self
+            .replace_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let generation = self
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .1
+            + 1;
+        let new_cf = generation_cf(&self.table, generation);
+        let (db_name, clock) = self.read(|map| (map.db_name.clone(), map.clock.clone()));
+        if self.rocksdb.cf_handle(&new_cf).is_some() {
+            drop_table(&self.rocksdb, &db_name, &new_cf, clock.as_ref())?;
+        }
+        self.rocksdb.create_cf(&new_cf, &self.options)?;
+        record_cf_options(&self.rocksdb, &new_cf, &self.options);
+        let new_map = DBMap::<K, V>::reopen(&self.rocksdb, Some(&new_cf))?;
+
+        let mut written = 0;
+        let mut entries = new_entries.into_iter().peekable();
+        while entries.peek().is_some() {
+            let chunk: Vec<_> = entries.by_ref().take(REPLACE_BATCH_SIZE).collect();
+            written += chunk.len() as u64;
+            new_map.batch().insert_batch(&new_map, chunk)?.write()?;
+        }
+
+        let previous_cf = {
+            let mut current = self
+                .current
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let pointers = self
+                .rocksdb
+                .cf_handle(SWAP_POINTERS_CF)
+                .ok_or_else(|| TypedStoreError::UnregisteredColumn(SWAP_POINTERS_CF.to_owned()))?;
+            // Replacing the contents writes to the current table, which can't be frozen
+            let _permit = permit_writes(&self.rocksdb, [SWAP_POINTERS_CF, current.0.cf.as_str()])?;
+            self.rocksdb
+                .put_cf(&pointers, &self.table, bincode::serialize(&generation)?)?;
+            let previous = std::mem::replace(&mut *current, (new_map, generation));
+            previous.0.cf
+        };
+        drop_table(&self.rocksdb, &db_name, &previous_cf, clock.as_ref())?;
+        info!(
+            "Replaced the contents of {} with {written} entries, in {new_cf}",
+            self.table
+        );
+        Ok(written)
+    }
+}
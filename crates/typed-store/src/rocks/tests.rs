@@ -256,6 +256,38 @@ fn test_iter() {
     assert_eq!(None, iter.next());
 }
 
+#[test]
+fn test_iter_snapshot_pinning() {
+    let db = DBMap::open(temp_dir(), None, Some("table")).expect("Failed to open storage");
+    db.insert(&1, &"1".to_string()).expect("Failed to insert");
+    db.insert(&2, &"2".to_string()).expect("Failed to insert");
+
+    let mut latest = db.iter_latest();
+    let mut snapshot = db.iter_snapshot();
+    assert_eq!(latest.pinning(), SnapshotPinning::None);
+    assert_eq!(snapshot.pinning(), SnapshotPinning::Explicit);
+    assert!(db.iter().pins_snapshot());
+    assert!(!latest.pins_snapshot());
+    assert_eq!(Some((1, "1".to_string())), latest.next());
+    assert_eq!(Some((1, "1".to_string())), snapshot.next());
+
+    db.insert(&3, &"3".to_string()).expect("Failed to insert");
+    // The tailing iterator sees the new key, the snapshot doesn't
+    assert_eq!(
+        latest.collect::<Vec<_>>(),
+        vec![(2, "2".to_string()), (3, "3".to_string())]
+    );
+    assert_eq!(snapshot.collect::<Vec<_>>(), vec![(2, "2".to_string())]);
+
+    let pinned = crate::metrics::DBMetrics::get()
+        .op_metrics
+        .rocksdb_iter_pinned_seconds
+        .with_label_values(&[db.db_name(), "table"]);
+    let count = pinned.get_sample_count();
+    drop(db.iter_snapshot());
+    assert_eq!(pinned.get_sample_count(), count + 1);
+}
+
 #[test]
 fn test_iter_reverse() {
     let db = DBMap::open(temp_dir(), None, None).expect("Failed to open storage");
@@ -341,6 +373,29 @@ fn test_insert_batch() {
     }
 }
 
+#[test]
+fn test_batch_stats() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).expect("Failed to open storage");
+    let keys_vals = (10..20).map(|i| (i, i.to_string()));
+    let stats = db
+        .batch()
+        .insert_batch(&db, keys_vals)
+        .expect("Failed to batch insert")
+        .delete_batch(&db, [10, 11])
+        .expect("Failed to batch delete")
+        .delete_range(&db, &12, &14)
+        .expect("Failed to delete range")
+        .write()
+        .expect("Failed to execute batch");
+
+    assert_eq!(stats.entries, 10 + 2 + 1);
+    // fixint encoded i32 keys are 4 bytes each
+    assert_eq!(stats.key_bytes, (10 + 2 + 2) * 4);
+    // each value is a u64 length prefix followed by two bytes of string data
+    assert_eq!(stats.value_bytes, 10 * (8 + 2));
+    assert_eq!(db.iter().count(), 6);
+}
+
 #[test]
 fn test_insert_batch_across_cf() {
     let rocks = open_cf(temp_dir(), None, &["First_CF", "Second_CF"]).unwrap();
@@ -443,6 +498,51 @@ fn test_delete_range() {
     assert!(db.contains_key(&100).expect("Failed to query legel key"));
 }
 
+#[test]
+fn test_remove_prefix() {
+    let db: DBMap<(u64, u8), String> =
+        DBMap::open(temp_dir(), None, None).expect("Failed to open storage");
+    let epochs = [0, 1, 2, u64::MAX];
+    db.multi_insert(
+        epochs
+            .iter()
+            .flat_map(|epoch| (0..10).map(move |i| ((*epoch, i), i.to_string()))),
+    )
+    .expect("Failed to multi-insert");
+
+    db.remove_prefix(&1u64).expect("Failed to remove prefix");
+    assert_eq!(db.keys().filter(|(epoch, _)| *epoch == 1).count(), 0);
+    assert_eq!(db.keys().count(), 30);
+
+    // Prefixes made of 0xFF bytes have no upper bound
+    db.remove_prefix(&u64::MAX)
+        .expect("Failed to remove prefix");
+    db.remove_prefix(&(2u64, 5u8))
+        .expect("Failed to remove prefix");
+    // A prefix without keys is a no-op
+    db.remove_prefix(&7u64).expect("Failed to remove prefix");
+    assert_eq!(db.keys().filter(|(epoch, _)| *epoch == 0).count(), 10);
+    assert_eq!(
+        db.keys()
+            .filter(|(epoch, _)| *epoch == 2)
+            .collect::<Vec<_>>(),
+        (0..10)
+            .filter(|i| *i != 5)
+            .map(|i| (2, i))
+            .collect::<Vec<_>>()
+    );
+    assert!(!db.keys().any(|(epoch, _)| epoch == u64::MAX));
+
+    assert_eq!(
+        KeyRange::prefix(&[1u8, 0xFF]).unwrap(),
+        KeyRange {
+            start: Some(vec![1, 0xFF]),
+            end: Some(vec![2]),
+        }
+    );
+    assert_eq!(KeyRange::prefix(&()).unwrap().end, None);
+}
+
 #[test]
 fn test_clear() {
     let db = DBMap::<i32, String>::open(temp_dir(), None, Some("table"))
@@ -583,3 +683,3121 @@ async fn open_as_secondary_test() {
     // New value should be present
     assert_eq!(secondary_db.get(&0).unwrap(), Some("10".to_string()));
 }
+
+#[test]
+fn test_replica_lag() {
+    let primary_path = temp_dir();
+    let primary_db = DBMap::<i32, String>::open(primary_path.clone(), None, Some("table"))
+        .expect("Failed to open storage");
+    primary_db
+        .multi_insert((0..10).map(|i| (i, i.to_string())))
+        .expect("Failed to multi-insert");
+
+    let opt = rocksdb::Options::default();
+    let secondary_store =
+        open_cf_opts_secondary(primary_path, None, None, &[("table", &opt)]).unwrap();
+    let secondary_db = DBMap::<i32, String>::reopen(&secondary_store, Some("table")).unwrap();
+    secondary_db.try_catch_up_with_primary().unwrap();
+    assert_eq!(secondary_db.lag(&primary_db).sequences_behind(), 0);
+
+    primary_db.insert(&0, &"10".to_string()).unwrap();
+    primary_db.insert(&1, &"11".to_string()).unwrap();
+    let lag = secondary_db.lag(&primary_db);
+    assert_eq!(lag.sequences_behind(), 2);
+    assert_eq!(
+        ReplicaLag::from_primary_sequence(lag.primary_sequence, &secondary_store),
+        lag
+    );
+
+    // Also once the writes are flushed out of the WAL
+    primary_db.flush().unwrap();
+    assert_eq!(secondary_db.lag(&primary_db).sequences_behind(), 2);
+    secondary_db.try_catch_up_with_primary().unwrap();
+    assert_eq!(secondary_db.lag(&primary_db).sequences_behind(), 0);
+}
+
+#[test]
+fn test_chunked_batch() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).expect("Failed to open storage");
+    let keys_vals = (0..1000).map(|i| (i, i.to_string()));
+
+    let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let reported_clone = reported.clone();
+    let progress = db
+        .chunked_batch(100, DEFAULT_CHUNK_MAX_BYTES)
+        .with_progress(move |p| reported_clone.lock().unwrap().push(*p))
+        .insert_batch(&db, keys_vals.clone())
+        .expect("Failed to batch insert")
+        .write()
+        .expect("Failed to execute batch");
+
+    assert_eq!(progress.chunks_committed, 10);
+    assert_eq!(progress.entries_committed, 1000);
+    assert_eq!(reported.lock().unwrap().len(), 10);
+    for (k, v) in keys_vals {
+        let val = db.get(&k).expect("Failed to get inserted key");
+        assert_eq!(Some(v), val);
+    }
+
+    // A small byte budget splits the batch as well, and deletes are chunked too
+    let progress = db
+        .chunked_batch(DEFAULT_CHUNK_MAX_ENTRIES, 64)
+        .delete_batch(&db, 0..500)
+        .expect("Failed to batch delete")
+        .write()
+        .expect("Failed to execute batch");
+    assert!(progress.chunks_committed > 1);
+    assert_eq!(progress.entries_committed, 500);
+    assert_eq!(db.iter().count(), 500);
+}
+
+#[test]
+fn test_cross_db_journal() {
+    let source = open_cf(temp_dir(), None, &["data", "journal"]).unwrap();
+    let target = open_cf(temp_dir(), None, &["data"]).unwrap();
+    let source_data = DBMap::<i32, String>::reopen(&source, Some("data")).unwrap();
+    let target_data = DBMap::<i32, String>::reopen(&target, Some("data")).unwrap();
+    let journal_map = DBMap::<u64, JournalIntent>::reopen(&source, Some("journal")).unwrap();
+
+    // The journal must live in a different database than the target
+    assert!(CrossDBJournal::new(DBMap::reopen(&target, Some("data")).unwrap(), &target).is_err());
+
+    let journal = CrossDBJournal::new(journal_map.clone(), &target).unwrap();
+    assert_eq!(journal.recover().unwrap(), 0);
+
+    target_data.insert(&2, &"2".to_string()).unwrap();
+    let batch = source_data
+        .batch()
+        .insert_batch(&source_data, [(1, "1".to_string())])
+        .unwrap();
+    let intent = JournalIntent::new()
+        .insert_batch(&target_data, [(1, "1".to_string())])
+        .unwrap()
+        .delete_batch(&target_data, [2])
+        .unwrap();
+    journal.commit(batch, intent.clone()).unwrap();
+
+    assert_eq!(source_data.get(&1).unwrap(), Some("1".to_string()));
+    assert_eq!(target_data.get(&1).unwrap(), Some("1".to_string()));
+    assert!(!target_data.contains_key(&2).unwrap());
+    assert_eq!(journal.pending(), 0);
+
+    // Simulate a crash between the source commit and the target write
+    target_data.clear().unwrap();
+    journal_map.insert(&7, &intent).unwrap();
+    let journal = CrossDBJournal::new(journal_map.clone(), &target).unwrap();
+    assert_eq!(journal.pending(), 1);
+    assert_eq!(journal.recover().unwrap(), 1);
+    assert_eq!(target_data.get(&1).unwrap(), Some("1".to_string()));
+    assert_eq!(journal.pending(), 0);
+
+    // Replaying is idempotent
+    journal_map.insert(&8, &intent).unwrap();
+    assert_eq!(journal.recover().unwrap(), 1);
+    assert_eq!(target_data.iter().count(), 1);
+}
+
+#[test]
+fn test_open_with_repair_policy() {
+    let path = temp_dir();
+    let opts = default_rocksdb_options();
+    {
+        let db =
+            open_cf_opts_with_repair_policy(&path, None, &[("table", &opts)], RepairPolicy::Fail)
+                .unwrap();
+        let map = DBMap::<i32, String>::reopen(&db, Some("table")).unwrap();
+        map.insert(&1, &"1".to_string()).unwrap();
+        db.flush_cf(&map.cf()).unwrap();
+    }
+
+    // Corrupt the manifest
+    let current = std::fs::read_to_string(path.join("CURRENT")).unwrap();
+    std::fs::write(path.join(current.trim()), b"not a manifest").unwrap();
+
+    let err = open_cf_opts_with_repair_policy(&path, None, &[("table", &opts)], RepairPolicy::Fail)
+        .unwrap_err();
+    match err {
+        TypedStoreError::OpenFailure(report) => {
+            assert!(report.is_corruption);
+            assert_eq!(report.suggested_policy, Some(RepairPolicy::Repair));
+            assert!(report.to_string().contains(&path.display().to_string()));
+        }
+        e => panic!("Unexpected error {e}"),
+    }
+
+    let db =
+        open_cf_opts_with_repair_policy(&path, None, &[("table", &opts)], RepairPolicy::Repair)
+            .unwrap();
+    let map = DBMap::<i32, String>::reopen(&db, Some("table")).unwrap();
+    assert_eq!(map.get(&1).unwrap(), Some("1".to_string()));
+}
+
+#[test]
+fn test_open_failure_report_without_corruption() {
+    // A regular file can't be opened as a database
+    let path = temp_dir().join("file");
+    std::fs::write(&path, b"").unwrap();
+
+    let err = open_cf_opts_with_repair_policy(&path, None, &[], RepairPolicy::Repair).unwrap_err();
+    match err {
+        TypedStoreError::OpenFailure(report) => {
+            assert!(!report.is_corruption);
+            assert_eq!(report.suggested_policy, None);
+        }
+        e => panic!("Unexpected error {e}"),
+    }
+}
+
+#[test]
+fn test_pause_background_work() {
+    let rocks = open_cf(temp_dir(), None, &["First_CF", "Second_CF"]).unwrap();
+    let db = DBMap::<i32, String>::reopen(&rocks, Some("First_CF")).unwrap();
+
+    assert!(pause_background_work(&rocks, &["unknown"]).is_err());
+    {
+        let _guard = BackgroundWorkGuard::new(&rocks, &["First_CF", "Second_CF"]).unwrap();
+        // Writes and flushes still go through while compactions are paused
+        db.insert(&1, &"1".to_string()).unwrap();
+        rocks.flush_cf(&db.cf()).unwrap();
+    }
+    pause_background_work(&rocks, &["First_CF"]).unwrap();
+    continue_background_work(&rocks, &["First_CF"]).unwrap();
+    assert_eq!(db.get(&1).unwrap(), Some("1".to_string()));
+}
+
+#[test]
+fn test_nested_background_work_guards() {
+    let path = temp_dir();
+    let rocks = open_cf(&path, None, &["First_CF", "Second_CF"]).unwrap();
+    let auto_compactions_disabled = |cf: &str| {
+        options_summary::read_latest_cf_options(&path)
+            .unwrap()
+            .unwrap()[cf]["disable_auto_compactions"]
+            == "true"
+    };
+
+    let outer = BackgroundWorkGuard::new(&rocks, &["First_CF", "Second_CF"]).unwrap();
+    let inner = BackgroundWorkGuard::new(&rocks, &["First_CF"]).unwrap();
+    assert!(auto_compactions_disabled("First_CF"));
+    // The outer guard still pauses the column family of the inner one
+    drop(inner);
+    assert!(auto_compactions_disabled("First_CF"));
+    assert!(auto_compactions_disabled("Second_CF"));
+    drop(outer);
+    assert!(!auto_compactions_disabled("First_CF"));
+    assert!(!auto_compactions_disabled("Second_CF"));
+}
+
+#[test]
+fn test_low_priority_writes() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None)
+        .expect("Failed to open storage")
+        .with_low_priority_writes();
+
+    db.insert(&1, &"1".to_string()).expect("Failed to insert");
+    db.multi_insert((2..10).map(|i| (i, i.to_string())))
+        .expect("Failed to multi-insert");
+    db.remove(&1).expect("Failed to remove");
+    assert_eq!(db.iter().count(), 8);
+
+    // Low priority can also be set per batch
+    let plain = DBMap::<i32, String>::reopen(&db.rocksdb, None).unwrap();
+    plain
+        .batch()
+        .low_priority()
+        .delete_batch(&plain, 2..5)
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(plain.iter().count(), 5);
+}
+
+#[test]
+fn test_metrics() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, Some("metrics_table"))
+        .expect("Failed to open storage")
+        .with_db_name("test_metrics_db");
+    assert_eq!(db.db_name(), "test_metrics_db");
+
+    db.insert(&1, &"1".to_string()).unwrap();
+    db.get(&1).unwrap();
+    db.multi_get([1, 2]).unwrap();
+    db.remove(&1).unwrap();
+    db.multi_insert([(2, "2".to_string())]).unwrap();
+
+    let op_metrics = &crate::metrics::DBMetrics::get().op_metrics;
+    let labels = ["test_metrics_db", "metrics_table"];
+    for histogram in [
+        &op_metrics.rocksdb_get_latency_seconds,
+        &op_metrics.rocksdb_get_bytes,
+        &op_metrics.rocksdb_multiget_latency_seconds,
+        &op_metrics.rocksdb_put_latency_seconds,
+        &op_metrics.rocksdb_put_bytes,
+        &op_metrics.rocksdb_delete_latency_seconds,
+    ] {
+        assert_eq!(
+            histogram
+                .get_metric_with_label_values(&labels)
+                .unwrap()
+                .get_sample_count(),
+            1
+        );
+    }
+    assert_eq!(
+        op_metrics
+            .rocksdb_batch_commit_latency_seconds
+            .get_metric_with_label_values(&["test_metrics_db"])
+            .unwrap()
+            .get_sample_count(),
+        1
+    );
+}
+
+#[test]
+fn test_metrics_init_after_get() {
+    crate::metrics::DBMetrics::get();
+    // The metrics are already registered in a private registry
+    assert!(matches!(
+        crate::metrics::DBMetrics::init(&prometheus::Registry::new()),
+        Err(prometheus::Error::AlreadyReg)
+    ));
+}
+
+#[test]
+fn test_parse_statistics() {
+    let stats = "rocksdb.block.cache.miss COUNT : 3
+rocksdb.block.cache.hit COUNT : 9
+rocksdb.db.get.micros P50 : 1.500000 P95 : 2.000000 P99 : 3.000000 P100 : 4.000000 COUNT : 5 SUM : 6
+";
+    let (tickers, histograms) = statistics::parse_statistics(stats);
+    assert_eq!(
+        tickers,
+        vec![
+            ("rocksdb.block.cache.miss".to_string(), 3),
+            ("rocksdb.block.cache.hit".to_string(), 9)
+        ]
+    );
+    assert_eq!(histograms.len(), 1);
+    assert_eq!(histograms[0].name, "rocksdb.db.get.micros");
+    assert_eq!(histograms[0].values[0], ("P50".to_string(), 1.5));
+    assert_eq!(histograms[0].values.len(), 6);
+}
+
+#[test]
+fn test_report_rocksdb_statistics() {
+    let mut options = default_rocksdb_options();
+    options.enable_statistics();
+    let rocks = open_cf(temp_dir(), Some(options.clone()), &["stats_table"]).unwrap();
+    let db = DBMap::<i32, String>::reopen(&rocks, Some("stats_table")).unwrap();
+    db.multi_insert((0..100).map(|i| (i, i.to_string())))
+        .unwrap();
+    for i in 0..100 {
+        db.get(&i).unwrap();
+    }
+
+    statistics::report_rocksdb_statistics("test_stats_db", &rocks, Some(&options)).unwrap();
+
+    let stats_metrics = &crate::metrics::DBMetrics::get().stats_metrics;
+    assert!(
+        stats_metrics
+            .rocksdb_ticker
+            .get_metric_with_label_values(&["test_stats_db", "rocksdb.number.keys.written"])
+            .unwrap()
+            .get()
+            >= 100
+    );
+    assert!(
+        stats_metrics
+            .rocksdb_cf_property
+            .get_metric_with_label_values(&[
+                "test_stats_db",
+                "stats_table",
+                "rocksdb.cur-size-all-mem-tables"
+            ])
+            .unwrap()
+            .get()
+            > 0
+    );
+}
+
+#[test]
+fn test_event_poller() {
+    use events::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingListener {
+        flushes: Mutex<Vec<JobsCompletedInfo>>,
+        stalls: Mutex<Vec<WriteStallInfo>>,
+    }
+
+    impl DBEventListener for RecordingListener {
+        fn on_flush_completed(&self, db_name: &str, info: JobsCompletedInfo) {
+            assert_eq!(db_name, "test_events_db");
+            self.flushes.lock().unwrap().push(info);
+        }
+
+        fn on_stall_conditions_changed(&self, _db_name: &str, info: WriteStallInfo) {
+            self.stalls.lock().unwrap().push(info);
+        }
+    }
+
+    let mut options = default_rocksdb_options();
+    options.enable_statistics();
+    let rocks = open_cf(temp_dir(), Some(options.clone()), &["events_table"]).unwrap();
+    let db = DBMap::<i32, String>::reopen(&rocks, Some("events_table")).unwrap();
+    let listener = Arc::new(RecordingListener::default());
+    let mut poller = DBEventPoller::new(
+        "test_events_db".to_string(),
+        Some(options),
+        listener.clone(),
+    );
+
+    poller.poll(&rocks).unwrap();
+    assert!(listener.flushes.lock().unwrap().is_empty());
+
+    db.insert(&1, &"1".to_string()).unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+    poller.poll(&rocks).unwrap();
+    assert_eq!(
+        *listener.flushes.lock().unwrap(),
+        vec![JobsCompletedInfo {
+            completed: 1,
+            total_completed: 1
+        }]
+    );
+
+    // Nothing changed since the last poll
+    poller.poll(&rocks).unwrap();
+    assert_eq!(listener.flushes.lock().unwrap().len(), 1);
+    assert!(listener.stalls.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_disk_quota() {
+    let rocks = open_cf(temp_dir(), None, &["quota_table", "other_table"]).unwrap();
+    let db = DBMap::<i32, String>::reopen(&rocks, Some("quota_table")).unwrap();
+    db.multi_insert((0..1000).map(|i| (i, i.to_string())))
+        .unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+    let used_bytes = db.disk_usage().unwrap();
+    assert!(used_bytes > 0);
+
+    let quota = DiskQuota::new()
+        .with_db_limit(u64::MAX)
+        .with_table_limit("quota_table", used_bytes)
+        .with_table_limit("other_table", 0);
+    assert!(check_disk_quota("quota_db", &rocks, &quota)
+        .unwrap()
+        .is_empty());
+
+    let quota = quota
+        .with_db_limit(1)
+        .with_table_limit("quota_table", used_bytes - 1);
+    let exceeded = check_disk_quota("quota_db", &rocks, &quota).unwrap();
+    assert_eq!(exceeded.len(), 2);
+    assert_eq!(exceeded[0].table, None);
+    assert!(exceeded[0].used_bytes >= used_bytes);
+    assert_eq!(
+        exceeded[1],
+        QuotaExceeded {
+            table: Some("quota_table".to_string()),
+            used_bytes,
+            limit_bytes: used_bytes - 1,
+        }
+    );
+    assert_eq!(
+        crate::metrics::DBMetrics::get()
+            .quota_metrics
+            .rocksdb_disk_quota_exceeded
+            .with_label_values(&["quota_db", "quota_table"])
+            .get(),
+        1
+    );
+
+    let quota = DiskQuota::new().with_table_limit("missing_table", 0);
+    assert!(matches!(
+        check_disk_quota("quota_db", &rocks, &quota),
+        Err(TypedStoreError::UnregisteredColumn(_))
+    ));
+}
+
+#[test]
+fn test_db_health() {
+    use events::*;
+
+    let rocks = open_cf(temp_dir(), None, &["health_table"]).unwrap();
+    let health = db_health(&rocks).unwrap();
+    assert_eq!(health, DBHealth::default());
+    assert!(health.is_healthy());
+
+    let broadcaster = BackgroundErrorBroadcaster::default();
+    let mut receiver = broadcaster.subscribe();
+    let info = BackgroundErrorInfo {
+        new_errors: 1,
+        total_errors: 1,
+    };
+    broadcaster.on_background_error("health_db", info);
+    assert_eq!(
+        receiver.try_recv().unwrap(),
+        BackgroundErrorEvent {
+            db_name: "health_db".to_string(),
+            info
+        }
+    );
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_compare_databases() {
+    let (path_a, path_b) = (temp_dir(), temp_dir());
+    {
+        let db_a = DBMap::<u32, u32>::open(&path_a, None, Some("table")).unwrap();
+        let db_b = DBMap::<u32, u32>::open(&path_b, None, Some("table")).unwrap();
+        // Span several chunks
+        db_a.multi_insert((0..3000).map(|i| (i, i))).unwrap();
+        db_b.multi_insert((0..3000).filter(|i| i % 1000 != 1).map(|i| (i, i)))
+            .unwrap();
+        db_b.insert(&2, &0).unwrap();
+        db_b.insert(&5000, &5000).unwrap();
+    }
+
+    let mut diff = compare_databases(&path_a, &path_b, &["table"], KeyRange::all()).unwrap();
+    let entries: Vec<_> = diff.by_ref().map(|e| e.unwrap()).collect();
+    assert_eq!(
+        entries[0],
+        DiffEntry::OnlyInA {
+            table: "table".to_string(),
+            key: be_fix_int_ser(&1u32).unwrap(),
+            value: bincode::serialize(&1u32).unwrap(),
+        }
+    );
+    assert_eq!(
+        entries[1],
+        DiffEntry::ValueMismatch {
+            table: "table".to_string(),
+            key: be_fix_int_ser(&2u32).unwrap(),
+            value_a: bincode::serialize(&2u32).unwrap(),
+            value_b: bincode::serialize(&0u32).unwrap(),
+        }
+    );
+    assert!(matches!(entries.last(), Some(DiffEntry::OnlyInB { .. })));
+    assert_eq!(
+        diff.summary(),
+        DiffSummary {
+            keys_compared: 3001,
+            only_in_a: 3,
+            only_in_b: 1,
+            value_mismatches: 1,
+        }
+    );
+
+    // Restricted to a key range
+    let range = KeyRange::new(Some(&1000u32), Some(&2000u32)).unwrap();
+    let mut diff = compare_databases(&path_a, &path_b, &["table"], range).unwrap();
+    assert_eq!(diff.by_ref().count(), 1);
+    assert_eq!(diff.summary().keys_compared, 1000);
+}
+
+#[test]
+fn test_size_report() {
+    let db = DBMap::<(u8, u32), Vec<u8>>::open(temp_dir(), None, None).unwrap();
+    db.multi_insert((0..100).map(|i| ((0, i), vec![0; 10])))
+        .unwrap();
+    db.multi_insert((0..100).map(|i| ((1, i), vec![0; 1000])))
+        .unwrap();
+
+    let report = db
+        .size_report(SizeAnalysisOptions::full().with_prefix_len(1))
+        .unwrap();
+    assert_eq!(report.entries_analyzed, 200);
+    assert_eq!(report.estimated_entries, 200);
+    // (u8, u32) keys are encoded on 5 bytes
+    assert_eq!(report.key_sizes.max, 5);
+    assert_eq!(
+        report.key_sizes.non_empty_buckets().collect::<Vec<_>>(),
+        vec![(8, 200)]
+    );
+    // Vec values are prefixed with their length on 8 bytes
+    assert_eq!(report.value_sizes.max, 1008);
+    assert_eq!(report.prefixes.len(), 2);
+    let top = report.top_prefixes(1);
+    assert_eq!(top[0].0, &[1u8]);
+    assert_eq!(top[0].1.total_bytes, 100 * (5 + 1008));
+
+    let report = db.size_report(SizeAnalysisOptions::sampled(10)).unwrap();
+    assert_eq!(report.entries_scanned, 200);
+    assert_eq!(report.entries_analyzed, 20);
+    assert_eq!(report.estimated_entries, 200);
+    assert!(report.prefixes.is_empty());
+
+    let report = db
+        .size_report(SizeAnalysisOptions::full().with_max_entries(50))
+        .unwrap();
+    assert_eq!(report.entries_analyzed, 50);
+}
+
+#[test]
+fn test_lsm_report() {
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, Some("lsm_table")).unwrap();
+    let report = db.lsm_report().unwrap();
+    assert!(report.levels.is_empty());
+    assert_eq!(report.estimated_read_amplification, 0);
+
+    for i in 0..3 {
+        db.insert(&i, &i).unwrap();
+        db.rocksdb.flush_cf(&db.cf()).unwrap();
+    }
+    let report = db.lsm_report().unwrap();
+    assert_eq!(report.table, "lsm_table");
+    assert_eq!(report.levels[0].num_files, 3);
+    assert_eq!(report.levels[0].num_entries, 3);
+    assert_eq!(report.estimated_read_amplification, 3);
+    assert!(report.total_size_bytes > 0);
+    assert!(report.cfstats.is_some());
+
+    db.rocksdb
+        .compact_range_cf(&db.cf(), None::<&[u8]>, None::<&[u8]>);
+    let report = db.lsm_report().unwrap();
+    assert_eq!(report.estimated_read_amplification, 1);
+}
+
+#[test]
+fn test_get_or_insert_with() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    let inits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            // The maps reopened over the same table share the lock of its clones
+            let db = match i % 2 {
+                0 => db.clone(),
+                _ => DBMap::<i32, String>::reopen(&db.rocksdb, None).unwrap(),
+            };
+            let inits = inits.clone();
+            std::thread::spawn(move || {
+                db.get_or_insert_with(&1, || {
+                    inits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    i.to_string()
+                })
+                .unwrap()
+            })
+        })
+        .collect();
+    let values: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    assert_eq!(inits.load(std::sync::atomic::Ordering::SeqCst), 1);
+    assert!(values.iter().all(|v| *v == values[0]));
+    assert_eq!(db.get(&1).unwrap(), Some(values[0].clone()));
+}
+
+#[test]
+fn test_prefetching_iter() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    db.multi_insert((0..1000).map(|i| (i, i.to_string())))
+        .unwrap();
+
+    let entries: Vec<_> = db.prefetching_iter(64).map(|e| e.unwrap()).collect();
+    assert_eq!(entries, db.iter().collect::<Vec<_>>());
+
+    // Dropping the iterator early stops the background work
+    assert_eq!(db.prefetching_iter(10).take(15).count(), 15);
+
+    // Deserialization failures are reported
+    let db = DBMap::<u64, String>::reopen(&db.rocksdb, None).unwrap();
+    assert!(db
+        .prefetching_iter(DEFAULT_PREFETCH_BATCH_SIZE)
+        .any(|e| e.is_err()));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_value_format() {
+    // serde_json::Value can't be deserialized from bincode, the values must be stored as json directly
+    let db = DBMap::<i32, serde_json::Value>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_value_format(ValueFormat::Json)
+        .with_accumulator()
+        .unwrap();
+    let value = serde_json::json!({ "balance": 10, "owner": ["a", "b"] });
+    db.insert(&1, &value).expect("Failed to insert");
+    assert_eq!(db.get(&1).unwrap(), Some(value.clone()));
+    assert_eq!(db.iter().collect::<Vec<_>>(), vec![(1, value.clone())]);
+    let raw = db
+        .rocksdb
+        .get_cf(&db.cf(), be_fix_int_ser(&1).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(raw, serde_json::to_vec(&value).unwrap());
+
+    // The accumulator is updated with the values as stored, like when it's built from the table
+    db.insert(&1, &serde_json::json!(null))
+        .expect("Failed to insert");
+    let rebuilt = DBMap::<i32, serde_json::Value>::reopen(&db.rocksdb, None)
+        .unwrap()
+        .with_value_format(ValueFormat::Json)
+        .with_accumulator()
+        .unwrap();
+    assert_eq!(db.accumulated_digest(), rebuilt.accumulated_digest());
+}
+
+#[cfg(feature = "bcs")]
+#[test]
+fn test_bcs_key_format() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+    enum Kind {
+        Owned,
+        Shared(u32),
+    }
+
+    let db = DBMap::<(u64, Kind), String>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_key_format(KeyFormat::Bcs);
+    db.insert(&(256, Kind::Owned), &"c".to_owned()).unwrap();
+    db.insert(&(1, Kind::Shared(2)), &"b".to_owned()).unwrap();
+    db.insert(&(1, Kind::Owned), &"a".to_owned()).unwrap();
+
+    // The integers are big endian, the variant indexes ULEB128 as in BCS
+    let raw = db
+        .rocksdb
+        .get_cf(&db.cf(), [0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 2])
+        .unwrap();
+    assert_eq!(raw, Some(bincode::serialize(&"b".to_owned()).unwrap()));
+    // The keys keep the order of their integers
+    assert_eq!(
+        db.keys().collect::<Vec<_>>(),
+        vec![(1, Kind::Owned), (1, Kind::Shared(2)), (256, Kind::Owned)]
+    );
+    assert_eq!(
+        db.iter()
+            .skip_to(&(1, Kind::Shared(0)))
+            .unwrap()
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>(),
+        vec!["b".to_owned(), "c".to_owned()]
+    );
+    // The prefixes are encoded in the key format too
+    db.remove_prefix(&1u64).unwrap();
+    assert_eq!(db.keys().collect::<Vec<_>>(), vec![(256, Kind::Owned)]);
+    assert_eq!(db.get(&(256, Kind::Owned)).unwrap(), Some("c".to_owned()));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_envelope_encryption() {
+    use super::encryption::*;
+
+    let keys = MasterKeys::new(MasterKey::new(0, [1u8; 32]));
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    let codec = EnvelopeCodec::open(&db.rocksdb, "default", &keys).unwrap();
+    let db = db.with_value_codec(Arc::new(codec));
+
+    db.insert(&1, &"one".to_owned()).expect("Failed to insert");
+    assert_eq!(db.get(&1).unwrap(), Some("one".to_owned()));
+    assert_eq!(db.iter().collect::<Vec<_>>(), vec![(1, "one".to_owned())]);
+    // The stored bytes are not the plain serialized value
+    let raw = db
+        .rocksdb
+        .get_cf(&db.cf(), be_fix_int_ser(&1).unwrap())
+        .unwrap()
+        .unwrap();
+    assert_ne!(raw, bincode::serialize(&"one".to_owned()).unwrap());
+    // The value is authenticated along its key, and can't be moved to another one
+    db.rocksdb
+        .put_cf(&db.cf(), be_fix_int_ser(&3).unwrap(), &raw)
+        .unwrap();
+    assert!(matches!(
+        db.get(&3),
+        Err(TypedStoreError::EncryptionError(_))
+    ));
+    db.remove(&3).unwrap();
+
+    // A new data key encrypts the values written after reopening, the old ones remain readable
+    assert_eq!(rotate_data_key(&db.rocksdb, "default", &keys).unwrap(), 1);
+    let codec = EnvelopeCodec::open(&db.rocksdb, "default", &keys).unwrap();
+    let db = db.with_value_codec(Arc::new(codec));
+    db.insert(&2, &"two".to_owned()).expect("Failed to insert");
+    assert_eq!(db.get(&1).unwrap(), Some("one".to_owned()));
+    assert_eq!(db.get(&2).unwrap(), Some("two".to_owned()));
+    // The data keys are not entries of the table, even when it's the default column family
+    assert_eq!(db.keys().collect::<Vec<_>>(), vec![1, 2]);
+
+    // After a master key rotation, the previous master key is no longer needed
+    let new_keys =
+        MasterKeys::new(MasterKey::new(1, [2u8; 32])).with_previous(MasterKey::new(0, [1u8; 32]));
+    rotate_master_key(&db.rocksdb, "default", &new_keys).unwrap();
+    let new_keys = MasterKeys::new(MasterKey::new(1, [2u8; 32]));
+    let codec = EnvelopeCodec::open(&db.rocksdb, "default", &new_keys).unwrap();
+    let db = db.with_value_codec(Arc::new(codec));
+    assert_eq!(db.get(&1).unwrap(), Some("one".to_owned()));
+    assert_eq!(db.get(&2).unwrap(), Some("two".to_owned()));
+    assert!(EnvelopeCodec::open(&db.rocksdb, "default", &keys).is_err());
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_envelope_encryption_concurrent_open() {
+    use super::encryption::*;
+
+    let keys = MasterKeys::new(MasterKey::new(0, [1u8; 32]));
+    let db = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    // The concurrent openings of a new table agree on a single first data key
+    let codecs: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| scope.spawn(|| EnvelopeCodec::open(&db.rocksdb, "default", &keys).unwrap()))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let encoded = codecs[0].encode(b"key", b"value".to_vec()).unwrap();
+    for codec in &codecs {
+        assert_eq!(codec.decode(b"key", &encoded).unwrap(), b"value");
+    }
+    assert_eq!(rotate_data_key(&db.rocksdb, "default", &keys).unwrap(), 1);
+}
+
+#[test]
+fn test_content_hash() {
+    let db_a = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    let db_b = DBMap::<i32, String>::open(temp_dir(), None, None).unwrap();
+    let entries: Vec<_> = (0..100).map(|i| (i, i.to_string())).collect();
+    db_a.multi_insert(entries.clone()).unwrap();
+    db_b.multi_insert(entries).unwrap();
+    assert_eq!(
+        db_a.content_hash(KeyRange::all()).unwrap(),
+        db_b.content_hash(KeyRange::all()).unwrap()
+    );
+
+    // Resuming from checkpoints gives the same digest
+    let mut checkpoint = ContentHashCheckpoint::new(KeyRange::all());
+    while !checkpoint.is_complete() {
+        checkpoint = db_a.content_hash_resume(checkpoint, 7).unwrap();
+    }
+    assert_eq!(checkpoint.entries(), 100);
+    assert_eq!(
+        checkpoint.digest(),
+        db_b.content_hash(KeyRange::all()).unwrap()
+    );
+
+    // A modified value changes the digest, but not outside of the modified range
+    db_b.insert(&50, &"fifty".to_owned()).unwrap();
+    assert_ne!(
+        db_a.content_hash(KeyRange::all()).unwrap(),
+        db_b.content_hash(KeyRange::all()).unwrap()
+    );
+    let range = KeyRange::new(Some(&0), Some(&50)).unwrap();
+    assert_eq!(
+        db_a.content_hash(range.clone()).unwrap(),
+        db_b.content_hash(range).unwrap()
+    );
+}
+
+#[test]
+fn test_accumulator() {
+    let db_a = DBMap::<i32, String>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_accumulator()
+        .unwrap();
+    let empty = db_a.accumulated_digest().unwrap();
+
+    // Writes in different orders lead to the same digest
+    db_a.insert(&1, &"one".to_owned()).unwrap();
+    db_a.insert(&2, &"two".to_owned()).unwrap();
+    db_a.insert(&3, &"three".to_owned()).unwrap();
+    let db_b = DBMap::<i32, String>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_accumulator()
+        .unwrap();
+    db_b.batch()
+        .insert_batch(
+            &db_b,
+            [(3, "three"), (1, "uno"), (2, "two")].map(|(k, v)| (k, v.to_owned())),
+        )
+        .unwrap()
+        .insert_batch(&db_b, [(1, "one".to_owned())])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(db_a.accumulated_digest(), db_b.accumulated_digest());
+
+    // The accumulator matches the one computed from a scan of the table
+    db_a.remove(&2).unwrap();
+    db_a.batch()
+        .delete_range(&db_a, &3, &10)
+        .unwrap()
+        .write()
+        .unwrap();
+    let rescanned = DBMap::<i32, String>::reopen(&db_a.rocksdb, None)
+        .unwrap()
+        .with_accumulator()
+        .unwrap();
+    assert_eq!(db_a.accumulated_digest(), rescanned.accumulated_digest());
+    assert_ne!(db_a.accumulated_digest(), db_b.accumulated_digest());
+
+    db_a.remove(&1).unwrap();
+    assert_eq!(db_a.accumulated_digest(), Some(empty));
+    assert!(DBMap::<i32, String>::open(temp_dir(), None, None)
+        .unwrap()
+        .accumulated_digest()
+        .is_none());
+}
+
+#[test]
+fn test_diff_checkpoints() {
+    let (before, after) = (temp_dir().join("before"), temp_dir().join("after"));
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, Some("table")).unwrap();
+    db.multi_insert((0..10).map(|i| (i, i))).unwrap();
+    rocksdb::checkpoint::Checkpoint::new(&db.rocksdb)
+        .unwrap()
+        .create_checkpoint(&before)
+        .unwrap();
+
+    db.remove(&1).unwrap();
+    db.insert(&2, &20).unwrap();
+    db.insert(&10, &10).unwrap();
+    rocksdb::checkpoint::Checkpoint::new(&db.rocksdb)
+        .unwrap()
+        .create_checkpoint(&after)
+        .unwrap();
+
+    let changes = diff_checkpoints(&before, &after, &[]).unwrap();
+    assert!(changes["default"].is_empty());
+    assert_eq!(
+        changes["table"],
+        TableChanges {
+            added: vec![be_fix_int_ser(&10u32).unwrap()],
+            removed: vec![be_fix_int_ser(&1u32).unwrap()],
+            modified: vec![be_fix_int_ser(&2u32).unwrap()],
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_watch_prefix() {
+    let db = DBMap::<(u32, u32), String>::open(temp_dir(), None, None).unwrap();
+    let mut watch = db.watch_prefix(&1u32).unwrap();
+    let mut watch_all = db.watch_prefix(&()).unwrap();
+
+    db.insert(&(0, 1), &"ignored".to_owned()).unwrap();
+    db.insert(&(1, 1), &"one".to_owned()).unwrap();
+    db.batch()
+        .delete_batch(&db, [(1u32, 1u32)])
+        .unwrap()
+        .delete_range(&db, &(1, 5), &(2, 0))
+        .unwrap()
+        .write()
+        .unwrap();
+
+    assert_eq!(
+        watch.recv().await.unwrap(),
+        ChangeEvent::Inserted {
+            key: (1, 1),
+            value: "one".to_owned()
+        }
+    );
+    assert_eq!(
+        watch.recv().await.unwrap(),
+        ChangeEvent::Removed { key: (1, 1) }
+    );
+    assert_eq!(
+        watch.recv().await.unwrap(),
+        ChangeEvent::RangeRemoved {
+            from: (1, 5),
+            to: (2, 0)
+        }
+    );
+    assert!(matches!(
+        watch_all.recv().await.unwrap(),
+        ChangeEvent::Inserted { key: (0, 1), .. }
+    ));
+
+    // Dropped watchers are forgotten
+    drop(watch);
+    drop(watch_all);
+    db.insert(&(1, 2), &"two".to_owned()).unwrap();
+    assert!(db.watchers.is_empty());
+}
+
+#[tokio::test]
+async fn test_watch_invalidations() {
+    let db = DBMap::<u32, String>::open(temp_dir(), None, None).unwrap();
+    let mut invalidations = db.watch_invalidations();
+
+    db.insert(&1, &"one".to_owned()).unwrap();
+    db.remove(&1).unwrap();
+    db.batch()
+        .delete_range(&db, &5, &10)
+        .unwrap()
+        .write()
+        .unwrap();
+    db.clear().unwrap();
+    assert_eq!(invalidations.recv().await.unwrap(), Invalidation::Key(1));
+    assert_eq!(invalidations.recv().await.unwrap(), Invalidation::Key(1));
+    assert_eq!(
+        invalidations.recv().await.unwrap(),
+        Invalidation::Range { from: 5, to: 10 }
+    );
+    assert_eq!(invalidations.recv().await.unwrap(), Invalidation::All);
+
+    // A lagging watch invalidates everything, then resumes with the buffered changes
+    for i in 0..DEFAULT_WATCH_CAPACITY as u32 + 1 {
+        db.insert(&i, &i.to_string()).unwrap();
+    }
+    assert_eq!(invalidations.recv().await.unwrap(), Invalidation::All);
+    assert_eq!(invalidations.recv().await.unwrap(), Invalidation::Key(1));
+}
+
+#[test]
+fn test_set_options() {
+    let db = DBMap::<i32, String>::open(temp_dir(), None, Some("table")).unwrap();
+    db.set_options(&[("write_buffer_size", "1048576")]).unwrap();
+    // Options which can't be changed at runtime are rejected
+    assert!(db.set_options(&[("num_levels", "3")]).is_err());
+
+    let options = RuntimeOptions {
+        level0_file_num_compaction_trigger: Some(8),
+        disable_auto_compactions: Some(false),
+        ..Default::default()
+    };
+    assert_eq!(
+        options.to_pairs(),
+        vec![
+            ("level0_file_num_compaction_trigger", "8".to_owned()),
+            ("disable_auto_compactions", "false".to_owned())
+        ]
+    );
+    db.set_runtime_options(&options).unwrap();
+
+    let config = [("unknown".to_owned(), options)].into_iter().collect();
+    assert!(matches!(
+        apply_runtime_config(&db.rocksdb, &["table"], &config),
+        Err(TypedStoreError::UnregisteredColumn(table)) if table == "unknown"
+    ));
+}
+
+#[test]
+fn test_auto_flush() {
+    let rocks = open_cf(temp_dir(), None, &["busy", "idle"]).unwrap();
+    let busy = DBMap::<i32, String>::reopen(&rocks, Some("busy")).unwrap();
+    let idle = DBMap::<i32, String>::reopen(&rocks, Some("idle")).unwrap();
+    let mut flusher = AutoFlusher::new(&["busy", "idle"]);
+
+    busy.insert(&1, &"1".to_owned()).unwrap();
+    idle.insert(&1, &"1".to_owned()).unwrap();
+    assert!(flusher.flush_idle_tables(&rocks).unwrap().is_empty());
+
+    busy.insert(&2, &"2".to_owned()).unwrap();
+    assert_eq!(flusher.flush_idle_tables(&rocks).unwrap(), vec!["idle"]);
+    assert_eq!(idle.get(&1).unwrap(), Some("1".to_owned()));
+
+    // Empty memtables are not flushed again
+    assert_eq!(flusher.flush_idle_tables(&rocks).unwrap(), vec!["busy"]);
+    assert!(flusher.flush_idle_tables(&rocks).unwrap().is_empty());
+}
+
+#[test]
+fn test_dbset() {
+    let rocks = open_cf(temp_dir(), None, &["a", "b"]).unwrap();
+    let a = DBSet::<u32>::reopen(&rocks, Some("a")).unwrap();
+    let b = DBSet::<u32>::reopen(&rocks, Some("b")).unwrap();
+
+    a.multi_insert([1, 2, 3, 5]).unwrap();
+    b.insert(&2).unwrap();
+    b.insert(&4).unwrap();
+    b.insert(&5).unwrap();
+    b.insert(&9).unwrap();
+    assert!(a.contains(&1).unwrap());
+    assert!(!a.contains(&4).unwrap());
+    assert_eq!(
+        a.multi_contains([1, 4, 5]).unwrap(),
+        vec![true, false, true]
+    );
+
+    assert_eq!(a.union(&b).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 9]);
+    assert_eq!(a.intersection(&b).collect::<Vec<_>>(), vec![2, 5]);
+    assert_eq!(a.difference(&b).collect::<Vec<_>>(), vec![1, 3]);
+    assert_eq!(b.difference(&a).collect::<Vec<_>>(), vec![4, 9]);
+
+    DBBatch::new(&rocks)
+        .delete_set_batch(&a, [1, 2])
+        .unwrap()
+        .insert_set_batch(&b, [10])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(a.iter().collect::<Vec<_>>(), vec![3, 5]);
+    assert_eq!(b.iter().collect::<Vec<_>>(), vec![2, 4, 5, 9, 10]);
+    a.remove(&3).unwrap();
+    a.multi_remove([5]).unwrap();
+    assert_eq!(a.iter().count(), 0);
+}
+
+#[test]
+fn test_multimap() {
+    let db = DBMultiMap::<(u32, u32), String>::open(temp_dir(), None, None).unwrap();
+    assert_eq!(db.insert(&(1, 1), &"a".to_owned()).unwrap(), 0);
+    assert_eq!(db.insert(&(1, 1), &"b".to_owned()).unwrap(), 1);
+    db.multi_insert([
+        ((1, 1), "a".to_owned()),
+        ((1, 2), "c".to_owned()),
+        ((2, 1), "d".to_owned()),
+    ])
+    .unwrap();
+    assert_eq!(db.insert(&(1, 1), &"e".to_owned()).unwrap(), 3);
+
+    assert_eq!(db.get_all(&(1, 1)).unwrap(), vec!["a", "b", "a", "e"]);
+    assert!(db.get_all(&(3, 0)).unwrap().is_empty());
+    assert_eq!(
+        db.iter_prefix(&1u32).unwrap().collect::<Vec<_>>(),
+        vec![
+            ((1, 1), "a".to_owned()),
+            ((1, 1), "b".to_owned()),
+            ((1, 1), "a".to_owned()),
+            ((1, 1), "e".to_owned()),
+            ((1, 2), "c".to_owned()),
+        ]
+    );
+
+    assert_eq!(db.remove_value(&(1, 1), &"a".to_owned()).unwrap(), 2);
+    assert_eq!(db.get_all(&(1, 1)).unwrap(), vec!["b", "e"]);
+    db.remove_all(&(1, 1)).unwrap();
+    assert!(db.get_all(&(1, 1)).unwrap().is_empty());
+    assert_eq!(db.iter().count(), 2);
+}
+
+#[test]
+fn test_timeseries() {
+    let series = DBTimeSeries::<String>::open(temp_dir(), None, None).unwrap();
+    assert_eq!(series.append(10, &"a".to_owned()).unwrap(), 0);
+    assert_eq!(series.append(10, &"b".to_owned()).unwrap(), 1);
+    assert_eq!(series.append(5, &"c".to_owned()).unwrap(), 0);
+    series.append(256, &"d".to_owned()).unwrap();
+    series.append(1 << 40, &"e".to_owned()).unwrap();
+
+    // Points are ordered by timestamp, including across byte boundaries
+    assert_eq!(
+        series.range(0..u64::MAX).collect::<Vec<_>>(),
+        vec![
+            (5, "c".to_owned()),
+            (10, "a".to_owned()),
+            (10, "b".to_owned()),
+            (256, "d".to_owned()),
+            (1 << 40, "e".to_owned())
+        ]
+    );
+    assert_eq!(
+        series.range(6..256).map(|(_, v)| v).collect::<Vec<_>>(),
+        vec!["a", "b"]
+    );
+    assert_eq!(
+        series.downsample(0..300, 100).collect::<Vec<_>>(),
+        vec![(5, "c".to_owned()), (256, "d".to_owned())]
+    );
+
+    series.prune_before(10).unwrap();
+    assert_eq!(series.range(0..u64::MAX).count(), 4);
+
+    // All the points are expired after a retention of one second
+    let series = series.with_retention(Duration::from_secs(1));
+    series.append_now(&"f".to_owned()).unwrap();
+    series.prune_expired().unwrap();
+    assert_eq!(
+        series
+            .range(0..u64::MAX)
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>(),
+        vec!["f"]
+    );
+}
+
+#[test]
+fn test_versioned_values() {
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, None)
+        .expect("Failed to open storage")
+        .with_value_codec(Arc::new(VersionedCodec::new(0)));
+    db.insert(&1, &10).unwrap();
+    db.insert(&2, &20).unwrap();
+
+    // Version 2 values are strings, upgraded from version 0 through version 1
+    let codec = VersionedCodec::new(2)
+        .with_upgrade(0, |v: u32| u64::from(v) * 2)
+        .with_upgrade(1, |v: u64| v.to_string());
+    let v2 = DBMap::<u32, String>::reopen(&db.rocksdb, None)
+        .unwrap()
+        .with_value_codec(Arc::new(codec));
+    assert_eq!(v2.get(&1).unwrap(), Some("20".to_owned()));
+    v2.insert(&3, &"30".to_owned()).unwrap();
+    assert_eq!(
+        v2.iter().collect::<Vec<_>>(),
+        vec![
+            (1, "20".to_owned()),
+            (2, "40".to_owned()),
+            (3, "30".to_owned())
+        ]
+    );
+
+    // Without write back, the stored values keep their version
+    assert_eq!(
+        &db.rocksdb
+            .get(be_fix_int_ser(&1).unwrap())
+            .unwrap()
+            .unwrap()[..4],
+        &[0, 0, 0, 0]
+    );
+
+    // Values newer than the codec, or without a registered upgrade, can't be read
+    assert!(matches!(
+        db.get(&3),
+        Err(TypedStoreError::ValueVersionError(_))
+    ));
+    let v2 = DBMap::<u32, String>::reopen(&db.rocksdb, None)
+        .unwrap()
+        .with_value_codec(Arc::new(VersionedCodec::new(2)));
+    assert!(matches!(
+        v2.get(&1),
+        Err(TypedStoreError::ValueVersionError(_))
+    ));
+
+    // With write back, the maintenance pass stores the upgraded values with the current version
+    let codec = VersionedCodec::new(1)
+        .with_upgrade(0, |v: u32| u64::from(v) * 2)
+        .with_write_back();
+    let v1 = DBMap::<u32, u64>::reopen(&db.rocksdb, None)
+        .unwrap()
+        .with_value_codec(Arc::new(codec));
+    let stored_version = |key: u32| {
+        db.rocksdb
+            .get(be_fix_int_ser(&key).unwrap())
+            .unwrap()
+            .unwrap()[..4]
+            .to_vec()
+    };
+    // Reads don't write
+    assert_eq!(v1.get(&2).unwrap(), Some(40));
+    assert_eq!(stored_version(2), [0, 0, 0, 0]);
+    assert_eq!(v1.rewrite_stale_values().unwrap(), 2);
+    assert_eq!(stored_version(2), [0, 0, 0, 1]);
+    assert_eq!(v1.get(&2).unwrap(), Some(40));
+    assert_eq!(v1.rewrite_stale_values().unwrap(), 0);
+}
+
+#[test]
+fn test_in_memory_map() {
+    let db =
+        DBMap::<(u32, String), u64>::open(temp_dir(), None, None).expect("Failed to open storage");
+    let entries = [
+        ((2, "a".to_owned()), 1),
+        ((1, "b".to_owned()), 2),
+        ((1, "a".to_owned()), 3),
+    ];
+    db.multi_insert(entries.clone()).unwrap();
+
+    let map = crate::InMemoryMap::<(u32, String), u64>::new();
+    map.multi_insert(entries).unwrap();
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        db.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(map.get(&(1, "b".to_owned())).unwrap(), Some(2));
+    map.remove(&(1, "b".to_owned())).unwrap();
+    assert!(!map.contains_key(&(1, "b".to_owned())).unwrap());
+
+    // The encoded entries of a table are loaded as is
+    let mut db_iter = db.rocksdb.raw_iterator();
+    db_iter.seek_to_first();
+    let mut encoded = Vec::new();
+    while let (Some(key), Some(value)) = (db_iter.key(), db_iter.value()) {
+        encoded.push((key.to_vec(), value.to_vec()));
+        db_iter.next();
+    }
+    let map = crate::InMemoryMap::<(u32, String), u64>::from_encoded(encoded);
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        db.iter().collect::<Vec<_>>()
+    );
+}
+
+#[cfg(feature = "bitmap")]
+#[test]
+fn test_bitmap_merge() {
+    use super::bitmap::*;
+
+    let rocks = open_cf_opts(temp_dir(), None, &[("owned", &bitmap_options())]).unwrap();
+    let db = DBMap::<String, Bitmap>::reopen(&rocks, Some("owned")).unwrap();
+    let alice = "alice".to_owned();
+
+    assert!(!db.contains(&alice, 1).unwrap());
+    db.insert(&alice, &[1, 2].into_iter().collect()).unwrap();
+    db.add(&alice, [3, u64::MAX]).unwrap();
+    db.union_into(&alice, &[2, 1 << 40].into_iter().collect())
+        .unwrap();
+    assert!(db.contains(&alice, 3).unwrap());
+    assert!(!db.contains(&alice, 4).unwrap());
+
+    let expected: Bitmap = [1, 2, 3, 1 << 40, u64::MAX].into_iter().collect();
+    assert_eq!(db.get(&alice).unwrap(), Some(expected.clone()));
+    // Compactions apply the merge operands
+    rocks.compact_range_cf(&db.cf(), None::<&[u8]>, None::<&[u8]>);
+    assert_eq!(
+        db.iter().collect::<Vec<_>>(),
+        vec![(alice.clone(), expected)]
+    );
+
+    // The merge operator can't decode the values of a codec
+    let db = db.with_value_codec(Arc::new(VersionedCodec::new(0)));
+    assert_eq!(
+        db.add(&alice, [5]),
+        Err(TypedStoreError::UnsupportedValueCodec("owned".to_owned()))
+    );
+}
+
+#[test]
+fn test_export_snapshot() {
+    let rocks = open_cf(temp_dir(), None, &["table", "other"]).unwrap();
+    let db = DBMap::<u32, String>::reopen(&rocks, Some("table")).unwrap();
+    db.multi_insert((0..100).map(|i| (i, i.to_string())))
+        .unwrap();
+
+    let dir = temp_dir();
+    let exported = export_snapshot(&rocks, &["table", "other"], &dir, ExportFormat::Raw).unwrap();
+    assert_eq!(
+        exported
+            .iter()
+            .map(|e| (e.table.as_str(), e.entries))
+            .collect::<Vec<_>>(),
+        vec![("table", 100), ("other", 0)]
+    );
+    // The checkpoint is removed once exported
+    assert!(!dir.join("checkpoint").exists());
+
+    let entries = read_raw_export(&exported[0].path).unwrap();
+    let map = crate::InMemoryMap::<u32, String>::from_encoded(entries);
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        db.iter().collect::<Vec<_>>()
+    );
+    assert!(read_raw_export(&exported[1].path).unwrap().is_empty());
+    assert!(matches!(
+        read_raw_export(&dir.join("missing.bin")),
+        Err(TypedStoreError::IoError(_))
+    ));
+
+    assert!(matches!(
+        export_snapshot(&rocks, &["missing"], &dir, ExportFormat::Raw),
+        Err(TypedStoreError::UnregisteredColumn(_))
+    ));
+}
+
+#[test]
+fn test_filter_policies() {
+    let budget = MemoryBudget::new(64 << 20).unwrap();
+    let budget_options = budget.table_options_with_filter(
+        default_rocksdb_options(),
+        &[("budget", 1)],
+        "budget",
+        Some(FilterPolicy::ribbon()),
+    );
+    let rocks = open_cf_opts(
+        temp_dir(),
+        None,
+        &[
+            ("none", &default_rocksdb_options()),
+            (
+                "bloom",
+                &options_with_filter(FilterPolicy::whole_key_bloom()),
+            ),
+            (
+                "prefix",
+                &options_with_filter(FilterPolicy::prefix_bloom(8)),
+            ),
+            ("ribbon", &options_with_filter(FilterPolicy::ribbon())),
+            ("budget", &budget_options),
+        ],
+    )
+    .unwrap();
+
+    for table in ["none", "bloom", "prefix", "ribbon", "budget"] {
+        let db = DBMap::<(u64, u64), String>::reopen(&rocks, Some(table)).unwrap();
+        db.multi_insert((0..100).map(|i| ((i % 4, i), i.to_string())))
+            .unwrap();
+        rocks.flush_cf(&db.cf()).unwrap();
+
+        assert_eq!(db.get(&(1, 5)).unwrap(), Some("5".to_owned()));
+        assert_eq!(db.get(&(1, 4)).unwrap(), None);
+        assert_eq!(
+            db.iter()
+                .skip_to(&(3, 0))
+                .unwrap()
+                .map(|((prefix, _), _)| prefix)
+                .collect::<Vec<_>>(),
+            vec![3; 25]
+        );
+
+        let properties = rocks
+            .property_value_cf(&db.cf(), "rocksdb.aggregated-table-properties")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            properties.contains("filter block size=0;"),
+            table == "none",
+            "{table}: {properties}"
+        );
+    }
+}
+
+#[test]
+fn test_prefix_filter_iterates_across_prefixes() {
+    let rocks = open_cf_opts(
+        temp_dir(),
+        None,
+        &[(
+            "prefix",
+            &options_with_filter(FilterPolicy::prefix_bloom(8)),
+        )],
+    )
+    .unwrap();
+    let db = DBMap::<(u64, u64), String>::reopen(&rocks, Some("prefix")).unwrap();
+    db.multi_insert((0..30).map(|i| ((i % 3 * 2, i), i.to_string())))
+        .unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+
+    let prefixes = |iter: Iter<'_, (u64, u64), String>| {
+        iter.map(|((prefix, _), _)| prefix).collect::<Vec<_>>()
+    };
+    assert_eq!(db.iter().count(), 30);
+    // Seeks to an absent prefix, and past the keys of a prefix, continue with the next prefixes
+    assert_eq!(
+        prefixes(db.iter().skip_to(&(1, 0)).unwrap()),
+        [vec![2; 10], vec![4; 10]].concat()
+    );
+    assert_eq!(
+        prefixes(db.iter().skip_to(&(2, 1000)).unwrap()),
+        vec![4; 10]
+    );
+    assert_eq!(
+        prefixes(db.iter_snapshot().skip_to(&(3, 0)).unwrap()),
+        vec![4; 10]
+    );
+    assert_eq!(db.scan_filtered((1, 0).., |_, _| true).unwrap().count(), 20);
+}
+
+#[test]
+fn test_memory_budget_rejects_invalid_shares() {
+    let shares = |block_cache, write_buffers, pinned_iterators| MemoryShares {
+        block_cache,
+        write_buffers,
+        pinned_iterators,
+    };
+    for invalid in [
+        shares(0.0, 0.0, 0.0),
+        shares(1.0, -0.5, 0.0),
+        shares(f64::NAN, 1.0, 1.0),
+        shares(f64::INFINITY, 1.0, 1.0),
+    ] {
+        assert!(matches!(
+            MemoryBudget::with_shares(64 << 20, invalid),
+            Err(TypedStoreError::InvalidConfiguration(_))
+        ));
+    }
+
+    // The shares are normalized
+    let budget = MemoryBudget::with_shares(100, shares(2.0, 2.0, 0.0)).unwrap();
+    assert_eq!(budget.block_cache_bytes(), 50);
+    assert_eq!(budget.write_buffer_bytes(), 50);
+    assert_eq!(budget.pinned_iterator_bytes(), 0);
+}
+
+#[test]
+fn test_large_table_options() {
+    let budget = MemoryBudget::new(64 << 20).unwrap();
+    let budget_options = budget.large_table_options(
+        default_rocksdb_options(),
+        &[("budget", 1)],
+        "budget",
+        FilterPolicy::prefix_bloom(8),
+    );
+    let rocks = open_cf_opts(
+        temp_dir(),
+        None,
+        &[
+            ("large", &default_options_for_large_table()),
+            ("budget", &budget_options),
+        ],
+    )
+    .unwrap();
+
+    for table in ["large", "budget"] {
+        let db = DBMap::<(u64, u64), String>::reopen(&rocks, Some(table)).unwrap();
+        db.multi_insert((0..10_000).map(|i| ((i % 4, i), i.to_string())))
+            .unwrap();
+        rocks.flush_cf(&db.cf()).unwrap();
+
+        assert_eq!(db.get(&(1, 5)).unwrap(), Some("5".to_owned()));
+        assert_eq!(db.get(&(1, 4)).unwrap(), None);
+        assert_eq!(db.iter().skip_to(&(3, 0)).unwrap().count(), 2_500);
+
+        let properties = rocks
+            .property_value_cf(&db.cf(), "rocksdb.aggregated-table-properties")
+            .unwrap()
+            .unwrap();
+        assert!(!properties.contains("filter block size=0;"), "{properties}");
+        assert!(
+            !properties.contains("# index partitions=0;"),
+            "{properties}"
+        );
+    }
+}
+
+#[test]
+fn test_option_presets() {
+    let rocks = open_cf_opts(
+        temp_dir(),
+        None,
+        &[
+            ("universal", &universal_compaction_options()),
+            ("write_heavy", &write_heavy_options()),
+            ("point_lookup", &point_lookup_options()),
+        ],
+    )
+    .unwrap();
+
+    for table in ["universal", "write_heavy", "point_lookup"] {
+        let db = DBMap::<u64, String>::reopen(&rocks, Some(table)).unwrap();
+        for i in 0..4 {
+            db.multi_insert((i * 100..(i + 1) * 100).map(|k| (k, k.to_string())))
+                .unwrap();
+            rocks.flush_cf(&db.cf()).unwrap();
+        }
+        rocks.compact_range_cf(&db.cf(), None::<&[u8]>, None::<&[u8]>);
+
+        assert_eq!(db.get(&42).unwrap(), Some("42".to_owned()));
+        assert_eq!(db.get(&400).unwrap(), None);
+        assert_eq!(db.iter().skip_to(&300).unwrap().count(), 100);
+    }
+}
+
+#[test]
+fn test_iter_rate_limited() {
+    let db: DBMap<u64, String> =
+        DBMap::open(temp_dir(), None, None).expect("Failed to open storage");
+    // 8 bytes of key and 100 bytes of encoded value per entry
+    db.multi_insert((0..100).map(|i| (i, "v".repeat(92))))
+        .unwrap();
+
+    let start = Instant::now();
+    let entries: Vec<_> = db.iter_rate_limited(54_000).collect();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(entries, db.iter().collect::<Vec<_>>());
+
+    // Seeks and reversal work as with `iter`
+    assert_eq!(
+        db.iter_rate_limited(u64::MAX)
+            .skip_to(&90)
+            .unwrap()
+            .map(|(k, _)| k)
+            .collect::<Vec<_>>(),
+        (90..100).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_multi_contains() {
+    let rocks = open_cf_opts(
+        temp_dir(),
+        None,
+        &[(
+            "table",
+            &options_with_filter(FilterPolicy::whole_key_bloom()),
+        )],
+    )
+    .unwrap();
+    let db = DBMap::<u64, String>::reopen(&rocks, Some("table")).unwrap();
+    db.multi_insert((0..100).step_by(2).map(|i| (i, i.to_string())))
+        .unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+    // Some keys are in the memtable, others in the SST file
+    db.insert(&101, &"101".to_owned()).unwrap();
+
+    let keys: Vec<u64> = (90..105).collect();
+    assert_eq!(
+        db.multi_contains(&keys).unwrap(),
+        keys.iter()
+            .map(|k| db.contains_key(k).unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        db.multi_contains([101, 3, 0]).unwrap(),
+        vec![true, false, true]
+    );
+    assert!(db
+        .multi_contains(std::iter::empty::<u64>())
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_notify_read() {
+    let db = DBMap::<u32, String>::open(temp_dir(), None, None).unwrap();
+    db.insert(&1, &"one".to_owned()).unwrap();
+
+    let reader = {
+        let db = db.clone();
+        tokio::spawn(async move { db.notify_read([1, 2, 3]).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!reader.is_finished());
+
+    db.insert(&3, &"three".to_owned()).unwrap();
+    db.batch()
+        .insert_batch(&db, [(2, "two".to_owned())])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(
+        reader.await.unwrap().unwrap(),
+        vec!["one".to_owned(), "two".to_owned(), "three".to_owned()]
+    );
+
+    // The keys already written are returned without waiting, and leave no waiter behind
+    assert_eq!(db.notify_read([3, 1]).await.unwrap(), vec!["three", "one"]);
+    assert!(db.watchers.is_empty());
+}
+
+#[test]
+fn test_iter_values_lazy() {
+    let db = DBMap::<(u32, u32), String>::open(temp_dir(), None, None).unwrap();
+    db.multi_insert((0..10).map(|i| ((i % 2, i), i.to_string())))
+        .unwrap();
+
+    let odd: Vec<_> = db
+        .iter()
+        .values_lazy()
+        .filter(|((parity, _), _)| *parity == 1)
+        .map(|(k, v)| (k, v.decode().unwrap()))
+        .collect();
+    assert_eq!(
+        odd,
+        db.iter()
+            .filter(|((parity, _), _)| *parity == 1)
+            .collect::<Vec<_>>()
+    );
+
+    let (key, value) = db
+        .iter()
+        .skip_to(&(1, 3))
+        .unwrap()
+        .values_lazy()
+        .next()
+        .unwrap();
+    assert_eq!(key, (1, 3));
+    assert_eq!(value.raw_bytes(), bincode::serialize("3").unwrap());
+    assert_eq!(value.decode().unwrap(), "3");
+
+    // Values are decoded with the codec of the table
+    let versioned = DBMap::<u32, String>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_value_codec(Arc::new(VersionedCodec::new(1)));
+    versioned.insert(&1, &"one".to_owned()).unwrap();
+    let (_, value) = versioned.iter().values_lazy().next().unwrap();
+    assert_eq!(
+        value.raw_bytes().len(),
+        std::mem::size_of::<ValueVersion>() + bincode::serialize("one").unwrap().len()
+    );
+    assert_eq!(value.decode().unwrap(), "one");
+}
+
+#[test]
+fn test_open_cf_mismatch_policy() {
+    let path = temp_dir();
+    let opts = default_rocksdb_options();
+    drop(open_cf_opts(&path, None, &[("old", &opts), ("new", &opts)]).unwrap());
+
+    match open_cf_opts_with_cf_policy(
+        &path,
+        None,
+        &[("old", &opts), ("newer", &opts)],
+        CfMismatchPolicy::ErrorOnUnknownCf,
+    ) {
+        Err(TypedStoreError::ColumnFamilyMismatch {
+            unknown, missing, ..
+        }) => {
+            assert_eq!(unknown, vec!["new".to_owned()]);
+            assert_eq!(missing, vec!["newer".to_owned()]);
+        }
+        other => panic!("Unexpected result {:?}", other.map(|_| ())),
+    }
+    // Nothing was created by the failed open
+    let mut tables = list_tables(path.clone()).unwrap();
+    tables.sort();
+    assert_eq!(tables, vec!["new", "old"]);
+
+    for policy in [CfMismatchPolicy::OpenWithDefaults, CfMismatchPolicy::Ignore] {
+        let rocks =
+            open_cf_opts_with_cf_policy(&path, None, &[("old", &opts), ("newer", &opts)], policy)
+                .unwrap();
+        assert!(rocks.cf_handle("new").is_some());
+        assert!(rocks.cf_handle("newer").is_some());
+    }
+
+    // Creating column families is not a mismatch
+    let rocks = open_cf_opts_with_cf_policy(
+        &path,
+        None,
+        &[
+            ("old", &opts),
+            ("new", &opts),
+            ("newer", &opts),
+            ("newest", &opts),
+        ],
+        CfMismatchPolicy::ErrorOnUnknownCf,
+    )
+    .unwrap();
+    assert!(rocks.cf_handle("newest").is_some());
+}
+
+#[test]
+fn test_timeseries_mock_clock() {
+    let clock = crate::testing::clock::MockClock::new(1_000_000);
+    let series = DBTimeSeries::<u32>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_retention(Duration::from_secs(60))
+        .with_mock_clock(clock.clone());
+    for i in 0..3 {
+        series.append_now(&i).unwrap();
+        clock.advance(Duration::from_secs(30));
+    }
+    assert_eq!(
+        series
+            .range(0..u64::MAX)
+            .map(|(ts, _)| ts)
+            .collect::<Vec<_>>(),
+        vec![1_000_000, 1_030_000, 1_060_000]
+    );
+
+    // 90 seconds after the first point, only the points of the last minute are kept
+    series.prune_expired().unwrap();
+    assert_eq!(
+        series
+            .range(0..u64::MAX)
+            .map(|(_, v)| v)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    // Expiry can also be forced at any time
+    series.prune_expired_at(u64::MAX).unwrap();
+    assert_eq!(series.range(0..u64::MAX).count(), 0);
+}
+
+#[test]
+fn test_session_reads_its_writes() {
+    let rocks = open_cf(temp_dir(), None, &["first", "second"]).unwrap();
+    let first = DBMap::<u32, String>::reopen(&rocks, Some("first")).unwrap();
+    let second = DBMap::<u32, u64>::reopen(&rocks, Some("second")).unwrap();
+    first.insert(&1, &"stored".to_owned()).unwrap();
+    first.insert(&2, &"removed".to_owned()).unwrap();
+
+    let mut session = first.session();
+    session.insert(&first, &1, &"updated".to_owned()).unwrap();
+    session.remove(&first, &2).unwrap();
+    session.insert(&second, &1, &10).unwrap();
+    assert_eq!(session.len(), 3);
+
+    // The session reads its writes, the tables are unchanged until committed
+    assert_eq!(session.get(&first, &1).unwrap(), Some("updated".to_owned()));
+    assert_eq!(session.get(&first, &2).unwrap(), None);
+    assert!(!session.contains_key(&first, &2).unwrap());
+    assert_eq!(session.get(&second, &1).unwrap(), Some(10));
+    assert_eq!(session.get(&second, &2).unwrap(), None);
+    assert_eq!(first.get(&1).unwrap(), Some("stored".to_owned()));
+    assert_eq!(second.get(&1).unwrap(), None);
+
+    // Keys not written by the session are read from the tables
+    first.insert(&3, &"concurrent".to_owned()).unwrap();
+    assert_eq!(
+        session.get(&first, &3).unwrap(),
+        Some("concurrent".to_owned())
+    );
+
+    session.commit().unwrap();
+    assert_eq!(first.get(&1).unwrap(), Some("updated".to_owned()));
+    assert!(!first.contains_key(&2).unwrap());
+    assert_eq!(second.get(&1).unwrap(), Some(10));
+
+    // Dropped sessions write nothing, and sessions can't span databases
+    let mut session = first.session();
+    session.insert(&first, &4, &"dropped".to_owned()).unwrap();
+    drop(session);
+    assert!(!first.contains_key(&4).unwrap());
+    let other = DBMap::<u32, String>::open(temp_dir(), None, None).unwrap();
+    assert!(matches!(
+        first.session().insert(&other, &1, &"other".to_owned()),
+        Err(TypedStoreError::CrossDBBatch)
+    ));
+}
+
+#[test]
+fn test_write_limit() {
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_write_limit(WriteLimit::OpsPerSec(10), ThrottlePolicy::Error);
+
+    // The bucket holds one second of writes, shared by the clones of the map
+    db.multi_insert((0..8).map(|i| (i, i))).unwrap();
+    db.clone().insert(&8, &8).unwrap();
+    db.insert(&9, &9).unwrap();
+    assert_eq!(
+        db.insert(&10, &10),
+        Err(TypedStoreError::WriteThrottled("default".to_owned()))
+    );
+    // Throttled batches write nothing
+    assert!(db.multi_insert([(11, 11), (12, 12)]).is_err());
+    assert!(!db.contains_key(&10).unwrap() && !db.contains_key(&11).unwrap());
+
+    // The writes which fail give their tokens back
+    let clock = crate::testing::clock::MockClock::new(0);
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_clock(Arc::new(clock))
+        .with_write_limit(WriteLimit::OpsPerSec(10), ThrottlePolicy::Error);
+    db.freeze().unwrap();
+    for _ in 0..3 {
+        assert!(db.multi_insert((0..8).map(|i| (i, i))).is_err());
+        assert!(db.insert(&8, &8).is_err());
+    }
+    db.unfreeze();
+    db.multi_insert((0..10).map(|i| (i, i))).unwrap();
+
+    // Writes beyond the limit wait for the bucket to refill
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_write_limit(WriteLimit::OpsPerSec(100), ThrottlePolicy::Wait);
+    let start = Instant::now();
+    db.multi_insert((0..120).map(|i| (i, i))).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(db.keys().count(), 120);
+
+    // Maps without a limit are not throttled
+    let db = DBMap::<u32, u32>::reopen(&db.rocksdb, None).unwrap();
+    db.multi_insert((0..1000).map(|i| (i, i))).unwrap();
+}
+
+#[test]
+fn test_split_table_by_key_ranges() {
+    let mut options = default_rocksdb_options();
+    options.create_if_missing(true);
+    options.create_missing_column_families(true);
+    options.set_disable_auto_compactions(true);
+    let rocks = open_cf(temp_dir(), Some(options), &["table", "low", "high"]).unwrap();
+    let table = DBMap::<u32, Vec<u8>>::reopen(&rocks, Some("table")).unwrap();
+    let low = DBMap::<u32, Vec<u8>>::reopen(&rocks, Some("low")).unwrap();
+    let high = DBMap::<u32, Vec<u8>>::reopen(&rocks, Some("high")).unwrap();
+
+    // Without SST files, the table can't be split
+    table
+        .multi_insert((0..100).map(|i| (i, vec![0; 128])))
+        .unwrap();
+    assert_eq!(table.split_ranges(2).unwrap(), vec![KeyRange::all()]);
+
+    // Four files of about the same size
+    rocks.flush_cf(&table.cf()).unwrap();
+    for chunk in 1..4 {
+        table
+            .multi_insert((chunk * 100..(chunk + 1) * 100).map(|i| (i, vec![0; 128])))
+            .unwrap();
+        rocks.flush_cf(&table.cf()).unwrap();
+    }
+    let ranges = table.split_ranges(2).unwrap();
+    assert_eq!(ranges.len(), 2);
+    assert_eq!(ranges[0].start, None);
+    assert_eq!(ranges[0].end, ranges[1].start);
+    assert_eq!(ranges[1].end, None);
+    let split_point = ranges[1].start.clone().unwrap();
+    assert!(
+        split_point == be_fix_int_ser(&200u32).unwrap()
+            || split_point == be_fix_int_ser(&300u32).unwrap()
+    );
+    assert!(table.split_ranges(1).unwrap().len() == 1);
+    assert!(table.split_ranges(100).unwrap().len() <= 4);
+
+    // The ranges are copied to their own tables
+    assert_eq!(
+        table.copy_range_to(&ranges[0], &low).unwrap()
+            + table.copy_range_to(&ranges[1], &high).unwrap(),
+        400
+    );
+    assert_eq!(low.keys().next(), Some(0));
+    assert_eq!(high.keys().last(), Some(399));
+    assert!(low.keys().chain(high.keys()).eq(table.keys()));
+    assert!(!low.contains_key(&399).unwrap() && !high.contains_key(&0).unwrap());
+}
+
+#[test]
+fn test_batch_labels() {
+    use events::*;
+
+    #[derive(Default)]
+    struct LabelListener {
+        writes: Mutex<Vec<BTreeMap<String, u64>>>,
+    }
+
+    impl DBEventListener for LabelListener {
+        fn on_labelled_writes_flushed(&self, db_name: &str, writes: &BTreeMap<String, u64>) {
+            assert_eq!(db_name, "test_labels_db");
+            self.writes.lock().unwrap().push(writes.clone());
+        }
+    }
+
+    let mut options = default_rocksdb_options();
+    options.enable_statistics();
+    let rocks = open_cf(temp_dir(), Some(options.clone()), &["table"]).unwrap();
+    let db = DBMap::<u32, u32>::reopen(&rocks, Some("table"))
+        .unwrap()
+        .with_db_name("test_labels_db");
+    let listener = Arc::new(LabelListener::default());
+    let mut poller =
+        DBEventPoller::new("test_labels_db".to_owned(), Some(options), listener.clone());
+
+    let stats = db
+        .batch()
+        .with_label("checkpoint commit")
+        .insert_batch(&db, [(1, 1), (2, 2)])
+        .unwrap()
+        .write()
+        .unwrap();
+    db.batch()
+        .insert_batch(&db, [(3, 3)])
+        .unwrap()
+        .write()
+        .unwrap();
+    let bytes = (stats.key_bytes + stats.value_bytes) as u64;
+    assert_eq!(
+        DBMetrics::get()
+            .op_metrics
+            .rocksdb_batch_label_bytes
+            .with_label_values(&["test_labels_db", "checkpoint commit"])
+            .get(),
+        bytes
+    );
+
+    // The labelled writes are reported with the next flush
+    poller.poll(&rocks).unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+    poller.poll(&rocks).unwrap();
+    assert_eq!(
+        *listener.writes.lock().unwrap(),
+        vec![BTreeMap::from([("checkpoint commit".to_owned(), bytes)])]
+    );
+    assert!(take_labelled_writes("test_labels_db").is_empty());
+}
+
+#[test]
+fn test_replace_table_contents() {
+    let path = temp_dir();
+    let rocks = open_cf(&path, None, &[SWAP_POINTERS_CF, "config"]).unwrap();
+    let config = SwappableMap::<String, u64>::reopen(&rocks, "config").unwrap();
+    assert_eq!(config.current_cf(), "config");
+    config
+        .read(|map| map.insert(&"old".to_owned(), &1))
+        .unwrap();
+
+    let written = config
+        .replace_table_contents((0..2000).map(|i| (format!("key{i}"), i)))
+        .unwrap();
+    assert_eq!(written, 2000);
+    assert_eq!(config.current_cf(), "config__swap_1");
+    assert!(!config.contains_key(&"old".to_owned()).unwrap());
+    assert_eq!(config.get(&"key1999".to_owned()).unwrap(), Some(1999));
+    assert!(rocks.cf_handle("config").is_none());
+
+    // An interrupted replacement is dropped when the table is reopened
+    config
+        .replace_table_contents([("new".to_owned(), 7)])
+        .unwrap();
+    rocks
+        .create_cf("config__swap_3", &default_rocksdb_options())
+        .unwrap();
+    drop(config);
+    drop(rocks);
+    let rocks = open_cf(&path, None, &[SWAP_POINTERS_CF]).unwrap();
+    let config = SwappableMap::<String, u64>::reopen(&rocks, "config").unwrap();
+    assert_eq!(config.current_cf(), "config__swap_2");
+    assert!(rocks.cf_handle("config__swap_3").is_none());
+    assert_eq!(
+        config.read(|map| map.iter().collect::<Vec<_>>()),
+        vec![("new".to_owned(), 7)]
+    );
+}
+
+#[test]
+fn test_replace_table_contents_keeps_table_options() {
+    let path = temp_dir();
+    let mut options = default_rocksdb_options();
+    options.set_write_buffer_size(3 << 20);
+    let rocks = open_cf_opts(
+        &path,
+        None,
+        &[
+            (SWAP_POINTERS_CF, &default_rocksdb_options()),
+            ("config", &options),
+        ],
+    )
+    .unwrap();
+    let config = SwappableMap::<String, u64>::reopen(&rocks, "config").unwrap();
+    config
+        .replace_table_contents([("new".to_owned(), 7)])
+        .unwrap();
+
+    // The new generation is created with the options of the table, not the default ones
+    let cf_options = options_summary::read_latest_cf_options(&path)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        cf_options["config__swap_1"]["write_buffer_size"],
+        (3 << 20).to_string()
+    );
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn test_archive_table() {
+    let db = DBMap::<u32, String>::open(temp_dir(), None, Some("table")).unwrap();
+    db.multi_insert((0..1000).map(|i| (i, format!("value {i}"))))
+        .unwrap();
+    let mut archive = vec![];
+    assert_eq!(db.archive(&mut archive).unwrap(), 1000);
+    // The entries are similar, and compress well
+    let archived_bytes: usize = db
+        .iter()
+        .map(|(_, value)| 8 + 4 + bincode::serialized_size(&value).unwrap() as usize)
+        .sum();
+    assert!(archive.len() < archived_bytes / 2);
+
+    let reader = TableArchiveReader::new(archive.as_slice()).unwrap();
+    assert_eq!(reader.table(), "table");
+    let entries: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(entries.len(), 1000);
+    for ((key, value), i) in entries.into_iter().zip(0u32..) {
+        assert_eq!(key, be_fix_int_ser(&i).unwrap());
+        assert_eq!(
+            bincode::deserialize::<String>(&value).unwrap(),
+            format!("value {i}")
+        );
+    }
+
+    // Other streams are rejected
+    let mut not_archive = vec![];
+    zstd::stream::copy_encode(&b"not an archive"[..], &mut not_archive, 0).unwrap();
+    assert!(matches!(
+        TableArchiveReader::new(not_archive.as_slice()),
+        Err(TypedStoreError::SerializationError(_))
+    ));
+}
+
+#[test]
+fn test_compatibility_check() {
+    let path = temp_dir();
+    let rocks = open_cf(&path, None, &["table"]).unwrap();
+    assert_eq!(check_compatibility(&rocks).unwrap(), DatabaseWriter::New);
+
+    record_compatibility(&rocks, &[VALUE_CODECS_FEATURE]).unwrap();
+    record_compatibility(&rocks, &[SECONDARY_INDEXES_FEATURE]).unwrap();
+    let record = check_compatibility(&rocks).unwrap().into_record().unwrap();
+    assert_eq!(record.format_version, TYPED_STORE_FORMAT_VERSION);
+    assert_eq!(record.rocksdb_format_version, ROCKSDB_FORMAT_VERSION);
+    assert_eq!(
+        record.features.into_iter().collect::<Vec<_>>(),
+        vec![SECONDARY_INDEXES_FEATURE, VALUE_CODECS_FEATURE]
+    );
+    // The record is kept out of the default column family, which a table may use
+    let default_table = DBMap::<Vec<u8>, Vec<u8>>::reopen(&rocks, None).unwrap();
+    assert!(default_table.is_empty());
+    // And its column family is not a table
+    assert!(find_orphan_cfs(&rocks, &["table"]).unwrap().is_empty());
+    drop(default_table);
+    drop(rocks);
+    let rocks = open_cf_opts_with_cf_policy(
+        &path,
+        None,
+        &[("table", &default_rocksdb_options())],
+        CfMismatchPolicy::ErrorOnUnknownCf,
+    )
+    .unwrap();
+    let record = check_compatibility(&rocks).unwrap().into_record().unwrap();
+    assert_eq!(record.features.len(), 2);
+
+    // Databases written by newer versions are rejected
+    let newer = |record: CompatibilityRecord| {
+        let cf = rocks.cf_handle(METADATA_CF).unwrap();
+        rocks
+            .put_cf(
+                &cf,
+                b"typed_store_compatibility",
+                bincode::serialize(&record).unwrap(),
+            )
+            .unwrap();
+        check_compatibility(&rocks)
+    };
+    assert!(matches!(
+        newer(CompatibilityRecord {
+            crate_version: "99.0.0".to_owned(),
+            format_version: TYPED_STORE_FORMAT_VERSION + 1,
+            ..Default::default()
+        }),
+        Err(TypedStoreError::IncompatibleDatabase { written_by, .. }) if written_by == "99.0.0"
+    ));
+    assert!(matches!(
+        newer(CompatibilityRecord {
+            features: ["time_travel".to_owned()].into_iter().collect(),
+            ..Default::default()
+        }),
+        Err(TypedStoreError::IncompatibleDatabase { .. })
+    ));
+    assert!(matches!(
+        newer(CompatibilityRecord {
+            rocksdb_format_version: ROCKSDB_FORMAT_VERSION + 1,
+            ..Default::default()
+        }),
+        Err(TypedStoreError::IncompatibleDatabase { reason, .. }) if reason.contains("RocksDB")
+    ));
+
+    // Older formats are upgraded, keeping their features
+    newer(CompatibilityRecord {
+        format_version: 0,
+        features: [VALUE_CODECS_FEATURE.to_owned()].into_iter().collect(),
+        ..Default::default()
+    })
+    .unwrap();
+    record_compatibility(&rocks, &[]).unwrap();
+    let record = check_compatibility(&rocks).unwrap().into_record().unwrap();
+    assert_eq!(record.format_version, TYPED_STORE_FORMAT_VERSION);
+    assert!(record.features.contains(VALUE_CODECS_FEATURE));
+
+    // The records written before the RocksDB format was recorded are still read
+    let cf = rocks.cf_handle(METADATA_CF).unwrap();
+    let legacy = (
+        "0.1.0".to_owned(),
+        TYPED_STORE_FORMAT_VERSION,
+        BTreeSet::from([VALUE_CODECS_FEATURE.to_owned()]),
+    );
+    rocks
+        .put_cf(
+            &cf,
+            b"typed_store_compatibility",
+            bincode::serialize(&legacy).unwrap(),
+        )
+        .unwrap();
+    let record = check_compatibility(&rocks).unwrap().into_record().unwrap();
+    assert_eq!(record.crate_version, "0.1.0");
+    assert_eq!(record.rocksdb_format_version, ROCKSDB_FORMAT_VERSION);
+}
+
+#[test]
+fn test_compatibility_unknown_writer() {
+    // A database with entries but without a record isn't assumed to be written by this version
+    let rocks = open_cf(temp_dir(), None, &["table"]).unwrap();
+    let db = DBMap::<u32, u32>::reopen(&rocks, Some("table")).unwrap();
+    db.insert(&1, &1).unwrap();
+    let writer = check_compatibility(&rocks).unwrap();
+    assert_eq!(writer, DatabaseWriter::Unknown);
+    assert_eq!(writer.into_record(), None);
+
+    // Until a writer records it
+    record_compatibility(&rocks, &[]).unwrap();
+    assert_eq!(
+        check_compatibility(&rocks).unwrap(),
+        DatabaseWriter::Recorded(CompatibilityRecord::default())
+    );
+}
+
+#[test]
+fn test_idempotent_batches() {
+    let rocks = open_cf(temp_dir(), None, &["table", APPLIED_BATCHES_CF]).unwrap();
+    let db = DBMap::<u32, u32>::reopen(&rocks, Some("table")).unwrap();
+    let apply = |value: u32| {
+        db.batch()
+            .with_idempotency_key(b"operation 1")
+            .insert_batch(&db, [(1, value)])
+            .unwrap()
+            .write()
+            .unwrap()
+    };
+
+    assert!(!is_batch_applied(&rocks, b"operation 1").unwrap());
+    let stats = apply(1);
+    assert!(!stats.already_applied);
+    assert!(is_batch_applied(&rocks, b"operation 1").unwrap());
+
+    // A retry is skipped, other keys are applied
+    db.insert(&1, &10).unwrap();
+    let stats = apply(2);
+    assert!(stats.already_applied);
+    assert_eq!(stats.entries, 0);
+    assert_eq!(db.get(&1).unwrap(), Some(10));
+    db.batch()
+        .with_idempotency_key(b"operation 2")
+        .insert_batch(&db, [(2, 2)])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(db.get(&2).unwrap(), Some(2));
+
+    // Pruned keys are applied again
+    assert_eq!(
+        prune_applied_batches(&rocks, Duration::from_secs(3600)).unwrap(),
+        0
+    );
+    std::thread::sleep(Duration::from_millis(2));
+    assert_eq!(prune_applied_batches(&rocks, Duration::ZERO).unwrap(), 2);
+    assert!(!apply(3).already_applied);
+    assert_eq!(db.get(&1).unwrap(), Some(3));
+
+    // The column family of the keys is required
+    let other = DBMap::<u32, u32>::open(temp_dir(), None, None).unwrap();
+    assert!(other
+        .batch()
+        .with_idempotency_key(b"operation 1")
+        .write()
+        .is_err());
+}
+
+#[test]
+fn test_durability_profiles() {
+    assert_eq!(DurabilityProfile::default(), DurabilityProfile::Balanced);
+    assert!(DurabilityProfile::Durable.sync_writes());
+    assert!(!DurabilityProfile::Balanced.sync_writes());
+    assert_eq!(DurabilityProfile::Throughput.bytes_per_sync(), 0);
+    assert_eq!(DurabilityProfile::Throughput.wal_bytes_per_sync(), 0);
+    assert!(DurabilityProfile::Balanced.wal_bytes_per_sync() > 0);
+
+    let mut options = default_rocksdb_options();
+    DurabilityProfile::Durable.apply_to_options(&mut options);
+    let rocks = open_cf_opts(
+        temp_dir(),
+        Some(options),
+        &[("table", &default_rocksdb_options())],
+    )
+    .unwrap();
+    let db = DBMap::<u32, String>::reopen(&rocks, Some("table"))
+        .unwrap()
+        .with_durability(DurabilityProfile::Durable);
+    db.insert(&1, &"one".to_string()).unwrap();
+    db.batch()
+        .insert_batch(&db, [(2, "two".to_string())])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(db.get(&1).unwrap(), Some("one".to_string()));
+    assert_eq!(db.get(&2).unwrap(), Some("two".to_string()));
+}
+
+#[test]
+fn test_read_amp_sampling() {
+    let rocks = open_cf_opts(temp_dir(), None, &[("table", &point_lookup_options())]).unwrap();
+    let db = DBMap::<u32, String>::reopen(&rocks, Some("table"))
+        .unwrap()
+        .with_db_name("test_read_amp_db")
+        .with_read_amp_sampling(0.5);
+    for i in 0..10 {
+        db.insert(&i, &i.to_string()).unwrap();
+    }
+    rocks.flush_cf(&db.cf()).unwrap();
+    for i in 0..10 {
+        assert_eq!(db.get(&i).unwrap(), Some(i.to_string()));
+    }
+
+    let op_metrics = &crate::metrics::DBMetrics::get().op_metrics;
+    let labels = ["test_read_amp_db", "table"];
+    let sst_files = op_metrics
+        .rocksdb_read_amp_sst_files
+        .get_metric_with_label_values(&labels)
+        .unwrap();
+    // One of every two gets is sampled, and each reads the single SST file of the table
+    assert_eq!(sst_files.get_sample_count(), 5);
+    assert_eq!(sst_files.get_sample_sum(), 5.0);
+    assert_eq!(
+        op_metrics
+            .rocksdb_read_amp_blocks_read
+            .get_metric_with_label_values(&labels)
+            .unwrap()
+            .get_sample_count(),
+        5
+    );
+}
+
+#[test]
+fn test_read_amp_sampling_without_filters() {
+    use rocksdb::perf::{PerfContext, PerfMetric, PerfStatsLevel};
+
+    let rocks = open_cf(temp_dir(), None, &["table"]).unwrap();
+    let db = DBMap::<u32, String>::reopen(&rocks, Some("table"))
+        .unwrap()
+        .with_db_name("test_read_amp_unfiltered_db")
+        .with_read_amp_sampling(1.0);
+    db.insert(&1, &"one".to_owned()).unwrap();
+    rocks.flush_cf(&db.cf()).unwrap();
+
+    // The perf context of the thread keeps counting through the sampled gets
+    set_perf_level(PerfStatsLevel::EnableCount);
+    let mut context = PerfContext::default();
+    context.reset();
+    rocks
+        .get_cf(&db.cf(), be_fix_int_ser(&1u32).unwrap())
+        .unwrap();
+    let read_bytes = context.metric(PerfMetric::GetReadBytes);
+    assert!(read_bytes > 0);
+    assert_eq!(db.get(&1).unwrap(), Some("one".to_owned()));
+    assert_eq!(context.metric(PerfMetric::GetReadBytes), 2 * read_bytes);
+
+    // The file of the table is read without filters, and is counted
+    let sst_files = crate::metrics::DBMetrics::get()
+        .op_metrics
+        .rocksdb_read_amp_sst_files
+        .get_metric_with_label_values(&["test_read_amp_unfiltered_db", "table"])
+        .unwrap();
+    assert_eq!(sst_files.get_sample_count(), 1);
+    assert_eq!(sst_files.get_sample_sum(), 1.0);
+}
+
+#[test]
+fn test_open_progress() {
+    let path = temp_dir();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    set_open_progress_callback(Some(Arc::new(move |progress: &OpenProgress| {
+        recorded.lock().unwrap().push(progress.clone())
+    })));
+    let _rocks = open_cf(&path, None, &["second", "first"]).unwrap();
+    set_open_progress_callback(None);
+
+    // Other databases may be opened concurrently by the other tests
+    let events: Vec<_> = events
+        .lock()
+        .unwrap()
+        .drain(..)
+        .filter(|progress| match progress {
+            OpenProgress::OpeningDatabase { path: p, .. }
+            | OpenProgress::DatabaseOpened { path: p, .. } => *p == path,
+            OpenProgress::TableOpened { .. } => false,
+        })
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        events[0],
+        OpenProgress::OpeningDatabase {
+            path: path.clone(),
+            column_families: vec!["first".to_owned(), "second".to_owned()],
+        }
+    );
+    assert!(matches!(
+        events[1],
+        OpenProgress::DatabaseOpened {
+            column_families: 2,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_merge_iter() {
+    let shards: Vec<_> = (0..3)
+        .map(|_| DBMap::<i64, String>::open(temp_dir(), None, None).unwrap())
+        .collect();
+    // Negative keys sort after the positive ones in the tables
+    for (shard, keys) in shards.iter().zip([vec![1, 4, -1], vec![2, 4], vec![]]) {
+        for key in keys {
+            shard.insert(&key, &key.to_string()).unwrap();
+        }
+    }
+
+    let merged: Vec<_> = merge_iter(
+        shards
+            .iter()
+            .enumerate()
+            .map(|(epoch, shard)| (epoch, shard.iter())),
+    )
+    .map(|(epoch, key, value)| {
+        assert_eq!(key.to_string(), value);
+        (epoch, key)
+    })
+    .collect();
+    assert_eq!(merged, vec![(0, 1), (1, 2), (0, 4), (1, 4), (0, -1)]);
+
+    assert_eq!(
+        merge_iter(Vec::<(usize, Iter<'_, i64, String>)>::new()).count(),
+        0
+    );
+}
+
+#[test]
+fn test_logical_deletes() {
+    let map = DBLogicalDeleteMap::<u64, String>::open(temp_dir(), None, None).unwrap();
+    for key in 0..10 {
+        map.insert(&key, &key.to_string()).unwrap();
+    }
+    map.remove(&3).unwrap();
+    map.multi_remove([5u64, 7]).unwrap();
+
+    assert_eq!(map.get(&3).unwrap(), None);
+    assert!(!map.contains_key(&5).unwrap());
+    assert_eq!(
+        map.multi_get([6u64, 7]).unwrap(),
+        vec![Some("6".to_owned()), None]
+    );
+    assert_eq!(map.keys().collect::<Vec<_>>(), vec![0, 1, 2, 4, 6, 8, 9]);
+    assert_eq!(map.tombstones(), 3);
+
+    // A key can be inserted again after its removal
+    map.insert(&5, &"5".to_owned()).unwrap();
+    assert_eq!(map.get(&5).unwrap(), Some("5".to_owned()));
+    assert_eq!(map.tombstones(), 2);
+
+    // The tombstones of the previous generations are dropped by the compaction
+    let generation = map.logical_deletes().generation();
+    assert_eq!(map.compact_tombstones().unwrap(), generation);
+    map.remove(&8).unwrap();
+    assert_eq!(map.tombstones(), 1);
+    assert_eq!(
+        map.iter().collect::<Vec<_>>(),
+        [0, 1, 2, 4, 5, 6, 9]
+            .into_iter()
+            .map(|key| (key, key.to_string()))
+            .collect::<Vec<_>>()
+    );
+    map.compact_tombstones().unwrap();
+    assert_eq!(map.tombstones(), 0);
+    assert_eq!(map.get(&8).unwrap(), None);
+}
+
+#[test]
+fn test_scan_filtered() {
+    let db = DBMap::<u64, String>::open(temp_dir(), None, None).unwrap();
+    for key in 0..20 {
+        db.insert(&key, &key.to_string()).unwrap();
+    }
+    let even = |key: &u64, _: &[u8]| key % 2 == 0;
+
+    fn keys(
+        iter: Result<impl Iterator<Item = Result<(u64, String), TypedStoreError>>, TypedStoreError>,
+    ) -> Vec<u64> {
+        iter.unwrap().map(|entry| entry.unwrap().0).collect()
+    }
+    assert_eq!(keys(db.scan_filtered(4..10, even)), vec![4, 6, 8]);
+    assert_eq!(keys(db.scan_filtered(4..=10, even)), vec![4, 6, 8, 10]);
+    assert_eq!(keys(db.scan_filtered(15.., even)), vec![16, 18]);
+    assert_eq!(
+        keys(db.scan_filtered((Bound::Excluded(4), Bound::Included(8)), even)),
+        vec![6, 8]
+    );
+    assert_eq!(keys(db.scan_filtered(.., |_, _| false)), Vec::<u64>::new());
+
+    // The predicate sees the serialized values, e.g. the bytes of the strings
+    let entries: Vec<_> = db
+        .scan_filtered(.., |_, value| value.ends_with(b"7"))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries, vec![(7, "7".to_owned()), (17, "17".to_owned())]);
+
+    // The values which fail to decode are returned as errors, without stopping the scan
+    db.rocksdb
+        .put_cf(&db.cf(), be_fix_int_ser(&5u64).unwrap(), [0xff])
+        .unwrap();
+    let entries: Vec<_> = db.scan_filtered(4..7, |_, _| true).unwrap().collect();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].as_ref().unwrap(), &(4, "4".to_owned()));
+    assert!(entries[1].is_err());
+    assert_eq!(entries[2].as_ref().unwrap(), &(6, "6".to_owned()));
+    // Unless they are rejected by the predicate
+    assert_eq!(keys(db.scan_filtered(4..7, even)), vec![4, 6]);
+}
+
+#[test]
+fn test_prefix_stats() {
+    let db = DBMap::<(u64, u64), String>::open(temp_dir(), None, None).unwrap();
+    // Epoch 2 has the most entries, epoch 1 the largest values
+    for (epoch, count, value_len) in [(0u64, 10u64, 1), (1, 5, 100), (2, 20, 2)] {
+        for seq in 0..count {
+            db.insert(&(epoch, seq), &"x".repeat(value_len)).unwrap();
+        }
+    }
+
+    let stats = db
+        .prefix_stats(SizeAnalysisOptions::full(), |(epoch, _)| *epoch)
+        .unwrap();
+    assert_eq!(stats.entries_analyzed, 35);
+    assert_eq!(stats.prefixes[&2].entries, 20);
+    let top: Vec<_> = stats
+        .top_prefixes(2)
+        .into_iter()
+        .map(|(epoch, _)| *epoch)
+        .collect();
+    assert_eq!(top, vec![1, 2]);
+
+    // The same grouping on the 8 bytes of the encoded epoch
+    let byte_stats = db
+        .byte_prefix_stats(8, SizeAnalysisOptions::full())
+        .unwrap();
+    assert_eq!(
+        byte_stats.prefixes[&1u64.to_be_bytes().to_vec()],
+        stats.prefixes[&1]
+    );
+    assert_eq!(
+        db.size_report(SizeAnalysisOptions::full().with_prefix_len(8))
+            .unwrap()
+            .prefixes,
+        byte_stats.prefixes
+    );
+
+    // A sampled scan extrapolates the counts to the whole table
+    let sampled = db
+        .prefix_stats(SizeAnalysisOptions::sampled(5), |(epoch, _)| *epoch)
+        .unwrap();
+    assert_eq!(sampled.entries_analyzed, 7);
+    assert_eq!(sampled.estimated_usage(&2).entries, 20);
+    assert_eq!(sampled.estimated_usage(&3), PrefixUsage::default());
+}
+
+#[test]
+fn test_update_batch() {
+    let db = DBMap::<i32, u64>::open(temp_dir(), None, None).unwrap();
+    db.multi_insert([(1, 10), (2, 20)]).unwrap();
+
+    // Increments the existing counters, creates the missing one, and removes the one at 20
+    db.update_batch([1, 2, 3], |_, value| match value {
+        Some(20) => None,
+        Some(value) => Some(value + 1),
+        None => Some(0),
+    })
+    .unwrap();
+    assert_eq!(db.iter().collect::<Vec<_>>(), vec![(1, 11), (3, 0)]);
+
+    // The locked updates of the same keys don't lose increments
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    db.update_batch_locked([1, 3], |_, value| Some(value.unwrap_or(0) + 1))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(db.multi_get([1, 3]).unwrap(), vec![Some(411), Some(400)]);
+}
+
+#[tokio::test]
+async fn test_injected_clock() {
+    let clock = crate::testing::clock::MockClock::new(1_000_000);
+    let db = DBMap::<u32, u32>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()))
+        .with_write_limit(WriteLimit::OpsPerSec(100), ThrottlePolicy::Wait);
+
+    // The throttled writes sleep on the mock clock, which moves them forward without waiting
+    let start = Instant::now();
+    db.multi_insert((0..120).map(|i| (i, i))).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(150));
+    assert!(clock.now_millis() >= 1_000_150);
+
+    // The commit latency of the batches is measured on the clock as well
+    let stats = db
+        .batch()
+        .insert_batch(&db, [(200, 200)])
+        .unwrap()
+        .write()
+        .unwrap();
+    assert_eq!(stats.commit_latency, Duration::ZERO);
+
+    // So are the rate limited iterators, with 8 bytes per entry
+    let before = clock.now_millis();
+    let start = Instant::now();
+    assert_eq!(db.iter_rate_limited(100).count(), 121);
+    assert!(start.elapsed() < Duration::from_millis(150));
+    assert!(clock.now_millis() >= before + 9_000);
+
+    // And the ages of the idempotency keys
+    let rocks = open_cf(temp_dir(), None, &["table", APPLIED_BATCHES_CF]).unwrap();
+    let table = DBMap::<u32, u32>::reopen(&rocks, Some("table"))
+        .unwrap()
+        .with_clock(Arc::new(clock.clone()));
+    table
+        .batch()
+        .with_idempotency_key(b"operation")
+        .insert_batch(&table, [(1, 1)])
+        .unwrap()
+        .write()
+        .unwrap();
+    let max_age = Duration::from_secs(60);
+    assert_eq!(
+        prune_applied_batches_with_clock(&rocks, max_age, &clock).unwrap(),
+        0
+    );
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(
+        prune_applied_batches_with_clock(&rocks, max_age, &clock).unwrap(),
+        1
+    );
+
+    // The async sleeps wait for the clock to be moved past their deadline
+    let mut sleep = tokio::spawn(Clock::sleep_async(&clock, Duration::from_secs(10)));
+    clock.advance(Duration::from_secs(5));
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut sleep)
+        .await
+        .is_err());
+    clock.advance(Duration::from_secs(5));
+    sleep.await.unwrap();
+}
+
+#[test]
+fn test_validate_options() {
+    let path = temp_dir();
+    let mut prefixed = default_rocksdb_options();
+    prefixed.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(8));
+    let mut merged = default_rocksdb_options();
+    merged.set_merge_operator_associative("concat", |_, existing, operands| {
+        let mut value = existing.map(|v| v.to_vec()).unwrap_or_default();
+        operands
+            .iter()
+            .for_each(|operand| value.extend_from_slice(operand));
+        Some(value)
+    });
+    let plain = default_rocksdb_options();
+    drop(open_cf_opts(&path, None, &[("prefixed", &prefixed), ("merged", &merged)]).unwrap());
+
+    // A database which doesn't exist yet, and unchanged or new tables, are valid
+    validate_options(temp_dir().join("missing"), &[("prefixed", &plain)]).unwrap();
+    validate_options(
+        &path,
+        &[
+            ("prefixed", &prefixed),
+            ("merged", &merged),
+            ("new", &plain),
+        ],
+    )
+    .unwrap();
+
+    // The prefix extractor can be changed or removed
+    validate_options(&path, &[("prefixed", &plain)]).unwrap();
+    let mut reprefixed = default_rocksdb_options();
+    reprefixed.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(4));
+    validate_options(&path, &[("prefixed", &reprefixed)]).unwrap();
+
+    let mut reversed = default_rocksdb_options();
+    reversed.set_comparator("reversed", |a, b| b.cmp(a));
+    assert!(matches!(
+        validate_options(&path, &[("prefixed", &reversed)]),
+        Err(TypedStoreError::IncompatibleOptions { table, option, existing, .. })
+            if table == "prefixed" && option == "comparator" && existing == "leveldb.BytewiseComparator"
+    ));
+    assert!(matches!(
+        validate_options(&path, &[("merged", &plain)]),
+        Err(TypedStoreError::IncompatibleOptions { option, requested, .. })
+            if option == "merge_operator" && requested == "nullptr"
+    ));
+    // Adding a merge operator is valid
+    let mut prefixed_merged = prefixed.clone();
+    prefixed_merged.set_merge_operator_associative("concat", |_, _, _| None);
+    validate_options(&path, &[("prefixed", &prefixed_merged)]).unwrap();
+}
+
+#[test]
+fn test_scan_checkpoint() {
+    let rocks = open_cf(temp_dir(), None, &["objects", "other", "checkpoints"]).unwrap();
+    let objects = DBMap::<u64, String>::reopen(&rocks, Some("objects")).unwrap();
+    let checkpoints =
+        DBMap::<String, ScanCheckpoint<u64>>::reopen(&rocks, Some("checkpoints")).unwrap();
+    objects
+        .multi_insert((0..10).map(|i| (i, i.to_string())))
+        .unwrap();
+
+    let mut checkpoint = ScanCheckpoint::start(&objects);
+    for (key, _) in checkpoint.resume(&objects).unwrap().take(5) {
+        checkpoint.advance(key);
+    }
+    checkpoints.insert(&"job".to_owned(), &checkpoint).unwrap();
+    let checkpoint = checkpoints.get(&"job".to_owned()).unwrap().unwrap();
+    assert_eq!((checkpoint.last_key, checkpoint.entries), (Some(4), 5));
+
+    // The scan resumes after the last key, even once it is removed
+    objects.remove(&4).unwrap();
+    let remaining: Vec<_> = checkpoint
+        .resume(&objects)
+        .unwrap()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(remaining, vec![5, 6, 7, 8, 9]);
+
+    // A checkpoint only resumes the scans of its table
+    let other = DBMap::<u64, String>::reopen(&rocks, Some("other")).unwrap();
+    assert!(checkpoint.resume(&other).is_err());
+}
+
+#[test]
+fn test_retain() {
+    let db = DBMap::<u64, String>::open(temp_dir(), None, None).unwrap();
+    db.multi_insert((0..1000).map(|i| (i, i.to_string())))
+        .unwrap();
+
+    // Keep the multiples of 10 and the keys from 500, leaving a run of deleted keys before 500
+    let chunks = Arc::new(Mutex::new(Vec::new()));
+    let reported = chunks.clone();
+    let options = RetainOptions {
+        chunk_size: 300,
+        ..Default::default()
+    }
+    .with_range_deletes(50)
+    .with_progress(move |progress| reported.lock().unwrap().push(*progress));
+    let progress = db
+        .retain_with(options, |key, value| {
+            assert_eq!(value, &key.to_string());
+            *key >= 500 || key % 10 == 0
+        })
+        .unwrap();
+    assert_eq!(progress.chunks, 4);
+    assert_eq!(progress.scanned, 1000);
+    assert_eq!(progress.deleted, 450);
+    assert_eq!(progress.range_deletes, 0);
+    assert_eq!(chunks.lock().unwrap().last(), Some(&progress));
+
+    let keys: Vec<_> = db.keys().collect();
+    assert_eq!(keys.len(), 550);
+    assert!(keys.iter().all(|key| *key >= 500 || key % 10 == 0));
+
+    // By default, even a long run of deleted keys is written as point deletes
+    let progress = db.retain(|key, _| *key < 100 || *key >= 900).unwrap();
+    assert_eq!(progress.deleted, 440);
+    assert_eq!(progress.range_deletes, 0);
+    assert_eq!(db.keys().count(), 110);
+
+    // unless range deletes are enabled
+    let options = RetainOptions::default().with_range_deletes(50);
+    let progress = db
+        .retain_with(options, |key, _| *key < 10 || *key >= 950)
+        .unwrap();
+    assert_eq!(progress.deleted, 59);
+    assert_eq!(progress.range_deletes, 1);
+    assert_eq!(db.keys().count(), 51);
+    assert!(db.contains_key(&0).unwrap() && db.contains_key(&950).unwrap());
+}
+
+#[test]
+fn test_freeze_table() {
+    let rocks = open_cf(temp_dir(), None, &["frozen", "other"]).unwrap();
+    let frozen = DBMap::<u64, String>::reopen(&rocks, Some("frozen")).unwrap();
+    let other = DBMap::<u64, String>::reopen(&rocks, Some("other")).unwrap();
+    frozen.insert(&1, &"1".to_owned()).unwrap();
+    let pending = frozen
+        .batch()
+        .insert_batch(&frozen, [(2, "2".to_owned())])
+        .unwrap();
+
+    assert!(freeze_table(&rocks, "frozen").unwrap());
+    assert!(!frozen.freeze().unwrap());
+    assert!(frozen.is_frozen() && !other.is_frozen());
+    assert_eq!(frozen_tables(&rocks), vec!["frozen".to_owned()]);
+    assert!(matches!(
+        freeze_table(&rocks, "missing"),
+        Err(TypedStoreError::UnregisteredColumn(_))
+    ));
+
+    // The writes to the table are rejected, through any map and in batches built before the freeze
+    let rejected = TypedStoreError::TableFrozen("frozen".to_owned());
+    assert_eq!(frozen.insert(&3, &"3".to_owned()), Err(rejected.clone()));
+    assert_eq!(frozen.remove(&1), Err(rejected.clone()));
+    let reopened = DBMap::<u64, String>::reopen(&rocks, Some("frozen")).unwrap();
+    assert_eq!(reopened.clear(), Err(rejected.clone()));
+    assert_eq!(pending.write().map(|_| ()), Err(rejected.clone()));
+    let batch = other
+        .batch()
+        .insert_batch(&other, [(1, "1".to_owned())])
+        .unwrap()
+        .delete_batch(&frozen, [1])
+        .unwrap();
+    assert_eq!(batch.write().map(|_| ()), Err(rejected));
+    assert!(other.is_empty());
+
+    // while the reads continue
+    assert_eq!(frozen.get(&1).unwrap(), Some("1".to_owned()));
+    assert_eq!(frozen.keys().collect::<Vec<_>>(), vec![1]);
+    other.insert(&1, &"1".to_owned()).unwrap();
+
+    assert!(frozen.unfreeze());
+    assert!(!unfreeze_table(&rocks, "frozen"));
+    frozen.insert(&3, &"3".to_owned()).unwrap();
+    assert_eq!(frozen.keys().collect::<Vec<_>>(), vec![1, 3]);
+}
+
+#[test]
+fn test_freeze_dbset() {
+    let rocks = open_cf(temp_dir(), None, &["set"]).unwrap();
+    let set = DBSet::<u32>::reopen(&rocks, Some("set")).unwrap();
+    set.insert(&1).unwrap();
+    freeze_table(&rocks, "set").unwrap();
+
+    let rejected = TypedStoreError::TableFrozen("set".to_owned());
+    assert_eq!(set.insert(&2), Err(rejected.clone()));
+    assert_eq!(set.remove(&1), Err(rejected.clone()));
+    assert_eq!(set.multi_insert([2, 3]), Err(rejected.clone()));
+    assert_eq!(set.multi_remove([1]), Err(rejected));
+    assert_eq!(set.iter().collect::<Vec<_>>(), vec![1]);
+
+    unfreeze_table(&rocks, "set");
+    set.multi_insert([2, 3]).unwrap();
+    assert_eq!(set.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn test_freeze_journal_replay() {
+    let source = open_cf(temp_dir(), None, &["journal"]).unwrap();
+    let target = open_cf(temp_dir(), None, &["data"]).unwrap();
+    let target_data = DBMap::<i32, String>::reopen(&target, Some("data")).unwrap();
+    let journal_map = DBMap::<u64, JournalIntent>::reopen(&source, Some("journal")).unwrap();
+    let intent = JournalIntent::new()
+        .insert_batch(&target_data, [(1, "1".to_string())])
+        .unwrap();
+    journal_map.insert(&1, &intent).unwrap();
+    freeze_table(&target, "data").unwrap();
+
+    // The intent stays pending until the table is unfrozen
+    let journal = CrossDBJournal::new(journal_map, &target).unwrap();
+    assert_eq!(
+        journal.recover(),
+        Err(TypedStoreError::TableFrozen("data".to_owned()))
+    );
+    assert_eq!(journal.pending(), 1);
+    assert!(target_data.is_empty());
+
+    unfreeze_table(&target, "data");
+    assert_eq!(journal.recover().unwrap(), 1);
+    assert_eq!(target_data.get(&1).unwrap(), Some("1".to_string()));
+}
+
+#[test]
+fn test_freeze_split_copy() {
+    let rocks = open_cf(temp_dir(), None, &["table", "copy"]).unwrap();
+    let table = DBMap::<u32, u32>::reopen(&rocks, Some("table")).unwrap();
+    let copy = DBMap::<u32, u32>::reopen(&rocks, Some("copy")).unwrap();
+    table.multi_insert((0..10).map(|i| (i, i))).unwrap();
+    freeze_table(&rocks, "copy").unwrap();
+
+    assert_eq!(
+        table.copy_range_to(&KeyRange::all(), &copy),
+        Err(TypedStoreError::TableFrozen("copy".to_owned()))
+    );
+    assert!(copy.is_empty());
+
+    unfreeze_table(&rocks, "copy");
+    assert_eq!(table.copy_range_to(&KeyRange::all(), &copy).unwrap(), 10);
+}
+
+#[test]
+fn test_freeze_swap_pointer() {
+    let rocks = open_cf(temp_dir(), None, &[SWAP_POINTERS_CF, "config"]).unwrap();
+    let config = SwappableMap::<String, u64>::reopen(&rocks, "config").unwrap();
+    config
+        .read(|map| map.insert(&"old".to_owned(), &1))
+        .unwrap();
+    freeze_table(&rocks, "config").unwrap();
+
+    // The contents of a frozen table can't be replaced
+    assert_eq!(
+        config.replace_table_contents([("new".to_owned(), 2)]),
+        Err(TypedStoreError::TableFrozen("config".to_owned()))
+    );
+    assert_eq!(config.current_cf(), "config");
+    assert_eq!(config.get(&"old".to_owned()).unwrap(), Some(1));
+
+    unfreeze_table(&rocks, "config");
+    freeze_table(&rocks, SWAP_POINTERS_CF).unwrap();
+    assert_eq!(
+        config.replace_table_contents([("new".to_owned(), 2)]),
+        Err(TypedStoreError::TableFrozen(SWAP_POINTERS_CF.to_owned()))
+    );
+    assert_eq!(config.current_cf(), "config");
+}
+
+#[test]
+fn test_freeze_idempotency_keys() {
+    let rocks = open_cf(temp_dir(), None, &["table", APPLIED_BATCHES_CF]).unwrap();
+    let db = DBMap::<u32, u32>::reopen(&rocks, Some("table")).unwrap();
+    db.batch()
+        .with_idempotency_key(b"operation 1")
+        .insert_batch(&db, [(1, 1)])
+        .unwrap()
+        .write()
+        .unwrap();
+    freeze_table(&rocks, APPLIED_BATCHES_CF).unwrap();
+
+    // Neither recorded nor pruned
+    let rejected = TypedStoreError::TableFrozen(APPLIED_BATCHES_CF.to_owned());
+    let write = db
+        .batch()
+        .with_idempotency_key(b"operation 2")
+        .insert_batch(&db, [(2, 2)])
+        .unwrap()
+        .write();
+    assert_eq!(write.map(|_| ()), Err(rejected.clone()));
+    assert!(!db.contains_key(&2).unwrap());
+    assert_eq!(prune_applied_batches(&rocks, Duration::ZERO), Err(rejected));
+    assert!(is_batch_applied(&rocks, b"operation 1").unwrap());
+}
+
+#[test]
+fn test_insert_sorted_batch() {
+    let db = DBMap::<u64, String>::open(temp_dir(), None, None).unwrap();
+    db.insert(&5, &"old".to_owned()).unwrap();
+
+    let stats = db
+        .insert_sorted_batch((0..100u64).map(|i| (i, i.to_string())))
+        .unwrap();
+    assert_eq!(stats.entries, 100);
+    assert_eq!(db.keys().count(), 100);
+    assert_eq!(db.get(&5).unwrap(), Some("5".to_owned()));
+    assert_eq!(db.get(&99).unwrap(), Some("99".to_owned()));
+
+    // Unsorted and duplicated keys are rejected, without loading anything
+    let unsorted = [(200u64, "200"), (300, "300"), (250, "250")];
+    assert_eq!(
+        db.insert_sorted_batch(unsorted.iter().map(|(k, v)| (*k, v.to_string()))),
+        Err(TypedStoreError::UnsortedKeys {
+            table: "default".to_owned(),
+            position: 2,
+        })
+    );
+    let duplicated = [(200u64, "200"), (200, "200")];
+    assert!(db
+        .insert_sorted_batch(duplicated.iter().map(|(k, v)| (*k, v.to_string())))
+        .is_err());
+    assert!(!db.contains_key(&200).unwrap());
+
+    assert_eq!(
+        db.insert_sorted_batch(std::iter::empty::<(u64, String)>())
+            .unwrap()
+            .entries,
+        0
+    );
+    // The files written for the ingestions are removed, whether they were ingested or not
+    let leftovers = std::fs::read_dir(db.rocksdb.path())
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with("typed-store-ingest")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    // The files are written with the options of the table, e.g. its prefix extractor
+    let mut options = default_rocksdb_options();
+    options.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(4));
+    let rocks = open_cf_opts(temp_dir(), None, &[("prefixed", &options)]).unwrap();
+    let prefixed = DBMap::<u64, u64>::reopen(&rocks, Some("prefixed")).unwrap();
+    prefixed
+        .insert_sorted_batch((0..100u64).map(|i| (i << 32, i)))
+        .unwrap();
+    assert_eq!(prefixed.get(&(42 << 32)).unwrap(), Some(42));
+    assert_eq!(prefixed.keys().count(), 100);
+}
+
+#[test]
+fn test_table_lifecycle_events() {
+    let rocks = open_cf(temp_dir(), None, &["lifecycle", "orphan"]).unwrap();
+    let db = DBMap::<u64, String>::reopen(&rocks, Some("lifecycle")).unwrap();
+    let events = |cf_name: &str, event: TableLifecycleEvent| {
+        let metrics = &crate::metrics::DBMetrics::get().lifecycle_metrics;
+        let labels = [db.db_name(), cf_name, event.name()];
+        (
+            metrics
+                .rocksdb_table_lifecycle_events
+                .with_label_values(&labels)
+                .get(),
+            metrics
+                .rocksdb_table_lifecycle_size_bytes
+                .with_label_values(&labels)
+                .get(),
+        )
+    };
+    assert_eq!(events("lifecycle", TableLifecycleEvent::Opened).0, 1);
+
+    for i in 0..100u64 {
+        db.insert(&i, &i.to_string()).unwrap();
+    }
+    db.flush().unwrap();
+    let (flushes, flushed_size) = events("lifecycle", TableLifecycleEvent::Flushed);
+    assert_eq!(flushes, 1);
+    assert!(flushed_size > 0);
+    db.compact().unwrap();
+    assert_eq!(events("lifecycle", TableLifecycleEvent::Compacted).0, 1);
+
+    assert_eq!(
+        drop_orphan_cfs(&rocks, &["lifecycle"], &["orphan"]).unwrap(),
+        vec!["orphan".to_owned()]
+    );
+    assert_eq!(events("orphan", TableLifecycleEvent::Dropped).0, 1);
+}
+
+#[test]
+fn test_table_lifecycle_events_db_name() {
+    let path = temp_dir();
+    set_db_name(&path, "lifecycle_db");
+    let rocks = open_cf(&path, None, &["table"]).unwrap();
+    let db = DBMap::<u64, String>::reopen(&rocks, Some("table")).unwrap();
+    assert_eq!(db.db_name(), "lifecycle_db");
+    let events = |event: TableLifecycleEvent| {
+        crate::metrics::DBMetrics::get()
+            .lifecycle_metrics
+            .rocksdb_table_lifecycle_events
+            .with_label_values(&["lifecycle_db", "table", event.name()])
+            .get()
+    };
+    assert_eq!(events(TableLifecycleEvent::Opened), 1);
+
+    // The flushes of the idle tables are labelled like those of the map
+    db.insert(&1, &"1".to_owned()).unwrap();
+    let mut flusher = AutoFlusher::new(&["table"]);
+    assert!(flusher.flush_idle_tables(&rocks).unwrap().is_empty());
+    assert_eq!(flusher.flush_idle_tables(&rocks).unwrap(), vec!["table"]);
+    assert_eq!(events(TableLifecycleEvent::Flushed), 1);
+
+    db.clear().unwrap();
+    assert_eq!(events(TableLifecycleEvent::Dropped), 1);
+}
+
+#[test]
+fn test_iter_memory_cap() {
+    let db = DBMap::<u64, String>::open(temp_dir(), None, None).unwrap();
+    db.batch()
+        .insert_batch(&db, (0..100u64).map(|i| (i, "x".repeat(100))))
+        .unwrap()
+        .write()
+        .unwrap();
+    let entry_size = 8 + 8 + 100 + std::mem::size_of::<(u64, String)>();
+
+    // Under the cap, the entries are collected as without it
+    let entries: Vec<_> = db
+        .iter()
+        .memory_capped(100 * entry_size)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 100);
+
+    let mut capped = db.iter().memory_capped(10 * entry_size);
+    assert_eq!(capped.by_ref().take(10).filter(Result::is_ok).count(), 10);
+    assert_eq!(capped.held_bytes(), 10 * entry_size);
+    assert_eq!(
+        capped.next(),
+        Some(Err(TypedStoreError::IteratorMemoryCapExceeded {
+            table: "default".to_owned(),
+            cap: 10 * entry_size,
+            entries: 11,
+        }))
+    );
+    assert!(capped.next().is_none());
+
+    let reversed: Result<Vec<_>, _> = db
+        .iter()
+        .skip_to_last()
+        .reverse()
+        .memory_capped(entry_size)
+        .collect();
+    assert!(matches!(
+        reversed,
+        Err(TypedStoreError::IteratorMemoryCapExceeded { entries: 2, .. })
+    ));
+}
+
+/// Stores the values as runs of repeated bytes, (length, byte) pairs
+#[derive(Debug)]
+struct RunLengthCodec;
+
+impl ValueCodec for RunLengthCodec {
+    fn encode(&self, _key: &[u8], value: Vec<u8>) -> Result<Vec<u8>, TypedStoreError> {
+        let mut encoded: Vec<u8> = vec![];
+        for byte in value {
+            match encoded.len() {
+                len if len >= 2 && encoded[len - 1] == byte && encoded[len - 2] < u8::MAX => {
+                    encoded[len - 2] += 1;
+                }
+                _ => encoded.extend([1, byte]),
+            }
+        }
+        Ok(encoded)
+    }
+
+    fn decode(&self, _key: &[u8], bytes: &[u8]) -> Result<Vec<u8>, TypedStoreError> {
+        Ok(bytes
+            .chunks(2)
+            .flat_map(|run| std::iter::repeat(run[1]).take(run[0] as usize))
+            .collect())
+    }
+}
+
+#[test]
+fn test_iter_memory_cap_counts_decoded_values() {
+    let db = DBMap::<u64, String>::open(temp_dir(), None, None)
+        .unwrap()
+        .with_value_codec(Arc::new(RunLengthCodec));
+    db.insert(&0, &"x".repeat(10_000)).unwrap();
+    assert_eq!(db.get(&0).unwrap(), Some("x".repeat(10_000)));
+
+    // The value is stored in a few dozen bytes, but counts for its 10KB once decoded
+    let capped: Result<Vec<_>, _> = db.iter().memory_capped(1_000).collect();
+    assert!(matches!(
+        capped,
+        Err(TypedStoreError::IteratorMemoryCapExceeded { entries: 1, .. })
+    ));
+}
+
+#[test]
+fn test_managed_secondary_path_slots() {
+    use fs2::FileExt;
+
+    let primary_path = temp_dir();
+    let first = ManagedSecondaryPath::allocate(&primary_path, "slots").unwrap();
+    let second = ManagedSecondaryPath::allocate(&primary_path, "slots").unwrap();
+    assert_ne!(first.path(), second.path());
+    let dir = first.path().parent().unwrap().to_path_buf();
+    assert_eq!(first.path(), dir.join("0"));
+
+    // A slot locked by another handle, e.g. of another process, is skipped and left intact
+    let marker = dir.join("2").join("marker");
+    std::fs::create_dir_all(dir.join("2")).unwrap();
+    std::fs::write(&marker, b"live").unwrap();
+    let lock = std::fs::File::create(dir.join("2.lock")).unwrap();
+    lock.lock_exclusive().unwrap();
+    let third = ManagedSecondaryPath::allocate(&primary_path, "slots").unwrap();
+    assert_eq!(third.path(), dir.join("3"));
+    assert!(marker.exists());
+
+    // The slot of a dropped handle is reused, and its directory is removed
+    drop(first);
+    assert!(!dir.join("0").exists());
+    let fourth = ManagedSecondaryPath::allocate(&primary_path, "slots").unwrap();
+    assert_eq!(fourth.path(), dir.join("0"));
+    assert!(second.path().exists());
+}
+
+#[test]
+fn test_schema_not_recorded_on_read_only_open() {
+    let path = temp_dir();
+    let rocks = open_cf(&path, None, &["table"]).unwrap();
+    let schema: TableSchema = [("table".to_owned(), ("i32".to_owned(), "String".to_owned()))]
+        .into_iter()
+        .collect();
+    let changed: TableSchema = [("table".to_owned(), ("i32".to_owned(), "u64".to_owned()))]
+        .into_iter()
+        .collect();
+
+    // Nothing is recorded when the database isn't writable, so there's no drift to report
+    check_schema_on_open(&rocks, &schema, false);
+    assert!(schema_check(&rocks, &changed).unwrap().is_empty());
+
+    check_schema_on_open(&rocks, &schema, true);
+    assert_eq!(schema_check(&rocks, &changed).unwrap().len(), 1);
+}
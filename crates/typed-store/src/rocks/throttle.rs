@@ -0,0 +1,155 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rate limits of the writes to a table.
+//!
+//! A background job writing to a table as fast as it can (a backfill, a migration) competes with
+//! the foreground writes to the same database for its memtables and compactions, and can stall
+//! them. A `DBMap` returned by `DBMap::with_write_limit` takes its writes through `insert` and
+//! `DBBatch::insert_batch` from a token bucket refilled at the rate of its `WriteLimit`, holding up
+//! to one second of writes. Writes beyond the limit either wait for the bucket to refill, blocking
+//! the calling thread, or fail with `TypedStoreError::WriteThrottled`, per the `ThrottlePolicy`.
+//! A batch takes the tokens of all its entries at once when they are added to it, and gives them
+//! back if it fails to be written, like the writes of `insert` which fail.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{Clock, TypedStoreError};
+use crate::metrics::DBMetrics;
+
+/// The maximum rate of the writes to a table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteLimit {
+    /// Limits the number of keys written per second
+    OpsPerSec(u64),
+    /// Limits the encoded size of the keys and values written per second
+    BytesPerSec(u64),
+}
+
+impl WriteLimit {
+    fn per_sec(&self) -> f64 {
+        match *self {
+            WriteLimit::OpsPerSec(ops) => ops as f64,
+            WriteLimit::BytesPerSec(bytes) => bytes as f64,
+        }
+    }
+}
+
+/// What a write beyond the `WriteLimit` of its table does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThrottlePolicy {
+    /// Blocks the calling thread until the write is within the limit, in `insert` and
+    /// `DBBatch::insert_batch`. Such a map must not be written from an async task, including
+    /// through a `Store` whose commands run on one: write from a blocking task, e.g. with
+    /// `tokio::task::spawn_blocking`, or use `Error` and retry later
+    #[default]
+    Wait,
+    /// Fails the write with `TypedStoreError::WriteThrottled`, without writing anything. Writes
+    /// and batches larger than one second of the limit always fail
+    Error,
+}
+
+/// A token bucket shared by the clones of a `DBMap`, see the module documentation
+#[derive(Debug)]
+pub(crate) struct WriteThrottle {
+    limit: WriteLimit,
+    policy: ThrottlePolicy,
+    /// The tokens available and when they were last refilled, if ever. The tokens are negative
+    /// while the writes which waited for them are sleeping
+    bucket: Mutex<(f64, Option<Duration>)>,
+}
+
+impl WriteThrottle {
+    pub(crate) fn new(limit: WriteLimit, policy: ThrottlePolicy) -> Self {
+        Self {
+            limit,
+            policy,
+            bucket: Mutex::new((limit.per_sec(), None)),
+        }
+    }
+
+    fn lock_bucket(&self) -> std::sync::MutexGuard<'_, (f64, Option<Duration>)> {
+        self.bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Takes the tokens of writing `ops` keys of `bytes` encoded bytes to `cf_name`, waiting or
+    /// failing if there are not enough. The bucket is refilled and waited for per `clock`
+    pub(crate) fn acquire(
+        self: &Arc<Self>,
+        clock: &dyn Clock,
+        db_name: &str,
+        cf_name: &str,
+        ops: usize,
+        bytes: usize,
+    ) -> Result<ThrottledWrite, TypedStoreError> {
+        let rate = self.limit.per_sec();
+        let tokens = match self.limit {
+            WriteLimit::OpsPerSec(_) => ops as f64,
+            WriteLimit::BytesPerSec(_) => bytes as f64,
+        };
+        let deficit = {
+            let mut bucket = self.lock_bucket();
+            let (available, refilled) = &mut *bucket;
+            let now = clock.instant();
+            if let Some(refilled) = refilled {
+                let elapsed = now.saturating_sub(*refilled).as_secs_f64();
+                *available = (*available + elapsed * rate).min(rate);
+            }
+            *refilled = Some(now);
+            if *available >= tokens {
+                *available -= tokens;
+                return Ok(self.taken(tokens));
+            }
+            match self.policy {
+                ThrottlePolicy::Wait => {
+                    *available -= tokens;
+                    -*available
+                }
+                ThrottlePolicy::Error => 0.0,
+            }
+        };
+
+        DBMetrics::get()
+            .op_metrics
+            .rocksdb_throttled_writes
+            .with_label_values(&[db_name, cf_name])
+            .inc();
+        match self.policy {
+            ThrottlePolicy::Wait => {
+                if rate > 0.0 {
+                    clock.sleep(Duration::from_secs_f64(deficit / rate));
+                }
+                Ok(self.taken(tokens))
+            }
+            ThrottlePolicy::Error => Err(TypedStoreError::WriteThrottled(cf_name.to_owned())),
+        }
+    }
+
+    fn taken(self: &Arc<Self>, tokens: f64) -> ThrottledWrite {
+        ThrottledWrite {
+            throttle: self.clone(),
+            tokens,
+        }
+    }
+}
+
+/// The tokens taken by a write, given back with `refund` if the write fails
+#[derive(Debug)]
+pub(crate) struct ThrottledWrite {
+    throttle: Arc<WriteThrottle>,
+    tokens: f64,
+}
+
+impl ThrottledWrite {
+    /// Gives the tokens back to the bucket, since nothing was written with them
+    pub(crate) fn refund(self) {
+        let rate = self.throttle.limit.per_sec();
+        let mut bucket = self.throttle.lock_bucket();
+        bucket.0 = (bucket.0 + self.tokens).min(rate);
+    }
+}
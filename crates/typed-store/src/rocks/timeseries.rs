@@ -0,0 +1,242 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    marker::PhantomData,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bincode::Options;
+use rocksdb::{MultiThreaded, WriteBatch};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+use super::{
+    be_fix_int_ser, filters::total_order_read_options, Clock, DBMap, DBRawIteratorMultiThreaded,
+    SystemClock, TypedStoreError,
+};
+
+/// A persistent series of values ordered by timestamp, e.g. metrics persisted by a node.
+///
+/// Points are keyed by `(timestamp, sequence number)`, so that several points may share a timestamp,
+/// and are encoded in big endian so that the RocksDB ordering is the time ordering. Timestamps are
+/// in the unit chosen by the caller, `append_now` uses milliseconds since the Unix epoch.
+///
+/// ```
+/// use typed_store::rocks::*;
+/// let series = DBTimeSeries::<f64>::open(tempfile::tempdir().unwrap(), None, None).unwrap();
+/// for ts in 0..100 {
+///     series.append(ts, &(ts as f64)).unwrap();
+/// }
+/// assert_eq!(series.range(10..20).count(), 10);
+/// let downsampled: Vec<_> = series.downsample(0..100, 25).map(|(ts, _)| ts).collect();
+/// assert_eq!(downsampled, vec![0, 25, 50, 75]);
+/// series.prune_before(50).unwrap();
+/// assert_eq!(series.range(0..u64::MAX).count(), 50);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DBTimeSeries<V> {
+    map: DBMap<(u64, u64), V>,
+    retention: Option<Duration>,
+    // serializes the allocation of sequence numbers
+    append_lock: Arc<Mutex<()>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<V> DBTimeSeries<V> {
+    /// Opens a database from a path, with specific options and an optional column family
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        db_options: Option<rocksdb::Options>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        Ok(Self::from_map(DBMap::open(path, db_options, opt_cf)?))
+    }
+
+    /// Reopens an open database as a time series operating under a specific column family, see `DBMap::reopen`
+    pub fn reopen(
+        db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+        opt_cf: Option<&str>,
+    ) -> Result<Self, TypedStoreError> {
+        Ok(Self::from_map(DBMap::reopen(db, opt_cf)?))
+    }
+
+    fn from_map(map: DBMap<(u64, u64), V>) -> Self {
+        Self {
+            map,
+            retention: None,
+            append_lock: Arc::new(Mutex::new(())),
+            clock: SystemClock::shared(),
+        }
+    }
+
+    /// Keeps the points of the last `retention` when calling `prune_expired`, with millisecond timestamps
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Reads the current time from `clock` instead of the system clock, in `append_now` and `prune_expired`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Reads the current time from `clock`, see `with_clock`
+    #[cfg(any(feature = "testing", test))]
+    pub fn with_mock_clock(self, clock: crate::testing::clock::MockClock) -> Self {
+        self.with_clock(Arc::new(clock))
+    }
+
+    /// The current time in milliseconds since the Unix epoch
+    fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    pub fn rocksdb(&self) -> &Arc<rocksdb::DBWithThreadMode<MultiThreaded>> {
+        &self.map.rocksdb
+    }
+
+    /// Removes the points older than `timestamp`
+    pub fn prune_before(&self, timestamp: u64) -> Result<(), TypedStoreError> {
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(
+            &self.map.cf(),
+            be_fix_int_ser(&(0u64, 0u64))?,
+            be_fix_int_ser(&(timestamp, 0u64))?,
+        );
+        let _permit = self.map.permit_writes()?;
+        self.map
+            .rocksdb
+            .write_opt(batch, &self.map.write_options())?;
+        debug!("Pruned the points of {} before {timestamp}", self.map.cf);
+        Ok(())
+    }
+
+    /// Removes the points older than the retention of the series, if any
+    pub fn prune_expired(&self) -> Result<(), TypedStoreError> {
+        self.prune_expired_at(self.now_millis())
+    }
+
+    /// Removes the points which are expired at `now`, in milliseconds since the Unix epoch, e.g. to
+    /// force the expiry of the points in tests
+    pub fn prune_expired_at(&self, now: u64) -> Result<(), TypedStoreError> {
+        match self.retention {
+            Some(retention) => {
+                let cutoff = now.saturating_sub(retention.as_millis() as u64);
+                self.prune_before(cutoff)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn raw_iter_from(
+        &self,
+        timestamp: u64,
+    ) -> Result<DBRawIteratorMultiThreaded<'_>, TypedStoreError> {
+        let mut db_iter = self
+            .map
+            .rocksdb
+            .raw_iterator_cf_opt(&self.map.cf(), total_order_read_options());
+        db_iter.seek(be_fix_int_ser(&(timestamp, 0u64))?);
+        Ok(db_iter)
+    }
+}
+
+impl<V: Serialize + DeserializeOwned> DBTimeSeries<V> {
+    /// Appends a point at `timestamp`, after the points already at this timestamp,
+    /// and returns its sequence number
+    pub fn append(&self, timestamp: u64, value: &V) -> Result<u64, TypedStoreError> {
+        let _guard = self
+            .append_lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut db_iter = self
+            .map
+            .rocksdb
+            .raw_iterator_cf_opt(&self.map.cf(), total_order_read_options());
+        db_iter.seek_for_prev(be_fix_int_ser(&(timestamp, u64::MAX))?);
+        let seq = match db_iter.key().map(decode_key) {
+            Some(Some((ts, seq))) if ts == timestamp => seq + 1,
+            _ => 0,
+        };
+        let _permit = self.map.permit_writes()?;
+        self.map.rocksdb.put_cf_opt(
+            &self.map.cf(),
+            be_fix_int_ser(&(timestamp, seq))?,
+            bincode::serialize(value)?,
+            &self.map.write_options(),
+        )?;
+        Ok(seq)
+    }
+
+    /// Appends a point at the current time, in milliseconds since the Unix epoch
+    pub fn append_now(&self, value: &V) -> Result<u64, TypedStoreError> {
+        self.append(self.now_millis(), value)
+    }
+
+    /// Iterates over the points with a timestamp in `range`, in time order
+    pub fn range(&self, range: Range<u64>) -> TimeSeriesIter<'_, V> {
+        TimeSeriesIter {
+            db_iter: self.raw_iter_from(range.start).ok(),
+            end: range.end,
+            step: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Iterates over the first point of every `step` long interval of `range` which has points,
+    /// skipping over the other points without reading them
+    pub fn downsample(&self, range: Range<u64>, step: u64) -> TimeSeriesIter<'_, V> {
+        TimeSeriesIter {
+            db_iter: self.raw_iter_from(range.start).ok(),
+            end: range.end,
+            step: Some((range.start, step.max(1))),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+fn decode_key(key: &[u8]) -> Option<(u64, u64)> {
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .deserialize(key)
+        .ok()
+}
+
+/// An iterator over the points of a `DBTimeSeries`, see `DBTimeSeries::range`
+pub struct TimeSeriesIter<'a, V> {
+    db_iter: Option<DBRawIteratorMultiThreaded<'a>>,
+    end: u64,
+    /// The start of the downsampled range and the downsampling step
+    step: Option<(u64, u64)>,
+    _phantom: PhantomData<V>,
+}
+
+impl<'a, V: DeserializeOwned> Iterator for TimeSeriesIter<'a, V> {
+    type Item = (u64, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let db_iter = self.db_iter.as_mut()?;
+        let (timestamp, _) = decode_key(db_iter.key()?)?;
+        if timestamp >= self.end {
+            return None;
+        }
+        let value = bincode::deserialize(db_iter.value()?).ok()?;
+        match self.step {
+            Some((start, step)) => {
+                // Jump to the start of the next interval
+                let next_interval = (timestamp - start) / step * step + start;
+                match next_interval.checked_add(step) {
+                    Some(next) => db_iter.seek(be_fix_int_ser(&(next, 0u64)).ok()?),
+                    None => self.db_iter = None,
+                }
+            }
+            None => db_iter.next(),
+        }
+        Some((timestamp, value))
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::{ColumnFamilyDescriptor, MultiThreaded};
+use tracing::debug;
+
+use super::{options_summary::read_latest_cf_options, TypedStoreError};
+
+/// The value of an option which is not set, in the options files
+const UNSET: &str = "nullptr";
+
+/// Returns why changing `option` of a table from `existing` to `requested` breaks the table, if it
+/// does. Two unset options are equal. The prefix extractor can be changed: RocksDB records it in
+/// every SST file, and ignores the prefix filters of the files written with another one
+fn incompatibility(option: &str, existing: &str, requested: &str) -> Option<&'static str> {
+    if existing == requested {
+        return None;
+    }
+    match option {
+        "comparator" => Some(
+            "the existing files of the table are sorted by the existing comparator, and RocksDB refuses to open them with another one",
+        ),
+        "merge_operator" if requested == UNSET => Some(
+            "the table may hold merge operands, which can't be read without a merge operator",
+        ),
+        _ => None,
+    }
+}
+
+/// Checks that the database in `path`, if any, can be opened with the options of the column
+/// families in `opt_cfs`, as given to `open_cf_opts`. Fails with
+/// `TypedStoreError::IncompatibleOptions` on the first change of the comparator, or removal of the
+/// merge operator, of an existing table, which would break it, instead of the opaque error or the
+/// corruption it would cause once opened.
+///
+/// The options can't be read back from a `rocksdb::Options`: they are resolved by opening an empty
+/// database with them in a temporary directory, and compared with the latest options file of the
+/// existing database. The column families of `opt_cfs` which don't exist yet are not checked
+pub fn validate_options<P: AsRef<Path>>(
+    path: P,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+) -> Result<(), TypedStoreError> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(());
+    }
+    let existing = match read_latest_cf_options(path)? {
+        Some(existing) => existing,
+        None => return Ok(()),
+    };
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let scratch = std::env::temp_dir().join(format!(
+        "typed-store-validate-{}-{nanos}",
+        std::process::id()
+    ));
+    let requested = resolve_options(&scratch, opt_cfs);
+    if let Err(e) = fs::remove_dir_all(&scratch) {
+        debug!("Failed to remove {scratch:?}: {e}");
+    }
+    let requested = requested?;
+
+    for (table, _) in opt_cfs {
+        let (existing, requested) = match (existing.get(*table), requested.get(*table)) {
+            (Some(existing), Some(requested)) => (existing, requested),
+            _ => continue,
+        };
+        for option in ["comparator", "merge_operator"] {
+            let existing = existing.get(option).map_or(UNSET, String::as_str);
+            let requested = requested.get(option).map_or(UNSET, String::as_str);
+            if let Some(reason) = incompatibility(option, existing, requested) {
+                return Err(TypedStoreError::IncompatibleOptions {
+                    table: (*table).to_owned(),
+                    option: option.to_owned(),
+                    existing: existing.to_owned(),
+                    requested: requested.to_owned(),
+                    reason: reason.to_owned(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Opens an empty database in `scratch` with the column families of `opt_cfs`, and returns the
+/// options RocksDB resolved for them, by column family name
+fn resolve_options(
+    scratch: &Path,
+    opt_cfs: &[(&str, &rocksdb::Options)],
+) -> Result<BTreeMap<String, BTreeMap<String, String>>, TypedStoreError> {
+    let mut db_options = rocksdb::Options::default();
+    db_options.create_if_missing(true);
+    db_options.create_missing_column_families(true);
+    let cfs = opt_cfs
+        .iter()
+        .map(|(name, options)| ColumnFamilyDescriptor::new(*name, (*options).clone()));
+    let db =
+        rocksdb::DBWithThreadMode::<MultiThreaded>::open_cf_descriptors(&db_options, scratch, cfs)?;
+    drop(db);
+    read_latest_cf_options(scratch)?
+        .ok_or_else(|| TypedStoreError::IoError(format!("no options file found in {scratch:?}")))
+}
@@ -4,18 +4,26 @@ use std::marker::PhantomData;
 
 use serde::de::DeserializeOwned;
 
-use super::DBRawIteratorMultiThreaded;
+use super::{
+    codec::{decode_value, ValueEncoding},
+    DBRawIteratorMultiThreaded,
+};
 
 /// An iterator over the values of a prefix.
 pub struct Values<'a, V> {
     db_iter: DBRawIteratorMultiThreaded<'a>,
+    encoding: ValueEncoding<'a>,
     _phantom: PhantomData<V>,
 }
 
 impl<'a, V: DeserializeOwned> Values<'a, V> {
-    pub(crate) fn new(db_iter: DBRawIteratorMultiThreaded<'a>) -> Self {
+    pub(crate) fn new(
+        db_iter: DBRawIteratorMultiThreaded<'a>,
+        encoding: ValueEncoding<'a>,
+    ) -> Self {
         Self {
             db_iter,
+            encoding,
             _phantom: PhantomData,
         }
     }
@@ -26,10 +34,10 @@ impl<'a, V: DeserializeOwned> Iterator for Values<'a, V> {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.db_iter.valid() {
-            let value = self.db_iter.key().and_then(|_| {
+            let value = self.db_iter.key().and_then(|k| {
                 self.db_iter
                     .value()
-                    .and_then(|v| bincode::deserialize(v).ok())
+                    .and_then(|v| decode_value(self.encoding, k, v).ok())
             });
 
             self.db_iter.next();
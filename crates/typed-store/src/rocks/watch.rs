@@ -0,0 +1,260 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+use tokio::sync::{broadcast, oneshot};
+use tracing::warn;
+
+use super::{KeyFormat, TypedStoreError};
+
+/// The number of events buffered for each watcher before it lags
+pub const DEFAULT_WATCH_CAPACITY: usize = 1024;
+
+/// A change to the entries of a table, see `DBMap::watch_prefix`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeEvent<K, V> {
+    Inserted {
+        key: K,
+        value: V,
+    },
+    Removed {
+        key: K,
+    },
+    /// The keys in `[from, to)` were removed, some of them possibly outside of the watched prefix
+    RangeRemoved {
+        from: K,
+        to: K,
+    },
+    /// All the entries of the table were removed
+    Cleared,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum RawChange {
+    Put { key: Arc<[u8]>, value: Arc<[u8]> },
+    Delete { key: Arc<[u8]> },
+    DeleteRange { from: Arc<[u8]>, to: Arc<[u8]> },
+    Clear,
+}
+
+impl RawChange {
+    /// Whether the change may affect keys starting with `prefix`
+    fn matches(&self, prefix: &[u8]) -> bool {
+        match self {
+            RawChange::Put { key, .. } | RawChange::Delete { key } => key.starts_with(prefix),
+            RawChange::DeleteRange { from, to } => {
+                // Keys starting with `prefix` are all greater or equal to it, and all smaller than
+                // any key greater than `prefix` which doesn't start with it
+                if from.as_ref() <= prefix {
+                    prefix < to.as_ref()
+                } else {
+                    from.starts_with(prefix) && from < to
+                }
+            }
+            RawChange::Clear => true,
+        }
+    }
+}
+
+/// The watchers of a table, shared by a map and its clones
+#[derive(Debug, Default)]
+pub(crate) struct PrefixWatchers {
+    senders: RwLock<Vec<(Vec<u8>, broadcast::Sender<RawChange>)>>,
+    /// The waiters for the next value written to a key, see `DBMap::notify_read`
+    waiters: Mutex<HashMap<Vec<u8>, Vec<oneshot::Sender<Arc<[u8]>>>>>,
+}
+
+impl PrefixWatchers {
+    pub(crate) fn subscribe(&self, prefix: Vec<u8>) -> broadcast::Receiver<RawChange> {
+        let (sender, receiver) = broadcast::channel(DEFAULT_WATCH_CAPACITY);
+        self.senders
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((prefix, sender));
+        receiver
+    }
+
+    /// Registers a waiter for the next value written to `key`, which receives the value serialized
+    pub(crate) fn wait_for(&self, key: Vec<u8>) -> oneshot::Receiver<Arc<[u8]>> {
+        let (sender, receiver) = oneshot::channel();
+        self.lock_waiters().entry(key).or_default().push(sender);
+        receiver
+    }
+
+    /// Forgets the waiters for `key` which were dropped before a value was written
+    pub(crate) fn forget_dropped_waiters(&self, key: &[u8]) {
+        let mut waiters = self.lock_waiters();
+        if let Some(senders) = waiters.get_mut(key) {
+            senders.retain(|sender| !sender.is_closed());
+            if senders.is_empty() {
+                waiters.remove(key);
+            }
+        }
+    }
+
+    fn lock_waiters(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<Vec<u8>, Vec<oneshot::Sender<Arc<[u8]>>>>> {
+        self.waiters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether some watcher may be interested in `key`, to skip building the change otherwise
+    pub(crate) fn is_watching(&self, key: &[u8]) -> bool {
+        self.senders
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .any(|(prefix, _)| key.starts_with(prefix))
+            || self.lock_waiters().contains_key(key)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.senders
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .is_empty()
+            && self.lock_waiters().is_empty()
+    }
+
+    /// Sends `change` to the matching watchers, and forgets the watchers which were dropped.
+    /// The waiters for the key of a `RawChange::Put` receive its value
+    pub(crate) fn notify(&self, change: RawChange) {
+        if self.is_empty() {
+            return;
+        }
+        if let RawChange::Put { key, value } = &change {
+            if let Some(waiters) = self.lock_waiters().remove(key.as_ref()) {
+                for waiter in waiters {
+                    let _ = waiter.send(value.clone());
+                }
+            }
+        }
+        let mut senders = self
+            .senders
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        senders.retain(|(prefix, sender)| {
+            if change.matches(prefix) {
+                sender.send(change.clone()).is_ok()
+            } else {
+                sender.receiver_count() > 0
+            }
+        });
+    }
+}
+
+/// An error returned by `PrefixWatch::recv`
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("the watcher lagged behind and missed {0} changes")]
+    Lagged(u64),
+    #[error("the watched map was dropped")]
+    Closed,
+    #[error(transparent)]
+    Decode(#[from] TypedStoreError),
+}
+
+/// A subscription to the changes of the keys of a table starting with a prefix, see `DBMap::watch_prefix`
+pub struct PrefixWatch<K, V> {
+    receiver: broadcast::Receiver<RawChange>,
+    key_format: KeyFormat,
+    _phantom: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> PrefixWatch<K, V> {
+    pub(crate) fn new(receiver: broadcast::Receiver<RawChange>, key_format: KeyFormat) -> Self {
+        Self {
+            receiver,
+            key_format,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Waits for the next change. After `WatchError::Lagged`, the watch resumes from the oldest
+    /// change still buffered, and the watched keys should be scanned again to catch up
+    pub async fn recv(&mut self) -> Result<ChangeEvent<K, V>, WatchError> {
+        let change = self.receiver.recv().await.map_err(|e| match e {
+            broadcast::error::RecvError::Lagged(missed) => WatchError::Lagged(missed),
+            broadcast::error::RecvError::Closed => WatchError::Closed,
+        })?;
+        Ok(decode_change(self.key_format, change)?)
+    }
+}
+
+/// Entries of a table which a cache of the table must evict, see `DBMap::watch_invalidations`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Invalidation<K> {
+    /// The value of the key was written or removed
+    Key(K),
+    /// The keys in `[from, to)` were removed
+    Range { from: K, to: K },
+    /// Any entry may have changed: the table was cleared, or changes were missed
+    All,
+}
+
+/// A subscription to the keys written to a table, for the caches of the table outside of this
+/// crate, see `DBMap::watch_invalidations`
+pub struct InvalidationWatch<K> {
+    receiver: broadcast::Receiver<RawChange>,
+    key_format: KeyFormat,
+    _phantom: PhantomData<fn() -> K>,
+}
+
+impl<K: DeserializeOwned> InvalidationWatch<K> {
+    pub(crate) fn new(receiver: broadcast::Receiver<RawChange>, key_format: KeyFormat) -> Self {
+        Self {
+            receiver,
+            key_format,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Waits for the next invalidation. When the watch lagged behind the writes, the missed
+    /// changes are reported as `Invalidation::All`, so a cache evicting what it is told to never
+    /// serves stale entries
+    pub async fn recv(&mut self) -> Result<Invalidation<K>, WatchError> {
+        match self.receiver.recv().await {
+            Ok(RawChange::Put { key, .. }) | Ok(RawChange::Delete { key }) => {
+                Ok(Invalidation::Key(self.key_format.deserialize(&key)?))
+            }
+            Ok(RawChange::DeleteRange { from, to }) => Ok(Invalidation::Range {
+                from: self.key_format.deserialize(&from)?,
+                to: self.key_format.deserialize(&to)?,
+            }),
+            Ok(RawChange::Clear) => Ok(Invalidation::All),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Invalidation watch lagged behind and missed {missed} changes");
+                Ok(Invalidation::All)
+            }
+            Err(broadcast::error::RecvError::Closed) => Err(WatchError::Closed),
+        }
+    }
+}
+
+fn decode_change<K: DeserializeOwned, V: DeserializeOwned>(
+    key_format: KeyFormat,
+    change: RawChange,
+) -> Result<ChangeEvent<K, V>, TypedStoreError> {
+    Ok(match change {
+        RawChange::Put { key, value } => ChangeEvent::Inserted {
+            key: key_format.deserialize(&key)?,
+            value: bincode::deserialize(&value)?,
+        },
+        RawChange::Delete { key } => ChangeEvent::Removed {
+            key: key_format.deserialize(&key)?,
+        },
+        RawChange::DeleteRange { from, to } => ChangeEvent::RangeRemoved {
+            from: key_format.deserialize(&from)?,
+            to: key_format.deserialize(&to)?,
+        },
+        RawChange::Clear => ChangeEvent::Cleared,
+    })
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A clock moved by the tests, to check the expiry of entries without sleeping.
+//!
+//! ```
+//! use std::time::Duration;
+//! use typed_store::rocks::DBTimeSeries;
+//! use typed_store::testing::clock::MockClock;
+//!
+//! let clock = MockClock::new(0);
+//! let series = DBTimeSeries::<u32>::open(tempfile::tempdir().unwrap(), None, None)
+//!     .unwrap()
+//!     .with_retention(Duration::from_secs(60))
+//!     .with_mock_clock(clock.clone());
+//! series.append_now(&1).unwrap();
+//! clock.advance(Duration::from_secs(61));
+//! series.append_now(&2).unwrap();
+//! series.prune_expired().unwrap();
+//! assert_eq!(series.range(0..u64::MAX).map(|(_, v)| v).collect::<Vec<_>>(), vec![2]);
+//! ```
+//!
+//! It implements `typed_store::rocks::Clock`, to drive the other time dependent features.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::rocks::Clock;
+
+/// A clock in milliseconds since the Unix epoch, which only moves when told to.
+/// Clones share the same time
+///
+/// As a `Clock`, the blocking sleeps advance the clock by their duration, so that the threads
+/// throttled by it resume immediately, while the async sleeps wait for the clock to be moved past
+/// their deadline, e.g. to tick the periodic tasks one period at a time
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    millis: Arc<AtomicU64>,
+    /// Wakes up the async sleeps when the clock moves
+    moved: Arc<Notify>,
+}
+
+impl MockClock {
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: Arc::new(AtomicU64::new(millis)),
+            moved: Arc::default(),
+        }
+    }
+
+    pub fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+        self.moved.notify_waiters();
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+        self.moved.notify_waiters();
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(MockClock::now_millis(self))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn sleep_async(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let clock = self.clone();
+        let deadline = MockClock::now_millis(self) + duration.as_millis() as u64;
+        Box::pin(async move {
+            loop {
+                // Registered before reading the time, so that a move in between isn't missed
+                let moved = clock.moved.notified();
+                if MockClock::now_millis(&clock) >= deadline {
+                    break;
+                }
+                moved.await;
+            }
+        })
+    }
+}
@@ -0,0 +1,7 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Utilities for the tests of the crates using typed_store, enabled by the `testing` feature.
+
+pub mod clock;
+pub mod stress;
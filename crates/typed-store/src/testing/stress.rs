@@ -0,0 +1,271 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Concurrent stress workloads checking the invariants the tables should uphold.
+//!
+//! `run_stress` runs writers, readers and iterators concurrently against a set of tables for a
+//! while, and reports the invariant violations they observed:
+//! - read your writes: a writer reads back the value it just wrote,
+//! - batch atomicity: a batch writes the same generation to a key of every table, and readers
+//!   reading the key in all the tables from a snapshot never see a partially applied batch,
+//! - iterator order: iterators return strictly increasing keys.
+//!
+//! It can be used to validate new options or storage backends, e.g. from the tests of a crate
+//! opening the database with its own options:
+//!
+//! ```
+//! use std::time::Duration;
+//! use typed_store::rocks::open_cf;
+//! use typed_store::testing::stress::{run_stress, StressConfig};
+//!
+//! let db = open_cf(tempfile::tempdir().unwrap().into_path(), None, &["table1", "table2"]).unwrap();
+//! let config = StressConfig {
+//!     duration: Duration::from_millis(100),
+//!     ..StressConfig::default()
+//! };
+//! let report = run_stress(&db, &["table1", "table2"], &config).unwrap();
+//! assert!(report.violations.is_empty(), "{:?}", report.violations);
+//! ```
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rocksdb::MultiThreaded;
+
+use crate::{
+    rocks::{be_fix_int_ser, DBBatch, DBMap, TypedStoreError},
+    Map,
+};
+
+/// The keys written by the workloads: the id of the writer owning the key, and a slot
+type StressKey = (u32, u64);
+
+/// Configuration of a stress run
+#[derive(Clone, Debug)]
+pub struct StressConfig {
+    /// Number of threads writing batches to all the tables, each to its own keys
+    pub writers: usize,
+    /// Number of threads reading keys in all the tables from a snapshot
+    pub readers: usize,
+    /// Number of threads iterating over the tables
+    pub iterators: usize,
+    /// Number of keys of each writer
+    pub keys_per_writer: u64,
+    /// How long the workloads run
+    pub duration: Duration,
+    /// Seed of the pseudo random choice of the keys
+    pub seed: u64,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self {
+            writers: 4,
+            readers: 4,
+            iterators: 1,
+            keys_per_writer: 1000,
+            duration: Duration::from_secs(1),
+            seed: 0,
+        }
+    }
+}
+
+/// An invariant checked by the workloads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Invariant {
+    ReadYourWrites,
+    BatchAtomicity,
+    IteratorOrder,
+}
+
+/// An invariant violation observed by a workload
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub invariant: Invariant,
+    pub details: String,
+}
+
+/// The operations performed by a stress run, and the violations observed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StressReport {
+    /// Number of batches committed by the writers
+    pub batches: u64,
+    /// Number of keys read by the readers and writers
+    pub reads: u64,
+    /// Number of full iterations over a table
+    pub iterations: u64,
+    pub violations: Vec<Violation>,
+}
+
+impl StressReport {
+    fn merge(&mut self, other: StressReport) {
+        self.batches += other.batches;
+        self.reads += other.reads;
+        self.iterations += other.iterations;
+        self.violations.extend(other.violations);
+    }
+}
+
+/// Runs the workloads of `config` against `tables`, which must be column families of `db`
+/// dedicated to the run: the keys and values written are those of a `DBMap<(u32, u64), u64>`.
+/// Returns an error if an operation fails, and the violations observed otherwise.
+pub fn run_stress(
+    db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    tables: &[&str],
+    config: &StressConfig,
+) -> Result<StressReport, TypedStoreError> {
+    assert!(!tables.is_empty(), "Stress runs need at least one table");
+    let maps = tables
+        .iter()
+        .map(|table| Ok((*table, DBMap::<StressKey, u64>::reopen(db, Some(table))?)))
+        .collect::<Result<Vec<_>, TypedStoreError>>()?;
+    let deadline = Instant::now() + config.duration;
+
+    let results = std::thread::scope(|s| {
+        let maps = &maps;
+        let mut handles = Vec::new();
+        for writer in 0..config.writers {
+            let seed = config.seed ^ (writer as u64 + 1);
+            handles.push(s.spawn(move || write(maps, writer as u32, seed, config, deadline)));
+        }
+        for reader in 0..config.readers {
+            let seed = config.seed ^ ((reader as u64 + 1) << 32);
+            handles.push(s.spawn(move || read(db, maps, seed, config, deadline)));
+        }
+        for iterator in 0..config.iterators {
+            let (table, map) = &maps[iterator % maps.len()];
+            handles.push(s.spawn(move || iterate(table, map, deadline)));
+        }
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Stress workload panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut report = StressReport::default();
+    for result in results {
+        report.merge(result?);
+    }
+    Ok(report)
+}
+
+/// Writes a new generation of a random key of the writer to all the tables in a batch,
+/// and reads it back from every table
+fn write(
+    maps: &[(&str, DBMap<StressKey, u64>)],
+    writer: u32,
+    seed: u64,
+    config: &StressConfig,
+    deadline: Instant,
+) -> Result<StressReport, TypedStoreError> {
+    let mut report = StressReport::default();
+    let mut rng = XorShift::new(seed);
+    let mut generation = 0;
+    while Instant::now() < deadline {
+        generation += 1;
+        let key = (writer, rng.next() % config.keys_per_writer.max(1));
+        let batch = maps
+            .iter()
+            .try_fold(DBBatch::new(&maps[0].1.rocksdb), |batch, (_, map)| {
+                batch.insert_batch(map, [(key, generation)])
+            })?;
+        batch.write()?;
+        report.batches += 1;
+
+        for (table, map) in maps {
+            let read = map.get(&key)?;
+            report.reads += 1;
+            if read != Some(generation) {
+                report.violations.push(Violation {
+                    invariant: Invariant::ReadYourWrites,
+                    details: format!(
+                        "wrote generation {generation} of {key:?} in {table}, read {read:?}"
+                    ),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Reads a random key in all the tables from a snapshot, checking they have the same generation
+fn read(
+    db: &Arc<rocksdb::DBWithThreadMode<MultiThreaded>>,
+    maps: &[(&str, DBMap<StressKey, u64>)],
+    seed: u64,
+    config: &StressConfig,
+    deadline: Instant,
+) -> Result<StressReport, TypedStoreError> {
+    let mut report = StressReport::default();
+    let mut rng = XorShift::new(seed);
+    while Instant::now() < deadline {
+        let writer = (rng.next() % config.writers.max(1) as u64) as u32;
+        let key = (writer, rng.next() % config.keys_per_writer.max(1));
+        let key_buf = be_fix_int_ser(&key)?;
+
+        let snapshot = db.snapshot();
+        let generations = maps
+            .iter()
+            .map(|(table, _)| {
+                let cf = db
+                    .cf_handle(table)
+                    .ok_or_else(|| TypedStoreError::UnregisteredColumn(table.to_string()))?;
+                snapshot
+                    .get_cf(&cf, &key_buf)?
+                    .map(|bytes| bincode::deserialize::<u64>(&bytes))
+                    .transpose()
+                    .map_err(TypedStoreError::from)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        report.reads += maps.len() as u64;
+        if generations.windows(2).any(|w| w[0] != w[1]) {
+            report.violations.push(Violation {
+                invariant: Invariant::BatchAtomicity,
+                details: format!("read generations {generations:?} of {key:?} across the tables"),
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Iterates over a table, checking the keys are strictly increasing
+fn iterate(
+    table: &str,
+    map: &DBMap<StressKey, u64>,
+    deadline: Instant,
+) -> Result<StressReport, TypedStoreError> {
+    let mut report = StressReport::default();
+    while Instant::now() < deadline {
+        let mut previous: Option<StressKey> = None;
+        for (key, _) in map.iter() {
+            if let Some(previous) = previous.filter(|previous| *previous >= key) {
+                report.violations.push(Violation {
+                    invariant: Invariant::IteratorOrder,
+                    details: format!("{key:?} returned after {previous:?} in {table}"),
+                });
+            }
+            previous = Some(key);
+        }
+        report.iterations += 1;
+    }
+    Ok(report)
+}
+
+/// A small deterministic pseudo random generator, so that runs can be replayed from their seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
@@ -221,3 +221,75 @@ async fn iter_and_filter_successfully() {
     }
     assert_eq!(output.len(), key_values.len());
 }
+
+async fn wait_until(condition: impl Fn() -> bool) {
+    for _ in 0..5000 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    panic!("Timed out waiting for the condition");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn queue_metrics() {
+    let registry = prometheus::Registry::new();
+    let db = rocks::DBMap::<usize, String>::open(temp_dir(), None, None).unwrap();
+    let store = Store::new_with_metrics(db, "test", &registry);
+
+    let gauge = |name: &str| {
+        registry
+            .gather()
+            .into_iter()
+            .find(|family| family.get_name() == name)
+            .unwrap()
+            .get_metric()[0]
+            .get_gauge()
+            .get_value()
+    };
+    assert_eq!(gauge("store_pending_commands"), 0.0);
+    assert_eq!(gauge("store_oldest_pending_command_age_seconds"), 0.0);
+
+    // Keep the worker busy with an iteration until released
+    store.write(0, "0".to_string()).await;
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let (started_tx, release_rx) = (Mutex::new(started_tx), Mutex::new(release_rx));
+    let predicate = move |_: &(usize, String)| {
+        started_tx.lock().unwrap().send(()).unwrap();
+        release_rx.lock().unwrap().recv().unwrap();
+        true
+    };
+    let iter = tokio::spawn({
+        let store = store.clone();
+        async move { store.iter(Some(Box::new(predicate))).await }
+    });
+    tokio::task::spawn_blocking(move || started_rx.recv().unwrap())
+        .await
+        .unwrap();
+
+    // The commands are pending until processed, including the one being processed
+    let read = tokio::spawn({
+        let store = store.clone();
+        async move { store.read(0).await }
+    });
+    wait_until(|| gauge("store_pending_commands") == 2.0).await;
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    assert!(gauge("store_oldest_pending_command_age_seconds") >= 0.02);
+
+    // Fill the channel, then give up on a command waiting for room: it is not pending
+    for key in 1..100 {
+        store.remove(key).await;
+    }
+    assert_eq!(gauge("store_pending_commands"), 101.0);
+    let cancelled = tokio::time::timeout(std::time::Duration::from_millis(10), store.remove(100));
+    assert!(cancelled.await.is_err());
+    assert_eq!(gauge("store_pending_commands"), 101.0);
+
+    release_tx.send(()).unwrap();
+    assert_eq!(iter.await.unwrap().len(), 1);
+    assert_eq!(read.await.unwrap().unwrap(), Some("0".to_string()));
+    wait_until(|| gauge("store_pending_commands") == 0.0).await;
+    assert_eq!(gauge("store_oldest_pending_command_age_seconds"), 0.0);
+}
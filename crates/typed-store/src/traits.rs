@@ -1,6 +1,6 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{borrow::Borrow, collections::BTreeMap, error::Error};
 
 pub trait Map<'a, K, V>
@@ -95,6 +95,19 @@ pub trait TypedStoreDebug {
         page_number: usize,
     ) -> eyre::Result<BTreeMap<String, String>>;
 
+    /// Dump a DB table with pagination, within the byte budget and value truncation of `limits`.
+    /// The default implementation applies them after dumping the whole page
+    fn dump_table_with_limits(
+        &self,
+        table_name: String,
+        page_size: u16,
+        page_number: usize,
+        limits: &DumpLimits,
+    ) -> eyre::Result<DumpPage> {
+        let entries = self.dump_table(table_name, page_size, page_number)?;
+        Ok(limits.page(entries.into_iter()))
+    }
+
     /// Get the name of the DB. This is simply the name of the struct
     fn primary_db_name(&self) -> String;
 
@@ -103,4 +116,78 @@ pub trait TypedStoreDebug {
 
     /// Count the entries in the table
     fn count_table_keys(&self, table_name: String) -> eyre::Result<usize>;
+
+    /// Get the number of entries and the encoded sizes of the keys and values of the table.
+    /// The default implementation fails, for the implementations predating it
+    fn table_summary(&self, table_name: String) -> eyre::Result<TableSummary> {
+        Err(eyre::eyre!(
+            "the summary of the table {table_name} of {} is not supported",
+            self.primary_db_name()
+        ))
+    }
+}
+
+/// Size information about the content of a table
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableSummary {
+    pub num_keys: u64,
+    pub key_bytes_total: u64,
+    pub value_bytes_total: u64,
+    pub max_key_bytes: u64,
+    pub max_value_bytes: u64,
+}
+
+/// Limits of the output of a dump, so that dumping huge values can't exhaust the memory
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpLimits {
+    /// The page stops before the entry which would make its formatted keys and values exceed this
+    /// size, keeping at least one entry
+    pub max_page_bytes: Option<usize>,
+    /// The formatted values longer than this are truncated to this size
+    pub max_value_bytes: Option<usize>,
+}
+
+/// A page of a dump, see `DumpLimits`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpPage {
+    pub entries: BTreeMap<String, String>,
+    /// Whether the page stopped before its end because of `max_page_bytes`: the entries after the
+    /// last one can be dumped with a smaller page size
+    pub exceeded_page_bytes: bool,
+    /// The number of values truncated to `max_value_bytes`
+    pub truncated_values: usize,
+}
+
+impl DumpLimits {
+    /// Collects the formatted `entries` of a page into a `DumpPage`, consuming them only until the
+    /// byte budget is exhausted
+    pub fn page(&self, entries: impl Iterator<Item = (String, String)>) -> DumpPage {
+        let mut page = DumpPage::default();
+        let mut page_bytes = 0;
+        for (key, mut value) in entries {
+            let mut truncated = false;
+            if let Some(max_value_bytes) = self.max_value_bytes {
+                if value.len() > max_value_bytes {
+                    let total = value.len();
+                    let mut end = max_value_bytes;
+                    while !value.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    value.truncate(end);
+                    value.push_str(&format!("... ({total} bytes)"));
+                    truncated = true;
+                }
+            }
+            page_bytes += key.len() + value.len();
+            if let Some(max_page_bytes) = self.max_page_bytes {
+                if page_bytes > max_page_bytes && !page.entries.is_empty() {
+                    page.exceeded_page_bytes = true;
+                    break;
+                }
+            }
+            page.entries.insert(key, value);
+            page.truncated_values += truncated as usize;
+        }
+        page
+    }
 }
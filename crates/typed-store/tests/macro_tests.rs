@@ -1,5 +1,6 @@
 // Copyright (c) 2022, Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
+#![cfg(feature = "rocks")]
 #![allow(dead_code)]
 
 use once_cell::sync::Lazy;
@@ -11,8 +12,11 @@ use std::fmt::Debug;
 use std::sync::Mutex;
 use typed_store::rocks::list_tables;
 use typed_store::rocks::DBMap;
+use typed_store::rocks::ReadOnlyMap;
+use typed_store::rocks::RepairPolicy;
+use typed_store::rocks::TypedStoreError;
 use typed_store::traits::Map;
-use typed_store::traits::TypedStoreDebug;
+use typed_store::traits::{DumpLimits, TypedStoreDebug};
 use typed_store::Store;
 use typed_store_derive::DBMapUtils;
 
@@ -121,6 +125,326 @@ async fn macro_test() {
     assert_eq!(3, m.len());
     assert_eq!(format!("\"7\""), *m.get(&"\"7\"".to_string()).unwrap());
     assert_eq!(format!("\"8\""), *m.get(&"\"8\"".to_string()).unwrap());
+
+    // Test the limits of the output
+    let limits = DumpLimits {
+        max_page_bytes: Some(10),
+        max_value_bytes: Some(1),
+    };
+    let page = tbls_secondary
+        .dump_with_limits("table1", 3, 0, &limits)
+        .unwrap();
+    assert!(page.exceeded_page_bytes);
+    assert_eq!(page.truncated_values, 1);
+    assert_eq!(page.entries.len(), 1);
+    assert_eq!(page.entries["\"1\""], "\"... (3 bytes)");
+    let page = tbls_secondary
+        .dump_table_with_limits("table1".to_owned(), 3, 0, &DumpLimits::default())
+        .unwrap();
+    assert!(!page.exceeded_page_bytes);
+    assert_eq!(page.entries.len(), 3);
+}
+
+#[tokio::test]
+async fn macro_test_typed_batch() {
+    let primary_path = temp_dir();
+    let tables = Tables::open_tables_read_write(primary_path, None, None);
+
+    tables
+        .table2
+        .insert(&1, &"1".to_string())
+        .expect("Failed to insert");
+
+    let stats = tables
+        .batch()
+        .insert_table1(&"a".to_string(), &"b".to_string())
+        .expect("Failed to batch insert")
+        .insert_table2(&2, &"2".to_string())
+        .expect("Failed to batch insert")
+        .delete_table2(&1)
+        .expect("Failed to batch delete")
+        .commit()
+        .expect("Failed to commit batch");
+    assert_eq!(stats.entries, 3);
+
+    assert_eq!(
+        tables.table1.get(&"a".to_string()).unwrap(),
+        Some("b".to_string())
+    );
+    assert_eq!(tables.table2.get(&2).unwrap(), Some("2".to_string()));
+    assert!(!tables.table2.contains_key(&1).unwrap());
+
+    // The typed batch can be continued with raw writes, committed in the same atomic write
+    let other = DBMap::<i32, String>::reopen(&tables.table1.rocksdb, Some("table2")).unwrap();
+    let stats = tables
+        .batch()
+        .delete_table1(&"a".to_string())
+        .expect("Failed to batch delete")
+        .into_batch()
+        .insert_batch(&other, [(3, "3".to_string())])
+        .expect("Failed to batch insert")
+        .write()
+        .expect("Failed to write batch");
+    assert_eq!(stats.entries, 2);
+    assert!(!tables.table1.contains_key(&"a".to_string()).unwrap());
+    assert_eq!(tables.table2.get(&3).unwrap(), Some("3".to_string()));
+}
+
+#[tokio::test]
+async fn macro_test_open_with_repair_policy() {
+    let primary_path = temp_dir();
+    {
+        let tables = Tables::open_tables_with_repair_policy(
+            primary_path.clone(),
+            None,
+            None,
+            RepairPolicy::Fail,
+        )
+        .expect("Failed to open tables");
+        tables
+            .table2
+            .insert(&1, &"1".to_string())
+            .expect("Failed to insert");
+    }
+
+    let tables =
+        Tables::open_tables_with_repair_policy(primary_path, None, None, RepairPolicy::Repair)
+            .expect("Failed to reopen tables");
+    assert_eq!(tables.table2.get(&1).unwrap(), Some("1".to_string()));
+
+    // A file is not a valid DB path
+    let file_path = temp_dir().join("file");
+    std::fs::write(&file_path, b"").unwrap();
+    assert!(matches!(
+        Tables::open_tables_with_repair_policy(file_path, None, None, RepairPolicy::Fail),
+        Err(TypedStoreError::OpenFailure(_))
+    ));
+}
+
+#[tokio::test]
+async fn macro_test_table_summary() {
+    let primary_path = temp_dir();
+    let tables = Tables::open_tables_read_write(primary_path.clone(), None, None);
+    tables
+        .table2
+        .multi_insert((0..10).map(|i| (i, i.to_string())))
+        .expect("Failed to multi-insert");
+
+    let read_only = Tables::get_read_only_handle(primary_path, None, None);
+    let summary = read_only.table_summary("table2".to_owned()).unwrap();
+    assert_eq!(summary.num_keys, 10);
+    // i32 keys are encoded on 4 bytes
+    assert_eq!(summary.key_bytes_total, 40);
+    assert_eq!(summary.max_key_bytes, 4);
+    assert_eq!(read_only.summary("table1").unwrap(), Default::default());
+    assert!(read_only.summary("missing").is_err());
+}
+
+/// The tables used by some business logic, which can be implemented by other stores in its tests
+trait CounterStore {
+    fn counters(&self) -> &DBMap<String, u64>;
+    fn names(&self) -> &DBMap<u64, String>;
+}
+
+#[derive(DBMapUtils)]
+#[dbmap_utils(impl_trait = "CounterStore")]
+struct CounterTables {
+    counters: DBMap<String, u64>,
+    names: DBMap<u64, String>,
+}
+
+fn increment(store: &impl CounterStore, name: &str) -> u64 {
+    let count = store.counters().get(&name.to_owned()).unwrap().unwrap_or(0) + 1;
+    store.counters().insert(&name.to_owned(), &count).unwrap();
+    store.names().insert(&count, &name.to_owned()).unwrap();
+    count
+}
+
+#[tokio::test]
+async fn macro_test_impl_trait() {
+    let tables = CounterTables::open_tables_read_write(temp_dir(), None, None);
+    assert_eq!(increment(&tables, "a"), 1);
+    assert_eq!(increment(&tables, "a"), 2);
+    assert_eq!(tables.counters.get(&"a".to_owned()).unwrap(), Some(2));
+    assert_eq!(tables.names().get(&2).unwrap(), Some("a".to_owned()));
+}
+
+#[cfg(feature = "cli")]
+#[derive(DBMapUtils)]
+#[dbmap_utils(subcommands)]
+struct TablesWithSubcommands {
+    table1: DBMap<String, String>,
+    table2: DBMap<i32, String>,
+}
+
+#[cfg(feature = "cli")]
+#[derive(clap::Parser)]
+struct DbTool {
+    #[clap(subcommand)]
+    command: TablesWithSubcommandsCommand,
+}
+
+#[cfg(feature = "cli")]
+#[tokio::test]
+async fn macro_test_subcommands() {
+    use clap::Parser;
+
+    let primary_path = temp_dir();
+    let tables = TablesWithSubcommands::open_tables_read_write(primary_path.clone(), None, None);
+    tables
+        .table1
+        .insert(&"key".to_owned(), &"value".to_owned())
+        .unwrap();
+    tables.table2.insert(&1, &"one".to_owned()).unwrap();
+
+    let handle = TablesWithSubcommands::get_read_only_handle(primary_path, None, None);
+    let run = |args: &[&str]| {
+        DbTool::parse_from(["db-tool"].iter().chain(args.iter()))
+            .command
+            .run(&handle)
+    };
+    assert_eq!(run(&["count", "table1"]).unwrap(), "1");
+    assert_eq!(run(&["get", "table1", "key"]).unwrap(), "\"value\"");
+    assert_eq!(run(&["get", "table2", "1"]).unwrap(), "\"one\"");
+    assert_eq!(
+        run(&["dump", "table2", "--page-size", "10"]).unwrap(),
+        "1: \"one\""
+    );
+    assert!(run(&["summary", "table2"]).unwrap().contains("num_keys: 1"));
+    assert!(run(&["get", "table2", "2"]).is_err());
+    assert!(run(&["count", "table3"]).is_err());
+}
+
+#[cfg(feature = "bundle")]
+#[derive(DBMapUtils)]
+#[dbmap_utils(support_bundle)]
+struct TablesWithSupportBundle {
+    table1: DBMap<String, String>,
+    table2: DBMap<i32, String>,
+}
+
+#[cfg(feature = "bundle")]
+#[tokio::test]
+async fn macro_test_support_bundle() {
+    use std::io::Read;
+
+    let primary_path = temp_dir();
+    let tables = TablesWithSupportBundle::open_tables_read_write(primary_path.clone(), None, None);
+    tables.table2.insert(&1, &"one".to_owned()).unwrap();
+
+    let handle = TablesWithSupportBundle::get_read_only_handle(primary_path, None, None);
+    let output = temp_dir().join("bundle.tar.zst");
+    let manifest = handle.create_support_bundle(&[], &output).unwrap();
+    assert_eq!(manifest.tables, vec!["table1", "table2"]);
+
+    let mut archive =
+        tar::Archive::new(zstd::Decoder::new(std::fs::File::open(&output).unwrap()).unwrap());
+    let mut files = std::collections::BTreeMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        files.insert(entry.path().unwrap().display().to_string(), contents);
+    }
+    assert!(files.contains_key("manifest.json"));
+    assert!(files.contains_key("tables/table1/lsm.json"));
+    assert!(files["tables/table2/schema.json"].contains("i32"));
+    assert!(files["tables/table2/sample.json"].contains("one"));
+
+    assert!(handle.create_support_bundle(&["table3"], &output).is_err());
+}
+
+#[cfg(feature = "admin")]
+#[tokio::test]
+async fn macro_test_admin_service() {
+    use typed_store::admin::*;
+
+    let primary_path = temp_dir();
+    let tables = Tables::open_tables_read_write(primary_path.clone(), None, None);
+    tables
+        .table1
+        .multi_insert((0..5).map(|i| (i.to_string(), i.to_string())))
+        .expect("Failed to multi-insert");
+
+    let service = TypedStoreAdminService::new().with_db(Tables::get_read_only_handle(
+        primary_path,
+        None,
+        None,
+    ));
+    let request = |table_name: &str| {
+        tonic::Request::new(CountKeysRequest {
+            db_name: "Tables".to_owned(),
+            table_name: table_name.to_owned(),
+        })
+    };
+    let count = service.count_keys(request("table1")).await.unwrap();
+    assert_eq!(count.get_ref().count, 5);
+    let status = service.count_keys(request("missing")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    let dump = service
+        .dump_table(tonic::Request::new(DumpTableRequest {
+            db_name: "Tables".to_owned(),
+            table_name: "table1".to_owned(),
+            page_size: 2,
+            page_number: 0,
+        }))
+        .await
+        .unwrap();
+    assert_eq!(dump.get_ref().entries.len(), 2);
+
+    let summary = service
+        .table_summary(tonic::Request::new(TableSummaryRequest {
+            db_name: "Tables".to_owned(),
+            table_name: "table1".to_owned(),
+        }))
+        .await
+        .unwrap();
+    assert_eq!(summary.get_ref().num_keys, 5);
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn macro_test_debug_router() {
+    use tower::ServiceExt;
+    use typed_store::http::{debug_router, CountResponse, DumpResponse};
+
+    let primary_path = temp_dir();
+    let tables = Tables::open_tables_read_write(primary_path.clone(), None, None);
+    tables
+        .table2
+        .multi_insert((0..5).map(|i| (i, i.to_string())))
+        .expect("Failed to multi-insert");
+    let router = debug_router(Tables::get_read_only_handle(primary_path, None, None));
+
+    let get = |uri: &str| {
+        let request = hyper::Request::get(uri).body(hyper::Body::empty()).unwrap();
+        router.clone().oneshot(request)
+    };
+    let body = |response: hyper::Response<_>| async move {
+        hyper::body::to_bytes(response.into_body()).await.unwrap()
+    };
+
+    let response = get("/tables/table2/count").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let count: CountResponse = serde_json::from_slice(&body(response).await).unwrap();
+    assert_eq!(count.count, 5);
+
+    let response = get("/tables/table2/dump?cursor=1&page_size=2")
+        .await
+        .unwrap();
+    let dump: DumpResponse = serde_json::from_slice(&body(response).await).unwrap();
+    assert_eq!(dump.entries.len(), 2);
+    assert!(dump.entries.contains_key("2"));
+    assert_eq!(dump.next_cursor, Some(2));
+
+    let response = get("/tables").await.unwrap();
+    let tables: std::collections::BTreeMap<String, (String, String)> =
+        serde_json::from_slice(&body(response).await).unwrap();
+    assert_eq!(tables.len(), 2);
+
+    let response = get("/tables/missing/count").await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
 }
 
 /// We show that custom functions can be applied
@@ -183,6 +507,31 @@ async fn macro_test_configure() {
     assert_eq!(TABLE2_OPTIONS_SET_FLAG.lock().unwrap().len(), 6);
 }
 
+#[tokio::test]
+async fn macro_test_options_summary() {
+    let primary_path = temp_dir();
+    let tables = TablesCustomOptions::open_tables_read_write(primary_path, None, None);
+
+    let summary = tables.options_summary().unwrap();
+    let options_fns: Vec<_> = summary
+        .iter()
+        .map(|table| (table.table.as_str(), table.options_fn.as_str()))
+        .collect();
+    assert_eq!(
+        options_fns,
+        vec![
+            ("table1", "another_custom_fn_name"),
+            ("table2", "typed_store::rocks::default_rocksdb_options"),
+            ("table3", "custom_fn_name"),
+            ("table4", "another_custom_fn_name"),
+        ]
+    );
+    // The digest is read back from the options persisted by RocksDB
+    assert!(summary
+        .iter()
+        .all(|table| table.write_buffer_size.is_some() && table.compression.is_some()));
+}
+
 /// We show that custom functions can be applied
 #[derive(DBMapUtils)]
 struct TablesMemUsage {
@@ -207,6 +556,448 @@ async fn macro_test_get_memory_usage() {
     assert!(mem_table > 0);
 }
 
+#[tokio::test]
+async fn macro_test_pause_background_work() {
+    let tables = TablesMemUsage::open_tables_read_write(temp_dir(), None, None);
+
+    tables.pause_background_work().unwrap();
+    tables.continue_background_work().unwrap();
+    {
+        let _guard = tables.pause_background_work_guard().unwrap();
+        tables
+            .table1
+            .insert(&"1".to_string(), &"1".to_string())
+            .unwrap();
+    }
+    assert_eq!(tables.table1.iter().count(), 1);
+}
+
+#[tokio::test]
+async fn macro_test_health() {
+    let tables = Tables::open_tables_read_write(temp_dir(), None, None);
+    let mut errors = tables.watch_background_errors(std::time::Duration::from_millis(10));
+
+    tables
+        .table1
+        .insert(&"1".to_string(), &"1".to_string())
+        .unwrap();
+    assert!(tables.health().unwrap().is_healthy());
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(matches!(
+        errors.try_recv(),
+        Err(tokio::sync::broadcast::error::TryRecvError::Empty)
+    ));
+}
+
+#[tokio::test]
+async fn macro_test_apply_runtime_config() {
+    let tables = Tables::open_tables_read_write(temp_dir(), None, None);
+    let options = typed_store::rocks::RuntimeOptions {
+        write_buffer_size: Some(1 << 20),
+        ..Default::default()
+    };
+
+    let config = [("table1".to_owned(), options.clone())]
+        .into_iter()
+        .collect();
+    tables.apply_runtime_config(&config).unwrap();
+
+    let config = [("table3".to_owned(), options)].into_iter().collect();
+    assert!(tables.apply_runtime_config(&config).is_err());
+}
+
+#[derive(DBMapUtils)]
+struct WeightedTables {
+    #[memory_weight = 3]
+    hot: DBMap<String, String>,
+    cold: DBMap<String, String>,
+}
+
+#[tokio::test]
+async fn macro_test_memory_budget() {
+    let budget = typed_store::rocks::MemoryBudget::new(512 << 20).unwrap();
+    let mut db_options = typed_store::rocks::default_rocksdb_options();
+    budget.apply_to_db_options(&mut db_options);
+    let config = WeightedTables::memory_budget_configurator(&budget);
+    let tables =
+        WeightedTables::open_tables_read_write(temp_dir(), Some(db_options), Some(config.build()));
+
+    tables
+        .hot
+        .insert(&"key".to_owned(), &"value".to_owned())
+        .unwrap();
+    let usage = tables.memory_usage(&budget).unwrap();
+    assert!(usage.memtable_bytes > 0);
+    assert!(usage.total() <= budget.total_bytes());
+}
+
+#[derive(DBMapUtils)]
+struct FilteredTables {
+    #[filter(bloom)]
+    bloom: DBMap<String, String>,
+    #[filter(prefix_bloom, prefix_len = 8, bits_per_key = 12)]
+    prefix: DBMap<(u64, u64), String>,
+    #[filter(ribbon, bits_per_key = 9.5)]
+    ribbon: DBMap<u64, String>,
+}
+
+#[tokio::test]
+async fn macro_test_filters() {
+    let budget = typed_store::rocks::MemoryBudget::new(64 << 20).unwrap();
+    for config in [
+        None,
+        Some(FilteredTables::memory_budget_configurator(&budget).build()),
+    ] {
+        let tables = FilteredTables::open_tables_read_write(temp_dir(), None, config);
+        tables
+            .bloom
+            .insert(&"key".to_owned(), &"value".to_owned())
+            .unwrap();
+        tables
+            .prefix
+            .multi_insert((0..10).map(|i| ((i % 2, i), i.to_string())))
+            .unwrap();
+        tables.ribbon.insert(&1, &"1".to_owned()).unwrap();
+        tables.bloom.rocksdb.flush().unwrap();
+
+        assert_eq!(
+            tables.bloom.get(&"key".to_owned()).unwrap(),
+            Some("value".to_owned())
+        );
+        assert_eq!(tables.bloom.get(&"missing".to_owned()).unwrap(), None);
+        assert_eq!(tables.ribbon.get(&1).unwrap(), Some("1".to_owned()));
+        assert_eq!(
+            tables
+                .prefix
+                .iter()
+                .skip_to(&(1, 0))
+                .unwrap()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (1, 3), (1, 5), (1, 7), (1, 9)]
+        );
+    }
+}
+
+#[derive(DBMapUtils)]
+struct LargeTables {
+    #[large_table]
+    objects: DBMap<u64, String>,
+    #[large_table]
+    #[filter(prefix_bloom, prefix_len = 8)]
+    #[memory_weight = 2]
+    owned: DBMap<(u64, u64), String>,
+}
+
+#[tokio::test]
+async fn macro_test_large_tables() {
+    let budget = typed_store::rocks::MemoryBudget::new(64 << 20).unwrap();
+    for config in [
+        None,
+        Some(LargeTables::memory_budget_configurator(&budget).build()),
+    ] {
+        let tables = LargeTables::open_tables_read_write(temp_dir(), None, config);
+        tables
+            .objects
+            .multi_insert((0..100).map(|i| (i, i.to_string())))
+            .unwrap();
+        tables
+            .owned
+            .multi_insert((0..100).map(|i| ((i % 2, i), i.to_string())))
+            .unwrap();
+        tables.objects.rocksdb.flush().unwrap();
+
+        assert_eq!(tables.objects.get(&42).unwrap(), Some("42".to_owned()));
+        assert_eq!(tables.objects.get(&100).unwrap(), None);
+        assert_eq!(tables.owned.iter().skip_to(&(1, 0)).unwrap().count(), 50);
+    }
+}
+
+#[derive(DBMapUtils)]
+struct SecondaryTables {
+    table: DBMap<u32, String>,
+}
+
+#[tokio::test]
+async fn macro_test_managed_secondary_paths() {
+    let primary_path = temp_dir();
+    let primary = SecondaryTables::open_tables_read_write(primary_path.clone(), None, None);
+    primary.table.insert(&1, &"one".to_owned()).unwrap();
+    let live_instances = || {
+        typed_store::metrics::DBMetrics::get()
+            .instance_metrics
+            .rocksdb_secondary_instances
+            .with_label_values(&["SecondaryTables"])
+            .get()
+    };
+
+    let first = SecondaryTables::get_read_only_handle(primary_path.clone(), None, None);
+    let second = SecondaryTables::get_read_only_handle(primary_path.clone(), None, None);
+    let (first_path, second_path) = (
+        first.managed_secondary_path().unwrap().to_path_buf(),
+        second.managed_secondary_path().unwrap().to_path_buf(),
+    );
+    assert_ne!(first_path, second_path);
+    assert!(first_path.exists() && second_path.exists());
+    assert_eq!(live_instances(), 2);
+    first.table.try_catch_up_with_primary().unwrap();
+    assert_eq!(first.table.get(&1).unwrap(), Some("one".to_owned()));
+
+    // The path of a dropped handle is removed, and reused by the next handle
+    drop(first);
+    assert!(!first_path.exists());
+    assert_eq!(live_instances(), 1);
+    let third = SecondaryTables::get_read_only_handle(primary_path.clone(), None, None);
+    assert_eq!(third.managed_secondary_path(), Some(first_path.as_path()));
+
+    // Explicit secondary paths are left to the caller
+    let explicit = temp_dir();
+    let fourth = SecondaryTables::get_read_only_handle(primary_path, Some(explicit.clone()), None);
+    assert!(fourth.managed_secondary_path().is_none());
+    drop(fourth);
+    assert!(explicit.exists());
+
+    drop((second, third));
+    assert_eq!(live_instances(), 0);
+    assert!(!second_path.exists());
+}
+
+#[tokio::test]
+async fn macro_test_orphan_tables() {
+    let primary_path = temp_dir();
+    {
+        // TablesMemUsage has the tables of Tables and two more
+        let tables = TablesMemUsage::open_tables_read_write(primary_path.clone(), None, None);
+        tables
+            .table3
+            .multi_insert((0..10).map(|i| (i, i.to_string())))
+            .expect("Failed to multi-insert");
+    }
+
+    let tables = Tables::open_tables_read_write(primary_path.clone(), None, None);
+    let orphans = tables.orphan_tables().unwrap();
+    let names: Vec<_> = orphans.iter().map(|o| o.name.as_str()).collect();
+    assert_eq!(names, vec!["table3", "table4"]);
+    assert!(orphans[0].size_bytes > 0);
+
+    assert!(matches!(
+        tables.drop_orphan_tables(&["table1", "table3"]),
+        Err(TypedStoreError::NotAnOrphanColumn(name)) if name == "table1"
+    ));
+    assert_eq!(
+        tables.drop_orphan_tables(&["table3"]).unwrap(),
+        vec!["table3".to_string()]
+    );
+    assert_eq!(
+        typed_store::rocks::list_orphan_tables(&primary_path, &["table1", "table2"])
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(tables.orphan_tables().unwrap().len(), 1);
+}
+
+/// Same table names as `Tables`, with a different value type for `table2`
+#[derive(DBMapUtils)]
+struct TablesChangedSchema {
+    table1: DBMap<String, String>,
+    table2: DBMap<i32, u64>,
+}
+
+#[tokio::test]
+async fn macro_test_schema_check() {
+    let primary_path = temp_dir();
+    {
+        let tables = Tables::open_tables_read_write(primary_path.clone(), None, None);
+        assert!(tables.schema_check().unwrap().is_empty());
+    }
+
+    let tables = TablesChangedSchema::open_tables_read_write(primary_path, None, None);
+    let drifts = tables.schema_check().unwrap();
+    assert_eq!(drifts.len(), 1);
+    assert_eq!(drifts[0].table, "table2");
+    assert_eq!(drifts[0].recorded, ("i32".to_owned(), "String".to_owned()));
+    assert_eq!(drifts[0].current, ("i32".to_owned(), "u64".to_owned()));
+
+    tables.accept_schema().unwrap();
+    assert!(tables.schema_check().unwrap().is_empty());
+}
+
+#[cfg(feature = "encryption")]
+#[derive(DBMapUtils)]
+struct EncryptedTables {
+    #[encrypted]
+    secrets: DBMap<String, String>,
+    public: DBMap<String, String>,
+}
+
+#[cfg(feature = "encryption")]
+#[tokio::test]
+async fn macro_test_encrypted_tables() {
+    use typed_store::rocks::encryption::{MasterKey, MasterKeys};
+    use typed_store::rocks::TypedStoreError;
+
+    let primary_path = temp_dir();
+    let keys = MasterKeys::new(MasterKey::new(0, [7u8; 32]));
+    {
+        let tables =
+            EncryptedTables::open_tables_with_value_codecs(primary_path.clone(), None, None, &keys)
+                .unwrap();
+        tables
+            .secrets
+            .insert(&"key".to_owned(), &"secret".to_owned())
+            .unwrap();
+        tables
+            .public
+            .insert(&"key".to_owned(), &"public".to_owned())
+            .unwrap();
+    }
+
+    // Encrypted tables can't be opened without a codec
+    assert!(matches!(
+        EncryptedTables::open_tables_with_repair_policy(
+            primary_path.clone(),
+            None,
+            None,
+            RepairPolicy::Fail
+        ),
+        Err(TypedStoreError::MissingValueCodec(table)) if table == "secrets"
+    ));
+
+    let tables =
+        EncryptedTables::open_tables_with_value_codecs(primary_path, None, None, &keys).unwrap();
+    assert_eq!(
+        tables.secrets.get(&"key".to_owned()).unwrap(),
+        Some("secret".to_owned())
+    );
+    assert_eq!(
+        tables.public.get(&"key".to_owned()).unwrap(),
+        Some("public".to_owned())
+    );
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Object {
+    owner: String,
+    version: u64,
+}
+
+#[derive(DBMapUtils)]
+struct IndexedTables {
+    #[index(name = "by_owner", key = "owner")]
+    objects: DBMap<u32, Object>,
+}
+
+#[tokio::test]
+async fn macro_test_secondary_index() {
+    let object = |owner: &str, version| Object {
+        owner: owner.to_owned(),
+        version,
+    };
+    let tables = IndexedTables::open_tables_read_write(temp_dir(), None, None);
+
+    tables
+        .batch()
+        .insert_objects(&1, &object("alice", 1))
+        .unwrap()
+        .insert_objects(&2, &object("bob", 1))
+        .unwrap()
+        .insert_objects(&3, &object("alice", 1))
+        .unwrap()
+        // Several writes to a key in a batch leave a single index entry
+        .insert_objects(&2, &object("carol", 1))
+        .unwrap()
+        .insert_objects(&2, &object("alice", 2))
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(
+        tables.get_by_owner("alice").unwrap(),
+        vec![
+            (1, object("alice", 1)),
+            (2, object("alice", 2)),
+            (3, object("alice", 1))
+        ]
+    );
+    assert!(tables.get_by_owner("bob").unwrap().is_empty());
+    assert!(tables.get_by_owner("carol").unwrap().is_empty());
+
+    // Updating or deleting a value replaces its entry in the index
+    tables
+        .batch()
+        .insert_objects(&1, &object("bob", 2))
+        .unwrap()
+        .delete_objects(&3)
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(
+        tables.get_by_owner("alice").unwrap(),
+        vec![(2, object("alice", 2))]
+    );
+    assert_eq!(
+        tables.get_by_owner("bob").unwrap(),
+        vec![(1, object("bob", 2))]
+    );
+
+    // Looking up a key of another type than the field fails instead of matching nothing
+    assert!(matches!(
+        tables.get_by_owner(&1u64),
+        Err(TypedStoreError::IndexKeyMismatch { .. })
+    ));
+
+    // The index column family is not an orphan
+    assert!(tables.orphan_tables().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn macro_test_secondary_index_concurrent_writes() {
+    let tables = std::sync::Arc::new(IndexedTables::open_tables_read_write(
+        temp_dir(),
+        None,
+        None,
+    ));
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let tables = tables.clone();
+            std::thread::spawn(move || {
+                for version in 0..100 {
+                    let object = Object {
+                        owner: format!("owner{writer}"),
+                        version,
+                    };
+                    tables
+                        .batch()
+                        .insert_objects(&1, &object)
+                        .unwrap()
+                        .commit()
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    // Whichever write was last, the key has a single index entry, the one of its value
+    let object = tables.objects.get(&1).unwrap().unwrap();
+    let rocksdb = &tables.objects.rocksdb;
+    let index_cf = rocksdb
+        .cf_handle(&typed_store::rocks::index_cf_name("objects", "by_owner"))
+        .unwrap();
+    assert_eq!(
+        rocksdb
+            .iterator_cf(&index_cf, rocksdb::IteratorMode::Start)
+            .count(),
+        1
+    );
+    assert_eq!(
+        tables.get_by_owner(&object.owner).unwrap(),
+        vec![(1, object)]
+    );
+}
+
 #[derive(DBMapUtils)]
 struct StoreTables {
     table1: Store<Vec<u8>, Vec<u8>>,
@@ -260,3 +1051,321 @@ async fn store_iter_and_filter_successfully() {
     }
     assert_eq!(output.len(), key_values.len());
 }
+
+type Digest32 = [u8; 32];
+type Certificates = DBMap<Digest32, String>;
+
+#[derive(DBMapUtils)]
+struct AliasedTables {
+    digests: DBMap<Digest32, u64>,
+    #[dbmap(key = "Digest32", value = "String")]
+    certificates: Certificates,
+    qualified: typed_store::rocks::DBMap<u64, Digest32>,
+}
+
+/// The generated code only refers to the map types by their full paths, so a crate naming the tables
+/// by their paths doesn't have to import them
+mod qualified_paths {
+    pub trait QualifiedStore {
+        fn table(&self) -> &typed_store::rocks::DBMap<u64, String>;
+    }
+
+    #[derive(typed_store_derive::DBMapUtils)]
+    #[dbmap_utils(impl_trait = "QualifiedStore")]
+    pub struct QualifiedTables {
+        pub table: typed_store::rocks::DBMap<u64, String>,
+        #[read_only_after_open]
+        pub config: typed_store::rocks::ReadOnlyMap<u64, u64>,
+    }
+}
+
+#[tokio::test]
+async fn macro_test_qualified_paths() {
+    use qualified_paths::*;
+
+    let primary_path = temp_dir();
+    let (tables, writer) =
+        QualifiedTables::open_tables_read_write_with_writer(primary_path.clone(), None, None);
+    writer.config.insert(&1, &2).unwrap();
+    tables
+        .batch()
+        .insert_table(&1, &"1".to_owned())
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(tables.table().get(&1).unwrap(), Some("1".to_owned()));
+    assert_eq!(tables.config.get(&1).unwrap(), Some(2));
+
+    let read_only = QualifiedTables::get_read_only_handle(primary_path, None, None);
+    assert_eq!(read_only.count_keys("table").unwrap(), 1);
+}
+
+#[tokio::test]
+async fn macro_test_type_aliases() {
+    let tables = AliasedTables::open_tables_read_write(temp_dir(), None, None);
+    tables
+        .batch()
+        .insert_certificates(&[1; 32], &"cert".to_owned())
+        .unwrap()
+        .insert_digests(&[1; 32], &7)
+        .unwrap()
+        .insert_qualified(&7, &[1; 32])
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(
+        tables.certificates.get(&[1; 32]).unwrap(),
+        Some("cert".to_owned())
+    );
+
+    let described = AliasedTables::describe_tables();
+    assert_eq!(
+        described.get("certificates"),
+        Some(&("Digest32".to_owned(), "String".to_owned()))
+    );
+}
+
+#[derive(DBMapUtils)]
+#[dbmap_utils(db_name = "consensus")]
+struct NamedTables {
+    table1: DBMap<u32, u32>,
+    table2: DBMap<u32, String>,
+}
+
+#[tokio::test]
+async fn macro_test_db_name() {
+    let primary_path = temp_dir();
+    let tables = NamedTables::open_tables_read_write(primary_path.clone(), None, None);
+    assert_eq!(tables.table1.db_name(), "consensus");
+    assert_eq!(tables.table2.db_name(), "consensus");
+
+    let read_only_handle = NamedTables::get_read_only_handle(primary_path, None, None);
+    assert_eq!(read_only_handle.primary_db_name(), "consensus");
+
+    // Instances of the struct can be told apart
+    let shard = tables.with_db_name("consensus-shard-1");
+    assert_eq!(shard.table1.db_name(), "consensus-shard-1");
+    assert_eq!(shard.table2.db_name(), "consensus-shard-1");
+    shard
+        .batch()
+        .insert_table1(&1, &1)
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(shard.table1.get(&1).unwrap(), Some(1));
+}
+
+#[derive(DBMapUtils)]
+struct GuardedTables {
+    #[read_only_after_open]
+    committed: ReadOnlyMap<u64, String>,
+    pending: DBMap<u64, String>,
+}
+
+#[tokio::test]
+async fn macro_test_read_only_after_open() {
+    let primary_path = temp_dir();
+    let (tables, writer) =
+        GuardedTables::open_tables_read_write_with_writer(primary_path.clone(), None, None);
+    writer.committed.insert(&1, &"one".to_owned()).unwrap();
+    assert_eq!(tables.committed.get(&1).unwrap(), Some("one".to_owned()));
+    assert!(tables.committed.contains_key(&1).unwrap());
+    assert_eq!(tables.committed.keys().collect::<Vec<_>>(), vec![1]);
+
+    // The typed batch only writes the other tables
+    tables
+        .batch()
+        .insert_pending(&2, &"two".to_owned())
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(tables.pending.get(&2).unwrap(), Some("two".to_owned()));
+
+    let relabelled = tables.with_db_name("guarded");
+    assert_eq!(relabelled.committed.db_name(), "guarded");
+    drop(relabelled);
+    drop(writer);
+
+    // The read only tables are written by nobody without the writer
+    let reopened = GuardedTables::open_tables_read_write(primary_path, None, None);
+    assert_eq!(reopened.committed.get(&1).unwrap(), Some("one".to_owned()));
+}
+
+#[derive(DBMapUtils)]
+struct TablesV1 {
+    accounts: DBMap<u64, u64>,
+    names: DBMap<u64, String>,
+}
+
+#[derive(DBMapUtils)]
+#[dbmap_utils(migrate_from = "TablesV1")]
+struct TablesV2 {
+    balances: DBMap<u64, u128>,
+    owners: DBMap<u64, String>,
+    audit: DBMap<u64, String>,
+    fresh: DBMap<u64, String>,
+}
+
+#[tokio::test]
+async fn macro_test_migrate_from() {
+    use typed_store::rocks::TableMigration;
+
+    let old = TablesV1::open_tables_read_write(temp_dir(), None, None);
+    old.accounts
+        .multi_insert((0..100).map(|i| (i, i * 10)))
+        .unwrap();
+    old.names.insert(&1, &"alice".to_owned()).unwrap();
+    old.names.insert(&2, &"bob".to_owned()).unwrap();
+
+    let new = TablesV2::open_tables_read_write(temp_dir(), None, None);
+    new.fresh.insert(&1, &"kept".to_owned()).unwrap();
+    let migrated = new
+        .migrate_from(&old, |old| TablesV2Migration {
+            // A converted table, leaving out the empty accounts
+            balances: TableMigration::convert(&old.accounts, |(id, balance)| {
+                (balance > 0).then_some((id, balance as u128))
+            }),
+            // A renamed table
+            owners: TableMigration::copy(&old.names),
+            audit: TableMigration::fill([(0, "migrated".to_owned())]),
+            ..Default::default()
+        })
+        .unwrap();
+
+    assert_eq!(
+        migrated.keys().cloned().collect::<Vec<_>>(),
+        vec!["audit", "balances", "owners"]
+    );
+    assert_eq!(migrated["balances"].entries_committed, 99);
+    assert_eq!(new.balances.get(&0).unwrap(), None);
+    assert_eq!(new.balances.get(&42).unwrap(), Some(420));
+    assert_eq!(new.owners.get(&2).unwrap(), Some("bob".to_owned()));
+    assert_eq!(new.audit.keys().collect::<Vec<_>>(), vec![0]);
+    assert_eq!(new.fresh.get(&1).unwrap(), Some("kept".to_owned()));
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Account {
+    owner: String,
+    balance: u64,
+}
+
+#[cfg(feature = "json")]
+#[derive(DBMapUtils)]
+struct TablesWithCodecs {
+    #[codec = "json"]
+    json_table: DBMap<u64, Account>,
+    #[codec = "bincode"]
+    bincode_table: DBMap<u64, String>,
+    default_table: DBMap<u64, String>,
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn macro_test_codec() {
+    let tables = TablesWithCodecs::open_tables_read_write(temp_dir(), None, None);
+    let account = Account {
+        owner: "alice".to_owned(),
+        balance: 10,
+    };
+    tables.json_table.insert(&1, &account).unwrap();
+    tables.bincode_table.insert(&1, &"one".to_owned()).unwrap();
+    assert_eq!(tables.json_table.get(&1).unwrap(), Some(account.clone()));
+    assert_eq!(
+        tables.json_table.iter().collect::<Vec<_>>(),
+        vec![(1, account)]
+    );
+    assert_eq!(
+        tables.bincode_table.get(&1).unwrap(),
+        Some("one".to_owned())
+    );
+
+    // The values are stored in JSON, under keys serialized with bincode
+    let rocksdb = &tables.json_table.rocksdb;
+    let raw = rocksdb
+        .get_cf(
+            &rocksdb.cf_handle("json_table").unwrap(),
+            1u64.to_be_bytes(),
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(raw, br#"{"owner":"alice","balance":10}"#);
+
+    let descriptions = TablesWithCodecs::describe_tables();
+    assert_eq!(descriptions["json_table"].1, "Account as json");
+    assert_eq!(descriptions["bincode_table"].1, "String");
+    assert_eq!(descriptions["default_table"].1, "String");
+}
+
+#[cfg(feature = "bcs")]
+#[derive(DBMapUtils)]
+struct TablesWithKeyCodecs {
+    #[key_codec = "bcs"]
+    bcs_keys: DBMap<(u64, String), u64>,
+    #[key_codec = "bincode"]
+    bincode_keys: DBMap<(u64, String), u64>,
+}
+
+#[cfg(feature = "bcs")]
+#[tokio::test]
+async fn macro_test_key_codec() {
+    let tables = TablesWithKeyCodecs::open_tables_read_write(temp_dir(), None, None);
+    for (key, value) in [((2, "a"), 3), ((1, "b"), 2), ((1, "a"), 1)] {
+        let key = (key.0, key.1.to_owned());
+        tables.bcs_keys.insert(&key, &value).unwrap();
+        tables.bincode_keys.insert(&key, &value).unwrap();
+    }
+    // The keys keep their order in both formats
+    assert_eq!(tables.bcs_keys.values().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(
+        tables.bcs_keys.iter().collect::<Vec<_>>(),
+        tables.bincode_keys.iter().collect::<Vec<_>>()
+    );
+
+    // The keys are stored in BCS with big endian integers, the lengths in ULEB128
+    let rocksdb = &tables.bcs_keys.rocksdb;
+    let raw = rocksdb
+        .get_cf(
+            &rocksdb.cf_handle("bcs_keys").unwrap(),
+            [0, 0, 0, 0, 0, 0, 0, 1, 1, b'a'],
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(raw, 1u64.to_be_bytes());
+
+    let descriptions = TablesWithKeyCodecs::describe_tables();
+    assert_eq!(descriptions["bcs_keys"].0, "(u64, String) as bcs");
+    assert_eq!(descriptions["bincode_keys"].0, "(u64, String)");
+}
+
+/// An implementation written before `table_summary` was added to the trait
+struct LegacyDebug;
+
+impl TypedStoreDebug for LegacyDebug {
+    fn dump_table(
+        &self,
+        _table_name: String,
+        _page_size: u16,
+        _page_number: usize,
+    ) -> eyre::Result<std::collections::BTreeMap<String, String>> {
+        Ok(Default::default())
+    }
+
+    fn primary_db_name(&self) -> String {
+        "legacy".to_owned()
+    }
+
+    fn describe_all_tables(&self) -> std::collections::BTreeMap<String, (String, String)> {
+        Default::default()
+    }
+
+    fn count_table_keys(&self, _table_name: String) -> eyre::Result<usize> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn legacy_debug_table_summary() {
+    assert!(LegacyDebug.table_summary("table".to_owned()).is_err());
+}
@@ -0,0 +1,36 @@
+// Copyright (c) 2022, Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use typed_store::rocks::{default_rocksdb_options, open_cf};
+use typed_store::testing::stress::{run_stress, StressConfig};
+
+#[test]
+fn stress_small_write_buffers() {
+    // Small write buffers flush and compact the tables while the workloads run
+    let mut options = default_rocksdb_options();
+    options.set_write_buffer_size(64 * 1024);
+    options.set_level_zero_file_num_compaction_trigger(2);
+    let db = open_cf(
+        tempfile::tempdir().unwrap().into_path(),
+        Some(options),
+        &["table1", "table2", "table3"],
+    )
+    .unwrap();
+
+    let config = StressConfig {
+        writers: 2,
+        readers: 2,
+        iterators: 2,
+        keys_per_writer: 100,
+        duration: Duration::from_millis(500),
+        seed: 42,
+    };
+    let report = run_stress(&db, &["table1", "table2", "table3"], &config).unwrap();
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert!(report.batches > 0);
+    assert!(report.reads > 0);
+    assert!(report.iterations > 0);
+}